@@ -0,0 +1,42 @@
+//! Benchmarks of structuring, run with `cargo bench`.
+
+#![feature(test)]
+
+extern crate petgraph;
+extern crate radeco_lib;
+extern crate test;
+
+use petgraph::stable_graph::{NodeIndex, StableDiGraph};
+use radeco_lib::{structure_cfg, EdgeCondition, StructuringOptions};
+use test::Bencher;
+
+/// A graph of `n` blocks, each of which falls through to the next and
+/// branches to another one picked at random, so that it has many loops,
+/// some of them irreducible, and many loop headers with several latches.
+fn dense_random_cfg(
+    n: usize,
+    seed: u64,
+) -> (StableDiGraph<String, EdgeCondition<usize>>, NodeIndex) {
+    let mut state = seed;
+    let mut next = move || {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 33) as usize
+    };
+    let mut graph = StableDiGraph::new();
+    let nodes: Vec<_> = (0..n).map(|i| graph.add_node(format!("b{}", i))).collect();
+    for i in 0..n - 1 {
+        let target = next() % n;
+        graph.add_edge(nodes[i], nodes[target], EdgeCondition::If(i));
+        graph.add_edge(nodes[i], nodes[i + 1], EdgeCondition::Else);
+    }
+    (graph, nodes[0])
+}
+
+#[bench]
+fn dense_random(b: &mut Bencher) {
+    let opts = StructuringOptions::default();
+    let (graph, entry) = dense_random_cfg(32, 1);
+    b.iter(|| structure_cfg(graph.clone(), entry, &opts).unwrap());
+}
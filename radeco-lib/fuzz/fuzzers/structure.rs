@@ -0,0 +1,10 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate radeco_lib;
+
+use radeco_lib::backend::ctrl_flow_struct::fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::structure_and_check(data);
+});
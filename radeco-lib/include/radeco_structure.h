@@ -0,0 +1,89 @@
+/*
+ * C interface to the control flow structuring of radeco-lib, see
+ * src/ffi.rs. Build the library with
+ *
+ *     cargo rustc --release --features ffi --crate-type staticlib
+ *
+ * A function is described by its basic blocks, the first being the entry,
+ * and the edges between them. Structuring it gives a JSON string holding
+ * the structured AST, in the format documented on `ast_json` in src/ffi.rs.
+ *
+ * Every function returns one of the RADECO_* codes. On failure, if
+ * `out_error` isn't NULL, a message is stored there, to be freed with
+ * radeco_string_free.
+ */
+
+#ifndef RADECO_STRUCTURE_H
+#define RADECO_STRUCTURE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RADECO_OK 0
+/* a pointer was NULL or an index out of range */
+#define RADECO_ERR_ARGUMENT 1
+/* the blocks and edges don't describe a function that can be structured */
+#define RADECO_ERR_CFG 2
+/* structuring panicked */
+#define RADECO_ERR_PANIC 3
+
+/* the only successor of a block */
+#define RADECO_EDGE_ALWAYS 0
+/* where the conditional jump ending a block goes if it is taken */
+#define RADECO_EDGE_TAKEN 1
+/* where it goes if it isn't */
+#define RADECO_EDGE_NOT_TAKEN 2
+
+typedef struct radeco_block {
+	uint64_t addr;
+	uint64_t size;
+} radeco_block;
+
+/* an edge between the blocks at the indices `from` and `to` */
+typedef struct radeco_edge {
+	size_t from;
+	size_t to;
+	/* one of the RADECO_EDGE_* constants */
+	uint32_t kind;
+} radeco_edge;
+
+typedef struct radeco_options {
+	/* collapse the acyclic single-entry single-exit regions first */
+	bool collapse_sese_regions;
+} radeco_options;
+
+typedef struct radeco_cfg radeco_cfg;
+
+/*
+ * Builds a function out of `n_blocks` blocks and `n_edges` edges and
+ * stores it in `*out_cfg`. A block has either no successors, one
+ * RADECO_EDGE_ALWAYS edge, or one RADECO_EDGE_TAKEN and one
+ * RADECO_EDGE_NOT_TAKEN edge.
+ */
+int radeco_cfg_new(const radeco_block *blocks, size_t n_blocks,
+		const radeco_edge *edges, size_t n_edges,
+		radeco_cfg **out_cfg, char **out_error);
+
+/* frees a function; NULL is ignored */
+void radeco_cfg_free(radeco_cfg *cfg);
+
+/*
+ * Structures `cfg` and stores the result as JSON in `*out_json`, to be
+ * freed with radeco_string_free. `opts` may be NULL for the defaults.
+ */
+int radeco_structure(const radeco_cfg *cfg, const radeco_options *opts,
+		char **out_json, char **out_error);
+
+/* frees a string returned by the functions above; NULL is ignored */
+void radeco_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
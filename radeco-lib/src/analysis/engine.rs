@@ -23,6 +23,8 @@ use crate::analysis::functions::infer_regusage::Inferer;
 use crate::analysis::inst_combine::Combiner;
 use crate::analysis::interproc::fixcall::CallFixer;
 use crate::analysis::sccp::SCCP;
+use crate::backend::ctrl_flow_struct::from_ssa;
+use crate::backend::ctrl_flow_struct::StructuringOptions;
 use crate::frontend::radeco_containers::{FunctionKind, RadecoFunction, RadecoModule};
 use crate::middle::regfile::SubRegisterFile;
 
@@ -70,14 +72,23 @@ pub trait Engine: Any + Debug {
 #[derive(Debug)]
 pub struct RadecoEngine {
     max_iteration: u32,
+    structuring: Option<StructuringOptions>,
 }
 
 impl RadecoEngine {
     pub fn new(max_iteration: u32) -> Self {
         RadecoEngine {
             max_iteration: max_iteration,
+            structuring: None,
         }
     }
+
+    /// Structure the control flow of every function once its SSA has been
+    /// simplified, storing the result on the function.
+    pub fn structuring(mut self, opts: StructuringOptions) -> Self {
+        self.structuring = Some(opts);
+        self
+    }
 }
 
 impl Engine for RadecoEngine {
@@ -196,6 +207,44 @@ impl Engine for RadecoEngine {
             }
         }
 
+        if let Some(ref opts) = self.structuring {
+            radeco_trace!("structuring: {}", rfn.name);
+            let res = from_ssa::structure(rfn.ssa(), opts);
+            rfn.set_structured(res);
+        }
+
         None
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::middle::ir_reader;
+    use serde_json;
+    use std::fs;
+    use std::sync::Arc;
+
+    const REGISTER_PROFILE: &'static str = "test_files/x86_register_profile.json";
+
+    fn load_rfn(file: &str) -> RadecoFunction {
+        let s = fs::read_to_string(REGISTER_PROFILE).unwrap();
+        let reg_profile = serde_json::from_str(&*s).unwrap();
+        let regfile = Arc::new(SubRegisterFile::new(&reg_profile));
+        let mut rfn = RadecoFunction::default();
+        *rfn.ssa_mut() = ir_reader::parse_il(&fs::read_to_string(file).unwrap(), regfile);
+        rfn
+    }
+
+    #[test]
+    fn structuring_stage() {
+        let mut rfn = load_rfn("test_files/loopy_main_ssa");
+        RadecoEngine::new(2).run_func(&mut rfn);
+        assert!(rfn.structured().is_none());
+
+        let engine = RadecoEngine::new(2).structuring(StructuringOptions::default());
+        engine.run_func(&mut rfn);
+        let sf = rfn.structured().unwrap().as_ref().unwrap();
+        assert!(sf.ast != Default::default());
+    }
+}
@@ -0,0 +1,269 @@
+//! Merges the `if`s next to each other that test the same condition, see
+//! [`merge`].
+
+use super::ast::AstNode;
+use super::condition::{Condition, Context};
+use super::invariants::blocks_in;
+
+/// Merges each `if` in a sequence in `ast` into the one before it if both
+/// test the same condition, `if (c) A; if (c) B` becoming `if (c) { A; B }`,
+/// or opposite ones, `if (c) A; if (!c) B` becoming `if (c) A else B`. The
+/// `else` arms of either `if` are merged the same way, and so are the `if`s
+/// that merging brings next to each other in the arms.
+///
+/// The second `if` tests its condition anew, so this only holds if the
+/// first one can't change it. `modifies(block, cond)` tells whether running
+/// `block` may change the value of the condition variable `cond`, like for
+/// [`invariants::simplify_guards`](super::invariants::simplify_guards); with
+/// [`from_r2::may_modify`](super::from_r2::may_modify) or `|_, _| true`,
+/// an `if` is only merged if no block in it could touch its condition. An
+/// `if` with a label in it can be jumped into without having tested the
+/// condition, so it isn't merged either.
+pub fn merge<'cd, B, T, V, F>(
+    cctx: Context<'cd, T>,
+    ast: AstNode<B, Condition<'cd, T>, V>,
+    modifies: &mut F,
+) -> AstNode<B, Condition<'cd, T>, V>
+where
+    F: FnMut(&B, &T) -> bool,
+{
+    use self::AstNode::*;
+    match ast {
+        Seq(seq) => merge_seq(cctx, seq, modifies),
+        Cond(c, t, oe) => {
+            let t = Box::new(merge(cctx, *t, modifies));
+            Cond(c, t, oe.map(|e| Box::new(merge(cctx, *e, modifies))))
+        }
+        Loop(lt, b) => Loop(lt, Box::new(merge(cctx, *b, modifies))),
+        For(i, c, u, b) => For(i, c, u, Box::new(merge(cctx, *b, modifies))),
+        Switch(v, cases, default) => Switch(
+            v,
+            cases
+                .into_iter()
+                .map(|(vs, a)| (vs, merge(cctx, a, modifies)))
+                .collect(),
+            Box::new(merge(cctx, *default, modifies)),
+        ),
+        Try(b, h) => Try(Box::new(merge(cctx, *b, modifies)), h),
+        Unstructured(members) => Unstructured(
+            members
+                .into_iter()
+                .map(|(l, a)| (l, merge(cctx, a, modifies)))
+                .collect(),
+        ),
+        ast @ BasicBlock(_)
+        | ast @ Break
+        | ast @ Continue
+        | ast @ Return
+        | ast @ TailCall(_)
+        | ast @ IndirectJump(_)
+        | ast @ Goto(_)
+        | ast @ Label(_) => ast,
+    }
+}
+
+fn merge_seq<'cd, B, T, V, F>(
+    cctx: Context<'cd, T>,
+    seq: Vec<AstNode<B, Condition<'cd, T>, V>>,
+    modifies: &mut F,
+) -> AstNode<B, Condition<'cd, T>, V>
+where
+    F: FnMut(&B, &T) -> bool,
+{
+    use self::AstNode::*;
+    let mut new_seq: Vec<AstNode<B, Condition<'cd, T>, V>> = Vec::with_capacity(seq.len());
+    for a in seq {
+        let a = merge(cctx, a, modifies);
+        let (c2, t2, oe2) = match a {
+            Cond(c2, t2, oe2) => (c2, t2, oe2),
+            a => {
+                new_seq.push(a);
+                continue;
+            }
+        };
+        let opt_c = match new_seq.last() {
+            Some(Cond(c, _, _)) if c2 == *c || c2 == cctx.mk_not(*c) => Some(*c),
+            _ => None,
+        };
+        let c = match opt_c {
+            Some(c) if keeps(new_seq.last().unwrap(), c, modifies) => c,
+            _ => {
+                new_seq.push(Cond(c2, t2, oe2));
+                continue;
+            }
+        };
+        let (t1, oe1) = match new_seq.pop() {
+            Some(Cond(_, t1, oe1)) => (t1, oe1),
+            _ => unreachable!(),
+        };
+        let (t2, e2) = if c2 == c {
+            (Some(t2), oe2)
+        } else {
+            (oe2, Some(t2))
+        };
+        let then = concat(cctx, Some(t1), t2, modifies);
+        let opt_else = match concat(cctx, oe1, e2, modifies) {
+            Seq(ref s) if s.is_empty() => None,
+            e => Some(Box::new(e)),
+        };
+        new_seq.push(Cond(c, Box::new(then), opt_else));
+    }
+    if new_seq.len() == 1 {
+        new_seq.pop().unwrap()
+    } else {
+        Seq(new_seq)
+    }
+}
+
+/// Whether `cond` still has the same value after the arms of `first` run.
+fn keeps<'cd, B, T, V, F>(
+    first: &AstNode<B, Condition<'cd, T>, V>,
+    cond: Condition<'cd, T>,
+    modifies: &mut F,
+) -> bool
+where
+    F: FnMut(&B, &T) -> bool,
+{
+    if has_label(first) {
+        return false;
+    }
+    let mut blocks = Vec::new();
+    blocks_in(first, &mut blocks);
+    let vars = cond.vars();
+    blocks
+        .iter()
+        .all(|b| vars.iter().all(|v| !modifies(b, &**v)))
+}
+
+/// `a; b`, with the `if`s that meet in the middle merged.
+fn concat<'cd, B, T, V, F>(
+    cctx: Context<'cd, T>,
+    a: Option<Box<AstNode<B, Condition<'cd, T>, V>>>,
+    b: Option<Box<AstNode<B, Condition<'cd, T>, V>>>,
+    modifies: &mut F,
+) -> AstNode<B, Condition<'cd, T>, V>
+where
+    F: FnMut(&B, &T) -> bool,
+{
+    let mut seq = Vec::new();
+    for ast in a.into_iter().chain(b) {
+        match *ast {
+            AstNode::Seq(s) => seq.extend(s),
+            ast => seq.push(ast),
+        }
+    }
+    merge_seq(cctx, seq, modifies)
+}
+
+/// Whether `ast` has a `Label` anywhere in it.
+pub(super) fn has_label<B, C, V>(ast: &AstNode<B, C, V>) -> bool {
+    use self::AstNode::*;
+    match ast {
+        Label(_) | Unstructured(_) => true,
+        Seq(seq) => seq.iter().any(has_label),
+        Cond(_, t, oe) => has_label(t) || oe.iter().any(|e| has_label(e)),
+        Loop(_, b) | For(_, _, _, b) | Try(b, _) => has_label(b),
+        Switch(_, cases, default) => cases.iter().any(|(_, a)| has_label(a)) || has_label(default),
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) => {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::condition::Storage;
+
+    type Ast<'cd> = AstNode<&'static str, Condition<'cd, &'static str>, ()>;
+
+    /// `block` assigns to `var`, e.g. `a = 0` to `a`.
+    fn modifies(block: &&str, var: &&str) -> bool {
+        block.starts_with(&format!("{} = ", var))
+    }
+
+    fn bb(s: &'static str) -> Box<Ast<'static>> {
+        Box::new(BasicBlock(s))
+    }
+
+    #[test]
+    fn same_condition() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // s; if (c) a; if (c) b; if (c) d; t
+        let ast: Ast = Seq(vec![
+            BasicBlock("s"),
+            Cond(c, bb("a"), None),
+            Cond(c, bb("b"), None),
+            Cond(c, bb("d"), None),
+            BasicBlock("t"),
+        ]);
+        assert_eq!(
+            merge(cctx, ast, &mut modifies),
+            Seq(vec![
+                BasicBlock("s"),
+                Cond(
+                    c,
+                    Box::new(Seq(vec![BasicBlock("a"), BasicBlock("b"), BasicBlock("d")])),
+                    None
+                ),
+                BasicBlock("t"),
+            ])
+        );
+    }
+
+    #[test]
+    fn opposite_conditions() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+        let d = cctx.mk_var(cctx.new_var("d"));
+
+        // if (c) { if (d) a; } if (!c) b; if (c) { if (d) e; }
+        let ast: Ast = Seq(vec![
+            Cond(c, Box::new(Cond(d, bb("a"), None)), None),
+            Cond(cctx.mk_not(c), bb("b"), None),
+            Cond(c, Box::new(Cond(d, bb("e"), None)), None),
+        ]);
+        assert_eq!(
+            merge(cctx, ast, &mut modifies),
+            Cond(
+                c,
+                Box::new(Cond(
+                    d,
+                    Box::new(Seq(vec![BasicBlock("a"), BasicBlock("e")])),
+                    None
+                )),
+                Some(bb("b")),
+            )
+        );
+    }
+
+    #[test]
+    fn modified_condition() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // the first `if` may change `c`, the second one doesn't
+        let ast: Ast = Seq(vec![
+            Cond(c, bb("c = 0"), None),
+            Cond(c, bb("b"), None),
+            Cond(cctx.mk_not(c), bb("d"), None),
+        ]);
+        assert_eq!(
+            merge(cctx, ast, &mut modifies),
+            Seq(vec![
+                Cond(c, bb("c = 0"), None),
+                Cond(c, bb("b"), Some(bb("d")))
+            ])
+        );
+
+        // without an oracle, nothing is known not to change it
+        let ast: Ast = Seq(vec![Cond(c, bb("a"), None), Cond(c, bb("b"), None)]);
+        assert_eq!(merge(cctx, ast.clone(), &mut |_, _| true), ast);
+    }
+}
@@ -1,24 +1,171 @@
+use std::cmp;
+use std::iter;
+
 // B: basic block
 // C: condition
 // V: variable
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum AstNode<B, C, V> {
     BasicBlock(B),
     Seq(Vec<AstNode<B, C, V>>),
     Cond(C, Box<AstNode<B, C, V>>, Option<Box<AstNode<B, C, V>>>),
     Loop(LoopType<C>, Box<AstNode<B, C, V>>),
+    /// `for (init; cond; update) body`: `init`, then `body` and `update`
+    /// for as long as `cond` holds. A `continue` in `body` goes on to
+    /// `update`. Only [`for_loops::recover`](super::for_loops::recover)
+    /// makes these.
+    For(B, C, B, Box<AstNode<B, C, V>>),
     Break,
     Switch(V, Vec<(ValueSet, AstNode<B, C, V>)>, Box<AstNode<B, C, V>>),
+    /// `continue` the nearest enclosing loop
+    Continue,
+    /// return from the function
+    Return,
+    /// return the result of the call that ends the block, i.e. a tail call
+    TailCall(B),
+    /// jump to an address computed by the block, whose possible targets
+    /// couldn't be resolved
+    IndirectJump(B),
+    /// jump to the matching `Label`
+    Goto(LabelId),
+    /// the target of any `Goto`s with the same `LabelId`
+    Label(LabelId),
+    /// code whose exceptions unwind to the landing pad of a handler
+    Try(Box<AstNode<B, C, V>>, HandlerId),
+    /// a region that failed to structure, see
+    /// [`FailedRegion`](super::FailedRegion): its nodes, each with its
+    /// label, in the order they run in unless one jumps away with a `Goto`,
+    /// which is how their edges are kept. Falling off the last one leaves
+    /// the region. Only
+    /// [`ControlFlowGraph::structure_by_region`](super::ControlFlowGraph::structure_by_region)
+    /// makes these.
+    Unstructured(Vec<(LabelId, AstNode<B, C, V>)>),
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LabelId(pub usize);
+
+/// Which of the handlers structured along with a function a `Try` unwinds
+/// to, see
+/// [`ControlFlowGraph::structure_with_handlers`](super::ControlFlowGraph::structure_with_handlers).
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct HandlerId(pub usize);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum LoopType<C> {
     PreChecked(C),
     PostChecked(C),
     Endless,
 }
 
-pub type ValueSet = (); // XXX
+/// A set of `u64`s, stored as sorted, disjoint, non-adjacent inclusive
+/// ranges.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ValueSet {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl ValueSet {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    pub fn full() -> Self {
+        Self::range(0, u64::MAX)
+    }
+
+    pub fn single(val: u64) -> Self {
+        Self::range(val, val)
+    }
+
+    /// The values from `lo` to `hi`, inclusive.
+    pub fn range(lo: u64, hi: u64) -> Self {
+        if lo <= hi {
+            ValueSet {
+                ranges: vec![(lo, hi)],
+            }
+        } else {
+            Self::empty()
+        }
+    }
+
+    /// The sorted, disjoint, inclusive ranges making up this set.
+    pub fn ranges(&self) -> &[(u64, u64)] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.ranges == [(0, u64::MAX)]
+    }
+
+    /// The smallest value in the set.
+    pub fn min(&self) -> Option<u64> {
+        self.ranges.first().map(|r| r.0)
+    }
+
+    pub fn contains(&self, val: u64) -> bool {
+        self.ranges.iter().any(|&(lo, hi)| lo <= val && val <= hi)
+    }
+
+    pub fn complement(&self) -> Self {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 1);
+        let mut next = Some(0);
+        for &(lo, hi) in &self.ranges {
+            if let Some(n) = next {
+                if n < lo {
+                    ranges.push((n, lo - 1));
+                }
+            }
+            next = hi.checked_add(1);
+        }
+        if let Some(n) = next {
+            ranges.push((n, u64::MAX));
+        }
+        ValueSet { ranges }
+    }
+
+    /// The values of `domain` that aren't in this set.
+    pub fn complement_in(&self, domain: &Self) -> Self {
+        domain.intersection(&self.complement())
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        let mut all: Vec<_> = self.ranges.iter().chain(&other.ranges).cloned().collect();
+        all.sort_unstable();
+        let mut ranges: Vec<(u64, u64)> = Vec::with_capacity(all.len());
+        for (lo, hi) in all {
+            match ranges.last_mut() {
+                Some(last) if lo <= last.1.saturating_add(1) => {
+                    last.1 = last.1.max(hi);
+                }
+                _ => ranges.push((lo, hi)),
+            }
+        }
+        ValueSet { ranges }
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        self.complement().union(&other.complement()).complement()
+    }
+
+    pub fn is_disjoint(&self, other: &Self) -> bool {
+        self.intersection(other).is_empty()
+    }
+}
+
+impl iter::FromIterator<u64> for ValueSet {
+    fn from_iter<I: IntoIterator<Item = u64>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(Self::empty(), |acc, v| acc.union(&Self::single(v)))
+    }
+}
 
 impl<B, C, V> Default for AstNode<B, C, V> {
     /// Creates a no-op node.
@@ -26,3 +173,221 @@ impl<B, C, V> Default for AstNode<B, C, V> {
         AstNode::Seq(Vec::new())
     }
 }
+
+impl<B, C, V> AstNode<B, C, V> {
+    /// Replaces every condition in `self` with the result of calling `f` on
+    /// it, in pre-order.
+    pub fn map_conds<D, F>(self, f: &mut F) -> AstNode<B, D, V>
+    where
+        F: FnMut(C) -> D,
+    {
+        use self::AstNode::*;
+        match self {
+            BasicBlock(b) => BasicBlock(b),
+            Seq(seq) => Seq(seq.into_iter().map(|a| a.map_conds(f)).collect()),
+            Cond(c, t, oe) => {
+                let c = f(c);
+                let t = Box::new(t.map_conds(f));
+                Cond(c, t, oe.map(|e| Box::new(e.map_conds(f))))
+            }
+            Loop(lt, b) => {
+                let lt = match lt {
+                    LoopType::PreChecked(c) => LoopType::PreChecked(f(c)),
+                    LoopType::PostChecked(c) => LoopType::PostChecked(f(c)),
+                    LoopType::Endless => LoopType::Endless,
+                };
+                Loop(lt, Box::new(b.map_conds(f)))
+            }
+            For(i, c, u, b) => {
+                let c = f(c);
+                For(i, c, u, Box::new(b.map_conds(f)))
+            }
+            Break => Break,
+            Switch(v, cases, default) => Switch(
+                v,
+                cases
+                    .into_iter()
+                    .map(|(vs, a)| (vs, a.map_conds(f)))
+                    .collect(),
+                Box::new(default.map_conds(f)),
+            ),
+            Continue => Continue,
+            Return => Return,
+            TailCall(b) => TailCall(b),
+            IndirectJump(b) => IndirectJump(b),
+            Goto(l) => Goto(l),
+            Label(l) => Label(l),
+            Try(b, h) => Try(Box::new(b.map_conds(f)), h),
+            Unstructured(members) => Unstructured(
+                members
+                    .into_iter()
+                    .map(|(l, a)| (l, a.map_conds(f)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Replaces every block in `self`, those of `For`s, `TailCall`s and
+    /// `IndirectJump`s included, with the result of calling `f` on it, in
+    /// pre-order.
+    pub fn map_blocks<A, F>(self, f: &mut F) -> AstNode<A, C, V>
+    where
+        F: FnMut(B) -> A,
+    {
+        use self::AstNode::*;
+        match self {
+            BasicBlock(b) => BasicBlock(f(b)),
+            Seq(seq) => Seq(seq.into_iter().map(|a| a.map_blocks(f)).collect()),
+            Cond(c, t, oe) => {
+                let t = Box::new(t.map_blocks(f));
+                Cond(c, t, oe.map(|e| Box::new(e.map_blocks(f))))
+            }
+            Loop(lt, b) => Loop(lt, Box::new(b.map_blocks(f))),
+            For(i, c, u, b) => {
+                let i = f(i);
+                let u = f(u);
+                For(i, c, u, Box::new(b.map_blocks(f)))
+            }
+            Break => Break,
+            Switch(v, cases, default) => Switch(
+                v,
+                cases
+                    .into_iter()
+                    .map(|(vs, a)| (vs, a.map_blocks(f)))
+                    .collect(),
+                Box::new(default.map_blocks(f)),
+            ),
+            Continue => Continue,
+            Return => Return,
+            TailCall(b) => TailCall(f(b)),
+            IndirectJump(b) => IndirectJump(f(b)),
+            Goto(l) => Goto(l),
+            Label(l) => Label(l),
+            Try(b, h) => Try(Box::new(b.map_blocks(f)), h),
+            Unstructured(members) => Unstructured(
+                members
+                    .into_iter()
+                    .map(|(l, a)| (l, a.map_blocks(f)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Counts what `self` is made of, see [`AstMetrics`].
+    pub fn metrics(&self) -> AstMetrics {
+        use self::AstNode::*;
+        let nested = |inner: AstMetrics| AstMetrics {
+            max_depth: inner.max_depth + 1,
+            ..inner
+        };
+        let leaf = AstMetrics::default();
+        match self {
+            BasicBlock(_) | TailCall(_) | IndirectJump(_) => AstMetrics { blocks: 1, ..leaf },
+            Break | Continue | Return | Label(_) => leaf,
+            Goto(_) => AstMetrics { gotos: 1, ..leaf },
+            Seq(seq) => seq
+                .iter()
+                .map(AstNode::metrics)
+                .fold(leaf, AstMetrics::sibling),
+            Unstructured(members) => members
+                .iter()
+                .map(|(_, a)| a.metrics())
+                .fold(leaf, AstMetrics::sibling),
+            Cond(_, t, oe) => {
+                let e = oe.as_ref().map_or(leaf, |e| e.metrics());
+                AstMetrics { conds: 1, ..leaf }.sibling(nested(t.metrics().sibling(e)))
+            }
+            Loop(_, b) => AstMetrics { loops: 1, ..leaf }.sibling(nested(b.metrics())),
+            For(_, _, _, b) => AstMetrics {
+                blocks: 2,
+                loops: 1,
+                ..leaf
+            }
+            .sibling(nested(b.metrics())),
+            Switch(_, cases, default) => {
+                let arms = cases
+                    .iter()
+                    .map(|(_, a)| a.metrics())
+                    .fold(default.metrics(), AstMetrics::sibling);
+                AstMetrics {
+                    switches: 1,
+                    ..leaf
+                }
+                .sibling(nested(arms))
+            }
+            Try(b, _) => b.metrics(),
+        }
+    }
+
+    /// Replaces the variable of every `Switch` in `self` with the result of
+    /// calling `f` on it, in pre-order.
+    pub fn map_vars<W, F>(self, f: &mut F) -> AstNode<B, C, W>
+    where
+        F: FnMut(V) -> W,
+    {
+        use self::AstNode::*;
+        match self {
+            BasicBlock(b) => BasicBlock(b),
+            Seq(seq) => Seq(seq.into_iter().map(|a| a.map_vars(f)).collect()),
+            Cond(c, t, oe) => {
+                let t = Box::new(t.map_vars(f));
+                Cond(c, t, oe.map(|e| Box::new(e.map_vars(f))))
+            }
+            Loop(lt, b) => Loop(lt, Box::new(b.map_vars(f))),
+            For(i, c, u, b) => For(i, c, u, Box::new(b.map_vars(f))),
+            Break => Break,
+            Switch(v, cases, default) => Switch(
+                f(v),
+                cases
+                    .into_iter()
+                    .map(|(vs, a)| (vs, a.map_vars(f)))
+                    .collect(),
+                Box::new(default.map_vars(f)),
+            ),
+            Continue => Continue,
+            Return => Return,
+            TailCall(b) => TailCall(b),
+            IndirectJump(b) => IndirectJump(b),
+            Goto(l) => Goto(l),
+            Label(l) => Label(l),
+            Try(b, h) => Try(Box::new(b.map_vars(f)), h),
+            Unstructured(members) => Unstructured(
+                members
+                    .into_iter()
+                    .map(|(l, a)| (l, a.map_vars(f)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// What an AST is made of, to compare how well e.g. different versions or
+/// options structure the same function.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct AstMetrics {
+    /// `BasicBlock`s, `TailCall`s and `IndirectJump`s, and the `init` and
+    /// `update` of `For`s
+    pub blocks: usize,
+    /// `Cond`s, with or without an `else`
+    pub conds: usize,
+    /// `Loop`s and `For`s
+    pub loops: usize,
+    pub switches: usize,
+    pub gotos: usize,
+    /// how deeply `Cond`s, loops and `Switch`es are nested: 0 for none
+    pub max_depth: usize,
+}
+
+impl AstMetrics {
+    /// The metrics of two ASTs next to each other.
+    fn sibling(self, other: Self) -> Self {
+        AstMetrics {
+            blocks: self.blocks + other.blocks,
+            conds: self.conds + other.conds,
+            loops: self.loops + other.loops,
+            switches: self.switches + other.switches,
+            gotos: self.gotos + other.gotos,
+            max_depth: cmp::max(self.max_depth, other.max_depth),
+        }
+    }
+}
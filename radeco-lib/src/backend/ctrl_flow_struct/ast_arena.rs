@@ -0,0 +1,36 @@
+//! See [`AstArena`].
+
+use super::ast_context::AstContext;
+use super::AstNode;
+
+/// Owns the `AstNode`s of the nodes of a region while the region is being
+/// refined, so that refinement only has to move small [`AstRef`]s around
+/// instead of whole ASTs. Every `AstNode` is moved in and taken back out
+/// exactly once. The nodes of the [`ControlFlowGraph`](super::ControlFlowGraph)
+/// itself keep their whole ASTs: each region gets an arena of its own, which
+/// only lives while `structure_acyclic_sese_region` collapses it.
+pub(super) struct AstArena<'cd, A: AstContext> {
+    nodes: Vec<Option<AstNode<'cd, A>>>,
+}
+
+/// A handle to an `AstNode` in an [`AstArena`]. Deliberately not `Copy`, so
+/// each one can only be taken once.
+#[derive(Debug)]
+pub(super) struct AstRef(usize);
+
+impl<'cd, A: AstContext> AstArena<'cd, A> {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn alloc(&mut self, ast: AstNode<'cd, A>) -> AstRef {
+        self.nodes.push(Some(ast));
+        AstRef(self.nodes.len() - 1)
+    }
+
+    pub fn take(&mut self, r: AstRef) -> AstNode<'cd, A> {
+        self.nodes[r.0].take().expect("AstRef taken twice")
+    }
+}
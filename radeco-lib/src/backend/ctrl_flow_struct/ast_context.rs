@@ -1,8 +1,81 @@
+use super::idioms::Cmp;
+
+use std::ops::Range;
+
 pub trait AstContext {
+    /// The payload of a code node. Structuring keeps these aside and only
+    /// moves handles to them around, and only copies them through
+    /// [`AstContextMut::clone_block`].
     type Block;
-    type Variable;
+    /// Switch recovery compares these and may clone one into more than one
+    /// `Switch`.
+    type Variable: Clone + PartialEq;
     type BoolVariable;
     type Condition: 'static;
+
+    /// Returns the constant that `block` assigns to `var`, if it is known
+    /// to, for [`StructuringReport::state_machines`](super::StructuringReport::state_machines).
+    /// By default, nothing is known.
+    fn assigned_value(&self, _block: &Self::Block, _var: &Self::Variable) -> Option<u64> {
+        None
+    }
+
+    /// Whether running `block` may change the value of `cond`, as for
+    /// [`invariants::unrotate_loops`](super::invariants::unrotate_loops) when
+    /// loops are refined. By default, anything may change anything.
+    fn may_modify(&self, _block: &Self::Block, _cond: &Self::Condition) -> bool {
+        true
+    }
+
+    /// Returns the operands of `cond` if it is a comparison of two, for
+    /// the [idiom matchers](super::matchers). By default, nothing is known,
+    /// and the same goes for the methods below; see
+    /// [`MatchOracle`](super::matchers::MatchOracle) for what each means.
+    fn comparison(&self, _cond: &Self::Condition) -> Option<(String, Cmp, String)> {
+        None
+    }
+
+    fn assignment(&self, _block: &Self::Block) -> Option<(String, String)> {
+        None
+    }
+
+    fn negation(&self, _block: &Self::Block) -> Option<(String, String)> {
+        None
+    }
+
+    fn increment(&self, _block: &Self::Block) -> Option<(String, i64)> {
+        None
+    }
+
+    fn block_range(&self, _block: &Self::Block) -> Option<Range<u64>> {
+        None
+    }
+
+    fn is_pure_reread(&self, _block: &Self::Block) -> bool {
+        false
+    }
+
+    /// Returns a C expression for `cond`, for the summaries of
+    /// [`StructuringOptions::summarize_loop_exits`](super::StructuringOptions::summarize_loop_exits).
+    fn describe_cond(&self, _cond: &Self::Condition) -> Option<String> {
+        None
+    }
+
+    /// The value `cond` always has, if it is constant, e.g. because it is an
+    /// opaque predicate that was resolved. Structuring then drops the edge
+    /// that is never taken, like
+    /// [`ControlFlowGraph::set_constant`](super::ControlFlowGraph::set_constant).
+    fn constant_value(&self, _cond: &Self::Condition) -> Option<bool> {
+        None
+    }
+
+    /// Whether running `block` does more than compute what the branch after
+    /// it tests, for
+    /// [`StructuringOptions::split_latches`](super::StructuringOptions::split_latches).
+    /// By default, any block may.
+    fn has_side_effects(&self, _block: &Self::Block) -> bool {
+        true
+    }
 }
 
 pub trait AstContextMut: AstContext {
@@ -17,6 +90,25 @@ pub trait AstContextMut: AstContext {
     /// value, but it doesn't matter what.
     fn mk_fresh_bool_var(&mut self) -> Self::BoolVariable;
 
+    /// Like [`mk_fresh_var`](Self::mk_fresh_var), for a variable that the
+    /// [`Namer`](super::naming::Namer) of the options named `name`. By
+    /// default, the name is ignored.
+    fn mk_named_var(&mut self, _name: &str) -> Self::Variable {
+        self.mk_fresh_var()
+    }
+
+    /// Like [`mk_fresh_var_zeroed`](Self::mk_fresh_var_zeroed), for a
+    /// variable named `name`, see [`mk_named_var`](Self::mk_named_var).
+    fn mk_named_var_zeroed(&mut self, _name: &str) -> Self::Variable {
+        self.mk_fresh_var_zeroed()
+    }
+
+    /// Like [`mk_fresh_bool_var`](Self::mk_fresh_bool_var), for a variable
+    /// named `name`, see [`mk_named_var`](Self::mk_named_var).
+    fn mk_named_bool_var(&mut self, _name: &str) -> Self::BoolVariable {
+        self.mk_fresh_bool_var()
+    }
+
     /// Returns a `Condition` that represents `var` being equal to `val`.
     fn mk_cond_equals(&mut self, var: &Self::Variable, val: u64) -> Self::Condition;
 
@@ -33,4 +125,42 @@ pub trait AstContextMut: AstContext {
         var: &Self::BoolVariable,
         cond: &Self::Condition,
     ) -> Self::Block;
+
+    /// Returns a copy of `block`, for
+    /// [`StructuringOptions::duplicate_tails`](super::StructuringOptions::duplicate_tails),
+    /// or `None` if blocks can't be copied, which is the default.
+    fn clone_block(&mut self, _block: &Self::Block) -> Option<Self::Block> {
+        None
+    }
+
+    /// Returns a copy of `cond`, which is tested on its own, for
+    /// [`StructuringOptions::split_shared_regions`](super::StructuringOptions::split_shared_regions),
+    /// or `None` if conditions can't be copied, which is the default.
+    fn clone_cond(&mut self, _cond: &Self::Condition) -> Option<Self::Condition> {
+        None
+    }
+
+    /// Returns a block that runs `first` and then `second`, whose code
+    /// follows right after, for
+    /// [`StructuringOptions::merge_contiguous_blocks`](super::StructuringOptions::merge_contiguous_blocks).
+    /// Its range must cover both of theirs, and whatever the context keeps
+    /// about either, such as comments, must carry over to it; otherwise,
+    /// and by default, it returns `None` and the blocks stay apart.
+    fn merge_blocks(&mut self, _first: &Self::Block, _second: &Self::Block) -> Option<Self::Block> {
+        None
+    }
+
+    /// Splits `block`, which the branch on `cond` follows, into a block
+    /// with its side effects and a condition that does the rest of `block`
+    /// and then tests `cond`, for
+    /// [`StructuringOptions::split_latches`](super::StructuringOptions::split_latches).
+    /// Each keeps the addresses of its part, the block in its range. If
+    /// `block` can't be split, which is the default, returns `None`.
+    fn split_latch(
+        &mut self,
+        _block: &Self::Block,
+        _cond: &Self::Condition,
+    ) -> Option<(Self::Block, Self::Condition)> {
+        None
+    }
 }
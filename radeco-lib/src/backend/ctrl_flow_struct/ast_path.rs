@@ -0,0 +1,259 @@
+//! Paths to the nodes of an AST, for tools that edit one of them in place,
+//! see [`AstPath`].
+
+use super::ast::AstNode;
+use super::decisions::AstNodeId;
+
+use std::fmt;
+
+/// Which child of a node to go on to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Selector {
+    /// the statement at this index of a `Seq`
+    SeqChild(usize),
+    CondThen,
+    /// the `else` branch of a `Cond` that has one
+    CondElse,
+    /// the body of a `Loop` or a `For`
+    LoopBody,
+    /// the code of the case at this index of a `Switch`
+    SwitchArm(usize),
+    SwitchDefault,
+    /// the code that a `Try` runs
+    TryBody,
+    /// the code of the node at this index of an `Unstructured` region
+    Member(usize),
+}
+
+/// Where a node is in an AST: the children to go on to from the root to get
+/// to it. The empty path leads to the root.
+///
+/// A path only holds for the AST it was found in: once the AST changes,
+/// e.g. in a refinement, it may lead elsewhere or nowhere. Where it leads
+/// nowhere, [`AstNode::get`] and the others fail, see [`StalePath`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AstPath(pub Vec<Selector>);
+
+impl AstPath {
+    /// The path to the node of `ast` that has the [`AstNodeId`] `id`, as
+    /// the report of structuring gives them, if there is one.
+    pub fn of<B, C, V>(ast: &AstNode<B, C, V>, id: AstNodeId) -> Option<Self> {
+        fn go<B, C, V>(ast: &AstNode<B, C, V>, path: &mut Vec<Selector>, left: &mut usize) -> bool {
+            use self::AstNode::*;
+            use self::Selector::*;
+            if *left == 0 {
+                return true;
+            }
+            *left -= 1;
+            let mut child = |sel, a: &AstNode<B, C, V>| {
+                path.push(sel);
+                let found = go(a, path, left);
+                if !found {
+                    path.pop();
+                }
+                found
+            };
+            match ast {
+                Seq(seq) => seq.iter().enumerate().any(|(i, a)| child(SeqChild(i), a)),
+                Cond(_, t, oe) => {
+                    child(CondThen, t) || oe.as_ref().is_some_and(|e| child(CondElse, e))
+                }
+                Loop(_, b) | For(_, _, _, b) => child(LoopBody, b),
+                Try(b, _) => child(TryBody, b),
+                Unstructured(members) => members
+                    .iter()
+                    .enumerate()
+                    .any(|(i, (_, a))| child(Member(i), a)),
+                Switch(_, cases, default) => {
+                    cases
+                        .iter()
+                        .enumerate()
+                        .any(|(i, (_, a))| child(SwitchArm(i), a))
+                        || child(SwitchDefault, default)
+                }
+                BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_)
+                | Goto(_) | Label(_) => false,
+            }
+        }
+        let mut path = Vec::new();
+        if go(ast, &mut path, &mut id.0.clone()) {
+            Some(AstPath(path))
+        } else {
+            None
+        }
+    }
+
+    /// The path to the child `sel` of the node that `self` leads to.
+    pub fn child(&self, sel: Selector) -> Self {
+        let mut path = self.0.clone();
+        path.push(sel);
+        AstPath(path)
+    }
+}
+
+/// A path that doesn't lead to a node of the AST it was used on, since the
+/// node that its first `depth` selectors lead to has no such child as the
+/// next one selects.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StalePath {
+    pub depth: usize,
+}
+
+impl fmt::Display for StalePath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "stale path: no such child at depth {}", self.depth)
+    }
+}
+
+impl<B, C, V> AstNode<B, C, V> {
+    /// The node of `self` that `path` leads to, if it leads to one.
+    pub fn get(&self, path: &AstPath) -> Option<&Self> {
+        path.0.iter().try_fold(self, |a, &sel| a.child(sel))
+    }
+
+    /// Like [`get`](Self::get), but for changing the node.
+    pub fn get_mut(&mut self, path: &AstPath) -> Option<&mut Self> {
+        self.walk_mut(path).ok()
+    }
+
+    /// Replaces the node of `self` that `path` leads to with `new`, and
+    /// returns it, or fails without changing anything if `path` doesn't
+    /// lead to one.
+    pub fn replace_at(&mut self, path: &AstPath, new: Self) -> Result<Self, StalePath> {
+        Ok(std::mem::replace(self.walk_mut(path)?, new))
+    }
+
+    fn walk_mut(&mut self, path: &AstPath) -> Result<&mut Self, StalePath> {
+        let mut ast = self;
+        for (depth, &sel) in path.0.iter().enumerate() {
+            ast = ast.child_mut(sel).ok_or(StalePath { depth })?;
+        }
+        Ok(ast)
+    }
+
+    fn child(&self, sel: Selector) -> Option<&Self> {
+        use self::AstNode::*;
+        use self::Selector::*;
+        match (self, sel) {
+            (Seq(seq), SeqChild(i)) => seq.get(i),
+            (Cond(_, t, _), CondThen) => Some(t),
+            (Cond(_, _, Some(e)), CondElse) => Some(e),
+            (Loop(_, b), LoopBody) | (For(_, _, _, b), LoopBody) | (Try(b, _), TryBody) => Some(b),
+            (Switch(_, cases, _), SwitchArm(i)) => cases.get(i).map(|(_, a)| a),
+            (Switch(_, _, default), SwitchDefault) => Some(default),
+            (Unstructured(members), Member(i)) => members.get(i).map(|(_, a)| a),
+            _ => None,
+        }
+    }
+
+    fn child_mut(&mut self, sel: Selector) -> Option<&mut Self> {
+        use self::AstNode::*;
+        use self::Selector::*;
+        match (self, sel) {
+            (Seq(seq), SeqChild(i)) => seq.get_mut(i),
+            (Cond(_, t, _), CondThen) => Some(t),
+            (Cond(_, _, Some(e)), CondElse) => Some(e),
+            (Loop(_, b), LoopBody) | (For(_, _, _, b), LoopBody) | (Try(b, _), TryBody) => Some(b),
+            (Switch(_, cases, _), SwitchArm(i)) => cases.get_mut(i).map(|(_, a)| a),
+            (Switch(_, _, default), SwitchDefault) => Some(default),
+            (Unstructured(members), Member(i)) => members.get_mut(i).map(|(_, a)| a),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Selector::*;
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::{LoopType, ValueSet};
+    use crate::backend::ctrl_flow_struct::common_tails;
+    use crate::backend::ctrl_flow_struct::condition::{Condition, Storage};
+    use crate::backend::ctrl_flow_struct::decisions;
+
+    type Ast<'cd> = AstNode<&'static str, Condition<'cd, &'static str>, &'static str>;
+
+    #[test]
+    fn replace_deep() {
+        use self::AstNode::*;
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // s; for (;;) { switch (x) { case 1: if (c) { a; } default: d; } }
+        let mut ast: Ast = Seq(vec![
+            BasicBlock("s"),
+            Loop(
+                LoopType::Endless,
+                Box::new(Switch(
+                    "x",
+                    vec![(
+                        ValueSet::single(1),
+                        Cond(c, Box::new(BasicBlock("a")), None),
+                    )],
+                    Box::new(BasicBlock("d")),
+                )),
+            ),
+        ]);
+        let path = AstPath(vec![SeqChild(1), LoopBody, SwitchArm(0)]);
+        assert!(matches!(ast.get(&path), Some(Cond(..))));
+        let old = ast.replace_at(&path, BasicBlock("b")).unwrap();
+        assert!(matches!(old, Cond(..)));
+        assert_eq!(ast.get(&path), Some(&BasicBlock("b")));
+        *ast.get_mut(&AstPath(vec![SeqChild(1), LoopBody, SwitchDefault]))
+            .unwrap() = Break;
+        match &ast {
+            Seq(seq) => assert_eq!(
+                seq[1],
+                Loop(
+                    LoopType::Endless,
+                    Box::new(Switch(
+                        "x",
+                        vec![(ValueSet::single(1), BasicBlock("b"))],
+                        Box::new(Break),
+                    ))
+                )
+            ),
+            _ => unreachable!(),
+        }
+
+        // the paths of the nodes by their ids
+        for (i, a) in decisions::preorder(&ast).into_iter().enumerate() {
+            let path = AstPath::of(&ast, decisions::AstNodeId(i)).unwrap();
+            assert_eq!(ast.get(&path), Some(a));
+        }
+        assert_eq!(AstPath::of(&ast, decisions::AstNodeId(6)), None);
+        assert_eq!(
+            AstPath::of(&ast, decisions::AstNodeId(0)),
+            Some(AstPath::default())
+        );
+    }
+
+    #[test]
+    fn stale_path() {
+        use self::AstNode::*;
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // if (c) { a; t; } else { b; t; }
+        let ast: Ast = Cond(
+            c,
+            Box::new(Seq(vec![BasicBlock("a"), BasicBlock("t")])),
+            Some(Box::new(Seq(vec![BasicBlock("b"), BasicBlock("t")]))),
+        );
+        let path = AstPath::default().child(CondElse).child(SeqChild(1));
+        assert_eq!(ast.get(&path), Some(&BasicBlock("t")));
+
+        // `t` is moved out of the arms, which are no longer sequences
+        let mut ast = common_tails::sink(cctx, ast);
+        assert_eq!(ast.get(&path), None);
+        assert_eq!(ast.get_mut(&path), None);
+        let before = ast.clone();
+        assert_eq!(ast.replace_at(&path, Return), Err(StalePath { depth: 0 }));
+        assert_eq!(ast, before);
+        let path = AstPath(vec![SeqChild(0), CondElse, SeqChild(1)]);
+        assert_eq!(ast.replace_at(&path, Return), Err(StalePath { depth: 2 }));
+        assert_eq!(ast, before);
+    }
+}
@@ -0,0 +1,251 @@
+//! See [`BlockStore`].
+
+use super::ast_context::{AstContext, AstContextMut};
+use super::idioms::Cmp;
+use super::struct_vars::StructVar;
+use super::{CfgNode, ControlFlowGraph, Structured};
+
+use petgraph::visit::NodeIndexable;
+
+use std::mem;
+use std::ops::Range;
+
+/// A block kept in a [`BlockStore`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(super) struct BlockRef(usize);
+
+/// The context structuring runs in. It owns the blocks of the context it
+/// wraps, and the graph and the ASTs only hold [`BlockRef`]s to them, so
+/// moving a node around never moves its blocks, however large they are. A
+/// block is only copied when [`AstContextMut::clone_block`] of the wrapped
+/// context copies it, and each one is taken back out once, when the ASTs
+/// are handed back.
+pub(super) struct BlockStore<A: AstContext> {
+    actx: A,
+    blocks: Vec<Option<A::Block>>,
+}
+
+impl<A: AstContext> BlockStore<A> {
+    fn put(&mut self, block: A::Block) -> BlockRef {
+        self.blocks.push(Some(block));
+        BlockRef(self.blocks.len() - 1)
+    }
+
+    fn get(&self, block: &BlockRef) -> &A::Block {
+        stored(&self.blocks, block)
+    }
+
+    fn take(&mut self, block: BlockRef) -> A::Block {
+        self.blocks[block.0]
+            .take()
+            .expect("block taken out of the store twice")
+    }
+}
+
+impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
+    /// Moves the blocks of `self` into a [`BlockStore`] around its context.
+    pub(super) fn into_block_store(self) -> ControlFlowGraph<'cd, BlockStore<A>> {
+        let ControlFlowGraph {
+            mut graph,
+            entry,
+            cctx,
+            actx,
+            value_sets,
+            branch_weights,
+            no_duplicate,
+            report,
+            trace,
+            observer,
+            namer,
+            cancel,
+            dominators,
+            budget,
+            dump,
+            decisions,
+            struct_vars,
+        } = self;
+        let mut store = BlockStore {
+            actx,
+            blocks: Vec::new(),
+        };
+        let mut nodes: Vec<_> = (0..graph.node_bound()).map(|_| None).collect();
+        for n in graph.node_indices().collect::<Vec<_>>() {
+            nodes[n.index()] = Some(mem::replace(&mut graph[n], CfgNode::Dummy("stored")));
+        }
+        // `map` keeps the indices of the nodes and the edges
+        let graph = graph.map(
+            |n, _| match nodes[n.index()].take().unwrap() {
+                CfgNode::Code(ast) => CfgNode::Code(ast.map_blocks(&mut |b| store.put(b))),
+                CfgNode::Condition(c) => CfgNode::Condition(c),
+                CfgNode::Dummy(s) => CfgNode::Dummy(s),
+            },
+            |_, &e| e,
+        );
+        let struct_vars = struct_vars
+            .into_iter()
+            .map(|sv| StructVar {
+                var: sv.var,
+                initial: sv.initial,
+                tests: sv.tests,
+            })
+            .collect();
+        ControlFlowGraph {
+            graph,
+            entry,
+            cctx,
+            actx: store,
+            value_sets,
+            branch_weights,
+            no_duplicate,
+            report,
+            trace,
+            observer,
+            namer,
+            cancel,
+            dominators,
+            budget,
+            dump,
+            decisions,
+            struct_vars,
+        }
+    }
+}
+
+fn stored<'a, B>(blocks: &'a [Option<B>], block: &BlockRef) -> &'a B {
+    blocks[block.0]
+        .as_ref()
+        .expect("block taken out of the store")
+}
+
+/// Takes the blocks of the ASTs of `structured` back out of its store.
+pub(super) fn take_blocks<'cd, A: AstContext>(
+    structured: Structured<'cd, BlockStore<A>>,
+) -> Structured<'cd, A> {
+    let (ast, handler_asts, mut store, report) = structured;
+    let ast = ast.map_blocks(&mut |b| store.take(b));
+    let handler_asts = handler_asts
+        .into_iter()
+        .map(|a| a.map_blocks(&mut |b| store.take(b)))
+        .collect();
+    (ast, handler_asts, store.actx, report)
+}
+
+impl<A: AstContext> AstContext for BlockStore<A> {
+    type Block = BlockRef;
+    type Variable = A::Variable;
+    type BoolVariable = A::BoolVariable;
+    type Condition = A::Condition;
+
+    fn assigned_value(&self, block: &BlockRef, var: &A::Variable) -> Option<u64> {
+        self.actx.assigned_value(self.get(block), var)
+    }
+
+    fn may_modify(&self, block: &BlockRef, cond: &A::Condition) -> bool {
+        self.actx.may_modify(self.get(block), cond)
+    }
+
+    fn comparison(&self, cond: &A::Condition) -> Option<(String, Cmp, String)> {
+        self.actx.comparison(cond)
+    }
+
+    fn assignment(&self, block: &BlockRef) -> Option<(String, String)> {
+        self.actx.assignment(self.get(block))
+    }
+
+    fn negation(&self, block: &BlockRef) -> Option<(String, String)> {
+        self.actx.negation(self.get(block))
+    }
+
+    fn increment(&self, block: &BlockRef) -> Option<(String, i64)> {
+        self.actx.increment(self.get(block))
+    }
+
+    fn block_range(&self, block: &BlockRef) -> Option<Range<u64>> {
+        self.actx.block_range(self.get(block))
+    }
+
+    fn is_pure_reread(&self, block: &BlockRef) -> bool {
+        self.actx.is_pure_reread(self.get(block))
+    }
+
+    fn describe_cond(&self, cond: &A::Condition) -> Option<String> {
+        self.actx.describe_cond(cond)
+    }
+
+    fn constant_value(&self, cond: &A::Condition) -> Option<bool> {
+        self.actx.constant_value(cond)
+    }
+
+    fn has_side_effects(&self, block: &BlockRef) -> bool {
+        self.actx.has_side_effects(self.get(block))
+    }
+}
+
+impl<A: AstContextMut> AstContextMut for BlockStore<A> {
+    fn mk_fresh_var(&mut self) -> A::Variable {
+        self.actx.mk_fresh_var()
+    }
+
+    fn mk_fresh_var_zeroed(&mut self) -> A::Variable {
+        self.actx.mk_fresh_var_zeroed()
+    }
+
+    fn mk_fresh_bool_var(&mut self) -> A::BoolVariable {
+        self.actx.mk_fresh_bool_var()
+    }
+
+    fn mk_named_var(&mut self, name: &str) -> A::Variable {
+        self.actx.mk_named_var(name)
+    }
+
+    fn mk_named_var_zeroed(&mut self, name: &str) -> A::Variable {
+        self.actx.mk_named_var_zeroed(name)
+    }
+
+    fn mk_named_bool_var(&mut self, name: &str) -> A::BoolVariable {
+        self.actx.mk_named_bool_var(name)
+    }
+
+    fn mk_cond_equals(&mut self, var: &A::Variable, val: u64) -> A::Condition {
+        self.actx.mk_cond_equals(var, val)
+    }
+
+    fn mk_cond_from_bool_var(&mut self, var: &A::BoolVariable) -> A::Condition {
+        self.actx.mk_cond_from_bool_var(var)
+    }
+
+    fn mk_var_assign(&mut self, var: &A::Variable, val: u64) -> BlockRef {
+        let block = self.actx.mk_var_assign(var, val);
+        self.put(block)
+    }
+
+    fn mk_bool_var_assign(&mut self, var: &A::BoolVariable, cond: &A::Condition) -> BlockRef {
+        let block = self.actx.mk_bool_var_assign(var, cond);
+        self.put(block)
+    }
+
+    fn clone_block(&mut self, block: &BlockRef) -> Option<BlockRef> {
+        let copy = self.actx.clone_block(stored(&self.blocks, block))?;
+        Some(self.put(copy))
+    }
+
+    fn clone_cond(&mut self, cond: &A::Condition) -> Option<A::Condition> {
+        self.actx.clone_cond(cond)
+    }
+
+    fn merge_blocks(&mut self, first: &BlockRef, second: &BlockRef) -> Option<BlockRef> {
+        let merged = self
+            .actx
+            .merge_blocks(stored(&self.blocks, first), stored(&self.blocks, second))?;
+        Some(self.put(merged))
+    }
+
+    fn split_latch(
+        &mut self,
+        block: &BlockRef,
+        cond: &A::Condition,
+    ) -> Option<(BlockRef, A::Condition)> {
+        let (work, cond) = self.actx.split_latch(stored(&self.blocks, block), cond)?;
+        Some((self.put(work), cond))
+    }
+}
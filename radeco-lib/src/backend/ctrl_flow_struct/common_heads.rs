@@ -0,0 +1,228 @@
+//! Moves the code that both arms of an `if`-`else` start with before it, see
+//! [`hoist`].
+
+use super::ast::AstNode;
+use super::common_tails::{from_vec, into_vec, leaves};
+use super::condition::{Condition, Context};
+use super::invariants::blocks_in;
+
+/// Moves the statements that both arms of each `if`-`else` in `ast` start
+/// with out of them, to right before it: `if (c) { t; a } else { t; b }`
+/// becomes `t; if (c) { a } else { b }`, the mirror of
+/// [`common_tails::sink`](super::common_tails::sink). Statements are compared
+/// structurally, with nested sequences flattened. An arm that is all head
+/// goes away, so `if (c) { t } else { t; b }` becomes `t; if (!c) { b }`.
+///
+/// The condition is then tested after the statements instead of before
+/// them, so a statement is only moved if it can't change it.
+/// `modifies(block, cond)` tells whether running `block` may change the
+/// value of the condition variable `cond`, like for
+/// [`adjacent_conds::merge`](super::adjacent_conds::merge); with
+/// [`from_r2::may_modify`](super::from_r2::may_modify) or `|_, _| true`,
+/// nothing that may touch the condition moves. Statements that leave the
+/// normal flow or have a label in them, which could be jumped to without
+/// the arm having been chosen, stay where they are, and so does everything
+/// after them.
+pub fn hoist<'cd, B, T, V, F>(
+    cctx: Context<'cd, T>,
+    ast: AstNode<B, Condition<'cd, T>, V>,
+    modifies: &mut F,
+) -> AstNode<B, Condition<'cd, T>, V>
+where
+    B: PartialEq,
+    V: PartialEq,
+    F: FnMut(&B, &T) -> bool,
+{
+    use self::AstNode::*;
+    match ast {
+        Seq(seq) => from_vec(
+            seq.into_iter()
+                .flat_map(|a| into_vec(hoist(cctx, a, modifies)))
+                .collect(),
+        ),
+        Cond(c, t, Some(e)) => {
+            let mut then_seq = into_vec(hoist(cctx, *t, modifies));
+            let mut else_seq = into_vec(hoist(cctx, *e, modifies));
+            let vars = c.vars();
+            let common = then_seq
+                .iter()
+                .zip(else_seq.iter())
+                .take_while(|&(a, b)| {
+                    let mut blocks = Vec::new();
+                    blocks_in(a, &mut blocks);
+                    a == b
+                        && !leaves(a)
+                        && blocks
+                            .iter()
+                            .all(|b| vars.iter().all(|v| !modifies(b, &**v)))
+                })
+                .count();
+            if common == 0 {
+                return Cond(
+                    c,
+                    Box::new(from_vec(then_seq)),
+                    Some(Box::new(from_vec(else_seq))),
+                );
+            }
+            let head: Vec<_> = then_seq.drain(..common).collect();
+            else_seq.drain(..common);
+            let cond = match (then_seq.is_empty(), else_seq.is_empty()) {
+                (true, true) => None,
+                (false, true) => Some(Cond(c, Box::new(from_vec(then_seq)), None)),
+                (true, false) => Some(Cond(cctx.mk_not(c), Box::new(from_vec(else_seq)), None)),
+                (false, false) => Some(Cond(
+                    c,
+                    Box::new(from_vec(then_seq)),
+                    Some(Box::new(from_vec(else_seq))),
+                )),
+            };
+            from_vec(head.into_iter().chain(cond).collect())
+        }
+        Cond(c, t, None) => Cond(c, Box::new(hoist(cctx, *t, modifies)), None),
+        Loop(lt, b) => Loop(lt, Box::new(hoist(cctx, *b, modifies))),
+        For(i, c, u, b) => For(i, c, u, Box::new(hoist(cctx, *b, modifies))),
+        Switch(v, cases, default) => Switch(
+            v,
+            cases
+                .into_iter()
+                .map(|(vs, a)| (vs, hoist(cctx, a, modifies)))
+                .collect(),
+            Box::new(hoist(cctx, *default, modifies)),
+        ),
+        Try(b, h) => Try(Box::new(hoist(cctx, *b, modifies)), h),
+        Unstructured(members) => Unstructured(
+            members
+                .into_iter()
+                .map(|(l, a)| (l, hoist(cctx, a, modifies)))
+                .collect(),
+        ),
+        ast @ BasicBlock(_)
+        | ast @ Break
+        | ast @ Continue
+        | ast @ Return
+        | ast @ TailCall(_)
+        | ast @ IndirectJump(_)
+        | ast @ Goto(_)
+        | ast @ Label(_) => ast,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::{AstNode::*, LabelId, LoopType};
+    use crate::backend::ctrl_flow_struct::condition::Storage;
+
+    type Ast<'cd> = AstNode<&'static str, Condition<'cd, &'static str>, ()>;
+
+    /// `block` assigns to `var`, e.g. `a = 0` to `a`.
+    fn modifies(block: &&str, var: &&str) -> bool {
+        block.starts_with(&format!("{} = ", var))
+    }
+
+    fn seq(blocks: &[&'static str]) -> Box<Ast<'static>> {
+        Box::new(Seq(blocks.iter().map(|&b| BasicBlock(b)).collect()))
+    }
+
+    #[test]
+    fn equal_heads() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // if (c) { t; u; a } else { { t; u }; b }
+        let ast: Ast = Cond(
+            c,
+            seq(&["t", "u", "a"]),
+            Some(Box::new(Seq(vec![
+                Seq(vec![BasicBlock("t"), BasicBlock("u")]),
+                BasicBlock("b"),
+            ]))),
+        );
+        assert_eq!(
+            hoist(cctx, ast, &mut modifies),
+            Seq(vec![
+                BasicBlock("t"),
+                BasicBlock("u"),
+                Cond(
+                    c,
+                    Box::new(BasicBlock("a")),
+                    Some(Box::new(BasicBlock("b")))
+                ),
+            ])
+        );
+
+        // if (c) { t } else { t; b }
+        let ast: Ast = Cond(c, seq(&["t"]), Some(seq(&["t", "b"])));
+        assert_eq!(
+            hoist(cctx, ast, &mut modifies),
+            Seq(vec![
+                BasicBlock("t"),
+                Cond(cctx.mk_not(c), Box::new(BasicBlock("b")), None),
+            ])
+        );
+    }
+
+    #[test]
+    fn modified_condition() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // `t` can't move, since the condition isn't known to survive it
+        let ast: Ast = Cond(c, seq(&["t", "a"]), Some(seq(&["t", "b"])));
+        assert_eq!(hoist(cctx, ast.clone(), &mut |_, _| true), ast);
+
+        // `c = 0` changes the condition, so it and `t` after it stay
+        let ast: Ast = Cond(
+            c,
+            seq(&["s", "c = 0", "t", "a"]),
+            Some(seq(&["s", "c = 0", "t", "b"])),
+        );
+        assert_eq!(
+            hoist(cctx, ast, &mut modifies),
+            Seq(vec![
+                BasicBlock("s"),
+                Cond(
+                    c,
+                    seq(&["c = 0", "t", "a"]),
+                    Some(seq(&["c = 0", "t", "b"]))
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn labeled_arm() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // a `goto` elsewhere may enter the `then` arm at its start, and run
+        // `t` without the `if` having been taken
+        let ast: Ast = Cond(
+            c,
+            Box::new(Seq(vec![
+                Label(LabelId(0)),
+                BasicBlock("t"),
+                BasicBlock("a"),
+            ])),
+            Some(seq(&["t", "b"])),
+        );
+        assert_eq!(hoist(cctx, ast.clone(), &mut modifies), ast);
+
+        // nor does a label in a common statement move
+        let looping = || {
+            Loop(
+                LoopType::Endless,
+                Box::new(Seq(vec![Label(LabelId(1)), BasicBlock("t")])),
+            )
+        };
+        let ast: Ast = Cond(
+            c,
+            Box::new(Seq(vec![looping(), BasicBlock("a")])),
+            Some(Box::new(Seq(vec![looping(), BasicBlock("b")]))),
+        );
+        assert_eq!(hoist(cctx, ast.clone(), &mut modifies), ast);
+    }
+}
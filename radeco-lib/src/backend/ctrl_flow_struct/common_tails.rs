@@ -0,0 +1,220 @@
+//! Moves the code that both arms of an `if`-`else` end in after it, see
+//! [`sink`].
+
+use super::ast::AstNode;
+use super::condition::{Condition, Context};
+
+/// Moves the statements that both arms of each `if`-`else` in `ast` end in
+/// out of them, to right after it: `if (c) { a; t } else { b; t }` becomes
+/// `if (c) { a } else { b }; t`, which undoes the copies of
+/// [`StructuringOptions::duplicate_tails`](super::StructuringOptions::duplicate_tails)
+/// where they didn't help. Statements are compared structurally, with nested
+/// sequences flattened. An arm that is all tail goes away, so `if (c) { t }
+/// else { b; t }` becomes `if (!c) { b }; t`.
+///
+/// Statements that leave the normal flow, e.g. a `break` or a `return`, and
+/// those that contain such a statement, stay where they are, and so does
+/// everything before them.
+pub fn sink<'cd, B, T, V>(
+    cctx: Context<'cd, T>,
+    ast: AstNode<B, Condition<'cd, T>, V>,
+) -> AstNode<B, Condition<'cd, T>, V>
+where
+    B: PartialEq,
+    V: PartialEq,
+{
+    use self::AstNode::*;
+    match ast {
+        Seq(seq) => from_vec(
+            seq.into_iter()
+                .flat_map(|a| into_vec(sink(cctx, a)))
+                .collect(),
+        ),
+        Cond(c, t, Some(e)) => {
+            let mut then_seq = into_vec(sink(cctx, *t));
+            let mut else_seq = into_vec(sink(cctx, *e));
+            let common = then_seq
+                .iter()
+                .rev()
+                .zip(else_seq.iter().rev())
+                .take_while(|&(a, b)| a == b && !leaves(a))
+                .count();
+            if common == 0 {
+                return Cond(
+                    c,
+                    Box::new(from_vec(then_seq)),
+                    Some(Box::new(from_vec(else_seq))),
+                );
+            }
+            let tail = then_seq.split_off(then_seq.len() - common);
+            else_seq.truncate(else_seq.len() - common);
+            let cond = match (then_seq.is_empty(), else_seq.is_empty()) {
+                (true, true) => None,
+                (false, true) => Some(Cond(c, Box::new(from_vec(then_seq)), None)),
+                (true, false) => Some(Cond(cctx.mk_not(c), Box::new(from_vec(else_seq)), None)),
+                (false, false) => Some(Cond(
+                    c,
+                    Box::new(from_vec(then_seq)),
+                    Some(Box::new(from_vec(else_seq))),
+                )),
+            };
+            from_vec(cond.into_iter().chain(tail).collect())
+        }
+        Cond(c, t, None) => Cond(c, Box::new(sink(cctx, *t)), None),
+        Loop(lt, b) => Loop(lt, Box::new(sink(cctx, *b))),
+        For(i, c, u, b) => For(i, c, u, Box::new(sink(cctx, *b))),
+        Switch(v, cases, default) => Switch(
+            v,
+            cases
+                .into_iter()
+                .map(|(vs, a)| (vs, sink(cctx, a)))
+                .collect(),
+            Box::new(sink(cctx, *default)),
+        ),
+        Try(b, h) => Try(Box::new(sink(cctx, *b)), h),
+        Unstructured(members) => Unstructured(
+            members
+                .into_iter()
+                .map(|(l, a)| (l, sink(cctx, a)))
+                .collect(),
+        ),
+        ast @ BasicBlock(_)
+        | ast @ Break
+        | ast @ Continue
+        | ast @ Return
+        | ast @ TailCall(_)
+        | ast @ IndirectJump(_)
+        | ast @ Goto(_)
+        | ast @ Label(_) => ast,
+    }
+}
+
+/// The statements of `ast`, with nested sequences flattened.
+pub(super) fn into_vec<B, C, V>(ast: AstNode<B, C, V>) -> Vec<AstNode<B, C, V>> {
+    match ast {
+        AstNode::Seq(seq) => seq.into_iter().flat_map(into_vec).collect(),
+        ast => vec![ast],
+    }
+}
+
+pub(super) fn from_vec<B, C, V>(mut seq: Vec<AstNode<B, C, V>>) -> AstNode<B, C, V> {
+    if seq.len() == 1 {
+        seq.pop().unwrap()
+    } else {
+        AstNode::Seq(seq)
+    }
+}
+
+/// Whether `ast` is or contains a statement that leaves the normal flow, or
+/// a label that may be jumped to.
+pub(super) fn leaves<B, C, V>(ast: &AstNode<B, C, V>) -> bool {
+    use self::AstNode::*;
+    match ast {
+        Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_)
+        | Unstructured(_) => true,
+        BasicBlock(_) => false,
+        Seq(seq) => seq.iter().any(leaves),
+        Cond(_, t, oe) => leaves(t) || oe.iter().any(|e| leaves(e)),
+        Loop(_, b) | For(_, _, _, b) | Try(b, _) => leaves(b),
+        Switch(_, cases, default) => cases.iter().any(|(_, a)| leaves(a)) || leaves(default),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::condition::Storage;
+
+    type Ast<'cd> = AstNode<&'static str, Condition<'cd, &'static str>, ()>;
+
+    #[test]
+    fn equal_tails() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // if (c) { a; t; u } else { b; { t; u } }
+        let ast: Ast = Cond(
+            c,
+            Box::new(Seq(vec![BasicBlock("a"), BasicBlock("t"), BasicBlock("u")])),
+            Some(Box::new(Seq(vec![
+                BasicBlock("b"),
+                Seq(vec![BasicBlock("t"), BasicBlock("u")]),
+            ]))),
+        );
+        assert_eq!(
+            sink(cctx, ast),
+            Seq(vec![
+                Cond(
+                    c,
+                    Box::new(BasicBlock("a")),
+                    Some(Box::new(BasicBlock("b")))
+                ),
+                BasicBlock("t"),
+                BasicBlock("u"),
+            ])
+        );
+
+        // if (c) { t } else { b; t }, nested in a sequence
+        let ast: Ast = Seq(vec![
+            BasicBlock("s"),
+            Cond(
+                c,
+                Box::new(BasicBlock("t")),
+                Some(Box::new(Seq(vec![BasicBlock("b"), BasicBlock("t")]))),
+            ),
+            BasicBlock("return"),
+        ]);
+        assert_eq!(
+            sink(cctx, ast),
+            Seq(vec![
+                BasicBlock("s"),
+                Cond(cctx.mk_not(c), Box::new(BasicBlock("b")), None),
+                BasicBlock("t"),
+                BasicBlock("return"),
+            ])
+        );
+    }
+
+    #[test]
+    fn unequal_tails() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        let ast: Ast = Cond(
+            c,
+            Box::new(Seq(vec![BasicBlock("t"), BasicBlock("a")])),
+            Some(Box::new(Seq(vec![BasicBlock("t"), BasicBlock("b")]))),
+        );
+        assert_eq!(sink(cctx, ast.clone()), ast);
+    }
+
+    #[test]
+    fn returning_arm() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // `t` runs before the `return` in one arm only
+        let ast: Ast = Cond(
+            c,
+            Box::new(Seq(vec![BasicBlock("a"), BasicBlock("t"), Return])),
+            Some(Box::new(Seq(vec![BasicBlock("b"), BasicBlock("t")]))),
+        );
+        assert_eq!(sink(cctx, ast.clone()), ast);
+
+        // nor is the `return` itself moved when both arms end in it
+        let ast: Ast = Cond(
+            c,
+            Box::new(Seq(vec![BasicBlock("a"), BasicBlock("t"), Return])),
+            Some(Box::new(Seq(vec![
+                BasicBlock("b"),
+                BasicBlock("t"),
+                Return,
+            ]))),
+        );
+        assert_eq!(sink(cctx, ast.clone()), ast);
+    }
+}
@@ -30,9 +30,13 @@ mod test;
 use linear_map::set::LinearSet;
 use typed_arena::Arena;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::ops;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
 
 macro_rules! impl_copy {
     ($name:ident) => {
@@ -76,11 +80,31 @@ enum Op {
 }
 
 /// A variable. This can be freely copied.
-/// Use [`Context::new_var`] to make one.
+/// Use [`Context::new_var`] or [`Context::intern_var`] to make one.
 #[derive(Debug)]
-pub struct VarRef<'cd, T: 'cd>(&'cd T);
+pub struct VarRef<'cd, T: 'cd>(&'cd T, VarId);
 impl_copy! {VarRef}
 
+/// What tells variables apart, see [`VarRef::id`]. Unique across all
+/// [`Storage`]s, but for the variables interned with the same [`Interner`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VarId(usize);
+
+static NEXT_VAR_ID: AtomicUsize = AtomicUsize::new(0);
+
+impl VarId {
+    fn fresh() -> Self {
+        VarId(NEXT_VAR_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Gives equal values the same [`VarId`] in each [`Storage`] made with
+/// [`Storage::with_interner`] for it, on any thread, see
+/// [`Context::intern_var`].
+pub struct Interner<T> {
+    ids: Mutex<HashMap<T, VarId>>,
+}
+
 /// Helper for creating new conditions. This can be freely copied.
 /// Use [`Storage::cctx`] to make one.
 #[derive(Debug)]
@@ -95,6 +119,11 @@ pub struct Storage<'cd, T: 'cd> {
     conds: Arena<CondVariants<'cd, T>>,
     true_: CondVariants<'cd, T>,
     false_: CondVariants<'cd, T>,
+    /// the interner shared with other storages, if any, else `own_interner`
+    interner: Option<&'cd Interner<T>>,
+    own_interner: Interner<T>,
+    /// the values this storage holds for the interned variables
+    interned: RefCell<HashMap<VarId, &'cd T>>,
 }
 
 use self::CondVariants::*;
@@ -106,6 +135,18 @@ impl<'cd, T> Context<'cd, T> {
         self.store.mk_var(t)
     }
 
+    /// Returns the variable for `t`, which compares equal to those for equal
+    /// values, in this [`Storage`] and in those sharing its [`Interner`].
+    /// Structuring takes equal variables to be the same boolean, so only
+    /// conditions that have the same value wherever they are tested may be
+    /// interned, e.g. one only about a global that nothing changes.
+    pub fn intern_var(self, t: T) -> VarRef<'cd, T>
+    where
+        T: Clone + Eq + Hash,
+    {
+        self.store.intern_var(t)
+    }
+
     /// Creates a condition representing the given variable.
     pub fn mk_var(self, vr: VarRef<'cd, T>) -> Condition<'cd, T> {
         Condition(
@@ -314,6 +355,36 @@ impl<'cd, T> Context<'cd, T> {
             ),
         }
     }
+
+    /// Replaces the variables of `cond` that `value` knows the value of
+    /// with that value, and simplifies the result.
+    pub fn assign<F>(self, cond: Condition<'cd, T>, value: &mut F) -> Condition<'cd, T>
+    where
+        F: FnMut(VarRef<'cd, T>) -> Option<bool>,
+    {
+        match *cond.0 {
+            Var(inv, vr) => match value(vr) {
+                Some(holds) if holds == (inv == Negation::Normal) => self.mk_true(),
+                Some(_) => self.mk_false(),
+                None => cond,
+            },
+            Expr(op, ref opn_v) => {
+                let opn_v: Vec<_> = opn_v.iter().map(|&opn| self.assign(opn, value)).collect();
+                match op {
+                    Op::And => self.mk_and_from_iter(opn_v),
+                    Op::Or => self.mk_or_from_iter(opn_v),
+                }
+            }
+        }
+    }
+}
+
+impl<'cd, T> VarRef<'cd, T> {
+    /// What the variable is told apart by: variables are equal exactly when
+    /// their ids are, even if they are in different [`Storage`]s.
+    pub fn id(self) -> VarId {
+        self.1
+    }
 }
 
 impl<'cd, T> Condition<'cd, T> {
@@ -355,6 +426,77 @@ impl<'cd, T> Condition<'cd, T> {
         }
     }
 
+    /// Returns the variables used in this condition, in no particular order
+    /// and without duplicates.
+    pub fn vars(self) -> Vec<VarRef<'cd, T>> {
+        let mut ret = Vec::new();
+        self.collect_vars(&mut ret);
+        ret
+    }
+    /// Returns the operands of this condition if it is a conjunction, or else
+    /// just this condition. `true` has none.
+    pub fn conjuncts(self) -> Vec<Condition<'cd, T>> {
+        match self.0 {
+            &Expr(Op::And, ref opn_v) => opn_v.iter().cloned().collect(),
+            _ => vec![self],
+        }
+    }
+
+    fn collect_vars(self, out: &mut Vec<VarRef<'cd, T>>) {
+        match self.0 {
+            &Var(_, vr) => {
+                if !out.contains(&vr) {
+                    out.push(vr);
+                }
+            }
+            &Expr(_, ref opn_v) => {
+                for opn in opn_v {
+                    opn.collect_vars(out);
+                }
+            }
+        }
+    }
+
+    /// Returns the value of this condition, given the value of each of its
+    /// variables by `var`. Operands are evaluated in no particular order,
+    /// and only until the value is decided.
+    pub fn eval<F: FnMut(&T) -> bool>(self, var: &mut F) -> bool {
+        match self.0 {
+            &Var(inv, vr) => var(vr.0) == (inv == Negation::Normal),
+            &Expr(Op::And, ref opn_v) => opn_v.iter().all(|opn| opn.eval(var)),
+            &Expr(Op::Or, ref opn_v) => opn_v.iter().any(|opn| opn.eval(var)),
+        }
+    }
+
+    /// Returns the value of this condition, given the value of each of its
+    /// variables by `env`, or `None` if that doesn't decide it: if `env`
+    /// doesn't know the value of some variables, those that it does know
+    /// may still decide it, e.g. a conjunction with an operand that is
+    /// false is false whatever the others are. Operands are evaluated in no
+    /// particular order, and only until the value is decided.
+    pub fn evaluate(self, env: &dyn Fn(&T) -> Option<bool>) -> Option<bool> {
+        match *self.0 {
+            Var(inv, vr) => env(vr.0).map(|v| v == (inv == Negation::Normal)),
+            Expr(op, ref opn_v) => {
+                // the value that decides an operation by itself
+                let decisive = op == Op::Or;
+                let mut known = true;
+                for opn in opn_v {
+                    match opn.evaluate(env) {
+                        Some(v) if v == decisive => return Some(decisive),
+                        Some(_) => (),
+                        None => known = false,
+                    }
+                }
+                if known {
+                    Some(!decisive)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
     pub fn fold<F: Folder<T>>(self, mut folder: F) -> F::Output {
         match self.0 {
             &Var(inv, vr) => folder.var(inv == Negation::Normal, vr.0),
@@ -366,7 +508,8 @@ impl<'cd, T> Condition<'cd, T> {
 
 pub trait Folder<T> {
     type Output;
-    fn var(&mut self, negated: bool, var: &T) -> Self::Output;
+    /// Folds a variable, which is negated unless `normal`.
+    fn var(&mut self, normal: bool, var: &T) -> Self::Output;
     fn and<'a, I>(&mut self, operands: I) -> Self::Output
     where
         I: IntoIterator<Item = Condition<'a, T>>,
@@ -636,6 +779,18 @@ impl<'cd, T> Storage<'cd, T> {
             conds: Arena::new(),
             true_: Expr(Op::And, LinearSet::new()),
             false_: Expr(Op::Or, LinearSet::new()),
+            interner: None,
+            own_interner: Interner::new(),
+            interned: RefCell::default(),
+        }
+    }
+
+    /// A storage whose interned variables are shared with the other
+    /// storages of `interner`, see [`Context::intern_var`].
+    pub fn with_interner(interner: &'cd Interner<T>) -> Self {
+        Self {
+            interner: Some(interner),
+            ..Self::new()
         }
     }
 
@@ -650,7 +805,63 @@ impl<'cd, T> Storage<'cd, T> {
     }
 
     fn mk_var(&'cd self, t: T) -> VarRef<'cd, T> {
-        VarRef(self.vars.alloc(t))
+        VarRef(self.vars.alloc(t), VarId::fresh())
+    }
+
+    fn intern_var(&'cd self, t: T) -> VarRef<'cd, T>
+    where
+        T: Clone + Eq + Hash,
+    {
+        let id = self.interner.unwrap_or(&self.own_interner).intern(&t);
+        let mut interned = self.interned.borrow_mut();
+        let t = *interned.entry(id).or_insert_with(|| self.vars.alloc(t));
+        VarRef(t, id)
+    }
+}
+
+impl<T> Interner<T> {
+    pub fn new() -> Self {
+        Interner {
+            ids: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone + Eq + Hash> Interner<T> {
+    /// The id of the variables for `t`, if one was interned.
+    pub fn get(&self, t: &T) -> Option<VarId> {
+        self.lock().get(t).cloned()
+    }
+
+    /// The number of values interned.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn intern(&self, t: &T) -> VarId {
+        let mut ids = self.lock();
+        if let Some(&id) = ids.get(t) {
+            return id;
+        }
+        let id = VarId::fresh();
+        ids.insert(t.clone(), id);
+        id
+    }
+
+    /// Locks the ids, even after a panic elsewhere, which can't leave them
+    /// half updated.
+    fn lock(&self) -> MutexGuard<'_, HashMap<T, VarId>> {
+        self.ids.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> Default for Interner<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -669,6 +880,12 @@ impl Op {
     }
 }
 
+impl<T> fmt::Debug for Interner<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("<Interner>")
+    }
+}
+
 impl<'cd, T> fmt::Debug for Storage<'cd, T> {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.write_str("<Storage>")
@@ -689,14 +906,12 @@ impl<'cd, T: fmt::Debug> fmt::Debug for Condition<'cd, T> {
 impl<'cd, T> Eq for VarRef<'cd, T> {}
 impl<'cd, T> PartialEq for VarRef<'cd, T> {
     fn eq(&self, rhs: &Self) -> bool {
-        use std::ptr;
-        ptr::eq(self.0, rhs.0)
+        self.1 == rhs.1
     }
 }
 impl<'cd, T> Hash for VarRef<'cd, T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        let p: *const _ = self.0;
-        p.hash(state)
+        self.1.hash(state)
     }
 }
 impl<'cd, T> ops::Deref for VarRef<'cd, T> {
@@ -15,6 +15,36 @@ fn mk_ab<'cd>(cctx: Context<'cd, &'static str>) -> Condition<'cd, &'static str>
     ab
 }
 
+#[test]
+fn intern() {
+    let cstore = Storage::new();
+    let cctx = cstore.cctx();
+    let a = cctx.intern_var("a");
+    assert_eq!(cctx.intern_var("a"), a);
+    assert!(std::ptr::eq(cctx.intern_var("a").0, a.0));
+    assert_ne!(cctx.intern_var("b"), a);
+    assert_ne!(cctx.new_var("a"), a);
+    assert_eq!(
+        cctx.mk_and(cctx.mk_var(a), cctx.mk_var(cctx.intern_var("b"))),
+        cctx.mk_and(
+            cctx.mk_var(cctx.intern_var("a")),
+            cctx.mk_var(cctx.intern_var("b"))
+        )
+    );
+
+    // across storages, only with the same interner
+    let interner = Interner::new();
+    let (s1, s2) = (
+        Storage::with_interner(&interner),
+        Storage::with_interner(&interner),
+    );
+    let a1 = s1.cctx().intern_var("a");
+    assert_eq!(s2.cctx().intern_var("a").id(), a1.id());
+    assert_eq!(interner.get(&"a"), Some(a1.id()));
+    assert_eq!(interner.len(), 1);
+    assert_ne!(a.id(), a1.id());
+}
+
 #[test]
 fn identity() {
     let cstore = Storage::new();
@@ -30,6 +60,22 @@ fn identity() {
     assert_eq!(cctx.mk_or_from_iter(vec![f, a, f, b]), cctx.mk_or(a, b));
 }
 
+#[test]
+fn assign() {
+    let cstore = Storage::new();
+    let cctx = cstore.cctx();
+    let (va, vb) = (cctx.new_var("a"), cctx.new_var("b"));
+    let (a, b) = (cctx.mk_var(va), cctx.mk_var(vb));
+
+    // a = true, b unknown
+    let mut value = |v: VarRef<&str>| if v == va { Some(true) } else { None };
+    assert_eq!(cctx.assign(cctx.mk_and(a, b), &mut value), b);
+    assert_eq!(cctx.assign(cctx.mk_or(cctx.mk_not(a), b), &mut value), b);
+    assert!(cctx.assign(cctx.mk_or(a, b), &mut value).is_true());
+    assert!(cctx.assign(cctx.mk_not(a), &mut value).is_false());
+    assert_eq!(cctx.assign(b, &mut value), b);
+}
+
 #[test]
 fn annihilation_ptr_eq() {
     use std::ptr;
@@ -304,3 +350,80 @@ fn nested_complementation() {
         a_and_b
     );
 }
+
+#[test]
+fn evaluation() {
+    let cstore = Storage::new();
+    let cctx = cstore.cctx();
+    let a = cctx.mk_var(cctx.new_var("a"));
+    let b = cctx.mk_var(cctx.new_var("b"));
+    let c = cctx.mk_var(cctx.new_var("c"));
+
+    let expr = cctx.mk_or(cctx.mk_and(a, cctx.mk_not(b)), c);
+    for bits in 0..8 {
+        let mut value = |v: &&str| match *v {
+            "a" => bits & 1 != 0,
+            "b" => bits & 2 != 0,
+            _ => bits & 4 != 0,
+        };
+        let expected = (bits & 1 != 0 && bits & 2 == 0) || bits & 4 != 0;
+        assert_eq!(expr.eval(&mut value), expected, "{:03b}", bits);
+        assert_eq!(cctx.mk_not(expr).eval(&mut value), !expected);
+    }
+    assert!(cctx.mk_true().eval(&mut |_| unreachable!()));
+    assert!(!cctx.mk_false().eval(&mut |_| unreachable!()));
+}
+
+#[test]
+fn partial_evaluation() {
+    let cstore = Storage::new();
+    let cctx = cstore.cctx();
+    let a = cctx.mk_var(cctx.new_var("a"));
+    let b = cctx.mk_var(cctx.new_var("b"));
+    let c = cctx.mk_var(cctx.new_var("c"));
+
+    // `(a && !b) || c`, with only some of the variables known
+    let expr = cctx.mk_or(cctx.mk_and(a, cctx.mk_not(b)), c);
+    type Known = &'static [(&'static str, bool)];
+    let only =
+        |known: Known| move |v: &&str| known.iter().find(|&&(k, _)| k == *v).map(|&(_, b)| b);
+    let cases: &[(Known, Option<bool>)] = &[
+        // `c` decides the disjunction by itself
+        (&[("c", true)], Some(true)),
+        // and `a` the conjunction, which `c` then doesn't
+        (&[("a", false)], None),
+        (&[("a", false), ("c", false)], Some(false)),
+        (&[("b", true), ("c", false)], Some(false)),
+        (&[("a", true), ("b", false)], Some(true)),
+        (&[("a", true), ("c", false)], None),
+        (&[], None),
+    ];
+    for &(known, expected) in cases {
+        let env = only(known);
+        assert_eq!(expr.evaluate(&env), expected, "{:?}", known);
+        assert_eq!(
+            cctx.mk_not(expr).evaluate(&env),
+            expected.map(|v| !v),
+            "{:?}",
+            known
+        );
+    }
+
+    // with every variable known, it agrees with `eval`
+    for bits in 0..8 {
+        let value = |v: &&str| match *v {
+            "a" => bits & 1 != 0,
+            "b" => bits & 2 != 0,
+            _ => bits & 4 != 0,
+        };
+        let env = |v: &&str| Some(value(v));
+        assert_eq!(
+            expr.evaluate(&env),
+            Some(expr.eval(&mut { value })),
+            "{:03b}",
+            bits
+        );
+    }
+    assert_eq!(cctx.mk_true().evaluate(&|_| unreachable!()), Some(true));
+    assert_eq!(cctx.mk_false().evaluate(&|_| unreachable!()), Some(false));
+}
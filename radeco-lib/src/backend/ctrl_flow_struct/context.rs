@@ -0,0 +1,57 @@
+//! What the functions of a binary are structured against together, see
+//! [`StructuringContext`].
+
+use super::condition::{Interner, Storage, VarId};
+
+use std::hash::Hash;
+
+/// Shared by the functions of a binary as they are structured: the interner
+/// of the leaf predicates of their conditions, e.g. strings like
+/// `rax == 0`, so that each of them is only kept once per [`Storage`], and
+/// the ASTs of different functions can be asked which of them they test.
+///
+/// Each function gets a storage of its own from [`storage`](Self::storage)
+/// to make its [`ControlFlowGraph`](super::ControlFlowGraph) with, and
+/// interns the predicates it may with
+/// [`Context::intern_var`](super::condition::Context::intern_var). The
+/// context is `Sync`, so that the functions can be structured on different
+/// threads; it only locks to intern.
+#[derive(Debug)]
+pub struct StructuringContext<T> {
+    conds: Interner<T>,
+}
+
+impl<T> StructuringContext<T> {
+    pub fn new() -> Self {
+        StructuringContext {
+            conds: Interner::new(),
+        }
+    }
+
+    /// A storage for the conditions of one function, whose interned
+    /// variables are the same as those of the other storages of this
+    /// context.
+    pub fn storage(&self) -> Storage<'_, T> {
+        Storage::with_interner(&self.conds)
+    }
+}
+
+impl<T: Clone + Eq + Hash> StructuringContext<T> {
+    /// The id that the variables for the leaf predicate `pred` have in every
+    /// function that interned it, see
+    /// [`VarRef::id`](super::condition::VarRef::id).
+    pub fn var_id(&self, pred: &T) -> Option<VarId> {
+        self.conds.get(pred)
+    }
+
+    /// The number of different leaf predicates the functions interned.
+    pub fn interned(&self) -> usize {
+        self.conds.len()
+    }
+}
+
+impl<T> Default for StructuringContext<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,91 @@
+//! Merges the blocks next to each other whose code is contiguous, see
+//! [`merge`].
+
+use super::ast::AstNode;
+use super::ast_context::AstContextMut;
+
+/// Merges each block in a sequence in `ast` into the one before it if its
+/// code starts right where that of the one before it ends, as the context
+/// gives their [ranges](super::ast_context::AstContext::block_range). Such
+/// blocks were only apart because of the jumps between them that
+/// structuring made into the sequence, so the merged block, which the
+/// context [makes](AstContextMut::merge_blocks) from both, reads as the
+/// code it was in the binary.
+///
+/// Blocks without a range are left alone, and so is a block with a label
+/// before it, which a `goto` still jumps to: only the blocks that follow
+/// each other in the sequence are merged. The context declines to merge
+/// the blocks it can't make into one without losing what it keeps about
+/// them, such as their comments.
+pub fn merge<A, C, V>(actx: &mut A, ast: AstNode<A::Block, C, V>) -> AstNode<A::Block, C, V>
+where
+    A: AstContextMut,
+{
+    use self::AstNode::*;
+    match ast {
+        Seq(seq) => Seq(merge_seq(actx, seq)),
+        Cond(c, t, oe) => {
+            let t = Box::new(merge(actx, *t));
+            Cond(c, t, oe.map(|e| Box::new(merge(actx, *e))))
+        }
+        Loop(lt, b) => Loop(lt, Box::new(merge(actx, *b))),
+        For(i, c, u, b) => For(i, c, u, Box::new(merge(actx, *b))),
+        Switch(v, cases, default) => Switch(
+            v,
+            cases
+                .into_iter()
+                .map(|(vs, a)| (vs, merge(actx, a)))
+                .collect(),
+            Box::new(merge(actx, *default)),
+        ),
+        Try(b, h) => Try(Box::new(merge(actx, *b)), h),
+        Unstructured(members) => Unstructured(
+            members
+                .into_iter()
+                .map(|(l, a)| (l, merge(actx, a)))
+                .collect(),
+        ),
+        ast @ BasicBlock(_)
+        | ast @ Break
+        | ast @ Continue
+        | ast @ Return
+        | ast @ TailCall(_)
+        | ast @ IndirectJump(_)
+        | ast @ Goto(_)
+        | ast @ Label(_) => ast,
+    }
+}
+
+fn merge_seq<A, C, V>(
+    actx: &mut A,
+    seq: Vec<AstNode<A::Block, C, V>>,
+) -> Vec<AstNode<A::Block, C, V>>
+where
+    A: AstContextMut,
+{
+    let mut ret: Vec<AstNode<A::Block, C, V>> = Vec::with_capacity(seq.len());
+    for a in seq {
+        let a = merge(actx, a);
+        if let (Some(AstNode::BasicBlock(prev)), AstNode::BasicBlock(next)) = (ret.last_mut(), &a) {
+            if let Some(merged) = merge_two(actx, prev, next) {
+                *prev = merged;
+                continue;
+            }
+        }
+        ret.push(a);
+    }
+    ret
+}
+
+/// The block that runs `first` and then `second`, if the code of `second`
+/// follows right after that of `first` and the context can merge them.
+fn merge_two<A: AstContextMut>(
+    actx: &mut A,
+    first: &A::Block,
+    second: &A::Block,
+) -> Option<A::Block> {
+    if actx.block_range(first)?.end != actx.block_range(second)?.start {
+        return None;
+    }
+    actx.merge_blocks(first, second)
+}
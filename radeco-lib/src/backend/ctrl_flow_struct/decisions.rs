@@ -0,0 +1,328 @@
+//! Why the `Cond`s, `Loop`s and `Switch`es of a structured AST are the way
+//! they are: which phase of structuring made each of them, and which edges
+//! of the graph their guards come from. Where
+//! [`provenance`](super::provenance) tells which code a node stands for,
+//! this tells what structuring decided, e.g. why a loop became a
+//! `do`-`while`.
+//!
+//! Structuring records its decisions as it goes, and matches them up with
+//! the resulting ASTs in
+//! [`StructuringReport::decisions`](super::StructuringReport::decisions).
+
+use super::ast::{AstNode, LoopType};
+use super::condition::{self, Folder};
+
+use petgraph::graph::NodeIndex;
+
+use std::collections::HashMap;
+
+/// A node of the ASTs of a structured function, by its index in their
+/// [`preorder`]: the nodes of the normal path come first, then those of
+/// each handler, in order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct AstNodeId(pub usize);
+
+/// The phase of structuring that made a construct.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Phase {
+    /// A region or loop was collapsed as is: a `Cond` on the reaching
+    /// condition of the nodes it holds, or an `Endless` loop that no rule
+    /// of loop refinement applied to.
+    InitialCollapse,
+    /// A rule of loop refinement chose the kind of loop, see
+    /// [`Explanation::rule`].
+    LoopRefinement,
+    /// Nodes with opposite reaching conditions were paired into an
+    /// `if`-`else`, possibly one of a cascade of them.
+    IfElsePairing,
+    /// Nodes whose reaching conditions are all about the value of the same
+    /// variable were grouped into a `Switch`.
+    SwitchRecovery,
+    /// Structuring ran out of its budget and laid out the rest of the graph
+    /// with `Goto`s.
+    GotoFallback,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Explanation {
+    pub phase: Phase,
+    /// the rule of loop refinement that made a loop, mostly from *No More
+    /// Gotos*, e.g. `"DoWhile"`
+    pub rule: Option<&'static str>,
+    /// The edges of the graph being structured that the guard is made of:
+    /// for each condition in it, the edge out of its branch that is taken
+    /// when the condition is as it appears in the guard. For a `Switch`, the
+    /// edges into its cases. Conditions that structuring introduced itself,
+    /// see [`Fallback`](super::Fallback), have none.
+    pub edges: Vec<(NodeIndex, NodeIndex)>,
+}
+
+/// The explanations of the constructs of the ASTs of a structured function.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Decisions {
+    explanations: Vec<Option<Explanation>>,
+}
+
+impl Decisions {
+    /// How the node `id` came to be, if it is a `Cond`, `Loop` or `Switch`.
+    pub fn explain(&self, id: AstNodeId) -> Option<&Explanation> {
+        self.explanations.get(id.0).and_then(Option::as_ref)
+    }
+}
+
+/// The nodes of `ast` in pre-order, so that the node with the
+/// [`AstNodeId`] `n` is at index `n`. The children of a `Cond` are its
+/// `then` and `else` branches, those of a `Switch` its cases and then its
+/// default, and those of an `Unstructured` region the code of its nodes.
+pub fn preorder<B, C, V>(ast: &AstNode<B, C, V>) -> Vec<&AstNode<B, C, V>> {
+    fn go<'a, B, C, V>(ast: &'a AstNode<B, C, V>, out: &mut Vec<&'a AstNode<B, C, V>>) {
+        use self::AstNode::*;
+        out.push(ast);
+        match ast {
+            Seq(seq) => {
+                for a in seq {
+                    go(a, out);
+                }
+            }
+            Cond(_, t, oe) => {
+                go(t, out);
+                if let Some(e) = oe {
+                    go(e, out);
+                }
+            }
+            Loop(_, b) | For(_, _, _, b) | Try(b, _) => go(b, out),
+            Switch(_, cases, default) => {
+                for (_, a) in cases {
+                    go(a, out);
+                }
+                go(default, out);
+            }
+            Unstructured(members) => {
+                for (_, a) in members {
+                    go(a, out);
+                }
+            }
+            BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_)
+            | Label(_) => (),
+        }
+    }
+    let mut out = Vec::new();
+    go(ast, &mut out);
+    out
+}
+
+/// The edges out of each branch of the graph being structured, by the
+/// [`cond_var_key`](super::cond_var_key) of its condition and whether the
+/// condition holds on the edge.
+pub(super) type GuardEdges = HashMap<(usize, bool), Vec<(NodeIndex, NodeIndex)>>;
+
+/// What structuring decided so far. Guards are looked up by value, so that
+/// they are still found after being copied into the final AST.
+pub(super) struct DecisionLog<'cd, C: 'cd> {
+    conds: Vec<(condition::Condition<'cd, C>, Phase)>,
+    loops: Vec<(condition::Condition<'cd, C>, &'static str)>,
+    /// the conditions of the `if`-`else`s whose arms were turned around
+    flipped: Vec<condition::Condition<'cd, C>>,
+    /// the conditions that `dedup_conds` replaced, by the key of the
+    /// variable it replaced them with
+    aliases: HashMap<usize, usize>,
+}
+
+impl<'cd, C> Default for DecisionLog<'cd, C> {
+    fn default() -> Self {
+        DecisionLog {
+            conds: Vec::new(),
+            loops: Vec::new(),
+            flipped: Vec::new(),
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+impl<'cd, C> DecisionLog<'cd, C> {
+    /// Records that `phase` made a `Cond` on `cond`.
+    pub fn cond(&mut self, cond: condition::Condition<'cd, C>, phase: Phase) {
+        self.conds.push((cond, phase));
+    }
+
+    /// Records that the loop refinement `rule` made a loop on `guard`.
+    pub fn refined_loop(&mut self, guard: condition::Condition<'cd, C>, rule: &'static str) {
+        self.loops.push((guard, rule));
+    }
+
+    /// Records that an `if`-`else` on `cond` has its arms the other way
+    /// around than the way they were found, see
+    /// [`StructuringOptions::order_by_weight`](super::StructuringOptions::order_by_weight).
+    pub fn flipped(&mut self, cond: condition::Condition<'cd, C>) {
+        self.flipped.push(cond);
+    }
+
+    /// Records that `old` was rewritten into `new`, with `var` standing in
+    /// for the condition `orig` in it.
+    pub fn replaced(
+        &mut self,
+        old: condition::Condition<'cd, C>,
+        new: condition::Condition<'cd, C>,
+        var: condition::VarRef<'cd, C>,
+        orig: condition::VarRef<'cd, C>,
+    ) {
+        self.aliases.insert(var_key(&*var), var_key(&*orig));
+        if let Some(phase) = self.phase_of(old) {
+            self.conds.push((new, phase));
+        }
+        if self.flipped.contains(&old) {
+            self.flipped.push(new);
+        }
+    }
+
+    /// Whether `dedup_conds` replaced the condition `orig` with a variable.
+    pub fn is_copied(&self, orig: condition::VarRef<'cd, C>) -> bool {
+        let key = var_key(&*orig);
+        self.aliases.values().any(|&k| k == key)
+    }
+
+    fn phase_of(&self, cond: condition::Condition<'cd, C>) -> Option<Phase> {
+        self.conds
+            .iter()
+            .rev()
+            .find(|&&(c, _)| c == cond)
+            .map(|&(_, phase)| phase)
+    }
+
+    fn rule_of(&self, guard: condition::Condition<'cd, C>) -> Option<&'static str> {
+        self.loops
+            .iter()
+            .rev()
+            .find(|&&(c, _)| c == guard)
+            .map(|&(_, rule)| rule)
+    }
+
+    /// Explains the constructs of `asts`, numbered in pre-order across all
+    /// of them. `switch_edges` returns the edges into the cases of a
+    /// `Switch` on a variable.
+    pub fn explain<'a, B, V, F, I>(
+        &self,
+        cctx: condition::Context<'cd, C>,
+        edges: &GuardEdges,
+        switch_edges: F,
+        asts: I,
+    ) -> Decisions
+    where
+        B: 'a,
+        V: 'a,
+        'cd: 'a,
+        F: Fn(&V) -> Vec<(NodeIndex, NodeIndex)>,
+        I: IntoIterator<Item = &'a AstNode<B, condition::Condition<'cd, C>, V>>,
+    {
+        use self::AstNode::*;
+        let explanations = asts
+            .into_iter()
+            .flat_map(preorder)
+            .map(|ast| {
+                let (phase, rule, edges) = match ast {
+                    &Cond(c, _, _) => {
+                        // simplification may have negated it
+                        let phase = self
+                            .phase_of(c)
+                            .or_else(|| self.phase_of(cctx.mk_not(c)))
+                            .unwrap_or(Phase::InitialCollapse);
+                        (phase, None, self.guard_edges(c, edges))
+                    }
+                    Loop(LoopType::PreChecked(c), _) | Loop(LoopType::PostChecked(c), _) => (
+                        Phase::LoopRefinement,
+                        self.rule_of(*c),
+                        self.guard_edges(*c, edges),
+                    ),
+                    Loop(LoopType::Endless, _) => (Phase::InitialCollapse, None, Vec::new()),
+                    Switch(var, _, _) => {
+                        let mut edges = switch_edges(var);
+                        edges.sort();
+                        edges.dedup();
+                        (Phase::SwitchRecovery, None, edges)
+                    }
+                    _ => return None,
+                };
+                Some(Explanation { phase, rule, edges })
+            })
+            .collect();
+        Decisions { explanations }
+    }
+
+    /// The `if`-`else`s of `asts` that were turned around, numbered in
+    /// pre-order across all of them like for [`explain`](Self::explain).
+    pub fn flipped_ids<'a, B, V, I>(&self, asts: I) -> Vec<AstNodeId>
+    where
+        B: 'a,
+        V: 'a,
+        'cd: 'a,
+        I: IntoIterator<Item = &'a AstNode<B, condition::Condition<'cd, C>, V>>,
+    {
+        asts.into_iter()
+            .flat_map(preorder)
+            .enumerate()
+            .filter(
+                |(_, ast)| matches!(ast, AstNode::Cond(c, _, Some(_)) if self.flipped.contains(c)),
+            )
+            .map(|(i, _)| AstNodeId(i))
+            .collect()
+    }
+
+    fn guard_edges(
+        &self,
+        guard: condition::Condition<'cd, C>,
+        edges: &GuardEdges,
+    ) -> Vec<(NodeIndex, NodeIndex)> {
+        let mut atoms = Atoms(Vec::new());
+        guard.fold(&mut atoms);
+        let mut ret: Vec<_> = atoms
+            .0
+            .into_iter()
+            .flat_map(|(mut key, holds)| {
+                while let Some(&orig) = self.aliases.get(&key) {
+                    key = orig;
+                }
+                edges.get(&(key, holds)).into_iter().flatten().cloned()
+            })
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+}
+
+fn var_key<C>(var: &C) -> usize {
+    var as *const C as usize
+}
+
+/// The variables of a condition, by key, and whether each appears as is.
+struct Atoms(Vec<(usize, bool)>);
+
+impl<T> Folder<T> for &mut Atoms {
+    type Output = ();
+
+    fn var(&mut self, normal: bool, var: &T) {
+        self.0.push((var_key(var), normal));
+    }
+
+    fn and<'c, I>(&mut self, operands: I)
+    where
+        I: IntoIterator<Item = condition::Condition<'c, T>>,
+        T: 'c,
+    {
+        for opn in operands {
+            opn.fold(&mut **self);
+        }
+    }
+
+    fn or<'c, I>(&mut self, operands: I)
+    where
+        I: IntoIterator<Item = condition::Condition<'c, T>>,
+        T: 'c,
+    {
+        self.and(operands)
+    }
+}
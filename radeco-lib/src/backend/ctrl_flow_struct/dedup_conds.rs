@@ -1,8 +1,10 @@
 //! See [`run`].
 
 use super::ast::AstNode as AstNodeC;
+use super::ast_arena::AstArena;
 use super::ast_context::AstContextMut;
 use super::condition;
+use super::decisions::DecisionLog;
 use super::{AstNode, CondContext, CondVar, RegionAstContext};
 
 use std::mem;
@@ -12,10 +14,14 @@ use std::mem;
 /// and replaces all subsequent uses of the condition with the new variable.
 ///
 /// `Loop`s should never appear here since acyclic refinement can't introduce
-/// them and any "internal" loops are wrapped in `BasicBlock`s.
+/// them and any "internal" loops are wrapped in `BasicBlock`s, and neither
+/// should `Unstructured` regions, for the same reason.
 pub(super) fn run<'cd, A: AstContextMut>(
     actx: &mut A,
+    name: &mut dyn FnMut() -> String,
     cctx: CondContext<'cd, A>,
+    decisions: &mut DecisionLog<'cd, A::Condition>,
+    arena: &mut AstArena<'cd, A>,
     conds: &[CondVar<'cd, A>],
     mut ast: AstNode<'cd, RegionAstContext<'cd, A>>,
 ) -> AstNode<'cd, RegionAstContext<'cd, A>> {
@@ -28,10 +34,12 @@ pub(super) fn run<'cd, A: AstContextMut>(
 
             if uses.len() > 1 {
                 // replace condition uses with a new boolean variable
-                let bool_var = actx.mk_fresh_bool_var();
+                let bool_var = actx.mk_named_bool_var(&name());
                 let new_cond = cctx.new_var(actx.mk_cond_from_bool_var(&bool_var));
                 for use_cond in &mut uses {
-                    **use_cond = cctx.replace_var_in(**use_cond, check_cond, new_cond);
+                    let old = **use_cond;
+                    **use_cond = cctx.replace_var_in(old, check_cond, new_cond);
+                    decisions.replaced(old, **use_cond, new_cond, check_cond);
                 }
 
                 // escape info from borrow of `ast` (unnecessary after nll)
@@ -45,7 +53,8 @@ pub(super) fn run<'cd, A: AstContextMut>(
         if let Some((bool_var, first_use)) = opt_assign_info {
             // insert the assignment of `bool_var`
             let assign = actx.mk_bool_var_assign(&bool_var, &check_cond);
-            let _res = place_assign(&mut ast, AstNodeC::BasicBlock(assign), first_use);
+            let assign = arena.alloc(AstNodeC::BasicBlock(assign));
+            let _res = place_assign(&mut ast, assign, first_use);
             debug_assert!(_res.is_err());
         }
     }
@@ -84,8 +93,10 @@ impl<'a, 'cd, C> ConditionUseFinder<'a, 'cd, C> {
                     self.run(e);
                 }
             }
-            Loop(_, _) => panic!("found loop"),
-            Break => (),
+            Loop(_, _) | For(..) => panic!("found loop"),
+            Unstructured(_) => panic!("found unstructured region"),
+            Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => (),
+            Try(b, _) => self.run(b),
             Switch(_, cases, default) => {
                 for (_, a) in cases {
                     self.run(a);
@@ -127,8 +138,13 @@ fn place_assign<'cd, B, C, V>(
                 false
             }
         }
-        Loop(_, _) => panic!("found loop"),
-        Break => false,
+        Loop(_, _) | For(..) => panic!("found loop"),
+        Unstructured(_) => panic!("found unstructured region"),
+        Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => false,
+        Try(b, _) => {
+            assign = place_assign(b, assign, first_use)?;
+            false
+        }
         Switch(_, cases, default) => {
             for (_, a) in cases {
                 assign = place_assign(a, assign, first_use)?;
@@ -0,0 +1,187 @@
+//! Dominators computed elsewhere, e.g. for SSA construction, so that
+//! structuring doesn't compute them again, see
+//! [`ControlFlowGraph::set_dominators`].
+
+use super::ast_context::AstContext;
+use super::{internal, ControlFlowGraph, NodeSet, StructureError};
+
+use petgraph::algo::dominators::Dominators;
+use petgraph::prelude::*;
+use petgraph::visit::{NodeIndexable, Walker};
+
+use std::collections::HashMap;
+
+/// The dominator tree of a graph, as the interval of each node in a
+/// preorder of the tree: a node dominates the nodes whose interval is
+/// within its own.
+#[derive(Clone, Debug)]
+pub(super) struct DomTree {
+    /// `(first, last)` of each node by index, in the preorder; `None` for
+    /// the slots without a node
+    intervals: Vec<Option<(usize, usize)>>,
+}
+
+impl DomTree {
+    fn dominates(&self, a: NodeIndex, b: NodeIndex) -> bool {
+        let interval = |n: NodeIndex| self.intervals.get(n.index()).copied().flatten();
+        match (interval(a), interval(b)) {
+            (Some((a_first, a_last)), Some((b_first, b_last))) => {
+                a_first <= b_first && b_last <= a_last
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<'cd, A: AstContext> ControlFlowGraph<'cd, A> {
+    /// Has structuring use `doms` as the dominators of the graph, with all
+    /// of its edges, like `petgraph::algo::dominators::simple_fast(&cfg,
+    /// cfg.entry())` computes them, for the acyclic regions it collapses and
+    /// for [`structure_dominated`](Self::structure_dominated). They are
+    /// forgotten once the graph changes otherwise: by any of the methods
+    /// that add or remove nodes or edges, or once structuring gets to a
+    /// loop or to the handlers.
+    ///
+    /// Fails, and leaves the dominators structuring had, if `doms` can't be
+    /// those of the graph: if they aren't rooted at the entry, if a node
+    /// reachable from it has no immediate dominator, or if, by `doms`, the
+    /// immediate dominator of a node isn't the nearest common dominator of
+    /// the predecessors of the node that the node itself doesn't dominate.
+    /// That leaves dominators that are wrong only in saying that some nodes
+    /// dominate fewer nodes than they do; structuring is still right with
+    /// those, but collapses smaller regions, and `structure_dominated` only
+    /// gets to the nodes that `doms` says the node dominates.
+    pub fn set_dominators(&mut self, doms: &Dominators<NodeIndex>) -> Result<(), StructureError> {
+        let fail = |detail| Err(internal("set_dominators", detail));
+        if doms.root() != self.entry {
+            return fail(format!(
+                "the dominators are rooted at node {}, not at the entry, node {}",
+                doms.root().index(),
+                self.entry.index()
+            ));
+        }
+        let mut children: HashMap<_, Vec<_>> = HashMap::new();
+        let reachable: Vec<_> = Dfs::new(&self.graph, self.entry)
+            .iter(&self.graph)
+            .collect();
+        for &n in &reachable {
+            if n == self.entry {
+                continue;
+            }
+            match doms.immediate_dominator(n) {
+                Some(idom) if self.graph.contains_node(idom) => {
+                    children.entry(idom).or_default().push(n)
+                }
+                Some(idom) => {
+                    return fail(format!(
+                        "the immediate dominator of node {} is node {}, which isn't in the graph",
+                        n.index(),
+                        idom.index()
+                    ))
+                }
+                None => return fail(format!("node {} has no immediate dominator", n.index())),
+            }
+        }
+
+        let mut tree = DomTree {
+            intervals: vec![None; self.graph.node_bound()],
+        };
+        let mut next = 0;
+        let mut stack = vec![(self.entry, false)];
+        while let Some((n, done)) = stack.pop() {
+            if done {
+                let first = tree.intervals[n.index()].unwrap().0;
+                tree.intervals[n.index()] = Some((first, next - 1));
+                continue;
+            }
+            tree.intervals[n.index()] = Some((next, next));
+            next += 1;
+            stack.push((n, true));
+            stack.extend(children.get(&n).into_iter().flatten().map(|&c| (c, false)));
+        }
+        if next != reachable.len() {
+            return fail("the immediate dominators have a cycle".to_owned());
+        }
+
+        // `idom` is the immediate dominator of `n` iff it is the nearest
+        // common ancestor in the tree of the predecessors of `n` but those
+        // `n` dominates: it is one of them, or two of them are below
+        // different children of it
+        for cs in children.values_mut() {
+            cs.sort_by_key(|&c| tree.intervals[c.index()].unwrap().0);
+        }
+        for &n in &reachable {
+            if n == self.entry {
+                continue;
+            }
+            let idom = doms.immediate_dominator(n).unwrap();
+            // the child of `idom` that all the predecessors so far are
+            // below, if there is one
+            let mut below = None;
+            let mut nearest = false;
+            for p in self.graph.neighbors_directed(n, Incoming) {
+                if tree.intervals[p.index()].is_none() {
+                    continue;
+                }
+                if !tree.dominates(idom, p) {
+                    return fail(format!(
+                        "node {} is the immediate dominator of node {}, but not of its \
+                         predecessor, node {}",
+                        idom.index(),
+                        n.index(),
+                        p.index()
+                    ));
+                }
+                if p == idom {
+                    nearest = true;
+                    continue;
+                }
+                if tree.dominates(n, p) {
+                    continue;
+                }
+                let first = tree.intervals[p.index()].unwrap().0;
+                let cs = &children[&idom];
+                let c =
+                    cs[cs.partition_point(|&c| tree.intervals[c.index()].unwrap().0 <= first) - 1];
+                match below {
+                    None => below = Some(c),
+                    Some(b) if b != c => nearest = true,
+                    Some(_) => (),
+                }
+            }
+            match below {
+                Some(c) if !nearest => {
+                    return fail(format!(
+                        "node {} is the immediate dominator of node {}, but node {} dominates all \
+                         of its predecessors",
+                        idom.index(),
+                        n.index(),
+                        c.index()
+                    ))
+                }
+                _ => (),
+            }
+        }
+        self.dominators = Some(tree);
+        Ok(())
+    }
+
+    /// Forgets the dominators of [`set_dominators`](Self::set_dominators),
+    /// once the graph changes.
+    pub(super) fn graph_changed(&mut self) {
+        self.dominators = None;
+    }
+
+    /// The nodes that `h` dominates, by the dominators of
+    /// [`set_dominators`](Self::set_dominators) if there are any.
+    pub(super) fn dominated_by(&self, h: NodeIndex) -> NodeSet {
+        match &self.dominators {
+            Some(tree) => self
+                .graph
+                .node_indices()
+                .filter(|&n| tree.dominates(h, n))
+                .collect(),
+            None => super::graph_utils::dominated_by(&self.graph, self.entry, h),
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Dumps of each step of structuring as DOT, for debugging, see
+//! [`StructuringOptions::dump_dir`](super::StructuringOptions::dump_dir).
+//!
+//! Step `n`, counting from 1, collapses the region or loop headed by node
+//! `h`. Before it, the graph is written to `<n>-<h>-cfg.dot`, and after it,
+//! the AST the region became to `<n>-<h>-ast.dot`, with `n` padded to three
+//! digits so that the files sort in order. In the graph, the nodes of the
+//! region are filled, its header has a double border, and the back edges a
+//! loop lost are dashed. Structuring can't format blocks or conditions, so
+//! nodes are only shown by their index and what kind of AST they hold.
+//!
+//! When structuring makes no progress, what is left of the graph is
+//! written to `residual.json`, see [`residual_blocks`].
+//!
+//! Dumping is best-effort: once writing a file fails, the error is logged
+//! and nothing more is written.
+
+use super::ast::{self, LoopType};
+use super::ast_context::AstContext;
+use super::{CfgEdge, CfgNode, NodeSet};
+
+use petgraph::prelude::*;
+
+use std::fmt::Write;
+use std::fs;
+use std::iter;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub(super) struct Dumper {
+    dir: PathBuf,
+    step: usize,
+    failed: bool,
+}
+
+impl Dumper {
+    pub(super) fn new(dir: &Path) -> Self {
+        Dumper {
+            dir: dir.to_owned(),
+            step: 0,
+            failed: false,
+        }
+    }
+
+    /// Starts a step, dumping `graph` before the region `nodes` headed by
+    /// `header` is collapsed. `back_edges` are drawn but no longer in the
+    /// graph.
+    pub(super) fn before<A: AstContext>(
+        &mut self,
+        graph: &StableDiGraph<CfgNode<A>, CfgEdge>,
+        entry: NodeIndex,
+        header: NodeIndex,
+        nodes: &NodeSet,
+        back_edges: &[(NodeIndex, NodeIndex)],
+    ) {
+        self.step += 1;
+        let name = format!("{:03}-{}-cfg.dot", self.step, header.index());
+        self.write(&name, || cfg_dot(graph, entry, header, nodes, back_edges));
+    }
+
+    /// Finishes the step, dumping the AST the region became.
+    pub(super) fn after<B, C, V>(&mut self, header: NodeIndex, ast: &ast::AstNode<B, C, V>) {
+        let name = format!("{:03}-{}-ast.dot", self.step, header.index());
+        self.write(&name, || ast_dot(ast));
+    }
+
+    /// Dumps the blocks of [`residual_blocks`].
+    pub(super) fn residual(&mut self, blocks: &str) {
+        self.write("residual.json", || blocks.to_owned());
+    }
+
+    fn write<F: FnOnce() -> String>(&mut self, name: &str, contents: F) {
+        if self.failed {
+            return;
+        }
+        let path = self.dir.join(name);
+        let written = fs::create_dir_all(&self.dir).and_then(|()| fs::write(&path, contents()));
+        if let Err(_err) = written {
+            radeco_warn!(
+                "structure: dump failed path={} error={}",
+                path.display(),
+                _err
+            );
+            self.failed = true;
+        }
+    }
+}
+
+fn cfg_dot<A: AstContext>(
+    graph: &StableDiGraph<CfgNode<A>, CfgEdge>,
+    entry: NodeIndex,
+    header: NodeIndex,
+    nodes: &NodeSet,
+    back_edges: &[(NodeIndex, NodeIndex)],
+) -> String {
+    let mut dot = String::from("digraph cfg {\n    node [shape=box];\n");
+    for n in graph.node_indices() {
+        let kind = match &graph[n] {
+            CfgNode::Code(ast) => summary(ast),
+            CfgNode::Condition(_) => "condition".to_owned(),
+            CfgNode::Dummy(what) => format!("dummy: {}", what),
+        };
+        let entry = if n == entry { "entry " } else { "" };
+        let _ = write!(
+            dot,
+            "    n{} [label=\"{}{}: {}\"",
+            n.index(),
+            entry,
+            n.index(),
+            escape(&kind)
+        );
+        if nodes.contains(n) {
+            dot.push_str(", style=filled, fillcolor=lightgrey");
+        }
+        if n == header {
+            dot.push_str(", peripheries=2");
+        }
+        dot.push_str("];\n");
+    }
+    for e in graph.edge_references() {
+        let attrs = match (e.weight(), &graph[e.source()]) {
+            (CfgEdge::Unwind, _) => " [label=\"unwind\", style=dotted]",
+            (CfgEdge::Abnormal, _) => " [label=\"abnormal\", style=dotted]",
+            (CfgEdge::True, CfgNode::Condition(_)) => " [label=\"T\"]",
+            (CfgEdge::False, CfgNode::Condition(_)) => " [label=\"F\"]",
+            _ => "",
+        };
+        let _ = writeln!(
+            dot,
+            "    n{} -> n{}{};",
+            e.source().index(),
+            e.target().index(),
+            attrs
+        );
+    }
+    for &(from, to) in back_edges {
+        let _ = writeln!(
+            dot,
+            "    n{} -> n{} [label=\"back\", style=dashed];",
+            from.index(),
+            to.index()
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// `graph` as the radare2 blocks of a function, e.g. for a fixture that
+/// reproduces a bug, see [`from_r2::parse_blocks`](super::from_r2::parse_blocks).
+/// Node `n` is the block at `0x10 * (n + 1)`, and the entry comes first. A
+/// condition jumps along its `True` edge and fails along its `False` one.
+pub(super) fn residual_blocks<A: AstContext>(
+    graph: &StableDiGraph<CfgNode<A>, CfgEdge>,
+    entry: NodeIndex,
+) -> String {
+    let addr = |n: NodeIndex| 0x10 * (n.index() as u64 + 1);
+    let nodes = iter::once(entry).chain(graph.node_indices().filter(|&n| n != entry));
+    let blocks: Vec<_> = nodes
+        .map(|n| {
+            let mut block = format!("  {{\"addr\": {}, \"size\": 4", addr(n));
+            let mut jump = None;
+            let mut fail = None;
+            for e in graph.edges(n) {
+                match e.weight() {
+                    CfgEdge::False => fail = fail.or_else(|| Some(e.target())),
+                    _ => jump = jump.or_else(|| Some(e.target())),
+                }
+            }
+            if let Some(jump) = jump {
+                let _ = write!(block, ", \"jump\": {}", addr(jump));
+            }
+            if let Some(fail) = fail {
+                let _ = write!(block, ", \"fail\": {}", addr(fail));
+            }
+            block.push('}');
+            block
+        })
+        .collect();
+    format!("[\n{}\n]\n", blocks.join(",\n"))
+}
+
+fn ast_dot<B, C, V>(ast: &ast::AstNode<B, C, V>) -> String {
+    let mut dot = String::from("digraph ast {\n    node [shape=box];\n");
+    let mut next = 0;
+    ast_nodes(ast, &mut dot, &mut next);
+    dot.push_str("}\n");
+    dot
+}
+
+/// Writes `ast` and its children, numbering them from `next`, and returns
+/// the number of `ast`.
+fn ast_nodes<B, C, V>(ast: &ast::AstNode<B, C, V>, dot: &mut String, next: &mut usize) -> usize {
+    use self::ast::AstNode::*;
+
+    let id = *next;
+    *next += 1;
+    let _ = writeln!(dot, "    a{} [label=\"{}\"];", id, escape(&summary(ast)));
+    let mut child = |edge: &str, child: &ast::AstNode<B, C, V>, dot: &mut String| {
+        let child_id = ast_nodes(child, dot, next);
+        let _ = writeln!(dot, "    a{} -> a{} [label=\"{}\"];", id, child_id, edge);
+    };
+    match ast {
+        Seq(seq) => {
+            for (i, ast) in seq.iter().enumerate() {
+                child(&i.to_string(), ast, dot);
+            }
+        }
+        Cond(_, then, els) => {
+            child("then", then, dot);
+            if let Some(els) = els {
+                child("else", els, dot);
+            }
+        }
+        Loop(_, body) | For(_, _, _, body) => child("body", body, dot),
+        Switch(_, cases, default) => {
+            for (values, ast) in cases {
+                let values: Vec<_> = values
+                    .ranges()
+                    .iter()
+                    .map(|&(lo, hi)| match hi - lo {
+                        0 => lo.to_string(),
+                        _ => format!("{}..={}", lo, hi),
+                    })
+                    .collect();
+                child(&values.join(", "), ast, dot);
+            }
+            child("default", default, dot);
+        }
+        Try(body, _) => child("body", body, dot),
+        Unstructured(members) => {
+            for (label, ast) in members {
+                child(&format!("label {}", label.0), ast, dot);
+            }
+        }
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_)
+        | Label(_) => (),
+    }
+    id
+}
+
+/// What kind of AST `ast` is.
+fn summary<B, C, V>(ast: &ast::AstNode<B, C, V>) -> String {
+    use self::ast::AstNode::*;
+
+    match ast {
+        BasicBlock(_) => "block".to_owned(),
+        Seq(seq) if seq.is_empty() => "empty".to_owned(),
+        Seq(seq) => format!("seq of {}", seq.len()),
+        Cond(_, _, None) => "if".to_owned(),
+        Cond(_, _, Some(_)) => "if-else".to_owned(),
+        Loop(LoopType::PreChecked(_), _) => "while".to_owned(),
+        Loop(LoopType::PostChecked(_), _) => "do-while".to_owned(),
+        Loop(LoopType::Endless, _) => "endless loop".to_owned(),
+        For(..) => "for".to_owned(),
+        Break => "break".to_owned(),
+        Switch(_, cases, _) => format!("switch of {} cases", cases.len()),
+        Continue => "continue".to_owned(),
+        Return => "return".to_owned(),
+        TailCall(_) => "tail call".to_owned(),
+        IndirectJump(_) => "indirect jump".to_owned(),
+        Goto(label) => format!("goto {}", label.0),
+        Label(label) => format!("label {}", label.0),
+        Try(_, handler) => format!("try, handler {}", handler.0),
+        Unstructured(members) => format!("unstructured region of {}", members.len()),
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::condition;
+    use super::super::from_r2::{self, R2BasicBlock};
+    use super::super::trace::{TraceCollector, TraceOp};
+    use super::super::StructuringOptions;
+
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    fn block(addr: u64, jump: Option<u64>, fail: Option<u64>) -> R2BasicBlock {
+        R2BasicBlock {
+            addr,
+            size: 4,
+            jump,
+            fail,
+            cases: Vec::new(),
+            default: None,
+            unresolved_jump: false,
+            esil: Vec::new(),
+        }
+    }
+
+    /// Structures a loop with an `if` in it, and an `if` after it, dumping
+    /// to `dir`, and returns the number of collapses.
+    fn structure(dir: &Path) -> usize {
+        let blocks = vec![
+            block(0x10, Some(0x20), None),
+            block(0x20, Some(0x28), Some(0x24)),
+            block(0x24, Some(0x28), None),
+            block(0x28, Some(0x20), Some(0x30)),
+            block(0x30, Some(0x40), Some(0x38)),
+            block(0x38, Some(0x40), None),
+            block(0x40, None, None),
+        ];
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let collector = Rc::new(RefCell::new(TraceCollector::default()));
+        let opts = StructuringOptions {
+            trace: Some(collector.clone()),
+            dump_dir: Some(dir.to_owned()),
+            ..Default::default()
+        };
+        cfg.structure_whole_with(&opts);
+        let steps = &collector.borrow().steps;
+        steps.iter().filter(|s| s.op != TraceOp::Refinement).count()
+    }
+
+    #[test]
+    fn dumps() {
+        let dir = env::temp_dir().join("radeco_dump_test");
+        let _ = fs::remove_dir_all(&dir);
+        let collapses = structure(&dir);
+        // the `if` in the loop, before the main pass, the `if` after the
+        // loop, the loop, and the three regions they end up in
+        assert_eq!(collapses, 6);
+
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 2 * collapses, "{:?}", names);
+        for (i, pair) in names.chunks(2).enumerate() {
+            let step = format!("{:03}-", i + 1);
+            assert!(pair[0].starts_with(&step) && pair[0].ends_with("-ast.dot"));
+            assert_eq!(pair[1], pair[0].replace("-ast.dot", "-cfg.dot"));
+            for name in pair {
+                let dot = fs::read_to_string(dir.join(name)).unwrap();
+                assert!(dot.starts_with("digraph ") && dot.ends_with("}\n"));
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dump_errors_are_not_fatal() {
+        // a file, so that no directory can be made there
+        let file = env::temp_dir().join("radeco_dump_test_file");
+        fs::write(&file, "").unwrap();
+        assert_eq!(structure(&file.join("dump")), 6);
+        fs::remove_file(&file).unwrap();
+    }
+}
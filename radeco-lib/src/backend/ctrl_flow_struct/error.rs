@@ -0,0 +1,158 @@
+//! Why structuring fails, and what it repairs instead, see
+//! [`StructureError`] and [`InputDefect`].
+
+use petgraph::graph::NodeIndex;
+
+use std::fmt;
+
+/// How to deal with the defects of the blocks a frontend found for a
+/// function, see [`InputDefect`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum InputMode {
+    /// Repair what can be repaired, and report each defect in
+    /// [`StructuringReport::warnings`](super::StructuringReport::warnings).
+    #[default]
+    Lenient,
+    /// Fail with [`StructureError::Input`] on the first defect.
+    Strict,
+}
+
+/// Something wrong with the blocks a frontend found for a function, and how
+/// it is repaired in [`InputMode::Lenient`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum InputDefect {
+    /// The entry, at `entry`, isn't the block with the lowest address,
+    /// `lowest`. It stays the entry.
+    EntryNotLowest { entry: u64, lowest: u64 },
+    /// The block at this address is empty. It is kept.
+    EmptyBlock(u64),
+    /// The block at `from` goes on to `to`, which is inside the function but
+    /// not the start of a block, e.g. because the block there was filtered
+    /// out. The edge is dropped.
+    DanglingEdge { from: u64, to: u64 },
+    /// The block at this address can't be reached from the entry. It is
+    /// dropped.
+    Unreachable(u64),
+    /// The graph has an `Unwind` or `Abnormal` edge between these nodes,
+    /// which
+    /// [`ControlFlowGraph::structure_by_region`](super::ControlFlowGraph::structure_by_region)
+    /// doesn't support. It fails on it in either mode.
+    UnsupportedEdge { from: NodeIndex, to: NodeIndex },
+    /// The edge between `from` and `to` leaves the acyclic region headed by
+    /// `header` for a node other than its successor, in a graph that doesn't
+    /// nest. It becomes a `Goto`, see
+    /// [`StructuringReport::side_exits`](super::StructuringReport::side_exits).
+    SideExit {
+        header: NodeIndex,
+        from: NodeIndex,
+        to: NodeIndex,
+    },
+}
+
+impl fmt::Display for InputDefect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputDefect::EntryNotLowest { entry, lowest } => write!(
+                f,
+                "the entry at {:#x} isn't the lowest block, at {:#x}",
+                entry, lowest
+            ),
+            InputDefect::EmptyBlock(addr) => write!(f, "the block at {:#x} is empty", addr),
+            InputDefect::DanglingEdge { from, to } => write!(
+                f,
+                "the block at {:#x} goes on to {:#x}, where no block starts",
+                from, to
+            ),
+            InputDefect::Unreachable(addr) => {
+                write!(
+                    f,
+                    "the block at {:#x} can't be reached from the entry",
+                    addr
+                )
+            }
+            InputDefect::UnsupportedEdge { from, to } => write!(
+                f,
+                "the edge from node {} to node {} unwinds or is abnormal, which isn't supported \
+                 region by region",
+                from.index(),
+                to.index()
+            ),
+            InputDefect::SideExit { header, from, to } => write!(
+                f,
+                "the edge from node {} to node {} leaves the region headed by node {} for a node \
+                 but its successor",
+                from.index(),
+                to.index(),
+                header.index()
+            ),
+        }
+    }
+}
+
+/// Why a function couldn't be structured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum StructureError {
+    /// A callee couldn't be inlined, see
+    /// [`ControlFlowGraph::inline_at`](super::ControlFlowGraph::inline_at).
+    Inline(&'static str),
+    /// The function couldn't be converted into a `ControlFlowGraph`.
+    Import(&'static str),
+    /// The blocks of the function have a defect, in [`InputMode::Strict`].
+    Input(InputDefect),
+    /// The graph doesn't meet the preconditions of
+    /// [`ControlFlowGraph::new`](super::ControlFlowGraph::new), or structuring
+    /// broke one of its own invariants; `location` names the function that
+    /// found out.
+    Internal {
+        location: &'static str,
+        detail: String,
+    },
+    /// Structuring stopped shrinking the graph, or got to the end of its main
+    /// pass with more than one node left, which is a bug. `headers` are the
+    /// nodes left besides the entry, each of which should have headed a region
+    /// or loop. `graph` is what is left of the graph as radare2 blocks in the
+    /// format of the fixtures in `test_files`, see
+    /// [`from_r2::parse_blocks`](super::from_r2::parse_blocks); it is also
+    /// written to
+    /// [`StructuringOptions::dump_dir`](super::StructuringOptions::dump_dir) as
+    /// `residual.json`.
+    NoProgress {
+        headers: Vec<NodeIndex>,
+        graph: String,
+    },
+    /// The [`StructuringOptions::cancel`](super::StructuringOptions::cancel)
+    /// token was cancelled.
+    Cancelled,
+    /// The [`StructuringOptions::budget`](super::StructuringOptions::budget)
+    /// ran out before this region was structured; only in the
+    /// [`FailedRegion`](super::FailedRegion)s of
+    /// [`ControlFlowGraph::structure_by_region`](super::ControlFlowGraph::structure_by_region).
+    BudgetExhausted,
+}
+
+impl fmt::Display for StructureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StructureError::Import(msg) => write!(f, "{}", msg),
+            StructureError::Inline(msg) => write!(f, "inline: {}", msg),
+            StructureError::Cancelled => write!(f, "structuring was cancelled"),
+            StructureError::BudgetExhausted => write!(f, "the budget ran out"),
+            StructureError::Input(defect) => write!(f, "input: {}", defect),
+            StructureError::Internal { location, detail } => write!(f, "{}: {}", location, detail),
+            StructureError::NoProgress { headers, .. } => {
+                let headers: Vec<_> = headers.iter().map(|n| n.index().to_string()).collect();
+                write!(
+                    f,
+                    "structuring made no progress, with nodes {} left",
+                    headers.join(", ")
+                )
+            }
+        }
+    }
+}
+
+pub(super) fn internal(location: &'static str, detail: String) -> StructureError {
+    StructureError::Internal { location, detail }
+}
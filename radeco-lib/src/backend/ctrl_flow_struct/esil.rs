@@ -0,0 +1,472 @@
+//! Lifts the predicate of a conditional jump out of the ESIL of the
+//! instructions of its basic block, as radare2 prints it.
+//!
+//! Only the little ESIL that compares and conditional jumps are made of is
+//! understood: pushing registers and numbers, `==` and the flag computations
+//! (`$z`, `$b`, `$s`, `$o`) that follow it, assignments, `&`, `|`, `^`, `!`
+//! and the `?{` of the jump. Which comparison a jump makes is found by
+//! comparing the truth table of its predicate over the flags with those of
+//! the comparisons, so it doesn't matter how the predicate is spelled.
+//! Registers are named as they were when the flags were set; writes to them
+//! later in the block aren't followed.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// A comparison between two operands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    /// unsigned `<`
+    ULt,
+    UGe,
+    ULe,
+    UGt,
+    /// signed `<`
+    SLt,
+    SGe,
+    SLe,
+    SGt,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Reg(String),
+    Const(u64),
+    /// `lhs & rhs`
+    BitAnd(Box<Operand>, Box<Operand>),
+}
+
+/// The condition under which a jump is taken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Predicate {
+    /// `lhs op rhs`
+    Compare(CmpOp, Operand, Operand),
+    /// the ESIL of the jump, which couldn't be lifted
+    Raw(String),
+}
+
+/// Lifts the predicate of the conditional jump ending a basic block from
+/// `esil`, the ESIL of each instruction of the block; the jump is the last
+/// one. Falls back to [`Predicate::Raw`] if it can't.
+pub fn lift_condition<S: AsRef<str>>(esil: &[S]) -> Predicate {
+    let raw = || Predicate::Raw(esil.last().map_or("", |s| s.as_ref()).to_owned());
+    let (jump, rest) = match esil.split_last() {
+        Some(split) => split,
+        None => return raw(),
+    };
+    let mut flags = Flags::default();
+    for insn in rest {
+        flags.step(insn.as_ref());
+    }
+    flags.predicate(jump.as_ref()).unwrap_or_else(raw)
+}
+
+/// The flags of x86 and their names in ESIL.
+const FLAG_NAMES: &[&str] = &["zf", "cf", "sf", "of", "pf", "af"];
+
+/// A flag of the last comparison of `lhs` and `rhs`, i.e. of `lhs - rhs`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Flag {
+    Zero,
+    Borrow,
+    Sign,
+    Overflow,
+}
+
+/// A boolean computed from flags.
+#[derive(Clone, Debug)]
+enum Formula {
+    Const(bool),
+    Flag(Flag),
+    /// a flag computation we don't follow
+    Unknown,
+    Not(Box<Formula>),
+    And(Box<Formula>, Box<Formula>),
+    Or(Box<Formula>, Box<Formula>),
+    Xor(Box<Formula>, Box<Formula>),
+}
+
+/// An entry of the ESIL stack.
+#[derive(Clone, Debug)]
+enum Item {
+    /// a register or flag, which isn't read until it is used
+    Name(String),
+    Operand(Operand),
+    Formula(Formula),
+}
+
+/// The flags set by the instructions seen so far.
+#[derive(Debug, Default)]
+struct Flags {
+    values: HashMap<String, Formula>,
+    /// the operands of the last `==`
+    cmp: Option<(Operand, Operand)>,
+}
+
+impl Flags {
+    /// Follows an instruction that isn't the last one of the block.
+    fn step(&mut self, insn: &str) {
+        let mut stack = Vec::new();
+        if self.eval(insn, &mut stack).is_none() {
+            // if we couldn't follow it, it may have set any flag
+            let tokens = tokens(insn);
+            if tokens.iter().any(|t| FLAG_NAMES.contains(t)) || insn.contains("==") {
+                self.values.clear();
+                self.cmp = None;
+            }
+        }
+    }
+
+    /// Evaluates `insn` up to the end or up to its first `?{`, whose
+    /// condition is returned.
+    fn eval(&mut self, insn: &str, stack: &mut Vec<Item>) -> Option<Option<Formula>> {
+        for token in tokens(insn) {
+            match token {
+                "==" => {
+                    let lhs = self.operand(stack.pop()?)?;
+                    let rhs = self.operand(stack.pop()?)?;
+                    self.cmp = Some((lhs, rhs));
+                }
+                "$z" => stack.push(Item::Formula(Formula::Flag(Flag::Zero))),
+                "$b" | "$s" | "$o" | "$c" => {
+                    // the bit the flag is computed from
+                    stack.pop()?;
+                    let flag = match token {
+                        "$b" => Formula::Flag(Flag::Borrow),
+                        "$s" => Formula::Flag(Flag::Sign),
+                        "$o" => Formula::Flag(Flag::Overflow),
+                        _ => Formula::Unknown,
+                    };
+                    stack.push(Item::Formula(flag));
+                }
+                "$p" => stack.push(Item::Formula(Formula::Unknown)),
+                "=" | ":=" => {
+                    let dst = match stack.pop()? {
+                        Item::Name(dst) => dst,
+                        _ => return None,
+                    };
+                    let val = stack.pop()?;
+                    if FLAG_NAMES.contains(&&*dst) {
+                        let val = self.formula(val).unwrap_or(Formula::Unknown);
+                        self.values.insert(dst, val);
+                    }
+                }
+                "&" | "|" | "^" => {
+                    let a = stack.pop()?;
+                    let b = stack.pop()?;
+                    let item = match (self.formula(a.clone()), self.formula(b.clone())) {
+                        (Some(a), Some(b)) => {
+                            let (a, b) = (Box::new(a), Box::new(b));
+                            Item::Formula(match token {
+                                "&" => Formula::And(a, b),
+                                "|" => Formula::Or(a, b),
+                                _ => Formula::Xor(a, b),
+                            })
+                        }
+                        _ if token == "&" => {
+                            let a = self.operand(a)?;
+                            let b = self.operand(b)?;
+                            Item::Operand(if a == b {
+                                a
+                            } else {
+                                Operand::BitAnd(Box::new(a), Box::new(b))
+                            })
+                        }
+                        _ => return None,
+                    };
+                    stack.push(item);
+                }
+                "!" => {
+                    let a = self.formula(stack.pop()?)?;
+                    stack.push(Item::Formula(Formula::Not(Box::new(a))));
+                }
+                "?{" => return Some(Some(self.formula(stack.pop()?)?)),
+                _ => stack.push(item(token)?),
+            }
+        }
+        Some(None)
+    }
+
+    /// Lifts the condition of the jump `insn`, if it has one we understand.
+    fn predicate(&mut self, insn: &str) -> Option<Predicate> {
+        let formula = self.eval(insn, &mut Vec::new())??;
+        let (lhs, rhs) = self.cmp.clone()?;
+        let worlds = worlds(rhs == Operand::Const(0));
+        let truth_table = worlds
+            .iter()
+            .map(|w| formula.holds(w))
+            .collect::<Option<Vec<_>>>()?;
+        let op = CMP_OPS.iter().find(|op| {
+            worlds
+                .iter()
+                .map(|w| op.holds(w))
+                .eq(truth_table.iter().cloned())
+        })?;
+        Some(Predicate::Compare(*op, lhs, rhs))
+    }
+
+    fn operand(&self, item: Item) -> Option<Operand> {
+        match item {
+            Item::Name(ref name) if FLAG_NAMES.contains(&&**name) => None,
+            Item::Name(name) => Some(Operand::Reg(name)),
+            Item::Operand(op) => Some(op),
+            Item::Formula(_) => None,
+        }
+    }
+
+    fn formula(&self, item: Item) -> Option<Formula> {
+        match item {
+            Item::Name(name) => self.values.get(&name).cloned(),
+            Item::Operand(Operand::Const(0)) => Some(Formula::Const(false)),
+            Item::Operand(Operand::Const(1)) => Some(Formula::Const(true)),
+            Item::Operand(_) => None,
+            Item::Formula(f) => Some(f),
+        }
+    }
+}
+
+fn tokens(insn: &str) -> Vec<&str> {
+    insn.split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// The stack entry pushed by a register or number.
+fn item(token: &str) -> Option<Item> {
+    let num = if let Some(hex) = token.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        token.parse().ok()
+    };
+    if let Some(num) = num {
+        return Some(Item::Operand(Operand::Const(num)));
+    }
+    let is_name = token.starts_with(|c: char| c.is_ascii_alphabetic())
+        && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_name {
+        Some(Item::Name(token.to_owned()))
+    } else {
+        None
+    }
+}
+
+/// The values of the zero, borrow, sign and overflow flags.
+type World = [bool; 4];
+
+/// The values the flags of a comparison can take together. Equal operands
+/// set no other flag, and subtracting zero never borrows or overflows.
+fn worlds(rhs_is_zero: bool) -> Vec<World> {
+    (0..16)
+        .map(|i| [i & 1 != 0, i & 2 != 0, i & 4 != 0, i & 8 != 0])
+        .filter(|&[z, b, s, o]| !(z && (b || s || o)) && !(rhs_is_zero && (b || o)))
+        .collect()
+}
+
+/// The comparisons, in the order they are preferred in when several have
+/// the same truth table.
+const CMP_OPS: &[CmpOp] = &[
+    CmpOp::Eq,
+    CmpOp::Ne,
+    CmpOp::ULt,
+    CmpOp::UGe,
+    CmpOp::ULe,
+    CmpOp::UGt,
+    CmpOp::SLt,
+    CmpOp::SGe,
+    CmpOp::SLe,
+    CmpOp::SGt,
+];
+
+impl CmpOp {
+    /// Whether `lhs op rhs` holds when the flags of `lhs - rhs` are `w`.
+    fn holds(self, w: &World) -> bool {
+        let [z, b, s, o] = *w;
+        match self {
+            CmpOp::Eq => z,
+            CmpOp::Ne => !z,
+            CmpOp::ULt => b,
+            CmpOp::UGe => !b,
+            CmpOp::ULe => b || z,
+            CmpOp::UGt => !b && !z,
+            CmpOp::SLt => s != o,
+            CmpOp::SGe => s == o,
+            CmpOp::SLe => z || s != o,
+            CmpOp::SGt => !z && s == o,
+        }
+    }
+
+    /// The C operator, and whether its operands are signed.
+    pub fn c_operator(self) -> (&'static str, bool) {
+        match self {
+            CmpOp::Eq => ("==", false),
+            CmpOp::Ne => ("!=", false),
+            CmpOp::ULt => ("<", false),
+            CmpOp::UGe => (">=", false),
+            CmpOp::ULe => ("<=", false),
+            CmpOp::UGt => (">", false),
+            CmpOp::SLt => ("<", true),
+            CmpOp::SGe => (">=", true),
+            CmpOp::SLe => ("<=", true),
+            CmpOp::SGt => (">", true),
+        }
+    }
+}
+
+impl Predicate {
+    /// The value the predicate always has, if it compares an operand with
+    /// itself, or two numbers whose width doesn't matter to the comparison.
+    pub fn constant_value(&self) -> Option<bool> {
+        match self {
+            Predicate::Compare(op, lhs, rhs) if lhs == rhs => {
+                Some(op.holds(&[true, false, false, false]))
+            }
+            Predicate::Compare(op, Operand::Const(a), Operand::Const(b)) => match op {
+                CmpOp::Eq => Some(a == b),
+                CmpOp::Ne => Some(a != b),
+                CmpOp::ULt => Some(a < b),
+                CmpOp::UGe => Some(a >= b),
+                CmpOp::ULe => Some(a <= b),
+                CmpOp::UGt => Some(a > b),
+                // the sign bit depends on the width
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Formula {
+    fn holds(&self, w: &World) -> Option<bool> {
+        Some(match self {
+            Formula::Const(c) => *c,
+            Formula::Flag(f) => w[*f as usize],
+            Formula::Unknown => return None,
+            Formula::Not(a) => !a.holds(w)?,
+            Formula::And(a, b) => a.holds(w)? && b.holds(w)?,
+            Formula::Or(a, b) => a.holds(w)? || b.holds(w)?,
+            Formula::Xor(a, b) => a.holds(w)? != b.holds(w)?,
+        })
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(r) => f.write_str(r),
+            Operand::Const(c) if *c < 10 => write!(f, "{}", c),
+            Operand::Const(c) => write!(f, "{:#x}", c),
+            Operand::BitAnd(a, b) => write!(f, "({} & {})", a, b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CMP: &str = "rbx,rax,==,$z,zf,:=,64,$b,cf,:=,$p,pf,:=,63,$s,sf,:=,63,$o,of,:=";
+    const TEST: &str = "0,eax,eax,&,==,$z,zf,:=,$p,pf,:=,31,$s,sf,:=,0,cf,:=,0,of,:=";
+
+    fn reg(r: &str) -> Operand {
+        Operand::Reg(r.to_owned())
+    }
+
+    fn lift(insns: &[&str]) -> Predicate {
+        lift_condition(insns)
+    }
+
+    #[test]
+    fn cmp_and_jcc() {
+        let jumps = [
+            ("zf,?{,0x10,rip,=,}", CmpOp::Eq),
+            ("zf,!,?{,0x10,rip,=,}", CmpOp::Ne),
+            ("cf,?{,0x10,rip,=,}", CmpOp::ULt),
+            ("cf,!,?{,0x10,rip,=,}", CmpOp::UGe),
+            ("zf,cf,|,?{,0x10,rip,=,}", CmpOp::ULe),
+            ("cf,zf,|,!,?{,0x10,rip,=,}", CmpOp::UGt),
+            ("of,sf,^,?{,0x10,rip,=,}", CmpOp::SLt),
+            ("of,sf,^,!,?{,0x10,rip,=,}", CmpOp::SGe),
+            ("zf,sf,of,^,|,?{,0x10,rip,=,}", CmpOp::SLe),
+            ("sf,of,!,^,zf,!,&,?{,0x10,rip,=,}", CmpOp::SGt),
+        ];
+        for &(jump, op) in &jumps {
+            assert_eq!(
+                lift(&["1,rbx,=", CMP, jump]),
+                Predicate::Compare(op, reg("rax"), reg("rbx")),
+                "{}",
+                jump
+            );
+        }
+    }
+
+    #[test]
+    fn test_and_jcc() {
+        let zero = Operand::Const(0);
+        assert_eq!(
+            lift(&[TEST, "zf,?{,0x10,rip,=,}"]),
+            Predicate::Compare(CmpOp::Eq, reg("eax"), zero.clone())
+        );
+        assert_eq!(
+            lift(&[TEST, "sf,?{,0x10,rip,=,}"]),
+            Predicate::Compare(CmpOp::SLt, reg("eax"), zero.clone())
+        );
+        assert_eq!(
+            lift(&[TEST, "zf,sf,of,^,|,?{,0x10,rip,=,}"]),
+            Predicate::Compare(CmpOp::SLe, reg("eax"), zero.clone())
+        );
+        let and = Operand::BitAnd(Box::new(reg("eax")), Box::new(Operand::Const(4)));
+        assert_eq!(
+            lift(&[
+                "0,4,eax,&,==,$z,zf,:=,$p,pf,:=,31,$s,sf,:=,0,cf,:=,0,of,:=",
+                "zf,!,?{,0x10,rip,=,}"
+            ]),
+            Predicate::Compare(CmpOp::Ne, and, zero)
+        );
+    }
+
+    #[test]
+    fn constant_predicates() {
+        let cmp = |op, lhs, rhs| Predicate::Compare(op, lhs, rhs);
+        let c = Operand::Const;
+        assert_eq!(
+            cmp(CmpOp::Eq, reg("rax"), reg("rax")).constant_value(),
+            Some(true)
+        );
+        assert_eq!(
+            cmp(CmpOp::SLt, reg("rax"), reg("rax")).constant_value(),
+            Some(false)
+        );
+        assert_eq!(cmp(CmpOp::UGe, c(1), c(2)).constant_value(), Some(false));
+        assert_eq!(cmp(CmpOp::Ne, c(1), c(2)).constant_value(), Some(true));
+        // `1 < -1` as signed 64 bit, but not as signed 32 bit
+        assert_eq!(cmp(CmpOp::SLt, c(1), c(u64::MAX)).constant_value(), None);
+        assert_eq!(
+            cmp(CmpOp::Eq, reg("rax"), reg("rbx")).constant_value(),
+            None
+        );
+        assert_eq!(Predicate::Raw("zf".to_owned()).constant_value(), None);
+    }
+
+    #[test]
+    fn falls_back_to_raw() {
+        let raw = |s: &str| Predicate::Raw(s.to_owned());
+        // the parity flag isn't followed
+        assert_eq!(
+            lift(&[CMP, "pf,?{,0x10,rip,=,}"]),
+            raw("pf,?{,0x10,rip,=,}")
+        );
+        // no comparison sets the flags
+        assert_eq!(lift(&["zf,?{,0x10,rip,=,}"]), raw("zf,?{,0x10,rip,=,}"));
+        // an instruction we don't follow clobbers them
+        assert_eq!(
+            lift(&[CMP, "rax,rbx,+=,$c,cf,:=", "cf,?{,0x10,rip,=,}"]),
+            raw("cf,?{,0x10,rip,=,}")
+        );
+        // not a conditional jump
+        assert_eq!(lift(&[CMP, "0x10,rip,="]), raw("0x10,rip,="));
+        assert_eq!(lift(&[]), raw(""));
+    }
+}
@@ -1,4 +1,4 @@
-use super::ast::{AstNode as AstNodeC, LoopType};
+use super::ast::{AstNode as AstNodeC, LabelId, LoopType};
 use super::condition;
 use super::{AstNode, Condition};
 use crate::backend::lang_c::c_ast::{self, CAST};
@@ -63,8 +63,38 @@ impl<'a> Exporter<'a> {
                 let b = self.go(*b)?;
                 Ok(vec![self.conv.ast_mut().new_while(c, b)])
             }
+            // `continue` can't be exported, so nothing skips the update
+            For(i, c, u, b) => {
+                let mut stmts = i
+                    .into_iter()
+                    .map(|s| self.conv.to_c_ast_single(s))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let c = c.fold(&mut *self)?;
+                let mut b = self.go(*b)?;
+                for s in u {
+                    b.push(self.conv.to_c_ast_single(s)?);
+                }
+                stmts.push(self.conv.ast_mut().new_while(c, b));
+                Ok(stmts)
+            }
             Break => Ok(vec![self.conv.ast_mut().insert_break()]),
             Switch(_, _, _) => unimplemented!(), // TODO
+            Continue => Err("`continue` can't be exported"),
+            Return => Ok(vec![self.conv.ast_mut().ret(None)]),
+            // the result of the call is lost
+            TailCall(b) => {
+                let mut stmts = b
+                    .into_iter()
+                    .map(|c| self.conv.to_c_ast_single(c))
+                    .collect::<Result<Vec<_>, _>>()?;
+                stmts.push(self.conv.ast_mut().ret(None));
+                Ok(stmts)
+            }
+            IndirectJump(_) => Err("unresolved indirect jumps can't be exported"),
+            Goto(l) => Ok(vec![self.conv.ast_mut().goto(&label_name(l))]),
+            Label(l) => Ok(vec![self.conv.ast_mut().label(&label_name(l))]),
+            // C has no exceptions, so only the covered code is kept
+            Try(b, _) => self.go(*b),
         }
     }
 
@@ -120,6 +150,10 @@ impl<'a, 'b> condition::Folder<CCFGRef> for &'a mut Exporter<'b> {
     }
 }
 
+fn label_name(l: LabelId) -> String {
+    format!("label_{}", l.0)
+}
+
 fn transpose<T, E>(o: Option<Result<T, E>>) -> Result<Option<T>, E> {
     match o {
         Some(Ok(t)) => Ok(Some(t)),
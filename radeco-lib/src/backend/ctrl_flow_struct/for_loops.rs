@@ -0,0 +1,239 @@
+//! Turns `while` loops that count into `for` loops, see [`recover`].
+
+use super::ast::{AstNode, LoopType};
+
+/// Rewrites each `while (c) { body; update }` that comes right after an
+/// `init` into `for (init; c; update) body`, everywhere in `ast`. `init` and
+/// `update` must be blocks that `assigns(block, c)` says are assignments to
+/// a variable that `c` reads, so a loop whose update is conditional doesn't
+/// qualify.
+///
+/// A loop whose body `continue`s is left alone: the `continue` skips
+/// `update` in the `while`, but would run it in the `for`.
+pub fn recover<B, C, V, F>(ast: AstNode<B, C, V>, assigns: &mut F) -> AstNode<B, C, V>
+where
+    F: FnMut(&B, &C) -> bool,
+{
+    use self::AstNode::*;
+    match ast {
+        Seq(seq) => {
+            let mut new_seq: Vec<AstNode<B, C, V>> = Vec::with_capacity(seq.len());
+            for a in seq {
+                let a = recover(a, assigns);
+                let a = match a {
+                    Loop(LoopType::PreChecked(c), body) if ends_in_init(&new_seq, &c, assigns) => {
+                        match split_update(*body, &c, assigns) {
+                            Ok((body, update)) => {
+                                let init = match new_seq.pop() {
+                                    Some(BasicBlock(init)) => init,
+                                    _ => unreachable!(),
+                                };
+                                For(init, c, update, Box::new(body))
+                            }
+                            Err(body) => Loop(LoopType::PreChecked(c), Box::new(body)),
+                        }
+                    }
+                    a => a,
+                };
+                new_seq.push(a);
+            }
+            if new_seq.len() == 1 {
+                new_seq.pop().unwrap()
+            } else {
+                Seq(new_seq)
+            }
+        }
+        Cond(c, t, oe) => {
+            let t = Box::new(recover(*t, assigns));
+            Cond(c, t, oe.map(|e| Box::new(recover(*e, assigns))))
+        }
+        Loop(lt, b) => Loop(lt, Box::new(recover(*b, assigns))),
+        For(i, c, u, b) => For(i, c, u, Box::new(recover(*b, assigns))),
+        Switch(v, cases, default) => Switch(
+            v,
+            cases
+                .into_iter()
+                .map(|(vs, a)| (vs, recover(a, assigns)))
+                .collect(),
+            Box::new(recover(*default, assigns)),
+        ),
+        Try(b, h) => Try(Box::new(recover(*b, assigns)), h),
+        Unstructured(members) => Unstructured(
+            members
+                .into_iter()
+                .map(|(l, a)| (l, recover(a, assigns)))
+                .collect(),
+        ),
+        ast @ BasicBlock(_)
+        | ast @ Break
+        | ast @ Continue
+        | ast @ Return
+        | ast @ TailCall(_)
+        | ast @ IndirectJump(_)
+        | ast @ Goto(_)
+        | ast @ Label(_) => ast,
+    }
+}
+
+/// Whether the last node of `seq` is a block that initializes `c`.
+fn ends_in_init<B, C, V, F>(seq: &[AstNode<B, C, V>], c: &C, assigns: &mut F) -> bool
+where
+    F: FnMut(&B, &C) -> bool,
+{
+    match seq.last() {
+        Some(AstNode::BasicBlock(init)) => assigns(init, c),
+        _ => false,
+    }
+}
+
+/// A loop body without its update, and the update.
+type Split<B, C, V> = (AstNode<B, C, V>, B);
+
+/// Splits the body of a loop on `c` into the rest of the body and the
+/// update at its end, or gives it back if it doesn't end in one or
+/// `continue`s.
+fn split_update<B, C, V, F>(
+    body: AstNode<B, C, V>,
+    c: &C,
+    assigns: &mut F,
+) -> Result<Split<B, C, V>, AstNode<B, C, V>>
+where
+    F: FnMut(&B, &C) -> bool,
+{
+    use self::AstNode::*;
+    match body {
+        BasicBlock(u) if assigns(&u, c) => Ok((AstNode::default(), u)),
+        Seq(mut seq) => {
+            let is_update = match seq.last() {
+                Some(BasicBlock(u)) => assigns(u, c),
+                _ => false,
+            };
+            if !is_update || seq.iter().any(continues) {
+                return Err(Seq(seq));
+            }
+            let update = match seq.pop() {
+                Some(BasicBlock(u)) => u,
+                _ => unreachable!(),
+            };
+            let body = if seq.len() == 1 {
+                seq.pop().unwrap()
+            } else {
+                Seq(seq)
+            };
+            Ok((body, update))
+        }
+        body => Err(body),
+    }
+}
+
+/// Whether `ast` `continue`s the loop it is in.
+fn continues<B, C, V>(ast: &AstNode<B, C, V>) -> bool {
+    use self::AstNode::*;
+    match ast {
+        Continue => true,
+        Seq(seq) => seq.iter().any(continues),
+        Cond(_, t, oe) => continues(t) || oe.iter().any(|e| continues(e)),
+        Switch(_, cases, default) => cases.iter().any(|(_, a)| continues(a)) || continues(default),
+        Try(b, _) => continues(b),
+        Unstructured(members) => members.iter().any(|(_, a)| continues(a)),
+        // a nested loop has its own `continue`s
+        Loop(_, _) | For(..) => false,
+        BasicBlock(_) | Break | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::ast::LoopType::*;
+
+    type Ast = AstNode<&'static str, &'static str, ()>;
+
+    /// `block` assigns to the first word of `cond`, e.g. `i = i + 1` to
+    /// `i < n`.
+    fn assigns(block: &&str, cond: &&str) -> bool {
+        let var = cond.split(' ').next().unwrap();
+        block.starts_with(&format!("{} = ", var))
+    }
+
+    fn counted(body: Vec<Ast>) -> Ast {
+        Seq(vec![
+            BasicBlock("n = 10"),
+            BasicBlock("i = 0"),
+            Loop(PreChecked("i < n"), Box::new(Seq(body))),
+            BasicBlock("return i"),
+        ])
+    }
+
+    #[test]
+    fn counted_loop() {
+        let ast = counted(vec![BasicBlock("f(i)"), BasicBlock("i = i + 1")]);
+        assert_eq!(
+            recover(ast, &mut assigns),
+            Seq(vec![
+                BasicBlock("n = 10"),
+                For("i = 0", "i < n", "i = i + 1", Box::new(BasicBlock("f(i)"))),
+                BasicBlock("return i"),
+            ])
+        );
+
+        // nested in another loop, with an empty body
+        let ast = Loop(Endless, Box::new(counted(vec![BasicBlock("i = i + 1")])));
+        match recover(ast, &mut assigns) {
+            Loop(Endless, body) => match *body {
+                Seq(seq) => assert_eq!(
+                    seq[1],
+                    For("i = 0", "i < n", "i = i + 1", Box::new(Seq(Vec::new())))
+                ),
+                body => panic!("not a Seq: {:?}", body),
+            },
+            ast => panic!("not an endless loop: {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn conditional_update() {
+        let ast = counted(vec![
+            BasicBlock("f(i)"),
+            Cond("g(i)", Box::new(BasicBlock("i = i + 1")), None),
+        ]);
+        assert_eq!(recover(ast.clone(), &mut assigns), ast);
+    }
+
+    #[test]
+    fn continue_skips_update() {
+        let ast = counted(vec![
+            Cond("g(i)", Box::new(Continue), None),
+            BasicBlock("f(i)"),
+            BasicBlock("i = i + 1"),
+        ]);
+        assert_eq!(recover(ast.clone(), &mut assigns), ast);
+
+        // but the `continue`s of a nested loop don't matter
+        let inner = Loop(
+            PreChecked("j"),
+            Box::new(Cond("g(j)", Box::new(Continue), None)),
+        );
+        let ast = counted(vec![inner.clone(), BasicBlock("i = i + 1")]);
+        match recover(ast, &mut assigns) {
+            Seq(seq) => assert_eq!(seq[1], For("i = 0", "i < n", "i = i + 1", Box::new(inner))),
+            ast => panic!("not a Seq: {:?}", ast),
+        }
+    }
+
+    #[test]
+    fn unrelated_init() {
+        // `n = 10` doesn't initialize `i`
+        let ast: Ast = Seq(vec![
+            BasicBlock("n = 10"),
+            Loop(
+                PreChecked("i < n"),
+                Box::new(Seq(vec![BasicBlock("f(i)"), BasicBlock("i = i + 1")])),
+            ),
+        ]);
+        assert_eq!(recover(ast.clone(), &mut assigns), ast);
+    }
+}
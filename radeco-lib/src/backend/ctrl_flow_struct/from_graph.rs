@@ -0,0 +1,248 @@
+//! Structures a graph of blocks and conditions of any type, for users whose
+//! functions come neither from radare2 nor from SSA, see [`structure_cfg`].
+//!
+//! Each node of the graph is a block, and each edge out of it tells when it
+//! is taken, see [`EdgeCondition`]. Structuring only moves the blocks and
+//! conditions around, and copies them where the options ask it to, so the
+//! resulting AST has them as they were given, wrapped in a [`Block`] and a
+//! [`Cond`] so that it can also have the variables that structuring
+//! introduces.
+
+use super::ast_context::{AstContext, AstContextMut};
+use super::condition;
+pub use super::frontend::{Block, Cond, StructuredFunction, Var};
+use super::{
+    empty_node, mk_code_node, mk_cond_node, CfgEdge, ControlFlowGraph, StructureError,
+    StructuringOptions, StructuringReport,
+};
+
+use petgraph::prelude::*;
+use petgraph::visit::Walker;
+
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// When an edge of the graph given to [`structure_cfg`] is taken. A block
+/// has either no edges out of it, and returns, or a single `Always` edge,
+/// or an `If` edge and an `Else` edge.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EdgeCondition<P> {
+    Always,
+    /// taken if the condition holds when the block ends
+    If(P),
+    /// taken if the condition of the `If` edge out of the same block
+    /// doesn't hold
+    Else,
+}
+
+/// Text to use for the blocks of a graph with a lot of it, e.g. their
+/// disassembly: it is only stored once, however many copies of its block
+/// structuring makes, see [`structure_cfg`].
+pub type BlockText = Arc<str>;
+
+/// The result of [`structure_cfg`]. By default, it is that of a graph whose
+/// blocks and conditions are `String`s.
+pub type StructuredCfg<B = String, P = String> = StructuredFunction<B, P>;
+
+/// Structures `graph`, entered at `entry`, as `opts` ask, and reports what
+/// structuring did. This is the entry point for graphs built by hand; see
+/// the [module docs](self). The blocks that can't be reached from `entry`
+/// are left out.
+///
+/// A block is only copied, with `B::clone`, when structuring copies it,
+/// e.g. with [`StructuringOptions::split_shared_regions`]; to share the
+/// copies, make `B` a handle, e.g. a [`BlockText`].
+///
+/// Fails with [`StructureError::Import`] if `entry` isn't in `graph` or if a
+/// block has other edges out of it than [`EdgeCondition`] allows, and like
+/// [`ControlFlowGraph::structure_whole_checked`] otherwise.
+///
+/// ```
+/// use petgraph::stable_graph::StableDiGraph;
+/// use radeco_lib::backend::ctrl_flow_struct::ast::AstNode;
+/// use radeco_lib::{structure_cfg, Block, Cond, EdgeCondition, StructuringOptions};
+///
+/// // a; if (c) b; return;
+/// let mut graph = StableDiGraph::new();
+/// let a = graph.add_node("a".to_owned());
+/// let b = graph.add_node("b".to_owned());
+/// let ret = graph.add_node("return".to_owned());
+/// graph.add_edge(a, b, EdgeCondition::If("c".to_owned()));
+/// graph.add_edge(a, ret, EdgeCondition::Else);
+/// graph.add_edge(b, ret, EdgeCondition::Always);
+///
+/// let (structured, _) = structure_cfg(graph, a, &StructuringOptions::default()).unwrap();
+/// let block = |b: &str| AstNode::BasicBlock(Block::Code(b.to_owned()));
+/// assert_eq!(
+///     structured.ast,
+///     AstNode::Seq(vec![
+///         block("a"),
+///         AstNode::Cond(Cond::Pred("c".to_owned()), Box::new(block("b")), None),
+///         block("return"),
+///     ])
+/// );
+/// ```
+pub fn structure_cfg<B, P>(
+    mut graph: StableDiGraph<B, EdgeCondition<P>>,
+    entry: NodeIndex,
+    opts: &StructuringOptions,
+) -> Result<(StructuredCfg<B, P>, StructuringReport), StructureError>
+where
+    B: Clone,
+    P: Clone + 'static,
+{
+    if !graph.contains_node(entry) {
+        return Err(StructureError::Import(
+            "structure_cfg: the entry isn't in the graph",
+        ));
+    }
+    let reachable: HashSet<_> = Dfs::new(&graph, entry).iter(&graph).collect();
+    let mut exits: HashMap<_, Vec<_>> = HashMap::new();
+    let edges: Vec<_> = graph.edge_indices().collect();
+    for e in edges {
+        let (from, to) = graph.edge_endpoints(e).unwrap();
+        let cond = graph.remove_edge(e).unwrap();
+        if reachable.contains(&from) {
+            exits.entry(from).or_default().push((cond, to));
+        }
+    }
+
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let mut cfg_graph = StableDiGraph::new();
+    // the entry must be a source
+    let cfg_entry = cfg_graph.add_node(empty_node());
+    let blocks: Vec<_> = graph
+        .node_indices()
+        .filter(|n| reachable.contains(n))
+        .collect();
+    let mut nodes = HashMap::with_capacity(blocks.len());
+    for &n in &blocks {
+        let block = graph.remove_node(n).unwrap();
+        nodes.insert(n, cfg_graph.add_node(mk_code_node(Block::Code(block))));
+    }
+    cfg_graph.add_edge(cfg_entry, nodes[&entry], CfgEdge::True);
+    for &n in &blocks {
+        let (mut always, mut ifs, mut elses) = (Vec::new(), Vec::new(), Vec::new());
+        for (cond, to) in exits.remove(&n).unwrap_or_default() {
+            match cond {
+                EdgeCondition::Always => always.push(nodes[&to]),
+                EdgeCondition::If(p) => ifs.push((p, nodes[&to])),
+                EdgeCondition::Else => elses.push(nodes[&to]),
+            }
+        }
+        let code = nodes[&n];
+        match (always.len(), ifs.pop(), elses.len()) {
+            (0, None, 0) => (),
+            (1, None, 0) => {
+                cfg_graph.add_edge(code, always[0], CfgEdge::True);
+            }
+            (0, Some((p, then)), 1) if ifs.is_empty() => {
+                let cond = cfg_graph.add_node(mk_cond_node(cctx, Cond::Pred(p)));
+                cfg_graph.add_edge(code, cond, CfgEdge::True);
+                cfg_graph.add_edge(cond, then, CfgEdge::True);
+                cfg_graph.add_edge(cond, elses[0], CfgEdge::False);
+            }
+            _ => {
+                return Err(StructureError::Import(
+                    "structure_cfg: a block must have no edges out of it, an `Always` edge, or an \
+                     `If` and an `Else` edge",
+                ))
+            }
+        }
+    }
+
+    let actx = GraphAstContext {
+        vars: Vec::new(),
+        marker: PhantomData,
+    };
+    let cfg = ControlFlowGraph::new(cfg_graph, cfg_entry, cctx, actx);
+    let (ast, actx, report) = cfg.structure_whole_checked(opts)?;
+    let structured = StructuredFunction::detach(ast, actx.vars, |_| None);
+    Ok((structured, report))
+}
+
+struct GraphAstContext<B, P> {
+    vars: Vec<Option<u64>>,
+    marker: PhantomData<(B, P)>,
+}
+
+impl<B, P> GraphAstContext<B, P> {
+    fn mk_var(&mut self, init: Option<u64>) -> Var {
+        let ret = Var(self.vars.len());
+        self.vars.push(init);
+        ret
+    }
+}
+
+/// Whether running `block` may change the value of `cond`: the condition of
+/// an edge may be changed by any block of the graph, and a variable only by
+/// assigning to it.
+fn may_modify<B, P>(block: &Block<B, P>, cond: &Cond<P>) -> bool {
+    match cond {
+        Cond::Pred(_) => matches!(block, Block::Code(_)),
+        Cond::Equals(var, _) | Cond::BoolVar(var) => match block {
+            Block::Assign(v, _) | Block::BoolAssign(v, _) => v == var,
+            Block::Code(_) => false,
+        },
+        Cond::Not(c) => may_modify(block, c),
+        Cond::All(cs) | Cond::Any(cs) => cs.iter().any(|c| may_modify(block, c)),
+    }
+}
+
+impl<B, P: 'static> AstContext for GraphAstContext<B, P> {
+    type Block = Block<B, P>;
+    type Variable = Var;
+    type BoolVariable = Var;
+    type Condition = Cond<P>;
+
+    fn assigned_value(&self, block: &Block<B, P>, var: &Var) -> Option<u64> {
+        match block {
+            Block::Assign(v, val) if v == var => Some(*val),
+            _ => None,
+        }
+    }
+
+    fn may_modify(&self, block: &Block<B, P>, cond: &Cond<P>) -> bool {
+        may_modify(block, cond)
+    }
+}
+
+impl<B: Clone, P: Clone + 'static> AstContextMut for GraphAstContext<B, P> {
+    fn mk_fresh_var(&mut self) -> Var {
+        self.mk_var(None)
+    }
+
+    fn mk_fresh_var_zeroed(&mut self) -> Var {
+        self.mk_var(Some(0))
+    }
+
+    fn mk_fresh_bool_var(&mut self) -> Var {
+        self.mk_var(None)
+    }
+
+    fn mk_cond_equals(&mut self, var: &Var, val: u64) -> Cond<P> {
+        Cond::Equals(*var, val)
+    }
+
+    fn mk_cond_from_bool_var(&mut self, var: &Var) -> Cond<P> {
+        Cond::BoolVar(*var)
+    }
+
+    fn mk_var_assign(&mut self, var: &Var, val: u64) -> Block<B, P> {
+        Block::Assign(*var, val)
+    }
+
+    fn mk_bool_var_assign(&mut self, var: &Var, cond: &Cond<P>) -> Block<B, P> {
+        Block::BoolAssign(*var, cond.clone())
+    }
+
+    fn clone_block(&mut self, block: &Block<B, P>) -> Option<Block<B, P>> {
+        Some(block.clone())
+    }
+
+    fn clone_cond(&mut self, cond: &Cond<P>) -> Option<Cond<P>> {
+        Some(cond.clone())
+    }
+}
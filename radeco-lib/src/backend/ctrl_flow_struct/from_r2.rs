@@ -0,0 +1,1122 @@
+//! Builds a [`ControlFlowGraph`] from the basic blocks radare2 found for a
+//! function, as printed by `afbj`.
+//!
+//! Every basic block that is reachable from the entry (the first block
+//! listed) becomes one code node whose payload records the block's address
+//! and size. A block with both a `jump` and a `fail` target is followed by a
+//! condition node that is true when the jump is taken. A block with switch
+//! cases is followed by a chain of condition nodes, one per case, that ends
+//! in the switch's default target. Targets that aren't the start of a block
+//! of the function are tail calls: they become sinks that return the result
+//! of jumping there. A block that ends in an unresolved indirect jump
+//! becomes an `IndirectJump` sink. If the blocks come with the ESIL of their
+//! instructions, the conditions of jumps can be lifted from it with
+//! [`esil`](super::esil).
+//!
+//! radare2 may list a block in several functions, e.g. an epilogue shared
+//! by them, or when its analysis of two functions overlaps. Each function
+//! is imported on its own, with its own copy of such a block, so structuring
+//! one never depends on the others; the copies all have the address of the
+//! block as their provenance. [`shared_blocks`] lists them.
+//!
+//! Before structuring them, [`structure_blocks_reported`] and the functions
+//! built on it look for the defects that messy frontend output has, e.g.
+//! blocks that can't be reached or targets where no block starts, see
+//! [`repair_blocks`]. As chosen by [`ImportOptions::mode`], they either
+//! repair them and report them as warnings, or fail.
+
+use super::ast::{self, AstNode as AstNodeC, ValueSet};
+use super::ast_context::{AstContext, AstContextMut};
+use super::condition;
+use super::esil::{self, Predicate};
+use super::frontend;
+use super::provenance::Provenance;
+use super::{
+    CfgEdge, CfgNode, ControlFlowGraph, InputDefect, InputMode, StructureError, StructuringOptions,
+    StructuringReport,
+};
+
+use petgraph::prelude::*;
+use serde_json::{self, Value};
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+
+/// The value radare2 uses for "no address".
+const UT64_MAX: u64 = u64::MAX;
+
+/// A basic block as described by radare2.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct R2BasicBlock {
+    pub addr: u64,
+    pub size: u64,
+    /// the target of the jump ending the block, if any
+    pub jump: Option<u64>,
+    /// the fall-through target if the jump isn't taken, if any
+    pub fail: Option<u64>,
+    /// the values and targets of the switch ending the block, if any
+    pub cases: Vec<(u64, u64)>,
+    /// the target of the switch when no case matches, if known
+    pub default: Option<u64>,
+    /// whether the block ends in an indirect jump whose targets couldn't be
+    /// resolved. `afbj` doesn't tell, so this is only set by an
+    /// `"unresolved_jump": true` added by whoever exported the blocks
+    pub unresolved_jump: bool,
+    /// the ESIL of each instruction of the block, if known. `afbj` doesn't
+    /// print it either, so it's only set by an `"esil"` array added by
+    /// whoever exported the blocks
+    pub esil: Vec<String>,
+}
+
+/// An [`AstContext`] whose blocks are radare2 basic blocks.
+#[derive(Clone, Debug, Default)]
+pub struct R2AstContext {
+    vars: Vec<Option<u64>>,
+}
+
+pub use super::frontend::Var;
+
+/// The code of a block of the AST.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum R2Code {
+    /// the basic block at `addr` that is `size` bytes long
+    Block { addr: u64, size: u64 },
+    /// a jump to an address outside of the function
+    ExternalJump(u64),
+}
+
+/// The condition of a branch of the AST.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum R2Cond {
+    /// the jump ending the block at this address is taken
+    Taken(u64),
+    /// the jump ending the block at this address is taken, which it is when
+    /// this predicate lifted from its ESIL holds
+    Predicate(u64, Predicate),
+    /// the switch ending the block at this address selects the case with
+    /// this value
+    Case(u64, u64),
+}
+
+impl R2Cond {
+    /// The address of the block that ends in the branch.
+    pub fn addr(&self) -> u64 {
+        match *self {
+            R2Cond::Taken(addr) | R2Cond::Predicate(addr, _) | R2Cond::Case(addr, _) => addr,
+        }
+    }
+
+    /// Renders `self` as a C expression.
+    pub fn to_c(&self) -> String {
+        match self {
+            R2Cond::Taken(addr) | R2Cond::Predicate(addr, Predicate::Raw(_)) => {
+                format!("cond_{:x}", addr)
+            }
+            R2Cond::Predicate(_, Predicate::Compare(op, lhs, rhs)) => match op.c_operator() {
+                (op, true) => format!("(signed){} {} (signed){}", lhs, op, rhs),
+                (op, false) => format!("{} {} {}", lhs, op, rhs),
+            },
+            R2Cond::Case(addr, val) => format!("switch_{:x} == {}", addr, val),
+        }
+    }
+}
+
+pub type Block = frontend::Block<R2Code, R2Cond>;
+pub type CondExpr = frontend::Cond<R2Cond>;
+/// The result of structuring the blocks of a function.
+pub type StructuredFunction = frontend::StructuredFunction<R2Code, R2Cond>;
+
+impl StructuredFunction {
+    /// Copies `ast`, as structured with `actx`, out of its condition
+    /// storage.
+    pub fn new(
+        ast: ast::AstNode<Block, condition::Condition<CondExpr>, Var>,
+        actx: &R2AstContext,
+    ) -> Self {
+        Self::detach(ast, actx.vars.clone(), |_| None)
+    }
+}
+
+/// The [`Provenance`] of the blocks and conditions of functions imported
+/// from radare2.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct R2Provenance;
+
+impl Provenance<Block, CondExpr> for R2Provenance {
+    fn block_range(&self, block: &Block) -> Option<Range<u64>> {
+        match block {
+            Block::Code(R2Code::Block { addr, size }) => Some(*addr..*addr + *size),
+            _ => None,
+        }
+    }
+
+    fn cond_addr(&self, cond: &CondExpr) -> Option<u64> {
+        match cond {
+            CondExpr::Pred(c) => Some(c.addr()),
+            CondExpr::Equals(..) | CondExpr::BoolVar(_) => None,
+            CondExpr::Not(c) => self.cond_addr(c),
+            CondExpr::All(cs) | CondExpr::Any(cs) => {
+                // only if all the operands agree
+                let mut addrs = cs.iter().map(|c| self.cond_addr(c));
+                let first = addrs.next()??;
+                if addrs.all(|a| a == Some(first)) {
+                    Some(first)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Whether running `block` may change the value of `cond`, judging only by
+/// which block and which variables `cond` is about: the jump at the end of
+/// a block is only decided anew when that block runs, and a variable is
+/// only changed by assigning to it. This is the conservative oracle for
+/// [`invariants::simplify_guards`](super::invariants::simplify_guards),
+/// [`adjacent_conds::merge`](super::adjacent_conds::merge) and
+/// [`common_heads::hoist`](super::common_heads::hoist), and the one that
+/// [`R2AstContext`] gives for
+/// [`invariants::unrotate_loops`](super::invariants::unrotate_loops).
+pub fn may_modify(block: &Block, cond: &CondExpr) -> bool {
+    match cond {
+        CondExpr::Pred(c) => match block {
+            Block::Code(R2Code::Block { addr, .. }) => *addr == c.addr(),
+            Block::Code(R2Code::ExternalJump(_)) | Block::Assign(..) | Block::BoolAssign(..) => {
+                false
+            }
+        },
+        CondExpr::Equals(var, _) | CondExpr::BoolVar(var) => match block {
+            Block::Assign(v, _) | Block::BoolAssign(v, _) => v == var,
+            Block::Code(_) => false,
+        },
+        CondExpr::Not(c) => may_modify(block, c),
+        CondExpr::All(cs) | CondExpr::Any(cs) => cs.iter().any(|c| may_modify(block, c)),
+    }
+}
+
+impl R2AstContext {
+    /// The value `var` is initialized with, if it matters.
+    pub fn initial_value(&self, var: Var) -> Option<u64> {
+        self.vars[var.0]
+    }
+
+    fn mk_var(&mut self, init: Option<u64>) -> Var {
+        let ret = Var(self.vars.len());
+        self.vars.push(init);
+        ret
+    }
+}
+
+impl AstContext for R2AstContext {
+    type Block = Block;
+    type Variable = Var;
+    type BoolVariable = Var;
+    type Condition = CondExpr;
+
+    fn assigned_value(&self, block: &Block, var: &Var) -> Option<u64> {
+        match block {
+            Block::Assign(v, val) if v == var => Some(*val),
+            _ => None,
+        }
+    }
+
+    fn may_modify(&self, block: &Block, cond: &CondExpr) -> bool {
+        may_modify(block, cond)
+    }
+
+    fn block_range(&self, block: &Block) -> Option<Range<u64>> {
+        R2Provenance.block_range(block)
+    }
+
+    fn describe_cond(&self, cond: &CondExpr) -> Option<String> {
+        Some(match cond {
+            CondExpr::Pred(c) => c.to_c(),
+            CondExpr::Equals(var, val) => format!("{} == {}", var, val),
+            CondExpr::BoolVar(var) => var.to_string(),
+            CondExpr::Not(_) | CondExpr::All(_) | CondExpr::Any(_) => return None,
+        })
+    }
+
+    fn constant_value(&self, cond: &CondExpr) -> Option<bool> {
+        match cond {
+            CondExpr::Pred(R2Cond::Predicate(_, p)) => p.constant_value(),
+            _ => None,
+        }
+    }
+}
+
+impl AstContextMut for R2AstContext {
+    fn mk_fresh_var(&mut self) -> Var {
+        self.mk_var(None)
+    }
+
+    fn mk_fresh_var_zeroed(&mut self) -> Var {
+        self.mk_var(Some(0))
+    }
+
+    fn mk_fresh_bool_var(&mut self) -> Var {
+        self.mk_var(None)
+    }
+
+    fn mk_cond_equals(&mut self, var: &Var, val: u64) -> CondExpr {
+        CondExpr::Equals(*var, val)
+    }
+
+    fn mk_cond_from_bool_var(&mut self, var: &Var) -> CondExpr {
+        CondExpr::BoolVar(*var)
+    }
+
+    fn mk_var_assign(&mut self, var: &Var, val: u64) -> Block {
+        Block::Assign(*var, val)
+    }
+
+    fn mk_bool_var_assign(&mut self, var: &Var, cond: &CondExpr) -> Block {
+        Block::BoolAssign(*var, cond.clone())
+    }
+
+    fn clone_block(&mut self, block: &Block) -> Option<Block> {
+        Some(block.clone())
+    }
+
+    fn clone_cond(&mut self, cond: &CondExpr) -> Option<CondExpr> {
+        Some(cond.clone())
+    }
+
+    /// The blocks are only their addresses, which the comments are keyed by,
+    /// so two contiguous ones merge into the one spanning both.
+    fn merge_blocks(&mut self, first: &Block, second: &Block) -> Option<Block> {
+        match (first, second) {
+            (
+                &Block::Code(R2Code::Block { addr, size }),
+                &Block::Code(R2Code::Block {
+                    addr: next,
+                    size: more,
+                }),
+            ) if addr + size == next => Some(Block::Code(R2Code::Block {
+                addr,
+                size: size + more,
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// Parses the output of radare2's `afbj` command.
+pub fn parse_blocks(json: &str) -> Result<Vec<R2BasicBlock>, &'static str> {
+    let value: Value = serde_json::from_str(json).map_err(|_| "parse_blocks: invalid JSON")?;
+    let blocks = value
+        .as_array()
+        .ok_or("parse_blocks: expected an array of blocks")?;
+    blocks.iter().map(parse_block).collect()
+}
+
+fn parse_block(block: &Value) -> Result<R2BasicBlock, &'static str> {
+    // older versions of radare2 call the address `offset`
+    let addr = address(block, "addr")
+        .or_else(|| address(block, "offset"))
+        .ok_or("parse_blocks: block has no address")?;
+    let size = block
+        .get("size")
+        .and_then(Value::as_u64)
+        .ok_or("parse_blocks: block has no size")?;
+    let mut cases = Vec::new();
+    let mut default = None;
+    if let Some(switch_op) = block.get("switch_op") {
+        let json_cases = switch_op
+            .get("cases")
+            .and_then(Value::as_array)
+            .ok_or("parse_blocks: switch has no cases")?;
+        for case in json_cases {
+            let value = case
+                .get("value")
+                .and_then(Value::as_u64)
+                .ok_or("parse_blocks: switch case has no value")?;
+            let jump = address(case, "jump").ok_or("parse_blocks: switch case has no target")?;
+            cases.push((value, jump));
+        }
+        default = address(switch_op, "default");
+    }
+    let esil = match block.get("esil") {
+        Some(esil) => esil
+            .as_array()
+            .and_then(|insns| {
+                insns
+                    .iter()
+                    .map(|i| i.as_str().map(str::to_owned))
+                    .collect()
+            })
+            .ok_or("parse_blocks: esil isn't an array of strings")?,
+        None => Vec::new(),
+    };
+    Ok(R2BasicBlock {
+        addr,
+        size,
+        jump: address(block, "jump"),
+        fail: address(block, "fail"),
+        cases,
+        default,
+        unresolved_jump: block
+            .get("unresolved_jump")
+            .and_then(Value::as_bool)
+            .unwrap_or(false),
+        esil,
+    })
+}
+
+fn address(obj: &Value, key: &str) -> Option<u64> {
+    obj.get(key)
+        .and_then(Value::as_u64)
+        .filter(|&a| a != UT64_MAX)
+}
+
+/// A basic block that radare2 listed in more than one function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedBlock {
+    pub addr: u64,
+    /// the indices of the functions listing the block, in order
+    pub functions: Vec<usize>,
+}
+
+/// Finds the blocks that are listed in more than one of `funcs`, sorted by
+/// address. A block is identified by its start address.
+pub fn shared_blocks<'b, I>(funcs: I) -> Vec<SharedBlock>
+where
+    I: IntoIterator<Item = &'b [R2BasicBlock]>,
+{
+    let mut listed_in = BTreeMap::new();
+    for (i, blocks) in funcs.into_iter().enumerate() {
+        for block in blocks {
+            let functions: &mut Vec<usize> = listed_in.entry(block.addr).or_default();
+            if functions.last() != Some(&i) {
+                functions.push(i);
+            }
+        }
+    }
+    listed_in
+        .into_iter()
+        .filter(|(_, functions)| functions.len() > 1)
+        .map(|(addr, functions)| SharedBlock { addr, functions })
+        .collect()
+}
+
+/// Structures the function described by the output of radare2's `afbj`
+/// command.
+pub fn structure(
+    json: &str,
+    opts: &StructuringOptions,
+) -> Result<StructuredFunction, StructureError> {
+    structure_with(json, &ImportOptions::default(), opts)
+}
+
+/// Like [`structure`], but imports the blocks as chosen by `import_opts`.
+pub fn structure_with(
+    json: &str,
+    import_opts: &ImportOptions,
+    opts: &StructuringOptions,
+) -> Result<StructuredFunction, StructureError> {
+    let blocks = parse_blocks(json).map_err(StructureError::Import)?;
+    structure_blocks(&blocks, import_opts, opts)
+}
+
+/// Like [`structure_with`], but for blocks that have already been parsed.
+pub fn structure_blocks(
+    blocks: &[R2BasicBlock],
+    import_opts: &ImportOptions,
+    opts: &StructuringOptions,
+) -> Result<StructuredFunction, StructureError> {
+    structure_blocks_reported(blocks, import_opts, opts).map(|(sf, _)| sf)
+}
+
+/// Like [`structure_blocks`], but also reports what structuring did.
+pub fn structure_blocks_reported(
+    blocks: &[R2BasicBlock],
+    import_opts: &ImportOptions,
+    opts: &StructuringOptions,
+) -> Result<(StructuredFunction, StructuringReport), StructureError> {
+    let (blocks, warnings) = repair_blocks(blocks, import_opts.mode)?;
+    let cstore = condition::Storage::new();
+    let mut cfg =
+        import_with(cstore.cctx(), &blocks, import_opts).map_err(StructureError::Import)?;
+    cfg.report.warnings = warnings;
+    let (ast, actx, report) = cfg.structure_whole_checked(opts)?;
+    Ok((StructuredFunction::new(ast, &actx), report))
+}
+
+/// Looks for the [`InputDefect`]s of `blocks`, the first being the entry.
+/// In [`InputMode::Lenient`], returns the blocks with the defects repaired,
+/// along with the defects; in [`InputMode::Strict`], fails on the first one.
+///
+/// A target is dangling if it is between the lowest address of a block and
+/// the highest end of one, but isn't the start of a block. Targets outside
+/// of that are tail calls.
+pub fn repair_blocks(
+    blocks: &[R2BasicBlock],
+    mode: InputMode,
+) -> Result<(Vec<R2BasicBlock>, Vec<InputDefect>), StructureError> {
+    let mut defects = Vec::new();
+    let mut found = |defect| match mode {
+        InputMode::Lenient => {
+            defects.push(defect);
+            Ok(())
+        }
+        InputMode::Strict => Err(StructureError::Input(defect)),
+    };
+    let entry = match blocks.first() {
+        Some(b) => b.addr,
+        None => return Ok((Vec::new(), defects)),
+    };
+
+    let lowest = blocks.iter().map(|b| b.addr).min().unwrap();
+    if lowest < entry {
+        found(InputDefect::EntryNotLowest { entry, lowest })?;
+    }
+    for b in blocks.iter().filter(|b| b.size == 0) {
+        found(InputDefect::EmptyBlock(b.addr))?;
+    }
+
+    let end = blocks.iter().map(|b| b.addr + b.size).max().unwrap();
+    let starts: HashSet<_> = blocks.iter().map(|b| b.addr).collect();
+    let dangling = |to: u64| lowest <= to && to < end && !starts.contains(&to);
+    let mut repaired = blocks.to_vec();
+    for b in &mut repaired {
+        let from = b.addr;
+        let mut keep = |to: u64| -> Result<bool, StructureError> {
+            if dangling(to) {
+                found(InputDefect::DanglingEdge { from, to })?;
+                Ok(false)
+            } else {
+                Ok(true)
+            }
+        };
+        for target in &mut [&mut b.jump, &mut b.fail, &mut b.default] {
+            if let Some(to) = **target {
+                if !keep(to)? {
+                    **target = None;
+                }
+            }
+        }
+        let mut cases = Vec::with_capacity(b.cases.len());
+        for &(value, to) in &b.cases {
+            if keep(to)? {
+                cases.push((value, to));
+            }
+        }
+        b.cases = cases;
+    }
+
+    let blocks_at: HashMap<_, _> = repaired.iter().map(|b| (b.addr, b)).collect();
+    let mut reached = HashSet::new();
+    let mut stack = vec![entry];
+    reached.insert(entry);
+    while let Some(addr) = stack.pop() {
+        for succ in successors(blocks_at[&addr]) {
+            if blocks_at.contains_key(&succ) && reached.insert(succ) {
+                stack.push(succ);
+            }
+        }
+    }
+    for b in blocks.iter().filter(|b| !reached.contains(&b.addr)) {
+        found(InputDefect::Unreachable(b.addr))?;
+    }
+    repaired.retain(|b| reached.contains(&b.addr));
+    Ok((repaired, defects))
+}
+
+/// The targets that [`import_with`] gives `block` edges to.
+fn successors(block: &R2BasicBlock) -> Vec<u64> {
+    if let Some(&(_, last_target)) = block.cases.last() {
+        let mut ret: Vec<_> = block.cases.iter().map(|&(_, target)| target).collect();
+        ret.push(
+            block
+                .default
+                .or(block.fail)
+                .or(block.jump)
+                .unwrap_or(last_target),
+        );
+        return ret;
+    }
+    if is_unresolved(block) {
+        return Vec::new();
+    }
+    block.jump.iter().chain(&block.fail).cloned().collect()
+}
+
+/// Parses the output of radare2's `afbj` command and converts it into a
+/// [`ControlFlowGraph`] ready to be structured.
+pub fn import_json<'cd>(
+    cctx: condition::Context<'cd, CondExpr>,
+    json: &str,
+) -> Result<ControlFlowGraph<'cd, R2AstContext>, &'static str> {
+    import(cctx, &parse_blocks(json)?)
+}
+
+/// Converts `blocks` into a [`ControlFlowGraph`] ready to be structured.
+/// The first block is the entry of the function.
+pub fn import<'cd>(
+    cctx: condition::Context<'cd, CondExpr>,
+    blocks: &[R2BasicBlock],
+) -> Result<ControlFlowGraph<'cd, R2AstContext>, &'static str> {
+    import_with(cctx, blocks, &ImportOptions::default())
+}
+
+/// Like [`import`], but also supplies the value set of each switch case, so
+/// that switches are recovered as `Switch`es rather than `if` cascades. The
+/// operand of each switch is a fresh variable with no initial value.
+pub fn import_with_switches<'cd>(
+    cctx: condition::Context<'cd, CondExpr>,
+    blocks: &[R2BasicBlock],
+) -> Result<ControlFlowGraph<'cd, R2AstContext>, &'static str> {
+    let opts = ImportOptions {
+        switches: true,
+        ..ImportOptions::default()
+    };
+    import_with(cctx, blocks, &opts)
+}
+
+/// Knobs for [`import_with`].
+#[derive(Clone, Debug, Default)]
+pub struct ImportOptions {
+    /// whether to recover switches, see [`import_with_switches`]
+    pub switches: bool,
+    /// whether to lift the conditions of jumps from the ESIL of their blocks
+    /// into [`CondExpr::Predicate`]s; blocks without ESIL keep their
+    /// [`CondExpr::Taken`]
+    pub esil_conditions: bool,
+    /// what to do about the defects of the blocks, see [`repair_blocks`].
+    /// Only the `structure` functions look for them
+    pub mode: InputMode,
+}
+
+/// Converts `blocks` into a [`ControlFlowGraph`] as chosen by `opts`.
+pub fn import_with<'cd>(
+    cctx: condition::Context<'cd, CondExpr>,
+    blocks: &[R2BasicBlock],
+    opts: &ImportOptions,
+) -> Result<ControlFlowGraph<'cd, R2AstContext>, &'static str> {
+    let r2_entry = blocks.first().ok_or("import: function has no blocks")?.addr;
+    let blocks_at: HashMap<_, _> = blocks.iter().map(|b| (b.addr, b)).collect();
+
+    let mut graph = StableDiGraph::new();
+    let new_entry = graph.add_node(super::empty_node());
+    let mut converted = HashMap::new();
+    // the condition node of each switch case, with the address of the
+    // switch and the value of the case
+    let mut switch_cases = Vec::new();
+
+    // do a DFS over the blocks, inserting nodes and edges into `graph` as we
+    // discover them
+    let mut worklist = vec![(new_entry, CfgEdge::True, r2_entry)];
+    while let Some((f_pred, pred_edge_ty, cur)) = worklist.pop() {
+        let f_cur = match converted.entry(cur) {
+            Entry::Occupied(oe) => *oe.into_mut(),
+            Entry::Vacant(ve) => {
+                let f_cur = if let Some(block) = blocks_at.get(&cur) {
+                    let code = Block::Code(R2Code::Block {
+                        addr: block.addr,
+                        size: block.size,
+                    });
+                    let f_cur = if is_unresolved(block) {
+                        graph.add_node(CfgNode::Code(AstNodeC::IndirectJump(code)))
+                    } else {
+                        graph.add_node(super::mk_code_node(code))
+                    };
+                    add_successors(
+                        cctx,
+                        opts,
+                        &mut graph,
+                        &mut worklist,
+                        &mut switch_cases,
+                        f_cur,
+                        block,
+                    );
+                    f_cur
+                } else {
+                    graph.add_node(CfgNode::Code(AstNodeC::TailCall(Block::Code(
+                        R2Code::ExternalJump(cur),
+                    ))))
+                };
+                *ve.insert(f_cur)
+            }
+        };
+
+        graph.add_edge(f_pred, f_cur, pred_edge_ty);
+    }
+
+    let mut actx = R2AstContext::default();
+    if !opts.switches {
+        return Ok(ControlFlowGraph::new(graph, new_entry, cctx, actx));
+    }
+    // one variable per switch, standing for its operand
+    let mut operands = HashMap::new();
+    let switch_cases: Vec<_> = switch_cases
+        .into_iter()
+        .map(|(f_cond, switch_addr, value)| {
+            let var = *operands
+                .entry(switch_addr)
+                .or_insert_with(|| actx.mk_var(None));
+            (f_cond, var, value)
+        })
+        .collect();
+    let mut cfg = ControlFlowGraph::new(graph, new_entry, cctx, actx);
+    for (f_cond, var, value) in switch_cases {
+        cfg.set_value_set(f_cond, var, ValueSet::single(value));
+    }
+    Ok(cfg)
+}
+
+fn add_successors<'cd>(
+    cctx: condition::Context<'cd, CondExpr>,
+    opts: &ImportOptions,
+    graph: &mut StableDiGraph<CfgNode<'cd, R2AstContext>, CfgEdge>,
+    worklist: &mut Vec<(NodeIndex, CfgEdge, u64)>,
+    switch_cases: &mut Vec<(NodeIndex, u64, u64)>,
+    f_block: NodeIndex,
+    block: &R2BasicBlock,
+) {
+    if let Some((&(_, last_target), cases)) = block.cases.split_last() {
+        // without a known default, the last case is taken when no other
+        // case is
+        let (cases, default) = match block.default.or(block.fail).or(block.jump) {
+            Some(default) => (&block.cases[..], default),
+            None => (cases, last_target),
+        };
+        let mut f_prev = f_block;
+        let mut prev_edge_ty = CfgEdge::True;
+        for &(value, target) in cases {
+            let f_cond = graph.add_node(super::mk_cond_node(
+                cctx,
+                CondExpr::Pred(R2Cond::Case(block.addr, value)),
+            ));
+            graph.add_edge(f_prev, f_cond, prev_edge_ty);
+            worklist.push((f_cond, CfgEdge::True, target));
+            switch_cases.push((f_cond, block.addr, value));
+            f_prev = f_cond;
+            prev_edge_ty = CfgEdge::False;
+        }
+        worklist.push((f_prev, prev_edge_ty, default));
+        return;
+    }
+
+    if is_unresolved(block) {
+        // any targets radare2 did find are only some of them
+        return;
+    }
+
+    match (block.jump, block.fail) {
+        (Some(jump), Some(fail)) if jump != fail => {
+            let cond = if opts.esil_conditions && !block.esil.is_empty() {
+                CondExpr::Pred(R2Cond::Predicate(
+                    block.addr,
+                    esil::lift_condition(&block.esil),
+                ))
+            } else {
+                CondExpr::Pred(R2Cond::Taken(block.addr))
+            };
+            let f_cond = graph.add_node(super::mk_cond_node(cctx, cond));
+            graph.add_edge(f_block, f_cond, CfgEdge::True);
+            worklist.push((f_cond, CfgEdge::True, jump));
+            worklist.push((f_cond, CfgEdge::False, fail));
+        }
+        (Some(succ), _) | (None, Some(succ)) => worklist.push((f_block, CfgEdge::True, succ)),
+        (None, None) => (),
+    }
+}
+
+/// Whether `block` ends in an indirect jump with unknown targets. Switch
+/// cases mean the targets were found after all.
+fn is_unresolved(block: &R2BasicBlock) -> bool {
+    block.unresolved_jump && block.cases.is_empty()
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::provenance;
+    use super::super::roundtrip;
+    use super::super::AstNode;
+    use super::*;
+
+    use std::fs;
+
+    const SAMPLE: &str = "test_files/loopy_main_afbj.json";
+
+    /// Appends the blocks in `ast`, in order, to `out`.
+    fn blocks_in<'cd>(ast: &AstNode<'cd, R2AstContext>, out: &mut Vec<Block>) {
+        use self::AstNodeC::*;
+        match ast {
+            BasicBlock(b) | TailCall(b) | IndirectJump(b) => out.push(b.clone()),
+            Break | Continue | Return | Goto(_) | Label(_) => (),
+            Seq(seq) => {
+                for a in seq {
+                    blocks_in(a, out);
+                }
+            }
+            Cond(_, t, oe) => {
+                blocks_in(t, out);
+                if let Some(e) = oe {
+                    blocks_in(e, out);
+                }
+            }
+            Loop(_, b) | Try(b, _) => blocks_in(b, out),
+            Unstructured(members) => {
+                for (_, a) in members {
+                    blocks_in(a, out);
+                }
+            }
+            For(i, _, u, b) => {
+                out.push(i.clone());
+                blocks_in(b, out);
+                out.push(u.clone());
+            }
+            Switch(_, cases, default) => {
+                for (_, a) in cases {
+                    blocks_in(a, out);
+                }
+                blocks_in(default, out);
+            }
+        }
+    }
+
+    fn code_addrs(blocks: &[Block]) -> Vec<u64> {
+        let mut ret: Vec<_> = blocks
+            .iter()
+            .filter_map(|b| match b {
+                Block::Code(R2Code::Block { addr, .. }) => Some(*addr),
+                _ => None,
+            })
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn may_modify_is_syntactic() {
+        let code = Block::Code(R2Code::Block {
+            addr: 0x10,
+            size: 4,
+        });
+        assert!(may_modify(&code, &CondExpr::Pred(R2Cond::Taken(0x10))));
+        assert!(!may_modify(&code, &CondExpr::Pred(R2Cond::Taken(0x20))));
+        assert!(!may_modify(&code, &CondExpr::BoolVar(Var(0))));
+        let assign = Block::Assign(Var(0), 1);
+        assert!(may_modify(&assign, &CondExpr::Equals(Var(0), 2)));
+        assert!(!may_modify(&assign, &CondExpr::Equals(Var(1), 2)));
+        let either = CondExpr::Any(vec![
+            CondExpr::Pred(R2Cond::Taken(0x20)),
+            CondExpr::BoolVar(Var(0)),
+        ]);
+        assert!(may_modify(&assign, &CondExpr::Not(Box::new(either))));
+    }
+
+    #[test]
+    fn sample_parses() {
+        let blocks = parse_blocks(&fs::read_to_string(SAMPLE).unwrap()).unwrap();
+        assert_eq!(blocks.len(), 8);
+        assert_eq!(blocks[0].addr, 0x400526);
+        assert_eq!(blocks[0].size, 0x16);
+        assert_eq!(blocks[0].jump, Some(0x40055c));
+        assert_eq!(blocks[0].fail, None);
+        let switch = blocks.iter().find(|b| !b.cases.is_empty()).unwrap();
+        assert_eq!(switch.cases.len(), 3);
+        assert_eq!(switch.default, None);
+    }
+
+    #[test]
+    fn sample_structures() {
+        let json = fs::read_to_string(SAMPLE).unwrap();
+        let cstore = condition::Storage::new();
+        let cfg = import_json(cstore.cctx(), &json).unwrap();
+        let (ast, _) = cfg.structure_whole();
+
+        let mut blocks = Vec::new();
+        blocks_in(&ast, &mut blocks);
+        // every reachable block appears exactly once; the one at 0x4005a0 is
+        // dead
+        assert_eq!(
+            code_addrs(&blocks),
+            vec![0x400526, 0x40053c, 0x400546, 0x40054e, 0x400556, 0x40055c, 0x400566],
+        );
+        assert_eq!(
+            blocks[0],
+            Block::Code(R2Code::Block {
+                addr: 0x400526,
+                size: 0x16
+            })
+        );
+        // the tail call out of the function
+        assert!(blocks.contains(&Block::Code(R2Code::ExternalJump(0x400430))));
+    }
+
+    fn find_loop(
+        ast: &ast::AstNode<Block, CondExpr, Var>,
+    ) -> Option<&ast::AstNode<Block, CondExpr, Var>> {
+        use self::AstNodeC::*;
+        match ast {
+            Loop(..) => Some(ast),
+            Seq(seq) => seq.iter().filter_map(find_loop).next(),
+            Cond(_, t, oe) => find_loop(t).or_else(|| oe.as_ref().and_then(|e| find_loop(e))),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn sample_coverage() {
+        let json = fs::read_to_string(SAMPLE).unwrap();
+        for &collapse_sese_regions in &[true, false] {
+            let opts = StructuringOptions {
+                collapse_sese_regions,
+                ..Default::default()
+            };
+            let sf = structure(&json, &opts).unwrap();
+            // whether or not the acyclic region holding the switch and its
+            // cases is collapsed before the loop is structured, the same
+            // blocks are covered. The
+            // loop exits by returning, so the block at 0x400566 is part of it
+            let loop_ast = find_loop(&sf.ast).expect("no loop");
+            let loop_covered = provenance::covered(&R2Provenance, loop_ast);
+            assert_eq!(loop_covered.ranges().to_vec(), vec![0x40053c..0x40056b]);
+            let all_covered = provenance::covered(&R2Provenance, &sf.ast);
+            assert_eq!(all_covered.ranges().to_vec(), vec![0x400526..0x40056b]);
+        }
+    }
+
+    #[test]
+    fn conditional_and_external() {
+        let json = r#"[
+            {"offset": 16, "size": 4, "jump": 32, "fail": 20},
+            {"offset": 20, "size": 12, "jump": 4096},
+            {"offset": 32, "size": 2}
+        ]"#;
+        let cstore = condition::Storage::new();
+        let cfg = import_json(cstore.cctx(), json).unwrap();
+        let (ast, _) = cfg.structure_whole();
+
+        let mut blocks = Vec::new();
+        blocks_in(&ast, &mut blocks);
+        assert_eq!(code_addrs(&blocks), vec![16, 20, 32]);
+        assert!(blocks.contains(&Block::Code(R2Code::ExternalJump(4096))));
+    }
+
+    #[test]
+    fn switch_and_unresolved_jump() {
+        let json = r#"[
+            {"addr": 16, "size": 4, "switch_op": {"default": 64, "cases": [
+                {"value": 0, "jump": 32},
+                {"value": 1, "jump": 48}
+            ]}},
+            {"addr": 32, "size": 4, "jump": 80},
+            {"addr": 48, "size": 4, "jump": 80, "unresolved_jump": true},
+            {"addr": 64, "size": 4, "jump": 80},
+            {"addr": 80, "size": 2}
+        ]"#;
+        let blocks = parse_blocks(json).unwrap();
+        assert!(blocks[2].unresolved_jump && !blocks[0].unresolved_jump);
+        let cstore = condition::Storage::new();
+        let cfg = import_with_switches(cstore.cctx(), &blocks).unwrap();
+        let (ast, _) = cfg.structure_whole();
+        println!("{:#?}", ast);
+
+        use self::AstNodeC::*;
+        let cases = match &ast {
+            Seq(seq) => seq.iter().find_map(|a| match a {
+                Switch(_, cases, _) => Some(cases),
+                _ => None,
+            }),
+            _ => None,
+        };
+        let cases = cases.expect("no switch");
+        assert_eq!(cases.len(), 2);
+        // the case with the unresolved jump ends there instead of going on
+        // to the block at 80
+        assert_eq!(
+            cases[1].1,
+            IndirectJump(Block::Code(R2Code::Block { addr: 48, size: 4 }))
+        );
+    }
+
+    #[test]
+    fn esil_conditions() {
+        let json = r#"[
+            {"addr": 16, "size": 6, "jump": 32, "fail": 24, "esil": [
+                "rbx,rax,==,$z,zf,:=,64,$b,cf,:=,$p,pf,:=,63,$s,sf,:=,63,$o,of,:=",
+                "cf,?{,32,rip,=,}"
+            ]},
+            {"addr": 24, "size": 2, "jump": 40, "fail": 32},
+            {"addr": 32, "size": 2},
+            {"addr": 40, "size": 2}
+        ]"#;
+        let blocks = parse_blocks(json).unwrap();
+        assert_eq!(blocks[0].esil.len(), 2);
+        assert!(blocks[1].esil.is_empty());
+        let opts = ImportOptions {
+            esil_conditions: true,
+            ..ImportOptions::default()
+        };
+        let cstore = condition::Storage::new();
+        let cfg = import_with(cstore.cctx(), &blocks, &opts).unwrap();
+        let mut conds: Vec<_> = cfg
+            .graph
+            .node_indices()
+            .filter_map(|n| match &cfg.graph[n] {
+                CfgNode::Condition(c) => Some((**c).clone()),
+                _ => None,
+            })
+            .collect();
+        conds.sort_by_key(|c| R2Provenance.cond_addr(c));
+        let reg = |r: &str| esil::Operand::Reg(r.to_owned());
+        assert_eq!(
+            conds,
+            vec![
+                CondExpr::Pred(R2Cond::Predicate(
+                    16,
+                    Predicate::Compare(esil::CmpOp::ULt, reg("rax"), reg("rbx"))
+                )),
+                // no ESIL to lift from
+                CondExpr::Pred(R2Cond::Taken(24)),
+            ]
+        );
+
+        assert!(parse_blocks(r#"[{"addr": 0, "size": 1, "esil": "zf"}]"#).is_err());
+    }
+
+    #[test]
+    fn shared_tail() {
+        // both functions end up in the epilogue at 0x100
+        let first = parse_blocks(
+            r#"[
+            {"addr": 16, "size": 4, "jump": 32, "fail": 20},
+            {"addr": 20, "size": 4, "jump": 256},
+            {"addr": 32, "size": 4, "jump": 256},
+            {"addr": 256, "size": 6}
+        ]"#,
+        )
+        .unwrap();
+        let second = parse_blocks(
+            r#"[
+            {"addr": 64, "size": 8, "jump": 256},
+            {"addr": 256, "size": 6}
+        ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            shared_blocks(vec![&first[..], &second[..]]),
+            vec![SharedBlock {
+                addr: 256,
+                functions: vec![0, 1],
+            }]
+        );
+
+        let opts = StructuringOptions::default();
+        let import_opts = ImportOptions::default();
+        let covered = |blocks: &[R2BasicBlock]| {
+            let sf = structure_blocks(blocks, &import_opts, &opts).unwrap();
+            provenance::covered(&R2Provenance, &sf.ast)
+                .ranges()
+                .to_vec()
+        };
+        // structuring the second function first doesn't change the first
+        assert_eq!(covered(&second), vec![64..72, 256..262]);
+        assert_eq!(covered(&first), vec![16..24, 32..36, 256..262]);
+        assert_eq!(covered(&second), vec![64..72, 256..262]);
+    }
+
+    /// The entry isn't the lowest block, the block at 16 is dead, the one at
+    /// 40 is empty and the one at 48 jumps to 52, in the middle of itself.
+    const BROKEN: &str = "test_files/broken_afbj.json";
+
+    fn structure_broken(
+        mode: InputMode,
+    ) -> Result<(StructuredFunction, StructuringReport), StructureError> {
+        let blocks = parse_blocks(&fs::read_to_string(BROKEN).unwrap()).unwrap();
+        let import_opts = ImportOptions {
+            mode,
+            ..ImportOptions::default()
+        };
+        structure_blocks_reported(&blocks, &import_opts, &StructuringOptions::default())
+    }
+
+    #[test]
+    fn lenient_input() {
+        let (sf, report) = structure_broken(InputMode::Lenient).unwrap();
+        assert_eq!(
+            report.warnings,
+            vec![
+                InputDefect::EntryNotLowest {
+                    entry: 32,
+                    lowest: 16
+                },
+                InputDefect::EmptyBlock(40),
+                InputDefect::DanglingEdge { from: 48, to: 52 },
+                InputDefect::Unreachable(16),
+            ]
+        );
+        assert_eq!(
+            provenance::covered(&R2Provenance, &sf.ast).ranges(),
+            &[32..36, 48..56, 64..68]
+        );
+        // without its dangling jump, the block at 48 just goes on to 64
+        let blocks = parse_blocks(&fs::read_to_string(BROKEN).unwrap()).unwrap();
+        let (repaired, _) = repair_blocks(&blocks, InputMode::Lenient).unwrap();
+        assert_eq!(repaired[2].jump, None);
+        roundtrip::check(&repaired, &sf).unwrap();
+
+        // the sample only has a dead block
+        let blocks = parse_blocks(&fs::read_to_string(SAMPLE).unwrap()).unwrap();
+        let (_, warnings) = repair_blocks(&blocks, InputMode::Lenient).unwrap();
+        assert_eq!(warnings, vec![InputDefect::Unreachable(0x4005a0)]);
+    }
+
+    #[test]
+    fn strict_input() {
+        let err = structure_broken(InputMode::Strict).unwrap_err();
+        assert_eq!(
+            err,
+            StructureError::Input(InputDefect::EntryNotLowest {
+                entry: 32,
+                lowest: 16
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "input: the entry at 0x20 isn't the lowest block, at 0x10"
+        );
+
+        // the other defects, one at a time
+        let mut blocks = parse_blocks(&fs::read_to_string(BROKEN).unwrap()).unwrap();
+        blocks.remove(1);
+        let strict =
+            |blocks: &[R2BasicBlock]| repair_blocks(blocks, InputMode::Strict).unwrap_err();
+        assert_eq!(
+            strict(&blocks),
+            StructureError::Input(InputDefect::EmptyBlock(40))
+        );
+        blocks[1].size = 8;
+        assert_eq!(
+            strict(&blocks),
+            StructureError::Input(InputDefect::DanglingEdge { from: 48, to: 52 })
+        );
+        blocks[2].jump = None;
+        let dead = R2BasicBlock {
+            addr: 0x80,
+            ..blocks[3].clone()
+        };
+        blocks.push(dead);
+        assert_eq!(
+            strict(&blocks),
+            StructureError::Input(InputDefect::Unreachable(0x80))
+        );
+        blocks.pop();
+        assert!(repair_blocks(&blocks, InputMode::Strict)
+            .unwrap()
+            .1
+            .is_empty());
+    }
+
+    #[test]
+    fn bad_json() {
+        assert!(parse_blocks("{").is_err());
+        assert!(parse_blocks(r#"{"addr": 0}"#).is_err());
+        assert!(parse_blocks(r#"[{"size": 1}]"#).is_err());
+    }
+}
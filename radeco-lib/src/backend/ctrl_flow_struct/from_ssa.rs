@@ -0,0 +1,760 @@
+//! Builds a [`ControlFlowGraph`] from a function in SSA form.
+//!
+//! Every action (basic block or dynamic action) of the SSA that is reachable
+//! from its entry becomes one code node whose payload is the action itself.
+//! Actions that end in a conditional branch are followed by a condition node
+//! whose condition is the [`Predicate`] recovered from the action's selector
+//! (see [`branch_predicate`]). The SSA exit action is converted
+//! like any other action, so it becomes the unique sink that every `return`
+//! path flows into. Unreachable actions are dropped, as required by
+//! [`ControlFlowGraph::new`].
+
+use super::ast_context::{AstContext, AstContextMut};
+use super::condition;
+use super::frontend;
+use super::provenance::Provenance;
+use super::{CfgEdge, ControlFlowGraph, StructureError, StructuringOptions};
+
+use crate::middle::ir::MOpcode;
+use crate::middle::ssa::cfg_traits::CFG;
+use crate::middle::ssa::ssa_traits::SSA;
+use crate::middle::ssa::ssastorage::SSAStorage;
+
+use petgraph::prelude::*;
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// An [`AstContext`] whose blocks refer back to the actions of an SSA
+/// function.
+#[derive(Debug)]
+pub struct SsaAstContext<'a> {
+    ssa: &'a SSAStorage,
+    vars: Vec<Option<u64>>,
+}
+
+pub use super::frontend::Var;
+
+/// A block of the AST, whose code is a basic block or dynamic action of the
+/// SSA.
+pub type Block = frontend::Block<NodeIndex, Predicate>;
+
+/// A condition of the AST, whose branches are taken when their `Predicate`
+/// holds.
+pub type CondExpr = frontend::Cond<Predicate>;
+
+/// The result of structuring an SSA function. It doesn't borrow the SSA
+/// either, so it can be kept around with the function.
+pub type StructuredFunction = frontend::StructuredFunction<NodeIndex, Predicate>;
+
+/// The condition under which a conditional branch is taken.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Predicate {
+    Cmp(CmpOp, Operand, Operand),
+    And(Box<Predicate>, Box<Predicate>),
+    Or(Box<Predicate>, Box<Predicate>),
+    Not(Box<Predicate>),
+    /// the SSA value is non-zero; used when no comparison could be recovered
+    NonZero(NodeIndex),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    ULt,
+    ULe,
+    UGt,
+    UGe,
+    SLt,
+    SLe,
+    SGt,
+    SGe,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Value(NodeIndex),
+    Const(u64),
+}
+
+impl CmpOp {
+    pub fn negate(self) -> Self {
+        use self::CmpOp::*;
+        match self {
+            Eq => Ne,
+            Ne => Eq,
+            ULt => UGe,
+            ULe => UGt,
+            UGt => ULe,
+            UGe => ULt,
+            SLt => SGe,
+            SLe => SGt,
+            SGt => SLe,
+            SGe => SLt,
+        }
+    }
+}
+
+impl Predicate {
+    /// Returns the predicate that holds exactly when `self` doesn't, pushing
+    /// the negation down to the comparisons where possible.
+    pub fn negate(self) -> Self {
+        use self::Predicate::*;
+        match self {
+            Cmp(op, l, r) => Cmp(op.negate(), l, r),
+            And(l, r) => Or(Box::new(l.negate()), Box::new(r.negate())),
+            Or(l, r) => And(Box::new(l.negate()), Box::new(r.negate())),
+            Not(p) => *p,
+            NonZero(v) => Not(Box::new(NonZero(v))),
+        }
+    }
+}
+
+/// Recovers the condition under which a branch on `selector` is taken.
+///
+/// The def-use chain is followed through the values computing the selector,
+/// so a comparison is found even when it's done by a separate instruction
+/// whose result only reaches the branch through a flag:
+/// - `a == b`, `a < b` and `a > b` become unsigned comparisons, as the IR's
+///   are, even of sign-extended operands, which keep their unsigned order
+/// - `(a - b) == 0` becomes `a == b`
+/// - the sign bit of `a - b` differing from its overflow bit, i.e. `sf != of`
+///   after `cmp a, b`, becomes a signed `a < b`; the sign bit alone is left
+///   as it is, since it only tells `a < b` when `a - b` doesn't overflow
+/// - `&`, `|`, `!` and `^ 1` of booleans become the corresponding logic
+pub fn branch_predicate(ssa: &SSAStorage, selector: NodeIndex) -> Predicate {
+    use self::MOpcode::*;
+    use self::Predicate::*;
+
+    let bool_pred = |v| {
+        if is_bool(ssa, v) {
+            Some(branch_predicate(ssa, v))
+        } else {
+            None
+        }
+    };
+
+    let opt_pred = match (ssa.opcode(selector), &ssa.operands_of(selector)[..]) {
+        (Some(OpNarrow(_)), &[x]) | (Some(OpZeroExt(_)), &[x]) if is_bool(ssa, x) => {
+            Some(branch_predicate(ssa, x))
+        }
+        (Some(OpNot), &[x]) => bool_pred(x).map(Predicate::negate),
+        (Some(OpXor), &[x, y]) | (Some(OpXor), &[y, x]) if ssa.constant(y) == Some(1) => {
+            bool_pred(x).map(Predicate::negate)
+        }
+        (Some(OpXor), &[x, y]) => signed_less(ssa, x, y).map(|(a, b)| Cmp(CmpOp::SLt, a, b)),
+        (Some(OpAnd), &[x, y]) => match (bool_pred(x), bool_pred(y)) {
+            (Some(l), Some(r)) => Some(And(Box::new(l), Box::new(r))),
+            _ => None,
+        },
+        (Some(OpOr), &[x, y]) => match (bool_pred(x), bool_pred(y)) {
+            (Some(l), Some(r)) => Some(Or(Box::new(l), Box::new(r))),
+            _ => None,
+        },
+        (Some(OpEq), &[x, y]) => Some(equality(ssa, x, y)),
+        (Some(OpLt), &[x, y]) => Some(Cmp(CmpOp::ULt, operand(ssa, x), operand(ssa, y))),
+        (Some(OpGt), &[x, y]) => Some(Cmp(CmpOp::UGt, operand(ssa, x), operand(ssa, y))),
+        _ => None,
+    };
+
+    opt_pred.unwrap_or(NonZero(selector))
+}
+
+fn equality(ssa: &SSAStorage, x: NodeIndex, y: NodeIndex) -> Predicate {
+    let (zero_cmp, other) = if ssa.constant(y) == Some(0) {
+        (true, x)
+    } else if ssa.constant(x) == Some(0) {
+        (true, y)
+    } else {
+        (false, x)
+    };
+    if zero_cmp {
+        if let Some((a, b)) = difference(ssa, other) {
+            return Predicate::Cmp(CmpOp::Eq, a, b);
+        }
+        if is_bool(ssa, other) {
+            return branch_predicate(ssa, other).negate();
+        }
+    }
+    Predicate::Cmp(CmpOp::Eq, operand(ssa, x), operand(ssa, y))
+}
+
+/// If `v` is `a - b`, returns `a` and `b`.
+fn difference(ssa: &SSAStorage, v: NodeIndex) -> Option<(Operand, Operand)> {
+    match (ssa.opcode(v), &ssa.operands_of(v)[..]) {
+        (Some(MOpcode::OpSub), &[a, b]) => Some((operand(ssa, a), operand(ssa, b))),
+        _ => None,
+    }
+}
+
+/// If `x ^ y` is the sign bit of `a - b` xor the overflow bit of the same
+/// subtraction, in either order, returns `a` and `b`.
+fn signed_less(ssa: &SSAStorage, x: NodeIndex, y: NodeIndex) -> Option<(Operand, Operand)> {
+    [(x, y), (y, x)].iter().find_map(|&(sf, of)| {
+        let diff = sign_bit(ssa, sf)?;
+        let (a, b) = match (ssa.opcode(diff), &ssa.operands_of(diff)[..]) {
+            (Some(MOpcode::OpSub), &[a, b]) => (a, b),
+            _ => return None,
+        };
+        if overflow_of(ssa, sign_bit(ssa, of)?, a, b, diff) {
+            Some((operand(ssa, a), operand(ssa, b)))
+        } else {
+            None
+        }
+    })
+}
+
+/// Whether `v` is `(a ^ b) & (a ^ diff)`, whose sign bit tells that
+/// `diff = a - b` overflowed: `a` and `b` have different signs, and `diff`
+/// doesn't have that of `a`.
+fn overflow_of(
+    ssa: &SSAStorage,
+    v: NodeIndex,
+    a: NodeIndex,
+    b: NodeIndex,
+    diff: NodeIndex,
+) -> bool {
+    let xor_of = |v, p, q| match (ssa.opcode(v), &ssa.operands_of(v)[..]) {
+        (Some(MOpcode::OpXor), &[x, y]) => (x, y) == (p, q) || (x, y) == (q, p),
+        _ => false,
+    };
+    match (ssa.opcode(v), &ssa.operands_of(v)[..]) {
+        (Some(MOpcode::OpAnd), &[x, y]) => {
+            (xor_of(x, a, b) && xor_of(y, a, diff)) || (xor_of(y, a, b) && xor_of(x, a, diff))
+        }
+        _ => false,
+    }
+}
+
+/// If `v` is the sign bit of `x`, i.e. `x >> (width - 1)` narrowed to a
+/// boolean, returns `x`.
+fn sign_bit(ssa: &SSAStorage, v: NodeIndex) -> Option<NodeIndex> {
+    let shifted = match (ssa.opcode(v), &ssa.operands_of(v)[..]) {
+        (Some(MOpcode::OpNarrow(1)), &[shifted]) => shifted,
+        _ => return None,
+    };
+    match (ssa.opcode(shifted), &ssa.operands_of(shifted)[..]) {
+        (Some(MOpcode::OpLsr), &[x, shift]) => {
+            if ssa.constant(shift) == Some(u64::from(width_of(ssa, x)?) - 1) {
+                Some(x)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn operand(ssa: &SSAStorage, v: NodeIndex) -> Operand {
+    ssa.constant(v).map_or(Operand::Value(v), Operand::Const)
+}
+
+fn width_of(ssa: &SSAStorage, v: NodeIndex) -> Option<u16> {
+    ssa.node_data(v).ok()?.vt.width().get_width()
+}
+
+fn is_bool(ssa: &SSAStorage, v: NodeIndex) -> bool {
+    width_of(ssa, v) == Some(1)
+}
+
+/// Blocks cover the address range of the SSA basic block. Predicates aren't
+/// tied to an address.
+impl Provenance<Block, CondExpr> for SSAStorage {
+    fn block_range(&self, block: &Block) -> Option<Range<u64>> {
+        match block {
+            Block::Code(n) => {
+                let size = self.block_size(*n)?;
+                let start = self.starting_address(*n)?.address;
+                Some(start..start + size)
+            }
+            _ => None,
+        }
+    }
+
+    fn cond_addr(&self, _cond: &CondExpr) -> Option<u64> {
+        None
+    }
+}
+
+impl<'a> SsaAstContext<'a> {
+    pub fn ssa(&self) -> &'a SSAStorage {
+        self.ssa
+    }
+
+    /// The value `var` is initialized with, if it matters.
+    pub fn initial_value(&self, var: Var) -> Option<u64> {
+        self.vars[var.0]
+    }
+
+    fn mk_var(&mut self, init: Option<u64>) -> Var {
+        let ret = Var(self.vars.len());
+        self.vars.push(init);
+        ret
+    }
+}
+
+impl<'a> AstContext for SsaAstContext<'a> {
+    type Block = Block;
+    type Variable = Var;
+    type BoolVariable = Var;
+    type Condition = CondExpr;
+
+    fn assigned_value(&self, block: &Block, var: &Var) -> Option<u64> {
+        match block {
+            Block::Assign(v, val) if v == var => Some(*val),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> AstContextMut for SsaAstContext<'a> {
+    fn mk_fresh_var(&mut self) -> Var {
+        self.mk_var(None)
+    }
+
+    fn mk_fresh_var_zeroed(&mut self) -> Var {
+        self.mk_var(Some(0))
+    }
+
+    fn mk_fresh_bool_var(&mut self) -> Var {
+        self.mk_var(None)
+    }
+
+    fn mk_cond_equals(&mut self, var: &Var, val: u64) -> CondExpr {
+        CondExpr::Equals(*var, val)
+    }
+
+    fn mk_cond_from_bool_var(&mut self, var: &Var) -> CondExpr {
+        CondExpr::BoolVar(*var)
+    }
+
+    fn mk_var_assign(&mut self, var: &Var, val: u64) -> Block {
+        Block::Assign(*var, val)
+    }
+
+    fn mk_bool_var_assign(&mut self, var: &Var, cond: &CondExpr) -> Block {
+        Block::BoolAssign(*var, cond.clone())
+    }
+
+    fn clone_block(&mut self, block: &Block) -> Option<Block> {
+        Some(block.clone())
+    }
+
+    fn clone_cond(&mut self, cond: &CondExpr) -> Option<CondExpr> {
+        Some(cond.clone())
+    }
+}
+
+/// Structures `ssa` as a whole.
+pub fn structure(
+    ssa: &SSAStorage,
+    opts: &StructuringOptions,
+) -> Result<StructuredFunction, StructureError> {
+    let cstore = condition::Storage::new();
+    let cfg = import(cstore.cctx(), ssa).map_err(StructureError::Import)?;
+    let (ast, actx, _) = cfg.structure_whole_checked(opts)?;
+    Ok(StructuredFunction::detach(ast, actx.vars, |p| {
+        Some(p.clone().negate())
+    }))
+}
+
+/// Converts `ssa` into a [`ControlFlowGraph`] ready to be structured.
+pub fn import<'cd, 'a>(
+    cctx: condition::Context<'cd, CondExpr>,
+    ssa: &'a SSAStorage,
+) -> Result<ControlFlowGraph<'cd, SsaAstContext<'a>>, &'static str> {
+    let ssa_entry = ssa.entry_node().ok_or("import: SSA has no entry node")?;
+
+    let mut graph = StableDiGraph::new();
+    let new_entry = graph.add_node(super::empty_node());
+    let mut converted = HashMap::new();
+
+    // do a DFS over the SSA, inserting nodes and edges into `graph` as we
+    // discover them
+    let mut worklist = vec![(new_entry, CfgEdge::True, ssa_entry)];
+    while let Some((f_pred, pred_edge_ty, cur)) = worklist.pop() {
+        let f_cur = match converted.entry(cur) {
+            Entry::Occupied(oe) => *oe.into_mut(),
+            Entry::Vacant(ve) => {
+                if !ssa.is_action(cur) {
+                    return Err("import: control edge to a non-action node");
+                }
+                let f_cur = graph.add_node(super::mk_code_node(Block::Code(cur)));
+                if let Some(succ) = ssa.unconditional_block(cur) {
+                    // an indirect jump (a selector without conditional
+                    // successors) is treated like a direct one to its target
+                    worklist.push((f_cur, CfgEdge::True, succ));
+                } else if let Some(cond_info) = ssa.conditional_blocks(cur) {
+                    let selector = ssa
+                        .selector_in(cur)
+                        .ok_or("import: conditional branch has no selector")?;
+                    let pred = branch_predicate(ssa, selector);
+                    let f_cond = graph.add_node(super::mk_cond_node(cctx, CondExpr::Pred(pred)));
+                    graph.add_edge(f_cur, f_cond, CfgEdge::True);
+                    worklist.push((f_cond, CfgEdge::True, cond_info.true_side));
+                    worklist.push((f_cond, CfgEdge::False, cond_info.false_side));
+                }
+                *ve.insert(f_cur)
+            }
+        };
+
+        graph.add_edge(f_pred, f_cur, pred_edge_ty);
+    }
+
+    Ok(ControlFlowGraph::new(
+        graph,
+        new_entry,
+        cctx,
+        SsaAstContext {
+            ssa,
+            vars: Vec::new(),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::ast::{self, AstNode as AstNodeC};
+    use super::super::provenance;
+    use super::super::AstNode;
+    use super::*;
+    use crate::middle::ir::{MAddress, MOpcode, WidthSpec};
+    use crate::middle::ssa::cfg_traits::CFGMod;
+    use crate::middle::ssa::ssa_traits::{SSAMod, ValueInfo};
+
+    /// Appends the SSA actions in `ast`, in order, to `out`.
+    fn actions_in<'cd>(ast: &AstNode<'cd, SsaAstContext<'_>>, out: &mut Vec<NodeIndex>) {
+        use self::AstNodeC::*;
+        match ast {
+            BasicBlock(Block::Code(n)) => out.push(*n),
+            BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_)
+            | Label(_) => (),
+            Seq(seq) => {
+                for a in seq {
+                    actions_in(a, out);
+                }
+            }
+            Cond(_, t, oe) => {
+                actions_in(t, out);
+                if let Some(e) = oe {
+                    actions_in(e, out);
+                }
+            }
+            Loop(_, b) | Try(b, _) => actions_in(b, out),
+            Unstructured(members) => {
+                for (_, a) in members {
+                    actions_in(a, out);
+                }
+            }
+            For(i, _, u, b) => {
+                actions_in(&BasicBlock(i.clone()), out);
+                actions_in(b, out);
+                actions_in(&BasicBlock(u.clone()), out);
+            }
+            Switch(_, cases, default) => {
+                for (_, a) in cases {
+                    actions_in(a, out);
+                }
+                actions_in(default, out);
+            }
+        }
+    }
+
+    /// Finds the first `Cond` in `ast` and returns the SSA actions in each
+    /// of its branches.
+    fn first_cond<'cd>(
+        ast: &AstNode<'cd, SsaAstContext<'_>>,
+    ) -> Option<(Vec<NodeIndex>, Vec<NodeIndex>)> {
+        use self::AstNodeC::*;
+        match ast {
+            Seq(seq) => seq.iter().filter_map(first_cond).next(),
+            Cond(_, t, oe) => {
+                let mut then_actions = Vec::new();
+                let mut else_actions = Vec::new();
+                actions_in(t, &mut then_actions);
+                if let Some(e) = oe {
+                    actions_in(e, &mut else_actions);
+                }
+                Some((then_actions, else_actions))
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn ssa_diamond() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.insert_dynamic().unwrap();
+        let exit = ssa.insert_dynamic().unwrap();
+        ssa.set_entry_node(entry);
+        ssa.set_exit_node(exit);
+        let head = ssa.insert_block(MAddress::new(0x10, 0)).unwrap();
+        let then_b = ssa.insert_block(MAddress::new(0x20, 0)).unwrap();
+        let else_b = ssa.insert_block(MAddress::new(0x30, 0)).unwrap();
+        let dead = ssa.insert_block(MAddress::new(0x40, 0)).unwrap();
+
+        let vt = ValueInfo::new_unresolved(WidthSpec::from(1));
+        let cmp = ssa.insert_op(MOpcode::OpEq, vt, None).unwrap();
+        let c1 = ssa.insert_const(1, None).unwrap();
+        let c2 = ssa.insert_const(2, None).unwrap();
+        ssa.insert_into_block(cmp, head, MAddress::new(0x10, 0));
+        ssa.op_use(cmp, 0, c1);
+        ssa.op_use(cmp, 1, c2);
+        ssa.set_selector(cmp, head);
+
+        ssa.insert_control_edge(entry, head, 2);
+        ssa.insert_control_edge(head, then_b, 1);
+        ssa.insert_control_edge(head, else_b, 0);
+        ssa.insert_control_edge(then_b, exit, 2);
+        ssa.insert_control_edge(else_b, exit, 2);
+        ssa.insert_control_edge(dead, exit, 2);
+
+        let cstore = condition::Storage::new();
+        let cfg = import(cstore.cctx(), &ssa).unwrap();
+        let (ast, actx) = cfg.structure_whole();
+        assert_eq!(actx.ssa().entry_node(), Some(entry));
+
+        let mut actions = Vec::new();
+        actions_in(&ast, &mut actions);
+        assert_eq!(actions.len(), 5);
+        assert_eq!(actions[0], entry);
+        assert_eq!(actions[1], head);
+        assert_eq!(actions[4], exit);
+        assert!(!actions.contains(&dead));
+
+        let (then_actions, else_actions) = first_cond(&ast).expect("no `Cond` in AST");
+        let mut branches = vec![then_actions, else_actions];
+        branches.sort();
+        let mut expected = vec![vec![then_b], vec![else_b]];
+        expected.sort();
+        assert_eq!(branches, expected);
+    }
+
+    fn op(ssa: &mut SSAStorage, opc: MOpcode, width: u16, args: &[NodeIndex]) -> NodeIndex {
+        let vt = ValueInfo::new_unresolved(WidthSpec::from(width));
+        let ret = ssa.insert_op(opc, vt, None).unwrap();
+        for (i, &arg) in args.iter().enumerate() {
+            ssa.op_use(ret, i as u8, arg);
+        }
+        ret
+    }
+
+    fn reg(ssa: &mut SSAStorage, name: &str) -> NodeIndex {
+        let vt = ValueInfo::new_unresolved(WidthSpec::from(64));
+        ssa.insert_comment(vt, name.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn structure_detached() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.insert_dynamic().unwrap();
+        let exit = ssa.insert_dynamic().unwrap();
+        ssa.set_entry_node(entry);
+        ssa.set_exit_node(exit);
+        let a = reg(&mut ssa, "rax");
+        let zero = ssa.insert_const(0, None).unwrap();
+        let cmp = op(&mut ssa, MOpcode::OpEq, 1, &[a, zero]);
+        ssa.set_selector(cmp, entry);
+        let then_b = ssa.insert_block(MAddress::new(0x10, 0)).unwrap();
+        ssa.insert_control_edge(entry, then_b, 1);
+        ssa.insert_control_edge(entry, exit, 0);
+        ssa.insert_control_edge(then_b, exit, 2);
+
+        let sf = structure(&ssa, &StructuringOptions::default()).unwrap();
+        drop(ssa);
+
+        let eq = Predicate::Cmp(CmpOp::Eq, Operand::Value(a), Operand::Const(0));
+        let cond = |ast: &ast::AstNode<Block, CondExpr, Var>| match ast {
+            AstNodeC::Cond(c, t, None) => {
+                assert_eq!(**t, AstNodeC::BasicBlock(Block::Code(then_b)));
+                Some(c.clone())
+            }
+            _ => None,
+        };
+        let found = match &sf.ast {
+            AstNodeC::Seq(seq) => seq.iter().filter_map(cond).next(),
+            _ => None,
+        };
+        assert_eq!(found, Some(CondExpr::Pred(eq)));
+    }
+
+    #[test]
+    fn block_ranges() {
+        let mut ssa = SSAStorage::new();
+        let entry = ssa.insert_dynamic().unwrap();
+        let exit = ssa.insert_dynamic().unwrap();
+        ssa.set_entry_node(entry);
+        ssa.set_exit_node(exit);
+        let head = ssa.insert_block(MAddress::new(0x10, 0)).unwrap();
+        let body = ssa.insert_block(MAddress::new(0x18, 0)).unwrap();
+        let tail = ssa.insert_block(MAddress::new(0x30, 0)).unwrap();
+        ssa.set_block_size(head, 8);
+        ssa.set_block_size(body, 0x10);
+        ssa.set_block_size(tail, 4);
+        let sel = reg(&mut ssa, "zf");
+        ssa.set_selector(sel, head);
+        ssa.insert_control_edge(entry, head, 2);
+        ssa.insert_control_edge(head, body, 1);
+        ssa.insert_control_edge(head, tail, 0);
+        ssa.insert_control_edge(body, head, 2);
+        ssa.insert_control_edge(tail, exit, 2);
+
+        let sf = structure(&ssa, &StructuringOptions::default()).unwrap();
+        // the dynamic entry and exit don't cover anything
+        assert_eq!(
+            provenance::covered(&ssa, &sf.ast).ranges(),
+            &[0x10..0x28, 0x30..0x34]
+        );
+        let loop_ast = match &sf.ast {
+            AstNodeC::Seq(seq) => seq.iter().find(|a| matches!(a, AstNodeC::Loop(..))),
+            _ => None,
+        };
+        let loop_ast = loop_ast.expect("no loop");
+        // `tail` comes after the loop
+        assert_eq!(
+            provenance::covered(&ssa, loop_ast).ranges().to_vec(),
+            vec![0x10..0x28]
+        );
+    }
+
+    #[test]
+    fn predicate_unsigned_cmp() {
+        let mut ssa = SSAStorage::new();
+        let a = reg(&mut ssa, "rax");
+        let b = reg(&mut ssa, "rbx");
+        let lt = op(&mut ssa, MOpcode::OpLt, 1, &[a, b]);
+        let gt = op(&mut ssa, MOpcode::OpGt, 1, &[b, a]);
+        let narrowed = op(&mut ssa, MOpcode::OpNarrow(1), 1, &[gt]);
+
+        assert_eq!(
+            branch_predicate(&ssa, lt),
+            Predicate::Cmp(CmpOp::ULt, Operand::Value(a), Operand::Value(b))
+        );
+        assert_eq!(
+            branch_predicate(&ssa, narrowed),
+            Predicate::Cmp(CmpOp::UGt, Operand::Value(b), Operand::Value(a))
+        );
+    }
+
+    #[test]
+    fn predicate_sign_extended_cmp() {
+        let mut ssa = SSAStorage::new();
+        let a = reg(&mut ssa, "eax");
+        let b = reg(&mut ssa, "ebx");
+        let sa = op(&mut ssa, MOpcode::OpSignExt(64), 64, &[a]);
+        let sb = op(&mut ssa, MOpcode::OpSignExt(64), 64, &[b]);
+        let gt = op(&mut ssa, MOpcode::OpGt, 1, &[sa, sb]);
+        // the IR compares unsigned, and sign extension keeps the unsigned
+        // order, e.g. of `a = -1` and `b = 1`
+        assert_eq!(
+            branch_predicate(&ssa, gt),
+            Predicate::Cmp(CmpOp::UGt, Operand::Value(sa), Operand::Value(sb))
+        );
+    }
+
+    /// `sub a, b`, and the sign and overflow bits of the difference
+    fn sub_flags(ssa: &mut SSAStorage, a: NodeIndex, b: NodeIndex) -> (NodeIndex, NodeIndex) {
+        let diff = op(ssa, MOpcode::OpSub, 64, &[a, b]);
+        let sixty_three = ssa.insert_const(63, None).unwrap();
+        let sign_of = |ssa: &mut SSAStorage, x| {
+            let shifted = op(ssa, MOpcode::OpLsr, 64, &[x, sixty_three]);
+            op(ssa, MOpcode::OpNarrow(1), 1, &[shifted])
+        };
+        let sf = sign_of(ssa, diff);
+        let a_b = op(ssa, MOpcode::OpXor, 64, &[a, b]);
+        let a_diff = op(ssa, MOpcode::OpXor, 64, &[diff, a]);
+        let ovf = op(ssa, MOpcode::OpAnd, 64, &[a_b, a_diff]);
+        let of = sign_of(ssa, ovf);
+        (sf, of)
+    }
+
+    #[test]
+    fn predicate_signed_cmp() {
+        let mut ssa = SSAStorage::new();
+        let a = reg(&mut ssa, "rax");
+        let b = reg(&mut ssa, "rbx");
+        // `cmp rax, rbx; jl ...` tests `sf != of`
+        let (sf, of) = sub_flags(&mut ssa, a, b);
+        let lt = op(&mut ssa, MOpcode::OpXor, 1, &[of, sf]);
+        assert_eq!(
+            branch_predicate(&ssa, lt),
+            Predicate::Cmp(CmpOp::SLt, Operand::Value(a), Operand::Value(b))
+        );
+        let ge = op(&mut ssa, MOpcode::OpNot, 1, &[lt]);
+        assert_eq!(
+            branch_predicate(&ssa, ge),
+            Predicate::Cmp(CmpOp::SGe, Operand::Value(a), Operand::Value(b))
+        );
+
+        // the sign bit alone, as `js` tests it, is only `a < b` if the
+        // subtraction doesn't overflow
+        assert_eq!(branch_predicate(&ssa, sf), Predicate::NonZero(sf));
+        // and the overflow bit of another subtraction isn't that of this one
+        let c = reg(&mut ssa, "rcx");
+        let (_, other_of) = sub_flags(&mut ssa, c, b);
+        let mixed = op(&mut ssa, MOpcode::OpXor, 1, &[sf, other_of]);
+        assert_eq!(branch_predicate(&ssa, mixed), Predicate::NonZero(mixed));
+    }
+
+    #[test]
+    fn predicate_zero_flag() {
+        let mut ssa = SSAStorage::new();
+        let a = reg(&mut ssa, "rax");
+        let five = ssa.insert_const(5, None).unwrap();
+        let zero = ssa.insert_const(0, None).unwrap();
+        let diff = op(&mut ssa, MOpcode::OpSub, 64, &[a, five]);
+        let zf = op(&mut ssa, MOpcode::OpEq, 1, &[diff, zero]);
+        assert_eq!(
+            branch_predicate(&ssa, zf),
+            Predicate::Cmp(CmpOp::Eq, Operand::Value(a), Operand::Const(5))
+        );
+        let one = ssa.insert_const(1, Some(1)).unwrap();
+        let not_zf = op(&mut ssa, MOpcode::OpXor, 1, &[one, zf]);
+        assert_eq!(
+            branch_predicate(&ssa, not_zf),
+            Predicate::Cmp(CmpOp::Ne, Operand::Value(a), Operand::Const(5))
+        );
+
+        // not a comparison at all
+        assert_eq!(branch_predicate(&ssa, diff), Predicate::NonZero(diff));
+    }
+
+    #[test]
+    fn predicate_and_of_cmps() {
+        let mut ssa = SSAStorage::new();
+        let a = reg(&mut ssa, "rax");
+        let b = reg(&mut ssa, "rbx");
+        let ten = ssa.insert_const(10, None).unwrap();
+        let lt = op(&mut ssa, MOpcode::OpLt, 1, &[a, ten]);
+        let eq = op(&mut ssa, MOpcode::OpEq, 1, &[b, a]);
+        let both = op(&mut ssa, MOpcode::OpAnd, 1, &[lt, eq]);
+
+        let ult = || Predicate::Cmp(CmpOp::ULt, Operand::Value(a), Operand::Const(10));
+        let equ = || Predicate::Cmp(CmpOp::Eq, Operand::Value(b), Operand::Value(a));
+        let pred = branch_predicate(&ssa, both);
+        assert_eq!(pred, Predicate::And(Box::new(ult()), Box::new(equ())));
+
+        // the fall-through edge is taken when the negation holds
+        assert_eq!(
+            pred.negate(),
+            Predicate::Or(
+                Box::new(Predicate::Cmp(
+                    CmpOp::UGe,
+                    Operand::Value(a),
+                    Operand::Const(10)
+                )),
+                Box::new(Predicate::Cmp(
+                    CmpOp::Ne,
+                    Operand::Value(b),
+                    Operand::Value(a)
+                )),
+            )
+        );
+
+        // an `&` of non-booleans isn't a logical-and
+        let wide = op(&mut ssa, MOpcode::OpAnd, 64, &[a, b]);
+        assert_eq!(branch_predicate(&ssa, wide), Predicate::NonZero(wide));
+    }
+}
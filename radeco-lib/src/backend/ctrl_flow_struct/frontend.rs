@@ -0,0 +1,117 @@
+//! The blocks and conditions of the ASTs that the front-ends
+//! ([`from_r2`](super::from_r2), [`from_ssa`](super::from_ssa) and
+//! [`from_graph`](super::from_graph)) give. They only differ in the code of
+//! their blocks and in the conditions of their branches; the variables that
+//! structuring introduces are the same for all of them.
+
+use super::ast::AstNode;
+use super::condition;
+
+use std::fmt;
+
+/// A variable that structuring introduced, by its index in
+/// [`StructuredFunction::var_inits`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Var(pub usize);
+
+impl fmt::Display for Var {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+/// A block of code `B` of the front-end, or one that structuring made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Block<B, P> {
+    Code(B),
+    /// `var = val`
+    Assign(Var, u64),
+    /// `var = cond`
+    BoolAssign(Var, Cond<P>),
+}
+
+/// A condition `P` of a branch of the front-end, or one that structuring
+/// made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Cond<P> {
+    Pred(P),
+    /// `var == val`
+    Equals(Var, u64),
+    /// the value of a boolean variable
+    BoolVar(Var),
+    // the following only appear in a `StructuredFunction`
+    Not(Box<Cond<P>>),
+    /// true if empty
+    All(Vec<Cond<P>>),
+    /// false if empty
+    Any(Vec<Cond<P>>),
+}
+
+/// The result of structuring a function. Unlike the output of
+/// [`ControlFlowGraph::structure_whole`](super::ControlFlowGraph::structure_whole),
+/// it doesn't borrow the condition storage, so it can be kept around.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructuredFunction<B, P> {
+    pub ast: AstNode<Block<B, P>, Cond<P>, Var>,
+    /// the value each variable introduced by structuring must be initialized
+    /// with, if any
+    pub var_inits: Vec<Option<u64>>,
+}
+
+impl<B, P: Clone + 'static> StructuredFunction<B, P> {
+    /// Copies `ast` out of its condition storage. `negate` returns the
+    /// negation of a condition of the front-end, if it has one; otherwise a
+    /// negated one is wrapped in a `Not`.
+    pub(super) fn detach(
+        ast: AstNode<Block<B, P>, condition::Condition<Cond<P>>, Var>,
+        var_inits: Vec<Option<u64>>,
+        negate: fn(&P) -> Option<P>,
+    ) -> Self {
+        StructuredFunction {
+            ast: ast.map_conds(&mut |c| c.fold(Detacher(negate))),
+            var_inits,
+        }
+    }
+}
+
+/// Copies a `Condition` out of its storage.
+struct Detacher<P>(fn(&P) -> Option<P>);
+
+impl<P: Clone + 'static> condition::Folder<Cond<P>> for Detacher<P> {
+    type Output = Cond<P>;
+
+    fn var(&mut self, normal: bool, var: &Cond<P>) -> Cond<P> {
+        match (normal, var) {
+            (true, _) => var.clone(),
+            (false, Cond::Pred(p)) => match (self.0)(p) {
+                Some(negated) => Cond::Pred(negated),
+                None => Cond::Not(Box::new(var.clone())),
+            },
+            (false, _) => Cond::Not(Box::new(var.clone())),
+        }
+    }
+
+    fn and<'c, I>(&mut self, operands: I) -> Cond<P>
+    where
+        I: IntoIterator<Item = condition::Condition<'c, Cond<P>>>,
+    {
+        Cond::All(
+            operands
+                .into_iter()
+                .map(|c| c.fold(Detacher(self.0)))
+                .collect(),
+        )
+    }
+
+    fn or<'c, I>(&mut self, operands: I) -> Cond<P>
+    where
+        I: IntoIterator<Item = condition::Condition<'c, Cond<P>>>,
+    {
+        Cond::Any(
+            operands
+                .into_iter()
+                .map(|c| c.fold(Detacher(self.0)))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,269 @@
+//! Turns arbitrary bytes into the blocks of a function, for the fuzz target
+//! in `fuzz/`, which structures them with [`structure_and_check`].
+//!
+//! [`decode`] reads, taking missing bytes as zero:
+//!  - the number of blocks, modulo [`MAX_BLOCKS`], plus one,
+//!  - the index of the entry, modulo the number of blocks, and
+//!  - for each block, its kind and then its targets. Each target is the
+//!    index of a block or, one past the last, a tail call out of the
+//!    function. The kinds, modulo 5, are: 0 returns, 1 jumps to a target,
+//!    2 branches to two, 3 switches to up to 8 cases, whose number modulo 8
+//!    plus one comes first, and then a default, which may also be two past
+//!    the last, for none, and 4 jumps somewhere unknown.
+//!
+//! The block with index `i` is at `0x10 * (i + 1)`, and the entry is listed
+//! first. [`encode`] does the opposite, so that the blocks of a function,
+//! e.g. the fixtures in `test_files`, can seed the corpus.
+//!
+//! To fuzz, install `cargo-fuzz` and run, from `radeco-lib/fuzz`:
+//!
+//! ```text
+//! cargo +nightly fuzz run structure corpus/structure seeds/structure
+//! ```
+//!
+//! New inputs go to `corpus/structure`, and crashes to
+//! `artifacts/structure`.
+
+use super::from_r2::{self, ImportOptions, R2BasicBlock};
+use super::roundtrip;
+use super::{StructureError, StructuringOptions};
+
+use std::collections::HashMap;
+use std::slice;
+
+pub const MAX_BLOCKS: usize = 16;
+const MAX_CASES: usize = 8;
+/// where the tail calls go
+const EXTERNAL: u64 = 0x1000;
+
+const RETURN: u8 = 0;
+const JUMP: u8 = 1;
+const BRANCH: u8 = 2;
+const SWITCH: u8 = 3;
+const UNRESOLVED: u8 = 4;
+
+fn addr(i: usize) -> u64 {
+    0x10 * (i as u64 + 1)
+}
+
+struct Reader<'a> {
+    bytes: slice::Iter<'a, u8>,
+    n: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> usize {
+        self.bytes.next().cloned().unwrap_or(0) as usize
+    }
+
+    fn target(&mut self) -> u64 {
+        match self.byte() % (self.n + 1) {
+            t if t < self.n => addr(t),
+            _ => EXTERNAL,
+        }
+    }
+
+    fn default(&mut self) -> Option<u64> {
+        match self.byte() % (self.n + 2) {
+            t if t < self.n => Some(addr(t)),
+            t if t == self.n => Some(EXTERNAL),
+            _ => None,
+        }
+    }
+}
+
+/// The blocks described by `data`, as in the [module docs](self).
+pub fn decode(data: &[u8]) -> Vec<R2BasicBlock> {
+    let mut r = Reader {
+        bytes: data.iter(),
+        n: 0,
+    };
+    r.n = 1 + r.byte() % MAX_BLOCKS;
+    let entry = r.byte() % r.n;
+    let mut blocks: Vec<_> = (0..r.n)
+        .map(|i| {
+            let mut block = R2BasicBlock {
+                addr: addr(i),
+                size: 4,
+                jump: None,
+                fail: None,
+                cases: Vec::new(),
+                default: None,
+                unresolved_jump: false,
+                esil: Vec::new(),
+            };
+            match (r.byte() % 5) as u8 {
+                RETURN => (),
+                JUMP => block.jump = Some(r.target()),
+                BRANCH => {
+                    block.jump = Some(r.target());
+                    block.fail = Some(r.target());
+                }
+                SWITCH => {
+                    let count = 1 + r.byte() % MAX_CASES;
+                    block.cases = (0..count).map(|c| (c as u64, r.target())).collect();
+                    block.default = r.default();
+                }
+                _ => block.unresolved_jump = true,
+            }
+            block
+        })
+        .collect();
+    let entry = blocks.remove(entry);
+    blocks.insert(0, entry);
+    blocks
+}
+
+/// Describes `blocks`, the first being the entry, so that [`decode`] gives
+/// back blocks that import as the same graph, up to the addresses. Only the
+/// [`MAX_BLOCKS`] first blocks and the first 8 cases of each switch are
+/// kept, and targets that aren't blocks are tail calls.
+pub fn encode(blocks: &[R2BasicBlock]) -> Vec<u8> {
+    let blocks = &blocks[..blocks.len().min(MAX_BLOCKS)];
+    let n = blocks.len();
+    if n == 0 {
+        return vec![0, 0, RETURN];
+    }
+    let mut sorted: Vec<_> = blocks.iter().collect();
+    sorted.sort_by_key(|b| b.addr);
+    let index: HashMap<_, _> = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, b)| (b.addr, i))
+        .collect();
+    let target = |t: u64| *index.get(&t).unwrap_or(&n) as u8;
+
+    let mut data = vec![(n - 1) as u8, index[&blocks[0].addr] as u8];
+    for block in sorted {
+        if !block.cases.is_empty() {
+            let cases = &block.cases[..block.cases.len().min(MAX_CASES)];
+            data.push(SWITCH);
+            data.push((cases.len() - 1) as u8);
+            data.extend(cases.iter().map(|&(_, t)| target(t)));
+            // what importing falls back to when no case matches
+            let default = block.default.or(block.fail).or(block.jump);
+            data.push(default.map_or(n as u8 + 1, target));
+        } else if block.unresolved_jump {
+            data.push(UNRESOLVED);
+        } else {
+            match (block.jump, block.fail) {
+                (Some(j), Some(f)) => data.extend(&[BRANCH, target(j), target(f)]),
+                (Some(t), None) | (None, Some(t)) => data.extend(&[JUMP, target(t)]),
+                (None, None) => data.push(RETURN),
+            }
+        }
+    }
+    data
+}
+
+/// Structures the blocks described by `data` with
+/// [`structure_whole_checked`](super::ControlFlowGraph::structure_whole_checked)
+/// and checks the result. Panics if structuring breaks one of its
+/// invariants or makes no progress, or if the AST fails
+/// [`roundtrip::check`]. Other errors, e.g. for unreachable blocks, reject
+/// the input and are fine.
+pub fn structure_and_check(data: &[u8]) {
+    let blocks = decode(data);
+    let import_opts = ImportOptions::default();
+    match from_r2::structure_blocks(&blocks, &import_opts, &StructuringOptions::default()) {
+        Ok(sf) => {
+            // the check needs the blocks as they were imported
+            let (blocks, _) = from_r2::repair_blocks(&blocks, import_opts.mode).unwrap();
+            if let Err(mismatch) = roundtrip::check(&blocks, &sf) {
+                panic!("{:?}\n{:#?}", mismatch, blocks);
+            }
+        }
+        Err(err @ StructureError::Internal { .. })
+        | Err(err @ StructureError::NoProgress { .. }) => panic!("{}\n{:#?}", err, blocks),
+        Err(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+
+    /// The fixtures the corpus is seeded with, and the seeds.
+    const SEEDS: &[(&str, &str)] = &[
+        ("test_files/loopy_main_afbj.json", "loopy_main"),
+        ("test_files/broken_afbj.json", "broken"),
+    ];
+    const SEED_DIR: &str = "fuzz/seeds/structure";
+
+    fn seeds() -> Vec<(String, String)> {
+        let mut seeds: Vec<_> = SEEDS
+            .iter()
+            .map(|&(json, seed)| (json.to_owned(), seed.to_owned()))
+            .collect();
+        let mut structuring: Vec<_> = fs::read_dir("test_files/structuring")
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().map_or(false, |e| e == "json"))
+            .collect();
+        structuring.sort();
+        for path in structuring {
+            let seed = path.file_stem().unwrap().to_str().unwrap().to_owned();
+            seeds.push((path.to_str().unwrap().to_owned(), seed));
+        }
+        seeds
+    }
+
+    fn encode_fixture(json: &str) -> Vec<u8> {
+        let json = fs::read_to_string(json).unwrap();
+        encode(&from_r2::parse_blocks(&json).unwrap())
+    }
+
+    /// A few thousand inputs of all lengths.
+    fn inputs() -> impl Iterator<Item = Vec<u8>> {
+        let mut state = 0x2545_f491_u32;
+        (0..3000).map(move |i| {
+            (0..i % 64)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    state as u8
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn decode_anything() {
+        for data in inputs().chain(vec![vec![], vec![0xff; 200]]) {
+            let blocks = decode(&data);
+            assert!(!blocks.is_empty() && blocks.len() <= MAX_BLOCKS);
+            let ends = addr(blocks.len());
+            for block in &blocks {
+                let targets = block.jump.iter().chain(&block.fail).chain(&block.default);
+                for &t in targets.chain(block.cases.iter().map(|c| &c.1)) {
+                    assert!(t == EXTERNAL || (t < ends && t % 0x10 == 0), "{:?}", data);
+                }
+            }
+            assert_eq!(decode(&encode(&blocks)), blocks, "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn seeds_are_current() {
+        for (json, seed) in seeds() {
+            let path = format!("{}/{}", SEED_DIR, seed);
+            let data = fs::read(&path).unwrap();
+            assert_eq!(data, encode_fixture(&json), "{} is stale", path);
+            structure_and_check(&data);
+        }
+    }
+
+    /// Rewrites the seeds from the fixtures; run with
+    /// `cargo test --features fuzz write_seeds -- --ignored`.
+    #[test]
+    #[ignore]
+    fn write_seeds() {
+        fs::create_dir_all(SEED_DIR).unwrap();
+        for (json, seed) in seeds() {
+            fs::write(format!("{}/{}", SEED_DIR, seed), encode_fixture(&json)).unwrap();
+        }
+    }
+}
@@ -5,10 +5,13 @@
 
 pub mod ix_bit_set;
 mod ncd;
+mod reducibility;
+pub mod sese;
 #[cfg(test)]
 mod test;
 
 pub use self::ncd::nearest_common_dominator;
+pub use self::reducibility::{check_reducible, IrreducibleRegion};
 
 use self::ix_bit_set::{IndexLike, IxBitSet};
 
@@ -0,0 +1,161 @@
+//! Checks whether a graph is reducible, see [`check_reducible`].
+
+use super::ix_bit_set::IxBitSet;
+
+use petgraph::prelude::*;
+use petgraph::visit::NodeIndexable;
+
+type NodeSet = IxBitSet<NodeIndex>;
+
+/// A cycle of a graph that can be entered at more than one node.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IrreducibleRegion {
+    /// the nodes of the cycle that can be reached without going through any
+    /// other one of them, sorted
+    pub entries: Vec<NodeIndex>,
+    /// the nodes of the strongly connected component the cycle is in, those
+    /// of the cycles nested in it included, sorted
+    pub nodes: Vec<NodeIndex>,
+}
+
+/// Checks whether the part of `graph` reachable from `entry` is reducible,
+/// i.e. whether each of its loops can only be entered at its header.
+/// Otherwise, returns the loops that can't, sorted by their first node.
+///
+/// The loops are found as nested strongly connected components: those of
+/// the graph, and then those of each after the edges into its entries from
+/// within it are cut, so that a loop nested in another one is reported
+/// separately, whether or not the outer one is reducible. This doesn't
+/// depend on the order of a depth-first search, unlike back edges.
+pub fn check_reducible<N, E>(
+    graph: &StableDiGraph<N, E>,
+    entry: NodeIndex,
+) -> Result<(), Vec<IrreducibleRegion>> {
+    let mut reachable = NodeSet::with_capacity(graph.node_bound());
+    let mut dfs = Dfs::new(graph, entry);
+    while let Some(n) = dfs.next(graph) {
+        reachable.insert(n);
+    }
+    let mut state = State {
+        graph,
+        entry,
+        reachable: &reachable,
+        cut: NodeSet::with_capacity(graph.node_bound()),
+        regions: Vec::new(),
+    };
+    state.check(&reachable);
+    let mut regions = state.regions;
+    if regions.is_empty() {
+        Ok(())
+    } else {
+        regions.sort_by_key(|r| r.nodes[0]);
+        Err(regions)
+    }
+}
+
+struct State<'a, N, E> {
+    graph: &'a StableDiGraph<N, E>,
+    entry: NodeIndex,
+    reachable: &'a NodeSet,
+    /// the headers and entries of the loops found so far, whose incoming
+    /// edges no longer count for the components within their loops
+    cut: NodeSet,
+    regions: Vec<IrreducibleRegion>,
+}
+
+impl<'a, N, E> State<'a, N, E> {
+    /// Finds the irreducible loops among `nodes`.
+    fn check(&mut self, nodes: &NodeSet) {
+        for scc in self.sccs(nodes) {
+            let cyclic = scc.len() > 1 || {
+                let n = scc.iter().next().unwrap();
+                !self.cut.contains(n) && self.graph.neighbors(n).any(|m| m == n)
+            };
+            if !cyclic {
+                continue;
+            }
+            let entries: Vec<_> = scc
+                .iter()
+                .filter(|&n| {
+                    n == self.entry
+                        || self
+                            .graph
+                            .neighbors_directed(n, Incoming)
+                            .any(|p| self.reachable.contains(p) && !scc.contains(p))
+                })
+                .collect();
+            if entries.len() > 1 {
+                self.regions.push(IrreducibleRegion {
+                    entries: entries.clone(),
+                    nodes: scc.iter().collect(),
+                });
+            }
+            for &n in &entries {
+                self.cut.insert(n);
+            }
+            self.check(&scc);
+        }
+    }
+
+    /// The strongly connected components of the subgraph made of `nodes`
+    /// and the edges between them, but those into `cut` nodes, using
+    /// Tarjan's algorithm.
+    fn sccs(&self, nodes: &NodeSet) -> Vec<NodeSet> {
+        let mut tarjan = Tarjan {
+            index: vec![usize::MAX; self.graph.node_bound()],
+            lowlink: vec![0; self.graph.node_bound()],
+            on_stack: NodeSet::new(),
+            stack: Vec::new(),
+            next: 0,
+            sccs: Vec::new(),
+        };
+        for n in nodes {
+            if tarjan.index[n.index()] == usize::MAX {
+                tarjan.visit(self, nodes, n);
+            }
+        }
+        tarjan.sccs
+    }
+}
+
+struct Tarjan {
+    index: Vec<usize>,
+    lowlink: Vec<usize>,
+    on_stack: NodeSet,
+    stack: Vec<NodeIndex>,
+    next: usize,
+    sccs: Vec<NodeSet>,
+}
+
+impl Tarjan {
+    fn visit<N, E>(&mut self, state: &State<N, E>, nodes: &NodeSet, n: NodeIndex) {
+        self.index[n.index()] = self.next;
+        self.lowlink[n.index()] = self.next;
+        self.next += 1;
+        self.stack.push(n);
+        self.on_stack.insert(n);
+        for m in state.graph.neighbors(n) {
+            if !nodes.contains(m) || state.cut.contains(m) {
+                continue;
+            }
+            if self.index[m.index()] == usize::MAX {
+                self.visit(state, nodes, m);
+                self.lowlink[n.index()] = self.lowlink[n.index()].min(self.lowlink[m.index()]);
+            } else if self.on_stack.contains(m) {
+                self.lowlink[n.index()] = self.lowlink[n.index()].min(self.index[m.index()]);
+            }
+        }
+        if self.lowlink[n.index()] == self.index[n.index()] {
+            let mut scc = NodeSet::new();
+            loop {
+                let m = self.stack.pop().unwrap();
+                self.on_stack.remove(m);
+                scc.insert(m);
+                if m == n {
+                    break;
+                }
+            }
+            self.sccs.push(scc);
+        }
+    }
+}
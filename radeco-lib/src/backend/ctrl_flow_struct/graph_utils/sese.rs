@@ -0,0 +1,518 @@
+//! Finds the canonical single-entry single-exit regions of a graph and how
+//! they nest, using the cycle equivalence algorithm from
+//! [*The Program Structure Tree*](https://doi.org/10.1145/178243.178258).
+
+use fixedbitset::FixedBitSet;
+use petgraph::prelude::*;
+use petgraph::visit::NodeIndexable;
+
+use std::cmp;
+use std::iter;
+use std::usize;
+
+/// A canonical single-entry single-exit region: a region bounded by two
+/// cycle equivalent edges where the `entry` edge dominates the `exit` edge and
+/// no other edge in their equivalence class lies between them.
+#[derive(Debug)]
+pub struct Region {
+    /// the only edge into the region; `None` for the root
+    pub entry: Option<EdgeIndex>,
+    /// the only edge out of the region; `None` for the root
+    pub exit: Option<EdgeIndex>,
+    /// the target of `entry`, or the entry of the graph for the root
+    pub header: NodeIndex,
+    /// the target of `exit`
+    pub successor: Option<NodeIndex>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    /// the nodes of this region that aren't in any of its children
+    pub nodes: Vec<NodeIndex>,
+}
+
+/// The nesting of the canonical SESE regions of a graph, also known as its
+/// program structure tree. `regions[0]` is the root, which is the whole graph.
+#[derive(Debug)]
+pub struct RegionTree {
+    pub regions: Vec<Region>,
+}
+
+impl RegionTree {
+    /// Returns every region, each one after all of its descendants.
+    pub fn postorder(&self) -> Vec<usize> {
+        let mut ret = Vec::with_capacity(self.regions.len());
+        let mut stack = vec![(0, 0)];
+        while let Some((r, i)) = stack.pop() {
+            if let Some(&child) = self.regions[r].children.get(i) {
+                stack.push((r, i + 1));
+                stack.push((child, 0));
+            } else {
+                ret.push(r);
+            }
+        }
+        ret
+    }
+
+    /// Returns the nodes of region `r`, including those of its descendants.
+    pub fn all_nodes(&self, r: usize) -> Vec<NodeIndex> {
+        let mut ret = Vec::new();
+        let mut stack = vec![r];
+        while let Some(r) = stack.pop() {
+            ret.extend(&self.regions[r].nodes);
+            stack.extend(&self.regions[r].children);
+        }
+        ret
+    }
+
+    /// Returns the tree with the children of each region nested in it, and
+    /// the nodes of each region, including those of its descendants, as a
+    /// set of `node_bound` bits.
+    pub fn nest(&self, node_bound: usize) -> NestedRegion {
+        let mut nested: Vec<Option<NestedRegion>> = self.regions.iter().map(|_| None).collect();
+        for r in self.postorder() {
+            let region = &self.regions[r];
+            let mut members = FixedBitSet::with_capacity(node_bound);
+            for &n in &region.nodes {
+                members.insert(n.index());
+            }
+            let children: Vec<_> = region
+                .children
+                .iter()
+                .map(|&c| nested[c].take().unwrap())
+                .collect();
+            for c in &children {
+                members.union_with(&c.members);
+            }
+            nested[r] = Some(NestedRegion {
+                header: region.header,
+                exit: region.successor,
+                members,
+                children,
+            });
+        }
+        nested[0].take().unwrap()
+    }
+}
+
+/// A region of a [`RegionTree`] with its children nested in it, see
+/// [`RegionTree::nest`].
+#[derive(Clone, Debug)]
+pub struct NestedRegion {
+    pub header: NodeIndex,
+    /// the node the region goes on to; `None` for the root
+    pub exit: Option<NodeIndex>,
+    /// the nodes of the region, including those of its descendants, by
+    /// index
+    pub members: FixedBitSet,
+    pub children: Vec<NestedRegion>,
+}
+
+impl NestedRegion {
+    /// Returns the region and all of its descendants, each one before its
+    /// children.
+    pub fn iter(&self) -> impl Iterator<Item = &NestedRegion> {
+        let mut stack = vec![self];
+        iter::from_fn(move || {
+            let r = stack.pop()?;
+            stack.extend(r.children.iter().rev());
+            Some(r)
+        })
+    }
+}
+
+/// Builds the program structure tree of the part of `graph` reachable from
+/// `entry`. Runs in time linear in the size of the graph.
+pub fn region_tree<N, E>(graph: &StableDiGraph<N, E>, entry: NodeIndex) -> RegionTree {
+    let events = dfs_edges(graph, entry);
+    let node_bound = graph.node_bound();
+    let edge_bound = graph
+        .edge_indices()
+        .map(|e| e.index() + 1)
+        .max()
+        .unwrap_or(0);
+
+    // the theory needs every node to be able to reach the exit, so besides
+    // sinks, nodes that can't reach a sink (i.e. in infinite loops) get an edge
+    // to the exit too
+    let mut reachable = vec![false; node_bound];
+    reachable[entry.index()] = true;
+    for &(_, _, v, _) in &events {
+        reachable[v.index()] = true;
+    }
+    let is_sink = |n: NodeIndex| graph.neighbors(n).next().is_none();
+    let mut reaches_sink = vec![false; node_bound];
+    let mut stack: Vec<_> = graph
+        .node_indices()
+        .filter(|&n| reachable[n.index()] && is_sink(n))
+        .collect();
+    for &n in &stack {
+        reaches_sink[n.index()] = true;
+    }
+    while let Some(n) = stack.pop() {
+        for pred in graph.neighbors_directed(n, Incoming) {
+            if reachable[pred.index()] && !reaches_sink[pred.index()] {
+                reaches_sink[pred.index()] = true;
+                stack.push(pred);
+            }
+        }
+    }
+    let has_exit: Vec<_> = (0..node_bound)
+        .map(|i| reachable[i] && (!reaches_sink[i] || is_sink(NodeIndex::new(i))))
+        .collect();
+
+    let cyc_eq = CycleEquiv::run(graph, entry, &events, edge_bound, &has_exit);
+
+    // list the edges of each class in dominance order: an edge that dominates
+    // another is always traversed first
+    let mut class_edges = vec![Vec::new(); cyc_eq.num_classes];
+    let push_exit_edge = |class_edges: &mut Vec<Vec<_>>, n: NodeIndex| {
+        if has_exit[n.index()] {
+            let exit_edge = cyc_eq.exit_edge[n.index()];
+            class_edges[cyc_eq.class[exit_edge]].push(exit_edge);
+        }
+    };
+    class_edges[cyc_eq.class[cyc_eq.entry_edge]].push(cyc_eq.entry_edge);
+    push_exit_edge(&mut class_edges, entry);
+    for &(e, _, v, discovers) in &events {
+        class_edges[cyc_eq.class[e.index()]].push(e.index());
+        if discovers {
+            push_exit_edge(&mut class_edges, v);
+        }
+    }
+
+    let mut regions = vec![Region {
+        entry: None,
+        exit: None,
+        header: entry,
+        successor: None,
+        parent: None,
+        children: Vec::new(),
+        nodes: Vec::new(),
+    }];
+    let mut entry_region = vec![None; edge_bound];
+    let mut exit_region = vec![None; edge_bound];
+    for edges in &class_edges {
+        for pair in edges.windows(2) {
+            let (e1, e2) = (pair[0], pair[1]);
+            if e1 >= edge_bound || e2 >= edge_bound {
+                // bounded by a virtual edge
+                continue;
+            }
+            let (e1, e2) = (EdgeIndex::new(e1), EdgeIndex::new(e2));
+            entry_region[e1.index()] = Some(regions.len());
+            exit_region[e2.index()] = Some(regions.len());
+            regions.push(Region {
+                entry: Some(e1),
+                exit: Some(e2),
+                header: graph.edge_endpoints(e1).unwrap().1,
+                successor: Some(graph.edge_endpoints(e2).unwrap().1),
+                parent: None,
+                children: Vec::new(),
+                nodes: Vec::new(),
+            });
+        }
+    }
+
+    // nest the regions by walking the edges again, tracking which region
+    // we're currently in
+    let mut region_of = vec![0; node_bound];
+    regions[0].nodes.push(entry);
+    for &(e, u, v, discovers) in &events {
+        let mut r = region_of[u.index()];
+        if exit_region[e.index()] == Some(r) {
+            r = regions[r].parent.unwrap();
+        }
+        if let Some(inner) = entry_region[e.index()] {
+            debug_assert!(discovers);
+            regions[inner].parent = Some(r);
+            regions[r].children.push(inner);
+            r = inner;
+        }
+        if discovers {
+            region_of[v.index()] = r;
+            regions[r].nodes.push(v);
+        }
+    }
+
+    RegionTree { regions }
+}
+
+/// Does a depth-first search from `entry`, returning every edge in the order
+/// it was traversed along with its endpoints and whether it discovered its
+/// target.
+fn dfs_edges<N, E>(
+    graph: &StableDiGraph<N, E>,
+    entry: NodeIndex,
+) -> Vec<(EdgeIndex, NodeIndex, NodeIndex, bool)> {
+    let mut ret = Vec::with_capacity(graph.edge_count());
+    let mut discovered = vec![false; graph.node_bound()];
+    let mut stack = vec![graph.neighbors(entry).detach()];
+    discovered[entry.index()] = true;
+    while let Some(succs) = stack.last_mut() {
+        if let Some(e) = succs.next_edge(graph) {
+            let (u, v) = graph.edge_endpoints(e).unwrap();
+            let discovers = !discovered[v.index()];
+            ret.push((e, u, v, discovers));
+            if discovers {
+                discovered[v.index()] = true;
+                stack.push(graph.neighbors(v).detach());
+            }
+        } else {
+            stack.pop();
+        }
+    }
+    ret
+}
+
+const NONE: usize = usize::MAX;
+
+/// Cycle equivalence classes of the edges of a graph, viewed as undirected,
+/// after adding a virtual exit node that is connected to the entry and to
+/// every node that needs an edge to the exit. Virtual edges are numbered after
+/// the graph's own edges.
+struct CycleEquiv {
+    /// the class of each edge, indexed by edge number
+    class: Vec<usize>,
+    num_classes: usize,
+    /// the virtual edge to the entry
+    entry_edge: usize,
+    /// the virtual edge from each node to the exit, indexed by node
+    exit_edge: Vec<usize>,
+}
+
+/// An element of a bracket list; see the paper.
+struct Bracket {
+    prev: usize,
+    next: usize,
+    /// the backedge this is for, or `NONE` for a capping backedge
+    edge: usize,
+    recent_size: usize,
+    recent_class: usize,
+}
+
+/// A doubly linked list of `Bracket`s, with the top at `head`.
+#[derive(Copy, Clone)]
+struct BracketList {
+    head: usize,
+    tail: usize,
+    size: usize,
+}
+
+impl CycleEquiv {
+    fn run<N, E>(
+        graph: &StableDiGraph<N, E>,
+        entry: NodeIndex,
+        events: &[(EdgeIndex, NodeIndex, NodeIndex, bool)],
+        edge_bound: usize,
+        has_exit: &[bool],
+    ) -> Self {
+        let exit = graph.node_bound();
+        let num_nodes = exit + 1;
+
+        // the undirected graph: endpoints of each edge and the edges incident
+        // to each node
+        let mut ends = vec![(NONE, NONE); edge_bound];
+        for &(e, u, v, _) in events {
+            ends[e.index()] = (u.index(), v.index());
+        }
+        ends.push((exit, entry.index()));
+        let mut exit_edge = vec![NONE; exit];
+        for n in (0..exit).filter(|&n| has_exit[n]) {
+            exit_edge[n] = ends.len();
+            ends.push((n, exit));
+        }
+        let num_edges = ends.len();
+        let mut incident = vec![Vec::new(); num_nodes];
+        for (e, &(a, b)) in ends.iter().enumerate().filter(|&(_, &(a, _))| a != NONE) {
+            incident[a].push(e);
+            incident[b].push(e);
+        }
+        let other = |e: usize, n: usize| if ends[e].0 == n { ends[e].1 } else { ends[e].0 };
+
+        let mut class = vec![NONE; num_edges];
+        let mut num_classes = 0;
+        let mut new_class = || {
+            num_classes += 1;
+            num_classes - 1
+        };
+
+        // undirected depth-first search from the virtual node
+        let mut dfsnum = vec![NONE; num_nodes];
+        let mut order = Vec::with_capacity(num_nodes);
+        let mut parent_edge = vec![NONE; num_nodes];
+        let mut children = vec![Vec::new(); num_nodes];
+        // backedges from each node to its ancestors
+        let mut backedges_up = vec![Vec::new(); num_nodes];
+        // backedges from descendants of each node to it
+        let mut backedges_down = vec![Vec::new(); num_nodes];
+        dfsnum[exit] = 0;
+        order.push(exit);
+        let mut stack = vec![(exit, 0)];
+        while let Some(&mut (u, ref mut i)) = stack.last_mut() {
+            let e = if let Some(&e) = incident[u].get(*i) {
+                *i += 1;
+                e
+            } else {
+                stack.pop();
+                continue;
+            };
+            let w = other(e, u);
+            if e == parent_edge[u] {
+                continue;
+            } else if w == u {
+                // a self-loop is only equivalent to itself
+                if class[e] == NONE {
+                    class[e] = new_class();
+                }
+            } else if dfsnum[w] == NONE {
+                dfsnum[w] = order.len();
+                order.push(w);
+                parent_edge[w] = e;
+                children[u].push(w);
+                stack.push((w, 0));
+            } else if dfsnum[w] < dfsnum[u] {
+                backedges_up[u].push(e);
+                backedges_down[w].push(e);
+            }
+            // otherwise, it's a backedge we already saw from the other end
+        }
+
+        let mut brackets: Vec<Bracket> = Vec::new();
+        let mut bracket_of = vec![NONE; num_edges];
+        let mut capping = vec![Vec::new(); num_nodes];
+        let mut lists = vec![BracketList::new(); num_nodes];
+        let mut hi = vec![NONE; num_nodes];
+        for &n in order.iter().rev() {
+            let hi0 = backedges_up[n]
+                .iter()
+                .map(|&e| dfsnum[other(e, n)])
+                .min()
+                .unwrap_or(NONE);
+            let (mut hi1, mut hi2) = (NONE, NONE);
+            for &c in &children[n] {
+                if hi[c] < hi1 {
+                    hi2 = hi1;
+                    hi1 = hi[c];
+                } else if hi[c] < hi2 {
+                    hi2 = hi[c];
+                }
+            }
+            hi[n] = cmp::min(hi0, hi1);
+
+            let mut list = BracketList::new();
+            for &c in &children[n] {
+                list = list.concat(lists[c], &mut brackets);
+            }
+            for &d in &capping[n] {
+                list.delete(d, &mut brackets);
+            }
+            for &e in &backedges_down[n] {
+                list.delete(bracket_of[e], &mut brackets);
+                if class[e] == NONE {
+                    class[e] = new_class();
+                }
+            }
+            for &e in &backedges_up[n] {
+                bracket_of[e] = brackets.len();
+                list.push(Bracket::new(e), &mut brackets);
+            }
+            // a child's backedges may only reach up to `n` itself, which
+            // doesn't need capping
+            if hi2 < hi0 && hi2 < dfsnum[n] {
+                capping[order[hi2]].push(brackets.len());
+                list.push(Bracket::new(NONE), &mut brackets);
+            }
+
+            let e = parent_edge[n];
+            if e != NONE {
+                if list.size == 0 {
+                    // a bridge
+                    class[e] = new_class();
+                } else {
+                    let top = &mut brackets[list.head];
+                    if top.recent_size != list.size {
+                        top.recent_size = list.size;
+                        top.recent_class = new_class();
+                    }
+                    class[e] = top.recent_class;
+                    if list.size == 1 && top.edge != NONE {
+                        class[top.edge] = class[e];
+                    }
+                }
+            }
+            lists[n] = list;
+        }
+
+        Self {
+            class,
+            num_classes,
+            entry_edge: edge_bound,
+            exit_edge,
+        }
+    }
+}
+
+impl Bracket {
+    fn new(edge: usize) -> Self {
+        Self {
+            prev: NONE,
+            next: NONE,
+            edge,
+            recent_size: NONE,
+            recent_class: NONE,
+        }
+    }
+}
+
+impl BracketList {
+    fn new() -> Self {
+        Self {
+            head: NONE,
+            tail: NONE,
+            size: 0,
+        }
+    }
+
+    fn push(&mut self, mut bracket: Bracket, brackets: &mut Vec<Bracket>) {
+        let b = brackets.len();
+        bracket.next = self.head;
+        if self.head != NONE {
+            brackets[self.head].prev = b;
+        } else {
+            self.tail = b;
+        }
+        brackets.push(bracket);
+        self.head = b;
+        self.size += 1;
+    }
+
+    fn delete(&mut self, b: usize, brackets: &mut [Bracket]) {
+        let (prev, next) = (brackets[b].prev, brackets[b].next);
+        if prev != NONE {
+            brackets[prev].next = next;
+        } else {
+            self.head = next;
+        }
+        if next != NONE {
+            brackets[next].prev = prev;
+        } else {
+            self.tail = prev;
+        }
+        self.size -= 1;
+    }
+
+    fn concat(self, other: Self, brackets: &mut [Bracket]) -> Self {
+        if self.size == 0 {
+            other
+        } else if other.size == 0 {
+            self
+        } else {
+            brackets[self.tail].next = other.head;
+            brackets[other.head].prev = self.tail;
+            Self {
+                head: self.head,
+                tail: other.tail,
+                size: self.size + other.size,
+            }
+        }
+    }
+}
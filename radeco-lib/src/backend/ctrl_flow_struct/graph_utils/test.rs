@@ -1,10 +1,11 @@
 use super::*;
 use petgraph::algo;
 use petgraph::prelude::{Outgoing, StableDiGraph};
-use petgraph::visit::IntoEdgeReferences;
+use petgraph::visit::{IntoEdgeReferences, NodeIndexable};
 
+use fixedbitset::FixedBitSet;
 use quickcheck::TestResult;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::iter::FromIterator;
 
@@ -165,6 +166,152 @@ fn qc_dag_transitive_closure(graph: StableDiGraph<(), ()>) -> TestResult {
     }))
 }
 
+/// Tests that `region_tree` finds exactly the canonical SESE regions and
+/// puts every node in exactly one region.
+#[quickcheck]
+fn qc_region_tree(mut graph: StableDiGraph<(), ()>, root_i: usize) -> TestResult {
+    let root = if let Some(root) = mk_rooted_stable_graph(&mut graph, root_i, false) {
+        root
+    } else {
+        return TestResult::discard();
+    };
+    println!("graph: {:?}", graph);
+    println!("root: {:?}", root);
+
+    // the undirected graph with a virtual exit node connected to the root,
+    // every sink and every node that can't reach a sink
+    let exit = graph.node_bound();
+    let reaches_sink = |n| {
+        Dfs::new(&graph, n)
+            .iter(&graph)
+            .any(|m| graph.neighbors(m).next().is_none())
+    };
+    let real_edges: Vec<_> = graph.edge_indices().collect();
+    let mut ends: Vec<_> = real_edges
+        .iter()
+        .map(|&e| {
+            let (u, v) = graph.edge_endpoints(e).unwrap();
+            (u.index(), v.index())
+        })
+        .collect();
+    ends.push((exit, root.index()));
+    ends.extend(
+        graph
+            .node_indices()
+            .filter(|&n| graph.neighbors(n).next().is_none() || !reaches_sink(n))
+            .map(|n| (n.index(), exit)),
+    );
+    let connected_without = |skip: &[usize]| {
+        let mut uf: Vec<_> = (0..=exit).collect();
+        fn find(uf: &mut Vec<usize>, x: usize) -> usize {
+            if uf[x] != x {
+                let r = find(uf, uf[x]);
+                uf[x] = r;
+            }
+            uf[x]
+        }
+        let mut components = exit + 1;
+        for (i, &(a, b)) in ends.iter().enumerate() {
+            if !skip.contains(&i) {
+                let (ra, rb) = (find(&mut uf, a), find(&mut uf, b));
+                if ra != rb {
+                    uf[ra] = rb;
+                    components -= 1;
+                }
+            }
+        }
+        components == 1
+    };
+    let equiv = |i: usize, j: usize| {
+        connected_without(&[i]) && connected_without(&[j]) && !connected_without(&[i, j])
+    };
+    let dominates = |i: usize, j: usize| {
+        // is the source of `j` unreachable without going through `i`?
+        let src = graph.edge_endpoints(real_edges[j]).unwrap().0;
+        let mut reached = IxBitSet::new();
+        let mut stack = vec![root];
+        reached.insert(root);
+        while let Some(n) = stack.pop() {
+            for e in graph.edges(n) {
+                if e.id() != real_edges[i] && reached.insert(e.target()) {
+                    stack.push(e.target());
+                }
+            }
+        }
+        !reached.contains(src)
+    };
+
+    let mut true_regions = HashSet::new();
+    for i in 0..real_edges.len() {
+        for j in 0..real_edges.len() {
+            if i == j || !equiv(i, j) || !dominates(i, j) {
+                continue;
+            }
+            let between = (0..real_edges.len())
+                .any(|k| k != i && k != j && equiv(i, k) && dominates(i, k) && dominates(k, j));
+            if !between {
+                true_regions.insert((real_edges[i], real_edges[j]));
+            }
+        }
+    }
+
+    let tree = sese::region_tree(&graph, root);
+    let regions: HashSet<_> = tree.regions[1..]
+        .iter()
+        .map(|r| (r.entry.unwrap(), r.exit.unwrap()))
+        .collect();
+    if regions != true_regions {
+        println!("wrong regions:");
+        println!("  real: {:?}", true_regions);
+        println!("  found: {:?}", regions);
+        return TestResult::failed();
+    }
+
+    let mut seen = IxBitSet::new();
+    for r in &tree.regions {
+        for &n in &r.nodes {
+            if !seen.insert(n) {
+                println!("{:?} in multiple regions", n);
+                return TestResult::failed();
+            }
+        }
+    }
+    TestResult::from_bool(graph.node_indices().all(|n| seen.contains(n)))
+}
+
+/// Tests that the nested regions of `RegionTree::nest` hold all nodes, and
+/// that the members of each region are those of its children, which are
+/// disjoint, and its own.
+#[quickcheck]
+fn qc_nested_regions(mut graph: StableDiGraph<(), ()>, root_i: usize) -> TestResult {
+    let root = if let Some(root) = mk_rooted_stable_graph(&mut graph, root_i, false) {
+        root
+    } else {
+        return TestResult::discard();
+    };
+    let tree = sese::region_tree(&graph, root);
+    let nested = tree.nest(graph.node_bound());
+    if nested.members.ones().count() != graph.node_count() {
+        println!("missing nodes: {:?}", nested);
+        return TestResult::failed();
+    }
+    for r in nested.iter() {
+        let mut children = FixedBitSet::with_capacity(graph.node_bound());
+        for c in &r.children {
+            if !children.is_disjoint(&c.members) {
+                println!("overlapping siblings in {:?}", r);
+                return TestResult::failed();
+            }
+            children.union_with(&c.members);
+        }
+        if !children.is_subset(&r.members) || !graph.contains_node(r.header) {
+            println!("children outside of {:?}", r);
+            return TestResult::failed();
+        }
+    }
+    TestResult::from_bool(nested.iter().count() == tree.regions.len())
+}
+
 fn mk_rooted_stable_graph(
     graph: &mut StableDiGraph<(), ()>,
     root_i: usize,
@@ -215,3 +362,100 @@ where
         .edge_references()
         .all(|e| order_idx[&e.source()] < order_idx[&e.target()])
 }
+
+/// Tests that `check_reducible` finds a graph reducible if and only if the
+/// target of each back edge dominates its source.
+#[quickcheck]
+fn qc_check_reducible(mut graph: StableDiGraph<(), ()>, root_i: usize) -> TestResult {
+    let root = if let Some(root) = mk_rooted_stable_graph(&mut graph, root_i, false) {
+        root
+    } else {
+        return TestResult::discard();
+    };
+    let dominators = algo::dominators::simple_fast(&graph, root);
+    let mut reducible = true;
+    depth_first_search(&graph, root, |ev| {
+        if let DfsEvent::BackEdge(e) = ev {
+            reducible &= dominators
+                .dominators(e.source())
+                .unwrap()
+                .any(|d| d == e.target());
+        }
+    });
+
+    let result = check_reducible(&graph, root);
+    if let Err(regions) = &result {
+        for r in regions {
+            if r.entries.len() < 2 || r.entries.iter().any(|n| !r.nodes.contains(n)) {
+                return TestResult::failed();
+            }
+        }
+    }
+    TestResult::from_bool(result.is_ok() == reducible)
+}
+
+#[test]
+fn check_reducible_two_entries() {
+    // 0 -> 1 <-> 2 <- 0
+    let mut graph = StableDiGraph::<(), ()>::new();
+    let n: Vec<_> = (0..4).map(|_| graph.add_node(())).collect();
+    for &(u, v) in &[
+        (n[0], n[1]),
+        (n[0], n[2]),
+        (n[1], n[2]),
+        (n[2], n[1]),
+        (n[2], n[3]),
+    ] {
+        graph.add_edge(u, v, ());
+    }
+    assert_eq!(
+        check_reducible(&graph, n[0]),
+        Err(vec![IrreducibleRegion {
+            entries: vec![n[1], n[2]],
+            nodes: vec![n[1], n[2]],
+        }])
+    );
+
+    // with one entry, it is a loop
+    let e = graph.find_edge(n[0], n[2]).unwrap();
+    graph.remove_edge(e);
+    assert_eq!(check_reducible(&graph, n[0]), Ok(()));
+}
+
+#[test]
+fn check_reducible_two_regions() {
+    // two of the above one after the other, and a reducible loop with one
+    // nested in it: 4 -> 5 -> 6 <-> 7 <- 5 -> 8, 7 -> 5
+    let mut graph = StableDiGraph::<(), ()>::new();
+    let n: Vec<_> = (0..9).map(|_| graph.add_node(())).collect();
+    for &(u, v) in &[
+        (n[0], n[1]),
+        (n[0], n[2]),
+        (n[1], n[2]),
+        (n[2], n[1]),
+        (n[2], n[3]),
+        (n[3], n[4]),
+        (n[4], n[5]),
+        (n[5], n[6]),
+        (n[5], n[7]),
+        (n[6], n[7]),
+        (n[7], n[6]),
+        (n[7], n[5]),
+        (n[5], n[8]),
+    ] {
+        graph.add_edge(u, v, ());
+    }
+    assert_eq!(
+        check_reducible(&graph, n[0]),
+        Err(vec![
+            IrreducibleRegion {
+                entries: vec![n[1], n[2]],
+                nodes: vec![n[1], n[2]],
+            },
+            IrreducibleRegion {
+                entries: vec![n[6], n[7]],
+                nodes: vec![n[6], n[7]],
+            },
+        ])
+    );
+}
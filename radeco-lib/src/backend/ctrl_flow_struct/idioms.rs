@@ -0,0 +1,241 @@
+//! Finds the `if`s that compute a minimum, maximum or absolute value, see
+//! [`MinMax`]. The AST is left as it is; the idioms are only named, e.g.
+//! for [`WriterOptions::annotations`](crate::backend::lang_c::c_writer::WriterOptions::annotations).
+
+use super::ast::AstNode;
+use super::decisions::AstNodeId;
+use super::matchers::{self, Annotation, IdiomMatcher, MatchCtx, MatchNode, MatchOracle};
+
+use std::fmt;
+
+/// An ordering comparison, as in `a < b`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A computation that an `if` is the idiom for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Idiom {
+    /// `if (a < b) x = a; else x = b;`
+    Min { dest: String, a: String, b: String },
+    /// `if (a > b) x = a; else x = b;`
+    Max { dest: String, a: String, b: String },
+    /// `if (a < 0) a = -a;`
+    Abs { dest: String },
+}
+
+impl fmt::Display for Idiom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Idiom::Min { dest, a, b } => write!(f, "{} = min({}, {})", dest, a, b),
+            Idiom::Max { dest, a, b } => write!(f, "{} = max({}, {})", dest, a, b),
+            Idiom::Abs { dest } => write!(f, "{} = abs({})", dest, dest),
+        }
+    }
+}
+
+/// Returns the `Cond`s of `ast` that are idioms, by their [`AstNodeId`]
+/// in `ast` alone, in pre-order, as [`MinMax`] finds them with `oracle`.
+pub fn annotate<B, C, V, O>(ast: &AstNode<B, C, V>, oracle: &O) -> Vec<(AstNodeId, Idiom)>
+where
+    O: MatchOracle<B, C, V>,
+{
+    matchers::run(ast, oracle, &[&MinMax])
+        .into_iter()
+        .filter_map(|(id, a)| match a {
+            Annotation::Idiom(idiom) => Some((id, idiom)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Matches the `Cond`s that are idioms:
+///
+/// - `if (a < b) x = a; else x = b;`, and the same with `<=`, is
+///   `x = min(a, b)`, and `x = max(a, b)` with the arms the other way
+///   around; `>` and `>=` are the other way around too.
+/// - `if (a < 0) a = -a;`, and the same with `<=` or with `0 > a`, is
+///   `a = abs(a)`.
+///
+/// The arms must be single blocks that the context recognizes.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MinMax;
+
+impl IdiomMatcher for MinMax {
+    fn try_match(&self, node: &MatchNode, ctx: &MatchCtx) -> Option<Annotation> {
+        idiom(node, ctx).map(Annotation::Idiom)
+    }
+}
+
+fn idiom(ast: &MatchNode, ctx: &MatchCtx) -> Option<Idiom> {
+    use self::AstNode::*;
+    match *ast {
+        Cond(c, box BasicBlock(t), Some(box BasicBlock(e))) => {
+            let (l, cmp, r) = ctx.comparison(c)?;
+            let (dest, then_src) = ctx.assignment(t)?;
+            let (else_dest, else_src) = ctx.assignment(e)?;
+            if dest != else_dest {
+                return None;
+            }
+            // whether the `then` arm, taken when `cmp` holds, assigns `l`
+            let then_l = match (
+                then_src == l && else_src == r,
+                then_src == r && else_src == l,
+            ) {
+                (true, false) => true,
+                (false, true) => false,
+                _ => return None,
+            };
+            let smaller = match cmp {
+                Cmp::Lt | Cmp::Le => then_l,
+                Cmp::Gt | Cmp::Ge => !then_l,
+            };
+            let (a, b) = (then_src, else_src);
+            Some(if smaller {
+                Idiom::Min { dest, a, b }
+            } else {
+                Idiom::Max { dest, a, b }
+            })
+        }
+        Cond(c, box BasicBlock(t), None) => {
+            let (l, cmp, r) = ctx.comparison(c)?;
+            let operand = match cmp {
+                Cmp::Lt | Cmp::Le if r == "0" => l,
+                Cmp::Gt | Cmp::Ge if l == "0" => r,
+                _ => return None,
+            };
+            match ctx.negation(t)? {
+                (dest, src) if dest == operand && src == operand => Some(Idiom::Abs { dest }),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+
+    type Ast = AstNode<&'static str, &'static str, ()>;
+
+    /// Understands `a < b` and the like, `x = a` and `x = -a`, with single
+    /// words as operands.
+    struct Words;
+
+    impl MatchOracle<&'static str, &'static str, ()> for Words {
+        fn comparison(&self, cond: &&str) -> Option<(String, Cmp, String)> {
+            let words: Vec<_> = cond.split(' ').collect();
+            let cmp = match words.get(1) {
+                Some(&"<") => Cmp::Lt,
+                Some(&"<=") => Cmp::Le,
+                Some(&">") => Cmp::Gt,
+                Some(&">=") => Cmp::Ge,
+                _ => return None,
+            };
+            Some((words[0].to_owned(), cmp, words.get(2)?.to_string()))
+        }
+
+        fn assignment(&self, block: &&str) -> Option<(String, String)> {
+            let mut words = block.split(" = ");
+            let (dest, src) = (words.next()?, words.next()?);
+            if src.starts_with('-') {
+                return None;
+            }
+            Some((dest.to_owned(), src.to_owned()))
+        }
+
+        fn negation(&self, block: &&str) -> Option<(String, String)> {
+            let mut words = block.split(" = -");
+            Some((words.next()?.to_owned(), words.next()?.to_owned()))
+        }
+    }
+
+    fn if_else(c: &'static str, t: &'static str, e: &'static str) -> Ast {
+        Cond(c, Box::new(BasicBlock(t)), Some(Box::new(BasicBlock(e))))
+    }
+
+    fn min_max(dest: &str, a: &str, b: &str, min: bool) -> Idiom {
+        let (dest, a, b) = (dest.to_owned(), a.to_owned(), b.to_owned());
+        if min {
+            Idiom::Min { dest, a, b }
+        } else {
+            Idiom::Max { dest, a, b }
+        }
+    }
+
+    #[test]
+    fn min() {
+        let ast = Seq(vec![
+            BasicBlock("s"),
+            if_else("a < b", "x = a", "x = b"),
+            if_else("a >= b", "y = b", "y = a"),
+        ]);
+        assert_eq!(
+            annotate(&ast, &Words),
+            vec![
+                (AstNodeId(2), min_max("x", "a", "b", true)),
+                (AstNodeId(5), min_max("y", "b", "a", true)),
+            ]
+        );
+        assert_eq!(min_max("x", "a", "b", true).to_string(), "x = min(a, b)");
+    }
+
+    #[test]
+    fn max() {
+        let ast = Seq(vec![
+            if_else("a > b", "x = a", "x = b"),
+            if_else("a <= b", "y = b", "y = a"),
+        ]);
+        assert_eq!(
+            annotate(&ast, &Words),
+            vec![
+                (AstNodeId(1), min_max("x", "a", "b", false)),
+                (AstNodeId(4), min_max("y", "b", "a", false)),
+            ]
+        );
+    }
+
+    #[test]
+    fn abs() {
+        let ast: Ast = Cond("a < 0", Box::new(BasicBlock("a = -a")), None);
+        let abs = Idiom::Abs {
+            dest: "a".to_owned(),
+        };
+        assert_eq!(annotate(&ast, &Words), vec![(AstNodeId(0), abs.clone())]);
+        assert_eq!(abs.to_string(), "a = abs(a)");
+
+        let ast: Ast = Cond("0 > a", Box::new(BasicBlock("a = -a")), None);
+        assert_eq!(annotate(&ast, &Words), vec![(AstNodeId(0), abs)]);
+    }
+
+    #[test]
+    fn near_misses() {
+        let asts = vec![
+            // different destinations
+            if_else("a < b", "x = a", "y = b"),
+            // not the operands compared
+            if_else("a < b", "x = a", "x = c"),
+            if_else("a < b", "x = a", "x = a"),
+            // more than an assignment in an arm
+            Cond(
+                "a < b",
+                Box::new(Seq(vec![BasicBlock("x = a"), BasicBlock("f")])),
+                Some(Box::new(BasicBlock("x = b"))),
+            ),
+            // negates something else, or against something other than zero
+            Cond("a < 0", Box::new(BasicBlock("b = -b")), None),
+            Cond("a < 1", Box::new(BasicBlock("a = -a")), None),
+            Cond("a > 0", Box::new(BasicBlock("a = -a")), None),
+        ];
+        for ast in asts {
+            assert_eq!(annotate(&ast, &Words), Vec::new(), "{:?}", ast);
+        }
+    }
+}
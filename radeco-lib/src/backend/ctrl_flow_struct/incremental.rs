@@ -0,0 +1,953 @@
+//! Re-structures a function after a few of its nodes or edges change,
+//! without starting over, e.g. after patching an instruction or overriding
+//! the target of a branch.
+//!
+//! An [`IncrementalCfg`] keeps its own copy of the graph and structures it
+//! one canonical SESE region at a time, innermost first: the graph of a
+//! region, with each of its child regions replaced by a single node holding
+//! the AST of the child, is structured on its own, and the AST is kept.
+//! Editing nodes and edges marks them as changed, and
+//! [`restructure`](IncrementalCfg::restructure) finds the regions of the
+//! edited graph, keeps the AST of every region that is still there with
+//! none of its nodes changed, and only structures the others, i.e. the
+//! regions with a change in them and the ones enclosing those. An edit that
+//! merges regions, e.g. by adding an edge from one into another, rebuilds
+//! the smallest region still enclosing them, which at worst is the whole
+//! function. So does a region that only goes on to its successor from the
+//! middle of a loop, which can't be structured apart from what follows it.
+//!
+//! Structuring region by region gives different, though equivalent, ASTs
+//! than [`ControlFlowGraph::structure_whole_with`]; in particular, a region
+//! that ends the function somewhere gets an explicit `Return` there.
+//!
+//! It also keeps what goes wrong within a region: a region that fails to
+//! structure, e.g. on a bug that makes structuring panic, is laid out as an
+//! `Unstructured` region of `Goto`s instead, see [`FailedRegion`], and the
+//! regions around it structure as usual. So are the regions left once the
+//! budget runs out or structuring is cancelled.
+//! [`ControlFlowGraph::structure_by_region`] structures a function this way
+//! once.
+
+use super::ast::LabelId;
+use super::ast_context::{AstContext, AstContextMut};
+use super::graph_utils;
+use super::naming::{self, CounterNamer};
+use super::{
+    continues_switch, count_gotos, empty_node, is_sink, BranchWeights, Budget, BudgetLeft, CfgEdge,
+    CfgNode, CondContext, ControlFlowGraph, FailedRegion, InputDefect, NodeSet, RegionTree,
+    StructureError, StructuringOptions, StructuringReport, ValueSets,
+};
+
+use petgraph::prelude::*;
+use petgraph::visit::NodeIndexable;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::iter;
+use std::rc::Rc;
+use std::time::Instant;
+
+type AstNode<'cd, A> = super::AstNode<'cd, A>;
+
+/// A function structured region by region, see the [module docs](self).
+pub struct IncrementalCfg<'cd, A: AstContext> {
+    graph: StableDiGraph<CfgNode<'cd, A>, CfgEdge>,
+    entry: NodeIndex,
+    cctx: CondContext<'cd, A>,
+    /// `None` only while a region is being structured
+    actx: Option<A>,
+    value_sets: ValueSets<A>,
+    branch_weights: BranchWeights,
+    /// the nodes of `ControlFlowGraph::set_no_duplicate`
+    no_duplicate: NodeSet,
+    /// the options each region is structured with, but for the budget
+    opts: StructuringOptions,
+    /// the budget of each call to `restructure`, and what is left of it
+    budget: Option<Budget>,
+    budget_left: Option<BudgetLeft>,
+    /// the regions structured on their own, innermost first, so the root is
+    /// last
+    regions: Vec<StructuredRegion<'cd, A>>,
+    /// the nodes edited since the graph was last structured
+    changed: NodeSet,
+    /// the next label for the layout of a failed region that isn't a node
+    /// of the graph, such as its end; always past the nodes of the graph
+    next_label: usize,
+}
+
+struct StructuredRegion<'cd, A: AstContext> {
+    key: RegionKey,
+    ast: Rc<AstNode<'cd, A>>,
+    /// the regions and loops collapsed in it, or why it failed to
+    /// structure, in which case `ast` is its layout
+    outcome: Outcome,
+}
+
+type Outcome = Result<(usize, usize), StructureError>;
+
+/// What identifies a region across edits.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct RegionKey {
+    header: NodeIndex,
+    successor: Option<NodeIndex>,
+    /// all the nodes of the region, sorted
+    nodes: Vec<NodeIndex>,
+}
+
+impl<'cd, A> IncrementalCfg<'cd, A>
+where
+    A: AstContextMut + Clone,
+    A::Block: Clone,
+    A::Variable: Clone,
+{
+    /// Structures `cfg` region by region with `opts`. The budget is shared by
+    /// the regions structured in each call to
+    /// [`restructure`](Self::restructure), and once it runs out or the
+    /// cancellation token is cancelled, the regions left fail with
+    /// `BudgetExhausted` or `Cancelled`; they are structured again by the
+    /// next call. The context is cloned for each region, to go on with
+    /// should the region fail. All regions share one namer, so that none of
+    /// them reuses the name of a variable of another.
+    ///
+    /// # Panics
+    /// Panics if `cfg` has `Unwind` or `Abnormal` edges, which aren't
+    /// supported.
+    pub fn new(cfg: ControlFlowGraph<'cd, A>, opts: &StructuringOptions) -> Self {
+        assert!(
+            cfg.graph
+                .edge_references()
+                .all(|e| !e.weight().is_abnormal()),
+            "IncrementalCfg: `Unwind` and `Abnormal` edges aren't supported"
+        );
+        let mut ret = IncrementalCfg {
+            graph: cfg.graph,
+            entry: cfg.entry,
+            cctx: cfg.cctx,
+            actx: Some(cfg.actx),
+            value_sets: cfg.value_sets,
+            branch_weights: cfg.branch_weights,
+            no_duplicate: cfg.no_duplicate,
+            opts: StructuringOptions {
+                budget: None,
+                namer: Some(
+                    opts.namer
+                        .clone()
+                        .unwrap_or_else(|| Rc::new(RefCell::new(CounterNamer::default()))),
+                ),
+                ..opts.clone()
+            },
+            budget: opts.budget,
+            budget_left: None,
+            regions: Vec::new(),
+            changed: NodeSet::new(),
+            next_label: 0,
+        };
+        ret.restructure();
+        ret
+    }
+
+    /// The AST of the whole function, as of the last call to
+    /// [`restructure`](Self::restructure).
+    pub fn ast(&self) -> &AstNode<'cd, A> {
+        &self.regions.last().unwrap().ast
+    }
+
+    /// The AST of the region headed by `header`, if it was structured on its
+    /// own. It stays the same `Rc` for as long as the region doesn't need
+    /// to be re-structured.
+    pub fn region_ast(&self, header: NodeIndex) -> Option<&Rc<AstNode<'cd, A>>> {
+        self.regions
+            .iter()
+            .find(|r| r.key.header == header)
+            .map(|r| &r.ast)
+    }
+
+    /// The regions that failed to structure, as of the last call to
+    /// [`restructure`](Self::restructure), innermost first.
+    pub fn failed_regions(&self) -> Vec<FailedRegion> {
+        self.regions
+            .iter()
+            .filter_map(|r| match &r.outcome {
+                Ok(_) => None,
+                Err(err) => Some(FailedRegion {
+                    header: r.key.header,
+                    nodes: r.key.nodes.clone(),
+                    error: err.clone(),
+                }),
+            })
+            .collect()
+    }
+
+    pub fn graph(&self) -> &StableDiGraph<CfgNode<'cd, A>, CfgEdge> {
+        &self.graph
+    }
+
+    pub fn context(&self) -> &A {
+        self.actx.as_ref().unwrap()
+    }
+
+    /// Replaces node `n`, e.g. to change the condition of a branch.
+    pub fn set_node(&mut self, n: NodeIndex, node: CfgNode<'cd, A>) {
+        self.graph[n] = node;
+        self.changed.insert(n);
+    }
+
+    pub fn add_node(&mut self, node: CfgNode<'cd, A>) -> NodeIndex {
+        let n = self.graph.add_node(node);
+        self.changed.insert(n);
+        n
+    }
+
+    /// Removes node `n` and its edges.
+    pub fn remove_node(&mut self, n: NodeIndex) {
+        let neighbors: Vec<_> = self.graph.neighbors_undirected(n).collect();
+        self.changed.extend(neighbors);
+        self.graph.remove_node(n);
+        self.no_duplicate.remove(n);
+    }
+
+    /// # Panics
+    /// Panics if `edge` is `Unwind` or `Abnormal`.
+    pub fn add_edge(&mut self, from: NodeIndex, to: NodeIndex, edge: CfgEdge) -> EdgeIndex {
+        assert!(
+            !edge.is_abnormal(),
+            "IncrementalCfg: `Unwind` and `Abnormal` edges aren't supported"
+        );
+        self.changed.insert(from);
+        self.changed.insert(to);
+        self.graph.add_edge(from, to, edge)
+    }
+
+    pub fn remove_edge(&mut self, e: EdgeIndex) {
+        if let Some((from, to)) = self.graph.edge_endpoints(e) {
+            self.changed.insert(from);
+            self.changed.insert(to);
+            self.graph.remove_edge(e);
+        }
+    }
+
+    /// Structures the regions affected by the edits since the last call, and
+    /// returns how many regions that was. Once the edits are done, the graph
+    /// must satisfy the preconditions of [`ControlFlowGraph::new`]; the nodes
+    /// they left unreachable are ignored.
+    pub fn restructure(&mut self) -> usize {
+        self.budget_left = self.budget.map(|b| match b {
+            Budget::Steps(steps) => BudgetLeft::Steps(steps),
+            Budget::Time(time) => BudgetLeft::Until(Instant::now() + time),
+        });
+        let tree = {
+            let graph = self.graph.map(|_, _| (), |_, &e| e);
+            graph_utils::sese::region_tree(&graph, self.entry)
+        };
+
+        // the ASTs of the old regions without changes, but those left
+        // unstructured since the budget ran out or structuring was cancelled
+        let mut old_asts: HashMap<_, _> = {
+            let changed = &self.changed;
+            self.regions
+                .drain(..)
+                .filter(|r| {
+                    !matches!(
+                        r.outcome,
+                        Err(StructureError::BudgetExhausted) | Err(StructureError::Cancelled)
+                    )
+                })
+                .filter(|r| r.key.nodes.iter().all(|&n| !changed.contains(n)))
+                .map(|r| (r.key, (r.ast, r.outcome)))
+                .collect()
+        };
+        self.next_label = self.next_label.max(self.graph.node_bound());
+
+        let mut on_own = self.structured_on_own(&tree);
+        let mut asts = vec![None; tree.regions.len()];
+        let mut structured = 0;
+        for r in tree.postorder() {
+            if !on_own[r] {
+                continue;
+            }
+            let region = &tree.regions[r];
+            let mut nodes = tree.all_nodes(r);
+            nodes.sort();
+            let key = RegionKey {
+                header: region.header,
+                successor: region.successor,
+                nodes,
+            };
+            let (ast, outcome) = match old_asts.remove(&key) {
+                Some(old) => old,
+                None => {
+                    structured += 1;
+                    match self.structure_region(&tree, r, &on_own, &asts) {
+                        Some((ast, outcome)) => (Rc::new(ast), outcome),
+                        None => {
+                            on_own[r] = false;
+                            continue;
+                        }
+                    }
+                }
+            };
+            asts[r] = Some(ast.clone());
+            self.regions.push(StructuredRegion { key, ast, outcome });
+        }
+        self.changed.clear();
+        radeco_trace!(
+            "structure: incremental regions={} structured={}",
+            self.regions.len(),
+            structured
+        );
+        structured
+    }
+
+    /// Which regions of `tree` are structured on their own: the root, and
+    /// those with more than one node, unless that would split a switch like
+    /// in [`ControlFlowGraph::structure_acyclic_sese_regions`].
+    fn structured_on_own(&self, tree: &RegionTree) -> Vec<bool> {
+        tree.regions
+            .iter()
+            .enumerate()
+            .map(|(r, region)| {
+                r == 0
+                    || (region.nodes.len() + region.children.len() > 1
+                        && !continues_switch(&self.graph, &self.value_sets, region.header))
+            })
+            .collect()
+    }
+
+    /// Structures region `r`, whose descendants that are structured on their
+    /// own already are, with their ASTs in `asts`. Returns `None` if the AST
+    /// doesn't end by going on to the successor of the region, e.g. when a
+    /// loop inside it exits there from the middle of its body, in which case
+    /// the region must be structured as part of its parent. If structuring
+    /// fails, or isn't tried since the budget ran out or structuring was
+    /// cancelled, returns the layout of the region instead, with the error.
+    fn structure_region(
+        &mut self,
+        tree: &RegionTree,
+        r: usize,
+        on_own: &[bool],
+        asts: &[Option<Rc<AstNode<'cd, A>>>],
+    ) -> Option<(AstNode<'cd, A>, Outcome)> {
+        let actx = self.actx.take().unwrap();
+        let budget = match &self.opts.cancel {
+            Some(token) if token.is_cancelled() => Err(StructureError::Cancelled),
+            _ => self.region_budget(),
+        };
+        let err = match budget {
+            Err(err) => err,
+            Ok(budget) => {
+                let part = self.region_graph(tree, r, on_own, asts);
+                let cfg = self.region_cfg(part.graph, part.entry, part.no_duplicate, actx.clone());
+                cfg.check();
+                let opts = StructuringOptions {
+                    budget,
+                    ..self.opts.clone()
+                };
+                match cfg.structure_whole_checked(&opts) {
+                    Ok((_, _, report)) if report.budget_exhausted => {
+                        self.budget_left = Some(BudgetLeft::Steps(0));
+                        StructureError::BudgetExhausted
+                    }
+                    Ok((mut ast, actx, report)) => {
+                        self.actx = Some(actx);
+                        if let Some(BudgetLeft::Steps(steps)) = &mut self.budget_left {
+                            *steps = steps.saturating_sub(report.regions + report.loops);
+                        }
+                        return if remove_exits(&mut ast) {
+                            Some((ast, Ok((report.regions, report.loops))))
+                        } else {
+                            None
+                        };
+                    }
+                    Err(err) => err,
+                }
+            }
+        };
+        radeco_warn!(
+            "structure: region header={} failed, laying it out with gotos: {}",
+            tree.regions[r].header.index(),
+            err
+        );
+
+        // the target of each label: the nodes of the region are labeled with
+        // their own index
+        let part = self.region_graph(tree, r, on_own, asts);
+        let labels: HashMap<_, _> = part
+            .graph
+            .node_indices()
+            .map(|n| {
+                let label = match part.nodes.get(&n) {
+                    Some(&old) => LabelId(old.index()),
+                    None => {
+                        self.next_label += 1;
+                        LabelId(self.next_label - 1)
+                    }
+                };
+                (n, label)
+            })
+            .collect();
+        let mut cfg = self.region_cfg(part.graph, part.entry, part.no_duplicate, actx);
+        let ast = cfg.unstructured(part.exit, |n| labels[&n]);
+        self.actx = Some(cfg.actx);
+        // the end of the region is laid out last
+        let mut ast = ast.ok()?;
+        if remove_exits(&mut ast) {
+            Some((ast, Err(err)))
+        } else {
+            None
+        }
+    }
+
+    /// The budget to structure the next region with, or
+    /// `StructureError::BudgetExhausted` if none is left.
+    fn region_budget(&self) -> Result<Option<Budget>, StructureError> {
+        match self.budget_left {
+            None => Ok(None),
+            Some(BudgetLeft::Steps(0)) => Err(StructureError::BudgetExhausted),
+            Some(BudgetLeft::Steps(steps)) => Ok(Some(Budget::Steps(steps))),
+            Some(BudgetLeft::Until(deadline)) => {
+                let now = Instant::now();
+                if now < deadline {
+                    Ok(Some(Budget::Time(deadline - now)))
+                } else {
+                    Err(StructureError::BudgetExhausted)
+                }
+            }
+        }
+    }
+
+    /// The graph of region `r`, with the descendants that are structured on
+    /// their own collapsed into a node holding their AST, and an `EXIT`
+    /// label for its successor.
+    fn region_graph(
+        &self,
+        tree: &RegionTree,
+        r: usize,
+        on_own: &[bool],
+        asts: &[Option<Rc<AstNode<'cd, A>>>],
+    ) -> RegionGraph<'cd, A> {
+        let region = &tree.regions[r];
+        // the nodes left once the descendants structured on their own are
+        // collapsed, and those descendants
+        let mut own_nodes: Vec<NodeIndex> = Vec::new();
+        let mut collapsed = Vec::new();
+        let mut stack = vec![r];
+        while let Some(s) = stack.pop() {
+            own_nodes.extend(&tree.regions[s].nodes);
+            for &c in &tree.regions[s].children {
+                if on_own[c] {
+                    collapsed.push(c);
+                } else {
+                    stack.push(c);
+                }
+            }
+        }
+
+        let mut graph = StableDiGraph::new();
+        let mut old_new_map = HashMap::new();
+        let mut nodes = HashMap::new();
+        for &n in &own_nodes {
+            let mut node = self.graph[n].clone();
+            // the region goes on to its successor when it falls off its end,
+            // so the nodes ending the function must return
+            if region.successor.is_some() && is_sink(&self.graph, n) {
+                if let CfgNode::Code(ast) = &mut node {
+                    super::append_leaf(ast, super::ast::AstNode::Return);
+                }
+            }
+            let new = graph.add_node(node);
+            old_new_map.insert(n, new);
+            nodes.insert(new, n);
+        }
+        let mut no_duplicate: NodeSet = own_nodes
+            .iter()
+            .filter(|&&n| self.no_duplicate.contains(n))
+            .map(|n| old_new_map[n])
+            .collect();
+        for &c in &collapsed {
+            let ast = (**asts[c].as_ref().unwrap()).clone();
+            let new = graph.add_node(CfgNode::Code(ast));
+            old_new_map.insert(tree.regions[c].header, new);
+            // the AST holds the nodes of the descendants of `c` too
+            let mut stack = vec![c];
+            while let Some(d) = stack.pop() {
+                if tree.regions[d]
+                    .nodes
+                    .iter()
+                    .any(|&n| self.no_duplicate.contains(n))
+                {
+                    no_duplicate.insert(new);
+                }
+                stack.extend(&tree.regions[d].children);
+            }
+        }
+        let mut exit = None;
+        if let Some(succ) = region.successor {
+            let node = graph.add_node(CfgNode::Code(super::ast::AstNode::Label(EXIT)));
+            old_new_map.insert(succ, node);
+            exit = Some(node);
+        }
+
+        for &n in &own_nodes {
+            // `edges` lists the newest edge first; add them in the order they
+            // were added, so that the copy is structured like the original
+            let edges: Vec<_> = self.graph.edges(n).collect();
+            for e in edges.into_iter().rev() {
+                graph.add_edge(old_new_map[&n], old_new_map[&e.target()], *e.weight());
+            }
+        }
+        for &c in &collapsed {
+            let child = &tree.regions[c];
+            let succ = child.successor.expect("child region without a successor");
+            graph.add_edge(
+                old_new_map[&child.header],
+                old_new_map[&succ],
+                CfgEdge::True,
+            );
+        }
+
+        // a loop header isn't a source
+        let mut entry = old_new_map[&region.header];
+        if graph.neighbors_directed(entry, Incoming).next().is_some() {
+            let pre_entry = graph.add_node(empty_node());
+            graph.add_edge(pre_entry, entry, CfgEdge::True);
+            entry = pre_entry;
+        }
+        RegionGraph {
+            graph,
+            entry,
+            exit,
+            nodes,
+            no_duplicate,
+        }
+    }
+
+    fn region_cfg(
+        &self,
+        graph: StableDiGraph<CfgNode<'cd, A>, CfgEdge>,
+        entry: NodeIndex,
+        no_duplicate: NodeSet,
+        actx: A,
+    ) -> ControlFlowGraph<'cd, A> {
+        ControlFlowGraph {
+            graph,
+            entry,
+            cctx: self.cctx,
+            actx,
+            value_sets: self.value_sets.clone(),
+            branch_weights: self.branch_weights.clone(),
+            no_duplicate,
+            report: Default::default(),
+            trace: None,
+            observer: None,
+            namer: None,
+            cancel: None,
+            dominators: None,
+            budget: None,
+            dump: None,
+            decisions: Default::default(),
+            struct_vars: Vec::new(),
+        }
+    }
+
+    /// The AST of the whole function, and a report of the regions and loops
+    /// collapsed in all regions, the `Goto`s, their labels, the failed
+    /// regions and whether the budget ran out.
+    fn into_structured(self) -> (AstNode<'cd, A>, A, StructuringReport) {
+        let failed_regions = self.failed_regions();
+        let mut report = StructuringReport {
+            budget_exhausted: failed_regions
+                .iter()
+                .any(|f| f.error == StructureError::BudgetExhausted),
+            failed_regions,
+            ..Default::default()
+        };
+        for r in &self.regions {
+            if let Ok((regions, loops)) = r.outcome {
+                report.regions += regions;
+                report.loops += loops;
+            }
+        }
+        let ast = (*self.regions.last().unwrap().ast).clone();
+        report.gotos = count_gotos(&ast);
+        report.labels = naming::name_labels(self.opts.namer.as_ref().unwrap(), iter::once(&ast));
+        (ast, self.actx.unwrap(), report)
+    }
+}
+
+/// The graph of a region, see [`IncrementalCfg::region_graph`].
+struct RegionGraph<'cd, A: AstContext> {
+    graph: StableDiGraph<CfgNode<'cd, A>, CfgEdge>,
+    entry: NodeIndex,
+    /// the node for the successor of the region, if it has one
+    exit: Option<NodeIndex>,
+    /// the node of the whole graph each node of the region is a copy of,
+    /// leaving out those of the collapsed descendants, the exit and the
+    /// entry if it was added
+    nodes: HashMap<NodeIndex, NodeIndex>,
+    /// the nodes that must not be copied, those holding the AST of a
+    /// descendant with such a node included
+    no_duplicate: NodeSet,
+}
+
+impl<'cd, A> ControlFlowGraph<'cd, A>
+where
+    A: AstContextMut + Clone,
+    A::Block: Clone,
+    A::Variable: Clone,
+{
+    /// Structures the graph one canonical SESE region at a time, innermost
+    /// first, like an [`IncrementalCfg`] does, so that a region that fails
+    /// to structure only fails by itself: it is laid out as an
+    /// `Unstructured` region, and reported in
+    /// [`StructuringReport::failed_regions`], while the rest of the function
+    /// is structured as usual. The regions share the budget of `opts`, and
+    /// those left once it runs out are laid out the same way, failing with
+    /// [`StructureError::BudgetExhausted`]. The report only tells the regions
+    /// and loops collapsed, the `Goto`s, their labels, the failed regions and
+    /// whether the budget ran out.
+    ///
+    /// Fails with [`InputDefect::UnsupportedEdge`] if the graph has
+    /// `Unwind` or `Abnormal` edges, and with [`StructureError::Cancelled`]
+    /// once the cancellation token of `opts` is cancelled.
+    pub fn structure_by_region(
+        self,
+        opts: &StructuringOptions,
+    ) -> Result<(AstNode<'cd, A>, A, StructuringReport), StructureError> {
+        if let Some(e) = self
+            .graph
+            .edge_references()
+            .find(|e| e.weight().is_abnormal())
+        {
+            return Err(StructureError::Input(InputDefect::UnsupportedEdge {
+                from: e.source(),
+                to: e.target(),
+            }));
+        }
+        let inc = IncrementalCfg::new(self, opts);
+        if inc
+            .regions
+            .iter()
+            .any(|r| r.outcome == Err(StructureError::Cancelled))
+        {
+            return Err(StructureError::Cancelled);
+        }
+        Ok(inc.into_structured())
+    }
+}
+
+/// Marks where the graph of a region goes on to the successor of the region.
+const EXIT: LabelId = LabelId(usize::MAX);
+
+/// Removes the `EXIT` labels from `ast`, and returns whether all of them were
+/// in tail position, where going on means going on to the successor.
+fn remove_exits<B, C, V>(ast: &mut super::ast::AstNode<B, C, V>) -> bool {
+    use super::ast::AstNode::*;
+    match ast {
+        Label(l) if *l == EXIT => {
+            *ast = Seq(Vec::new());
+            true
+        }
+        Seq(seq) => {
+            let ok = match seq.split_last_mut() {
+                Some((last, init)) => !init.iter().any(contains_exit) && remove_exits(last),
+                None => true,
+            };
+            seq.retain(|n| !matches!(n, Seq(s) if s.is_empty()));
+            ok
+        }
+        Cond(_, t, e) => remove_exits(t) & e.as_mut().map_or(true, |e| remove_exits(e)),
+        Switch(_, cases, default) => cases
+            .iter_mut()
+            .fold(remove_exits(default), |ok, (_, case)| {
+                remove_exits(case) & ok
+            }),
+        Try(body, _) => remove_exits(body),
+        Unstructured(members) => match members.split_last_mut() {
+            Some(((_, last), init)) => {
+                !init.iter().any(|(_, a)| contains_exit(a)) && remove_exits(last)
+            }
+            None => true,
+        },
+        Loop(_, body) | For(_, _, _, body) => !contains_exit(body),
+        _ => true,
+    }
+}
+
+fn contains_exit<B, C, V>(ast: &super::ast::AstNode<B, C, V>) -> bool {
+    use super::ast::AstNode::*;
+    match ast {
+        Label(l) => *l == EXIT,
+        Seq(seq) => seq.iter().any(contains_exit),
+        Cond(_, t, e) => contains_exit(t) || e.as_ref().map_or(false, |e| contains_exit(e)),
+        Switch(_, cases, default) => {
+            contains_exit(default) || cases.iter().any(|(_, case)| contains_exit(case))
+        }
+        Loop(_, body) | For(_, _, _, body) | Try(body, _) => contains_exit(body),
+        Unstructured(members) => members.iter().any(|(_, a)| contains_exit(a)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::ast;
+    use super::super::condition;
+    use super::super::decisions;
+    use super::super::from_r2::{
+        self, Block, CondExpr, R2AstContext, R2BasicBlock, R2Code, R2Cond,
+    };
+    use super::super::roundtrip;
+    use super::super::trace::{TraceOp, TraceSink, TraceStep};
+    use super::super::CancelToken;
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::mem;
+
+    fn block(addr: u64, jump: Option<u64>, fail: Option<u64>) -> R2BasicBlock {
+        R2BasicBlock {
+            addr,
+            size: 4,
+            jump,
+            fail,
+            cases: Vec::new(),
+            default: None,
+            unresolved_jump: false,
+            esil: Vec::new(),
+        }
+    }
+
+    /// Two loops one after the other, each with an `if` in it.
+    fn two_loops() -> Vec<R2BasicBlock> {
+        vec![
+            block(0x10, Some(0x20), None),
+            block(0x20, Some(0x28), Some(0x24)),
+            block(0x24, Some(0x28), None),
+            block(0x28, Some(0x20), Some(0x30)),
+            block(0x30, Some(0x40), None),
+            block(0x40, Some(0x48), Some(0x44)),
+            block(0x44, Some(0x48), None),
+            block(0x48, Some(0x40), Some(0x50)),
+            block(0x50, None, None),
+        ]
+    }
+
+    fn code_node<'cd>(inc: &IncrementalCfg<'cd, R2AstContext>, addr: u64) -> NodeIndex {
+        inc.graph()
+            .node_indices()
+            .find(|&n| match &inc.graph()[n] {
+                CfgNode::Code(ast::AstNode::BasicBlock(Block::Code(R2Code::Block {
+                    addr: a,
+                    ..
+                }))) => *a == addr,
+                _ => false,
+            })
+            .unwrap()
+    }
+
+    fn check(inc: &IncrementalCfg<R2AstContext>, blocks: &[R2BasicBlock]) {
+        check_ast(inc.ast(), inc.context(), blocks);
+    }
+
+    fn check_ast(ast: &AstNode<R2AstContext>, actx: &R2AstContext, blocks: &[R2BasicBlock]) {
+        let sf = from_r2::StructuredFunction::new(ast.clone(), actx);
+        if let Err(err) = roundtrip::check(blocks, &sf) {
+            panic!("{}\nast: {:#?}", err, sf.ast);
+        }
+    }
+
+    #[test]
+    fn edit_inner_condition() {
+        let mut blocks = two_loops();
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let mut inc = IncrementalCfg::new(cfg, &StructuringOptions::default());
+        check(&inc, &blocks);
+
+        let loop1 = code_node(&inc, 0x20);
+        let loop2 = code_node(&inc, 0x40);
+        let loop1_ast = inc.region_ast(loop1).unwrap().clone();
+        let loop2_ast = inc.region_ast(loop2).unwrap().clone();
+        assert_eq!(inc.restructure(), 0);
+        assert!(Rc::ptr_eq(inc.region_ast(loop2).unwrap(), &loop2_ast));
+
+        // negate the branch at 0x40, inside the second loop
+        let branch = inc.graph().neighbors(loop2).next().unwrap();
+        let negated = CondExpr::Not(Box::new(CondExpr::Pred(R2Cond::Taken(0x40))));
+        inc.set_node(branch, CfgNode::Condition(cstore.cctx().new_var(negated)));
+        // the `if` in the second loop, the loop, and the whole function
+        assert_eq!(inc.restructure(), 3);
+        assert!(Rc::ptr_eq(inc.region_ast(loop1).unwrap(), &loop1_ast));
+        assert!(!Rc::ptr_eq(inc.region_ast(loop2).unwrap(), &loop2_ast));
+        blocks[5].jump = Some(0x44);
+        blocks[5].fail = Some(0x48);
+        check(&inc, &blocks);
+    }
+
+    #[test]
+    fn edit_across_regions() {
+        let mut blocks = two_loops();
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let mut inc = IncrementalCfg::new(cfg, &StructuringOptions::default());
+
+        // jump from the `if` in the first loop into the second one
+        let from = code_node(&inc, 0x24);
+        let old_edge = inc.graph().edges(from).next().unwrap().id();
+        inc.remove_edge(old_edge);
+        let to = code_node(&inc, 0x44);
+        inc.add_edge(from, to, CfgEdge::True);
+        // the loops are no longer regions of their own, so nothing cached
+        // can be reused as is
+        assert!(inc.restructure() > 0);
+        assert!(inc.region_ast(from).is_none());
+        blocks[2].jump = Some(0x44);
+        check(&inc, &blocks);
+    }
+
+    /// Makes structuring panic when it first collapses a loop.
+    #[derive(Debug, Default)]
+    struct FailFirstLoop {
+        failed: bool,
+    }
+
+    impl TraceSink for FailFirstLoop {
+        fn step(&mut self, step: &TraceStep) {
+            if step.op == TraceOp::LoopCollapse && !mem::replace(&mut self.failed, true) {
+                panic!("injected failure");
+            }
+        }
+    }
+
+    fn fail_first_loop() -> StructuringOptions {
+        StructuringOptions::default().trace(Rc::new(RefCell::new(FailFirstLoop::default())))
+    }
+
+    #[test]
+    fn failed_region() {
+        let blocks = two_loops();
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let mut inc = IncrementalCfg::new(cfg, &fail_first_loop());
+        // the layout of the failed loop still runs like it
+        check(&inc, &blocks);
+
+        let loop1 = code_node(&inc, 0x20);
+        let loop2 = code_node(&inc, 0x40);
+        let failed = inc.failed_regions();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].header, loop1);
+        assert!(failed[0].nodes.contains(&code_node(&inc, 0x28)));
+        assert!(failed[0].error.to_string().contains("injected failure"));
+        // each node of the failed loop is labeled with its own index, and the
+        // regions around it are structured
+        let layout = inc.region_ast(loop1).unwrap();
+        assert_eq!(layout.metrics().loops, 0);
+        assert!(matches!(&**layout, ast::AstNode::Unstructured(members)
+            if members.iter().any(|&(l, _)| l == LabelId(loop1.index()))));
+        assert_eq!(inc.region_ast(loop2).unwrap().metrics().loops, 1);
+        let metrics = inc.ast().metrics();
+        assert_eq!(metrics.loops, 1);
+        assert!(metrics.gotos > 0);
+        // the failure is kept with the region
+        assert_eq!(inc.restructure(), 0);
+        assert_eq!(inc.failed_regions(), failed);
+
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let (ast, _, report) = cfg.structure_by_region(&fail_first_loop()).unwrap();
+        assert_eq!(ast.metrics(), metrics);
+        assert_eq!(report.failed_regions, failed);
+        assert_eq!((report.loops, report.gotos), (1, metrics.gotos));
+        #[cfg(feature = "serde")]
+        assert!(serde_json::to_string(&report).unwrap().contains(&format!(
+            "\"failed_regions\":[{{\"header\":{},",
+            loop1.index()
+        )));
+
+        // without the failure, nothing fails
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let (ast, _, report) = cfg
+            .structure_by_region(&StructuringOptions::default())
+            .unwrap();
+        assert!(report.failed_regions.is_empty());
+        assert_eq!((ast.metrics().loops, report.loops, report.gotos), (2, 2, 0));
+    }
+
+    #[test]
+    fn region_error() {
+        let blocks = two_loops();
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        // the `if`s make no progress without acyclic collapses, which fails
+        // them without a panic
+        let opts = StructuringOptions {
+            disabled_collapse: Some(TraceOp::AcyclicCollapse),
+            ..Default::default()
+        };
+        let (ast, actx, report) = cfg.structure_by_region(&opts).unwrap();
+        check_ast(&ast, &actx, &blocks);
+        assert!(!report.failed_regions.is_empty());
+        for failed in &report.failed_regions {
+            assert!(
+                matches!(failed.error, StructureError::NoProgress { .. }),
+                "{}",
+                failed.error
+            );
+        }
+        assert!(!report.budget_exhausted);
+        assert!(decisions::preorder(&ast)
+            .iter()
+            .any(|a| matches!(a, ast::AstNode::Unstructured(_))));
+        assert!(ast.metrics().gotos > 0);
+    }
+
+    #[test]
+    fn budget_exhausted() {
+        let blocks = two_loops();
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let opts = StructuringOptions::default().budget(Budget::Steps(2));
+        let mut inc = IncrementalCfg::new(cfg, &opts);
+        check(&inc, &blocks);
+        // the `if` in the first loop takes both steps, and the rest is laid
+        // out
+        let failed = inc.failed_regions();
+        assert!(!failed.is_empty());
+        assert!(failed
+            .iter()
+            .all(|f| f.error == StructureError::BudgetExhausted));
+        assert_eq!(failed.last().unwrap().header, inc.entry);
+        assert!(matches!(inc.ast(), ast::AstNode::Unstructured(_)));
+        // each call has a budget of its own, and tries the regions left again
+        let left = failed.len();
+        assert_eq!(inc.restructure(), left);
+        assert_eq!(inc.failed_regions()[..], failed[1..]);
+        check(&inc, &blocks);
+
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let (_, _, report) = cfg.structure_by_region(&opts).unwrap();
+        assert!(report.budget_exhausted);
+        assert_eq!(report.failed_regions.len(), left);
+        assert_eq!((report.regions, report.loops), (2, 0));
+    }
+
+    #[test]
+    fn by_region_errors() {
+        let blocks = two_loops();
+        let cstore = condition::Storage::new();
+        let token = CancelToken::new();
+        token.cancel();
+        let cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let res = cfg.structure_by_region(&StructuringOptions::default().cancel(token));
+        assert_eq!(res.map(|r| r.2).unwrap_err(), StructureError::Cancelled);
+
+        let mut cfg = from_r2::import(cstore.cctx(), &blocks).unwrap();
+        let (from, to) = (NodeIndex::new(1), NodeIndex::new(4));
+        cfg.graph.add_edge(from, to, CfgEdge::Unwind);
+        let res = cfg.structure_by_region(&StructuringOptions::default());
+        assert_eq!(
+            res.map(|r| r.2).unwrap_err(),
+            StructureError::Input(InputDefect::UnsupportedEdge { from, to })
+        );
+    }
+}
@@ -0,0 +1,565 @@
+//! Simplifies loops with what their bodies never change, see
+//! [`simplify_guards`] and [`unrotate_loops`].
+
+use super::ast::{AstNode, LoopType};
+use super::condition::{Condition, Context};
+
+/// Simplifies the conditions of and in loops using the terms of their guards
+/// that the loops never change. `modifies(block, cond)` tells whether
+/// running `block` may change the value of the condition variable `cond`.
+///
+/// A conjunct `a` of the guard of `while (a && b) { ... }` that no block of
+/// the body modifies holds everywhere in the body, so it is removed from the
+/// conditions there: `if (a && c)` becomes `if (c)`. The same goes for the
+/// condition of a `For`, as long as its update doesn't modify it either.
+///
+/// Conversely, the invariant conjuncts of a test that breaks out of the
+/// loop first thing are moved into the guard: `while (a) { if (!b || c)
+/// break; ... }` becomes `while (a && b) { if (c) break; ... }`. Tests
+/// further down the body stay where they are, since the code before them
+/// would run once more than in the original when they fail.
+///
+/// This is only as good as `modifies`. With a conservative one, such as
+/// [`from_r2::may_modify`](super::from_r2::may_modify), it only fires on
+/// terms that no block of the loop touches syntactically, e.g. about a jump
+/// outside of the loop or a variable that the loop doesn't assign to.
+pub fn simplify_guards<'cd, B, T, V, F>(
+    cctx: Context<'cd, T>,
+    ast: AstNode<B, Condition<'cd, T>, V>,
+    modifies: &mut F,
+) -> AstNode<B, Condition<'cd, T>, V>
+where
+    F: FnMut(&B, &T) -> bool,
+{
+    Simplifier { cctx, modifies }.go(ast, &[])
+}
+
+/// Undoes loop rotation: turns each `if (c) { do { ... } while (d); }` into
+/// `while (c) { ... }`. Compilers rotate `while` loops this way, so that
+/// the test is at the bottom and a copy of it runs before the loop.
+///
+/// This only holds if `d` is `c` whenever it is tested. That is the case if
+/// they are the same, or the same once the conjuncts of `c` that the body never
+/// changes, which hold throughout since they held on entry, are taken out of
+/// both: `if (a && b) { do { ... } while (b); }` becomes `while (a && b) {
+/// ... }` if the body doesn't modify `a`. `modifies` is as for
+/// [`simplify_guards`]; with `|_, _| true`, the tests must be the same.
+pub fn unrotate_loops<'cd, B, T, V, F>(
+    cctx: Context<'cd, T>,
+    ast: AstNode<B, Condition<'cd, T>, V>,
+    modifies: &mut F,
+) -> AstNode<B, Condition<'cd, T>, V>
+where
+    F: FnMut(&B, &T) -> bool,
+{
+    unrotate(cctx, ast, modifies, &mut Vec::new())
+}
+
+/// Like [`unrotate_loops`], pushing the guard of each `while` loop it makes
+/// onto `guards`.
+pub(super) fn unrotate<'cd, B, T, V, F>(
+    cctx: Context<'cd, T>,
+    ast: AstNode<B, Condition<'cd, T>, V>,
+    modifies: &mut F,
+    guards: &mut Vec<Condition<'cd, T>>,
+) -> AstNode<B, Condition<'cd, T>, V>
+where
+    F: FnMut(&B, &T) -> bool,
+{
+    Simplifier { cctx, modifies }.unrotate(ast, guards)
+}
+
+struct Simplifier<'cd, 'f, T: 'cd, F> {
+    cctx: Context<'cd, T>,
+    modifies: &'f mut F,
+}
+
+impl<'cd, 'f, T, F> Simplifier<'cd, 'f, T, F> {
+    /// Simplifies `ast`, knowing that the conditions in `known` hold.
+    fn go<B, V>(
+        &mut self,
+        ast: AstNode<B, Condition<'cd, T>, V>,
+        known: &[Condition<'cd, T>],
+    ) -> AstNode<B, Condition<'cd, T>, V>
+    where
+        F: FnMut(&B, &T) -> bool,
+    {
+        use self::AstNode::*;
+        match ast {
+            Seq(seq) => Seq(seq.into_iter().map(|a| self.go(a, known)).collect()),
+            Cond(c, t, oe) => {
+                let c = self.strip(c, known);
+                if c.is_true() {
+                    self.go(*t, known)
+                } else if c.is_false() {
+                    oe.map_or_else(AstNode::default, |e| self.go(*e, known))
+                } else {
+                    let t = Box::new(self.go(*t, known));
+                    Cond(c, t, oe.map(|e| Box::new(self.go(*e, known))))
+                }
+            }
+            Loop(LoopType::PreChecked(g), body) => {
+                let g = self.strip(g, known);
+                let (g, body) = self.hoist(g, *body);
+                if g.is_true() {
+                    return Loop(LoopType::Endless, Box::new(self.go(body, known)));
+                }
+                let mut blocks = Vec::new();
+                blocks_in(&body, &mut blocks);
+                let known = self.extend(known, g, &blocks);
+                Loop(LoopType::PreChecked(g), Box::new(self.go(body, &known)))
+            }
+            Loop(LoopType::PostChecked(g), body) => {
+                // the guard doesn't hold on the first iteration
+                let g = self.strip(g, known);
+                Loop(LoopType::PostChecked(g), Box::new(self.go(*body, known)))
+            }
+            Loop(LoopType::Endless, body) => {
+                Loop(LoopType::Endless, Box::new(self.go(*body, known)))
+            }
+            For(i, c, u, body) => {
+                let c = self.strip(c, known);
+                let mut blocks = vec![&u];
+                blocks_in(&body, &mut blocks);
+                let inner = self.extend(known, c, &blocks);
+                For(i, c, u, Box::new(self.go(*body, &inner)))
+            }
+            Switch(v, cases, default) => Switch(
+                v,
+                cases
+                    .into_iter()
+                    .map(|(vs, a)| (vs, self.go(a, known)))
+                    .collect(),
+                Box::new(self.go(*default, known)),
+            ),
+            Try(b, h) => Try(Box::new(self.go(*b, known)), h),
+            Unstructured(members) => Unstructured(
+                members
+                    .into_iter()
+                    .map(|(l, a)| (l, self.go(a, known)))
+                    .collect(),
+            ),
+            ast @ BasicBlock(_)
+            | ast @ Break
+            | ast @ Continue
+            | ast @ Return
+            | ast @ TailCall(_)
+            | ast @ IndirectJump(_)
+            | ast @ Goto(_)
+            | ast @ Label(_) => ast,
+        }
+    }
+
+    fn unrotate<B, V>(
+        &mut self,
+        ast: AstNode<B, Condition<'cd, T>, V>,
+        guards: &mut Vec<Condition<'cd, T>>,
+    ) -> AstNode<B, Condition<'cd, T>, V>
+    where
+        F: FnMut(&B, &T) -> bool,
+    {
+        use self::AstNode::*;
+        match ast {
+            Seq(seq) => Seq(seq.into_iter().map(|a| self.unrotate(a, guards)).collect()),
+            Cond(c, t, None) => match self.unrotate(*t, guards) {
+                Loop(LoopType::PostChecked(d), body) => {
+                    let mut blocks = Vec::new();
+                    blocks_in(&body, &mut blocks);
+                    let known = self.extend(&[], c, &blocks);
+                    if d == c || self.strip(d, &known) == self.strip(c, &known) {
+                        guards.push(c);
+                        Loop(LoopType::PreChecked(c), body)
+                    } else {
+                        Cond(c, Box::new(Loop(LoopType::PostChecked(d), body)), None)
+                    }
+                }
+                t => Cond(c, Box::new(t), None),
+            },
+            Cond(c, t, Some(e)) => {
+                let t = Box::new(self.unrotate(*t, guards));
+                Cond(c, t, Some(Box::new(self.unrotate(*e, guards))))
+            }
+            Loop(lt, b) => Loop(lt, Box::new(self.unrotate(*b, guards))),
+            For(i, c, u, b) => For(i, c, u, Box::new(self.unrotate(*b, guards))),
+            Switch(v, cases, default) => Switch(
+                v,
+                cases
+                    .into_iter()
+                    .map(|(vs, a)| (vs, self.unrotate(a, guards)))
+                    .collect(),
+                Box::new(self.unrotate(*default, guards)),
+            ),
+            Try(b, h) => Try(Box::new(self.unrotate(*b, guards)), h),
+            Unstructured(members) => Unstructured(
+                members
+                    .into_iter()
+                    .map(|(l, a)| (l, self.unrotate(a, guards)))
+                    .collect(),
+            ),
+            ast @ BasicBlock(_)
+            | ast @ Break
+            | ast @ Continue
+            | ast @ Return
+            | ast @ TailCall(_)
+            | ast @ IndirectJump(_)
+            | ast @ Goto(_)
+            | ast @ Label(_) => ast,
+        }
+    }
+
+    /// Removes the conjuncts of `cond` that are known to hold.
+    fn strip(&self, cond: Condition<'cd, T>, known: &[Condition<'cd, T>]) -> Condition<'cd, T> {
+        let conjuncts = cond.conjuncts();
+        if conjuncts
+            .iter()
+            .any(|&c| known.contains(&self.cctx.mk_not(c)))
+        {
+            return self.cctx.mk_false();
+        }
+        self.cctx
+            .mk_and_from_iter(conjuncts.into_iter().filter(|c| !known.contains(c)))
+    }
+
+    /// Moves the invariant conjuncts of a test at the start of `body` that
+    /// breaks out of the loop into the guard `g`.
+    fn hoist<B, V>(
+        &mut self,
+        g: Condition<'cd, T>,
+        body: AstNode<B, Condition<'cd, T>, V>,
+    ) -> (Condition<'cd, T>, AstNode<B, Condition<'cd, T>, V>)
+    where
+        F: FnMut(&B, &T) -> bool,
+    {
+        use self::AstNode::*;
+        let (test, rest) = match body {
+            Cond(c, box Break, None) => (c, Vec::new()),
+            Seq(mut seq) => match seq.first() {
+                Some(&Cond(c, box Break, None)) => {
+                    seq.remove(0);
+                    (c, seq)
+                }
+                _ => return (g, Seq(seq)),
+            },
+            body => return (g, body),
+        };
+        let mut blocks = Vec::new();
+        for a in &rest {
+            blocks_in(a, &mut blocks);
+        }
+        // the loop goes on while `stay` holds
+        let (moved, stay): (Vec<_>, Vec<_>) = self
+            .cctx
+            .mk_not(test)
+            .conjuncts()
+            .into_iter()
+            .partition(|&c| self.invariant(c, &blocks));
+        let g = self
+            .cctx
+            .mk_and_from_iter(g.conjuncts().into_iter().chain(moved));
+        let stay = self.cctx.mk_and_from_iter(stay);
+        let mut seq = rest;
+        if !stay.is_true() {
+            seq.insert(0, Cond(self.cctx.mk_not(stay), Box::new(Break), None));
+        }
+        let body = if seq.len() == 1 {
+            seq.pop().unwrap()
+        } else {
+            Seq(seq)
+        };
+        (g, body)
+    }
+
+    /// `known` and the conjuncts of `cond` that none of `blocks` modifies.
+    fn extend<B>(
+        &mut self,
+        known: &[Condition<'cd, T>],
+        cond: Condition<'cd, T>,
+        blocks: &[&B],
+    ) -> Vec<Condition<'cd, T>>
+    where
+        F: FnMut(&B, &T) -> bool,
+    {
+        let mut known = known.to_vec();
+        for c in cond.conjuncts() {
+            if self.invariant(c, blocks) {
+                known.push(c);
+            }
+        }
+        known
+    }
+
+    /// Whether none of `blocks` modifies `cond`.
+    fn invariant<B>(&mut self, cond: Condition<'cd, T>, blocks: &[&B]) -> bool
+    where
+        F: FnMut(&B, &T) -> bool,
+    {
+        let vars = cond.vars();
+        let modifies = &mut *self.modifies;
+        blocks
+            .iter()
+            .all(|b| vars.iter().all(|v| !modifies(b, &**v)))
+    }
+}
+
+/// The blocks in `ast` that run before the loop it is in goes around again.
+/// Those of `TailCall`s and `IndirectJump`s don't, since they leave the
+/// function.
+pub(super) fn blocks_in<'a, B, C, V>(ast: &'a AstNode<B, C, V>, out: &mut Vec<&'a B>) {
+    use self::AstNode::*;
+    match ast {
+        BasicBlock(b) => out.push(b),
+        Seq(seq) => {
+            for a in seq {
+                blocks_in(a, out);
+            }
+        }
+        Cond(_, t, oe) => {
+            blocks_in(t, out);
+            if let Some(e) = oe {
+                blocks_in(e, out);
+            }
+        }
+        Loop(_, b) | Try(b, _) => blocks_in(b, out),
+        For(i, _, u, b) => {
+            out.push(i);
+            blocks_in(b, out);
+            out.push(u);
+        }
+        Switch(_, cases, default) => {
+            for (_, a) in cases {
+                blocks_in(a, out);
+            }
+            blocks_in(default, out);
+        }
+        Unstructured(members) => {
+            for (_, a) in members {
+                blocks_in(a, out);
+            }
+        }
+        Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::ast::LoopType::*;
+    use crate::backend::ctrl_flow_struct::condition::Storage;
+
+    type Ast<'cd> = AstNode<&'static str, Condition<'cd, &'static str>, ()>;
+
+    /// `block` assigns to `var`, e.g. `a = 0` to `a`.
+    fn modifies(block: &&str, var: &&str) -> bool {
+        block.starts_with(&format!("{} = ", var))
+    }
+
+    #[test]
+    fn strip_invariant_terms() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let a = cctx.mk_var(cctx.new_var("a"));
+        let b = cctx.mk_var(cctx.new_var("b"));
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // while (a && b) { if (a && c) f; b = 0; }
+        let body = |cond| -> Ast {
+            Seq(vec![
+                Cond(cond, Box::new(BasicBlock("f")), None),
+                BasicBlock("b = 0"),
+            ])
+        };
+        let guard = cctx.mk_and(a, b);
+        let ast = Loop(PreChecked(guard), Box::new(body(cctx.mk_and(a, c))));
+        assert_eq!(
+            simplify_guards(cctx, ast, &mut modifies),
+            Loop(PreChecked(guard), Box::new(body(c)))
+        );
+
+        // `b` changes, so it can't be stripped
+        let ast = Loop(PreChecked(guard), Box::new(body(cctx.mk_and(b, c))));
+        assert_eq!(simplify_guards(cctx, ast.clone(), &mut modifies), ast);
+
+        // nor can the guard of a do-while, which doesn't hold at first
+        let ast = Loop(PostChecked(guard), Box::new(body(cctx.mk_and(a, c))));
+        assert_eq!(simplify_guards(cctx, ast.clone(), &mut modifies), ast);
+
+        // a repeated invariant guard goes away altogether, also in a nested
+        // loop
+        let ast: Ast = Loop(
+            PreChecked(a),
+            Box::new(Loop(
+                PreChecked(cctx.mk_and(a, c)),
+                Box::new(Cond(a, Box::new(BasicBlock("f")), None)),
+            )),
+        );
+        assert_eq!(
+            simplify_guards(cctx, ast, &mut modifies),
+            Loop(
+                PreChecked(a),
+                Box::new(Loop(PreChecked(c), Box::new(BasicBlock("f")))),
+            )
+        );
+    }
+
+    #[test]
+    fn strip_in_for() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let a = cctx.mk_var(cctx.new_var("a"));
+        let i = cctx.mk_var(cctx.new_var("i"));
+
+        // the update only changes `i`
+        let ast: Ast = For(
+            "i = 0",
+            cctx.mk_and(a, i),
+            "i = 1",
+            Box::new(Cond(
+                cctx.mk_and(a, i),
+                Box::new(BasicBlock("f")),
+                Some(Box::new(BasicBlock("g"))),
+            )),
+        );
+        assert_eq!(
+            simplify_guards(cctx, ast, &mut modifies),
+            For(
+                "i = 0",
+                cctx.mk_and(a, i),
+                "i = 1",
+                Box::new(Cond(
+                    i,
+                    Box::new(BasicBlock("f")),
+                    Some(Box::new(BasicBlock("g"))),
+                )),
+            )
+        );
+    }
+
+    #[test]
+    fn hoist_into_guard() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let a = cctx.mk_var(cctx.new_var("a"));
+        let b = cctx.mk_var(cctx.new_var("b"));
+        let c = cctx.mk_var(cctx.new_var("c"));
+        let not = |x| cctx.mk_not(x);
+
+        // while (a) { if (!b || c) break; f; if (a && b) g; c = 0; }
+        let ast: Ast = Loop(
+            PreChecked(a),
+            Box::new(Seq(vec![
+                Cond(cctx.mk_or(not(b), c), Box::new(Break), None),
+                BasicBlock("f"),
+                Cond(cctx.mk_and(a, b), Box::new(BasicBlock("g")), None),
+                BasicBlock("c = 0"),
+            ])),
+        );
+        // `b` moves into the guard, and then holds in the body too
+        assert_eq!(
+            simplify_guards(cctx, ast, &mut modifies),
+            Loop(
+                PreChecked(cctx.mk_and(a, b)),
+                Box::new(Seq(vec![
+                    Cond(c, Box::new(Break), None),
+                    BasicBlock("f"),
+                    BasicBlock("g"),
+                    BasicBlock("c = 0"),
+                ])),
+            )
+        );
+
+        // the test isn't the first thing in the body, so `f` would run one
+        // more time than it should
+        let ast: Ast = Loop(
+            PreChecked(a),
+            Box::new(Seq(vec![
+                BasicBlock("f"),
+                Cond(not(b), Box::new(Break), None),
+            ])),
+        );
+        assert_eq!(simplify_guards(cctx, ast.clone(), &mut modifies), ast);
+
+        // `b` changes in the body
+        let ast: Ast = Loop(
+            PreChecked(a),
+            Box::new(Seq(vec![
+                Cond(not(b), Box::new(Break), None),
+                BasicBlock("b = 0"),
+            ])),
+        );
+        assert_eq!(simplify_guards(cctx, ast.clone(), &mut modifies), ast);
+    }
+
+    #[test]
+    fn unrotate_same_test() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // s; if (c) { do { c = 0; } while (c); }
+        let body = || Box::new(BasicBlock("c = 0"));
+        let ast: Ast = Seq(vec![
+            BasicBlock("s"),
+            Cond(c, Box::new(Loop(PostChecked(c), body())), None),
+        ]);
+        assert_eq!(
+            unrotate_loops(cctx, ast, &mut |_, _| true),
+            Seq(vec![BasicBlock("s"), Loop(PreChecked(c), body())])
+        );
+    }
+
+    #[test]
+    fn unrotate_invariant_test() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let a = cctx.mk_var(cctx.new_var("a"));
+        let b = cctx.mk_var(cctx.new_var("b"));
+
+        // if (a && b) { do { b = 0; } while (b); }
+        let guard = cctx.mk_and(a, b);
+        let body = |blk| Box::new(BasicBlock(blk));
+        let ast: Ast = Cond(guard, Box::new(Loop(PostChecked(b), body("b = 0"))), None);
+        assert_eq!(
+            unrotate_loops(cctx, ast.clone(), &mut modifies),
+            Loop(PreChecked(guard), body("b = 0"))
+        );
+        // without knowing that `a` holds throughout, the tests differ
+        assert_eq!(unrotate_loops(cctx, ast.clone(), &mut |_, _| true), ast);
+
+        // the body changes `a`, so `b` alone decides whether it goes around
+        let ast: Ast = Cond(guard, Box::new(Loop(PostChecked(b), body("a = 0"))), None);
+        assert_eq!(unrotate_loops(cctx, ast.clone(), &mut modifies), ast);
+    }
+
+    #[test]
+    fn unrotate_other_test() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let c = cctx.mk_var(cctx.new_var("c"));
+        let d = cctx.mk_var(cctx.new_var("d"));
+
+        let ast: Ast = Cond(
+            c,
+            Box::new(Loop(PostChecked(d), Box::new(BasicBlock("f")))),
+            None,
+        );
+        assert_eq!(unrotate_loops(cctx, ast.clone(), &mut modifies), ast);
+
+        // nor is a loop with something else in the `if`, or an `else`
+        let ast: Ast = Cond(
+            c,
+            Box::new(Seq(vec![
+                BasicBlock("f"),
+                Loop(PostChecked(c), Box::new(BasicBlock("g"))),
+            ])),
+            None,
+        );
+        assert_eq!(unrotate_loops(cctx, ast.clone(), &mut modifies), ast);
+        let ast: Ast = Cond(
+            c,
+            Box::new(Loop(PostChecked(c), Box::new(BasicBlock("g")))),
+            Some(Box::new(BasicBlock("h"))),
+        );
+        assert_eq!(unrotate_loops(cctx, ast.clone(), &mut modifies), ast);
+    }
+}
@@ -0,0 +1,351 @@
+//! Sums up when the loops that stay endless stop, see [`summarize`].
+
+use super::ast::{AstNode, LoopType};
+use super::condition::{Condition, Context, Folder};
+use super::decisions::{self, AstNodeId};
+
+use std::fmt;
+use std::iter;
+
+/// When an `Endless` loop stops, as far as its `break`s tell.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LoopExit {
+    /// the guards of the `break`s, or-ed together, as a C expression; empty
+    /// if none of them could be described
+    pub when: String,
+    /// whether some of the `break`s are left out of `when`, since their
+    /// guards couldn't be described
+    pub partial: bool,
+}
+
+impl fmt::Display for LoopExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.when.is_empty(), self.partial) {
+            (true, _) => f.write_str("exits when: unknown"),
+            (false, false) => write!(f, "exits when: {}", self.when),
+            (false, true) => write!(f, "exits when: {} (partial)", self.when),
+        }
+    }
+}
+
+/// Sums up when each `Endless` loop in `ast` that has a `break` exits: the
+/// guard of each of its `break`s, i.e. the conditions of the `if`s it is in
+/// within the loop, or-ed together and simplified. The `break`s in nested
+/// loops leave those instead, so they aren't counted.
+///
+/// `describe` returns a C expression for a condition variable, or `None`
+/// if it can't describe it. A `break` with a guard it can't describe makes
+/// the summary partial, and so does one in a `Switch` case, whose values
+/// aren't a condition. Returns the summaries by the [`AstNodeId`] of their
+/// loop in `ast`.
+pub fn summarize<'cd, B, T, V, F>(
+    cctx: Context<'cd, T>,
+    ast: &AstNode<B, Condition<'cd, T>, V>,
+    describe: &mut F,
+) -> Vec<(AstNodeId, LoopExit)>
+where
+    F: FnMut(&T) -> Option<String>,
+{
+    let mut ret = Vec::new();
+    for (i, a) in decisions::preorder(ast).into_iter().enumerate() {
+        if let AstNode::Loop(LoopType::Endless, body) = a {
+            let mut guards = Vec::new();
+            let mut partial = false;
+            breaks(cctx, body, cctx.mk_true(), &mut guards, &mut partial);
+            if guards.is_empty() {
+                if partial {
+                    let when = String::new();
+                    ret.push((AstNodeId(i), LoopExit { when, partial }));
+                }
+                continue;
+            }
+            let when = cctx.mk_or_from_iter(guards);
+            let when = match when.fold(Describe {
+                describe: &mut *describe,
+                partial: &mut partial,
+            }) {
+                Some((when, _)) => when,
+                None => {
+                    partial = true;
+                    String::new()
+                }
+            };
+            ret.push((AstNodeId(i), LoopExit { when, partial }));
+        }
+    }
+    ret
+}
+
+/// Appends the guard of each `break` in `ast` that leaves the loop `ast` is
+/// the body of, or part of it, to `out`, given that `guard` holds on
+/// entering `ast`. Sets `partial` for those that have none.
+fn breaks<'cd, B, T, V>(
+    cctx: Context<'cd, T>,
+    ast: &AstNode<B, Condition<'cd, T>, V>,
+    guard: Condition<'cd, T>,
+    out: &mut Vec<Condition<'cd, T>>,
+    partial: &mut bool,
+) {
+    use self::AstNode::*;
+    match ast {
+        Break => out.push(guard),
+        Seq(seq) => {
+            for a in seq {
+                breaks(cctx, a, guard, out, partial);
+            }
+        }
+        Cond(c, t, oe) => {
+            breaks(cctx, t, cctx.mk_and(guard, *c), out, partial);
+            if let Some(e) = oe {
+                breaks(cctx, e, cctx.mk_and(guard, cctx.mk_not(*c)), out, partial);
+            }
+        }
+        Switch(_, cases, default) => {
+            let mut in_cases = Vec::new();
+            for a in cases.iter().map(|(_, a)| a).chain(iter::once(&**default)) {
+                breaks(cctx, a, guard, &mut in_cases, partial);
+            }
+            if !in_cases.is_empty() {
+                *partial = true;
+            }
+        }
+        Try(b, _) => breaks(cctx, b, guard, out, partial),
+        // the guard of a `break` in a member depends on how it was jumped to
+        Unstructured(members) => {
+            let mut inside = Vec::new();
+            for (_, a) in members {
+                breaks(cctx, a, guard, &mut inside, partial);
+            }
+            if !inside.is_empty() {
+                *partial = true;
+            }
+        }
+        Loop(..) | For(..) | BasicBlock(_) | Continue | Return | TailCall(_) | IndirectJump(_)
+        | Goto(_) | Label(_) => (),
+    }
+}
+
+/// How tightly a C expression binds, for whether it needs parentheses as
+/// an operand.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Prec {
+    Or,
+    And,
+    Atom,
+}
+
+/// Folds a condition into a C expression, leaving out the operands of `||`
+/// that can't be described.
+struct Describe<'a, F> {
+    describe: &'a mut F,
+    partial: &'a mut bool,
+}
+
+impl<'a, T, F: FnMut(&T) -> Option<String>> Folder<T> for Describe<'a, F> {
+    type Output = Option<(String, Prec)>;
+
+    fn var(&mut self, normal: bool, var: &T) -> Self::Output {
+        let text = (self.describe)(var)?;
+        Some(if normal {
+            (text, Prec::Atom)
+        } else if text.contains(' ') {
+            (format!("!({})", text), Prec::Atom)
+        } else {
+            (format!("!{}", text), Prec::Atom)
+        })
+    }
+
+    fn and<'c, I>(&mut self, operands: I) -> Self::Output
+    where
+        I: IntoIterator<Item = Condition<'c, T>>,
+        T: 'c,
+    {
+        let mut texts = Vec::new();
+        for c in operands {
+            // the whole conjunction is undescribed if any part of it is
+            let mut inner = false;
+            let (text, prec) = c.fold(Describe {
+                describe: &mut *self.describe,
+                partial: &mut inner,
+            })?;
+            *self.partial |= inner;
+            texts.push(if prec < Prec::And {
+                format!("({})", text)
+            } else {
+                text
+            });
+        }
+        if texts.is_empty() {
+            return Some(("1".to_owned(), Prec::Atom));
+        }
+        Some((texts.join(" && "), Prec::And))
+    }
+
+    fn or<'c, I>(&mut self, operands: I) -> Self::Output
+    where
+        I: IntoIterator<Item = Condition<'c, T>>,
+        T: 'c,
+    {
+        let mut texts = Vec::new();
+        let mut any = false;
+        for c in operands {
+            any = true;
+            match c.fold(Describe {
+                describe: &mut *self.describe,
+                partial: &mut *self.partial,
+            }) {
+                Some((text, _)) => texts.push(text),
+                None => *self.partial = true,
+            }
+        }
+        if !any {
+            return Some(("0".to_owned(), Prec::Atom));
+        }
+        if texts.is_empty() {
+            return None;
+        }
+        Some((texts.join(" || "), Prec::Or))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::ast::ValueSet;
+    use crate::backend::ctrl_flow_struct::condition::Storage;
+
+    type Ast<'cd> = AstNode<&'static str, Condition<'cd, &'static str>, &'static str>;
+
+    /// Describes the conditions but those starting with `?`.
+    fn describe(cond: &&str) -> Option<String> {
+        if cond.starts_with('?') {
+            None
+        } else {
+            Some(cond.to_string())
+        }
+    }
+
+    fn bb<'cd>(b: &'static str) -> Box<Ast<'cd>> {
+        Box::new(BasicBlock(b))
+    }
+
+    #[test]
+    fn two_breaks() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let a = cctx.mk_var(cctx.new_var("a < n"));
+        let b = cctx.mk_var(cctx.new_var("done"));
+        let c = cctx.mk_var(cctx.new_var("c"));
+
+        // for (;;) { f(); if (!(a < n)) break; if (c) { g(); } else if (done) break; }
+        let ast: Ast = Seq(vec![
+            BasicBlock("s"),
+            Loop(
+                LoopType::Endless,
+                Box::new(Seq(vec![
+                    BasicBlock("f()"),
+                    Cond(cctx.mk_not(a), Box::new(Break), None),
+                    Cond(c, bb("g()"), Some(Box::new(Cond(b, Box::new(Break), None)))),
+                ])),
+            ),
+        ]);
+        let exits = summarize(cctx, &ast, &mut describe);
+        assert_eq!(
+            exits,
+            vec![(
+                AstNodeId(2),
+                LoopExit {
+                    when: "!(a < n) || !c && done".to_owned(),
+                    partial: false,
+                }
+            )]
+        );
+        assert_eq!(exits[0].1.to_string(), "exits when: !(a < n) || !c && done");
+
+        // the same guard twice is simplified to one
+        let ast: Ast = Loop(
+            LoopType::Endless,
+            Box::new(Seq(vec![
+                Cond(c, Box::new(Break), None),
+                BasicBlock("f()"),
+                Cond(c, Box::new(Break), None),
+            ])),
+        );
+        assert_eq!(
+            summarize(cctx, &ast, &mut describe),
+            vec![(
+                AstNodeId(0),
+                LoopExit {
+                    when: "c".to_owned(),
+                    partial: false,
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn nested_loop() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let a = cctx.mk_var(cctx.new_var("a"));
+        let b = cctx.mk_var(cctx.new_var("b"));
+
+        // the `break` on `b` leaves the inner loop only
+        let inner: Ast = Loop(
+            LoopType::Endless,
+            Box::new(Seq(vec![BasicBlock("g()"), Cond(b, Box::new(Break), None)])),
+        );
+        let ast: Ast = Loop(
+            LoopType::Endless,
+            Box::new(Seq(vec![
+                inner,
+                Cond(a, Box::new(Break), None),
+                BasicBlock("f()"),
+            ])),
+        );
+        let exits = |when: &str| LoopExit {
+            when: when.to_owned(),
+            partial: false,
+        };
+        assert_eq!(
+            summarize(cctx, &ast, &mut describe),
+            vec![(AstNodeId(0), exits("a")), (AstNodeId(2), exits("b"))]
+        );
+
+        // a loop without `break`s doesn't exit
+        let ast: Ast = Loop(LoopType::Endless, bb("f()"));
+        assert_eq!(summarize(cctx, &ast, &mut describe), Vec::new());
+    }
+
+    #[test]
+    fn partial() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let a = cctx.mk_var(cctx.new_var("a"));
+        let u = cctx.mk_var(cctx.new_var("?u"));
+
+        let ast: Ast = Loop(
+            LoopType::Endless,
+            Box::new(Seq(vec![
+                Cond(a, Box::new(Break), None),
+                Cond(u, Box::new(Break), None),
+            ])),
+        );
+        let exits = summarize(cctx, &ast, &mut describe);
+        assert_eq!(exits[0].1.to_string(), "exits when: a (partial)");
+
+        // a `break` in a `switch` case
+        let ast: Ast = Loop(
+            LoopType::Endless,
+            Box::new(Switch(
+                "x",
+                vec![(ValueSet::single(1), Break)],
+                Box::new(Seq(Vec::new())),
+            )),
+        );
+        let exits = summarize(cctx, &ast, &mut describe);
+        assert_eq!(exits[0].1.to_string(), "exits when: unknown");
+    }
+}
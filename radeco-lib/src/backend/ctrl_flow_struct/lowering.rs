@@ -0,0 +1,401 @@
+//! Lowers an AST back into a graph that can be structured again, see
+//! [`ControlFlowGraph::from_ast`].
+
+use super::ast::{LabelId, LoopType, ValueSet};
+use super::ast_context::{AstContext, AstContextMut};
+use super::condition::{self, Folder};
+use super::{
+    cond_var_key, empty_node, internal, AstNodeC, CfgEdge, CfgNode, CondContext, CondVar,
+    ControlFlowGraph, NodeSet, StructureError,
+};
+
+use petgraph::prelude::*;
+use petgraph::visit::Walker;
+
+use std::collections::{HashMap, HashSet};
+
+type AstNode<'cd, A> = super::AstNode<'cd, A>;
+type Condition<'cd, A> = super::Condition<'cd, A>;
+
+/// The most values a case of a `Switch` can have to be lowered, since each
+/// is tested for on its own.
+const MAX_CASE_VALUES: u64 = 256;
+
+impl<'cd, A> ControlFlowGraph<'cd, A>
+where
+    A: AstContextMut,
+    A::Block: Clone,
+{
+    /// Lowers `ast`, as structured with `actx`, back into a graph of its
+    /// own, e.g. to structure it again with other options. Unlike
+    /// [`AstNode::to_cfg`](super::ast::AstNode::to_cfg), which only borrows
+    /// the AST to run it:
+    ///  - a `Seq` becomes a chain of its parts;
+    ///  - a `Cond` becomes a condition node for each variable in its
+    ///    condition, short-circuiting like `&&` and `||` do, which goes on
+    ///    to its branches, and these join where it goes on;
+    ///  - a loop gets an edge back to where it checks its condition, or to
+    ///    the start of its body if it has none;
+    ///  - a `Switch` becomes a test for each value of each case in turn,
+    ///    with the value of its variable as the value set of each test, so
+    ///    that switch recovery finds it again;
+    ///  - a `Break`, `Continue` or `Goto` becomes an edge to where it goes,
+    ///    and a `Return` one to an empty node that ends the graph.
+    ///
+    /// The blocks are copied into the graph, so whatever provenance they
+    /// carry, such as their addresses, carries over, and the condition
+    /// nodes test the same variables as `ast`, or, where it tests one more
+    /// than once, [copies](AstContextMut::clone_cond) of them if the context
+    /// can make them. The code that can't be
+    /// reached is left out, and so are the handlers of `Try`s. Fails on a
+    /// `Goto` without its `Label`, a `Break` or `Continue` outside of a
+    /// loop, and a case with more than 256 values.
+    pub fn from_ast(
+        ast: &AstNode<'cd, A>,
+        cctx: CondContext<'cd, A>,
+        actx: A,
+    ) -> Result<Self, StructureError> {
+        let mut graph = StableDiGraph::new();
+        let exit = graph.add_node(empty_node());
+        let mut lowerer = Lowerer {
+            graph,
+            cctx,
+            actx,
+            labels: HashMap::new(),
+            defined: HashSet::new(),
+            placeholders: Vec::new(),
+            value_sets: Vec::new(),
+            tested: HashSet::new(),
+        };
+        let entry = lowerer.lower(ast, exit, None)?;
+        if let Some(l) = lowerer.labels.keys().find(|l| !lowerer.defined.contains(l)) {
+            return Err(internal(
+                "from_ast",
+                format!("`goto` to the missing label {}", l.0),
+            ));
+        }
+        let Lowerer {
+            mut graph,
+            actx,
+            placeholders,
+            value_sets,
+            ..
+        } = lowerer;
+
+        let mut entry = contract(&mut graph, entry, &placeholders);
+        // the entry must be a source, e.g. not the header of a loop
+        if graph.neighbors_directed(entry, Incoming).next().is_some() {
+            let preheader = graph.add_node(empty_node());
+            graph.add_edge(preheader, entry, CfgEdge::True);
+            entry = preheader;
+        }
+        let reachable: NodeSet = Dfs::new(&graph, entry).iter(&graph).collect();
+        let unreachable: Vec<_> = graph
+            .node_indices()
+            .filter(|&n| !reachable.contains(n))
+            .collect();
+        for n in unreachable {
+            graph.remove_node(n);
+        }
+
+        let mut cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+        for (n, var, values) in value_sets {
+            if cfg.graph.contains_node(n) {
+                cfg.set_value_set(n, var, values);
+            }
+        }
+        Ok(cfg)
+    }
+}
+
+struct Lowerer<'cd, A: AstContext> {
+    graph: StableDiGraph<CfgNode<'cd, A>, CfgEdge>,
+    cctx: CondContext<'cd, A>,
+    actx: A,
+    /// the node of each label, in front of what follows it
+    labels: HashMap<LabelId, NodeIndex>,
+    /// the labels whose `Label` has been lowered
+    defined: HashSet<LabelId>,
+    /// the empty nodes standing in for a label or for where a loop starts
+    /// over, which is lowered after the edges to it
+    placeholders: Vec<NodeIndex>,
+    /// the value sets of the tests of `Switch`es
+    value_sets: Vec<(NodeIndex, A::Variable, ValueSet)>,
+    /// the variables that a condition node already tests, by
+    /// [`cond_var_key`]
+    tested: HashSet<usize>,
+}
+
+/// Where `break` and `continue` go in the innermost loop.
+#[derive(Copy, Clone)]
+struct LoopExits {
+    break_to: NodeIndex,
+    continue_to: NodeIndex,
+}
+
+impl<'cd, A> Lowerer<'cd, A>
+where
+    A: AstContextMut,
+    A::Block: Clone,
+{
+    /// Lowers `ast`, to continue at `next`, and returns its entry.
+    fn lower(
+        &mut self,
+        ast: &AstNode<'cd, A>,
+        next: NodeIndex,
+        exits: Option<LoopExits>,
+    ) -> Result<NodeIndex, StructureError> {
+        use self::AstNodeC::*;
+        let outside_loop = |what| internal("from_ast", format!("`{}` outside of a loop", what));
+        Ok(match ast {
+            BasicBlock(b) => self.code(BasicBlock(b.clone()), Some(next)),
+            Seq(seq) => {
+                let mut next = next;
+                for a in seq.iter().rev() {
+                    next = self.lower(a, next, exits)?;
+                }
+                next
+            }
+            Cond(c, t, oe) => {
+                let t = self.lower(t, next, exits)?;
+                let e = match oe {
+                    Some(e) => self.lower(e, next, exits)?,
+                    None => next,
+                };
+                self.cond(*c, t, e)
+            }
+            Loop(lt, body) => {
+                let header = self.placeholder();
+                let body_exits = LoopExits {
+                    break_to: next,
+                    continue_to: header,
+                };
+                let body = self.lower(body, header, Some(body_exits))?;
+                match lt {
+                    LoopType::PreChecked(c) => {
+                        let test = self.cond(*c, body, next);
+                        self.graph.add_edge(header, test, CfgEdge::True);
+                        header
+                    }
+                    LoopType::PostChecked(c) => {
+                        let test = self.cond(*c, body, next);
+                        self.graph.add_edge(header, test, CfgEdge::True);
+                        body
+                    }
+                    LoopType::Endless => {
+                        self.graph.add_edge(header, body, CfgEdge::True);
+                        header
+                    }
+                }
+            }
+            For(init, c, update, body) => {
+                let header = self.placeholder();
+                let update = self.code(BasicBlock(update.clone()), Some(header));
+                let body_exits = LoopExits {
+                    break_to: next,
+                    continue_to: update,
+                };
+                let body = self.lower(body, update, Some(body_exits))?;
+                let test = self.cond(*c, body, next);
+                self.graph.add_edge(header, test, CfgEdge::True);
+                self.code(BasicBlock(init.clone()), Some(header))
+            }
+            Break => exits.ok_or_else(|| outside_loop("break"))?.break_to,
+            Continue => exits.ok_or_else(|| outside_loop("continue"))?.continue_to,
+            Return => self.graph.add_node(empty_node()),
+            Switch(v, cases, default) => {
+                let mut els = self.lower(default, next, exits)?;
+                for (vs, a) in cases.iter().rev() {
+                    let then = self.lower(a, next, exits)?;
+                    if then == els {
+                        continue;
+                    }
+                    for &(lo, hi) in vs.ranges().iter().rev() {
+                        if hi - lo >= MAX_CASE_VALUES {
+                            return Err(internal(
+                                "from_ast",
+                                format!("a case with the values {} to {}", lo, hi),
+                            ));
+                        }
+                        for val in (lo..=hi).rev() {
+                            let cond = self.actx.mk_cond_equals(v, val);
+                            let test = self
+                                .graph
+                                .add_node(CfgNode::Condition(self.cctx.new_var(cond)));
+                            self.graph.add_edge(test, then, CfgEdge::True);
+                            self.graph.add_edge(test, els, CfgEdge::False);
+                            self.value_sets
+                                .push((test, v.clone(), ValueSet::single(val)));
+                            els = test;
+                        }
+                    }
+                }
+                els
+            }
+            TailCall(b) => self.code(TailCall(b.clone()), None),
+            IndirectJump(b) => self.code(IndirectJump(b.clone()), None),
+            Goto(l) => self.label(*l),
+            Label(l) => {
+                let n = self.label(*l);
+                if self.defined.insert(*l) {
+                    self.graph.add_edge(n, next, CfgEdge::True);
+                }
+                n
+            }
+            Try(b, _) => self.lower(b, next, exits)?,
+            Unstructured(members) => {
+                let mut next = next;
+                for (l, a) in members.iter().rev() {
+                    next = self.lower(a, next, exits)?;
+                    next = self.lower(&Label(*l), next, exits)?;
+                }
+                next
+            }
+        })
+    }
+
+    fn code(&mut self, ast: AstNode<'cd, A>, next: Option<NodeIndex>) -> NodeIndex {
+        let n = self.graph.add_node(CfgNode::Code(ast));
+        if let Some(next) = next {
+            self.graph.add_edge(n, next, CfgEdge::True);
+        }
+        n
+    }
+
+    /// The tests of `c`, going on to `then` if it holds and to `els`
+    /// otherwise. Conditions can always be evaluated, so there are none if
+    /// both are the same.
+    fn cond(&mut self, c: Condition<'cd, A>, then: NodeIndex, els: NodeIndex) -> NodeIndex {
+        if then == els {
+            return then;
+        }
+        let vars = c
+            .vars()
+            .into_iter()
+            .map(|v| (cond_var_key::<A>(v), v))
+            .collect();
+        c.fold(CondLowerer {
+            lowerer: self,
+            vars: &vars,
+            then,
+            els,
+        })
+    }
+
+    fn placeholder(&mut self) -> NodeIndex {
+        let n = self.graph.add_node(empty_node());
+        self.placeholders.push(n);
+        n
+    }
+
+    fn label(&mut self, l: LabelId) -> NodeIndex {
+        if let Some(&n) = self.labels.get(&l) {
+            return n;
+        }
+        let n = self.placeholder();
+        self.labels.insert(l, n);
+        n
+    }
+}
+
+/// Lowers a condition into tests of its variables, going on to `then` if it
+/// holds and to `els` otherwise.
+struct CondLowerer<'a, 'cd, A: AstContext> {
+    lowerer: &'a mut Lowerer<'cd, A>,
+    /// the variables of the condition, by [`cond_var_key`]
+    vars: &'a HashMap<usize, CondVar<'cd, A>>,
+    then: NodeIndex,
+    els: NodeIndex,
+}
+
+impl<'a, 'cd, A: AstContextMut> Folder<A::Condition> for CondLowerer<'a, 'cd, A> {
+    type Output = NodeIndex;
+
+    fn var(&mut self, normal: bool, var: &A::Condition) -> NodeIndex {
+        let key = var as *const A::Condition as usize;
+        let lowerer = &mut *self.lowerer;
+        // structuring takes each condition node to test a variable of its
+        // own, so the ones tested again are copied if they can be
+        let var = match lowerer.tested.insert(key) {
+            true => self.vars[&key],
+            false => match lowerer.actx.clone_cond(var) {
+                Some(copy) => lowerer.cctx.new_var(copy),
+                None => self.vars[&key],
+            },
+        };
+        let (t, f) = if normal {
+            (self.then, self.els)
+        } else {
+            (self.els, self.then)
+        };
+        let n = lowerer.graph.add_node(CfgNode::Condition(var));
+        lowerer.graph.add_edge(n, t, CfgEdge::True);
+        lowerer.graph.add_edge(n, f, CfgEdge::False);
+        n
+    }
+
+    fn and<'c, I>(&mut self, operands: I) -> NodeIndex
+    where
+        I: IntoIterator<Item = condition::Condition<'c, A::Condition>>,
+        A::Condition: 'c,
+    {
+        let operands: Vec<_> = operands.into_iter().collect();
+        operands.into_iter().rev().fold(self.then, |then, c| {
+            c.fold(CondLowerer {
+                lowerer: &mut *self.lowerer,
+                vars: self.vars,
+                then,
+                els: self.els,
+            })
+        })
+    }
+
+    fn or<'c, I>(&mut self, operands: I) -> NodeIndex
+    where
+        I: IntoIterator<Item = condition::Condition<'c, A::Condition>>,
+        A::Condition: 'c,
+    {
+        let operands: Vec<_> = operands.into_iter().collect();
+        operands.into_iter().rev().fold(self.els, |els, c| {
+            c.fold(CondLowerer {
+                lowerer: &mut *self.lowerer,
+                vars: self.vars,
+                then: self.then,
+                els,
+            })
+        })
+    }
+}
+
+/// Removes each of `placeholders` that only goes on to another node,
+/// moving the edges into it to that node, and returns what becomes of
+/// `entry`.
+fn contract<A: AstContext>(
+    graph: &mut StableDiGraph<CfgNode<A>, CfgEdge>,
+    mut entry: NodeIndex,
+    placeholders: &[NodeIndex],
+) -> NodeIndex {
+    for &p in placeholders {
+        let succ = match graph.neighbors(p).next() {
+            Some(succ) if succ != p => succ,
+            _ => continue,
+        };
+        let preds: Vec<_> = graph
+            .edges_directed(p, Incoming)
+            .map(|e| (e.source(), *e.weight()))
+            .collect();
+        if p == entry {
+            // the entry stays a source
+            if graph.neighbors_directed(succ, Incoming).any(|n| n != p) {
+                continue;
+            }
+            entry = succ;
+        }
+        graph.remove_node(p);
+        // `edges_directed` lists the newest edge first
+        for (pred, weight) in preds.into_iter().rev() {
+            graph.add_edge(pred, succ, weight);
+        }
+    }
+    entry
+}
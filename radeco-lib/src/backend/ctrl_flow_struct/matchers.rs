@@ -0,0 +1,498 @@
+//! Lets idiom matchers name what the parts of an AST compute, see
+//! [`IdiomMatcher`].
+//!
+//! A matcher sees the AST with its payloads replaced by ids, as a
+//! [`MatchNode`], and asks a [`MatchCtx`] about them, so that the same
+//! matcher works for any [`AstContext`](super::AstContext). It only gets
+//! shared references to both, so it can annotate the AST but never change
+//! it.
+
+use super::ast::{AstNode, HandlerId, LoopType};
+use super::condition::{self, Folder};
+use super::decisions::AstNodeId;
+use super::idioms::{Cmp, Idiom};
+use super::loop_exits::LoopExit;
+use super::state_machines::StateMachine;
+use super::trip_counts::TripCount;
+use super::AstContext;
+
+use std::fmt;
+use std::ops::Range;
+
+/// The `n`th block of the AST being matched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockId(pub usize);
+
+/// The `n`th condition of the AST being matched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CondId(pub usize);
+
+/// The `n`th `Switch` variable of the AST being matched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VarId(pub usize);
+
+/// The AST that matchers see, with the payloads numbered in pre-order.
+pub type MatchNode = AstNode<BlockId, CondId, VarId>;
+
+/// What is known about the blocks, conditions and variables of an AST.
+/// Operands and destinations are compared by their names, so the same
+/// variable must always get the same one; zero is `"0"`. By default,
+/// nothing is known.
+pub trait MatchOracle<B, C, V> {
+    /// The operands of `cond` if it is a comparison of two, e.g.
+    /// `("a", Cmp::Lt, "b")` for `a < b`.
+    fn comparison(&self, _cond: &C) -> Option<(String, Cmp, String)> {
+        None
+    }
+
+    /// The destination and source of `block` if all it does is assign one
+    /// operand to a variable, e.g. `("x", "a")` for `x = a`.
+    fn assignment(&self, _block: &B) -> Option<(String, String)> {
+        None
+    }
+
+    /// The destination and operand of `block` if all it does is assign the
+    /// negation of an operand to a variable, e.g. `("x", "a")` for
+    /// `x = -a`.
+    fn negation(&self, _block: &B) -> Option<(String, String)> {
+        None
+    }
+
+    /// The variable and the constant that `block` adds to it if that is all
+    /// it does, e.g. `("i", 1)` for `i++` and `("i", -4)` for `i -= 4`.
+    fn increment(&self, _block: &B) -> Option<(String, i64)> {
+        None
+    }
+
+    /// The constant that `block` assigns to `var`, if any.
+    fn assigned_value(&self, _block: &B, _var: &V) -> Option<u64> {
+        None
+    }
+
+    /// The addresses of the code in `block`, if it has any.
+    fn block_range(&self, _block: &B) -> Option<Range<u64>> {
+        None
+    }
+
+    /// Whether all `block` does is load values from memory again, e.g. for
+    /// the condition after it to test, with no other side effects.
+    fn is_pure_reread(&self, _block: &B) -> bool {
+        false
+    }
+}
+
+/// Answers what a matcher asks about the payloads of a [`MatchNode`], see
+/// [`MatchOracle`] for what each means.
+pub struct MatchCtx<'a> {
+    payloads: &'a (dyn Payloads + 'a),
+    id: AstNodeId,
+}
+
+impl<'a> MatchCtx<'a> {
+    /// The node being matched, in the whole AST.
+    pub fn id(&self) -> AstNodeId {
+        self.id
+    }
+
+    pub fn comparison(&self, cond: CondId) -> Option<(String, Cmp, String)> {
+        self.payloads.comparison(cond)
+    }
+
+    pub fn assignment(&self, block: BlockId) -> Option<(String, String)> {
+        self.payloads.assignment(block)
+    }
+
+    pub fn negation(&self, block: BlockId) -> Option<(String, String)> {
+        self.payloads.negation(block)
+    }
+
+    pub fn increment(&self, block: BlockId) -> Option<(String, i64)> {
+        self.payloads.increment(block)
+    }
+
+    pub fn assigned_value(&self, block: BlockId, var: VarId) -> Option<u64> {
+        self.payloads.assigned_value(block, var)
+    }
+
+    pub fn block_range(&self, block: BlockId) -> Option<Range<u64>> {
+        self.payloads.block_range(block)
+    }
+
+    pub fn is_pure_reread(&self, block: BlockId) -> bool {
+        self.payloads.is_pure_reread(block)
+    }
+}
+
+/// What a matcher found a node to be.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Annotation {
+    Idiom(Idiom),
+    StateMachine(StateMachine),
+    /// a loop that busy-waits, see [`SpinLoops`](super::spin_loops::SpinLoops)
+    SpinWait,
+    /// a call that may return more than once, like one to `setjmp`, and the
+    /// handler it returns to the other times; put there by structuring
+    /// itself, see [`CfgEdge::Abnormal`](super::CfgEdge::Abnormal)
+    ReturnsAgain(HandlerId),
+    /// an `Endless` loop and when it exits; put there by structuring itself,
+    /// see [`StructuringOptions::summarize_loop_exits`](super::StructuringOptions::summarize_loop_exits)
+    Exits(LoopExit),
+    /// a loop that counts, and how many times it runs, see
+    /// [`TripCounts`](super::trip_counts::TripCounts)
+    TripCount(TripCount),
+    /// anything else, as its comment
+    Custom(String),
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Annotation::Idiom(idiom) => idiom.fmt(f),
+            Annotation::StateMachine(sm) => sm.fmt(f),
+            Annotation::SpinWait => f.write_str("spin-wait"),
+            Annotation::ReturnsAgain(h) => write!(f, "returns again to handler_{}", h.0),
+            Annotation::Exits(exit) => exit.fmt(f),
+            Annotation::TripCount(count) => count.fmt(f),
+            Annotation::Custom(s) => f.write_str(s),
+        }
+    }
+}
+
+/// Recognizes some kind of node, e.g. [`MinMax`](super::idioms::MinMax).
+/// Matchers are registered through
+/// [`StructuringOptions::matchers`](super::StructuringOptions::matchers), and
+/// what they find is listed in
+/// [`StructuringReport::annotations`](super::StructuringReport::annotations).
+pub trait IdiomMatcher: fmt::Debug {
+    /// What `node` is, if this matcher recognizes it. The children of
+    /// `node` have already been matched.
+    fn try_match(&self, node: &MatchNode, ctx: &MatchCtx) -> Option<Annotation>;
+}
+
+/// Runs each of `matchers` on each node of `ast`, in post-order, and
+/// returns what they found, by [`AstNodeId`] in `ast` alone, sorted; the
+/// annotations of a node are in the order of `matchers`.
+pub fn run<B, C, V, O>(
+    ast: &AstNode<B, C, V>,
+    oracle: &O,
+    matchers: &[&dyn IdiomMatcher],
+) -> Vec<(AstNodeId, Annotation)>
+where
+    O: MatchOracle<B, C, V>,
+{
+    let mut erased = Erased {
+        blocks: Vec::new(),
+        conds: Vec::new(),
+        vars: Vec::new(),
+        oracle,
+    };
+    let node = erased.erase(ast);
+    let mut found = Vec::new();
+    let mut next_id = 0;
+    visit(&node, &erased, matchers, &mut next_id, &mut found);
+    found.sort_by_key(|&(id, _)| id.0);
+    found
+}
+
+fn visit(
+    node: &MatchNode,
+    payloads: &dyn Payloads,
+    matchers: &[&dyn IdiomMatcher],
+    next_id: &mut usize,
+    found: &mut Vec<(AstNodeId, Annotation)>,
+) {
+    use self::AstNode::*;
+    let id = AstNodeId(*next_id);
+    *next_id += 1;
+    match node {
+        Seq(seq) => {
+            for a in seq {
+                visit(a, payloads, matchers, next_id, found);
+            }
+        }
+        Cond(_, t, oe) => {
+            visit(t, payloads, matchers, next_id, found);
+            if let Some(e) = oe {
+                visit(e, payloads, matchers, next_id, found);
+            }
+        }
+        Loop(_, b) | For(_, _, _, b) | Try(b, _) => visit(b, payloads, matchers, next_id, found),
+        Switch(_, cases, default) => {
+            for (_, a) in cases {
+                visit(a, payloads, matchers, next_id, found);
+            }
+            visit(default, payloads, matchers, next_id, found);
+        }
+        Unstructured(members) => {
+            for (_, a) in members {
+                visit(a, payloads, matchers, next_id, found);
+            }
+        }
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_)
+        | Label(_) => (),
+    }
+    let ctx = MatchCtx { payloads, id };
+    found.extend(
+        matchers
+            .iter()
+            .filter_map(|m| m.try_match(node, &ctx))
+            .map(|a| (id, a)),
+    );
+}
+
+trait Payloads {
+    fn comparison(&self, cond: CondId) -> Option<(String, Cmp, String)>;
+    fn assignment(&self, block: BlockId) -> Option<(String, String)>;
+    fn negation(&self, block: BlockId) -> Option<(String, String)>;
+    fn increment(&self, block: BlockId) -> Option<(String, i64)>;
+    fn assigned_value(&self, block: BlockId, var: VarId) -> Option<u64>;
+    fn block_range(&self, block: BlockId) -> Option<Range<u64>>;
+    fn is_pure_reread(&self, block: BlockId) -> bool;
+}
+
+struct Erased<'a, B, C, V, O> {
+    blocks: Vec<&'a B>,
+    conds: Vec<&'a C>,
+    vars: Vec<&'a V>,
+    oracle: &'a O,
+}
+
+impl<'a, B, C, V, O> Erased<'a, B, C, V, O> {
+    fn block(&mut self, b: &'a B) -> BlockId {
+        self.blocks.push(b);
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn cond(&mut self, c: &'a C) -> CondId {
+        self.conds.push(c);
+        CondId(self.conds.len() - 1)
+    }
+
+    fn erase(&mut self, ast: &'a AstNode<B, C, V>) -> MatchNode {
+        use self::AstNode::*;
+        match ast {
+            BasicBlock(b) => BasicBlock(self.block(b)),
+            Seq(seq) => Seq(seq.iter().map(|a| self.erase(a)).collect()),
+            Cond(c, t, oe) => {
+                let c = self.cond(c);
+                let t = Box::new(self.erase(t));
+                Cond(c, t, oe.as_ref().map(|e| Box::new(self.erase(e))))
+            }
+            Loop(lt, b) => {
+                let lt = match lt {
+                    LoopType::PreChecked(c) => LoopType::PreChecked(self.cond(c)),
+                    LoopType::PostChecked(c) => LoopType::PostChecked(self.cond(c)),
+                    LoopType::Endless => LoopType::Endless,
+                };
+                Loop(lt, Box::new(self.erase(b)))
+            }
+            For(i, c, u, b) => {
+                let i = self.block(i);
+                let c = self.cond(c);
+                let u = self.block(u);
+                For(i, c, u, Box::new(self.erase(b)))
+            }
+            Switch(v, cases, default) => {
+                self.vars.push(v);
+                let v = VarId(self.vars.len() - 1);
+                let cases = cases
+                    .iter()
+                    .map(|(vs, a)| (vs.clone(), self.erase(a)))
+                    .collect();
+                Switch(v, cases, Box::new(self.erase(default)))
+            }
+            Try(b, h) => Try(Box::new(self.erase(b)), *h),
+            Unstructured(members) => {
+                Unstructured(members.iter().map(|(l, a)| (*l, self.erase(a))).collect())
+            }
+            Break => Break,
+            Continue => Continue,
+            Return => Return,
+            TailCall(b) => TailCall(self.block(b)),
+            IndirectJump(b) => IndirectJump(self.block(b)),
+            Goto(l) => Goto(*l),
+            Label(l) => Label(*l),
+        }
+    }
+}
+
+impl<'a, B, C, V, O: MatchOracle<B, C, V>> Payloads for Erased<'a, B, C, V, O> {
+    fn comparison(&self, cond: CondId) -> Option<(String, Cmp, String)> {
+        self.oracle.comparison(self.conds[cond.0])
+    }
+
+    fn assignment(&self, block: BlockId) -> Option<(String, String)> {
+        self.oracle.assignment(self.blocks[block.0])
+    }
+
+    fn negation(&self, block: BlockId) -> Option<(String, String)> {
+        self.oracle.negation(self.blocks[block.0])
+    }
+
+    fn increment(&self, block: BlockId) -> Option<(String, i64)> {
+        self.oracle.increment(self.blocks[block.0])
+    }
+
+    fn assigned_value(&self, block: BlockId, var: VarId) -> Option<u64> {
+        self.oracle
+            .assigned_value(self.blocks[block.0], self.vars[var.0])
+    }
+
+    fn block_range(&self, block: BlockId) -> Option<Range<u64>> {
+        self.oracle.block_range(self.blocks[block.0])
+    }
+
+    fn is_pure_reread(&self, block: BlockId) -> bool {
+        self.oracle.is_pure_reread(self.blocks[block.0])
+    }
+}
+
+/// Asks an [`AstContext`] about the ASTs it was structured with. A
+/// condition is a comparison if it is a single condition variable, maybe
+/// negated, that the context says is one.
+pub(super) struct ContextOracle<'a, A>(pub(super) &'a A);
+
+impl<'a, 'cd, A: AstContext>
+    MatchOracle<A::Block, condition::Condition<'cd, A::Condition>, A::Variable>
+    for ContextOracle<'a, A>
+{
+    fn comparison(
+        &self,
+        cond: &condition::Condition<'cd, A::Condition>,
+    ) -> Option<(String, Cmp, String)> {
+        let normal = cond.fold(Atom)?;
+        let (l, cmp, r) = self.0.comparison(&cond.vars()[0])?;
+        if normal {
+            return Some((l, cmp, r));
+        }
+        let cmp = match cmp {
+            Cmp::Lt => Cmp::Ge,
+            Cmp::Le => Cmp::Gt,
+            Cmp::Gt => Cmp::Le,
+            Cmp::Ge => Cmp::Lt,
+        };
+        Some((l, cmp, r))
+    }
+
+    fn assignment(&self, block: &A::Block) -> Option<(String, String)> {
+        self.0.assignment(block)
+    }
+
+    fn negation(&self, block: &A::Block) -> Option<(String, String)> {
+        self.0.negation(block)
+    }
+
+    fn increment(&self, block: &A::Block) -> Option<(String, i64)> {
+        self.0.increment(block)
+    }
+
+    fn assigned_value(&self, block: &A::Block, var: &A::Variable) -> Option<u64> {
+        self.0.assigned_value(block, var)
+    }
+
+    fn block_range(&self, block: &A::Block) -> Option<Range<u64>> {
+        self.0.block_range(block)
+    }
+
+    fn is_pure_reread(&self, block: &A::Block) -> bool {
+        self.0.is_pure_reread(block)
+    }
+}
+
+/// Folds a condition into whether it is a single variable that isn't
+/// negated, or `None` if it isn't a single variable.
+struct Atom;
+
+impl<T> Folder<T> for Atom {
+    type Output = Option<bool>;
+
+    fn var(&mut self, normal: bool, _var: &T) -> Self::Output {
+        Some(normal)
+    }
+
+    fn and<'a, I>(&mut self, _operands: I) -> Self::Output
+    where
+        I: IntoIterator<Item = condition::Condition<'a, T>>,
+        T: 'a,
+    {
+        None
+    }
+
+    fn or<'a, I>(&mut self, _operands: I) -> Self::Output
+    where
+        I: IntoIterator<Item = condition::Condition<'a, T>>,
+        T: 'a,
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::condition::Storage;
+
+    use std::cell::RefCell;
+
+    /// Records the nodes it is asked about, and the comparisons of `Cond`s.
+    #[derive(Debug, Default)]
+    struct Recorder(RefCell<Vec<AstNodeId>>);
+
+    impl IdiomMatcher for Recorder {
+        fn try_match(&self, node: &MatchNode, ctx: &MatchCtx) -> Option<Annotation> {
+            self.0.borrow_mut().push(ctx.id());
+            match *node {
+                Cond(c, _, _) => {
+                    let (l, cmp, r) = ctx.comparison(c)?;
+                    Some(Annotation::Custom(format!("{} {:?} {}", l, cmp, r)))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    struct Lt;
+
+    impl AstContext for Lt {
+        type Block = &'static str;
+        type Variable = ();
+        type BoolVariable = ();
+        type Condition = &'static str;
+
+        fn comparison(&self, cond: &&'static str) -> Option<(String, Cmp, String)> {
+            let mut words = cond.split(" < ");
+            Some((words.next()?.to_owned(), Cmp::Lt, words.next()?.to_owned()))
+        }
+    }
+
+    #[test]
+    fn post_order() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let lt = cctx.mk_var(cctx.new_var("a < b"));
+        let p = cctx.mk_var(cctx.new_var("p"));
+
+        // if (a < b) { if (!(a < b)) x; if (p && a < b) y; }
+        let ast = Cond(
+            lt,
+            Box::new(Seq(vec![
+                Cond(cctx.mk_not(lt), Box::new(BasicBlock("x")), None),
+                Cond(cctx.mk_and(p, lt), Box::new(BasicBlock("y")), None),
+            ])),
+            None,
+        );
+        let recorder = Recorder::default();
+        let found = run(&ast, &ContextOracle(&Lt), &[&recorder]);
+        let visited: Vec<_> = recorder.0.into_inner().into_iter().map(|id| id.0).collect();
+        assert_eq!(visited, vec![3, 2, 5, 4, 1, 0]);
+        assert_eq!(
+            found,
+            vec![
+                (AstNodeId(0), Annotation::Custom("a Lt b".to_owned())),
+                (AstNodeId(2), Annotation::Custom("a Ge b".to_owned())),
+            ]
+        );
+    }
+}
@@ -1,32 +1,105 @@
 //! Recovers high-level control-flow constructs from a control-flow graph.
 //! Implements the algorithm described in
 //! [*No More Gotos*](https://doi.org/10.14722/ndss.2015.23185)
+//!
+//! With the `trace_log` feature, structuring logs the regions and loops it
+//! finds at the `debug` level, each node it visits at the `trace` level, and
+//! irreducible loops at the `warn` level, as `structure: ` messages with
+//! `key=value` context. It never prints anything itself.
 
 #![allow(dead_code)]
 
+pub mod adjacent_conds;
+pub mod ast;
 pub mod ast_context;
+pub mod ast_path;
+pub mod common_heads;
+pub mod common_tails;
 pub mod condition;
+pub mod context;
+pub mod contiguous_blocks;
+pub mod decisions;
+pub mod esil;
 pub mod export;
-
-mod ast;
+pub mod for_loops;
+pub mod from_graph;
+pub mod from_r2;
+pub mod from_ssa;
+pub mod frontend;
+#[cfg(any(test, feature = "fuzz"))]
+pub mod fuzz;
+pub mod idioms;
+pub mod incremental;
+pub mod invariants;
+pub mod loop_exits;
+pub mod matchers;
+pub mod multi_entry;
+pub mod naming;
+pub mod observer;
+pub mod provenance;
+pub mod rename;
+pub mod roundtrip;
+pub mod semantics;
+pub mod spin_loops;
+pub mod stable_ids;
+pub mod state_machines;
+pub mod trace;
+pub mod trip_counts;
+pub mod x86;
+
+mod ast_arena;
+mod block_store;
 mod dedup_conds;
+mod dom_tree;
+mod dump;
+mod error;
 mod graph_utils;
+mod lowering;
+mod options;
+mod reaching_conds;
 mod refinement;
+mod report;
+mod struct_vars;
 #[cfg(test)]
 mod test;
+mod visit;
 
-use self::ast::AstNode as AstNodeC;
+use self::ast::{AstNode as AstNodeC, HandlerId, LabelId, ValueSet};
+use self::ast_arena::{AstArena, AstRef};
 use self::ast_context::*;
+use self::decisions::{DecisionLog, GuardEdges, Phase};
+use self::dump::Dumper;
+use self::error::internal;
 use self::graph_utils::ix_bit_set::IxBitSet;
-
+use self::matchers::{Annotation, ContextOracle, IdiomMatcher};
+use self::naming::{CounterNamer, Namer};
+use self::observer::{Observer, ShapeNode};
+use self::reaching_conds::ReachingConds;
+use self::struct_vars::StructVar;
+use self::trace::{TraceOp, TraceSink, TraceStep};
+
+pub use self::error::{InputDefect, InputMode, StructureError};
+pub use self::graph_utils::sese::{NestedRegion, Region, RegionTree};
+pub use self::graph_utils::IrreducibleRegion;
+pub use self::options::{Budget, CancelToken, DuplicationLimit, StructuringOptions};
+pub use self::report::{
+    CaseOverlap, DeclineReason, DeclinedCopy, FailedRegion, Fallback, InlinedCall, PhaseTimes,
+    StructuringReport,
+};
+
+use fixedbitset::FixedBitSet;
 use petgraph::prelude::*;
 use petgraph::visit::{DfsPostOrder, NodeIndexable, Walker};
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::iter;
 use std::marker::PhantomData;
 use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::time::Instant;
 
 /// Note: Conditions may be evaluated "eagerly". Thus, all conditions must always
 /// be "safe" to evaluate, but may produce garbage.
@@ -35,6 +108,30 @@ pub struct ControlFlowGraph<'cd, A: AstContext> {
     entry: NodeIndex,
     cctx: CondContext<'cd, A>,
     actx: A,
+    value_sets: ValueSets<A>,
+    branch_weights: BranchWeights,
+    /// the nodes of `set_no_duplicate`, and those they were collapsed into
+    no_duplicate: NodeSet,
+    report: StructuringReport,
+    /// the sink of `StructuringOptions::trace`, while structuring
+    trace: Option<Rc<RefCell<dyn TraceSink>>>,
+    /// the observer of `StructuringOptions::observer`, while structuring
+    observer: Option<Rc<RefCell<dyn Observer>>>,
+    /// the namer of `StructuringOptions::namer`, while structuring
+    namer: Option<Rc<RefCell<dyn Namer>>>,
+    /// the token of `StructuringOptions::cancel`, while structuring
+    cancel: Option<CancelToken>,
+    /// the dominators of `set_dominators`, until the graph changes
+    dominators: Option<dom_tree::DomTree>,
+    /// what is left of `StructuringOptions::budget`, while structuring
+    budget: Option<BudgetLeft>,
+    /// the dumps of `StructuringOptions::dump_dir`, while structuring
+    dump: Option<Dumper>,
+    /// what made the constructs of the ASTs, see [`Decisions`]
+    decisions: RefCell<DecisionLog<'cd, A::Condition>>,
+    /// the variables introduced for abnormal entries and exits, see
+    /// [`StructuringOptions::fold_struct_vars`]
+    struct_vars: Vec<StructVar<'cd, A>>,
 }
 
 type NodeSet = IxBitSet<NodeIndex>;
@@ -53,11 +150,151 @@ pub enum CfgNode<'cd, A: AstContext> {
 pub enum CfgEdge {
     True,
     False,
+    /// From a code node that may throw to the landing pad its exceptions
+    /// unwind to. Not normal control flow: the handler starting at the
+    /// landing pad is structured on its own, see
+    /// [`ControlFlowGraph::structure_with_handlers`].
+    Unwind,
+    /// From a code node ending in a call that may return more than once,
+    /// like one to `setjmp`, to the code it returns to the other times, e.g.
+    /// once a `longjmp` comes back to it. Not normal control flow either:
+    /// like an `Unwind` edge, it is left out of structuring, and the code it
+    /// leads to is structured on its own, as a handler that the call is
+    /// annotated with, see [`Annotation::ReturnsAgain`].
+    Abnormal,
+}
+
+impl CfgEdge {
+    pub fn is_unwind(self) -> bool {
+        matches!(self, CfgEdge::Unwind)
+    }
+
+    /// Whether this is an `Unwind` or an `Abnormal` edge.
+    pub fn is_abnormal(self) -> bool {
+        matches!(self, CfgEdge::Unwind | CfgEdge::Abnormal)
+    }
+}
+
+/// The graph of a handler, its landing pad, and the nodes of it that must
+/// not be copied, see [`ControlFlowGraph::set_no_duplicate`].
+type HandlerGraph<'cd, A> = (StableDiGraph<CfgNode<'cd, A>, CfgEdge>, NodeIndex, NodeSet);
+/// The ASTs of the normal path and of the handlers, the context and the
+/// report.
+type Structured<'cd, A> = (AstNode<'cd, A>, Vec<AstNode<'cd, A>>, A, StructuringReport);
+/// The code of each node laid out with its label, in order, and the labels
+/// that a `Goto` jumps to, see [`ControlFlowGraph::lay_out`].
+type Layout<'cd, A> = (Vec<(LabelId, AstNode<'cd, A>)>, HashSet<LabelId>);
+
+#[derive(Copy, Clone, Debug)]
+enum BudgetLeft {
+    Steps(usize),
+    Until(Instant),
+}
+
+/// How a graph decomposes, as [`ControlFlowGraph::analyze`] finds it.
+#[derive(Debug)]
+pub struct Decomposition {
+    /// the loops, in the order structuring collapses them, so that each
+    /// comes after those nested in it
+    pub loops: Vec<LoopInfo>,
+    /// as [`ControlFlowGraph::region_tree`] gives it
+    pub regions: RegionTree,
+}
+
+/// A loop of a [`Decomposition`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoopInfo {
+    pub header: NodeIndex,
+    /// its nodes, those of the loops nested in it included, sorted
+    pub nodes: Vec<NodeIndex>,
+    /// the innermost loop it is nested in, by its index in
+    /// [`Decomposition::loops`]
+    pub parent: Option<usize>,
+    /// the nodes other than the header it is entered at, which make it
+    /// irreducible, sorted
+    pub entries: Vec<NodeIndex>,
+    /// the nodes it is left for besides the one it goes on to, sorted
+    pub exits: Vec<NodeIndex>,
+}
+
+impl Decomposition {
+    /// The loops entered elsewhere than at their header.
+    pub fn irreducible(&self) -> impl Iterator<Item = &LoopInfo> {
+        self.loops.iter().filter(|l| !l.entries.is_empty())
+    }
+
+    /// The nodes that only a `goto` could jump to in structured code: the
+    /// other entries of the loops, and the nodes they are left for besides
+    /// the one they go on to, sorted. Structuring dispatches on a variable to
+    /// get to them instead, see [`Fallback`]. It only leaves `Goto`s behind
+    /// once it runs out of [`StructuringOptions::budget`], in the regions that
+    /// [`ControlFlowGraph::structure_by_region`] couldn't structure, see
+    /// [`FailedRegion`], and for the side exits of the acyclic regions, see
+    /// [`StructuringReport::side_exits`].
+    pub fn goto_targets(&self) -> Vec<NodeIndex> {
+        let mut ret: Vec<_> = self
+            .loops
+            .iter()
+            .flat_map(|l| l.entries.iter().chain(&l.exits))
+            .cloned()
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+}
+
+/// How many collapses in a row may leave the graph no smaller before
+/// structuring gives up with [`StructureError::NoProgress`]. Funnelling the
+/// entries of an irreducible loop can grow the graph, so a few may.
+const MAX_STALLED_STEPS: usize = 32;
+
+/// Whether the collapses of a pass keep shrinking the graph, as the sum of
+/// its nodes and edges.
+struct Progress {
+    size: usize,
+    stalled: usize,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Progress {
+            size: 0,
+            stalled: 0,
+        }
+    }
+
+    /// Call before each collapse.
+    fn start<N, E>(&mut self, graph: &StableDiGraph<N, E>) {
+        self.size = graph.node_count() + graph.edge_count();
+    }
+
+    /// Call after each collapse. Returns `false` once the last
+    /// [`MAX_STALLED_STEPS`] collapses all left the graph no smaller.
+    fn made<N, E>(&mut self, graph: &StableDiGraph<N, E>) -> bool {
+        if graph.node_count() + graph.edge_count() < self.size {
+            self.stalled = 0;
+        } else {
+            self.stalled += 1;
+        }
+        self.stalled < MAX_STALLED_STEPS
+    }
 }
 
 type CondVar<'cd, A> = condition::VarRef<'cd, <A as AstContext>::Condition>;
+/// The value sets supplied with [`ControlFlowGraph::set_value_set`], keyed by
+/// the address of the condition variable, and which of the edges out of the
+/// condition node is the out-of-range one, as a value of the condition, if
+/// [`ControlFlowGraph::set_out_of_range`] said.
+type ValueSets<A> = HashMap<usize, (<A as AstContext>::Variable, ValueSet, Option<bool>)>;
+/// The weights of the edges out of the condition nodes supplied with
+/// [`ControlFlowGraph::set_branch_weights`], where the condition holds and
+/// where it doesn't, keyed by the address of the condition variable.
+type BranchWeights = HashMap<usize, (u64, u64)>;
 type Condition<'cd, A> = condition::Condition<'cd, <A as AstContext>::Condition>;
 type CondContext<'cd, A> = condition::Context<'cd, <A as AstContext>::Condition>;
+type AstShapeNode<'cd, A> =
+    ShapeNode<<A as AstContext>::Block, Condition<'cd, A>, <A as AstContext>::Variable>;
 // hoping https://github.com/rust-lang/rust/issues/49683 lands soon
 type AstNode<'cd, A> =
     ast::AstNode<<A as AstContext>::Block, Condition<'cd, A>, <A as AstContext>::Variable>;
@@ -66,6 +303,14 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
     /// Preconditions:
     /// - `entry` must be a source
     /// - all nodes must be reachable from `entry`
+    /// - only code nodes may have an `Unwind` or `Abnormal` edge, and at most
+    ///   one
+    /// - landing pads may only be entered through `Unwind` edges, or only
+    ///   through `Abnormal` edges
+    ///
+    /// They are only asserted in debug builds;
+    /// [`structure_whole_checked`](Self::structure_whole_checked) checks them
+    /// in any build.
     pub fn new(
         graph: StableDiGraph<CfgNode<'cd, A>, CfgEdge>,
         entry: NodeIndex,
@@ -77,6 +322,19 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
             entry,
             cctx,
             actx,
+            value_sets: HashMap::new(),
+            branch_weights: HashMap::new(),
+            no_duplicate: NodeSet::new(),
+            report: StructuringReport::default(),
+            trace: None,
+            observer: None,
+            namer: None,
+            cancel: None,
+            dominators: None,
+            budget: None,
+            dump: None,
+            decisions: RefCell::default(),
+            struct_vars: Vec::new(),
         };
         ret.check();
         ret
@@ -87,60 +345,1594 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
 
     #[cfg(debug_assertions)]
     fn check(&self) {
+        if let Err(err) = self.validate() {
+            panic!("{}", err);
+        }
+    }
+
+    /// Checks the preconditions of [`new`](Self::new).
+    fn validate(&self) -> Result<(), StructureError> {
+        let fail = |detail| Err(internal("validate", detail));
+        if !self.graph.contains_node(self.entry) {
+            return fail("the entry isn't a node of the graph".to_owned());
+        }
+        let reachable: NodeSet = Dfs::new(&self.graph, self.entry)
+            .iter(&self.graph)
+            .collect();
         for n in self.graph.node_indices() {
-            assert!(graph_utils::is_source(&self.graph, n) == (n == self.entry));
-            match &self.graph[n] {
-                CfgNode::Code(_) => assert!(self.graph.neighbors(n).count() <= 1),
-                CfgNode::Condition(_) => assert!(self.graph.neighbors(n).count() == 2),
-                CfgNode::Dummy(s) => panic!("found `CfgNode::Dummy({:?})`", s),
+            let count = |dir, abnormal| {
+                self.graph
+                    .edges_directed(n, dir)
+                    .filter(|e| e.weight().is_abnormal() == abnormal)
+                    .count()
+            };
+            if !reachable.contains(n) {
+                return fail(format!("node {} can't be reached", n.index()));
+            }
+            // the other nodes are reachable, so they do have predecessors
+            let is_source = n == self.entry || count(Incoming, true) > 0;
+            if is_source && count(Incoming, false) > 0 {
+                return fail(format!(
+                    "node {} is the entry or a landing pad, but has predecessors",
+                    n.index()
+                ));
+            }
+            let unwinds = self
+                .graph
+                .edges_directed(n, Incoming)
+                .filter(|e| e.weight().is_unwind())
+                .count();
+            if unwinds > 0 && unwinds < count(Incoming, true) {
+                return fail(format!(
+                    "node {} is entered through both `Unwind` and `Abnormal` edges",
+                    n.index()
+                ));
+            }
+            let ok = match &self.graph[n] {
+                CfgNode::Code(_) => count(Outgoing, false) <= 1 && count(Outgoing, true) <= 1,
+                CfgNode::Condition(_) => count(Outgoing, false) == 2 && count(Outgoing, true) == 0,
+                CfgNode::Dummy(s) => return fail(format!("found `CfgNode::Dummy({:?})`", s)),
+            };
+            if !ok {
+                return fail(format!("node {} has the wrong successors", n.index()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells switch recovery that the condition of the condition node
+    /// `cond_node` holds exactly when `var` has one of the values in
+    /// `values`, e.g. as found by value-set analysis. Conditions that are
+    /// only ever combined with others about the same `var` may then become
+    /// the cases of a `Switch` on `var`.
+    ///
+    /// `var` must not change between the evaluations of any two such
+    /// conditions.
+    ///
+    /// # Panics
+    /// Panics if `cond_node` isn't a condition node.
+    pub fn set_value_set(&mut self, cond_node: NodeIndex, var: A::Variable, values: ValueSet) {
+        match &self.graph[cond_node] {
+            CfgNode::Condition(c) => {
+                self.value_sets
+                    .insert(cond_var_key::<A>(*c), (var, values, None));
+            }
+            _ => panic!("set_value_set: not a condition node"),
+        }
+    }
+
+    /// Tells switch recovery that `edge`, out of the condition node
+    /// `cond_node`, is taken when the value of the variable of its value set
+    /// has none of the cases, e.g. the range check in front of a jump table.
+    /// What it leads to then becomes the default of the `Switch`, without
+    /// having to tell from the value sets.
+    ///
+    /// # Panics
+    /// Panics if `cond_node` isn't a condition node with a value set, or if
+    /// `edge` is neither `True` nor `False`.
+    pub fn set_out_of_range(&mut self, cond_node: NodeIndex, edge: CfgEdge) {
+        let when = match edge {
+            CfgEdge::True => true,
+            CfgEdge::False => false,
+            _ => panic!("set_out_of_range: not a branch edge"),
+        };
+        let entry = match &self.graph[cond_node] {
+            CfgNode::Condition(c) => self.value_sets.get_mut(&cond_var_key::<A>(*c)),
+            _ => panic!("set_out_of_range: not a condition node"),
+        };
+        match entry {
+            Some((_, _, out_of_range)) => *out_of_range = Some(when),
+            None => panic!("set_out_of_range: no value set"),
+        }
+    }
+
+    /// Tells how heavy the edges out of the condition node `cond_node` are,
+    /// the one taken when its condition holds and the other, e.g. how often
+    /// a profile saw each taken. With
+    /// [`StructuringOptions::order_by_weight`], the heavier one then goes
+    /// first in the `if`-`else` on it. The weights of different conditions
+    /// are compared with each other too, so they should be in the same
+    /// unit, such as execution counts.
+    ///
+    /// # Panics
+    /// Panics if `cond_node` isn't a condition node.
+    pub fn set_branch_weights(&mut self, cond_node: NodeIndex, holds: u64, fails: u64) {
+        match &self.graph[cond_node] {
+            CfgNode::Condition(c) => {
+                self.branch_weights
+                    .insert(cond_var_key::<A>(*c), (holds, fails));
+            }
+            _ => panic!("set_branch_weights: not a condition node"),
+        }
+    }
+
+    /// Marks the code node `node` as ending in a call that never returns,
+    /// e.g. to `abort` or `exit`. It is then structured like a `return`:
+    /// its outgoing edge, which is bogus, is removed, along with the nodes
+    /// that were only reachable through it, and it ends in a `Return` leaf.
+    ///
+    /// # Panics
+    /// Panics if `node` isn't a code node.
+    pub fn set_noreturn(&mut self, node: NodeIndex) {
+        self.terminate(node, AstNodeC::Return, "set_noreturn");
+    }
+
+    /// Marks the code node `node` as ending in a tail call, e.g. a jump to
+    /// another function, whose result is returned. `call` is the block
+    /// making the call; it becomes a `TailCall` leaf at the end of `node`.
+    /// Any outgoing edge of `node`, e.g. to a missing target, is removed like
+    /// with [`set_noreturn`](Self::set_noreturn).
+    ///
+    /// # Panics
+    /// Panics if `node` isn't a code node.
+    pub fn set_tail_call(&mut self, node: NodeIndex, call: A::Block) {
+        self.terminate(node, AstNodeC::TailCall(call), "set_tail_call");
+    }
+
+    /// Marks the code node `node` as ending in an indirect jump whose
+    /// targets couldn't be resolved. `jump` is the block computing the
+    /// target; it becomes an `IndirectJump` leaf at the end of `node`, which
+    /// is then structured like a `return`. The outgoing edge of `node`, if
+    /// any, only leads to some of the targets, so it is removed like with
+    /// [`set_noreturn`](Self::set_noreturn).
+    ///
+    /// # Panics
+    /// Panics if `node` isn't a code node.
+    pub fn set_unresolved_jump(&mut self, node: NodeIndex, jump: A::Block) {
+        self.terminate(node, AstNodeC::IndirectJump(jump), "set_unresolved_jump");
+    }
+
+    /// Marks `node` as one that structuring must never copy, e.g. because
+    /// it makes a call with side effects, does an atomic operation, or is
+    /// too large to repeat, however many copies
+    /// [`StructuringOptions::max_duplicated_nodes`] allows. Neither the
+    /// tails of [`StructuringOptions::duplicate_tails`], nor the parts of
+    /// [`StructuringOptions::split_shared_regions`], nor the parts of the
+    /// loops entered elsewhere than at their header are then copied if they
+    /// include it, or a region it was collapsed into. Structuring does
+    /// without each such copy like it does without one over the limit, and
+    /// lists it in [`StructuringReport::declined`] with
+    /// [`DeclineReason::NoDuplicate`].
+    ///
+    /// # Panics
+    /// Panics if there is no node `node`.
+    pub fn set_no_duplicate(&mut self, node: NodeIndex) {
+        assert!(
+            self.graph.contains_node(node),
+            "set_no_duplicate: no node {}",
+            node.index()
+        );
+        self.no_duplicate.insert(node);
+    }
+
+    /// Tells structuring that the condition of the condition node
+    /// `cond_node` always has the value `value`, e.g. because it is an
+    /// opaque predicate that was resolved. The edge that is never taken is
+    /// removed and the other one becomes unconditional, so the dead branch
+    /// isn't structured at all. The nodes only it reached are removed too,
+    /// and listed in [`StructuringReport::pruned`].
+    ///
+    /// # Panics
+    /// Panics if `cond_node` isn't a condition node.
+    pub fn set_constant(&mut self, cond_node: NodeIndex, value: bool) {
+        match self.graph[cond_node] {
+            CfgNode::Condition(_) => self.fold_constant(cond_node, value),
+            _ => panic!("set_constant: not a condition node"),
+        }
+        let mut pruned = self.prune_unreachable();
+        self.report.pruned.append(&mut pruned);
+        self.check();
+    }
+
+    /// Makes the condition node `cond_node` an empty code node that goes on
+    /// to where it went when its condition has the value `value`.
+    fn fold_constant(&mut self, cond_node: NodeIndex, value: bool) {
+        self.graph_changed();
+        self.graph[cond_node] = empty_node();
+        let out_edges: Vec<_> = self
+            .graph
+            .edges(cond_node)
+            .map(|e| (e.id(), e.target(), matches!(e.weight(), CfgEdge::True)))
+            .collect();
+        for (e, target, holds) in out_edges {
+            self.graph.remove_edge(e);
+            if holds == value {
+                self.graph.add_edge(cond_node, target, CfgEdge::True);
+            }
+        }
+    }
+
+    /// Folds each condition node whose condition is constant, as
+    /// [`AstContext::constant_value`] tells, like
+    /// [`set_constant`](Self::set_constant) does.
+    fn fold_constant_conditions(&mut self) {
+        let constants: Vec<_> = self
+            .graph
+            .node_indices()
+            .filter_map(|n| match &self.graph[n] {
+                CfgNode::Condition(c) => self.actx.constant_value(c).map(|value| (n, value)),
+                _ => None,
+            })
+            .collect();
+        if constants.is_empty() {
+            return;
+        }
+        for (n, value) in constants {
+            self.fold_constant(n, value);
+        }
+        let mut pruned = self.prune_unreachable();
+        self.report.pruned.append(&mut pruned);
+    }
+
+    /// Splits each critical edge, i.e. each edge from a condition node to a
+    /// node with more than one predecessor, with a new empty code node, so
+    /// that there is a node of its own to put code in that runs only when
+    /// the edge is taken. The condition node goes on to the new node on the
+    /// same edge, and the new node goes on to where the edge went.
+    /// `Unwind` and `Abnormal` edges aren't split, nor counted.
+    ///
+    /// The new nodes are listed in [`StructuringReport::split_edges`]. The
+    /// assignments to the variables for the loops entered or left in more
+    /// than one place, see [`Fallback`], go in them, and so do the `break`s
+    /// leaving loops; those left empty leave nothing behind in the AST.
+    /// Structuring does this first with
+    /// [`StructuringOptions::split_critical_edges`].
+    pub fn split_critical_edges(&mut self) {
+        self.graph_changed();
+        let graph = &self.graph;
+        let normal_degree = |n, dir| {
+            graph
+                .edges_directed(n, dir)
+                .filter(|e| !e.weight().is_abnormal())
+                .count()
+        };
+        let critical: Vec<_> = graph
+            .edge_references()
+            .filter(|e| !e.weight().is_abnormal())
+            .filter(|e| {
+                normal_degree(e.source(), Outgoing) > 1 && normal_degree(e.target(), Incoming) > 1
+            })
+            .map(|e| (e.id(), e.target()))
+            .collect();
+        for (e, target) in critical {
+            let split = self.graph.add_node(empty_node());
+            graph_utils::retarget_edge(&mut self.graph, e, split);
+            self.graph.add_edge(split, target, CfgEdge::True);
+            self.report.split_edges.push(split);
+        }
+        self.check();
+    }
+
+    /// Merges each chain of code nodes that run one after the other, e.g.
+    /// from a frontend that makes a node of each instruction, or that splits
+    /// blocks at calls, into its first node, so that structuring has fewer
+    /// nodes to go through. A node goes on the chain of the code node before
+    /// it if that node has no other successor, and it has no other
+    /// predecessor. Its code goes after that of the node before it, which
+    /// goes on to where it went, and is marked with
+    /// [`set_no_duplicate`](Self::set_no_duplicate) if it was. Neither the
+    /// code of a landing pad, which is the first of its chain, nor that of a
+    /// node with an `Unwind` or `Abnormal` edge, nor one with a `Label`, is
+    /// merged into the node before it, nor is that of a loop header, which
+    /// has its back edges coming in too; the nodes of
+    /// [`split_critical_edges`](Self::split_critical_edges) and
+    /// [`insert_preheaders`](Self::insert_preheaders) are left alone as
+    /// well.
+    ///
+    /// The chains are listed in [`StructuringReport::merged_chains`].
+    /// Structuring does this first with
+    /// [`StructuringOptions::merge_linear_chains`].
+    pub fn merge_linear_chains(&mut self) {
+        self.graph_changed();
+        let nodes: Vec<_> = self.graph.node_indices().collect();
+        let tails: NodeSet = nodes
+            .iter()
+            .filter_map(|&n| self.chain_successor(n))
+            .collect();
+        for head in nodes {
+            if tails.contains(head) {
+                continue;
+            }
+            let mut chain = Vec::new();
+            while let Some(next) = self.chain_successor(head) {
+                let tail = match mem::replace(&mut self.graph[next], empty_node()) {
+                    CfgNode::Code(ast) => ast,
+                    _ => unreachable!(),
+                };
+                if let CfgNode::Code(ast) = &mut self.graph[head] {
+                    *ast = concat(mem::take(ast), tail);
+                }
+                let out_edges: Vec<_> = self
+                    .graph
+                    .edges(next)
+                    .map(|e| (e.target(), *e.weight()))
+                    .collect();
+                for (target, edge) in out_edges {
+                    let target = if target == next { head } else { target };
+                    self.graph.add_edge(head, target, edge);
+                }
+                self.graph.remove_node(next);
+                if self.no_duplicate.contains(next) {
+                    self.no_duplicate.remove(next);
+                    self.no_duplicate.insert(head);
+                }
+                chain.push(next);
+            }
+            if !chain.is_empty() {
+                self.report.merged_chains.push((head, chain));
+            }
+        }
+        self.check();
+    }
+
+    /// The node that [`merge_linear_chains`](Self::merge_linear_chains)
+    /// merges into `n`, if any.
+    fn chain_successor(&self, n: NodeIndex) -> Option<NodeIndex> {
+        let is_code = |n| match &self.graph[n] {
+            CfgNode::Code(ast) => !adjacent_conds::has_label(ast),
+            _ => false,
+        };
+        let added = |n| self.report.split_edges.contains(&n) || self.report.preheaders.contains(&n);
+        let mut out_edges = self.graph.edges(n);
+        let next = match (out_edges.next(), out_edges.next()) {
+            (Some(e), None) if !e.weight().is_abnormal() => e.target(),
+            _ => return None,
+        };
+        let single_pred = self.graph.edges_directed(next, Incoming).count() == 1;
+        let abnormal_out = self.graph.edges(next).any(|e| e.weight().is_abnormal());
+        if next == n || !single_pred || abnormal_out || added(n) || added(next) {
+            return None;
+        }
+        if matches!(self.graph[n], CfgNode::Code(_)) && is_code(next) {
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    /// The source of `edge`, if it is a node that
+    /// [`split_critical_edges`](Self::split_critical_edges) made and that
+    /// is still empty, for code that runs only when `edge` is taken.
+    fn empty_split_source(&self, edge: EdgeIndex) -> Option<NodeIndex> {
+        let (source, _) = self.graph.edge_endpoints(edge)?;
+        let empty =
+            matches!(&self.graph[source], CfgNode::Code(AstNodeC::Seq(seq)) if seq.is_empty());
+        if empty && self.report.split_edges.contains(&source) {
+            Some(source)
+        } else {
+            None
+        }
+    }
+
+    /// Gives each natural loop a preheader: a new empty code node that all
+    /// the edges into its header from outside of the loop go to instead,
+    /// and that goes on to the header, so that the loop is entered from a
+    /// single node. The loops entered elsewhere than at their header aren't
+    /// natural, and are left as they are, as are those already entered from
+    /// a single code node. `Unwind` and `Abnormal` edges aren't moved.
+    ///
+    /// The preheaders are listed in [`StructuringReport::preheaders`]. They
+    /// have no code, so they leave nothing behind in the AST or in what it
+    /// tells of the binary. Structuring does this first with
+    /// [`StructuringOptions::insert_preheaders`].
+    pub fn insert_preheaders(&mut self) {
+        self.graph_changed();
+        let skeleton = self.normal_skeleton();
+        let (_, back_edges) = postorder_and_back_edges(&skeleton, self.entry);
+        for header in back_edges.headers() {
+            let in_loop = graph_utils::dominated_by(&skeleton, self.entry, header);
+            let (latches, entries): (Vec<_>, Vec<_>) = self
+                .graph
+                .edges_directed(header, Incoming)
+                .filter(|e| !e.weight().is_abnormal())
+                .map(|e| (e.id(), e.source()))
+                .partition(|&(_, pred)| in_loop.contains(pred));
+            if latches.is_empty() || entries.is_empty() {
+                continue;
+            }
+            if let [(_, pred)] = entries[..] {
+                if matches!(self.graph[pred], CfgNode::Code(_)) {
+                    continue;
+                }
+            }
+            let preheader = self.graph.add_node(empty_node());
+            for (e, _) in entries {
+                graph_utils::retarget_edge(&mut self.graph, e, preheader);
+            }
+            self.graph.add_edge(preheader, header, CfgEdge::True);
+            self.report.preheaders.push(preheader);
+        }
+        self.check();
+    }
+
+    /// Splits each latch of a loop that does some work besides computing
+    /// what the test after it takes, so that the test alone can be the
+    /// condition of a `do`-`while` and the work its body: a code node whose
+    /// only successor is a condition node going back to the loop header,
+    /// which it is the only predecessor of, and whose block
+    /// [has side effects](AstContext::has_side_effects). The block is
+    /// [split](AstContextMut::split_latch) into the work, which stays in the
+    /// code node, and the rest, which the condition node then tests. A latch
+    /// that only computes the test is left whole, as are those the context
+    /// can't split and the tests of a [value set](Self::set_value_set).
+    ///
+    /// The split latches are listed in [`StructuringReport::split_latches`].
+    /// Structuring does this first with
+    /// [`StructuringOptions::split_latches`].
+    pub fn split_latches(&mut self) {
+        self.graph_changed();
+        let skeleton = self.normal_skeleton();
+        let (_, back_edges) = postorder_and_back_edges(&skeleton, self.entry);
+        let mut tests: Vec<_> = back_edges
+            .0
+            .iter()
+            .flatten()
+            .filter_map(|&e| skeleton.edge_endpoints(e))
+            .map(|(source, _)| source)
+            .collect();
+        tests.sort();
+        tests.dedup();
+        for test in tests {
+            let cond = match self.graph[test] {
+                CfgNode::Condition(c) if !self.value_sets.contains_key(&test.index()) => c,
+                _ => continue,
+            };
+            let mut preds = self.graph.neighbors_directed(test, Incoming);
+            let latch = match (preds.next(), preds.next()) {
+                (Some(latch), None) if self.graph.neighbors(latch).count() == 1 => latch,
+                _ => continue,
+            };
+            let split = match &self.graph[latch] {
+                CfgNode::Code(AstNodeC::BasicBlock(b)) if self.actx.has_side_effects(b) => {
+                    self.actx.split_latch(b, &cond)
+                }
+                _ => None,
+            };
+            if let Some((work, cond)) = split {
+                self.graph[latch] = CfgNode::Code(AstNodeC::BasicBlock(work));
+                self.graph[test] = CfgNode::Condition(self.cctx.new_var(cond));
+                self.report.split_latches.push(latch);
+            }
+        }
+        self.check();
+    }
+
+    /// Splices a copy of the graph of `callee`, e.g. a small helper, in
+    /// after the code node `call_node` that calls it, so that it is
+    /// structured as part of this function: `call_node` goes on to the entry
+    /// of the copy, and the nodes of the copy that return go on to where
+    /// `call_node` went, if anywhere. A tail call ending `callee` becomes a
+    /// plain call there; the nodes that end the program, see
+    /// [`set_noreturn`](Self::set_noreturn), still do. The copy keeps the
+    /// conditions of `callee` and their value sets, and is listed in
+    /// [`StructuringReport::inlined`].
+    ///
+    /// Fails, leaving the graph as it is, if `call_node` isn't a code node,
+    /// if `callee` has an unresolved indirect jump, which may go anywhere,
+    /// if a block of `callee` can't be [copied](AstContextMut::clone_block),
+    /// or if inlining would be recursive: `callee` starts at the same
+    /// address as this function, or had it inlined, as far as
+    /// [`AstContext::block_range`] tells the addresses.
+    pub fn inline_at(
+        &mut self,
+        call_node: NodeIndex,
+        callee: &ControlFlowGraph<'cd, A>,
+    ) -> Result<(), StructureError> {
+        self.graph_changed();
+        if !matches!(self.graph.node_weight(call_node), Some(CfgNode::Code(_))) {
+            return Err(StructureError::Inline("the call isn't a code node"));
+        }
+        let callee_addr = callee.entry_addr();
+        let own_addr = self.entry_addr();
+        let recursive = callee_addr.is_some() && callee_addr == own_addr
+            || own_addr.is_some() && callee.report.inlined.iter().any(|i| i.callee == own_addr);
+        if recursive {
+            return Err(StructureError::Inline("the callee is the caller"));
+        }
+        let has_jump = callee.graph.node_indices().any(|n| match &callee.graph[n] {
+            CfgNode::Code(ast) => decisions::preorder(ast)
+                .iter()
+                .any(|a| matches!(a, AstNodeC::IndirectJump(_))),
+            _ => false,
+        });
+        if has_jump {
+            return Err(StructureError::Inline(
+                "the callee has an unresolved indirect jump",
+            ));
+        }
+
+        let mut copies = Vec::new();
+        for n in callee.graph.node_indices() {
+            let copy = match &callee.graph[n] {
+                CfgNode::Code(ast) => match clone_ast(&mut self.actx, ast) {
+                    Some(ast) => CfgNode::Code(ast),
+                    None => return Err(StructureError::Inline("the callee can't be copied")),
+                },
+                CfgNode::Condition(c) => CfgNode::Condition(*c),
+                CfgNode::Dummy(s) => CfgNode::Dummy(s),
+            };
+            copies.push((n, copy));
+        }
+        let copy_of: HashMap<_, _> = copies
+            .into_iter()
+            .map(|(n, copy)| (n, self.graph.add_node(copy)))
+            .collect();
+        for e in callee.graph.edge_references() {
+            let (source, target) = (copy_of[&e.source()], copy_of[&e.target()]);
+            self.graph.add_edge(source, target, *e.weight());
+        }
+
+        let succ = self
+            .graph
+            .edges(call_node)
+            .find(|e| !e.weight().is_abnormal())
+            .map(|e| (e.id(), e.target(), *e.weight()));
+        if let Some((e, _, _)) = succ {
+            self.graph.remove_edge(e);
+        }
+        self.graph
+            .add_edge(call_node, copy_of[&callee.entry], CfgEdge::True);
+        for n in callee.graph.node_indices() {
+            let copy = copy_of[&n];
+            let returns = callee.graph.edges(n).all(|e| e.weight().is_abnormal());
+            if !returns {
+                continue;
+            }
+            let ends_program = match &mut self.graph[copy] {
+                CfgNode::Code(ast) => !untail_call(ast) && ends_in_return(ast),
+                _ => false,
+            };
+            if let (Some((_, target, weight)), false) = (succ, ends_program) {
+                self.graph.add_edge(copy, target, weight);
+            }
+        }
+        for (k, v) in &callee.value_sets {
+            self.value_sets.insert(*k, v.clone());
+        }
+        self.no_duplicate
+            .extend(callee.no_duplicate.iter().map(|n| copy_of[&n]));
+        self.branch_weights.extend(&callee.branch_weights);
+
+        let mut nodes: Vec<_> = copy_of.iter().map(|(&n, &copy)| (n, copy)).collect();
+        nodes.sort();
+        // and what was inlined into `callee`, which now is in the copy
+        for inner in &callee.report.inlined {
+            self.report.inlined.push(InlinedCall {
+                call: copy_of[&inner.call],
+                callee: inner.callee,
+                nodes: inner
+                    .nodes
+                    .iter()
+                    .filter_map(|&(n, c)| copy_of.get(&c).map(|&copy| (n, copy)))
+                    .collect(),
+            });
+        }
+        self.report.inlined.push(InlinedCall {
+            call: call_node,
+            callee: callee_addr,
+            nodes,
+        });
+        self.check();
+        Ok(())
+    }
+
+    /// The address of the first block of the entry, as far as
+    /// [`AstContext::block_range`] tells.
+    fn entry_addr(&self) -> Option<u64> {
+        let ast = match &self.graph[self.entry] {
+            CfgNode::Code(ast) => ast,
+            _ => return None,
+        };
+        decisions::preorder(ast).into_iter().find_map(|a| match a {
+            AstNodeC::BasicBlock(b) => self.actx.block_range(b).map(|r| r.start),
+            _ => None,
+        })
+    }
+
+    fn terminate(&mut self, node: NodeIndex, leaf: AstNode<'cd, A>, caller: &str) {
+        self.graph_changed();
+        match &mut self.graph[node] {
+            CfgNode::Code(ast) => append_leaf(ast, leaf),
+            _ => panic!("{}: not a code node", caller),
+        }
+
+        let out_edges: Vec<_> = self.graph.edges(node).map(|e| e.id()).collect();
+        if out_edges.is_empty() {
+            return;
+        }
+        for e in out_edges {
+            self.graph.remove_edge(e);
+        }
+        self.prune_unreachable();
+        self.check();
+    }
+
+    /// Removes the nodes that can't be reached from the entry, and returns
+    /// them.
+    fn prune_unreachable(&mut self) -> Vec<NodeIndex> {
+        self.graph_changed();
+        let reachable: NodeSet = Dfs::new(&self.graph, self.entry)
+            .iter(&self.graph)
+            .collect();
+        let unreachable: Vec<_> = self
+            .graph
+            .node_indices()
+            .filter(|&n| !reachable.contains(n))
+            .collect();
+        for &n in &unreachable {
+            self.graph.remove_node(n);
+            self.no_duplicate.remove(n);
+        }
+        unreachable
+    }
+
+    /// Copies each tail of at most `max_len` code nodes that two branches
+    /// share, see [`StructuringOptions::duplicate_tails`], so that one of
+    /// them goes on to the copy, as far as `limit` allows.
+    fn duplicate_shared_tails(&mut self, max_len: usize, limit: Option<DuplicationLimit>) {
+        self.graph_changed();
+        let heads: Vec<_> = self.graph.node_indices().collect();
+        for head in heads {
+            let (chain, join) = match self.shared_tail(head, max_len) {
+                Some(tail) => tail,
+                None => continue,
+            };
+            let nodes = chain.len();
+            if let Some(reason) = self.decline_reason(limit, chain.iter().cloned()) {
+                self.report.declined.push(DeclinedCopy::SharedTail {
+                    head,
+                    nodes,
+                    reason,
+                });
+                continue;
+            }
+            let (graph, actx) = (&self.graph, &mut self.actx);
+            let copies = chain
+                .iter()
+                .map(|&n| match &graph[n] {
+                    CfgNode::Code(ast) => clone_ast(actx, ast),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>();
+            let copies: Vec<_> = match copies {
+                Some(copies) => copies
+                    .into_iter()
+                    .map(|ast| self.graph.add_node(CfgNode::Code(ast)))
+                    .collect(),
+                None => continue,
+            };
+            radeco_trace!(
+                "structure: duplicate_tail head={} nodes={} join={}",
+                head.index(),
+                chain.len(),
+                join.index()
+            );
+            for (i, &copy) in copies.iter().enumerate() {
+                let next = copies.get(i + 1).cloned().unwrap_or(join);
+                self.graph.add_edge(copy, next, CfgEdge::True);
+            }
+            let (e, pred, weight) = self
+                .graph
+                .edges_directed(head, Incoming)
+                .map(|e| (e.id(), e.source(), *e.weight()))
+                .last()
+                .unwrap();
+            self.graph.remove_edge(e);
+            self.graph.add_edge(pred, copies[0], weight);
+            self.report.duplicated.extend(chain.into_iter().zip(copies));
+        }
+    }
+
+    /// Why `nodes` can't be copied, if they can't: one of them must not be,
+    /// see [`set_no_duplicate`](Self::set_no_duplicate), or copying them
+    /// would go over `limit`.
+    fn decline_reason<I>(&self, limit: Option<DuplicationLimit>, nodes: I) -> Option<DeclineReason>
+    where
+        I: IntoIterator<Item = NodeIndex>,
+    {
+        let mut count = 0;
+        for n in nodes {
+            if self.no_duplicate.contains(n) {
+                return Some(DeclineReason::NoDuplicate(n));
+            }
+            count += 1;
+        }
+        if limit.is_some_and(|l| !l.allows(count, self.report.duplicated.len())) {
+            return Some(DeclineReason::Limit);
+        }
+        None
+    }
+
+    /// The chain of code nodes starting at `head`, if `head` is entered from
+    /// two different nodes and the chain goes on to a node that is also
+    /// reached without it, and that node. The other nodes of the chain may
+    /// only be entered from the chain.
+    fn shared_tail(&self, head: NodeIndex, max_len: usize) -> Option<(Vec<NodeIndex>, NodeIndex)> {
+        let in_degree = |n| self.graph.neighbors_directed(n, Incoming).count();
+        let mut preds = self.graph.neighbors_directed(head, Incoming);
+        match (preds.next(), preds.next(), preds.next()) {
+            (Some(p1), Some(p2), None) if p1 != p2 && max_len > 0 => (),
+            _ => return None,
+        }
+        let mut chain = vec![head];
+        let join = loop {
+            let n = *chain.last().unwrap();
+            if !matches!(self.graph[n], CfgNode::Code(_)) {
+                return None;
+            }
+            let succ = self.graph.neighbors(n).next()?;
+            let in_chain = matches!(self.graph[succ], CfgNode::Code(_)) && in_degree(succ) == 1;
+            if in_chain && chain.len() < max_len {
+                chain.push(succ);
+            } else {
+                break succ;
+            }
+        };
+        // otherwise the branches really join at `head`, or it is in a loop
+        let from_join: NodeSet = Dfs::new(&self.graph, join).iter(&self.graph).collect();
+        let last = *chain.last().unwrap();
+        let joins_elsewhere = self
+            .graph
+            .neighbors_directed(join, Incoming)
+            .any(|p| p != last && !from_join.contains(p));
+        if from_join.contains(head) || !joins_elsewhere {
+            return None;
+        }
+        Some((chain, join))
+    }
+
+    /// Copies each part of at most `max_nodes` nodes that more than one
+    /// branch enters, see [`StructuringOptions::split_shared_regions`], so
+    /// that each branch but the first goes on to a copy of its own, as far
+    /// as `limit` allows.
+    fn split_shared_regions(&mut self, max_nodes: usize, limit: Option<DuplicationLimit>) {
+        self.graph_changed();
+        let heads: Vec<_> = self.graph.node_indices().collect();
+        for head in heads {
+            let part = match self.shared_region(head, max_nodes) {
+                Some(part) => part,
+                None => continue,
+            };
+            let nodes = part.len();
+            let entries: Vec<_> = self
+                .graph
+                .edges_directed(head, Incoming)
+                .map(|e| (e.id(), e.source(), *e.weight()))
+                .collect();
+            for (e, pred, weight) in entries.into_iter().skip(1) {
+                if let Some(reason) = self.decline_reason(limit, &part) {
+                    self.report.declined.push(DeclinedCopy::SharedRegion {
+                        head,
+                        nodes,
+                        reason,
+                    });
+                    continue;
+                }
+                // each copy of a condition is tested on its own, so it
+                // needs a variable of its own too
+                let (graph, actx, cctx) = (&self.graph, &mut self.actx, self.cctx);
+                let mut cond_copies = Vec::new();
+                let copies = part
+                    .iter()
+                    .map(|n| match &graph[n] {
+                        CfgNode::Code(ast) => clone_ast(actx, ast).map(CfgNode::Code),
+                        CfgNode::Condition(c) => {
+                            let copy = cctx.new_var(actx.clone_cond(c)?);
+                            cond_copies.push((*c, copy));
+                            Some(CfgNode::Condition(copy))
+                        }
+                        CfgNode::Dummy(s) => Some(CfgNode::Dummy(s)),
+                    })
+                    .collect::<Option<Vec<_>>>();
+                let copy_of: HashMap<_, _> = match copies {
+                    Some(copies) => part
+                        .iter()
+                        .zip(copies)
+                        .map(|(n, copy)| (n, self.graph.add_node(copy)))
+                        .collect(),
+                    None => break,
+                };
+                for (c, copy) in cond_copies {
+                    let (key, copy_key) = (cond_var_key::<A>(c), cond_var_key::<A>(copy));
+                    if let Some(vs) = self.value_sets.get(&key).cloned() {
+                        self.value_sets.insert(copy_key, vs);
+                    }
+                    if let Some(&w) = self.branch_weights.get(&key) {
+                        self.branch_weights.insert(copy_key, w);
+                    }
+                }
+                radeco_trace!(
+                    "structure: split_shared_region head={} pred={} nodes={}",
+                    head.index(),
+                    pred.index(),
+                    nodes
+                );
+                let mut edges = Vec::new();
+                for n in &part {
+                    edges.extend(self.graph.edges(n).map(|e| (n, e.target(), *e.weight())));
+                }
+                for (n, succ, weight) in edges {
+                    let succ = copy_of.get(&succ).cloned().unwrap_or(succ);
+                    self.graph.add_edge(copy_of[&n], succ, weight);
+                }
+                self.graph.remove_edge(e);
+                self.graph.add_edge(pred, copy_of[&head], weight);
+                for n in &part {
+                    self.report.duplicated.push((n, copy_of[&n]));
+                }
+            }
+        }
+    }
+
+    /// The nodes that only `head` leads to, if `head` is entered from more
+    /// than one node, and they are no more than `max_nodes` and leave for
+    /// more than one node that doesn't go back to `head`. Their loops are
+    /// left out, so they are acyclic.
+    fn shared_region(&self, head: NodeIndex, max_nodes: usize) -> Option<NodeSet> {
+        if self.graph.neighbors_directed(head, Incoming).count() < 2 {
+            return None;
+        }
+        let mut part = NodeSet::with_capacity(self.graph.node_bound());
+        part.insert(head);
+        let succs = loop {
+            let succs = graph_utils::strict_successors_of_set(&self.graph, &part);
+            let only_from_part: Vec<_> = succs
+                .iter()
+                .filter(|&s| {
+                    self.graph
+                        .neighbors_directed(s, Incoming)
+                        .all(|p| part.contains(p))
+                })
+                .collect();
+            if only_from_part.is_empty() {
+                break succs;
+            }
+            part.extend(only_from_part);
+            if part.len() > max_nodes {
+                return None;
+            }
+        };
+        if succs.len() < 2 || self.graph.find_edge(head, head).is_some() {
+            return None;
+        }
+        let mut from_succs = Dfs::empty(&self.graph);
+        for s in &succs {
+            from_succs.move_to(s);
+            while let Some(n) = from_succs.next(&self.graph) {
+                if n == head {
+                    return None;
+                }
+            }
+        }
+        Some(part)
+    }
+
+    /// Returns the program structure tree of the graph, leaving out
+    /// `Unwind` and `Abnormal` edges and the handlers only they lead to.
+    pub fn region_tree(&self) -> RegionTree {
+        graph_utils::sese::region_tree(&self.normal_skeleton(), self.entry)
+    }
+
+    /// Returns the program structure tree of the graph like
+    /// [`region_tree`](Self::region_tree) does, the one that structuring
+    /// collapses the regions of, as nested regions with the set of their
+    /// nodes, e.g. for analyses of each region on its own. The root is the
+    /// whole graph, and [`NestedRegion::iter`] goes through all regions.
+    pub fn regions(&self) -> NestedRegion {
+        self.region_tree().nest(self.graph.node_bound())
+    }
+
+    /// Whether each loop on the normal path of the graph can only be entered
+    /// at its header, see [`check_reducible`](Self::check_reducible).
+    pub fn is_reducible(&self) -> bool {
+        self.check_reducible().is_ok()
+    }
+
+    /// Checks whether each loop on the normal path of the graph can only be
+    /// entered at its header, and otherwise returns the loops that can be
+    /// entered elsewhere too, which structuring would have to dispatch on a
+    /// variable to enter or, with
+    /// [`StructuringOptions::max_duplicated_nodes`], copy nodes for. Unlike
+    /// [`analyze`](Self::analyze), this doesn't depend on how structuring
+    /// goes about it.
+    pub fn check_reducible(&self) -> Result<(), Vec<IrreducibleRegion>> {
+        graph_utils::check_reducible(&self.normal_skeleton(), self.entry)
+    }
+
+    /// Finds how structuring would decompose the normal path of the graph,
+    /// without structuring it or changing the graph: its loops, as
+    /// structuring finds and collapses them, which of them are entered or
+    /// left in more than one place, and its region tree. This is for the
+    /// default options; copying, as with
+    /// [`StructuringOptions::max_duplicated_nodes`], can make fewer loops
+    /// irreducible.
+    pub fn analyze(&self) -> Decomposition {
+        let mut graph = self.normal_skeleton();
+        let (podfs_trace, back_edges) = postorder_and_back_edges(&graph, self.entry);
+        let mut loops: Vec<LoopInfo> = Vec::new();
+        let mut visited = NodeSet::with_capacity(graph.node_bound());
+        for &header in &podfs_trace {
+            visited.insert(header);
+            if !back_edges.is_header(header) {
+                continue;
+            }
+            // the same as `structure_graph` does, on the graph with each
+            // inner loop collapsed into its header
+            let backedges: Vec<_> = graph
+                .edges_directed(header, Incoming)
+                .filter(|e| visited.contains(e.source()))
+                .map(|e| (e.id(), e.source()))
+                .collect();
+            let latch_nodes: NodeSet = backedges.iter().map(|&(_, latch)| latch).collect();
+            for &(e, _) in &backedges {
+                graph.remove_edge(e);
+            }
+            let mut loop_nodes = graph_utils::slice(&graph, header, &latch_nodes).nodes;
+            let outside_preds = |n| {
+                graph
+                    .neighbors_directed(n, Incoming)
+                    .filter(|&p| !loop_nodes.contains(p))
+                    .collect::<Vec<_>>()
+            };
+            let entries: Vec<_> = loop_nodes
+                .iter()
+                .filter(|&n| n != header && !outside_preds(n).is_empty())
+                .collect();
+            let entry_preds: Vec<_> = entries.iter().flat_map(|&n| outside_preds(n)).collect();
+            let mut succ_nodes = graph_utils::strict_successors_of_set(&graph, &loop_nodes);
+            refine_loop_nodes(&graph, &mut loop_nodes, &mut succ_nodes);
+            let final_succ = DfsPostOrder::new(&graph, self.entry)
+                .iter(&graph)
+                .find(|&n| succ_nodes.contains(n));
+            let exits = succ_nodes
+                .iter()
+                .filter(|&n| Some(n) != final_succ)
+                .collect();
+
+            // collapse the loop: its header is entered wherever it was, and
+            // goes on to wherever it was left for
+            let mut nodes: Vec<_> = loop_nodes.iter().collect();
+            let index = loops.len();
+            for inner in &mut loops {
+                if inner.parent.is_none() && loop_nodes.contains(inner.header) {
+                    inner.parent = Some(index);
+                    nodes.extend(&inner.nodes);
+                }
+            }
+            nodes.sort();
+            nodes.dedup();
+            for n in &loop_nodes {
+                if n != header {
+                    graph.remove_node(n);
+                }
+            }
+            let out_edges: Vec<_> = graph.edges(header).map(|e| e.id()).collect();
+            for e in out_edges {
+                graph.remove_edge(e);
+            }
+            for p in entry_preds {
+                graph.add_edge(p, header, CfgEdge::True);
+            }
+            for s in &succ_nodes {
+                graph.add_edge(header, s, CfgEdge::True);
+            }
+            loops.push(LoopInfo {
+                header,
+                nodes,
+                parent: None,
+                entries,
+                exits,
+            });
+        }
+        Decomposition {
+            loops,
+            regions: self.region_tree(),
+        }
+    }
+
+    /// The shape of the graph without `Unwind` and `Abnormal` edges.
+    fn normal_skeleton(&self) -> StableDiGraph<(), CfgEdge> {
+        let mut graph = self.graph.map(|_, _| (), |_, &e| e);
+        graph.retain_edges(|g, e| !g[e].is_abnormal());
+        graph
+    }
+
+    pub fn structure_whole(self) -> (AstNode<'cd, A>, A) {
+        self.structure_whole_with(&StructuringOptions::default())
+    }
+
+    /// Structures the graph. The handlers that `Unwind` and `Abnormal` edges
+    /// lead to are left out; use [`structure_with_handlers`](Self::structure_with_handlers)
+    /// to get them too.
+    pub fn structure_whole_with(self, opts: &StructuringOptions) -> (AstNode<'cd, A>, A) {
+        let (ast, _, actx) = self.structure_with_handlers(opts);
+        (ast, actx)
+    }
+
+    /// Structures the graph, and each handler that `Unwind` or `Abnormal`
+    /// edges lead to on its own. A handler is its landing pad and the code
+    /// reachable from it that isn't on the normal path or part of an
+    /// earlier handler; its edges back to those are cut, so its AST ends
+    /// where it would rejoin them.
+    ///
+    /// Returns the AST of the normal path, the ASTs of the handlers, indexed
+    /// by `HandlerId`, and the context. In both, the code of each node with
+    /// an `Unwind` edge is wrapped in a `Try` naming the handler it leads to.
+    /// The code of a node with an `Abnormal` edge is left as is; the report
+    /// of [`structure_whole_reported`](Self::structure_whole_reported)
+    /// annotates it with the handler instead.
+    pub fn structure_with_handlers(
+        self,
+        opts: &StructuringOptions,
+    ) -> (AstNode<'cd, A>, Vec<AstNode<'cd, A>>, A) {
+        let (ast, handler_asts, actx, _) = self.structure_all(opts);
+        (ast, handler_asts, actx)
+    }
+
+    /// Like [`structure_whole_with`](Self::structure_whole_with), but also
+    /// reports what structuring did.
+    pub fn structure_whole_reported(
+        self,
+        opts: &StructuringOptions,
+    ) -> (AstNode<'cd, A>, A, StructuringReport) {
+        let (ast, _, actx, report) = self.structure_all(opts);
+        (ast, actx, report)
+    }
+
+    /// Like [`structure_whole_reported`](Self::structure_whole_reported),
+    /// but fails instead of panicking: on a graph that doesn't meet the
+    /// preconditions of [`new`](Self::new), on an invariant that structuring
+    /// finds broken along the way, and, as a last resort, on any other panic,
+    /// which is caught. The panic is still reported to the panic hook.
+    pub fn structure_whole_checked(
+        self,
+        opts: &StructuringOptions,
+    ) -> Result<(AstNode<'cd, A>, A, StructuringReport), StructureError> {
+        self.validate()?;
+        match panic::catch_unwind(AssertUnwindSafe(move || self.try_structure_all(opts))) {
+            Ok(res) => res.map(|(ast, _, actx, report)| (ast, actx, report)),
+            Err(payload) => {
+                let detail = if let Some(msg) = payload.downcast_ref::<&str>() {
+                    (*msg).to_owned()
+                } else if let Some(msg) = payload.downcast_ref::<String>() {
+                    msg.clone()
+                } else {
+                    "panicked".to_owned()
+                };
+                Err(internal("structure_whole_checked", detail))
+            }
+        }
+    }
+
+    /// Structures the part of the graph reachable from `root` as if `root`
+    /// were the entry, e.g. the code from one branch of an `if` on. The
+    /// edges into that part from the rest of the graph are left out, and so
+    /// are the handlers, like [`structure_whole_with`](Self::structure_whole_with)
+    /// does. The graph itself is left as it is.
+    pub fn structure_from(
+        &self,
+        root: NodeIndex,
+        opts: &StructuringOptions,
+    ) -> Result<AstNode<'cd, A>, StructureError>
+    where
+        A: Clone,
+        A::Block: Clone,
+    {
+        if !self.graph.contains_node(root) {
+            return Err(internal(
+                "structure_from",
+                format!("no node {}", root.index()),
+            ));
+        }
+        let part = Dfs::new(&self.graph, root).iter(&self.graph).collect();
+        self.structure_part(root, &part, opts)
+    }
+
+    /// Like [`structure_from`](Self::structure_from), but only structures
+    /// the nodes that `root` dominates, e.g. the body of one branch of an
+    /// `if`. The edges leaving them are left out too, except for those of
+    /// condition nodes, which go to a new empty node instead.
+    pub fn structure_dominated(
+        &self,
+        root: NodeIndex,
+        opts: &StructuringOptions,
+    ) -> Result<AstNode<'cd, A>, StructureError>
+    where
+        A: Clone,
+        A::Block: Clone,
+    {
+        if !self.graph.contains_node(root) {
+            return Err(internal(
+                "structure_dominated",
+                format!("no node {}", root.index()),
+            ));
+        }
+        let part = self.dominated_by(root);
+        self.structure_part(root, &part, opts)
+    }
+
+    /// Structures a copy of the nodes of `part`, which must all be reachable
+    /// from `root` within it, entered at `root`.
+    fn structure_part(
+        &self,
+        root: NodeIndex,
+        part: &NodeSet,
+        opts: &StructuringOptions,
+    ) -> Result<AstNode<'cd, A>, StructureError>
+    where
+        A: Clone,
+        A::Block: Clone,
+    {
+        let mut graph = StableDiGraph::new();
+        let mut copy_of = HashMap::new();
+        for n in part {
+            let node = match &self.graph[n] {
+                CfgNode::Code(ast) => CfgNode::Code(ast.clone()),
+                CfgNode::Condition(c) => CfgNode::Condition(*c),
+                CfgNode::Dummy(s) => CfgNode::Dummy(s),
+            };
+            copy_of.insert(n, graph.add_node(node));
+        }
+        let mut exit = None;
+        for e in self.graph.edge_references() {
+            let source = match copy_of.get(&e.source()) {
+                Some(&source) => source,
+                None => continue,
+            };
+            match copy_of.get(&e.target()) {
+                Some(&target) => {
+                    graph.add_edge(source, target, *e.weight());
+                }
+                // a condition node must keep both of its successors
+                None if matches!(self.graph[e.source()], CfgNode::Condition(_)) => {
+                    let exit = *exit.get_or_insert_with(|| graph.add_node(empty_node()));
+                    graph.add_edge(source, exit, *e.weight());
+                }
+                None => (),
+            }
+        }
+        let mut entry = copy_of[&root];
+        // `root` may head a loop, but the entry must be a source
+        if graph.neighbors_directed(entry, Incoming).next().is_some() {
+            let preheader = graph.add_node(empty_node());
+            graph.add_edge(preheader, entry, CfgEdge::True);
+            entry = preheader;
+        }
+        let mut cfg = ControlFlowGraph::new(graph, entry, self.cctx, self.actx.clone());
+        cfg.value_sets = self.value_sets.clone();
+        cfg.branch_weights = self.branch_weights.clone();
+        cfg.try_structure_all(opts).map(|(ast, ..)| ast)
+    }
+
+    fn structure_all(self, opts: &StructuringOptions) -> Structured<'cd, A> {
+        self.try_structure_all(opts)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn try_structure_all(
+        self,
+        opts: &StructuringOptions,
+    ) -> Result<Structured<'cd, A>, StructureError> {
+        let structured = self.into_block_store().structure_stored(opts)?;
+        Ok(block_store::take_blocks(structured))
+    }
+
+    /// The body of `try_structure_all`, with the blocks in a
+    /// [`BlockStore`](block_store::BlockStore).
+    fn structure_stored(
+        mut self,
+        opts: &StructuringOptions,
+    ) -> Result<Structured<'cd, A>, StructureError> {
+        if opts.check_invariants {
+            self.validate()?;
+        }
+        if opts.merge_linear_chains {
+            self.merge_linear_chains();
+        }
+        if opts.split_critical_edges {
+            self.split_critical_edges();
+        }
+        if opts.insert_preheaders {
+            self.insert_preheaders();
+        }
+        if opts.split_latches {
+            self.split_latches();
+        }
+        self.trace = opts.trace.clone();
+        self.observer = opts.observer.clone();
+        self.namer = Some(
+            opts.namer
+                .clone()
+                .unwrap_or_else(|| Rc::new(RefCell::new(CounterNamer::default()))),
+        );
+        self.cancel = opts.cancel.clone();
+        self.budget = opts.budget.map(|b| match b {
+            Budget::Steps(steps) => BudgetLeft::Steps(steps),
+            Budget::Time(time) => BudgetLeft::Until(Instant::now() + time),
+        });
+        self.dump = opts.dump_dir.as_deref().map(Dumper::new);
+        let guard_edges = self.guard_edges();
+        let start = Instant::now();
+        let (handlers, reentries) = self.split_handlers()?;
+        self.report.times.split_handlers = start.elapsed();
+        let ast = self.structure_graph(opts)?;
+        let handler_asts = handlers
+            .into_iter()
+            .map(|(graph, landing_pad, no_duplicate)| {
+                self.check_cancelled()?;
+                self.graph = graph;
+                self.entry = landing_pad;
+                self.no_duplicate = no_duplicate;
+                self.structure_graph(opts)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (ast, handler_asts) = if opts.fold_struct_vars {
+            let before = self.shapes(&ast, &handler_asts);
+            let (cctx, actx, vars) = (self.cctx, &self.actx, &self.struct_vars);
+            let decisions = self.decisions.borrow();
+            let copied: Vec<_> = vars
+                .iter()
+                .map(|sv| sv.tests.iter().any(|&(t, _)| decisions.is_copied(t)))
+                .collect();
+            let fold = |a| struct_vars::fold(cctx, actx, vars, &copied, a);
+            let (ast, handler_asts) = (
+                fold(ast),
+                handler_asts.into_iter().map(fold).collect::<Vec<_>>(),
+            );
+            self.refined("fold_struct_vars", before, &ast, &handler_asts);
+            (ast, handler_asts)
+        } else {
+            (ast, handler_asts)
+        };
+        let (ast, handler_asts) = if opts.refine_loops {
+            let before = self.shapes(&ast, &handler_asts);
+            let (cctx, actx) = (self.cctx, &self.actx);
+            let mut guards = Vec::new();
+            let mut unrotate =
+                |a| invariants::unrotate(cctx, a, &mut |b, c| actx.may_modify(b, c), &mut guards);
+            let ast = unrotate(ast);
+            let handler_asts: Vec<_> = handler_asts.into_iter().map(&mut unrotate).collect();
+            let mut decisions = self.decisions.borrow_mut();
+            for g in guards {
+                decisions.refined_loop(g, "Unrotate");
+            }
+            self.refined("unrotate_loops", before, &ast, &handler_asts);
+            (ast, handler_asts)
+        } else {
+            (ast, handler_asts)
+        };
+        let (ast, handler_asts) = match opts.guard_clauses {
+            Some(min_depth) => {
+                let before = self.shapes(&ast, &handler_asts);
+                let cctx = self.cctx;
+                let unnest = |a| refinement::guard_clauses::<A>(cctx, a, min_depth);
+                let (ast, handler_asts) = (
+                    unnest(ast),
+                    handler_asts.into_iter().map(unnest).collect::<Vec<_>>(),
+                );
+                self.refined("guard_clauses", before, &ast, &handler_asts);
+                (ast, handler_asts)
+            }
+            None => (ast, handler_asts),
+        };
+        // before the reentries are unwrapped, whose code keeps a node of its
+        // own for its annotation
+        let (ast, handler_asts) = if opts.merge_contiguous_blocks {
+            let before = self.shapes(&ast, &handler_asts);
+            let actx = &mut self.actx;
+            let ast = contiguous_blocks::merge(actx, ast);
+            let handler_asts: Vec<_> = handler_asts
+                .into_iter()
+                .map(|a| contiguous_blocks::merge(actx, a))
+                .collect();
+            self.refined("merge_contiguous_blocks", before, &ast, &handler_asts);
+            (ast, handler_asts)
+        } else {
+            (ast, handler_asts)
+        };
+        let mut returns_again = Vec::new();
+        let (ast, handler_asts) = if reentries.is_empty() {
+            (ast, handler_asts)
+        } else {
+            let mut offset = 0;
+            let mut unwrap = |a| {
+                let (a, found) = unwrap_reentries(a, &reentries);
+                returns_again.extend(
+                    found
+                        .into_iter()
+                        .map(|(id, h)| (decisions::AstNodeId(id.0 + offset), h)),
+                );
+                offset += decisions::preorder(&a).len();
+                a
+            };
+            let ast = unwrap(ast);
+            (ast, handler_asts.into_iter().map(unwrap).collect())
+        };
+        self.report.gotos = iter::once(&ast).chain(&handler_asts).map(count_gotos).sum();
+        self.report.labels =
+            naming::name_labels(self.namer(), iter::once(&ast).chain(&handler_asts));
+        let oracle = ContextOracle(&self.actx);
+        let matchers: Vec<&dyn IdiomMatcher> = opts.matchers.iter().map(|m| &**m).collect();
+        let mut offset = 0;
+        for a in iter::once(&ast).chain(&handler_asts) {
+            let mut found = matchers::run(a, &oracle, &matchers);
+            if opts.summarize_loop_exits {
+                let actx = &self.actx;
+                let exits = loop_exits::summarize(self.cctx, a, &mut |c| actx.describe_cond(c));
+                found.extend(exits.into_iter().map(|(id, e)| (id, Annotation::Exits(e))));
+            }
+            self.report.annotations.extend(
+                found
+                    .into_iter()
+                    .map(|(id, an)| (decisions::AstNodeId(id.0 + offset), an)),
+            );
+            offset += decisions::preorder(a).len();
+        }
+        self.report.annotations.extend(
+            returns_again
+                .into_iter()
+                .map(|(id, h)| (id, Annotation::ReturnsAgain(h))),
+        );
+        self.report.annotations.sort_by_key(|&(id, _)| id);
+        self.report.state_machines = self
+            .report
+            .annotations
+            .iter()
+            .filter_map(|(id, an)| match an {
+                Annotation::StateMachine(sm) => Some((*id, sm.clone())),
+                _ => None,
+            })
+            .collect();
+        let value_sets = &self.value_sets;
+        let switch_edges = |var: &A::Variable| {
+            value_sets
+                .iter()
+                .filter(|(_, (v, ..))| v == var)
+                .flat_map(|(&key, _)| guard_edges.get(&(key, true)).into_iter().flatten())
+                .cloned()
+                .collect()
+        };
+        self.report.decisions = self.decisions.borrow().explain(
+            self.cctx,
+            &guard_edges,
+            switch_edges,
+            iter::once(&ast).chain(&handler_asts),
+        );
+        self.report.flipped = self
+            .decisions
+            .borrow()
+            .flipped_ids(iter::once(&ast).chain(&handler_asts));
+        Ok((ast, handler_asts, self.actx, self.report))
+    }
+
+    /// The edges out of each condition node, for [`Decisions`].
+    fn guard_edges(&self) -> GuardEdges {
+        let mut edges = GuardEdges::new();
+        for n in self.graph.node_indices() {
+            if let CfgNode::Condition(c) = self.graph[n] {
+                for e in self.graph.edges(n) {
+                    let holds = match e.weight() {
+                        CfgEdge::True => true,
+                        CfgEdge::False => false,
+                        CfgEdge::Unwind | CfgEdge::Abnormal => continue,
+                    };
+                    let key = (cond_var_key::<A>(c), holds);
+                    edges.entry(key).or_default().push((n, e.target()));
+                }
+            }
+        }
+        edges
+    }
+
+    /// Moves the handlers out of the graph, leaving only the normal path and
+    /// no `Unwind` or `Abnormal` edges, and wraps the nodes that had one in a
+    /// `Try`. Returns the graph and landing pad of each handler, ordered by
+    /// the index of the landing pad, and the handlers that `Abnormal` edges
+    /// lead to.
+    fn split_handlers(
+        &mut self,
+    ) -> Result<(Vec<HandlerGraph<'cd, A>>, Vec<HandlerId>), StructureError> {
+        let unwinds: Vec<_> = self
+            .graph
+            .edge_references()
+            .filter(|e| e.weight().is_abnormal())
+            .map(|e| (e.id(), e.source(), e.target(), *e.weight()))
+            .collect();
+        if !unwinds.is_empty() {
+            self.graph_changed();
+        }
+        let mut landing_pads: Vec<_> = unwinds.iter().map(|&(_, _, pad, _)| pad).collect();
+        landing_pads.sort();
+        landing_pads.dedup();
+
+        let mut reentries = Vec::new();
+        for &(e, src, pad, kind) in &unwinds {
+            self.graph.remove_edge(e);
+            let handler = HandlerId(landing_pads.binary_search(&pad).unwrap());
+            if !kind.is_unwind() {
+                reentries.push(handler);
+            }
+            match &mut self.graph[src] {
+                CfgNode::Code(ast) => *ast = AstNodeC::Try(Box::new(mem::take(ast)), handler),
+                _ => {
+                    return Err(internal(
+                        "split_handlers",
+                        format!("unwind edge from condition node {}", src.index()),
+                    ))
+                }
             }
         }
-    }
 
-    pub fn structure_whole(mut self) -> (AstNode<'cd, A>, A) {
-        let mut loop_headers = NodeSet::new();
-        let mut podfs_trace = Vec::new();
-        graph_utils::depth_first_search(&self.graph, self.entry, |ev| {
-            use self::graph_utils::DfsEvent::*;
-            match ev {
-                BackEdge(e) => {
-                    loop_headers.insert(e.target());
+        let mut taken: NodeSet = Dfs::new(&self.graph, self.entry)
+            .iter(&self.graph)
+            .collect();
+        let mut handlers = Vec::with_capacity(landing_pads.len());
+        for pad in landing_pads {
+            let mut nodes = vec![pad];
+            taken.insert(pad);
+            let mut stack = vec![pad];
+            while let Some(n) = stack.pop() {
+                for m in self.graph.neighbors(n) {
+                    if taken.insert(m) {
+                        nodes.push(m);
+                        stack.push(m);
+                    }
                 }
-                Finish(n) => podfs_trace.push(n),
-                _ => (),
             }
-        });
-        let (podfs_trace, loop_headers) = (podfs_trace, loop_headers);
+            radeco_trace!(
+                "structure: handler landing_pad={} nodes={}",
+                pad.index(),
+                nodes.len()
+            );
+
+            let mut graph = StableDiGraph::with_capacity(nodes.len() + 1, nodes.len());
+            let mut old_new_map = HashMap::with_capacity(nodes.len());
+            for &n in &nodes {
+                let cfg_node = mem::replace(&mut self.graph[n], CfgNode::Dummy("moved to handler"));
+                old_new_map.insert(n, graph.add_node(cfg_node));
+            }
+            // where the handler rejoins the rest of the function
+            let mut rejoin = None;
+            for &n in &nodes {
+                for e in self.graph.edges(n) {
+                    let dst = match old_new_map.get(&e.target()) {
+                        Some(&m) => m,
+                        None => *rejoin.get_or_insert_with(|| {
+                            graph.add_node(CfgNode::Code(AstNodeC::default()))
+                        }),
+                    };
+                    graph.add_edge(old_new_map[&n], dst, *e.weight());
+                }
+            }
+            let no_duplicate = nodes
+                .iter()
+                .filter(|&&n| self.no_duplicate.remove(n))
+                .map(|n| old_new_map[n])
+                .collect();
+            for &n in &nodes {
+                self.graph.remove_node(n);
+            }
+            handlers.push((graph, old_new_map[&pad], no_duplicate));
+        }
+        reentries.sort();
+        reentries.dedup();
+        Ok((handlers, reentries))
+    }
+
+    /// Structures `self.graph`, which must have no `Unwind` or `Abnormal`
+    /// edges, into a single AST, leaving it empty.
+    fn structure_graph(
+        &mut self,
+        opts: &StructuringOptions,
+    ) -> Result<AstNode<'cd, A>, StructureError> {
+        radeco_trace!(
+            "structure: graph entry={} nodes={} edges={}",
+            self.entry.index(),
+            self.graph.node_count(),
+            self.graph.edge_count()
+        );
+        self.fold_constant_conditions();
+        if let Some(max_len) = opts.duplicate_tails {
+            self.duplicate_shared_tails(max_len, opts.max_duplicated_nodes);
+        }
+        if let Some(max_nodes) = opts.split_shared_regions {
+            self.split_shared_regions(max_nodes, opts.max_duplicated_nodes);
+        }
+        if opts.collapse_sese_regions {
+            let start = Instant::now();
+            self.structure_acyclic_sese_regions(opts)?;
+            self.report.times.sese_regions += start.elapsed();
+        }
+        let start = Instant::now();
+
+        let (podfs_trace, back_edges) = postorder_and_back_edges(&self.graph, self.entry);
 
-        let mut visited = NodeSet::with_capacity(self.graph.node_bound());
+        // original nodes not visited yet, the DFS ancestors of the current
+        // one among them. Nodes are only removed once visited, so their
+        // indices aren't reused
+        let mut pending: NodeSet = podfs_trace.iter().cloned().collect();
+        let mut progress = Progress::new();
         for &cur_node in &podfs_trace {
-            visited.insert(cur_node);
+            pending.remove(cur_node);
+            radeco_detail!("structure: visit node={}", cur_node.index());
 
-            if loop_headers.contains(cur_node) {
+            if back_edges.is_header(cur_node) {
                 // loop
+                self.check_cancelled()?;
+                if !self.take_step() {
+                    break;
+                }
+                progress.start(&self.graph);
 
                 // find latch nodes
                 let mut backedges = EdgeSet::new();
                 let mut latch_nodes = NodeSet::new();
+                let mut latch_edges = Vec::new();
                 for edge in self.graph.edges_directed(cur_node, Incoming) {
-                    // backedges are always from original graph nodes
-                    if visited.contains(edge.source()) {
+                    // any edge from a visited node, or from one made since,
+                    // like a copy or the exit cascade of an inner loop, to a
+                    // pending one goes back up the DFS tree
+                    if !pending.contains(edge.source()) {
                         backedges.insert(edge.id());
                         latch_nodes.insert(edge.source());
+                        latch_edges.push((edge.source(), *edge.weight()));
                     }
                 }
 
+                self.observe(|o| o.on_loop_found(cur_node, &self.node_bits(&latch_nodes)));
+
                 // remove backedges
+                self.graph_changed();
                 for e in &backedges {
                     self.graph.remove_edge(e);
                 }
 
                 // regionify loop
                 let mut loop_nodes = graph_utils::slice(&self.graph, cur_node, &latch_nodes).nodes;
-                let loop_header = self.funnel_abnormal_entries(cur_node, &loop_nodes);
+                // a natural loop only has descendants of its header, but in an
+                // irreducible graph the slice can go back up through ancestors;
+                // these are entered from outside and get their own loops later
+                loop_nodes.difference_with(&pending);
+                radeco_trace!(
+                    "structure: loop header={} nodes={} latches={}",
+                    cur_node.index(),
+                    loop_nodes.len(),
+                    latch_nodes.len()
+                );
+                self.report.loops += 1;
+                if let Some(limit) = opts.max_duplicated_nodes {
+                    self.split_abnormal_entries(limit, cur_node, &loop_nodes, &latch_edges);
+                }
+                let loop_header = self.funnel_abnormal_entries(cur_node, &loop_nodes)?;
                 let mut succ_nodes =
                     graph_utils::strict_successors_of_set(&self.graph, &loop_nodes);
-                self.refine_loop(&mut loop_nodes, &mut succ_nodes);
+                refine_loop_nodes(&self.graph, &mut loop_nodes, &mut succ_nodes);
+                // the body of the loop goes back to its header when it falls
+                // off the end, so the nodes ending the function must return.
+                // The latches only look like they do
+                let mut body_sinks = loop_nodes.clone();
+                body_sinks.difference_with(&latch_nodes);
+                self.terminate_sinks(&body_sinks);
                 let loop_succ_opt = {
                     // pick the successor with the smallest post-order
                     let final_succ_opt = DfsPostOrder::new(&self.graph, self.entry)
@@ -159,54 +1951,554 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
                     graph_utils::strict_successors_of_set(&self.graph, &loop_nodes).len() <= 1
                 );
 
-                let loop_body = self.structure_acyclic_sese_region(loop_header, &loop_nodes);
-                let repl_ast = refinement::refine_loop::<A>(self.cctx, loop_body);
+                if let Some(dump) = &mut self.dump {
+                    let back_edges: Vec<_> = latch_nodes.iter().map(|l| (l, cur_node)).collect();
+                    dump.before(&self.graph, self.entry, cur_node, &loop_nodes, &back_edges);
+                }
+                let loop_body =
+                    self.structure_acyclic_sese_region(opts, loop_header, &loop_nodes)?;
+                let repl_ast = if opts.refine_loops {
+                    let ast = refinement::refine_loop::<A>(self.cctx, &self.decisions, loop_body);
+                    let changed = !matches!(ast, AstNodeC::Loop(ast::LoopType::Endless, _));
+                    self.observe(|o| o.on_refinement("refine_loop", changed));
+                    ast
+                } else {
+                    AstNodeC::Loop(ast::LoopType::Endless, Box::new(loop_body))
+                };
+                if let Some(dump) = &mut self.dump {
+                    dump.after(cur_node, &repl_ast);
+                }
                 self.graph[loop_header] = CfgNode::Code(repl_ast);
+                self.trace(TraceOp::LoopCollapse, cur_node, &loop_nodes, loop_header);
+                self.collapse_marks(&loop_nodes, loop_header);
+                self.observe(|o| {
+                    o.on_region_collapsed(cur_node, &self.node_bits(&loop_nodes), loop_header)
+                });
                 if let Some(loop_succ) = loop_succ_opt {
                     self.graph.add_edge(loop_header, loop_succ, CfgEdge::True);
                 }
+                if !progress.made(&self.graph) {
+                    return Err(self.no_progress());
+                }
             } else {
                 // acyclic
-                let region = graph_utils::dominated_by(&self.graph, self.entry, cur_node);
+                let region = self.dominated_by(cur_node);
                 // single-block regions aren't interesting
-                if region.len() > 1 {
+                if region.len() > 1 && !self.continues_switch(cur_node) {
                     let succs = graph_utils::strict_successors_of_set(&self.graph, &region);
-                    // `region` must have one or zero successors
-                    if succs.len() <= 1 {
-                        let opt_succ = succs.iter().next();
-                        let repl_ast = self.structure_acyclic_sese_region(cur_node, &region);
-                        self.graph[cur_node] = CfgNode::Code(repl_ast);
-                        if let Some(succ) = opt_succ {
-                            self.graph.add_edge(cur_node, succ, CfgEdge::True);
+                    // `region` must have one or zero successors. A region
+                    // that ends the function somewhere is left to an
+                    // enclosing one if it has a successor, since the
+                    // collapsed node would always go on to it
+                    if succs.is_empty() || (succs.len() == 1 && !self.has_sink(&region)) {
+                        self.check_cancelled()?;
+                        if !self.take_step() {
+                            break;
+                        }
+                        radeco_trace!(
+                            "structure: acyclic region header={} nodes={}",
+                            cur_node.index(),
+                            region.len()
+                        );
+                        progress.start(&self.graph);
+                        #[cfg(test)]
+                        let disabled = opts.disabled_collapse == Some(TraceOp::AcyclicCollapse);
+                        #[cfg(not(test))]
+                        let disabled = false;
+                        if !disabled {
+                            self.collapse_acyclic_region(
+                                opts,
+                                TraceOp::AcyclicCollapse,
+                                cur_node,
+                                &region,
+                                succs.iter().next(),
+                            )?;
+                        }
+                        if !progress.made(&self.graph) {
+                            return Err(self.no_progress());
                         }
+                    } else {
+                        radeco_detail!(
+                            "structure: acyclic region header={} nodes={} successors={} left to \
+                             an enclosing region",
+                            cur_node.index(),
+                            region.len(),
+                            succs.len()
+                        );
                     }
                 }
             }
         }
 
-        let ret = self.graph.remove_node(self.entry).unwrap();
-        debug_assert!(self.graph.node_count() == 0);
+        if self.report.budget_exhausted {
+            let ret = self.virtualize_edges(None, |n| LabelId(n.index()));
+            self.report.times.main += start.elapsed();
+            return ret;
+        }
+        if self.graph.node_count() > 1 {
+            return Err(self.no_progress());
+        }
+        let ret = self
+            .graph
+            .remove_node(self.entry)
+            .ok_or_else(|| internal("structure_graph", "the entry is gone".to_owned()))?;
+        self.report.times.main += start.elapsed();
 
         if let CfgNode::Code(ret) = ret {
-            (ret, self.actx)
+            Ok(ret)
         } else {
-            panic!("last node wasn't a Code node")
+            Err(internal(
+                "structure_graph",
+                "the last node isn't a code node".to_owned(),
+            ))
+        }
+    }
+
+    /// The error for a graph that structuring stopped shrinking, which is
+    /// also dumped, see [`StructureError::NoProgress`].
+    fn no_progress(&mut self) -> StructureError {
+        let headers: Vec<_> = self
+            .graph
+            .node_indices()
+            .filter(|&n| n != self.entry)
+            .collect();
+        let graph = dump::residual_blocks(&self.graph, self.entry);
+        radeco_warn!(
+            "structure: no progress nodes={} edges={}",
+            self.graph.node_count(),
+            self.graph.edge_count()
+        );
+        if let Some(dump) = &mut self.dump {
+            dump.residual(&graph);
+        }
+        StructureError::NoProgress { headers, graph }
+    }
+
+    /// Fails once the token of the options is cancelled.
+    fn check_cancelled(&self) -> Result<(), StructureError> {
+        match &self.cancel {
+            Some(token) if token.is_cancelled() => {
+                radeco_warn!(
+                    "structure: cancelled with nodes={} left",
+                    self.graph.node_count()
+                );
+                Err(StructureError::Cancelled)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes a step out of the budget, if there is one. Returns `false`, from
+    /// then on, once it is used up.
+    fn take_step(&mut self) -> bool {
+        if self.report.budget_exhausted {
+            return false;
+        }
+        let left = match &mut self.budget {
+            None => true,
+            Some(BudgetLeft::Steps(0)) => false,
+            Some(BudgetLeft::Steps(steps)) => {
+                *steps -= 1;
+                true
+            }
+            Some(BudgetLeft::Until(deadline)) => Instant::now() < *deadline,
+        };
+        if !left {
+            radeco_warn!(
+                "structure: budget exhausted with nodes={} left, virtualizing edges={}",
+                self.graph.node_count(),
+                self.graph.edge_count()
+            );
+            self.report.budget_exhausted = true;
+        }
+        left
+    }
+
+    /// Lays out what is left of the graph in reverse post-order, each node
+    /// labeled and going on to its successors through `Goto`s unless it
+    /// falls through to them, leaving the graph empty. `last`, if given, is
+    /// laid out last instead, and `label` gives the label of each node.
+    fn virtualize_edges<F>(
+        &mut self,
+        last: Option<NodeIndex>,
+        label: F,
+    ) -> Result<AstNode<'cd, A>, StructureError>
+    where
+        F: Fn(NodeIndex) -> LabelId,
+    {
+        let (members, targets) = self.lay_out(last, label)?;
+        let mut seq = Vec::with_capacity(2 * members.len());
+        for (l, ast) in members {
+            // only keep the labels something jumps to
+            if targets.contains(&l) {
+                seq.push(AstNodeC::Label(l));
+            }
+            seq.push(ast);
+        }
+        Ok(refinement::simplify_ast_node::<A>(self.cctx, AstNodeC::Seq(seq)).unwrap_or_default())
+    }
+
+    /// Like [`virtualize_edges`](Self::virtualize_edges), but keeps the
+    /// layout as an `Unstructured` region, with all its labels.
+    fn unstructured<F>(
+        &mut self,
+        last: Option<NodeIndex>,
+        label: F,
+    ) -> Result<AstNode<'cd, A>, StructureError>
+    where
+        F: Fn(NodeIndex) -> LabelId,
+    {
+        let (members, _) = self.lay_out(last, label)?;
+        let ast = AstNodeC::Unstructured(members);
+        Ok(refinement::simplify_ast_node::<A>(self.cctx, ast).unwrap_or_default())
+    }
+
+    /// Lays out the graph for [`virtualize_edges`](Self::virtualize_edges).
+    fn lay_out<F>(
+        &mut self,
+        last: Option<NodeIndex>,
+        label: F,
+    ) -> Result<Layout<'cd, A>, StructureError>
+    where
+        F: Fn(NodeIndex) -> LabelId,
+    {
+        self.graph_changed();
+        let mut order: Vec<_> = DfsPostOrder::new(&self.graph, self.entry)
+            .iter(&self.graph)
+            .filter(|&n| Some(n) != last)
+            .collect();
+        order.reverse();
+        order.extend(last);
+        debug_assert!(order.len() == self.graph.node_count());
+
+        let mut targets = HashSet::new();
+        let mut members = Vec::with_capacity(order.len());
+        for (i, &n) in order.iter().enumerate() {
+            let next = order.get(i + 1).cloned();
+            let mut goto = |succ: NodeIndex| {
+                targets.insert(label(succ));
+                AstNodeC::Goto(label(succ))
+            };
+            let code = match mem::replace(&mut self.graph[n], CfgNode::Dummy("virtualized")) {
+                CfgNode::Code(mut ast) => {
+                    if !ends_in_jump(&ast) {
+                        match self.graph.neighbors(n).next() {
+                            Some(succ) if Some(succ) != next => append_leaf(&mut ast, goto(succ)),
+                            Some(_) => (),
+                            None if next.is_some() => append_leaf(&mut ast, AstNodeC::Return),
+                            None => (),
+                        }
+                    }
+                    ast
+                }
+                CfgNode::Condition(c) => {
+                    let (mut then, mut els) = (None, None);
+                    for e in self.graph.edges(n) {
+                        match e.weight() {
+                            CfgEdge::True => then = Some(e.target()),
+                            _ => els = Some(e.target()),
+                        }
+                    }
+                    let (then, els) = match (then, els) {
+                        (Some(then), Some(els)) => (then, els),
+                        _ => {
+                            return Err(internal(
+                                "lay_out",
+                                format!("condition node {} lacks a successor", n.index()),
+                            ))
+                        }
+                    };
+                    let then = Box::new(goto(then));
+                    let els = if Some(els) == next {
+                        None
+                    } else {
+                        Some(Box::new(goto(els)))
+                    };
+                    let cond = self.cctx.mk_var(c);
+                    self.decisions.get_mut().cond(cond, Phase::GotoFallback);
+                    AstNodeC::Cond(cond, then, els)
+                }
+                CfgNode::Dummy(s) => {
+                    return Err(internal(
+                        "lay_out",
+                        format!("found `CfgNode::Dummy({:?})`", s),
+                    ))
+                }
+            };
+            members.push((label(n), code));
+        }
+        self.graph.clear();
+        Ok((members, targets))
+    }
+
+    /// Collapses the canonical SESE regions of the graph that don't contain
+    /// any loops, innermost first. Collapsing a region only replaces it with
+    /// a single node, so the regions found beforehand stay valid.
+    fn structure_acyclic_sese_regions(
+        &mut self,
+        opts: &StructuringOptions,
+    ) -> Result<(), StructureError> {
+        let region_tree = self.region_tree();
+
+        let (_, back_edges) = postorder_and_back_edges(&self.graph, self.entry);
+
+        let mut progress = Progress::new();
+        for r in region_tree.postorder() {
+            let region = &region_tree.regions[r];
+            let succ = match region.successor {
+                Some(succ) => succ,
+                None => continue,
+            };
+
+            // inner regions were already collapsed into their headers, so
+            // find what's left of this one
+            let mut nodes = NodeSet::new();
+            let mut stack = vec![region.header];
+            nodes.insert(region.header);
+            while let Some(n) = stack.pop() {
+                for m in self.graph.neighbors(n) {
+                    if m != succ && nodes.insert(m) {
+                        stack.push(m);
+                    }
+                }
+            }
+
+            // single-block regions aren't interesting
+            if nodes.len() > 1
+                && nodes.iter().all(|n| !back_edges.is_header(n))
+                && !self.continues_switch(region.header)
+                && !self.has_sink(&nodes)
+            {
+                debug_assert!(graph_utils::strict_successors_of_set(&self.graph, &nodes)
+                    .iter()
+                    .all(|n| n == succ));
+                self.check_cancelled()?;
+                if !self.take_step() {
+                    return Ok(());
+                }
+                radeco_trace!(
+                    "structure: sese region header={} nodes={}",
+                    region.header.index(),
+                    nodes.len()
+                );
+                progress.start(&self.graph);
+                self.collapse_acyclic_region(
+                    opts,
+                    TraceOp::SeseCollapse,
+                    region.header,
+                    &nodes,
+                    Some(succ),
+                )?;
+                if !progress.made(&self.graph) {
+                    return Err(self.no_progress());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `header` is a condition with a value set whose only
+    /// predecessor is a condition on the same variable. The region headed by
+    /// `header` is then left to the enclosing one, so that switch recovery
+    /// sees all of the cases at once.
+    fn continues_switch(&self, header: NodeIndex) -> bool {
+        continues_switch(&self.graph, &self.value_sets, header)
+    }
+
+    /// Replaces the acyclic region headed by `header` with a single `Code`
+    /// node whose only successor is `opt_succ`. `op` is the step to trace.
+    fn collapse_acyclic_region(
+        &mut self,
+        opts: &StructuringOptions,
+        op: TraceOp,
+        header: NodeIndex,
+        region: &NodeSet,
+        opt_succ: Option<NodeIndex>,
+    ) -> Result<(), StructureError> {
+        let mut region = region.clone();
+        self.keep_side_exits(opts, header, &mut region, opt_succ)?;
+        let region = &region;
+        // the region is only entered at `header`, so the nodes left still
+        // dominate each other like they did, and the dominators of
+        // `set_dominators` still hold for them
+        self.report.regions += 1;
+        if let Some(dump) = &mut self.dump {
+            dump.before(&self.graph, self.entry, header, region, &[]);
+        }
+        let repl_ast = self.structure_acyclic_sese_region(opts, header, region)?;
+        if let Some(dump) = &mut self.dump {
+            dump.after(header, &repl_ast);
+        }
+        // `header` may still have edges straight to `opt_succ`
+        let header_exits: Vec<_> = self.graph.edges(header).map(|e| e.id()).collect();
+        for e in header_exits {
+            self.graph.remove_edge(e);
+        }
+        self.graph[header] = CfgNode::Code(repl_ast);
+        if let Some(succ) = opt_succ {
+            self.graph.add_edge(header, succ, CfgEdge::True);
+        }
+        self.trace(op, header, region, header);
+        self.collapse_marks(region, header);
+        self.observe(|o| o.on_region_collapsed(header, &self.node_bits(region), header));
+        Ok(())
+    }
+
+    /// Makes each edge out of `region`, headed by `header`, to anything but
+    /// `opt_succ` go to a new node of the region instead, a `Goto` to a new
+    /// label node put in front of the target, so that collapsing the region
+    /// doesn't drop it, reporting each as an [`InputDefect::SideExit`].
+    /// Fails on the first instead in [`InputMode::Strict`].
+    fn keep_side_exits(
+        &mut self,
+        opts: &StructuringOptions,
+        header: NodeIndex,
+        region: &mut NodeSet,
+        opt_succ: Option<NodeIndex>,
+    ) -> Result<(), StructureError> {
+        let side_exits: Vec<_> = region
+            .iter()
+            .flat_map(|n| self.graph.edges(n))
+            .filter(|e| !region.contains(e.target()) && Some(e.target()) != opt_succ)
+            .map(|e| (e.id(), e.source(), e.target(), *e.weight()))
+            .collect();
+        if side_exits.is_empty() {
+            return Ok(());
+        }
+        if opts.input_mode == InputMode::Strict {
+            let (_, from, to, _) = side_exits[0];
+            let defect = InputDefect::SideExit { header, from, to };
+            return Err(StructureError::Input(defect));
+        }
+        radeco_warn!(
+            "structure: region header={} has side exits={}, making them gotos",
+            header.index(),
+            side_exits.len()
+        );
+        self.graph_changed();
+        let mut labels = HashMap::new();
+        for (e, source, target, weight) in side_exits {
+            let label = match labels.get(&target) {
+                Some(&label) => label,
+                None => {
+                    let label_node = self.graph.add_node(empty_node());
+                    let label = LabelId(label_node.index());
+                    self.graph[label_node] = CfgNode::Code(AstNodeC::Label(label));
+                    let preds: Vec<_> = self
+                        .graph
+                        .edges_directed(target, Incoming)
+                        .filter(|e| !region.contains(e.source()))
+                        .map(|e| (e.id(), e.source(), *e.weight()))
+                        .collect();
+                    for (pred_e, pred, pred_weight) in preds {
+                        self.graph.remove_edge(pred_e);
+                        self.graph.add_edge(pred, label_node, pred_weight);
+                    }
+                    self.graph.add_edge(label_node, target, CfgEdge::True);
+                    labels.insert(target, label);
+                    label
+                }
+            };
+            let goto = self.graph.add_node(CfgNode::Code(AstNodeC::Goto(label)));
+            self.graph.remove_edge(e);
+            self.graph.add_edge(source, goto, weight);
+            region.insert(goto);
+            self.report.side_exits.push((source, target));
+            let defect = InputDefect::SideExit {
+                header,
+                from: source,
+                to: target,
+            };
+            self.report.warnings.push(defect);
+        }
+        Ok(())
+    }
+
+    /// Marks `result`, which `nodes` were collapsed into, as not to be
+    /// copied if any of them was, see
+    /// [`set_no_duplicate`](Self::set_no_duplicate).
+    fn collapse_marks(&mut self, nodes: &NodeSet, result: NodeIndex) {
+        if nodes.iter().any(|n| self.no_duplicate.contains(n)) {
+            self.no_duplicate.difference_with(nodes);
+            self.no_duplicate.insert(result);
+        }
+    }
+
+    /// Reports a step to the trace sink, if there is one.
+    fn trace(&self, op: TraceOp, header: NodeIndex, nodes: &NodeSet, result: NodeIndex) {
+        if let Some(sink) = &self.trace {
+            sink.borrow_mut().step(&TraceStep {
+                op,
+                header,
+                nodes: nodes.iter().collect(),
+                result,
+            });
+        }
+    }
+
+    /// Tells the observer of an event, if there is one.
+    fn observe<F: FnOnce(&mut dyn Observer)>(&self, event: F) {
+        if let Some(observer) = &self.observer {
+            event(&mut *observer.borrow_mut());
+        }
+    }
+
+    /// `nodes` as the bits of their indices, for the observer, with a bit
+    /// for each node of the graph too, even once `nodes` are gone from it.
+    fn node_bits(&self, nodes: &NodeSet) -> FixedBitSet {
+        let bound = nodes.iter().map(|n| n.index() + 1).max().unwrap_or(0);
+        let mut bits = FixedBitSet::with_capacity(bound.max(self.graph.node_bound()));
+        for n in nodes {
+            bits.insert(n.index());
+        }
+        bits
+    }
+
+    /// The shapes of `ast` and `handler_asts`, if there is an observer to
+    /// tell whether a refinement pass changes them, see [`refined`](Self::refined).
+    fn shapes(
+        &self,
+        ast: &AstNode<'cd, A>,
+        handler_asts: &[AstNode<'cd, A>],
+    ) -> Option<Vec<AstShapeNode<'cd, A>>> {
+        self.observer.as_ref().map(|_| {
+            iter::once(ast)
+                .chain(handler_asts)
+                .flat_map(observer::shape)
+                .collect()
+        })
+    }
+
+    /// Tells the observer, if there is one, that the refinement `pass` ran,
+    /// and whether it changed the shapes of the ASTs from `before`.
+    fn refined(
+        &self,
+        pass: &str,
+        before: Option<Vec<AstShapeNode<'cd, A>>>,
+        ast: &AstNode<'cd, A>,
+        handler_asts: &[AstNode<'cd, A>],
+    ) {
+        if let Some(before) = before {
+            let changed = self.shapes(ast, handler_asts).as_ref() != Some(&before);
+            self.observe(|o| o.on_refinement(pass, changed));
         }
     }
 
     /// Converts the given acyclic region headed by `header` into an `AstNode`.
     fn structure_acyclic_sese_region(
         &mut self,
+        opts: &StructuringOptions,
         header: NodeIndex,
         region: &NodeSet,
-    ) -> AstNode<'cd, A> {
+    ) -> Result<AstNode<'cd, A>, StructureError> {
         let slice = graph_utils::slice(&self.graph, header, region);
-        let reaching_conds = self.reaching_conditions(&slice);
+        let mut reaching_conds = ReachingConds::new(&self.graph, self.cctx, &slice);
 
         let mut region_graph =
             StableDiGraph::with_capacity(slice.topo_order.len(), slice.edges.len());
         let mut old_new_map = HashMap::with_capacity(slice.topo_order.len());
         let mut region_conditions = Vec::new();
+        let mut arena = AstArena::with_capacity(slice.topo_order.len());
 
         // move all region nodes into `region_graph`.
         for &old_n in &slice.topo_order {
@@ -218,18 +2510,27 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
             let new_node = match cfg_node {
                 // refinement needs to be able to see `Break`s
                 CfgNode::Code(AstNodeC::Break) => Some(AstNodeC::Break),
+                // empty nodes, like those on split edges, run nothing, and
+                // would only make their reaching conditions count as uses
+                // of the conditions in them
+                CfgNode::Code(AstNodeC::Seq(ref seq)) if seq.is_empty() => None,
                 // other nodes should be opaque
-                CfgNode::Code(ast) => Some(AstNodeC::BasicBlock(ast)),
+                CfgNode::Code(ast) => Some(AstNodeC::BasicBlock(arena.alloc(ast))),
                 _ => None,
             };
-            let new_n = region_graph.add_node((reaching_conds[&old_n], new_node));
+            let new_n = region_graph.add_node((reaching_conds.get(old_n), new_node));
             old_new_map.insert(old_n, new_n);
         }
         let old_new_map = old_new_map;
 
         // copy over edges
         for e in &slice.edges {
-            let (src, dst) = self.graph.edge_endpoints(e).unwrap();
+            let (src, dst) = self.graph.edge_endpoints(e).ok_or_else(|| {
+                internal(
+                    "structure_acyclic_sese_region",
+                    format!("edge {} of the region is gone", e.index()),
+                )
+            })?;
             region_graph.add_edge(old_new_map[&src], old_new_map[&dst], ());
         }
 
@@ -243,86 +2544,226 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
             }
         }
 
+        let overlaps = RefCell::default();
         let ast = refinement::refine::<RegionAstContext<A>>(
             self.cctx,
+            &self.value_sets,
+            &self.branch_weights,
+            &self.decisions,
+            &overlaps,
             region_graph,
             old_new_map[&header],
+            opts,
+        );
+        self.report
+            .overlapping_cases
+            .extend(overlaps.into_inner().into_iter().map(|values| CaseOverlap {
+                region: header,
+                values,
+            }));
+        self.trace(TraceOp::Refinement, header, region, header);
+
+        let (namer, names) = (&mut self.namer, &mut self.report.var_names);
+        let ast = dedup_conds::run(
+            &mut self.actx,
+            &mut || fresh_name(namer, names, "cond"),
+            self.cctx,
+            self.decisions.get_mut(),
+            &mut arena,
+            &region_conditions,
+            ast,
         );
+        let ast = RegionAstContext::<A>::export(ast, &mut arena);
+        Ok(refinement::simplify_ast_node::<A>(self.cctx, ast).unwrap_or_default())
+    }
 
-        let ast = dedup_conds::run(&mut self.actx, self.cctx, &region_conditions, ast);
-        let ast = RegionAstContext::<A>::export(ast);
-        refinement::simplify_ast_node::<A>(self.cctx, ast).unwrap_or_default()
+    /// The namer of the options, or the default one if structuring hasn't
+    /// started.
+    fn namer(&mut self) -> &RefCell<dyn Namer> {
+        self.namer
+            .get_or_insert_with(|| Rc::new(RefCell::new(CounterNamer::default())))
     }
 
-    /// Computes the reaching condition for every node in the given graph slice.
-    fn reaching_conditions(
-        &self,
-        slice: &graph_utils::GraphSlice<NodeIndex, EdgeIndex>,
-    ) -> HashMap<NodeIndex, Condition<'cd, A>> {
-        // {Node, Edge}Filtered don't implement IntoNeighborsDirected :(
-        // https://github.com/bluss/petgraph/pull/219
-        // Also EdgeFiltered<Reversed<_>, _> isn't Into{Neighbors, Edges}
-        // because Reversed<_> isn't IntoEdges
-
-        let mut ret = HashMap::with_capacity(slice.topo_order.len());
-
-        let mut iter = slice.topo_order.iter();
-        if let Some(&start) = iter.next() {
-            ret.insert(start, self.cctx.mk_true());
-            for &n in iter {
-                let reach_cond = self.cctx.mk_or_from_iter(
-                    // manually restrict to slice
-                    self.graph
-                        .edges_directed(n, Incoming)
-                        .filter(|e| slice.edges.contains(e.id()))
-                        .map(|e| {
-                            let src_cond = ret[&e.source()];
-                            match (&self.graph[e.source()], e.weight()) {
-                                (&CfgNode::Condition(c), CfgEdge::True) => {
-                                    self.cctx.mk_and(src_cond, self.cctx.mk_var(c))
-                                }
-                                (&CfgNode::Condition(c), CfgEdge::False) => self
-                                    .cctx
-                                    .mk_and(src_cond, self.cctx.mk_not(self.cctx.mk_var(c))),
-                                (_, CfgEdge::True) => src_cond,
-                                (_, CfgEdge::False) => self.cctx.mk_false(),
-                            }
-                        }),
-                );
-                let _old = ret.insert(n, reach_cond);
-                debug_assert!(_old.is_none());
+    /// A name for a new variable, see [`fresh_name`].
+    fn fresh_name(&mut self, hint: &str) -> String {
+        fresh_name(&mut self.namer, &mut self.report.var_names, hint)
+    }
+
+    /// Whether `n` is a code node that ends the function by falling off its
+    /// end.
+    fn is_sink(&self, n: NodeIndex) -> bool {
+        is_sink(&self.graph, n)
+    }
+
+    fn has_sink(&self, region: &NodeSet) -> bool {
+        region.iter().any(|n| self.is_sink(n))
+    }
+
+    /// Makes the nodes in `region` that end the function by falling off
+    /// their end `return` explicitly, so that they still do once `region` is
+    /// part of a node that goes on to something else.
+    fn terminate_sinks(&mut self, region: &NodeSet) {
+        for n in region {
+            if self.is_sink(n) {
+                if let CfgNode::Code(ast) = &mut self.graph[n] {
+                    append_leaf(ast, AstNodeC::Return);
+                }
             }
         }
+    }
 
-        ret
+    /// Gives each entry of the loop headed by `header` from outside it, but
+    /// at `header`, a copy of the part of the loop it leads to, as far as
+    /// `limit` allows, see [`DuplicationLimit`]. The copy goes back to
+    /// `header` where the part does, through `latch_edges`, the back edges
+    /// that were removed, so that the entry then enters the loop at
+    /// `header`.
+    fn split_abnormal_entries(
+        &mut self,
+        limit: DuplicationLimit,
+        header: NodeIndex,
+        loop_nodes: &NodeSet,
+        latch_edges: &[(NodeIndex, CfgEdge)],
+    ) {
+        let outside_preds = |graph: &StableDiGraph<_, _>, n| {
+            graph
+                .edges_directed(n, Incoming)
+                .filter(|e| !loop_nodes.contains(e.source()))
+                .map(|e| (e.id(), e.source(), *e.weight()))
+                .collect::<Vec<_>>()
+        };
+        let entries: Vec<_> = loop_nodes
+            .iter()
+            .filter(|&n| n != header && !outside_preds(&self.graph, n).is_empty())
+            .collect();
+        for entry in entries {
+            // the back edges are gone, so this doesn't get to `header`
+            let mut part = NodeSet::with_capacity(self.graph.node_bound());
+            let mut stack = vec![entry];
+            while let Some(n) = stack.pop() {
+                if part.insert(n) {
+                    stack.extend(
+                        self.graph
+                            .neighbors(n)
+                            .filter(|&s| s != header && loop_nodes.contains(s)),
+                    );
+                }
+            }
+            let nodes = part.len();
+            if let Some(reason) = self.decline_reason(Some(limit), &part) {
+                self.report.declined.push(DeclinedCopy::LoopEntry {
+                    header,
+                    entry,
+                    nodes,
+                    reason,
+                });
+                continue;
+            }
+            let (graph, actx) = (&self.graph, &mut self.actx);
+            let copies = part
+                .iter()
+                .map(|n| match &graph[n] {
+                    CfgNode::Code(ast) => clone_ast(actx, ast).map(CfgNode::Code),
+                    CfgNode::Condition(c) => Some(CfgNode::Condition(*c)),
+                    CfgNode::Dummy(s) => Some(CfgNode::Dummy(s)),
+                })
+                .collect::<Option<Vec<_>>>();
+            let copy_of: HashMap<_, _> = match copies {
+                Some(copies) => part
+                    .iter()
+                    .zip(copies)
+                    .map(|(n, copy)| (n, self.graph.add_node(copy)))
+                    .collect(),
+                None => continue,
+            };
+            radeco_trace!(
+                "structure: split_loop_entry header={} entry={} nodes={}",
+                header.index(),
+                entry.index(),
+                nodes
+            );
+            let mut edges = Vec::new();
+            for n in &part {
+                edges.extend(self.graph.edges(n).map(|e| (n, e.target(), *e.weight())));
+            }
+            edges.extend(
+                latch_edges
+                    .iter()
+                    .filter(|&&(l, _)| part.contains(l))
+                    .map(|&(l, weight)| (l, header, weight)),
+            );
+            for (n, succ, weight) in edges {
+                let succ = copy_of.get(&succ).cloned().unwrap_or(succ);
+                self.graph.add_edge(copy_of[&n], succ, weight);
+            }
+            for (e, pred, weight) in outside_preds(&self.graph, entry) {
+                self.graph.remove_edge(e);
+                self.graph.add_edge(pred, copy_of[&entry], weight);
+            }
+            for n in &part {
+                self.report.duplicated.push((n, copy_of[&n]));
+            }
+        }
     }
 
     /// Transforms the loop into a single-entry loop.
     /// Returns the new loop header.
-    fn funnel_abnormal_entries(&mut self, header: NodeIndex, loop_nodes: &NodeSet) -> NodeIndex {
-        let mut entry_map = HashMap::new();
+    fn funnel_abnormal_entries(
+        &mut self,
+        header: NodeIndex,
+        loop_nodes: &NodeSet,
+    ) -> Result<NodeIndex, StructureError> {
+        // indexed by node so that entries are numbered in a deterministic order
+        let mut entry_map = vec![Vec::new(); self.graph.node_bound()];
         for n in loop_nodes {
             for e in self.graph.edges_directed(n, Incoming) {
                 if !loop_nodes.contains(e.source()) {
-                    entry_map.entry(n).or_insert(Vec::new()).push(e.id());
+                    entry_map[n.index()].push(e.id());
                 }
             }
         }
-        // loop must be reachable, so the header must have entries
-        let header_entries = entry_map.remove(&header).unwrap();
-        debug_assert!(!header_entries.is_empty());
-        let abnormal_entry_map = entry_map;
-        if abnormal_entry_map.is_empty() {
+        // loop must be reachable, so the header must have entries, unless
+        // the function starts there
+        let header_entries = mem::replace(&mut entry_map[header.index()], Vec::new());
+        let entered_at_start = header == self.entry;
+        debug_assert!(entered_at_start || !header_entries.is_empty());
+        let abnormal_entries: Vec<_> = entry_map
+            .into_iter()
+            .enumerate()
+            .filter(|(_, entries)| !entries.is_empty())
+            .map(|(i, entries)| (NodeIndex::new(i), entries))
+            .collect();
+        if abnormal_entries.is_empty() {
             // no abnormal entries
-            return header;
+            return Ok(header);
         }
-        let abnormal_entry_iter = (1..).zip(&abnormal_entry_map);
-
-        let struct_var = self.actx.mk_fresh_var();
+        radeco_warn!(
+            "structure: loop header={} has abnormal_entries={}, dispatching on a new variable",
+            header.index(),
+            abnormal_entries.len()
+        );
+        let fallback = Fallback::AbnormalEntries {
+            header,
+            entries: abnormal_entries.len(),
+        };
+        self.observe(|o| o.on_fallback(&fallback));
+        self.report.fallbacks.push(fallback);
+        let abnormal_entry_iter = (1..).zip(&abnormal_entries);
+
+        let name = self.fresh_name("entry");
+        // the function enters the header with the value the variable starts
+        // out with
+        let struct_var = if entered_at_start {
+            self.actx.mk_named_var_zeroed(&name)
+        } else {
+            self.actx.mk_named_var(&name)
+        };
+        let mut tests = Vec::new();
+        let mut recognized = true;
 
         // make condition cascade
         let new_header = {
-            let abnormal_entry_iter = abnormal_entry_iter.clone().map(|(n, (&t, _))| (n, t));
+            let abnormal_entry_iter = abnormal_entry_iter.clone().map(|(n, &(t, _))| (n, t));
 
             let dummy_preheader = self.graph.add_node(CfgNode::Dummy("loop \"preheader\""));
 
@@ -337,15 +2778,17 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
                 let prev_cond_eq = self
                     .cctx
                     .new_var(self.actx.mk_cond_equals(&struct_var, prev_entry_num));
+                tests.push((prev_cond_eq, prev_entry_num));
                 let cascade_node = self.graph.add_node(CfgNode::Condition(prev_cond_eq));
                 self.graph
                     .add_edge(prev_cascade_node, cascade_node, CfgEdge::False);
                 self.graph
                     .add_edge(cascade_node, prev_entry_target, CfgEdge::True);
 
-                let struct_reset = self.graph.add_node(CfgNode::Code(AstNodeC::BasicBlock(
-                    self.actx.mk_var_assign(&struct_var, 0),
-                )));
+                let reset = self.mk_struct_assign(&struct_var, 0, &mut recognized);
+                let struct_reset = self
+                    .graph
+                    .add_node(CfgNode::Code(AstNodeC::BasicBlock(reset)));
                 self.graph
                     .add_edge(struct_reset, entry_target, CfgEdge::True);
 
@@ -358,55 +2801,68 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
                 .add_edge(prev_cascade_node, prev_entry_target, CfgEdge::False);
 
             // we always add an edge from dummy_preheader
-            let new_header = self.graph.neighbors(dummy_preheader).next().unwrap();
+            let new_header = self
+                .graph
+                .neighbors(dummy_preheader)
+                .next()
+                .ok_or_else(|| {
+                    internal(
+                        "funnel_abnormal_entries",
+                        "the loop preheader has no successor".to_owned(),
+                    )
+                })?;
             self.graph.remove_node(dummy_preheader);
             new_header
         };
+        if entered_at_start {
+            self.entry = new_header;
+        }
 
         // redirect entries
         for (entry_num, entry_edges) in
             iter::once((0, &header_entries)).chain(abnormal_entry_iter.map(|(n, (_, e))| (n, e)))
         {
-            let struct_assign = self.graph.add_node(CfgNode::Code(AstNodeC::BasicBlock(
-                self.actx.mk_var_assign(&struct_var, entry_num),
-            )));
-            self.graph
-                .add_edge(struct_assign, new_header, CfgEdge::True);
+            let mut struct_assign = None;
             for &entry_edge in entry_edges {
-                graph_utils::retarget_edge(&mut self.graph, entry_edge, struct_assign);
+                // the node split off a critical edge takes the assignment
+                if let Some(split) = self.empty_split_source(entry_edge) {
+                    let assign = self.mk_struct_assign(&struct_var, entry_num, &mut recognized);
+                    self.graph[split] = CfgNode::Code(AstNodeC::BasicBlock(assign));
+                    graph_utils::retarget_edge(&mut self.graph, entry_edge, new_header);
+                    continue;
+                }
+                let target = match struct_assign {
+                    Some(target) => target,
+                    None => {
+                        let assign = self.mk_struct_assign(&struct_var, entry_num, &mut recognized);
+                        let target = self
+                            .graph
+                            .add_node(CfgNode::Code(AstNodeC::BasicBlock(assign)));
+                        self.graph.add_edge(target, new_header, CfgEdge::True);
+                        *struct_assign.insert(target)
+                    }
+                };
+                graph_utils::retarget_edge(&mut self.graph, entry_edge, target);
             }
         }
+        if recognized {
+            self.struct_vars.push(StructVar {
+                var: struct_var,
+                initial: if entered_at_start { Some(0) } else { None },
+                tests,
+            });
+        }
 
-        new_header
+        Ok(new_header)
     }
 
-    /// Incrementally adds nodes dominated by the loop to the loop until
-    /// there's only one successor or there are no more nodes to add.
-    fn refine_loop(&self, loop_nodes: &mut NodeSet, succ_nodes: &mut NodeSet) -> () {
-        // reuse this `NodeSet` so we avoid allocating
-        let mut new_nodes = NodeSet::new();
-        while succ_nodes.len() > 1 {
-            for n in &*succ_nodes {
-                if self
-                    .graph
-                    .neighbors_directed(n, Incoming)
-                    .all(|pred| loop_nodes.contains(pred))
-                {
-                    // post-pone removal from `succ_nodes` b/c rust ownership
-                    loop_nodes.insert(n);
-                    new_nodes.extend(self.graph.neighbors(n).filter(|&u| !loop_nodes.contains(u)));
-                }
-            }
-
-            // do the removal
-            succ_nodes.difference_with(&loop_nodes);
-
-            if new_nodes.is_empty() {
-                break;
-            }
-            succ_nodes.union_with(&new_nodes);
-            new_nodes.clear();
-        }
+    /// Makes a block that assigns `val` to the structuring variable `var`,
+    /// and clears `recognized` unless the context tells that it does, since
+    /// only then can [`struct_vars::fold`] follow the variable.
+    fn mk_struct_assign(&mut self, var: &A::Variable, val: u64, recognized: &mut bool) -> A::Block {
+        let block = self.actx.mk_var_assign(var, val);
+        *recognized &= self.actx.assigned_value(&block, var) == Some(val);
+        block
     }
 
     /// Transforms the loop so that all loop exits are `break`.
@@ -423,6 +2879,11 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
                 graph_utils::edges_from_region_to_node(&self.graph, &loop_nodes, final_succ)
                     .collect();
             for exit_edge in exit_edges {
+                if let Some(split) = self.empty_split_source(exit_edge) {
+                    self.graph[split] = CfgNode::Code(AstNodeC::Break);
+                    self.graph.remove_edge(exit_edge);
+                    continue;
+                }
                 let break_node = self.graph.add_node(CfgNode::Code(AstNodeC::Break));
                 graph_utils::retarget_edge(&mut self.graph, exit_edge, break_node);
                 loop_nodes.insert(break_node);
@@ -433,9 +2894,23 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
             // no abnormal exits
             return final_succ;
         }
+        radeco_trace!(
+            "structure: loop successor={} abnormal_exits={}",
+            final_succ.index(),
+            abn_succ_nodes.len()
+        );
+        let fallback = Fallback::AbnormalExits {
+            successor: final_succ,
+            exits: abn_succ_nodes.len(),
+        };
+        self.observe(|o| o.on_fallback(&fallback));
+        self.report.fallbacks.push(fallback);
 
         let abn_succ_iter = (1..).zip(abn_succ_nodes);
-        let struct_var = self.actx.mk_fresh_var_zeroed();
+        let name = self.fresh_name("exit");
+        let struct_var = self.actx.mk_named_var_zeroed(&name);
+        let mut tests = Vec::new();
+        let mut recognized = true;
 
         // replace abnormal exit edges with "break"
         for (exit_num, exit_target) in abn_succ_iter.clone() {
@@ -443,10 +2918,17 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
                 graph_utils::edges_from_region_to_node(&self.graph, &loop_nodes, exit_target)
                     .collect();
             for exit_edge in exit_edges {
-                let break_node = self.graph.add_node(CfgNode::Code(AstNodeC::Seq(vec![
-                    AstNodeC::BasicBlock(self.actx.mk_var_assign(&struct_var, exit_num)),
+                let assign = self.mk_struct_assign(&struct_var, exit_num, &mut recognized);
+                let node = CfgNode::Code(AstNodeC::Seq(vec![
+                    AstNodeC::BasicBlock(assign),
                     AstNodeC::Break,
-                ])));
+                ]));
+                if let Some(split) = self.empty_split_source(exit_edge) {
+                    self.graph[split] = node;
+                    self.graph.remove_edge(exit_edge);
+                    continue;
+                }
+                let break_node = self.graph.add_node(node);
                 graph_utils::retarget_edge(&mut self.graph, exit_edge, break_node);
                 loop_nodes.insert(break_node);
             }
@@ -459,6 +2941,7 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
             let cond = self
                 .cctx
                 .new_var(self.actx.mk_cond_equals(&struct_var, exit_num));
+            tests.push((cond, exit_num));
             let cascade_node = self.graph.add_node(CfgNode::Condition(cond));
             self.graph
                 .add_edge(cascade_node, exit_target, CfgEdge::True);
@@ -466,6 +2949,13 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
 
             cur_succ = cascade_node;
         }
+        if recognized {
+            self.struct_vars.push(StructVar {
+                var: struct_var,
+                initial: Some(0),
+                tests,
+            });
+        }
 
         cur_succ
     }
@@ -474,37 +2964,347 @@ impl<'cd, A: AstContextMut> ControlFlowGraph<'cd, A> {
 struct RegionAstContext<'cd, A>(PhantomData<(&'cd (), A)>);
 
 impl<'cd, A: AstContext> AstContext for RegionAstContext<'cd, A> {
-    type Block = AstNode<'cd, A>;
+    type Block = AstRef;
     type Condition = A::Condition;
     type BoolVariable = A::BoolVariable;
     type Variable = A::Variable;
 }
 
 impl<'cd, A: AstContext> RegionAstContext<'cd, A> {
-    fn export(ast: AstNode<'cd, Self>) -> AstNode<'cd, A> {
+    fn export(ast: AstNode<'cd, Self>, arena: &mut AstArena<'cd, A>) -> AstNode<'cd, A> {
         use self::AstNodeC::*;
         match ast {
-            BasicBlock(b) => b,
-            Seq(seq) => Seq(seq.into_iter().map(Self::export).collect()),
+            BasicBlock(b) => arena.take(b),
+            Seq(seq) => Seq(seq.into_iter().map(|a| Self::export(a, arena)).collect()),
             Cond(c, t, oe) => Cond(
                 c,
-                Box::new(Self::export(*t)),
-                oe.map(|e| Box::new(Self::export(*e))),
+                Box::new(Self::export(*t, arena)),
+                oe.map(|e| Box::new(Self::export(*e, arena))),
             ),
-            Loop(t, b) => Loop(t, Box::new(Self::export(*b))),
+            Loop(t, b) => Loop(t, Box::new(Self::export(*b, arena))),
+            For(..) => unreachable!("structuring doesn't make `For`s"),
             Break => Break,
+            Continue => Continue,
+            Return => Return,
+            // only ever inside the opaque nodes of a region
+            TailCall(_) | IndirectJump(_) => unreachable!("jump leaf in a region graph"),
+            Unstructured(_) => unreachable!("unstructured region in a region graph"),
+            Goto(l) => Goto(l),
+            Label(l) => Label(l),
+            Try(b, h) => Try(Box::new(Self::export(*b, arena)), h),
             Switch(v, cases, default) => Switch(
                 v,
                 cases
                     .into_iter()
-                    .map(|(vs, a)| (vs, Self::export(a)))
+                    .map(|(vs, a)| (vs, Self::export(a, arena)))
                     .collect(),
-                Box::new(Self::export(*default)),
+                Box::new(Self::export(*default, arena)),
             ),
         }
     }
 }
 
+/// Does a depth-first search of `graph` from `entry`, and returns the nodes
+/// in the order it finished them, which is the order structuring visits
+/// them in, and the targets of back edges, which head loops.
+fn postorder_and_back_edges<N, E>(
+    graph: &StableDiGraph<N, E>,
+    entry: NodeIndex,
+) -> (Vec<NodeIndex>, BackEdges) {
+    let mut back_edges = BackEdges(vec![Vec::new(); graph.node_bound()]);
+    let mut podfs_trace = Vec::new();
+    graph_utils::depth_first_search(graph, entry, |ev| {
+        use self::graph_utils::DfsEvent::*;
+        match ev {
+            BackEdge(e) => back_edges.0[e.target().index()].push(e.id()),
+            Finish(n) => podfs_trace.push(n),
+            _ => (),
+        }
+    });
+    (podfs_trace, back_edges)
+}
+
+/// The back edges a depth-first search found, indexed by the loop header
+/// they go to, each in the order the search found them.
+struct BackEdges(Vec<Vec<EdgeIndex>>);
+
+impl BackEdges {
+    fn is_header(&self, n: NodeIndex) -> bool {
+        n.index() < self.0.len() && !self.0[n.index()].is_empty()
+    }
+
+    /// The loop headers, sorted.
+    fn headers<'a>(&'a self) -> impl Iterator<Item = NodeIndex> + 'a {
+        (0..self.0.len())
+            .map(NodeIndex::new)
+            .filter(move |&n| self.is_header(n))
+    }
+}
+
+/// Incrementally adds nodes dominated by the loop to the loop until
+/// there's only one successor or there are no more nodes to add.
+fn refine_loop_nodes<N, E>(
+    graph: &StableDiGraph<N, E>,
+    loop_nodes: &mut NodeSet,
+    succ_nodes: &mut NodeSet,
+) {
+    // reuse this `NodeSet` so we avoid allocating
+    let mut new_nodes = NodeSet::new();
+    while succ_nodes.len() > 1 {
+        for n in &*succ_nodes {
+            if graph
+                .neighbors_directed(n, Incoming)
+                .all(|pred| loop_nodes.contains(pred))
+            {
+                // post-pone removal from `succ_nodes` b/c rust ownership
+                loop_nodes.insert(n);
+                new_nodes.extend(graph.neighbors(n).filter(|&u| !loop_nodes.contains(u)));
+            }
+        }
+
+        // do the removal
+        succ_nodes.difference_with(loop_nodes);
+
+        if new_nodes.is_empty() {
+            break;
+        }
+        succ_nodes.union_with(&new_nodes);
+        new_nodes.clear();
+    }
+}
+
+fn continues_switch<A: AstContext>(
+    graph: &StableDiGraph<CfgNode<A>, CfgEdge>,
+    value_sets: &ValueSets<A>,
+    header: NodeIndex,
+) -> bool {
+    let var_of = |n| match &graph[n] {
+        CfgNode::Condition(c) => value_sets.get(&cond_var_key::<A>(*c)).map(|(v, ..)| v),
+        _ => None,
+    };
+    let mut preds = graph.neighbors_directed(header, Incoming);
+    match (var_of(header), preds.next(), preds.next()) {
+        (Some(v), Some(p), None) => var_of(p) == Some(v),
+        _ => false,
+    }
+}
+
+/// Asks `namer`, or a new default one if there is none, for a name for a
+/// variable, and notes it in `names`.
+fn fresh_name(
+    namer: &mut Option<Rc<RefCell<dyn Namer>>>,
+    names: &mut Vec<String>,
+    hint: &str,
+) -> String {
+    let name = namer
+        .get_or_insert_with(|| Rc::new(RefCell::new(CounterNamer::default())))
+        .borrow_mut()
+        .fresh_var(hint);
+    names.push(name.clone());
+    name
+}
+
+fn is_sink<A: AstContext>(graph: &StableDiGraph<CfgNode<A>, CfgEdge>, n: NodeIndex) -> bool {
+    match &graph[n] {
+        CfgNode::Code(ast) => graph.neighbors(n).next().is_none() && !ends_in_jump(ast),
+        _ => false,
+    }
+}
+
+/// Unwraps the `Try`s in `ast` that name one of `reentries`, and returns
+/// the result along with the [`AstNodeId`](decisions::AstNodeId) in it of
+/// the code each wrapped, and the handler it named.
+fn unwrap_reentries<B, C, V>(
+    ast: ast::AstNode<B, C, V>,
+    reentries: &[HandlerId],
+) -> (
+    ast::AstNode<B, C, V>,
+    Vec<(decisions::AstNodeId, HandlerId)>,
+) {
+    // the code takes the place of its `Try` in pre-order, and everything
+    // after it moves up by one
+    let mut found = Vec::new();
+    for (i, a) in decisions::preorder(&ast).into_iter().enumerate() {
+        if let AstNodeC::Try(_, h) = a {
+            if reentries.contains(h) {
+                found.push((decisions::AstNodeId(i - found.len()), *h));
+            }
+        }
+    }
+    (unwrap_tries(ast, reentries), found)
+}
+
+fn unwrap_tries<B, C, V>(
+    ast: ast::AstNode<B, C, V>,
+    reentries: &[HandlerId],
+) -> ast::AstNode<B, C, V> {
+    use self::AstNodeC::*;
+    let go = |a: Box<ast::AstNode<B, C, V>>| Box::new(unwrap_tries(*a, reentries));
+    match ast {
+        Try(b, h) if reentries.contains(&h) => unwrap_tries(*b, reentries),
+        Try(b, h) => Try(go(b), h),
+        Unstructured(members) => Unstructured(
+            members
+                .into_iter()
+                .map(|(l, a)| (l, unwrap_tries(a, reentries)))
+                .collect(),
+        ),
+        Seq(seq) => Seq(seq
+            .into_iter()
+            .map(|a| unwrap_tries(a, reentries))
+            .collect()),
+        Cond(c, t, e) => Cond(c, go(t), e.map(go)),
+        Loop(lt, b) => Loop(lt, go(b)),
+        For(i, c, u, b) => For(i, c, u, go(b)),
+        Switch(v, cases, default) => Switch(
+            v,
+            cases
+                .into_iter()
+                .map(|(vs, a)| (vs, unwrap_tries(a, reentries)))
+                .collect(),
+            go(default),
+        ),
+        ast @ BasicBlock(_)
+        | ast @ Break
+        | ast @ Continue
+        | ast @ Return
+        | ast @ TailCall(_)
+        | ast @ IndirectJump(_)
+        | ast @ Goto(_)
+        | ast @ Label(_) => ast,
+    }
+}
+
+fn cond_var_key<A: AstContext>(c: CondVar<A>) -> usize {
+    &*c as *const A::Condition as usize
+}
+
+/// `a; b`, as a single `Seq` if either is one.
+fn concat<B, C, V>(a: ast::AstNode<B, C, V>, b: ast::AstNode<B, C, V>) -> ast::AstNode<B, C, V> {
+    let mut seq = Vec::new();
+    for ast in [a, b] {
+        match ast {
+            AstNodeC::Seq(s) => seq.extend(s),
+            ast => seq.push(ast),
+        }
+    }
+    if seq.len() == 1 {
+        seq.pop().unwrap()
+    } else {
+        AstNodeC::Seq(seq)
+    }
+}
+
+fn append_leaf<B, C, V>(ast: &mut ast::AstNode<B, C, V>, leaf: ast::AstNode<B, C, V>) {
+    *ast = match mem::take(ast) {
+        AstNodeC::Seq(mut seq) => {
+            seq.push(leaf);
+            AstNodeC::Seq(seq)
+        }
+        old => AstNodeC::Seq(vec![old, leaf]),
+    };
+}
+
+/// Turns the `TailCall` ending `ast`, if any, into a plain call, and
+/// returns whether it did.
+fn untail_call<B, C, V>(ast: &mut ast::AstNode<B, C, V>) -> bool {
+    match ast {
+        AstNodeC::Seq(seq) => seq.last_mut().is_some_and(untail_call),
+        AstNodeC::TailCall(_) => {
+            if let AstNodeC::TailCall(call) = mem::take(ast) {
+                *ast = AstNodeC::BasicBlock(call);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Whether `ast` ends in a `Return`.
+fn ends_in_return<B, C, V>(ast: &ast::AstNode<B, C, V>) -> bool {
+    match ast {
+        AstNodeC::Seq(seq) => seq.last().is_some_and(ends_in_return),
+        AstNodeC::Return => true,
+        _ => false,
+    }
+}
+
+/// A copy of `ast`, if `actx` can copy all of its blocks.
+fn clone_ast<'cd, A: AstContextMut>(
+    actx: &mut A,
+    ast: &AstNode<'cd, A>,
+) -> Option<AstNode<'cd, A>> {
+    use self::AstNodeC::*;
+    Some(match ast {
+        BasicBlock(b) => BasicBlock(actx.clone_block(b)?),
+        Seq(seq) => Seq(seq
+            .iter()
+            .map(|a| clone_ast(actx, a))
+            .collect::<Option<_>>()?),
+        Cond(c, t, oe) => {
+            let t = clone_ast(actx, t)?;
+            let oe = match oe {
+                Some(e) => Some(Box::new(clone_ast(actx, e)?)),
+                None => None,
+            };
+            Cond(*c, Box::new(t), oe)
+        }
+        Loop(lt, b) => Loop(lt.clone(), Box::new(clone_ast(actx, b)?)),
+        For(i, c, u, b) => {
+            let (i, u) = (actx.clone_block(i)?, actx.clone_block(u)?);
+            For(i, *c, u, Box::new(clone_ast(actx, b)?))
+        }
+        Switch(v, cases, default) => {
+            let cases = cases
+                .iter()
+                .map(|(vs, a)| Some((vs.clone(), clone_ast(actx, a)?)))
+                .collect::<Option<_>>()?;
+            Switch(v.clone(), cases, Box::new(clone_ast(actx, default)?))
+        }
+        Break => Break,
+        Continue => Continue,
+        Return => Return,
+        TailCall(b) => TailCall(actx.clone_block(b)?),
+        IndirectJump(b) => IndirectJump(actx.clone_block(b)?),
+        Goto(l) => Goto(*l),
+        Label(l) => Label(*l),
+        Try(b, h) => Try(Box::new(clone_ast(actx, b)?), *h),
+        Unstructured(members) => Unstructured(
+            members
+                .iter()
+                .map(|(l, a)| Some((*l, clone_ast(actx, a)?)))
+                .collect::<Option<_>>()?,
+        ),
+    })
+}
+
+pub(crate) fn count_gotos<B, C, V>(ast: &ast::AstNode<B, C, V>) -> usize {
+    use self::AstNodeC::*;
+    match ast {
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Label(_) => 0,
+        Goto(_) => 1,
+        Seq(seq) => seq.iter().map(count_gotos).sum(),
+        Cond(_, t, oe) => count_gotos(t) + oe.as_ref().map_or(0, |e| count_gotos(e)),
+        Loop(_, b) | For(_, _, _, b) | Try(b, _) => count_gotos(b),
+        Switch(_, cases, default) => {
+            cases.iter().map(|(_, a)| count_gotos(a)).sum::<usize>() + count_gotos(default)
+        }
+        Unstructured(members) => members.iter().map(|(_, a)| count_gotos(a)).sum(),
+    }
+}
+
+/// Whether control never falls off the end of `ast`.
+fn ends_in_jump<B, C, V>(ast: &ast::AstNode<B, C, V>) -> bool {
+    use self::AstNodeC::*;
+    match ast {
+        Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) => true,
+        Seq(seq) => seq.last().map_or(false, ends_in_jump),
+        Try(b, _) => ends_in_jump(b),
+        Unstructured(members) => members.last().is_some_and(|(_, a)| ends_in_jump(a)),
+        _ => false,
+    }
+}
+
 pub fn mk_code_node<A: AstContext>(block: A::Block) -> CfgNode<'static, A> {
     CfgNode::Code(AstNodeC::BasicBlock(block))
 }
@@ -537,6 +3337,21 @@ where
     }
 }
 
+impl<'cd, A> Clone for CfgNode<'cd, A>
+where
+    A: AstContext,
+    A::Block: Clone,
+    A::Variable: Clone,
+{
+    fn clone(&self) -> Self {
+        match self {
+            CfgNode::Code(c) => CfgNode::Code(c.clone()),
+            CfgNode::Condition(c) => CfgNode::Condition(*c),
+            CfgNode::Dummy(s) => CfgNode::Dummy(s),
+        }
+    }
+}
+
 impl<'cd, A> fmt::Debug for CfgNode<'cd, A>
 where
     A: AstContext,
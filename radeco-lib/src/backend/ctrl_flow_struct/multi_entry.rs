@@ -0,0 +1,244 @@
+//! Structures a function with more than one entry, e.g. a thunked entry or
+//! a compiler-generated alternate one, into one AST per entry, see
+//! [`structure_entries`].
+//!
+//! The code that more than one entry goes on to is only structured once:
+//! the nodes reachable from more than one entry are closed under
+//! successors, so once an entry gets to one of them, it stays among them.
+//! Each node it gets there at heads a shared part, which is structured on
+//! its own, entered at its head, and the graph of each entry ends in a
+//! `Goto` to the head wherever it gets there, instead of in the nodes of
+//! the shared part. The shared parts themselves may again share code with
+//! each other, and are split up the same way, so each node ends up in
+//! exactly one AST. The ASTs are kept in [`Rc`]s, and the AST of an entry
+//! that is itself the head of a shared part is that of the part.
+//!
+//! Each graph is structured on its own, so dominance and everything else is
+//! computed per entry. Where the entries all get to the same loop, though,
+//! the whole loop is reachable from each of them, and nothing is shared:
+//! each entry is structured on its own, and the code is copied.
+
+use super::ast::LabelId;
+use super::ast_context::{AstContext, AstContextMut};
+use super::{
+    empty_node, internal, AstNodeC, CfgEdge, CfgNode, CondContext, ControlFlowGraph, NodeSet,
+    StructureError, StructuringOptions,
+};
+
+use petgraph::prelude::*;
+use petgraph::visit::NodeIndexable;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+type AstNode<'cd, A> = super::AstNode<'cd, A>;
+
+/// The ASTs of a function with more than one entry.
+pub struct EntryAsts<'cd, A: AstContext> {
+    /// the AST of each entry, in the order they were given
+    pub entries: Vec<Rc<AstNode<'cd, A>>>,
+    /// the AST of each shared part, keyed by the label of its head,
+    /// `LabelId(head.index())`; `Goto(label)` in any of the ASTs goes on to
+    /// `shared[&label]`
+    pub shared: HashMap<LabelId, Rc<AstNode<'cd, A>>>,
+}
+
+/// Structures `graph`, which is entered at each of `entries`, into one AST
+/// per entry, sharing the ASTs of the code that more than one of them goes
+/// on to, as described in the [module docs](self).
+///
+/// `graph` must meet the preconditions of [`ControlFlowGraph::new`], but
+/// with each of `entries` taking the place of the entry: all nodes must be
+/// reachable from one of them, though an entry may have predecessors. The
+/// handlers that `Unwind` and `Abnormal` edges lead to are left out, like
+/// [`ControlFlowGraph::structure_from`] does.
+pub fn structure_entries<'cd, A>(
+    graph: StableDiGraph<CfgNode<'cd, A>, CfgEdge>,
+    entries: &[NodeIndex],
+    cctx: CondContext<'cd, A>,
+    actx: A,
+    opts: &StructuringOptions,
+) -> Result<(EntryAsts<'cd, A>, A), StructureError>
+where
+    A: AstContextMut,
+    A::Block: Clone,
+{
+    let fail = |detail| Err(internal("structure_entries", detail));
+    if entries.is_empty() {
+        return fail("no entries".to_owned());
+    }
+    for (i, &e) in entries.iter().enumerate() {
+        if !graph.contains_node(e) {
+            return fail(format!("no node {}", e.index()));
+        }
+        if entries[..i].contains(&e) {
+            return fail(format!("node {} is given twice", e.index()));
+        }
+    }
+    let nodes: NodeSet = graph.node_indices().collect();
+    let mut splitter = Splitter {
+        graph,
+        cctx,
+        actx: Some(actx),
+        opts,
+        shared: HashMap::new(),
+    };
+    let asts = splitter.structure(entries, &nodes)?;
+    let ret = EntryAsts {
+        entries: asts,
+        shared: splitter.shared,
+    };
+    Ok((ret, splitter.actx.unwrap()))
+}
+
+struct Splitter<'a, 'cd, A: AstContext> {
+    graph: StableDiGraph<CfgNode<'cd, A>, CfgEdge>,
+    cctx: CondContext<'cd, A>,
+    /// `None` only while a graph is being structured
+    actx: Option<A>,
+    opts: &'a StructuringOptions,
+    shared: HashMap<LabelId, Rc<AstNode<'cd, A>>>,
+}
+
+impl<'a, 'cd, A> Splitter<'a, 'cd, A>
+where
+    A: AstContextMut,
+    A::Block: Clone,
+{
+    /// Structures the part of `nodes` reachable from each of `entries`,
+    /// first structuring the parts they share and adding them to `shared`.
+    fn structure(
+        &mut self,
+        entries: &[NodeIndex],
+        nodes: &NodeSet,
+    ) -> Result<Vec<Rc<AstNode<'cd, A>>>, StructureError> {
+        let reach: Vec<_> = entries.iter().map(|&e| self.reach(e, nodes)).collect();
+        let mut any = NodeSet::with_capacity(self.graph.node_bound());
+        let mut shared = NodeSet::with_capacity(self.graph.node_bound());
+        for r in &reach {
+            for n in r {
+                if !any.insert(n) {
+                    shared.insert(n);
+                }
+            }
+        }
+        if shared.len() == any.len() {
+            // nothing to split off, see the module docs
+            shared.clear();
+        }
+        if !shared.is_empty() {
+            let heads: Vec<_> = shared
+                .iter()
+                .filter(|&n| {
+                    entries.contains(&n)
+                        || self
+                            .graph
+                            .neighbors_directed(n, Incoming)
+                            .any(|p| any.contains(p) && !shared.contains(p))
+                })
+                .collect();
+            let asts = self.structure(&heads, &shared)?;
+            for (h, ast) in heads.into_iter().zip(asts) {
+                self.shared.insert(label(h), ast);
+            }
+        }
+        entries
+            .iter()
+            .zip(&reach)
+            .map(|(&e, r)| {
+                if shared.contains(e) {
+                    Ok(Rc::clone(&self.shared[&label(e)]))
+                } else {
+                    self.structure_part(e, r, &shared).map(Rc::new)
+                }
+            })
+            .collect()
+    }
+
+    /// The nodes of `nodes` reachable from `entry` within them.
+    fn reach(&self, entry: NodeIndex, nodes: &NodeSet) -> NodeSet {
+        let mut reach = NodeSet::with_capacity(self.graph.node_bound());
+        let mut stack = vec![entry];
+        while let Some(n) = stack.pop() {
+            if reach.insert(n) {
+                stack.extend(self.graph.neighbors(n).filter(|&m| nodes.contains(m)));
+            }
+        }
+        reach
+    }
+
+    /// Structures a copy of the nodes of `part` entered at `entry`, each
+    /// node of `shared` it gets to replaced by a `Goto` to it. The copy
+    /// keeps the indices of the nodes, so that the labels of any `Goto`s
+    /// left over from structuring it are those of the original nodes too.
+    fn structure_part(
+        &mut self,
+        entry: NodeIndex,
+        part: &NodeSet,
+        shared: &NodeSet,
+    ) -> Result<AstNode<'cd, A>, StructureError> {
+        let mut graph = StableDiGraph::with_capacity(self.graph.node_bound(), 0);
+        for _ in 0..self.graph.node_bound() {
+            graph.add_node(CfgNode::Dummy("multi_entry"));
+        }
+        let mut kept = NodeSet::with_capacity(self.graph.node_bound());
+        for n in part {
+            if shared.contains(n) {
+                continue;
+            }
+            kept.insert(n);
+            graph[n] = self.graph[n].clone();
+            // `edges` lists the newest edge first; add them in the order
+            // they were added, so that the copy is structured like the
+            // original would be
+            let edges: Vec<_> = self.graph.edges(n).collect();
+            for e in edges.into_iter().rev() {
+                let target = e.target();
+                if shared.contains(target) && kept.insert(target) {
+                    graph[target] = CfgNode::Code(AstNodeC::Goto(label(target)));
+                }
+                graph.add_edge(n, target, *e.weight());
+            }
+        }
+        // the entry must be a source
+        let mut cfg_entry = entry;
+        if graph.neighbors_directed(entry, Incoming).next().is_some() {
+            cfg_entry = graph.add_node(empty_node());
+            graph.add_edge(cfg_entry, entry, CfgEdge::True);
+        }
+        for n in 0..self.graph.node_bound() {
+            let n = NodeIndex::new(n);
+            if !kept.contains(n) {
+                graph.remove_node(n);
+            }
+        }
+
+        let cfg = ControlFlowGraph {
+            graph,
+            entry: cfg_entry,
+            cctx: self.cctx,
+            actx: self.actx.take().unwrap(),
+            value_sets: Default::default(),
+            branch_weights: Default::default(),
+            no_duplicate: NodeSet::new(),
+            report: Default::default(),
+            trace: None,
+            observer: None,
+            namer: None,
+            cancel: None,
+            dominators: None,
+            budget: None,
+            dump: None,
+            decisions: Default::default(),
+            struct_vars: Vec::new(),
+        };
+        cfg.check();
+        let (ast, _, actx, _) = cfg.try_structure_all(self.opts)?;
+        self.actx = Some(actx);
+        Ok(ast)
+    }
+}
+
+fn label(n: NodeIndex) -> LabelId {
+    LabelId(n.index())
+}
@@ -0,0 +1,99 @@
+//! The names of what structuring makes up: the variables it introduces and
+//! the labels the `Goto`s it leaves behind go to, see [`Namer`].
+//!
+//! Structuring passes the name of each variable to the context as it makes
+//! it, see [`AstContextMut::mk_named_var`](super::ast_context::AstContextMut::mk_named_var),
+//! and names the labels once it is done, in the order of their
+//! [`LabelId`]s; both go in the report, see
+//! [`StructuringReport::var_names`](super::StructuringReport::var_names) and
+//! [`StructuringReport::labels`](super::StructuringReport::labels). A
+//! frontend whose own names may clash with the default ones installs its
+//! own namer in [`StructuringOptions::namer`](super::StructuringOptions::namer).
+
+use super::ast::{AstNode, LabelId};
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Names what structuring makes up. Structuring asks for the names in the
+/// same order each time it runs on the same graph with the same options, so
+/// a namer that only counts gives the same names each time too.
+pub trait Namer: fmt::Debug {
+    /// A name for a new variable. `hint` tells what it is for: `entry` for
+    /// the one that an irreducible loop dispatches on, `exit` for the one
+    /// that the successor of a loop dispatches on, and `cond` for one that
+    /// saves the value of a condition tested again later.
+    fn fresh_var(&mut self, hint: &str) -> String;
+
+    /// A name for a label.
+    fn fresh_label(&mut self) -> String;
+}
+
+/// The namer that structuring uses unless told otherwise: `{hint}_{n}` for
+/// the `n`th variable, and `label_{n}` for the `n`th label, each counted
+/// from 0 in each run.
+#[derive(Debug, Default)]
+pub struct CounterNamer {
+    vars: usize,
+    labels: usize,
+}
+
+impl Namer for CounterNamer {
+    fn fresh_var(&mut self, hint: &str) -> String {
+        self.vars += 1;
+        format!("{}_{}", hint, self.vars - 1)
+    }
+
+    fn fresh_label(&mut self) -> String {
+        self.labels += 1;
+        format!("label_{}", self.labels - 1)
+    }
+}
+
+/// Names the labels that the `Goto`s and `Label`s of `asts` refer to, in
+/// the order of their ids.
+pub(super) fn name_labels<'a, B: 'a, C: 'a, V: 'a, I>(
+    namer: &RefCell<dyn Namer>,
+    asts: I,
+) -> Vec<(LabelId, String)>
+where
+    I: IntoIterator<Item = &'a AstNode<B, C, V>>,
+{
+    fn find<B, C, V>(ast: &AstNode<B, C, V>, labels: &mut BTreeSet<LabelId>) {
+        use self::AstNode::*;
+        match ast {
+            Seq(seq) => seq.iter().for_each(|a| find(a, labels)),
+            Cond(_, t, e) => {
+                find(t, labels);
+                if let Some(e) = e {
+                    find(e, labels);
+                }
+            }
+            Loop(_, b) | For(_, _, _, b) | Try(b, _) => find(b, labels),
+            Switch(_, cases, default) => {
+                cases.iter().for_each(|(_, a)| find(a, labels));
+                find(default, labels);
+            }
+            Unstructured(members) => {
+                for (l, a) in members {
+                    labels.insert(*l);
+                    find(a, labels);
+                }
+            }
+            Goto(l) | Label(l) => {
+                labels.insert(*l);
+            }
+            BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) => (),
+        }
+    }
+    let mut labels = BTreeSet::new();
+    for ast in asts {
+        find(ast, &mut labels);
+    }
+    let mut namer = namer.borrow_mut();
+    labels
+        .into_iter()
+        .map(|l| (l, namer.fresh_label()))
+        .collect()
+}
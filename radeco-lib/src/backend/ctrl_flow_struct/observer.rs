@@ -0,0 +1,107 @@
+//! Callbacks on what structuring does, as it does it, e.g. for an animated
+//! view of the algorithm.
+//!
+//! Structuring calls the [`Observer`] installed in
+//! [`StructuringOptions::observer`](super::StructuringOptions::observer), if
+//! any, right as each event happens, before it goes on. Unlike the steps of
+//! a [trace](super::trace), the events also tell what structuring found on
+//! the way: the loops before they are collapsed, and where it had to fall
+//! back on a variable. An observer only gets nodes by their index and sets
+//! of them, never the graph. Without one, structuring does none of the work
+//! of telling it.
+
+use super::ast::{AstNode, LoopType};
+use super::Fallback;
+
+use fixedbitset::FixedBitSet;
+use petgraph::graph::NodeIndex;
+
+use std::fmt;
+use std::iter;
+use std::mem::{self, Discriminant};
+
+/// What structuring tells of its events. Each method does nothing unless
+/// overridden, so an observer only overrides those it cares for.
+pub trait Observer: fmt::Debug {
+    /// A loop headed by `header` was found, with a back edge from each of
+    /// `latches`. Once its body is structured, it is collapsed like a
+    /// region.
+    fn on_loop_found(&mut self, _header: NodeIndex, _latches: &FixedBitSet) {}
+
+    /// The region or loop of `members`, `header` included, became a single
+    /// AST, which the node `result` holds from then on.
+    fn on_region_collapsed(
+        &mut self,
+        _header: NodeIndex,
+        _members: &FixedBitSet,
+        _result: NodeIndex,
+    ) {
+    }
+
+    /// The refinement `pass` ran, and `changed` tells whether it changed
+    /// the shape of the AST: its nodes, how they nest, or what they test.
+    /// `refine_loop` runs on each loop before it is collapsed, and is
+    /// `changed` if the loop is no longer an endless one; `fold_struct_vars`,
+    /// `unrotate_loops`, `guard_clauses` and `merge_contiguous_blocks` run
+    /// once structuring is done, if the options ask for them.
+    fn on_refinement(&mut self, _pass: &str, _changed: bool) {}
+
+    /// Structuring had to fall back on a variable to give a loop a single
+    /// entry or exit.
+    fn on_fallback(&mut self, _fallback: &Fallback) {}
+}
+
+/// A node of an AST, as far as [`shape`] tells it apart from others.
+pub(super) type ShapeNode<B, C, V> = (
+    Discriminant<AstNode<B, C, V>>,
+    Option<Discriminant<LoopType<C>>>,
+    usize,
+    Option<C>,
+);
+
+/// The nodes of `ast` in preorder, each as its variant, its kind of loop if
+/// it is one, how many children it has, and the condition it tests, if
+/// any, so that comparing the shapes of an AST before and after a pass
+/// tells whether the pass changed anything but the blocks.
+pub(super) fn shape<B, C: Copy, V>(ast: &AstNode<B, C, V>) -> Vec<ShapeNode<B, C, V>> {
+    fn walk<B, C: Copy, V>(ast: &AstNode<B, C, V>, nodes: &mut Vec<ShapeNode<B, C, V>>) {
+        use self::AstNode::*;
+        let i = nodes.len();
+        nodes.push((mem::discriminant(ast), None, 0, None));
+        let children: Vec<&AstNode<B, C, V>> = match ast {
+            Seq(seq) => seq.iter().collect(),
+            Cond(c, t, e) => {
+                nodes[i].3 = Some(*c);
+                iter::once(&**t).chain(e.as_deref()).collect()
+            }
+            Loop(ty, b) => {
+                nodes[i].1 = Some(mem::discriminant(ty));
+                if let LoopType::PreChecked(c) | LoopType::PostChecked(c) = ty {
+                    nodes[i].3 = Some(*c);
+                }
+                vec![&**b]
+            }
+            For(_, c, _, b) => {
+                nodes[i].3 = Some(*c);
+                vec![&**b]
+            }
+            Try(b, _) => vec![&**b],
+            Switch(_, cases, default) => cases
+                .iter()
+                .map(|(_, a)| a)
+                .chain(Some(&**default))
+                .collect(),
+            Unstructured(members) => members.iter().map(|(_, a)| a).collect(),
+            BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_)
+            | Label(_) => Vec::new(),
+        };
+        nodes[i].2 = children.len();
+        for c in children {
+            walk(c, nodes);
+        }
+    }
+
+    let mut nodes = Vec::new();
+    walk(ast, &mut nodes);
+    nodes
+}
@@ -0,0 +1,359 @@
+//! See [`StructuringOptions`].
+
+use super::error::InputMode;
+use super::matchers::IdiomMatcher;
+use super::naming::Namer;
+use super::observer::Observer;
+#[cfg(test)]
+use super::trace::TraceOp;
+use super::trace::TraceSink;
+use super::{idioms, spin_loops, state_machines, trip_counts};
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Knobs controlling how [`ControlFlowGraph`](super::ControlFlowGraph)
+/// structures a graph. Start from [`default`](Self::default),
+/// [`strict`](Self::strict) or [`fast`](Self::fast) and change the fields, or
+/// chain the methods of the same names:
+///
+/// ```
+/// # use radeco_lib::backend::ctrl_flow_struct::{Budget, StructuringOptions};
+/// let opts = StructuringOptions::fast()
+///     .refine_loops(false)
+///     .budget(Budget::Steps(100));
+/// assert!(!opts.recover_switches);
+/// ```
+///
+/// The refinements only change how readable the AST is, never what it
+/// does.
+#[derive(Clone, Debug)]
+pub struct StructuringOptions {
+    /// Merge the chains of code nodes before structuring, see
+    /// [`merge_linear_chains`](super::ControlFlowGraph::merge_linear_chains).
+    pub merge_linear_chains: bool,
+    /// Split the critical edges before structuring, see
+    /// [`split_critical_edges`](super::ControlFlowGraph::split_critical_edges).
+    pub split_critical_edges: bool,
+    /// Give the loops preheaders before structuring, see
+    /// [`insert_preheaders`](super::ControlFlowGraph::insert_preheaders).
+    pub insert_preheaders: bool,
+    /// Split the latches of the loops that have side effects before
+    /// structuring, see
+    /// [`split_latches`](super::ControlFlowGraph::split_latches).
+    pub split_latches: bool,
+    /// Collapse the acyclic SESE regions before the main pass.
+    pub collapse_sese_regions: bool,
+    /// Group the code reached for values of a variable into a `Switch` on
+    /// it, see [`set_value_set`](super::ControlFlowGraph::set_value_set).
+    pub recover_switches: bool,
+    /// Nest code in `if`-`else`s by its reaching conditions, rather than
+    /// give each node an `if` of its own. The most expensive refinement.
+    pub refine_conditionals: bool,
+    /// Turn endless loops that test first or last into `while` and
+    /// `do`-`while` loops, see
+    /// [`invariants::unrotate_loops`](super::invariants::unrotate_loops).
+    pub refine_loops: bool,
+    /// Turn the `if`s whose other arm returns into guard clauses, where that
+    /// takes away at least this many levels of nesting.
+    pub guard_clauses: Option<usize>,
+    /// Merge the blocks of contiguous code next to each other, see
+    /// [`contiguous_blocks::merge`](super::contiguous_blocks::merge).
+    pub merge_contiguous_blocks: bool,
+    /// Put the heavier branch of each `if`-`else` first, as
+    /// [`set_branch_weights`](super::ControlFlowGraph::set_branch_weights)
+    /// tells.
+    pub order_by_weight: bool,
+    /// Fold the tests of the variables introduced for abnormal entries and
+    /// exits, see [`Fallback`](super::Fallback), where their value is known.
+    pub fold_struct_vars: bool,
+    /// Annotate each `Endless` loop with when it exits, see
+    /// [`loop_exits::summarize`](super::loop_exits::summarize).
+    pub summarize_loop_exits: bool,
+    /// Copy each tail of at most this many code nodes that two branches
+    /// share into both. It takes a context that can
+    /// [copy blocks](super::ast_context::AstContextMut::clone_block).
+    pub duplicate_tails: Option<usize>,
+    /// Copy each acyclic part of at most this many nodes that several
+    /// branches enter at the same node into each of them. It takes a
+    /// context that can copy blocks and conditions.
+    pub split_shared_regions: Option<usize>,
+    /// How many nodes the copies may take, see [`DuplicationLimit`].
+    /// Without a limit, loops are never copied.
+    pub max_duplicated_nodes: Option<DuplicationLimit>,
+    /// Check the preconditions of [`new`](super::ControlFlowGraph::new) up
+    /// front in any build.
+    pub check_invariants: bool,
+    /// What to do about the side exits of acyclic regions, see
+    /// [`InputDefect::SideExit`](super::InputDefect::SideExit). Apart from
+    /// [`ImportOptions::mode`](super::from_r2::ImportOptions::mode), which
+    /// is about the blocks.
+    pub input_mode: InputMode,
+    /// Where to report each step of structuring, see [`trace`](super::trace).
+    pub trace: Option<Rc<RefCell<dyn TraceSink>>>,
+    /// What to tell of each event as it happens, see
+    /// [`observer`](super::observer).
+    pub observer: Option<Rc<RefCell<dyn Observer>>>,
+    /// What to name the variables and labels that structuring makes up, see
+    /// [`naming`](super::naming). By default, a new
+    /// [`CounterNamer`](super::naming::CounterNamer) for each run.
+    pub namer: Option<Rc<RefCell<dyn Namer>>>,
+    /// What to recognize in the resulting ASTs, once they are done. By
+    /// default, the built-in matchers.
+    pub matchers: Vec<Rc<dyn IdiomMatcher>>,
+    /// How much work structuring may do before it lays out the rest of the
+    /// graph with `Goto`s.
+    pub budget: Option<Budget>,
+    /// Fail with [`Cancelled`](super::StructureError::Cancelled) once this
+    /// is cancelled.
+    pub cancel: Option<CancelToken>,
+    /// Where to dump the graph and the collapsed regions as DOT files, for
+    /// debugging.
+    pub dump_dir: Option<PathBuf>,
+    /// A kind of collapse to skip, so that tests can show the progress
+    /// guard at work.
+    #[cfg(test)]
+    pub(crate) disabled_collapse: Option<TraceOp>,
+}
+
+impl Default for StructuringOptions {
+    fn default() -> Self {
+        StructuringOptions {
+            merge_linear_chains: false,
+            split_critical_edges: false,
+            insert_preheaders: false,
+            split_latches: false,
+            collapse_sese_regions: true,
+            recover_switches: true,
+            refine_conditionals: true,
+            refine_loops: true,
+            guard_clauses: None,
+            merge_contiguous_blocks: false,
+            order_by_weight: false,
+            fold_struct_vars: true,
+            summarize_loop_exits: true,
+            duplicate_tails: None,
+            split_shared_regions: None,
+            max_duplicated_nodes: None,
+            check_invariants: false,
+            input_mode: InputMode::Lenient,
+            trace: None,
+            observer: None,
+            namer: None,
+            matchers: vec![
+                Rc::new(idioms::MinMax),
+                Rc::new(state_machines::StateMachines),
+                Rc::new(spin_loops::SpinLoops),
+                Rc::new(trip_counts::TripCounts),
+            ],
+            budget: None,
+            cancel: None,
+            dump_dir: None,
+            #[cfg(test)]
+            disabled_collapse: None,
+        }
+    }
+}
+
+impl StructuringOptions {
+    /// Every refinement, with the preconditions checked, failing on the side
+    /// exits.
+    pub fn strict() -> Self {
+        StructuringOptions {
+            check_invariants: true,
+            input_mode: InputMode::Strict,
+            ..Default::default()
+        }
+    }
+
+    /// Without the refinements of conditionals and switches, which take the
+    /// most time on large regions. Loops are still refined.
+    pub fn fast() -> Self {
+        StructuringOptions {
+            recover_switches: false,
+            refine_conditionals: false,
+            ..Default::default()
+        }
+    }
+
+    pub fn merge_linear_chains(mut self, on: bool) -> Self {
+        self.merge_linear_chains = on;
+        self
+    }
+
+    pub fn split_critical_edges(mut self, on: bool) -> Self {
+        self.split_critical_edges = on;
+        self
+    }
+
+    pub fn insert_preheaders(mut self, on: bool) -> Self {
+        self.insert_preheaders = on;
+        self
+    }
+
+    pub fn split_latches(mut self, on: bool) -> Self {
+        self.split_latches = on;
+        self
+    }
+
+    pub fn collapse_sese_regions(mut self, on: bool) -> Self {
+        self.collapse_sese_regions = on;
+        self
+    }
+
+    pub fn recover_switches(mut self, on: bool) -> Self {
+        self.recover_switches = on;
+        self
+    }
+
+    pub fn refine_conditionals(mut self, on: bool) -> Self {
+        self.refine_conditionals = on;
+        self
+    }
+
+    pub fn refine_loops(mut self, on: bool) -> Self {
+        self.refine_loops = on;
+        self
+    }
+
+    pub fn guard_clauses(mut self, min_depth: usize) -> Self {
+        self.guard_clauses = Some(min_depth);
+        self
+    }
+
+    pub fn merge_contiguous_blocks(mut self, on: bool) -> Self {
+        self.merge_contiguous_blocks = on;
+        self
+    }
+
+    pub fn order_by_weight(mut self, on: bool) -> Self {
+        self.order_by_weight = on;
+        self
+    }
+
+    pub fn fold_struct_vars(mut self, on: bool) -> Self {
+        self.fold_struct_vars = on;
+        self
+    }
+
+    pub fn summarize_loop_exits(mut self, on: bool) -> Self {
+        self.summarize_loop_exits = on;
+        self
+    }
+
+    pub fn duplicate_tails(mut self, max_len: usize) -> Self {
+        self.duplicate_tails = Some(max_len);
+        self
+    }
+
+    pub fn split_shared_regions(mut self, max_nodes: usize) -> Self {
+        self.split_shared_regions = Some(max_nodes);
+        self
+    }
+
+    pub fn max_duplicated_nodes(mut self, per_construct: usize, per_function: usize) -> Self {
+        self.max_duplicated_nodes = Some(DuplicationLimit {
+            per_construct,
+            per_function,
+        });
+        self
+    }
+
+    pub fn check_invariants(mut self, on: bool) -> Self {
+        self.check_invariants = on;
+        self
+    }
+
+    pub fn input_mode(mut self, mode: InputMode) -> Self {
+        self.input_mode = mode;
+        self
+    }
+
+    pub fn trace(mut self, sink: Rc<RefCell<dyn TraceSink>>) -> Self {
+        self.trace = Some(sink);
+        self
+    }
+
+    pub fn observer(mut self, observer: Rc<RefCell<dyn Observer>>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    pub fn namer(mut self, namer: Rc<RefCell<dyn Namer>>) -> Self {
+        self.namer = Some(namer);
+        self
+    }
+
+    /// Adds `matcher` to [`matchers`](Self::matchers).
+    pub fn matcher(mut self, matcher: Rc<dyn IdiomMatcher>) -> Self {
+        self.matchers.push(matcher);
+        self
+    }
+
+    pub fn budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn dump_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.dump_dir = Some(dir.into());
+        self
+    }
+}
+
+/// How many nodes structuring may copy, see
+/// [`StructuringOptions::max_duplicated_nodes`]: at most `per_construct` for
+/// each copy, and `per_function` for all of them. The copies it does without
+/// are listed in
+/// [`StructuringReport::declined`](super::StructuringReport::declined).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DuplicationLimit {
+    pub per_construct: usize,
+    pub per_function: usize,
+}
+
+impl DuplicationLimit {
+    /// Whether a copy of `nodes` nodes stays within the limit, after
+    /// `copied` nodes were copied already.
+    pub(super) fn allows(&self, nodes: usize, copied: usize) -> bool {
+        nodes <= self.per_construct && copied + nodes <= self.per_function
+    }
+}
+
+/// A limit on the work of structuring, see [`StructuringOptions::budget`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Budget {
+    /// the number of regions and loops that may be collapsed
+    Steps(usize),
+    /// the wall-clock time structuring may take
+    Time(Duration),
+}
+
+/// Cancels structuring from elsewhere, e.g. another thread, see
+/// [`StructuringOptions::cancel`]. It is checked before each collapse and
+/// each handler.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
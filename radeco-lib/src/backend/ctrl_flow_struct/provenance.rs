@@ -0,0 +1,369 @@
+//! Which parts of the binary the nodes of an AST come from, and, given a
+//! [`LineTable`], which source lines.
+
+use super::ast::AstNode;
+
+use std::cmp;
+use std::fmt;
+use std::ops::Range;
+
+/// Tells which addresses the blocks and conditions of an `AstNode` come
+/// from.
+pub trait Provenance<B, C> {
+    /// Returns the addresses of the code in `block`, if it has any.
+    fn block_range(&self, block: &B) -> Option<Range<u64>>;
+
+    /// Returns the address of the branch that `cond` was recovered from, if
+    /// there is a single one.
+    fn cond_addr(&self, cond: &C) -> Option<u64>;
+
+    /// Returns the address of the start of the code in `block`, if it has
+    /// any.
+    fn block_addr(&self, block: &B) -> Option<u64> {
+        self.block_range(block).map(|r| r.start)
+    }
+}
+
+/// A set of addresses, stored as sorted, disjoint, non-adjacent ranges.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddrSet {
+    ranges: Vec<Range<u64>>,
+}
+
+impl AddrSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ranges(&self) -> &[Range<u64>] {
+        &self.ranges
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        match self.ranges.binary_search_by(|r| r.start.cmp(&addr)) {
+            Ok(_) => true,
+            Err(0) => false,
+            Err(i) => addr < self.ranges[i - 1].end,
+        }
+    }
+
+    pub fn insert(&mut self, range: Range<u64>) {
+        if range.start >= range.end {
+            return;
+        }
+        // the ranges that overlap or touch `range`
+        let first = self.ranges.iter().position(|r| r.end >= range.start);
+        let first = first.unwrap_or(self.ranges.len());
+        let last = self.ranges[first..]
+            .iter()
+            .position(|r| r.start > range.end)
+            .map_or(self.ranges.len(), |i| first + i);
+        let merged = if first < last {
+            cmp::min(range.start, self.ranges[first].start)
+                ..cmp::max(range.end, self.ranges[last - 1].end)
+        } else {
+            range
+        };
+        self.ranges.splice(first..last, Some(merged));
+    }
+
+    pub fn union_with(&mut self, other: &AddrSet) {
+        for r in &other.ranges {
+            self.insert(r.clone());
+        }
+    }
+}
+
+/// Returns the addresses covered by the blocks in `ast`. A composite node
+/// covers the union of what its children cover, so e.g. a `Loop` covers all
+/// of its body. Conditions don't add anything since the branches they come
+/// from are part of some block. A block that was duplicated during
+/// structuring covers the same addresses in each copy.
+pub fn covered<B, C, V, P>(prov: &P, ast: &AstNode<B, C, V>) -> AddrSet
+where
+    P: Provenance<B, C>,
+{
+    let mut ret = AddrSet::new();
+    add_covered(prov, ast, &mut ret);
+    ret
+}
+
+/// Maps addresses to the source lines they were compiled from, e.g. as read
+/// out of DWARF `.debug_line` by the caller. Like there, each row holds from
+/// its address up to the address of the next row.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineTable {
+    /// sorted by address; `None` for code without line information
+    rows: Vec<(u64, Option<(String, u32)>)>,
+}
+
+/// A range of lines of a source file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLines {
+    pub file: String,
+    pub first: u32,
+    /// inclusive
+    pub last: u32,
+}
+
+impl LineTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Says that the code from `addr` on comes from `line` of `file`.
+    pub fn add_row(&mut self, addr: u64, file: &str, line: u32) {
+        self.insert(addr, Some((file.to_owned(), line)));
+    }
+
+    /// Says that the code from `addr` on has no line information, like the
+    /// end of a DWARF sequence.
+    pub fn end_sequence(&mut self, addr: u64) {
+        self.insert(addr, None);
+    }
+
+    fn insert(&mut self, addr: u64, row: Option<(String, u32)>) {
+        match self.rows.binary_search_by(|r| r.0.cmp(&addr)) {
+            Ok(i) => self.rows[i].1 = row,
+            Err(i) => self.rows.insert(i, (addr, row)),
+        }
+    }
+
+    /// Returns the lines that the code in `range` comes from. Lines from
+    /// files other than that of the first line, e.g. of inlined functions,
+    /// are left out.
+    pub fn lines_in(&self, range: Range<u64>) -> Option<SourceLines> {
+        self.rows_in(range).fold(None, SourceLines::union_opt)
+    }
+
+    /// The lines of the rows holding some of `range`, in order.
+    fn rows_in(&self, range: Range<u64>) -> impl Iterator<Item = SourceLines> + '_ {
+        // the row holding `range.start`, and the ones after it in `range`
+        let first = match self.rows.binary_search_by(|r| r.0.cmp(&range.start)) {
+            Ok(i) => i,
+            Err(0) => 0,
+            Err(i) => i - 1,
+        };
+        self.rows[first..]
+            .iter()
+            .take_while(move |r| r.0 < range.end)
+            .filter_map(|r| r.1.as_ref())
+            .map(|(file, line)| SourceLines {
+                file: file.clone(),
+                first: *line,
+                last: *line,
+            })
+    }
+}
+
+impl SourceLines {
+    /// The lines spanned by `acc` and `other`, ignoring `other` if it's of
+    /// another file.
+    fn union_opt(acc: Option<SourceLines>, other: SourceLines) -> Option<SourceLines> {
+        Some(match acc {
+            Some(acc) if acc.file == other.file => SourceLines {
+                first: cmp::min(acc.first, other.first),
+                last: cmp::max(acc.last, other.last),
+                file: acc.file,
+            },
+            Some(acc) => acc,
+            None => other,
+        })
+    }
+}
+
+impl fmt::Display for SourceLines {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}:{}", self.file, self.first)
+        } else {
+            write!(f, "{}:{}-{}", self.file, self.first, self.last)
+        }
+    }
+}
+
+/// Returns the source lines that the code of `ast` comes from, aggregated
+/// like [`covered`] does with addresses. As with
+/// [`LineTable::lines_in`], only the file of the first line counts.
+pub fn source_lines<B, C, V, P>(
+    prov: &P,
+    lines: &LineTable,
+    ast: &AstNode<B, C, V>,
+) -> Option<SourceLines>
+where
+    P: Provenance<B, C>,
+{
+    covered(prov, ast)
+        .ranges()
+        .iter()
+        .flat_map(|r| lines.rows_in(r.clone()))
+        .fold(None, SourceLines::union_opt)
+}
+
+fn add_covered<B, C, V, P>(prov: &P, ast: &AstNode<B, C, V>, out: &mut AddrSet)
+where
+    P: Provenance<B, C>,
+{
+    use self::AstNode::*;
+    match ast {
+        BasicBlock(b) => {
+            if let Some(r) = prov.block_range(b) {
+                out.insert(r);
+            }
+        }
+        Seq(seq) => {
+            for a in seq {
+                add_covered(prov, a, out);
+            }
+        }
+        Cond(_, t, oe) => {
+            add_covered(prov, t, out);
+            if let Some(e) = oe {
+                add_covered(prov, e, out);
+            }
+        }
+        Loop(_, b) | Try(b, _) => add_covered(prov, b, out),
+        For(i, _, u, b) => {
+            for block in &[i, u] {
+                if let Some(r) = prov.block_range(block) {
+                    out.insert(r);
+                }
+            }
+            add_covered(prov, b, out);
+        }
+        TailCall(b) | IndirectJump(b) => {
+            if let Some(r) = prov.block_range(b) {
+                out.insert(r);
+            }
+        }
+        Switch(_, cases, default) => {
+            for (_, a) in cases {
+                add_covered(prov, a, out);
+            }
+            add_covered(prov, default, out);
+        }
+        Unstructured(members) => {
+            for (_, a) in members {
+                add_covered(prov, a, out);
+            }
+        }
+        Break | Continue | Return | Goto(_) | Label(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::ast::LoopType;
+
+    /// Blocks are `(addr, size)`.
+    struct Sizes;
+
+    impl Provenance<(u64, u64), ()> for Sizes {
+        fn block_range(&self, &(addr, size): &(u64, u64)) -> Option<Range<u64>> {
+            Some(addr..addr + size)
+        }
+
+        fn cond_addr(&self, _cond: &()) -> Option<u64> {
+            None
+        }
+    }
+
+    fn bb(addr: u64, size: u64) -> AstNode<(u64, u64), (), ()> {
+        BasicBlock((addr, size))
+    }
+
+    #[test]
+    fn addr_set_insert() {
+        let mut set = AddrSet::new();
+        set.insert(10..20);
+        set.insert(30..40);
+        set.insert(0..5);
+        assert_eq!(set.ranges(), &[0..5, 10..20, 30..40]);
+        // adjacent ranges are merged
+        set.insert(20..25);
+        assert_eq!(set.ranges(), &[0..5, 10..25, 30..40]);
+        // so are all the ranges an insertion overlaps
+        set.insert(3..35);
+        assert_eq!(set.ranges().to_vec(), vec![0..40]);
+        set.insert(7..7);
+        assert_eq!(set.ranges().to_vec(), vec![0..40]);
+        assert!(set.contains(0));
+        assert!(set.contains(39));
+        assert!(!set.contains(40));
+    }
+
+    #[test]
+    fn nested_coverage() {
+        let inner = Cond((), Box::new(bb(0x20, 4)), Some(Box::new(bb(0x30, 8))));
+        let body = Seq(vec![bb(0x10, 0x10), inner.clone()]);
+        let ast = Seq(vec![
+            bb(0x0, 8),
+            Loop(LoopType::Endless, Box::new(body.clone())),
+            bb(0x80, 2),
+        ]);
+
+        assert_eq!(covered(&Sizes, &inner).ranges(), &[0x20..0x24, 0x30..0x38]);
+        // one level up, the loop covers the union of its members
+        let loop_covered = covered(&Sizes, &Loop(LoopType::Endless, Box::new(body)));
+        assert_eq!(loop_covered.ranges(), &[0x10..0x24, 0x30..0x38]);
+        assert_eq!(
+            covered(&Sizes, &ast).ranges(),
+            &[0x0..0x8, 0x10..0x24, 0x30..0x38, 0x80..0x82]
+        );
+    }
+
+    #[test]
+    fn duplicated_blocks() {
+        let ast = Cond(
+            (),
+            Box::new(Seq(vec![bb(0x10, 4), bb(0x40, 4)])),
+            Some(Box::new(Seq(vec![bb(0x20, 4), bb(0x40, 4)]))),
+        );
+        assert_eq!(
+            covered(&Sizes, &ast).ranges(),
+            &[0x10..0x14, 0x20..0x24, 0x40..0x44]
+        );
+    }
+
+    #[test]
+    fn lines_of_nodes() {
+        let mut lines = LineTable::new();
+        lines.add_row(0x0, "a.c", 1);
+        lines.add_row(0x8, "a.c", 2);
+        lines.add_row(0x10, "a.c", 3);
+        lines.add_row(0x18, "a.c", 4);
+        lines.add_row(0x20, "a.c", 5);
+        // inlined
+        lines.add_row(0x30, "inl.h", 10);
+        lines.add_row(0x34, "a.c", 6);
+        lines.end_sequence(0x38);
+        lines.add_row(0x80, "a.c", 9);
+        lines.end_sequence(0x82);
+
+        let inner = Cond((), Box::new(bb(0x20, 4)), Some(Box::new(bb(0x30, 8))));
+        let body = Seq(vec![bb(0x10, 0x10), inner.clone()]);
+        let lp = Loop(LoopType::Endless, Box::new(body));
+        let ast = Seq(vec![bb(0x0, 8), lp.clone(), bb(0x80, 2)]);
+
+        let at = |ast: &AstNode<(u64, u64), (), ()>| {
+            source_lines(&Sizes, &lines, ast).map(|l| l.to_string())
+        };
+        assert_eq!(at(&bb(0x0, 8)).as_deref(), Some("a.c:1"));
+        // a block spanning several lines carries all of them
+        assert_eq!(at(&bb(0x10, 0x10)).as_deref(), Some("a.c:3-4"));
+        // the lines of another file don't mix with the others
+        assert_eq!(at(&bb(0x30, 8)).as_deref(), Some("inl.h:10"));
+        assert_eq!(at(&inner).as_deref(), Some("a.c:5-6"));
+        assert_eq!(at(&lp).as_deref(), Some("a.c:3-6"));
+        assert_eq!(at(&ast).as_deref(), Some("a.c:1-9"));
+        // past the end of a sequence, and before the first row
+        assert_eq!(at(&bb(0x40, 4)), None);
+        assert_eq!(lines.lines_in(0x84..0x88), None);
+    }
+}
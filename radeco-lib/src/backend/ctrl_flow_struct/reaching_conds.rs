@@ -0,0 +1,133 @@
+//! See [`ReachingConds`].
+
+use super::ast_context::AstContext;
+use super::graph_utils::GraphSlice;
+use super::{CfgEdge, CfgNode, CondContext, CondVar, Condition};
+
+use petgraph::prelude::*;
+use petgraph::visit::NodeIndexable;
+
+/// The reaching conditions of the nodes in a graph slice.
+///
+/// Building this records, for every edge in the slice, which variable (if
+/// any) guards it. [`get`](Self::get) then builds the `Condition` of a node
+/// from those of its predecessors, once for each node, and turns each
+/// guarding variable into a `Condition` only once no matter how many edges
+/// it guards. Refinement compares the conditions of all the nodes of a
+/// region, so they are all built along with the graph it refines.
+pub(super) struct ReachingConds<'cd, A: AstContext> {
+    cctx: CondContext<'cd, A>,
+    start: NodeIndex,
+    /// for each node, its predecessors in the slice and the guard on the edge
+    /// from each of them
+    preds: Vec<Vec<(NodeIndex, EdgeGuard<'cd, A>)>>,
+    /// the reaching conditions built so far, indexed by node
+    conds: Vec<Option<Condition<'cd, A>>>,
+    /// the branch conditions built so far, indexed by the branching node
+    var_conds: Vec<Option<Condition<'cd, A>>>,
+}
+
+/// How an edge restricts the reaching condition of its target.
+enum EdgeGuard<'cd, A: AstContext> {
+    /// the edge is always taken
+    Always,
+    /// the edge is never taken
+    Never,
+    /// the edge is taken when the variable has the given value
+    Var(CondVar<'cd, A>, bool),
+}
+
+impl<'cd, A: AstContext> Copy for EdgeGuard<'cd, A> {}
+impl<'cd, A: AstContext> Clone for EdgeGuard<'cd, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'cd, A: AstContext> ReachingConds<'cd, A> {
+    pub fn new(
+        graph: &StableDiGraph<CfgNode<'cd, A>, CfgEdge>,
+        cctx: CondContext<'cd, A>,
+        slice: &GraphSlice<NodeIndex, EdgeIndex>,
+    ) -> Self {
+        // {Node, Edge}Filtered don't implement IntoNeighborsDirected :(
+        // https://github.com/bluss/petgraph/pull/219
+        // Also EdgeFiltered<Reversed<_>, _> isn't Into{Neighbors, Edges}
+        // because Reversed<_> isn't IntoEdges
+
+        let bound = graph.node_bound();
+        let mut preds = vec![Vec::new(); bound];
+        for &n in &slice.topo_order {
+            // manually restrict to slice
+            preds[n.index()] = graph
+                .edges_directed(n, Incoming)
+                .filter(|e| slice.edges.contains(e.id()))
+                .map(|e| {
+                    let guard = match (&graph[e.source()], e.weight()) {
+                        (&CfgNode::Condition(c), CfgEdge::True) => EdgeGuard::Var(c, true),
+                        (&CfgNode::Condition(c), CfgEdge::False) => EdgeGuard::Var(c, false),
+                        (_, CfgEdge::True) => EdgeGuard::Always,
+                        (_, CfgEdge::False) => EdgeGuard::Never,
+                        (_, CfgEdge::Unwind) | (_, CfgEdge::Abnormal) => {
+                            unreachable!("abnormal edge in a region")
+                        }
+                    };
+                    (e.source(), guard)
+                })
+                .collect();
+        }
+
+        Self {
+            cctx,
+            start: slice
+                .topo_order
+                .first()
+                .cloned()
+                .unwrap_or_else(NodeIndex::end),
+            preds,
+            conds: vec![None; bound],
+            var_conds: vec![None; bound],
+        }
+    }
+
+    /// Returns the reaching condition of `n`, which must be in the slice.
+    pub fn get(&mut self, n: NodeIndex) -> Condition<'cd, A> {
+        if let Some(cond) = self.conds[n.index()] {
+            return cond;
+        }
+
+        let cond = if n == self.start {
+            self.cctx.mk_true()
+        } else {
+            let cctx = self.cctx;
+            // `preds` doesn't change after construction, so it can be taken
+            // out while the conditions of the predecessors are built
+            let preds = std::mem::replace(&mut self.preds[n.index()], Vec::new());
+            let opn_v: Vec<_> = preds
+                .iter()
+                .map(|&(pred, guard)| {
+                    let src_cond = self.get(pred);
+                    match guard {
+                        EdgeGuard::Always => src_cond,
+                        EdgeGuard::Never => cctx.mk_false(),
+                        EdgeGuard::Var(c, val) => {
+                            let var_cond = self.var_cond(pred, c);
+                            let var_cond = if val { var_cond } else { cctx.mk_not(var_cond) };
+                            cctx.mk_and(src_cond, var_cond)
+                        }
+                    }
+                })
+                .collect();
+            self.preds[n.index()] = preds;
+            cctx.mk_or_from_iter(opn_v)
+        };
+
+        self.conds[n.index()] = Some(cond);
+        cond
+    }
+
+    fn var_cond(&mut self, branch: NodeIndex, c: CondVar<'cd, A>) -> Condition<'cd, A> {
+        let cctx = self.cctx;
+        *self.var_conds[branch.index()].get_or_insert_with(|| cctx.mk_var(c))
+    }
+}
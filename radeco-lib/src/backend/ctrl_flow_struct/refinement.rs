@@ -2,42 +2,85 @@
 //!
 //! Everything in this module does not impact correctness, only readability.
 
-use super::ast::LoopType;
+use super::ast::{LoopType, ValueSet};
 use super::ast_context::AstContext;
+use super::condition;
+use super::decisions::{DecisionLog, Phase};
 use super::graph_utils;
-use super::{AstNode, AstNodeC, CondContext, Condition, NodeSet};
+use super::{
+    AstNode, AstNodeC, BranchWeights, CondContext, Condition, NodeSet, StructuringOptions,
+    ValueSets,
+};
 
 use petgraph::algo;
 use petgraph::prelude::*;
 use petgraph::visit::{IntoNodeReferences, Topo, Walker};
 
+use std::cell::RefCell;
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::iter::FromIterator;
 
-pub(super) struct Refiner<'cd, A: AstContext> {
+pub(super) struct Refiner<'cd, 'vs, A: AstContext> {
     pub cctx: CondContext<'cd, A>,
+    pub value_sets: &'vs ValueSets<A>,
+    /// the weights [`StructuringOptions::order_by_weight`] orders by, if it
+    /// is on
+    pub weights: Option<&'vs BranchWeights>,
+    pub decisions: &'vs RefCell<DecisionLog<'cd, A::Condition>>,
+    /// the values that the nodes of a would-be `Switch` overlap on, see
+    /// [`StructuringReport::overlapping_cases`](super::StructuringReport::overlapping_cases)
+    pub overlaps: &'vs RefCell<Vec<ValueSet>>,
     pub graph: StableDiGraph<RefinementAstNode<'cd, A>, ()>,
+    /// [`StructuringOptions::recover_switches`]
+    pub switches: bool,
+    /// [`StructuringOptions::refine_conditionals`]
+    pub conditionals: bool,
 }
 
 pub(super) type RefinementAstNode<'cd, A> = (Condition<'cd, A>, Option<AstNode<'cd, A>>);
 
-/// Perform all refinements and return the resulting AST.
+/// Perform the refinements `opts` asks for and return the resulting AST.
+#[allow(clippy::too_many_arguments)]
 pub(super) fn refine<'cd, A: AstContext>(
     cctx: CondContext<'cd, A>,
+    value_sets: &ValueSets<A>,
+    weights: &BranchWeights,
+    decisions: &RefCell<DecisionLog<'cd, A::Condition>>,
+    overlaps: &RefCell<Vec<ValueSet>>,
     graph: StableDiGraph<RefinementAstNode<'cd, A>, ()>,
     entry: NodeIndex,
+    opts: &StructuringOptions,
 ) -> AstNode<'cd, A> {
-    let mut refiner = Refiner::<A> { cctx, graph };
+    let mut refiner = Refiner::<A> {
+        cctx,
+        value_sets,
+        weights: if opts.order_by_weight {
+            Some(weights)
+        } else {
+            None
+        },
+        decisions,
+        overlaps,
+        graph,
+        switches: opts.recover_switches,
+        conditionals: opts.refine_conditionals,
+    };
     refiner.combine_breaks(entry);
     refiner.refine()
 }
 
-impl<'cd, A: AstContext> Refiner<'cd, A> {
+impl<'cd, 'vs, A: AstContext> Refiner<'cd, 'vs, A> {
     fn refine(mut self) -> AstNode<'cd, A> {
-        self.try_find_if_else_pair();
-        self.try_find_if();
-        // TODO: find switch
-        self.try_find_if_else_cascade();
+        // before anything else can split up the nodes of the switch
+        if self.switches {
+            self.try_find_switch();
+        }
+        if self.conditionals {
+            self.try_find_if_else_pair();
+            self.try_find_if();
+            self.try_find_if_else_cascade();
+        }
 
         // move all nodes into a vec in topological order
         let mut ast_seq = Vec::new();
@@ -48,6 +91,9 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
                 let cond_ast = if cond.is_true() {
                     ast
                 } else {
+                    self.decisions
+                        .borrow_mut()
+                        .cond(cond, Phase::InitialCollapse);
                     AstNodeC::Cond(cond, Box::new(ast), None)
                 };
                 ast_seq.push(cond_ast);
@@ -161,6 +207,11 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
     /// condition.
     fn try_group_by_cond(&mut self, cond: Condition<'cd, A>, not_cond: Condition<'cd, A>) -> bool {
         let cctx = self.cctx;
+        let value_sets = self.value_sets;
+        let weights = self.weights;
+        let decisions = self.decisions;
+        let overlaps = self.overlaps;
+        let switches = self.switches;
 
         if cond.is_true() {
             return false;
@@ -209,7 +260,13 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
                         Some(
                             Refiner::<A> {
                                 cctx,
+                                value_sets,
+                                weights,
+                                decisions,
+                                overlaps,
                                 graph: else_graph,
+                                switches,
+                                conditionals: true,
                             }
                             .refine(),
                         ),
@@ -229,7 +286,13 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
                         Some(
                             Refiner::<A> {
                                 cctx,
+                                value_sets,
+                                weights,
+                                decisions,
+                                overlaps,
                                 graph: then_graph,
+                                switches,
+                                conditionals: true,
                             }
                             .refine(),
                         ),
@@ -249,7 +312,13 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
                             Some(
                                 Refiner::<A> {
                                     cctx,
+                                    value_sets,
+                                    weights,
+                                    decisions,
+                                    overlaps,
                                     graph: else_graph,
+                                    switches,
+                                    conditionals: true,
                                 }
                                 .refine(),
                             ),
@@ -276,6 +345,17 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
 
                 let (_, then_ast) = self.graph.remove_node(then_node).unwrap();
                 let (_, else_ast) = self.graph.remove_node(else_node).unwrap();
+                let heavier = |a, b| match (self.weight(a), self.weight(b)) {
+                    (Some(a), Some(b)) => a > b,
+                    _ => false,
+                };
+                let (cond, then_ast, else_ast) = if heavier(not_cond, cond) {
+                    decisions.borrow_mut().flipped(not_cond);
+                    (not_cond, else_ast, then_ast)
+                } else {
+                    (cond, then_ast, else_ast)
+                };
+                decisions.borrow_mut().cond(cond, Phase::IfElsePairing);
                 let if_node = self.graph.add_node((
                     cctx.mk_true(),
                     Some(AstNodeC::Cond(
@@ -298,9 +378,204 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
         true
     }
 
+    /// How heavy the path where `cond` holds is, if ordering by weight.
+    fn weight(&self, cond: Condition<'cd, A>) -> Option<u64> {
+        weigh(self.weights?, cond)
+    }
+
+    /// Repeatedly look for sets of code nodes whose reaching conditions are
+    /// all about the value of the same variable and group them into a
+    /// `Switch` on that variable.
+    fn try_find_switch(&mut self) {
+        if self.value_sets.is_empty() {
+            return;
+        }
+
+        // conditions that hold for every value are always true
+        let cctx = self.cctx;
+        let value_sets = self.value_sets;
+        for (cond, _) in self.graph.node_weights_mut() {
+            if let Some((_, vs)) = cond.fold(SwitchCondEval::<A> { value_sets }) {
+                if vs.is_full() {
+                    *cond = cctx.mk_true();
+                }
+            }
+        }
+
+        while self.try_find_one_switch() {}
+    }
+
+    fn try_find_one_switch(&mut self) -> bool {
+        let value_sets = self.value_sets;
+
+        // code nodes whose reaching condition only depends on the value of a
+        // single variable, in topological order
+        let mut cands = Vec::new();
+        for n in Topo::new(&self.graph).iter(&self.graph) {
+            let (cond, ref opt_ast) = self.graph[n];
+            if opt_ast.is_some() && !cond.is_true() {
+                if let Some((var, vs)) = cond.fold(SwitchCondEval::<A> { value_sets }) {
+                    cands.push((n, var, vs));
+                }
+            }
+        }
+        if cands.len() < MIN_SWITCH_NODES {
+            return false;
+        }
+
+        let trans_clos = graph_utils::dag_transitive_closure(&self.graph);
+
+        let mut tried_vars = Vec::new();
+        for &(_, var, _) in &cands {
+            if tried_vars.contains(&var) {
+                continue;
+            }
+            tried_vars.push(var);
+
+            // at most one of the nodes of a `Switch` runs, so none of them
+            // may reach another
+            let mut members: Vec<(NodeIndex, ValueSet)> = Vec::new();
+            for &(n, v, ref vs) in &cands {
+                if v == var
+                    && !vs.is_empty()
+                    && members
+                        .iter()
+                        .all(|&(m, _)| !trans_clos[&m].contains(n) && !trans_clos[&n].contains(m))
+                {
+                    members.push((n, vs.clone()));
+                }
+            }
+
+            // a `Switch` can't run more than one of the nodes whose value sets
+            // overlap; leave them for if-else cascades
+            let mut ambiguous = NodeSet::new();
+            for (i, (a, vs_a)) in members.iter().enumerate() {
+                for (b, vs_b) in &members[i + 1..] {
+                    let overlap = vs_a.intersection(vs_b);
+                    if !overlap.is_empty() {
+                        radeco_warn!("switch recovery: value sets of {:?} and {:?} overlap", a, b);
+                        ambiguous.insert(*a);
+                        ambiguous.insert(*b);
+                        let mut overlaps = self.overlaps.borrow_mut();
+                        if !overlaps.contains(&overlap) {
+                            overlaps.push(overlap);
+                        }
+                    }
+                }
+            }
+            members.retain(|&(n, _)| !ambiguous.contains(n));
+
+            if members.len() >= MIN_SWITCH_NODES {
+                self.mk_switch(var.clone(), members);
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Contracts `members` into a `Switch` on `var`. Each member becomes the
+    /// case for its value set, except that if the value sets cover every
+    /// value, one member becomes the default: the one reached for the values
+    /// that have no case, even if it is also reached for some cases. Those
+    /// are the values on the out-of-range edges of the conditions in the
+    /// reaching conditions of the members, if any were marked, or else the
+    /// ones none of those conditions hold for. If there isn't exactly one
+    /// such member, it is the one with the biggest value set. If the value
+    /// sets don't cover every value, the default is empty and the other
+    /// values go straight on to what follows the `Switch`.
+    fn mk_switch(&mut self, var: A::Variable, members: Vec<(NodeIndex, ValueSet)>) {
+        let cctx = self.cctx;
+
+        let covered = members
+            .iter()
+            .fold(ValueSet::empty(), |acc, (_, vs)| acc.union(vs));
+        let opt_default = if covered.is_full() {
+            let no_case = self.no_case(&var, &members);
+            let mut reached = members.iter().filter(|(_, vs)| !vs.is_disjoint(&no_case));
+            match (reached.next(), reached.next()) {
+                (Some(&(n, _)), None) => Some(n),
+                _ => members
+                    .iter()
+                    .max_by_key(|(_, vs)| cardinality(vs))
+                    .map(|&(n, _)| n),
+            }
+        } else {
+            None
+        };
+        let member_set: NodeSet = members.iter().map(|&(n, _)| n).collect();
+        let mut value_set_of: HashMap<_, _> = members.into_iter().collect();
+
+        graph_utils::contract_nodes_and_map(
+            &mut self.graph,
+            &member_set,
+            |n, (_, ast)| (n, ast),
+            |_, _| (),
+            |mut switch_graph| {
+                debug_assert!(switch_graph.edge_count() == 0);
+
+                let mut cases = Vec::new();
+                let mut default = AstNodeC::default();
+                while let Some(sn) = switch_graph.node_indices().next() {
+                    let (n, ast) = switch_graph.remove_node(sn).unwrap();
+                    let ast = ast.unwrap();
+                    if Some(n) == opt_default {
+                        default = ast;
+                    } else {
+                        cases.push((value_set_of.remove(&n).unwrap(), ast));
+                    }
+                }
+                cases.sort_by_key(|(vs, _)| vs.min());
+
+                (
+                    cctx.mk_true(),
+                    Some(AstNodeC::Switch(var, cases, Box::new(default))),
+                )
+            },
+        );
+    }
+
+    /// The values of `var` that none of the cases of a `Switch` of `members`
+    /// are for, as far as the conditions on `var` in the reaching conditions
+    /// of the members tell.
+    fn no_case(&self, var: &A::Variable, members: &[(NodeIndex, ValueSet)]) -> ValueSet {
+        let mut keys: Vec<_> = members
+            .iter()
+            .flat_map(|&(n, _)| self.graph[n].0.vars())
+            .map(|c| &*c as *const A::Condition as usize)
+            .collect();
+        keys.sort();
+        keys.dedup();
+        let conds: Vec<_> = keys
+            .iter()
+            .filter_map(|k| self.value_sets.get(k))
+            .filter(|(v, ..)| v == var)
+            .collect();
+        let out_of_range: Vec<_> = conds
+            .iter()
+            .filter_map(|(_, vs, when)| match when {
+                Some(true) => Some(vs.clone()),
+                Some(false) => Some(vs.complement()),
+                None => None,
+            })
+            .collect();
+        if out_of_range.is_empty() {
+            conds
+                .iter()
+                .fold(ValueSet::empty(), |acc, (_, vs, _)| acc.union(vs))
+                .complement()
+        } else {
+            out_of_range
+                .iter()
+                .fold(ValueSet::empty(), |acc, vs| acc.union(vs))
+        }
+    }
+
     /// Tries to find a set of code where exactly one of them will run.
     fn try_find_if_else_cascade(&mut self) {
         let cctx = self.cctx;
+        let decisions = self.decisions;
+        let weights = self.weights;
 
         // make a topological order of code nodes
         let mut order = Vec::new();
@@ -367,7 +642,17 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
                     // build if-else cascade starting from last else block with
                     // the most complex reaching condition
                     let mut casc_ast = nodes.pop().unwrap().1;
+                    // the else is still the most complex, but the rest go
+                    // heaviest first if all of them are weighed
+                    let weighed: Option<Vec<_>> =
+                        nodes.iter().map(|&(c, _)| weigh(weights?, c)).collect();
+                    if let Some(weighed) = weighed {
+                        let mut weighed: Vec<_> = weighed.into_iter().zip(nodes).collect();
+                        weighed.sort_by_key(|&(w, _)| Reverse(w));
+                        nodes = weighed.into_iter().map(|(_, n)| n).collect();
+                    }
                     for (cond, ast) in nodes.into_iter().rev() {
+                        decisions.borrow_mut().cond(cond, Phase::IfElsePairing);
                         casc_ast = AstNodeC::Cond(cond, Box::new(ast), Some(Box::new(casc_ast)));
                     }
 
@@ -378,6 +663,104 @@ impl<'cd, A: AstContext> Refiner<'cd, A> {
     }
 }
 
+/// How heavy the path where `cond` holds is, as far as `weights` tell: the
+/// weight of the edge where a condition is as it appears in `cond`, the
+/// lightest of those that must all hold, or the weights of those of which
+/// one must hold added up.
+fn weigh<T>(weights: &BranchWeights, cond: condition::Condition<T>) -> Option<u64> {
+    cond.fold(Weigh(weights))
+}
+
+struct Weigh<'vs>(&'vs BranchWeights);
+
+impl<'vs, T> condition::Folder<T> for Weigh<'vs> {
+    type Output = Option<u64>;
+
+    fn var(&mut self, normal: bool, var: &T) -> Self::Output {
+        let &(holds, fails) = self.0.get(&(var as *const _ as usize))?;
+        Some(if normal { holds } else { fails })
+    }
+
+    fn and<'c, I>(&mut self, operands: I) -> Self::Output
+    where
+        I: IntoIterator<Item = condition::Condition<'c, T>>,
+        T: 'c,
+    {
+        let weights: Option<Vec<_>> = operands.into_iter().map(|c| weigh(self.0, c)).collect();
+        weights?.into_iter().min()
+    }
+
+    fn or<'c, I>(&mut self, operands: I) -> Self::Output
+    where
+        I: IntoIterator<Item = condition::Condition<'c, T>>,
+        T: 'c,
+    {
+        let weights: Option<Vec<_>> = operands.into_iter().map(|c| weigh(self.0, c)).collect();
+        Some(weights?.into_iter().fold(0, u64::saturating_add))
+    }
+}
+
+/// A `Switch` is only worth it for at least this many nodes; fewer are
+/// better off as `if`s.
+const MIN_SWITCH_NODES: usize = 3;
+
+/// The number of values in `vs`.
+fn cardinality(vs: &ValueSet) -> u128 {
+    vs.ranges()
+        .iter()
+        .map(|&(lo, hi)| u128::from(hi - lo) + 1)
+        .sum()
+}
+
+/// Finds the values of a variable for which a condition holds, if the
+/// condition only depends on a single variable with supplied value sets.
+struct SwitchCondEval<'vs, A: AstContext> {
+    value_sets: &'vs ValueSets<A>,
+}
+
+impl<'vs, A: AstContext> condition::Folder<A::Condition> for SwitchCondEval<'vs, A> {
+    type Output = Option<(&'vs A::Variable, ValueSet)>;
+
+    fn var(&mut self, normal: bool, var: &A::Condition) -> Self::Output {
+        let (v, vs, _) = self.value_sets.get(&(var as *const _ as usize))?;
+        Some((v, if normal { vs.clone() } else { vs.complement() }))
+    }
+
+    fn and<'c, I>(&mut self, operands: I) -> Self::Output
+    where
+        I: IntoIterator<Item = condition::Condition<'c, A::Condition>>,
+    {
+        self.combine(operands, ValueSet::intersection)
+    }
+
+    fn or<'c, I>(&mut self, operands: I) -> Self::Output
+    where
+        I: IntoIterator<Item = condition::Condition<'c, A::Condition>>,
+    {
+        self.combine(operands, ValueSet::union)
+    }
+}
+
+impl<'vs, A: AstContext> SwitchCondEval<'vs, A> {
+    fn combine<'c, I, F>(&self, operands: I, op: F) -> Option<(&'vs A::Variable, ValueSet)>
+    where
+        I: IntoIterator<Item = condition::Condition<'c, A::Condition>>,
+        F: Fn(&ValueSet, &ValueSet) -> ValueSet,
+    {
+        let value_sets = self.value_sets;
+        let mut ret: Option<(&A::Variable, ValueSet)> = None;
+        for c in operands {
+            let (v, vs) = c.fold(SwitchCondEval::<A> { value_sets })?;
+            ret = match ret {
+                None => Some((v, vs)),
+                Some((ret_v, ret_vs)) if ret_v == v => Some((ret_v, op(&ret_vs, &vs))),
+                Some(_) => return None,
+            };
+        }
+        ret
+    }
+}
+
 /// Performs trivial simplifications.
 pub fn simplify_ast_node<'cd, A: AstContext>(
     cctx: CondContext<'cd, A>,
@@ -429,7 +812,25 @@ pub fn simplify_ast_node<'cd, A: AstContext>(
             let b = simplify_ast_node::<A>(cctx, *b).unwrap_or_default();
             Some(Loop(t, Box::new(b)))
         }
+        For(i, c, u, b) => {
+            let b = simplify_ast_node::<A>(cctx, *b).unwrap_or_default();
+            Some(For(i, c, u, Box::new(b)))
+        }
         Break => Some(Break),
+        Continue => Some(Continue),
+        Return => Some(Return),
+        TailCall(b) => Some(TailCall(b)),
+        IndirectJump(b) => Some(IndirectJump(b)),
+        Goto(l) => Some(Goto(l)),
+        Label(l) => Some(Label(l)),
+        Try(b, h) => simplify_ast_node::<A>(cctx, *b).map(|b| Try(Box::new(b), h)),
+        // the members stay, empty or not, since their labels may be jumped to
+        Unstructured(members) => Some(Unstructured(
+            members
+                .into_iter()
+                .map(|(l, a)| (l, simplify_ast_node::<A>(cctx, a).unwrap_or_default()))
+                .collect(),
+        )),
         Switch(v, cases, default) => {
             let cases: Vec<_> = cases
                 .into_iter()
@@ -445,11 +846,124 @@ pub fn simplify_ast_node<'cd, A: AstContext>(
     }
 }
 
-struct LoopRefiner<'cd, A: AstContext> {
+/// Turns each `if (c) { ...; if (d) { ... } else { return; } } else {
+/// return; }` at the end of a sequence into the guard clauses `if (!c) {
+/// return; } ...; if (!d) { return; } ...`, where that takes away at least
+/// `min_depth` levels of nesting. An arm that always leaves the function,
+/// by returning or some other way, is a guard clause. Shallower chains of
+/// them are left alone, since inverting a lone `if` rarely reads better.
+pub(super) fn guard_clauses<'cd, A: AstContext>(
     cctx: CondContext<'cd, A>,
+    ast: AstNode<'cd, A>,
+    min_depth: usize,
+) -> AstNode<'cd, A> {
+    use super::AstNodeC::*;
+    let go = |a| guard_clauses::<A>(cctx, a, min_depth);
+    let ast = match ast {
+        // an `if` that became guard clauses becomes part of the sequence
+        Seq(seq) => Seq(seq
+            .into_iter()
+            .flat_map(|a| match go(a) {
+                Seq(s) => s,
+                a => vec![a],
+            })
+            .collect()),
+        Cond(c, t, oe) => Cond(c, Box::new(go(*t)), oe.map(|e| Box::new(go(*e)))),
+        Loop(lt, b) => Loop(lt, Box::new(go(*b))),
+        For(i, c, u, b) => For(i, c, u, Box::new(go(*b))),
+        Switch(v, cases, default) => Switch(
+            v,
+            cases.into_iter().map(|(vs, a)| (vs, go(a))).collect(),
+            Box::new(go(*default)),
+        ),
+        Try(b, h) => Try(Box::new(go(*b)), h),
+        Unstructured(members) => {
+            Unstructured(members.into_iter().map(|(l, a)| (l, go(a))).collect())
+        }
+        ast => ast,
+    };
+    match ast {
+        Seq(mut seq) => {
+            if matches!(seq.last(), Some(a) if guard_depth(a) >= min_depth) {
+                let last = seq.pop().unwrap();
+                unnest_guards::<A>(cctx, last, &mut seq);
+            }
+            mk_seq_vec(seq)
+        }
+        ast @ Cond(..) if guard_depth(&ast) >= min_depth => {
+            let mut seq = Vec::new();
+            unnest_guards::<A>(cctx, ast, &mut seq);
+            mk_seq_vec(seq)
+        }
+        ast => ast,
+    }
 }
-impl<'cd, A: AstContext> Copy for LoopRefiner<'cd, A> {}
-impl<'cd, A: AstContext> Clone for LoopRefiner<'cd, A> {
+
+/// How many `if`s with an arm that always leaves the function are nested in
+/// `ast`, each at the end of the other arm of the one before.
+fn guard_depth<B, C, V>(ast: &AstNodeC<B, C, V>) -> usize {
+    use self::AstNodeC::*;
+    match ast {
+        Cond(_, t, Some(e)) if always_leaves(e) => 1 + guard_depth(last_of(t)),
+        Cond(_, t, Some(e)) if always_leaves(t) => 1 + guard_depth(last_of(e)),
+        _ => 0,
+    }
+}
+
+/// Appends `ast` to `out`, with the `if`s counted by [`guard_depth`] turned
+/// into guard clauses.
+fn unnest_guards<'cd, A: AstContext>(
+    cctx: CondContext<'cd, A>,
+    ast: AstNode<'cd, A>,
+    out: &mut Vec<AstNode<'cd, A>>,
+) {
+    use self::AstNodeC::*;
+    let (guard, rest) = match ast {
+        Cond(c, t, Some(e)) if always_leaves(&e) => (Cond(cctx.mk_not(c), e, None), *t),
+        Cond(c, t, Some(e)) if always_leaves(&t) => (Cond(c, t, None), *e),
+        ast => return out.push(ast),
+    };
+    out.push(guard);
+    match rest {
+        Seq(mut seq) => {
+            let last = seq.pop();
+            out.append(&mut seq);
+            if let Some(last) = last {
+                unnest_guards::<A>(cctx, last, out);
+            }
+        }
+        rest => unnest_guards::<A>(cctx, rest, out),
+    }
+}
+
+fn last_of<B, C, V>(ast: &AstNodeC<B, C, V>) -> &AstNodeC<B, C, V> {
+    match ast {
+        AstNodeC::Seq(seq) => seq.last().unwrap_or(ast),
+        _ => ast,
+    }
+}
+
+/// Whether `ast` never falls off its end nor leaves just the loop it is in.
+fn always_leaves<B, C, V>(ast: &AstNodeC<B, C, V>) -> bool {
+    use self::AstNodeC::*;
+    match ast {
+        Return | TailCall(_) | IndirectJump(_) | Goto(_) => true,
+        Seq(seq) => matches!(seq.last(), Some(a) if always_leaves(a)),
+        Cond(_, t, oe) => always_leaves(t) && matches!(oe, Some(e) if always_leaves(e)),
+        Try(b, _) => always_leaves(b),
+        Switch(_, cases, default) => {
+            always_leaves(default) && cases.iter().all(|(_, a)| always_leaves(a))
+        }
+        BasicBlock(_) | Break | Continue | Loop(..) | For(..) | Label(_) | Unstructured(_) => false,
+    }
+}
+
+struct LoopRefiner<'cd, 'd, A: AstContext> {
+    cctx: CondContext<'cd, A>,
+    decisions: &'d RefCell<DecisionLog<'cd, A::Condition>>,
+}
+impl<'cd, 'd, A: AstContext> Copy for LoopRefiner<'cd, 'd, A> {}
+impl<'cd, 'd, A: AstContext> Clone for LoopRefiner<'cd, 'd, A> {
     fn clone(&self) -> Self {
         *self
     }
@@ -457,9 +971,10 @@ impl<'cd, A: AstContext> Clone for LoopRefiner<'cd, A> {
 
 pub(super) fn refine_loop<'cd, A: AstContext>(
     cctx: CondContext<'cd, A>,
+    decisions: &RefCell<DecisionLog<'cd, A::Condition>>,
     body: AstNode<'cd, A>,
 ) -> AstNode<'cd, A> {
-    LoopRefiner::<A> { cctx }.refine_loop(body)
+    LoopRefiner::<A> { cctx, decisions }.refine_loop(body)
 }
 
 macro_rules! gen_rule {
@@ -474,7 +989,20 @@ macro_rules! gen_rule {
     );
 }
 
-impl<'cd, A: AstContext> LoopRefiner<'cd, A> {
+impl<'cd, 'd, A: AstContext> LoopRefiner<'cd, 'd, A> {
+    /// Makes a loop of `ty`, recording that `rule` chose it.
+    fn mk_loop(
+        self,
+        rule: &'static str,
+        ty: LoopType<Condition<'cd, A>>,
+        body: AstNode<'cd, A>,
+    ) -> AstNode<'cd, A> {
+        if let LoopType::PreChecked(c) | LoopType::PostChecked(c) = ty {
+            self.decisions.borrow_mut().refined_loop(c, rule);
+        }
+        AstNodeC::Loop(ty, Box::new(body))
+    }
+
     fn refine_loop(self, mut body: AstNode<'cd, A>) -> AstNode<'cd, A> {
         macro_rules! run_rules {
             ($($name:ident),+) => ($(
@@ -485,6 +1013,7 @@ impl<'cd, A: AstContext> LoopRefiner<'cd, A> {
             )+);
         }
         run_rules!(
+            rule_LatchTest,
             rule_While,
             rule_DoWhile,
             rule_NestedDoWhile,
@@ -495,11 +1024,47 @@ impl<'cd, A: AstContext> LoopRefiner<'cd, A> {
         AstNodeC::Loop(LoopType::Endless, Box::new(body))
     }
 
+    // `loop { ...; if (c) continue; break; }`, in any of the shapes the test
+    // at the bottom of the loop can take, is `do { ... } while (c)`. A
+    // `continue` before the test would skip it, so it can't be moved into
+    // the loop. The code of a latch is a node of its own, before the
+    // condition node that tests, so it is already in `...` and nothing of
+    // it has to be copied for the test to move; what of it only computes
+    // the test can be split off into it beforehand, see
+    // `ControlFlowGraph::split_latches`
+    gen_rule! {rule_LatchTest, |self, body| {
+        match body {
+            Seq(mut seq) => {
+                let test = match seq[..] {
+                    [.., Cond(c, box Continue, None), Break] => Some((2, c)),
+                    [.., Cond(c, box Break, None), Continue] => Some((2, self.cctx.mk_not(c))),
+                    [.., Cond(c, box Continue, Some(box Break))] => Some((1, c)),
+                    [.., Cond(c, box Break, Some(box Continue))] => Some((1, self.cctx.mk_not(c))),
+                    _ => None,
+                };
+                match test {
+                    Some((n, c)) if !seq[..seq.len() - n].iter().any(contains_continue) => {
+                        seq.truncate(seq.len() - n);
+                        Ok(self.mk_loop("LatchTest", PostChecked(c), mk_seq_vec(seq)))
+                    }
+                    _ => Err(Seq(seq)),
+                }
+            }
+            Cond(c, box Continue, Some(box Break)) => {
+                Ok(self.mk_loop("LatchTest", PostChecked(c), Seq(Vec::new())))
+            }
+            Cond(c, box Break, Some(box Continue)) => {
+                Ok(self.mk_loop("LatchTest", PostChecked(self.cctx.mk_not(c)), Seq(Vec::new())))
+            }
+            body => Err(body),
+        }
+    }}
+
     gen_rule! {rule_While, |self, body| {
         if let Seq(mut seq) = body {
             if let Some(&Cond(c, box Break, None)) = seq.first() {
                 seq.remove(0);
-                Ok(Loop(PreChecked(self.cctx.mk_not(c)), Box::new(mk_seq_vec(seq))))
+                Ok(self.mk_loop("While", PreChecked(self.cctx.mk_not(c)), mk_seq_vec(seq)))
             } else {
                 Err(Seq(seq))
             }
@@ -512,7 +1077,7 @@ impl<'cd, A: AstContext> LoopRefiner<'cd, A> {
         if let Seq(mut seq) = body {
             if let Some(&Cond(c, box Break, None)) = seq.last() {
                 seq.pop();
-                Ok(Loop(PostChecked(self.cctx.mk_not(c)), Box::new(mk_seq_vec(seq))))
+                Ok(self.mk_loop("DoWhile", PostChecked(self.cctx.mk_not(c)), mk_seq_vec(seq)))
             } else {
                 Err(Seq(seq))
             }
@@ -527,9 +1092,10 @@ impl<'cd, A: AstContext> LoopRefiner<'cd, A> {
                 if let Cond(c, t, None) = last {
                     if seq.iter().all(|a| !contains_break(a)) {
                         let new_body = mk_seq_2(
-                            Loop(
+                            self.mk_loop(
+                                "NestedDoWhile",
                                 PostChecked(self.cctx.mk_not(c)),
-                                Box::new(mk_seq_vec(seq)),
+                                mk_seq_vec(seq),
                             ),
                             *t
                         );
@@ -575,7 +1141,7 @@ impl<'cd, A: AstContext> LoopRefiner<'cd, A> {
     gen_rule! {rule_CondToSeq, |self, body| {
         if let Cond(c, t, Some(e)) = body {
             if !contains_break(&*t) && contains_break(&*e) {
-                Ok(self.refine_loop(mk_seq_2(Loop(PreChecked(c), t), *e)))
+                Ok(self.refine_loop(mk_seq_2(self.mk_loop("CondToSeq", PreChecked(c), *t), *e)))
             } else {
                 Err(Cond(c, t, Some(e)))
             }
@@ -587,7 +1153,8 @@ impl<'cd, A: AstContext> LoopRefiner<'cd, A> {
     gen_rule! {rule_CondToSeqNeg, |self, body| {
         if let Cond(c, t, Some(e)) = body {
             if contains_break(&*t) && !contains_break(&*e) {
-                Ok(self.refine_loop(mk_seq_2(Loop(PreChecked(self.cctx.mk_not(c)), t), *e)))
+                let ty = PreChecked(self.cctx.mk_not(c));
+                Ok(self.refine_loop(mk_seq_2(self.mk_loop("CondToSeqNeg", ty, *t), *e)))
             } else {
                 Err(Cond(c, t, Some(e)))
             }
@@ -603,22 +1170,46 @@ fn contains_break<B, C, V>(ast: &AstNodeC<B, C, V>) -> bool {
         BasicBlock(_) => false,
         Seq(seq) => !seq.iter().all(|a| !contains_break(a)),
         Cond(_, t, oe) => contains_break(t) || oe.as_ref().map_or(false, |e| contains_break(e)),
-        Loop(_, _) => false, // `break` only breaks the nearest loop
+        Loop(_, _) | For(..) => false, // `break` only breaks the nearest loop
         Break => true,
+        Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => false,
+        Try(b, _) => contains_break(b),
+        Unstructured(members) => members.iter().any(|(_, a)| contains_break(a)),
         Switch(_, cases, default) => {
             contains_break(default) || !cases.iter().all(|(_, a)| !contains_break(a))
         }
     }
 }
 
+fn contains_continue<B, C, V>(ast: &AstNodeC<B, C, V>) -> bool {
+    use self::AstNodeC::*;
+    match ast {
+        Seq(seq) => seq.iter().any(contains_continue),
+        Cond(_, t, oe) => contains_continue(t) || oe.iter().any(|e| contains_continue(e)),
+        Loop(_, _) | For(..) => false, // `continue` only continues the nearest loop
+        Continue => true,
+        BasicBlock(_) | Break | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => {
+            false
+        }
+        Try(b, _) => contains_continue(b),
+        Unstructured(members) => members.iter().any(|(_, a)| contains_continue(a)),
+        Switch(_, cases, default) => {
+            contains_continue(default) || cases.iter().any(|(_, a)| contains_continue(a))
+        }
+    }
+}
+
 fn always_breaks<B, C, V>(ast: &AstNodeC<B, C, V>) -> bool {
     use self::AstNodeC::*;
     match ast {
         BasicBlock(_) => false,
         Seq(seq) => seq.last().map_or(false, |a| always_breaks(a)),
         Cond(_, t, oe) => always_breaks(t) && oe.as_ref().map_or(false, |e| always_breaks(e)),
-        Loop(_, _) => false, // `break` only breaks the nearest loop
+        Loop(_, _) | For(..) => false, // `break` only breaks the nearest loop
         Break => true,
+        Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => false,
+        Unstructured(_) => false,
+        Try(b, _) => always_breaks(b),
         Switch(_, cases, default) => {
             always_breaks(default) && cases.iter().all(|(_, a)| always_breaks(a))
         }
@@ -645,7 +1236,17 @@ fn remove_breaks<B, C, V>(ast: AstNodeC<B, C, V>) -> Option<AstNodeC<B, C, V>> {
             oe.and_then(|e| remove_breaks(*e).map(Box::new)),
         )),
         Loop(t, b) => Some(Loop(t, b)),
+        For(i, c, u, b) => Some(For(i, c, u, b)),
         Break => None,
+        Continue => Some(Continue),
+        Return => Some(Return),
+        TailCall(b) => Some(TailCall(b)),
+        IndirectJump(b) => Some(IndirectJump(b)),
+        Goto(l) => Some(Goto(l)),
+        Label(l) => Some(Label(l)),
+        Try(b, h) => remove_breaks(*b).map(|b| Try(Box::new(b), h)),
+        // a region is left through its end, so it has none in tail position
+        Unstructured(members) => Some(Unstructured(members)),
         Switch(v, cases, default) => Some(Switch(
             v,
             cases
@@ -0,0 +1,61 @@
+//! Gives the values that a structured AST refers to human names, e.g. those
+//! of function arguments, known globals or names assigned by the user.
+//!
+//! This runs after structuring, on the output of
+//! [`ControlFlowGraph::structure_whole`](super::ControlFlowGraph::structure_whole).
+
+use super::ast_context::AstContext;
+use super::{AstNode, CondContext, CondVar, Condition};
+
+use std::collections::HashMap;
+
+/// Rewrites the leaves of conditions and the variables of switch heads to use
+/// human names.
+pub trait Renamer<C, V> {
+    /// Returns `cond` with the values it refers to renamed, or `None` if none
+    /// of them have names.
+    fn rename_cond(&mut self, cond: &C) -> Option<C>;
+
+    /// Returns the name of `var`, or `None` if it doesn't have one.
+    fn rename_var(&mut self, var: &V) -> Option<V>;
+}
+
+/// Renames every condition leaf and switch variable of `ast` that `renamer`
+/// has a name for, including loop guards. Each condition leaf is renamed
+/// once, so all its uses keep referring to the same (renamed) leaf, and
+/// conditions stay as simplified as they were.
+pub fn rename<'cd, A, R>(
+    cctx: CondContext<'cd, A>,
+    ast: AstNode<'cd, A>,
+    renamer: &mut R,
+) -> AstNode<'cd, A>
+where
+    A: AstContext,
+    R: Renamer<A::Condition, A::Variable>,
+{
+    let mut renamed_vars = HashMap::new();
+    let ast =
+        ast.map_conds(&mut |cond| rename_cond::<A, R>(cctx, cond, renamer, &mut renamed_vars));
+    ast.map_vars(&mut |var| renamer.rename_var(&var).unwrap_or(var))
+}
+
+fn rename_cond<'cd, A, R>(
+    cctx: CondContext<'cd, A>,
+    mut cond: Condition<'cd, A>,
+    renamer: &mut R,
+    renamed_vars: &mut HashMap<CondVar<'cd, A>, Option<CondVar<'cd, A>>>,
+) -> Condition<'cd, A>
+where
+    A: AstContext,
+    R: Renamer<A::Condition, A::Variable>,
+{
+    for var in cond.vars() {
+        let opt_new_var = *renamed_vars
+            .entry(var)
+            .or_insert_with(|| renamer.rename_cond(&var).map(|c| cctx.new_var(c)));
+        if let Some(new_var) = opt_new_var {
+            cond = cctx.replace_var_in(cond, var, new_var);
+        }
+    }
+    cond
+}
@@ -0,0 +1,216 @@
+//! See [`StructuringReport`].
+
+use super::ast::{LabelId, ValueSet};
+use super::decisions::{self, Decisions};
+use super::error::{InputDefect, StructureError};
+use super::matchers::Annotation;
+use super::state_machines::StateMachine;
+
+use petgraph::graph::NodeIndex;
+
+use std::time::Duration;
+
+/// What
+/// [`ControlFlowGraph::structure_whole_reported`](super::ControlFlowGraph::structure_whole_reported)
+/// did, handlers included. Structuring only duplicates nodes when told to, see
+/// [`StructuringOptions::duplicate_tails`](super::StructuringOptions::duplicate_tails)
+/// and
+/// [`StructuringOptions::max_duplicated_nodes`](super::StructuringOptions::max_duplicated_nodes);
+/// where the graph can't be structured as is, it introduces variables instead,
+/// see [`Fallback`]. With the `serde` feature, it can be serialized, e.g. as
+/// JSON.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StructuringReport {
+    /// the acyclic regions collapsed into a single node
+    pub regions: usize,
+    pub loops: usize,
+    /// the `Goto`s in the resulting ASTs
+    pub gotos: usize,
+    pub fallbacks: Vec<Fallback>,
+    pub times: PhaseTimes,
+    /// whether structuring ran out of
+    /// [`StructuringOptions::budget`](super::StructuringOptions::budget)
+    pub budget_exhausted: bool,
+    /// the defects of the input that were repaired, see
+    /// [`InputMode`](super::InputMode)
+    pub warnings: Vec<InputDefect>,
+    /// the switch cases that couldn't be told apart, see [`CaseOverlap`]
+    pub overlapping_cases: Vec<CaseOverlap>,
+    /// why the constructs of the resulting ASTs are the way they are
+    pub decisions: Decisions,
+    /// the nodes that constant conditions made unreachable, which were removed
+    /// before structuring, see
+    /// [`ControlFlowGraph::set_constant`](super::ControlFlowGraph::set_constant)
+    pub pruned: Vec<NodeIndex>,
+    /// the nodes of shared tails and loops that were copied, each with its
+    /// copy, see
+    /// [`StructuringOptions::duplicate_tails`](super::StructuringOptions::duplicate_tails)
+    /// and [`DuplicationLimit`](super::DuplicationLimit); both have the same
+    /// provenance
+    pub duplicated: Vec<(NodeIndex, NodeIndex)>,
+    /// the copies that would have gone over
+    /// [`StructuringOptions::max_duplicated_nodes`](super::StructuringOptions::max_duplicated_nodes)
+    pub declined: Vec<DeclinedCopy>,
+    /// the callees spliced in by
+    /// [`ControlFlowGraph::inline_at`](super::ControlFlowGraph::inline_at), in
+    /// the order they were
+    pub inlined: Vec<InlinedCall>,
+    /// what the
+    /// [`StructuringOptions::matchers`](super::StructuringOptions::matchers)
+    /// found the nodes of the resulting ASTs to be, by their
+    /// [`AstNodeId`](super::decisions::AstNodeId) like in
+    /// [`decisions`](Self::decisions), sorted
+    pub annotations: Vec<(decisions::AstNodeId, Annotation)>,
+    /// the loops among [`annotations`](Self::annotations) that run a state
+    /// machine, as far as
+    /// [`AstContext::assigned_value`](super::AstContext::assigned_value) tells
+    pub state_machines: Vec<(decisions::AstNodeId, StateMachine)>,
+    /// the `if`-`else`s whose arms
+    /// [`StructuringOptions::order_by_weight`](super::StructuringOptions::order_by_weight)
+    /// turned around, negating their condition, by their
+    /// [`AstNodeId`](super::decisions::AstNodeId), sorted
+    pub flipped: Vec<decisions::AstNodeId>,
+    /// the empty nodes that
+    /// [`ControlFlowGraph::insert_preheaders`](super::ControlFlowGraph::insert_preheaders)
+    /// put in front of loop headers
+    pub preheaders: Vec<NodeIndex>,
+    /// the latches of loops that
+    /// [`ControlFlowGraph::split_latches`](super::ControlFlowGraph::split_latches)
+    /// split
+    pub split_latches: Vec<NodeIndex>,
+    /// the empty nodes that
+    /// [`ControlFlowGraph::split_critical_edges`](super::ControlFlowGraph::split_critical_edges)
+    /// put on critical edges
+    pub split_edges: Vec<NodeIndex>,
+    /// the head of each chain that
+    /// [`ControlFlowGraph::merge_linear_chains`](super::ControlFlowGraph::merge_linear_chains)
+    /// merged, and the nodes it merged into it, in order, which are gone
+    pub merged_chains: Vec<(NodeIndex, Vec<NodeIndex>)>,
+    /// the edges out of the acyclic regions collapsed to anything but their
+    /// successor, as their source and target, each of which became a `Goto`
+    /// to a label put in front of the target
+    pub side_exits: Vec<(NodeIndex, NodeIndex)>,
+    /// the regions that
+    /// [`ControlFlowGraph::structure_by_region`](super::ControlFlowGraph::structure_by_region)
+    /// couldn't structure, innermost first
+    pub failed_regions: Vec<FailedRegion>,
+    /// the names of the variables that structuring introduced, in the order it
+    /// did, see [`StructuringOptions::namer`](super::StructuringOptions::namer)
+    pub var_names: Vec<String>,
+    /// the name of each label of the resulting ASTs, by id, see
+    /// [`StructuringOptions::namer`](super::StructuringOptions::namer)
+    pub labels: Vec<(LabelId, String)>,
+}
+
+/// How long each phase of structuring took.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PhaseTimes {
+    /// moving the handlers out of the graph
+    pub split_handlers: Duration,
+    /// collapsing the acyclic SESE regions up front, if enabled
+    pub sese_regions: Duration,
+    /// structuring the loops and the remaining acyclic regions
+    pub main: Duration,
+}
+
+/// A loop that structuring had to introduce a variable for. The nodes are
+/// those of the graph being structured at that point, so they may be ones
+/// that structuring made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Fallback {
+    /// The loop headed by `header` could also be entered at `entries` other
+    /// nodes, so its entries set a variable that its header dispatches on.
+    AbnormalEntries { header: NodeIndex, entries: usize },
+    /// The loop going on to `successor` could also be left for `exits` other
+    /// nodes, so its exits set a variable that its successor dispatches on.
+    AbnormalExits { successor: NodeIndex, exits: usize },
+}
+
+/// A callee that
+/// [`ControlFlowGraph::inline_at`](super::ControlFlowGraph::inline_at) spliced
+/// into the graph.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct InlinedCall {
+    /// the code node calling it, which now goes on to its entry
+    pub call: NodeIndex,
+    /// the address of its entry, if the context tells
+    pub callee: Option<u64>,
+    /// the nodes of its graph, each with its copy, sorted
+    pub nodes: Vec<(NodeIndex, NodeIndex)>,
+}
+
+/// A copy that structuring did without, since it would have gone over
+/// [`StructuringOptions::max_duplicated_nodes`](super::StructuringOptions::max_duplicated_nodes),
+/// or copied a node that must not be, see [`DeclineReason`]. The nodes are
+/// those of the graph being structured at that point.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum DeclinedCopy {
+    /// The tail of `nodes` nodes starting at `head` stays shared.
+    SharedTail {
+        head: NodeIndex,
+        nodes: usize,
+        reason: DeclineReason,
+    },
+    /// The part of `nodes` nodes entered at `head` stays shared by one more
+    /// of the branches entering it.
+    SharedRegion {
+        head: NodeIndex,
+        nodes: usize,
+        reason: DeclineReason,
+    },
+    /// The loop headed by `header` is entered at `entry` through a variable,
+    /// instead of through a copy of the `nodes` nodes from there.
+    LoopEntry {
+        header: NodeIndex,
+        entry: NodeIndex,
+        nodes: usize,
+        reason: DeclineReason,
+    },
+}
+
+/// Why structuring did without a copy, see [`DeclinedCopy`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum DeclineReason {
+    /// The copy would have gone over
+    /// [`StructuringOptions::max_duplicated_nodes`](super::StructuringOptions::max_duplicated_nodes).
+    Limit,
+    /// The copy would have included this node, which must not be copied, see
+    /// [`ControlFlowGraph::set_no_duplicate`](super::ControlFlowGraph::set_no_duplicate).
+    NoDuplicate(NodeIndex),
+}
+
+/// A canonical SESE region that
+/// [`ControlFlowGraph::structure_by_region`](super::ControlFlowGraph::structure_by_region)
+/// couldn't structure, or didn't get to before the budget ran out. It is laid
+/// out as an [`Unstructured`](super::ast::AstNode::Unstructured) region
+/// instead: each of its nodes in turn, labeled with its index, each of the
+/// regions nested in it as its AST, and the edges as `Goto`s where they don't
+/// fall through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FailedRegion {
+    pub header: NodeIndex,
+    /// all the nodes of the region, those of the nested regions included,
+    /// sorted
+    pub nodes: Vec<NodeIndex>,
+    pub error: StructureError,
+}
+
+/// Code nodes that a `Switch` could have been made of, except that their
+/// value sets overlap, so that a single case couldn't run the one reached.
+/// They are left for if-else cascades instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CaseOverlap {
+    /// the header of the region the nodes are in, in the graph being
+    /// structured at that point
+    pub region: NodeIndex,
+    /// the values more than one of the nodes is reached for
+    pub values: ValueSet,
+}
@@ -0,0 +1,1229 @@
+//! Checks a structured function against the blocks it was structured from,
+//! by lowering its AST back into a control flow graph.
+//!
+//! [`AstNode::to_cfg`] turns an AST into a [`LoweredCfg`]: a `Seq` becomes a
+//! chain, a `Cond` a diamond, a loop gets a back edge to its header and a
+//! `Switch` fans out into its cases. [`check`] then compares it with the
+//! radare2 blocks the AST came from. Rather than looking for an isomorphism,
+//! it explores every path through the blocks, taking each branch both ways,
+//! and runs the lowered graph along: whenever the lowered graph runs a
+//! block, it must be the one the blocks run next, matched by address. So
+//!  - a block that structuring duplicated is matched by each of its copies,
+//!  - conditions only need to have the same value, however they were
+//!    simplified or combined, and
+//!  - the variables structuring introduced are tracked with the values
+//!    assigned to them.
+//!
+//! A lowered block that covers several blocks running one after the other
+//! unconditionally stands for all of them. The handlers of `Try`s aren't
+//! lowered, only the code inside them.
+
+use super::ast::{AstNode, LabelId, LoopType, ValueSet};
+use super::from_r2::{Block, CondExpr, R2BasicBlock, R2Code, R2Cond, StructuredFunction, Var};
+
+use petgraph::prelude::*;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// A node of a [`LoweredCfg`].
+#[derive(Debug)]
+pub enum LoweredNode<'a, B, C, V> {
+    /// runs the block, then follows its `Next` edge
+    Block(&'a B),
+    /// follows the `True` or `False` edge, depending on the condition
+    Cond(&'a C),
+    /// follows the `Case` edge whose values contain the value of the
+    /// variable, or the `Default` edge
+    Switch(&'a V),
+    /// ends the function by returning the result of the call in the block
+    TailCall(&'a B),
+    /// runs the block, then jumps somewhere unknown
+    IndirectJump(&'a B),
+    /// follows its `Next` edge; `Label`s and the headers of endless loops.
+    /// The label of a `Goto` without a matching `Label` has no edge
+    Nop,
+    /// the end of the function
+    Exit,
+}
+
+#[derive(Debug)]
+pub enum LoweredEdge<'a> {
+    Next,
+    True,
+    False,
+    Case(&'a ValueSet),
+    Default,
+}
+
+/// An AST lowered back into a control flow graph, see [`AstNode::to_cfg`].
+pub struct LoweredCfg<'a, B, C, V> {
+    pub graph: StableDiGraph<LoweredNode<'a, B, C, V>, LoweredEdge<'a>>,
+    pub entry: NodeIndex,
+    /// the only `Exit` node, where `Return`s and falling off the end go
+    pub exit: NodeIndex,
+}
+
+impl<B, C, V> AstNode<B, C, V> {
+    /// Lowers `self` into a control flow graph that borrows its blocks,
+    /// conditions and variables.
+    pub fn to_cfg(&self) -> LoweredCfg<'_, B, C, V> {
+        let mut graph = StableDiGraph::new();
+        let exit = graph.add_node(LoweredNode::Exit);
+        let mut lowerer = Lowerer {
+            graph,
+            exit,
+            labels: HashMap::new(),
+        };
+        let entry = lowerer.lower(self, exit, None);
+        LoweredCfg {
+            graph: lowerer.graph,
+            entry,
+            exit,
+        }
+    }
+}
+
+struct Lowerer<'a, B, C, V> {
+    graph: StableDiGraph<LoweredNode<'a, B, C, V>, LoweredEdge<'a>>,
+    exit: NodeIndex,
+    labels: HashMap<LabelId, NodeIndex>,
+}
+
+/// Where `break` and `continue` go in the innermost loop.
+#[derive(Copy, Clone)]
+struct LoopExits {
+    break_to: NodeIndex,
+    continue_to: NodeIndex,
+}
+
+impl<'a, B, C, V> Lowerer<'a, B, C, V> {
+    /// Lowers `ast`, to continue at `next`, and returns its entry.
+    fn lower(
+        &mut self,
+        ast: &'a AstNode<B, C, V>,
+        next: NodeIndex,
+        exits: Option<LoopExits>,
+    ) -> NodeIndex {
+        use self::AstNode::*;
+        match ast {
+            BasicBlock(b) => self.node(LoweredNode::Block(b), vec![(LoweredEdge::Next, next)]),
+            Seq(seq) => seq
+                .iter()
+                .rev()
+                .fold(next, |next, a| self.lower(a, next, exits)),
+            Cond(c, t, oe) => {
+                let t = self.lower(t, next, exits);
+                let e = match oe {
+                    Some(e) => self.lower(e, next, exits),
+                    None => next,
+                };
+                self.node(
+                    LoweredNode::Cond(c),
+                    vec![(LoweredEdge::True, t), (LoweredEdge::False, e)],
+                )
+            }
+            Loop(lt, body) => {
+                let header = self.graph.add_node(match lt {
+                    LoopType::PreChecked(c) | LoopType::PostChecked(c) => LoweredNode::Cond(c),
+                    LoopType::Endless => LoweredNode::Nop,
+                });
+                let body_exits = LoopExits {
+                    break_to: next,
+                    continue_to: header,
+                };
+                let body = self.lower(body, header, Some(body_exits));
+                if let LoopType::Endless = lt {
+                    self.graph.add_edge(header, body, LoweredEdge::Next);
+                    return header;
+                }
+                self.graph.add_edge(header, body, LoweredEdge::True);
+                self.graph.add_edge(header, next, LoweredEdge::False);
+                match lt {
+                    LoopType::PostChecked(_) => body,
+                    _ => header,
+                }
+            }
+            For(init, c, update, body) => {
+                let header = self.graph.add_node(LoweredNode::Cond(c));
+                let update = self.node(
+                    LoweredNode::Block(update),
+                    vec![(LoweredEdge::Next, header)],
+                );
+                let body_exits = LoopExits {
+                    break_to: next,
+                    continue_to: update,
+                };
+                let body = self.lower(body, update, Some(body_exits));
+                self.graph.add_edge(header, body, LoweredEdge::True);
+                self.graph.add_edge(header, next, LoweredEdge::False);
+                self.node(LoweredNode::Block(init), vec![(LoweredEdge::Next, header)])
+            }
+            Break => exits.expect("`break` outside of a loop").break_to,
+            Continue => exits.expect("`continue` outside of a loop").continue_to,
+            Return => self.exit,
+            Switch(v, cases, default) => {
+                let mut edges: Vec<_> = cases
+                    .iter()
+                    .map(|(vs, a)| (LoweredEdge::Case(vs), self.lower(a, next, exits)))
+                    .collect();
+                edges.push((LoweredEdge::Default, self.lower(default, next, exits)));
+                self.node(LoweredNode::Switch(v), edges)
+            }
+            TailCall(b) => self.graph.add_node(LoweredNode::TailCall(b)),
+            IndirectJump(b) => self.graph.add_node(LoweredNode::IndirectJump(b)),
+            Goto(l) => self.label(*l),
+            Label(l) => {
+                let n = self.label(*l);
+                self.graph.add_edge(n, next, LoweredEdge::Next);
+                n
+            }
+            Try(b, _) => self.lower(b, next, exits),
+            Unstructured(members) => members.iter().rev().fold(next, |next, (l, a)| {
+                let entry = self.lower(a, next, exits);
+                let n = self.label(*l);
+                self.graph.add_edge(n, entry, LoweredEdge::Next);
+                n
+            }),
+        }
+    }
+
+    fn node(
+        &mut self,
+        node: LoweredNode<'a, B, C, V>,
+        edges: Vec<(LoweredEdge<'a>, NodeIndex)>,
+    ) -> NodeIndex {
+        let n = self.graph.add_node(node);
+        for (e, succ) in edges {
+            self.graph.add_edge(n, succ, e);
+        }
+        n
+    }
+
+    fn label(&mut self, l: LabelId) -> NodeIndex {
+        let graph = &mut self.graph;
+        *self
+            .labels
+            .entry(l)
+            .or_insert_with(|| graph.add_node(LoweredNode::Nop))
+    }
+}
+
+/// What a function does next.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Step {
+    /// runs the block at this address
+    Block(u64),
+    /// jumps to this address outside of the function
+    TailCall(u64),
+    /// jumps somewhere unknown
+    IndirectJump,
+    Return,
+}
+
+/// How a structured function differs from its blocks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// the addresses of the blocks run before the difference showed, in
+    /// order
+    pub trace: Vec<u64>,
+    pub kind: MismatchKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MismatchKind {
+    /// the structured function does something else next
+    WrongStep { expected: Step, found: Step },
+    /// the lowered block at this address ends in the middle of a block, or
+    /// goes on past a branch
+    PartialBlock(u64),
+    /// a condition was evaluated before the branch or the assignment it
+    /// depends on was run
+    Undefined,
+    /// the structured function loops forever without running a block
+    SilentLoop,
+    /// a `Goto` has no matching `Label`
+    DanglingGoto,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "after [")?;
+        for (i, addr) in self.trace.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:#x}", addr)?;
+        }
+        write!(f, "]: ")?;
+        match &self.kind {
+            MismatchKind::WrongStep { expected, found } => {
+                write!(f, "expected {:?}, found {:?}", expected, found)
+            }
+            MismatchKind::PartialBlock(addr) => {
+                write!(
+                    f,
+                    "the block at {:#x} doesn't match the original blocks",
+                    addr
+                )
+            }
+            MismatchKind::Undefined => write!(f, "a condition was evaluated too early"),
+            MismatchKind::SilentLoop => write!(f, "looping without running any block"),
+            MismatchKind::DanglingGoto => write!(f, "a goto has no label"),
+        }
+    }
+}
+
+/// Checks that `sf` does what `blocks` do, the first block being the entry,
+/// as described in the [module docs](self). The blocks must have been
+/// imported without recovering switches, since nothing assigns the operands
+/// of the switches that recovers.
+pub fn check(blocks: &[R2BasicBlock], sf: &StructuredFunction) -> Result<(), Mismatch> {
+    let entry = match blocks.first() {
+        Some(b) => b.addr,
+        None => return Ok(()),
+    };
+    let checker = Checker {
+        blocks_at: blocks.iter().map(|b| (b.addr, b)).collect(),
+        lowered: sf.ast.to_cfg(),
+        trace: Vec::new(),
+    };
+    let node = checker.lowered.entry;
+    checker.run(State {
+        expected: Step::Block(entry),
+        node,
+        outcomes: BTreeMap::new(),
+        vars: sf
+            .var_inits
+            .iter()
+            .enumerate()
+            .filter_map(|(i, init)| init.map(|v| (i, v)))
+            .collect(),
+        trace: None,
+    })
+}
+
+/// Which way a block branched the last time it ran.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Outcome {
+    Taken(bool),
+    /// the value of the switch case, or `None` for the default
+    Case(Option<u64>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct State {
+    expected: Step,
+    node: NodeIndex,
+    outcomes: BTreeMap<u64, Outcome>,
+    vars: BTreeMap<usize, u64>,
+    /// the last block run, in `Checker::trace`
+    trace: Option<usize>,
+}
+
+struct Checker<'a> {
+    blocks_at: HashMap<u64, &'a R2BasicBlock>,
+    lowered: LoweredCfg<'a, Block, CondExpr, Var>,
+    /// the blocks run, each with the one run before it
+    trace: Vec<(u64, Option<usize>)>,
+}
+
+impl<'a> Checker<'a> {
+    fn run(mut self, initial: State) -> Result<(), Mismatch> {
+        let mut seen = HashSet::new();
+        let mut worklist = vec![initial];
+        while let Some(state) = worklist.pop() {
+            if seen.insert(strip_trace(&state)) {
+                self.step(state, &mut worklist)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the lowered graph from `state` up to the next block, and pushes
+    /// one state for each way that block can branch.
+    fn step(&mut self, mut state: State, worklist: &mut Vec<State>) -> Result<(), Mismatch> {
+        // a path that doesn't run a block can't visit a node more than a
+        // few times without looping forever
+        let mut fuel = 4 * self.lowered.graph.node_count() + 1;
+        loop {
+            if fuel == 0 {
+                return Err(self.mismatch(&state, MismatchKind::SilentLoop));
+            }
+            fuel -= 1;
+            let graph = &self.lowered.graph;
+            let (found, ran) = match &graph[state.node] {
+                LoweredNode::Nop => match graph.neighbors(state.node).next() {
+                    Some(next) => {
+                        state.node = next;
+                        continue;
+                    }
+                    None => return Err(self.mismatch(&state, MismatchKind::DanglingGoto)),
+                },
+                LoweredNode::Cond(c) => {
+                    let value = match eval(c, &state) {
+                        Some(value) => value,
+                        None => return Err(self.mismatch(&state, MismatchKind::Undefined)),
+                    };
+                    state.node = self.successor(state.node, |e| match e {
+                        LoweredEdge::True => value,
+                        LoweredEdge::False => !value,
+                        _ => false,
+                    });
+                    continue;
+                }
+                LoweredNode::Switch(v) => {
+                    let value = match state.vars.get(&v.0) {
+                        Some(&value) => value,
+                        None => return Err(self.mismatch(&state, MismatchKind::Undefined)),
+                    };
+                    let case = graph
+                        .edges(state.node)
+                        .find(|e| match e.weight() {
+                            LoweredEdge::Case(vs) => vs.contains(value),
+                            _ => false,
+                        })
+                        .or_else(|| {
+                            graph
+                                .edges(state.node)
+                                .find(|e| matches!(e.weight(), LoweredEdge::Default))
+                        });
+                    state.node = case.expect("switch without a default").target();
+                    continue;
+                }
+                LoweredNode::Block(Block::Assign(var, value)) => {
+                    state.vars.insert(var.0, *value);
+                    state.node = self.successor(state.node, |_| true);
+                    continue;
+                }
+                LoweredNode::Block(Block::BoolAssign(var, cond)) => {
+                    match eval(cond, &state) {
+                        Some(value) => state.vars.insert(var.0, value as u64),
+                        None => state.vars.remove(&var.0),
+                    };
+                    state.node = self.successor(state.node, |_| true);
+                    continue;
+                }
+                LoweredNode::Block(Block::Code(R2Code::Block { addr, size })) => {
+                    (None, Some((*addr, *size)))
+                }
+                LoweredNode::Block(Block::Code(R2Code::ExternalJump(target)))
+                | LoweredNode::TailCall(Block::Code(R2Code::ExternalJump(target))) => {
+                    (Some(Step::TailCall(*target)), None)
+                }
+                LoweredNode::TailCall(Block::Code(R2Code::Block { addr, size })) => {
+                    (Some(Step::Return), Some((*addr, *size)))
+                }
+                LoweredNode::IndirectJump(Block::Code(R2Code::Block { addr, size })) => {
+                    (Some(Step::IndirectJump), Some((*addr, *size)))
+                }
+                LoweredNode::TailCall(_) | LoweredNode::IndirectJump(_) | LoweredNode::Exit => {
+                    (Some(Step::Return), None)
+                }
+            };
+
+            let (addr, size) = match ran {
+                Some(ran) => ran,
+                None => {
+                    let found = found.unwrap();
+                    if found != state.expected {
+                        let kind = MismatchKind::WrongStep {
+                            expected: state.expected,
+                            found,
+                        };
+                        return Err(self.mismatch(&state, kind));
+                    }
+                    return Ok(());
+                }
+            };
+            if state.expected != Step::Block(addr) {
+                let kind = MismatchKind::WrongStep {
+                    expected: state.expected,
+                    found: Step::Block(addr),
+                };
+                return Err(self.mismatch(&state, kind));
+            }
+            let last = self.run_blocks(&mut state, addr, size)?;
+            let next = match found {
+                // the end of the function, after the block
+                Some(found) => {
+                    state.node = self.lowered.exit;
+                    Some(found)
+                }
+                None => {
+                    state.node = self.successor(state.node, |_| true);
+                    None
+                }
+            };
+            for (outcome, expected) in self.branches(last) {
+                let mut succ = state.clone();
+                if let Some(outcome) = outcome {
+                    succ.outcomes.insert(last.addr, outcome);
+                }
+                succ.expected = expected;
+                if let Some(found) = next {
+                    if found != expected {
+                        let kind = MismatchKind::WrongStep { expected, found };
+                        return Err(self.mismatch(&succ, kind));
+                    }
+                } else {
+                    worklist.push(succ);
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    /// Runs the blocks covered by the lowered block at `addr`, which must
+    /// follow each other unconditionally, and returns the last one.
+    fn run_blocks(
+        &mut self,
+        state: &mut State,
+        addr: u64,
+        size: u64,
+    ) -> Result<&'a R2BasicBlock, Mismatch> {
+        let end = addr + size;
+        let mut block = self.blocks_at[&addr];
+        loop {
+            self.trace.push((block.addr, state.trace));
+            state.trace = Some(self.trace.len() - 1);
+            let block_end = block.addr + block.size;
+            if block_end == end {
+                return Ok(block);
+            }
+            let next = match &self.branches(block)[..] {
+                [(None, Step::Block(next))] if block_end < end && *next == block_end => *next,
+                _ => return Err(self.mismatch(state, MismatchKind::PartialBlock(addr))),
+            };
+            block = self.blocks_at[&next];
+        }
+    }
+
+    /// The ways `block` can branch, each with what happens next, as
+    /// imported by `from_r2`.
+    fn branches(&self, block: &R2BasicBlock) -> Vec<(Option<Outcome>, Step)> {
+        let step = |target| {
+            if self.blocks_at.contains_key(&target) {
+                Step::Block(target)
+            } else {
+                Step::TailCall(target)
+            }
+        };
+        if let Some((&(_, last_target), cases)) = block.cases.split_last() {
+            // the same cascade as the importer
+            let (cases, default) = match block.default.or(block.fail).or(block.jump) {
+                Some(default) => (&block.cases[..], default),
+                None => (cases, last_target),
+            };
+            let mut ret: Vec<(Option<Outcome>, Step)> = Vec::new();
+            for &(value, target) in cases {
+                let outcome = Some(Outcome::Case(Some(value)));
+                if ret.iter().all(|(o, _)| *o != outcome) {
+                    ret.push((outcome, step(target)));
+                }
+            }
+            ret.push((Some(Outcome::Case(None)), step(default)));
+            return ret;
+        }
+        if block.unresolved_jump {
+            return vec![(None, Step::IndirectJump)];
+        }
+        match (block.jump, block.fail) {
+            (Some(jump), Some(fail)) if jump != fail => vec![
+                (Some(Outcome::Taken(true)), step(jump)),
+                (Some(Outcome::Taken(false)), step(fail)),
+            ],
+            (Some(succ), _) | (None, Some(succ)) => vec![(None, step(succ))],
+            (None, None) => vec![(None, Step::Return)],
+        }
+    }
+
+    fn successor<F>(&self, node: NodeIndex, pick: F) -> NodeIndex
+    where
+        F: Fn(&LoweredEdge) -> bool,
+    {
+        self.lowered
+            .graph
+            .edges(node)
+            .find(|e| pick(e.weight()))
+            .expect("lowered node without a successor")
+            .target()
+    }
+
+    fn mismatch(&self, state: &State, kind: MismatchKind) -> Mismatch {
+        let mut trace = Vec::new();
+        let mut cur = state.trace;
+        while let Some(i) = cur {
+            trace.push(self.trace[i].0);
+            cur = self.trace[i].1;
+        }
+        trace.reverse();
+        Mismatch { trace, kind }
+    }
+}
+
+fn strip_trace(state: &State) -> State {
+    State {
+        trace: None,
+        ..state.clone()
+    }
+}
+
+/// The value of `cond` in `state`, or `None` if it depends on a branch or a
+/// variable that isn't known yet.
+fn eval(cond: &CondExpr, state: &State) -> Option<bool> {
+    match cond {
+        CondExpr::Pred(R2Cond::Taken(addr) | R2Cond::Predicate(addr, _)) => {
+            match state.outcomes.get(addr) {
+                Some(Outcome::Taken(taken)) => Some(*taken),
+                _ => None,
+            }
+        }
+        CondExpr::Pred(R2Cond::Case(addr, value)) => match state.outcomes.get(addr) {
+            Some(Outcome::Case(case)) => Some(*case == Some(*value)),
+            _ => None,
+        },
+        CondExpr::Equals(var, value) => state.vars.get(&var.0).map(|v| v == value),
+        CondExpr::BoolVar(var) => state.vars.get(&var.0).map(|&v| v != 0),
+        CondExpr::Not(c) => eval(c, state).map(|v| !v),
+        // short-circuiting, so that an operand that isn't known yet doesn't
+        // matter if another one decides the result
+        CondExpr::All(cs) => fold_known(cs, state, false),
+        CondExpr::Any(cs) => fold_known(cs, state, true),
+    }
+}
+
+/// `true` if any of `cs` is `decisive`, `false` if none is and all are
+/// known.
+fn fold_known(cs: &[CondExpr], state: &State, decisive: bool) -> Option<bool> {
+    let mut known = true;
+    for c in cs {
+        match eval(c, state) {
+            Some(v) if v == decisive => return Some(decisive),
+            Some(_) => (),
+            None => known = false,
+        }
+    }
+    if known {
+        Some(!decisive)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::from_r2::{self, ImportOptions};
+    use super::super::{Budget, StructuringOptions, StructuringReport};
+    use super::*;
+
+    use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+
+    use std::iter;
+    use std::time::Duration;
+
+    fn block(addr: u64, jump: Option<u64>, fail: Option<u64>) -> R2BasicBlock {
+        R2BasicBlock {
+            addr,
+            size: 4,
+            jump,
+            fail,
+            cases: Vec::new(),
+            default: None,
+            unresolved_jump: false,
+            esil: Vec::new(),
+        }
+    }
+
+    fn code(addr: u64, size: u64) -> AstNode<Block, CondExpr, Var> {
+        AstNode::BasicBlock(Block::Code(R2Code::Block { addr, size }))
+    }
+
+    fn structure(blocks: &[R2BasicBlock], opts: &StructuringOptions) -> StructuredFunction {
+        from_r2::structure_blocks(blocks, &ImportOptions::default(), opts).unwrap()
+    }
+
+    #[test]
+    fn lower_while() {
+        let ast = AstNode::Seq(vec![
+            AstNode::Loop(
+                LoopType::PreChecked(CondExpr::Pred(R2Cond::Taken(0x10))),
+                Box::new(AstNode::Seq(vec![
+                    code(0x14, 4),
+                    AstNode::Cond(
+                        CondExpr::Pred(R2Cond::Taken(0x14)),
+                        Box::new(AstNode::Break),
+                        None,
+                    ),
+                ])),
+            ),
+            code(0x18, 4),
+        ]);
+        let lowered = ast.to_cfg();
+        let g = &lowered.graph;
+        assert_eq!(g.node_count(), 5);
+        let header = lowered.entry;
+        assert!(matches!(
+            g[header],
+            LoweredNode::Cond(CondExpr::Pred(R2Cond::Taken(0x10)))
+        ));
+        let succ = |n, pick: fn(&LoweredEdge) -> bool| {
+            g.edges(n).find(|e| pick(e.weight())).unwrap().target()
+        };
+        let body = succ(header, |e| matches!(e, LoweredEdge::True));
+        let after = succ(header, |e| matches!(e, LoweredEdge::False));
+        assert!(matches!(
+            g[after],
+            LoweredNode::Block(Block::Code(R2Code::Block { addr: 0x18, .. }))
+        ));
+        assert_eq!(succ(after, |_| true), lowered.exit);
+        let inner = succ(body, |_| true);
+        // the `break` goes where the loop does when it ends, and the end of
+        // the body back to the header
+        assert_eq!(succ(inner, |e| matches!(e, LoweredEdge::True)), after);
+        assert_eq!(succ(inner, |e| matches!(e, LoweredEdge::False)), header);
+    }
+
+    #[test]
+    fn structured_matches() {
+        // a loop with two exits, the second ending the function
+        let blocks = vec![
+            block(0x10, Some(0x14), None),
+            block(0x14, Some(0x20), Some(0x18)),
+            block(0x18, Some(0x24), Some(0x1c)),
+            block(0x1c, Some(0x14), None),
+            block(0x20, None, None),
+            block(0x24, Some(0x1000), None),
+        ];
+        for &collapse_sese_regions in &[true, false] {
+            let opts = StructuringOptions {
+                collapse_sese_regions,
+                ..Default::default()
+            };
+            assert_eq!(check(&blocks, &structure(&blocks, &opts)), Ok(()));
+        }
+    }
+
+    #[test]
+    fn allowances() {
+        let blocks = vec![
+            block(0x10, Some(0x18), Some(0x14)),
+            block(0x14, Some(0x18), None),
+            block(0x18, Some(0x1c), None),
+            block(0x1c, None, None),
+        ];
+        let taken = || CondExpr::Pred(R2Cond::Taken(0x10));
+        // 0x18 is duplicated into both branches, and merged with 0x1c
+        let sf = StructuredFunction {
+            ast: AstNode::Cond(
+                CondExpr::Not(Box::new(taken())),
+                Box::new(AstNode::Seq(vec![code(0x14, 4), code(0x18, 8)])),
+                Some(Box::new(AstNode::Seq(vec![code(0x18, 4), code(0x1c, 4)]))),
+            ),
+            var_inits: Vec::new(),
+        };
+        // the condition comes before the block it's about
+        assert_eq!(
+            check(&blocks, &sf),
+            Err(Mismatch {
+                trace: vec![],
+                kind: MismatchKind::Undefined,
+            })
+        );
+        let sf = StructuredFunction {
+            ast: AstNode::Seq(vec![code(0x10, 4), sf.ast]),
+            ..sf
+        };
+        assert_eq!(check(&blocks, &sf), Ok(()));
+    }
+
+    #[test]
+    fn mismatches() {
+        let blocks = vec![
+            block(0x10, Some(0x18), Some(0x14)),
+            block(0x14, Some(0x18), None),
+            block(0x18, None, None),
+        ];
+        let mut sf = structure(&blocks, &StructuringOptions::default());
+        assert_eq!(check(&blocks, &sf), Ok(()));
+
+        // negate the condition of the `if`
+        fn negate(ast: &mut AstNode<Block, CondExpr, Var>) {
+            match ast {
+                AstNode::Seq(seq) => seq.iter_mut().for_each(negate),
+                AstNode::Cond(c, _, _) => *c = CondExpr::Not(Box::new(c.clone())),
+                _ => (),
+            }
+        }
+        negate(&mut sf.ast);
+        let err = check(&blocks, &sf).unwrap_err();
+        assert_eq!(err.trace, vec![0x10]);
+        assert!(matches!(err.kind, MismatchKind::WrongStep { .. }));
+        assert!(err.to_string().starts_with("after [0x10]: expected "));
+
+        let sf = StructuredFunction {
+            ast: AstNode::Seq(vec![code(0x10, 2)]),
+            var_inits: Vec::new(),
+        };
+        assert_eq!(
+            check(&blocks, &sf).unwrap_err().kind,
+            MismatchKind::PartialBlock(0x10)
+        );
+    }
+
+    /// A deterministic xorshift generator, so that a failing graph can be
+    /// found again from its seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    /// Makes up the blocks of a function with a reducible control flow
+    /// graph: forward edges, then back edges from blocks to their
+    /// dominators.
+    fn random_blocks(seed: u64) -> Vec<R2BasicBlock> {
+        let mut rng = Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+        let n = 2 + rng.below(9);
+        let addr = |i: u64| 0x10 * (i + 1);
+        let mut blocks = Vec::new();
+        for i in 0..n {
+            let target = |rng: &mut Rng| {
+                if i + 1 < n {
+                    addr(i + 1 + rng.below(n - i - 1))
+                } else {
+                    0x1000
+                }
+            };
+            let mut b = block(addr(i), None, None);
+            match rng.below(10) {
+                _ if i + 1 == n => (),
+                0 => (),
+                1 => b.jump = Some(0x1000 + 0x10 * rng.below(2)),
+                2 => b.unresolved_jump = true,
+                3 => {
+                    b.cases = (0..3).map(|v| (v, target(&mut rng))).collect();
+                    b.default = Some(target(&mut rng));
+                }
+                4..=6 => b.jump = Some(target(&mut rng)),
+                _ => {
+                    b.jump = Some(target(&mut rng));
+                    b.fail = Some(target(&mut rng));
+                }
+            }
+            blocks.push(b);
+        }
+
+        // dominators over the forward edges, which go to later blocks
+        let succs = |b: &R2BasicBlock| -> Vec<usize> {
+            let mut ret: Vec<_> = b.cases.iter().map(|c| c.1).collect();
+            ret.extend(b.jump.iter().chain(&b.fail).chain(&b.default));
+            ret.into_iter()
+                .filter(|&a| a < addr(n))
+                .map(|a| (a / 0x10 - 1) as usize)
+                .collect()
+        };
+        let mut doms: Vec<Option<Vec<usize>>> = vec![None; n as usize];
+        doms[0] = Some(vec![0]);
+        for i in 0..n as usize {
+            if let Some(dom) = doms[i].clone() {
+                if blocks[i].unresolved_jump {
+                    continue;
+                }
+                for s in succs(&blocks[i]) {
+                    let mut new: Vec<_> = dom.clone();
+                    if let Some(old) = &doms[s] {
+                        new.retain(|d| old.contains(d));
+                    }
+                    if !new.contains(&s) {
+                        new.push(s);
+                    }
+                    doms[s] = Some(new);
+                }
+            }
+        }
+        for _ in 0..rng.below(4) {
+            let i = rng.below(n) as usize;
+            let dom = match &doms[i] {
+                Some(dom) => dom.clone(),
+                None => continue,
+            };
+            let b = &mut blocks[i];
+            if !b.cases.is_empty() || b.unresolved_jump || b.fail.is_some() {
+                continue;
+            }
+            let header = addr(dom[rng.below(dom.len() as u64) as usize] as u64);
+            b.fail = b.jump;
+            b.jump = Some(header);
+        }
+        blocks
+    }
+
+    #[test]
+    fn random_reducible_graphs() {
+        for seed in 0..300 {
+            let blocks = random_blocks(seed);
+            // the refinements mustn't change what the AST does
+            let all_opts = vec![
+                StructuringOptions::default(),
+                StructuringOptions::default().collapse_sese_regions(false),
+                StructuringOptions::fast(),
+                StructuringOptions::default().refine_loops(false),
+            ];
+            for opts in all_opts {
+                let sf = structure(&blocks, &opts);
+                if let Err(err) = check(&blocks, &sf) {
+                    panic!(
+                        "seed {}, {:?}: {}\nblocks: {:#?}\nast: {:#?}",
+                        seed, opts, err, blocks, sf.ast
+                    );
+                }
+            }
+        }
+    }
+
+    /// Sections of a loop with an `if` in it, then an `if`, one after the
+    /// other.
+    fn medium_blocks(sections: u64) -> Vec<R2BasicBlock> {
+        let mut blocks = Vec::new();
+        for i in 0..sections {
+            let a = 0x100 * i;
+            blocks.extend(vec![
+                block(a, Some(a + 0x20), Some(a + 0x10)),
+                block(a + 0x10, Some(a + 0x20), None),
+                block(a + 0x20, Some(a), Some(a + 0x30)),
+                block(a + 0x30, Some(a + 0x100), Some(a + 0x40)),
+                block(a + 0x40, Some(a + 0x100), None),
+            ]);
+        }
+        blocks.push(block(0x100 * sections, None, None));
+        blocks
+    }
+
+    #[test]
+    fn exhausted_budgets() {
+        let blocks = medium_blocks(4);
+        let unlimited = from_r2::structure_blocks_reported(
+            &blocks,
+            &ImportOptions::default(),
+            &StructuringOptions::default(),
+        )
+        .unwrap()
+        .1;
+        assert!(!unlimited.budget_exhausted);
+        let steps = unlimited.regions + unlimited.loops;
+
+        let mut asts = Vec::new();
+        for budget in (0..steps)
+            .map(Budget::Steps)
+            .chain(iter::once(Budget::Time(Duration::from_secs(0))))
+        {
+            let opts = StructuringOptions {
+                budget: Some(budget),
+                ..Default::default()
+            };
+            let (sf, report) =
+                from_r2::structure_blocks_reported(&blocks, &ImportOptions::default(), &opts)
+                    .unwrap();
+            assert!(report.budget_exhausted, "{:?}", budget);
+            if let Budget::Steps(steps) = budget {
+                assert_eq!(report.regions + report.loops, steps);
+            }
+            if let Err(err) = check(&blocks, &sf) {
+                panic!("{:?}: {}\nast: {:#?}", budget, err, sf.ast);
+            }
+            asts.push((sf.ast, report.gotos));
+        }
+        // without any steps, every back edge at least is a `Goto`, and so is
+        // it without any time
+        assert!(asts[0].1 >= 4);
+        assert_eq!(asts[0], asts[asts.len() - 1]);
+
+        // enough steps for all of it
+        let opts = StructuringOptions {
+            budget: Some(Budget::Steps(steps)),
+            ..Default::default()
+        };
+        let report = from_r2::structure_blocks_reported(&blocks, &ImportOptions::default(), &opts)
+            .unwrap()
+            .1;
+        assert_eq!(
+            report,
+            StructuringReport {
+                times: report.times.clone(),
+                ..unlimited
+            }
+        );
+    }
+
+    #[test]
+    fn random_graphs_out_of_budget() {
+        for seed in 0..300 {
+            let blocks = random_blocks(seed);
+            for steps in 0..3 {
+                let opts = StructuringOptions {
+                    budget: Some(Budget::Steps(steps)),
+                    ..Default::default()
+                };
+                let sf = structure(&blocks, &opts);
+                if let Err(err) = check(&blocks, &sf) {
+                    panic!(
+                        "seed {}, {} steps: {}\nblocks: {:#?}\nast: {:#?}",
+                        seed, steps, err, blocks, sf.ast
+                    );
+                }
+            }
+        }
+    }
+
+    /// A structured statement, made of more of them, that lowers to the
+    /// blocks of a reducible control flow graph. It is generated with
+    /// quickcheck like the other property tests of the crate, and shrinks
+    /// toward smaller templates, see [`Arbitrary::shrink`].
+    #[derive(Clone, Debug)]
+    enum Template {
+        Block,
+        Seq(Vec<Template>),
+        If(Box<Template>, Option<Box<Template>>),
+        While(Box<Template>),
+        DoWhile(Box<Template>),
+        Switch(Vec<Template>),
+    }
+
+    impl Template {
+        fn gen<G: Gen>(g: &mut G, depth: usize) -> Self {
+            use self::Template::*;
+            let below = |g: &mut G, n: usize| usize::arbitrary(g) % n;
+            if depth == 0 || below(g, 3) == 0 {
+                return Block;
+            }
+            let sub = |g: &mut G| Box::new(Template::gen(g, depth - 1));
+            let subs = |g: &mut G| {
+                (0..2 + below(g, 2))
+                    .map(|_| Template::gen(g, depth - 1))
+                    .collect()
+            };
+            match below(g, 5) {
+                0 => Seq(subs(g)),
+                1 => {
+                    let then = sub(g);
+                    If(
+                        then,
+                        if bool::arbitrary(g) {
+                            Some(sub(g))
+                        } else {
+                            None
+                        },
+                    )
+                }
+                2 => While(sub(g)),
+                3 => DoWhile(sub(g)),
+                _ => Switch(subs(g)),
+            }
+        }
+
+        fn children(&self) -> Vec<&Template> {
+            use self::Template::*;
+            match self {
+                Block => Vec::new(),
+                Seq(ts) | Switch(ts) => ts.iter().collect(),
+                If(then, els) => iter::once(&**then).chain(els.as_deref()).collect(),
+                While(body) | DoWhile(body) => vec![&**body],
+            }
+        }
+
+        fn children_mut(&mut self) -> Vec<&mut Template> {
+            use self::Template::*;
+            match self {
+                Block => Vec::new(),
+                Seq(ts) | Switch(ts) => ts.iter_mut().collect(),
+                If(then, els) => iter::once(&mut **then).chain(els.as_deref_mut()).collect(),
+                While(body) | DoWhile(body) => vec![&mut **body],
+            }
+        }
+
+        fn size(&self) -> usize {
+            1 + self
+                .children()
+                .into_iter()
+                .map(Template::size)
+                .sum::<usize>()
+        }
+
+        fn loops(&self) -> usize {
+            let own = match self {
+                Template::While(_) | Template::DoWhile(_) => 1,
+                _ => 0,
+            };
+            own + self
+                .children()
+                .into_iter()
+                .map(Template::loops)
+                .sum::<usize>()
+        }
+
+        /// The blocks of a function that does `self` and returns, the
+        /// first being the entry.
+        fn blocks(&self) -> Vec<R2BasicBlock> {
+            let mut blocks = Vec::new();
+            let ret = add(&mut blocks);
+            let entry = self.lower(&mut blocks, ret);
+            let entry = blocks.iter().position(|b| b.addr == entry).unwrap();
+            let entry = blocks.remove(entry);
+            blocks.insert(0, entry);
+            blocks
+        }
+
+        /// Adds the blocks of `self`, which go on to `exit`, and returns
+        /// the address of its first.
+        fn lower(&self, blocks: &mut Vec<R2BasicBlock>, exit: u64) -> u64 {
+            use self::Template::*;
+            let at = |addr: u64| (addr / 0x10 - 1) as usize;
+            match self {
+                Block => {
+                    let b = add(blocks);
+                    blocks[at(b)].jump = Some(exit);
+                    b
+                }
+                Seq(ts) => ts.iter().rev().fold(exit, |next, t| t.lower(blocks, next)),
+                If(then, els) => {
+                    let cond = add(blocks);
+                    let jump = then.lower(blocks, exit);
+                    let fail = els.as_ref().map_or(exit, |e| e.lower(blocks, exit));
+                    blocks[at(cond)].jump = Some(jump);
+                    blocks[at(cond)].fail = Some(fail);
+                    cond
+                }
+                While(body) => {
+                    let header = add(blocks);
+                    let body = body.lower(blocks, header);
+                    blocks[at(header)].jump = Some(body);
+                    blocks[at(header)].fail = Some(exit);
+                    header
+                }
+                DoWhile(body) => {
+                    // the body starts with a block of its own, so that
+                    // nested loops don't share a header
+                    let top = add(blocks);
+                    let latch = add(blocks);
+                    let body = body.lower(blocks, latch);
+                    blocks[at(top)].jump = Some(body);
+                    blocks[at(latch)].jump = Some(top);
+                    blocks[at(latch)].fail = Some(exit);
+                    top
+                }
+                Switch(ts) => {
+                    let switch = add(blocks);
+                    let cases = ts
+                        .iter()
+                        .enumerate()
+                        .map(|(v, t)| (v as u64, t.lower(blocks, exit)))
+                        .collect();
+                    blocks[at(switch)].cases = cases;
+                    blocks[at(switch)].default = Some(exit);
+                    switch
+                }
+            }
+        }
+    }
+
+    /// Adds a block that returns, and returns its address.
+    fn add(blocks: &mut Vec<R2BasicBlock>) -> u64 {
+        let addr = 0x10 * (blocks.len() as u64 + 1);
+        blocks.push(block(addr, None, None));
+        addr
+    }
+
+    impl Arbitrary for Template {
+        fn arbitrary<G: Gen>(g: &mut G) -> Self {
+            Template::gen(g, 4)
+        }
+
+        /// A block, then the children in place of `self`, then `self` with
+        /// fewer or smaller children.
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            if let Template::Block = self {
+                return Box::new(iter::empty());
+            }
+            let mut ret = vec![Template::Block];
+            ret.extend(self.children().into_iter().cloned());
+            if let Template::Seq(ts) | Template::Switch(ts) = self {
+                for i in 0..ts.len() {
+                    let mut fewer = self.clone();
+                    if let Template::Seq(ts) | Template::Switch(ts) = &mut fewer {
+                        if ts.len() > 1 {
+                            ts.remove(i);
+                            ret.push(fewer);
+                        }
+                    }
+                }
+            }
+            if let Template::If(then, Some(_)) = self {
+                ret.push(Template::If(then.clone(), None));
+            }
+            for (i, child) in self.children().into_iter().enumerate() {
+                for smaller in child.shrink() {
+                    let mut t = self.clone();
+                    *t.children_mut()[i] = smaller;
+                    ret.push(t);
+                }
+            }
+            Box::new(ret.into_iter())
+        }
+    }
+
+    /// Tests that structuring a template needs no `Goto`s, finds one loop
+    /// per loop of the template, and gives an AST that does what its blocks
+    /// do.
+    fn qc_template(template: Template) -> TestResult {
+        let blocks = template.blocks();
+        let (sf, report) = from_r2::structure_blocks_reported(
+            &blocks,
+            &ImportOptions::default(),
+            &StructuringOptions::default(),
+        )
+        .unwrap();
+        println!("template: {:?}", template);
+        if report.gotos != 0 || report.loops != template.loops() {
+            println!("report: {:?}\nast: {:#?}", report, sf.ast);
+            return TestResult::failed();
+        }
+        if let Err(err) = check(&blocks, &sf) {
+            println!("{}\nblocks: {:#?}\nast: {:#?}", err, blocks, sf.ast);
+            return TestResult::failed();
+        }
+        TestResult::passed()
+    }
+
+    #[test]
+    fn random_templates() {
+        QuickCheck::new()
+            .tests(200)
+            .quickcheck(qc_template as fn(Template) -> TestResult);
+    }
+
+    #[test]
+    fn templates_shrink() {
+        use self::Template::*;
+        let template = Seq(vec![
+            While(Box::new(If(
+                Box::new(Block),
+                Some(Box::new(DoWhile(Box::new(Block)))),
+            ))),
+            Switch(vec![Block, Seq(vec![Block, Block])]),
+        ]);
+        let smaller: Vec<_> = template.shrink().collect();
+        assert!(smaller.iter().all(|t| t.size() < template.size()));
+        // down to a block, one loop at a time among others
+        assert_eq!(smaller[0].size(), 1);
+        assert!(smaller.iter().any(|t| t.loops() == 1));
+        for t in &smaller {
+            assert!(t.shrink().all(|s| s.size() < t.size()));
+        }
+    }
+}
@@ -0,0 +1,464 @@
+//! Checks an AST against the graph it was structured from, by running both.
+//!
+//! Every condition of the graph, i.e. every leaf of the conditions that
+//! structuring combines, is given a value, the same each time it's
+//! evaluated. [`ControlFlowGraph::run`] then runs the graph from its entry,
+//! following the edges that the values select, and [`run_ast`] runs the AST,
+//! lowered with [`AstNode::to_cfg`](super::ast::AstNode::to_cfg). Both must
+//! run the same blocks in the same order, and end the same way. A
+//! [`Machine`] runs what structuring made up: the blocks that assign its
+//! variables, and the conditions that test them.
+//!
+//! [`ControlFlowGraph::paths`] runs the graph under every assignment of its
+//! conditions if there are at most [`EXHAUSTIVE_LEAVES`] of them, or under
+//! random ones otherwise, and [`Paths::check`] runs the AST under the same
+//! ones. Unlike [`roundtrip`](super::roundtrip), this works with any
+//! [`AstContext`], but only tells apart conditions that don't change as the
+//! function runs. Conditions that switch recovery gave value sets are
+//! assigned like any other, so graphs with them can't be checked.
+//!
+//! [`AstNode::walk_path`](super::ast::AstNode::walk_path) runs an AST the
+//! same way, but for someone asking which blocks run if some conditions
+//! have some values: it only needs the values of those, and stops where
+//! they don't decide which way to go.
+
+use super::ast::AstNode as AstNodeC;
+use super::ast_context::AstContext;
+use super::condition;
+use super::roundtrip::{LoweredCfg, LoweredEdge, LoweredNode};
+use super::{AstNode, CfgEdge, CfgNode, ControlFlowGraph};
+
+use petgraph::prelude::*;
+
+use std::fmt;
+
+/// The most conditions a graph can have for [`ControlFlowGraph::paths`] to
+/// try every assignment of them.
+pub const EXHAUSTIVE_LEAVES: usize = 10;
+
+/// Runs the blocks and evaluates the conditions of an [`AstContext`].
+pub trait Machine<A: AstContext> {
+    /// What a block of the graph is recorded as when it runs.
+    type Visit: Clone + fmt::Debug + PartialEq;
+
+    /// Runs `block`, and returns what it's recorded as, or `None` if it's
+    /// one that structuring made up.
+    fn run(&mut self, block: &A::Block) -> Option<Self::Visit>;
+
+    /// Returns the value of `cond`, a condition of the graph or one that
+    /// structuring made up.
+    fn test(&mut self, cond: &A::Condition) -> bool;
+
+    /// Returns the value of the variable of a `Switch`.
+    fn value(&mut self, var: &A::Variable) -> u64;
+}
+
+/// How a run ends.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum End {
+    Return,
+    TailCall,
+    IndirectJump,
+    /// at a `Goto` without a matching `Label`
+    DanglingGoto,
+    /// after running as many blocks as allowed
+    StepLimit,
+    /// looping without running any block
+    SilentLoop,
+    /// at a condition that the known values don't decide, or at a
+    /// `Switch`, see [`AstNode::walk_path`](super::ast::AstNode::walk_path)
+    Undecided,
+}
+
+/// The blocks a run ran, in order, and how it ended.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path<V> {
+    pub visits: Vec<V>,
+    pub end: End,
+}
+
+/// The runs of a graph under some assignments of its conditions, see
+/// [`ControlFlowGraph::paths`].
+#[derive(Debug)]
+pub struct Paths<'cd, C: 'cd, V> {
+    leaves: Vec<condition::VarRef<'cd, C>>,
+    runs: Vec<(Vec<bool>, Path<V>)>,
+    limit: usize,
+}
+
+/// The first assignment under which an AST runs differently than its graph.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence<V> {
+    /// the values of the conditions, in the order of
+    /// [`ControlFlowGraph::leaves`]
+    pub assignment: Vec<bool>,
+    pub expected: Path<V>,
+    pub found: Path<V>,
+}
+
+impl<V: fmt::Debug> fmt::Display for Divergence<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bits: String = self
+            .assignment
+            .iter()
+            .map(|&b| if b { '1' } else { '0' })
+            .collect();
+        write!(
+            f,
+            "with conditions {}, expected {:?} then {:?}, found {:?} then {:?}",
+            bits, self.expected.visits, self.expected.end, self.found.visits, self.found.end
+        )
+    }
+}
+
+impl<'cd, A: AstContext> ControlFlowGraph<'cd, A> {
+    /// The conditions of the condition nodes, each once, in the order of
+    /// the nodes.
+    pub fn leaves(&self) -> Vec<condition::VarRef<'cd, A::Condition>> {
+        let mut ret = Vec::new();
+        for n in self.graph.node_indices() {
+            if let CfgNode::Condition(c) = self.graph[n] {
+                if !ret.contains(&c) {
+                    ret.push(c);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Runs the graph from its entry, for at most `limit` blocks.
+    pub fn run<M: Machine<A>>(&self, machine: &mut M, limit: usize) -> Path<M::Visit> {
+        let mut runner = Runner::new(machine, limit);
+        let end = runner.run_graph(self).unwrap_err();
+        runner.finish(end)
+    }
+
+    /// Runs the graph under every assignment of its conditions, or under
+    /// `samples` random ones if it has more than [`EXHAUSTIVE_LEAVES`].
+    /// `mk_machine` makes the machine for an assignment, given the value of
+    /// each condition.
+    pub fn paths<M, F>(
+        &self,
+        mut mk_machine: F,
+        samples: usize,
+        limit: usize,
+    ) -> Paths<'cd, A::Condition, M::Visit>
+    where
+        M: Machine<A>,
+        F: FnMut(&[(&A::Condition, bool)]) -> M,
+    {
+        let leaves = self.leaves();
+        let runs = assignments(leaves.len(), samples)
+            .into_iter()
+            .map(|values| {
+                let mut machine = mk_machine(&assigned(&leaves, &values));
+                let path = self.run(&mut machine, limit);
+                (values, path)
+            })
+            .collect();
+        Paths {
+            leaves,
+            runs,
+            limit,
+        }
+    }
+}
+
+impl<'cd, C, V: Clone + fmt::Debug + PartialEq> Paths<'cd, C, V> {
+    /// Runs `ast`, structured from the graph, under the same assignments,
+    /// with machines made by `mk_machine` as for
+    /// [`ControlFlowGraph::paths`], and returns the first that runs
+    /// differently.
+    pub fn check<A, M, F>(
+        &self,
+        ast: &AstNode<'cd, A>,
+        mut mk_machine: F,
+    ) -> Result<(), Divergence<V>>
+    where
+        A: AstContext<Condition = C>,
+        M: Machine<A, Visit = V>,
+        F: FnMut(&[(&C, bool)]) -> M,
+    {
+        let lowered = ast.to_cfg();
+        for (values, expected) in &self.runs {
+            let mut machine = mk_machine(&assigned(&self.leaves, values));
+            let found = run_lowered(&lowered, &mut machine, self.limit);
+            if found != *expected {
+                return Err(Divergence {
+                    assignment: values.clone(),
+                    expected: expected.clone(),
+                    found,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'cd, B, T, V> AstNodeC<B, condition::Condition<'cd, T>, V> {
+    /// Runs `self`, for at most `limit` blocks, with `env` giving the value
+    /// of each condition it knows, and returns the blocks that run, in
+    /// order, and how the run ends. Where `env` doesn't decide a condition,
+    /// the run ends there, [`Undecided`](End::Undecided), as it does at a
+    /// `Switch`, whose variable `env` can't tell. Each condition has the
+    /// same value each time it's evaluated, so a loop whose condition is
+    /// known to be true runs until the limit. The blocks and conditions
+    /// that structuring made up are run and asked of `env` like any other,
+    /// so a path through those of a dispatch only goes on if `env` knows
+    /// what they test.
+    pub fn walk_path(&self, env: &dyn Fn(&T) -> Option<bool>, limit: usize) -> Path<&B> {
+        let lowered = self.to_cfg();
+        let mut visits = Vec::new();
+        let mut node = lowered.entry;
+        let mut silent = 0;
+        let end = loop {
+            let taken = match lowered.graph[node] {
+                LoweredNode::Block(b) | LoweredNode::TailCall(b) | LoweredNode::IndirectJump(b) => {
+                    if visits.len() == limit {
+                        break End::StepLimit;
+                    }
+                    visits.push(b);
+                    silent = 0;
+                    match lowered.graph[node] {
+                        LoweredNode::TailCall(_) => break End::TailCall,
+                        LoweredNode::IndirectJump(_) => break End::IndirectJump,
+                        _ => None,
+                    }
+                }
+                LoweredNode::Cond(c) => match c.evaluate(env) {
+                    Some(v) => Some(v),
+                    None => break End::Undecided,
+                },
+                LoweredNode::Switch(_) => break End::Undecided,
+                LoweredNode::Nop => None,
+                LoweredNode::Exit => break End::Return,
+            };
+            silent += 1;
+            if silent > 4 * lowered.graph.node_count() + 16 {
+                break End::SilentLoop;
+            }
+            match next_node(&lowered, node, taken, None) {
+                Some(next) => node = next,
+                None => break End::DanglingGoto,
+            }
+        };
+        Path { visits, end }
+    }
+}
+
+/// Runs `ast`, for at most `limit` blocks.
+pub fn run_ast<'cd, A, M>(ast: &AstNode<'cd, A>, machine: &mut M, limit: usize) -> Path<M::Visit>
+where
+    A: AstContext,
+    M: Machine<A>,
+{
+    run_lowered(&ast.to_cfg(), machine, limit)
+}
+
+type Lowered<'a, 'cd, A> = LoweredCfg<
+    'a,
+    <A as AstContext>::Block,
+    condition::Condition<'cd, <A as AstContext>::Condition>,
+    <A as AstContext>::Variable,
+>;
+
+fn run_lowered<'cd, A, M>(
+    lowered: &Lowered<'_, 'cd, A>,
+    machine: &mut M,
+    limit: usize,
+) -> Path<M::Visit>
+where
+    A: AstContext,
+    M: Machine<A>,
+{
+    let mut runner = Runner::new(machine, limit);
+    let end = runner.run_lowered(lowered).err().unwrap_or(End::Return);
+    runner.finish(end)
+}
+
+/// The node of `lowered` that `node` goes to, having evaluated its
+/// condition to `taken` or its variable to `value`, if it has one.
+fn next_node<B, C, V>(
+    lowered: &LoweredCfg<'_, B, C, V>,
+    node: NodeIndex,
+    taken: Option<bool>,
+    value: Option<u64>,
+) -> Option<NodeIndex> {
+    let mut edges = lowered.graph.edges(node);
+    let next = match value {
+        Some(value) => edges
+            .find(|e| matches!(e.weight(), LoweredEdge::Case(vs) if vs.contains(value)))
+            .or_else(|| {
+                let mut edges = lowered.graph.edges(node);
+                edges.find(|e| matches!(e.weight(), LoweredEdge::Default))
+            }),
+        None => edges.find(|e| {
+            matches!(
+                (taken, e.weight()),
+                (None, LoweredEdge::Next)
+                    | (Some(true), LoweredEdge::True)
+                    | (Some(false), LoweredEdge::False)
+            )
+        }),
+    };
+    next.map(|e| e.target())
+}
+
+fn assigned<'a, C>(leaves: &'a [condition::VarRef<'_, C>], values: &[bool]) -> Vec<(&'a C, bool)> {
+    leaves
+        .iter()
+        .map(|l| &**l)
+        .zip(values.iter().cloned())
+        .collect()
+}
+
+/// Every assignment of `leaves` conditions, or `samples` random ones if
+/// there are too many.
+fn assignments(leaves: usize, samples: usize) -> Vec<Vec<bool>> {
+    if leaves <= EXHAUSTIVE_LEAVES {
+        return (0..1_u32 << leaves)
+            .map(|bits| (0..leaves).map(|i| bits >> i & 1 == 1).collect())
+            .collect();
+    }
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    (0..samples)
+        .map(|_| {
+            (0..leaves)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state & 1 == 1
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Runs blocks and records them. Each of its `run_*` methods returns
+/// `Err` with how the run ended if it did, or `Ok` if it fell through.
+struct Runner<'m, A: AstContext, M: Machine<A>> {
+    machine: &'m mut M,
+    visits: Vec<M::Visit>,
+    limit: usize,
+}
+
+impl<'m, A: AstContext, M: Machine<A>> Runner<'m, A, M> {
+    fn new(machine: &'m mut M, limit: usize) -> Self {
+        Runner {
+            machine,
+            visits: Vec::new(),
+            limit,
+        }
+    }
+
+    fn finish(self, end: End) -> Path<M::Visit> {
+        Path {
+            visits: self.visits,
+            end,
+        }
+    }
+
+    /// Runs `block`, and returns whether it was recorded.
+    fn visit(&mut self, block: &A::Block) -> Result<bool, End> {
+        match self.machine.run(block) {
+            Some(_) if self.visits.len() == self.limit => Err(End::StepLimit),
+            Some(visit) => {
+                self.visits.push(visit);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn run_graph(&mut self, cfg: &ControlFlowGraph<'_, A>) -> Result<(), End> {
+        let mut node = cfg.entry;
+        let mut silent = 0;
+        loop {
+            let taken = match &cfg.graph[node] {
+                CfgNode::Code(ast) => {
+                    if self.run_code(ast)? {
+                        silent = 0;
+                    }
+                    None
+                }
+                CfgNode::Condition(c) => Some(self.machine.test(c)),
+                CfgNode::Dummy(_) => None,
+            };
+            silent += 1;
+            if silent > 4 * cfg.graph.node_count() + 16 {
+                return Err(End::SilentLoop);
+            }
+            let next = cfg.graph.edges(node).find(|e| {
+                matches!(
+                    (taken, e.weight()),
+                    (None, CfgEdge::True)
+                        | (None, CfgEdge::False)
+                        | (Some(true), CfgEdge::True)
+                        | (Some(false), CfgEdge::False)
+                )
+            });
+            node = next.ok_or(End::Return)?.target();
+        }
+    }
+
+    /// Runs the AST of a code node, and returns whether it ran any block of
+    /// the graph. Code with control flow of its own, e.g. left by
+    /// incremental structuring, is run lowered, which can't tell returning
+    /// from falling off its end.
+    fn run_code(&mut self, ast: &AstNode<'_, A>) -> Result<bool, End> {
+        use self::AstNodeC::*;
+        match ast {
+            BasicBlock(b) => self.visit(b),
+            Seq(seq) => {
+                let mut ran = false;
+                for a in seq {
+                    ran |= self.run_code(a)?;
+                }
+                Ok(ran)
+            }
+            Return => Err(End::Return),
+            TailCall(b) => self.visit(b).and(Err(End::TailCall)),
+            IndirectJump(b) => self.visit(b).and(Err(End::IndirectJump)),
+            Try(b, _) => self.run_code(b),
+            _ => {
+                let before = self.visits.len();
+                self.run_lowered(&ast.to_cfg())?;
+                Ok(self.visits.len() > before)
+            }
+        }
+    }
+
+    fn run_lowered(&mut self, lowered: &Lowered<'_, '_, A>) -> Result<(), End> {
+        let mut node = lowered.entry;
+        let mut silent = 0;
+        loop {
+            let mut value = None;
+            let taken = match lowered.graph[node] {
+                LoweredNode::Block(b) => {
+                    if self.visit(b)? {
+                        silent = 0;
+                    }
+                    None
+                }
+                LoweredNode::Cond(c) => {
+                    let machine = &mut *self.machine;
+                    Some(c.eval(&mut |leaf| machine.test(leaf)))
+                }
+                LoweredNode::Switch(v) => {
+                    value = Some(self.machine.value(v));
+                    None
+                }
+                LoweredNode::TailCall(b) => return self.visit(b).and(Err(End::TailCall)),
+                LoweredNode::IndirectJump(b) => return self.visit(b).and(Err(End::IndirectJump)),
+                LoweredNode::Nop => None,
+                LoweredNode::Exit => return Ok(()),
+            };
+            silent += 1;
+            if silent > 4 * lowered.graph.node_count() + 16 {
+                return Err(End::SilentLoop);
+            }
+            node = next_node(lowered, node, taken, value).ok_or(End::DanglingGoto)?;
+        }
+    }
+}
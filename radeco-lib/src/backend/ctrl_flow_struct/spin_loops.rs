@@ -0,0 +1,105 @@
+//! Finds the loops that busy-wait, see [`SpinLoops`].
+
+use super::ast::AstNode;
+use super::matchers::{Annotation, IdiomMatcher, MatchCtx, MatchNode};
+
+/// Matches the loops that only test their condition over and over: those
+/// with an empty body, and those whose body is a single block that the
+/// context says [only loads again](super::matchers::MatchOracle::is_pure_reread)
+/// what the condition tests, as in `while (!*flag) ;`.
+///
+/// The loops are matched once they are refined, so it is a `while` loop
+/// that shows the condition being waited on, if there is one.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SpinLoops;
+
+impl IdiomMatcher for SpinLoops {
+    fn try_match(&self, node: &MatchNode, ctx: &MatchCtx) -> Option<Annotation> {
+        use self::AstNode::*;
+        let spins = match node {
+            Loop(_, box BasicBlock(b)) => ctx.is_pure_reread(*b),
+            Loop(_, body) => is_empty(body),
+            _ => false,
+        };
+        if spins {
+            Some(Annotation::SpinWait)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `ast` does nothing at all.
+fn is_empty<B, C, V>(ast: &AstNode<B, C, V>) -> bool {
+    match ast {
+        AstNode::Seq(seq) => seq.iter().all(is_empty),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::ast::LoopType;
+    use crate::backend::ctrl_flow_struct::decisions::AstNodeId;
+    use crate::backend::ctrl_flow_struct::matchers::{self, MatchOracle};
+
+    type Ast = AstNode<&'static str, &'static str, ()>;
+
+    /// Knows the blocks `r = *p` and the like to only load.
+    struct Loads;
+
+    impl MatchOracle<&'static str, &'static str, ()> for Loads {
+        fn is_pure_reread(&self, block: &&str) -> bool {
+            block.contains(" = *")
+        }
+    }
+
+    fn spin_waits(ast: &Ast) -> Vec<AstNodeId> {
+        matchers::run(ast, &Loads, &[&SpinLoops])
+            .into_iter()
+            .map(|(id, a)| {
+                assert_eq!(a, Annotation::SpinWait);
+                id
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_body() {
+        // while (!flag) ;
+        let ast: Ast = Seq(vec![
+            BasicBlock("a"),
+            Loop(LoopType::PreChecked("!flag"), Box::new(Seq(Vec::new()))),
+        ]);
+        assert_eq!(spin_waits(&ast), vec![AstNodeId(2)]);
+        assert_eq!(Annotation::SpinWait.to_string(), "spin-wait");
+
+        let ast: Ast = Loop(LoopType::Endless, Box::new(Seq(vec![Seq(Vec::new())])));
+        assert_eq!(spin_waits(&ast), vec![AstNodeId(0)]);
+    }
+
+    #[test]
+    fn reread_body() {
+        // do { r = *flag; } while (!r);
+        let ast: Ast = Loop(
+            LoopType::PostChecked("!r"),
+            Box::new(BasicBlock("r = *flag")),
+        );
+        assert_eq!(spin_waits(&ast), vec![AstNodeId(0)]);
+
+        // more than the load, or something else than a load
+        let asts: Vec<Ast> = vec![
+            Loop(
+                LoopType::PostChecked("!r"),
+                Box::new(Seq(vec![BasicBlock("r = *flag"), BasicBlock("f()")])),
+            ),
+            Loop(LoopType::PostChecked("!r"), Box::new(BasicBlock("r = f()"))),
+            Loop(LoopType::Endless, Box::new(Break)),
+        ];
+        for ast in asts {
+            assert_eq!(spin_waits(&ast), Vec::new(), "{:?}", ast);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Ids for the nodes of an AST that stay the same across runs, for tools
+//! that store what their users say about the nodes, like names, comments
+//! and bookmarks, see [`StableIds`].
+//!
+//! An [`AstNodeId`] is the index of a node in the preorder of its AST, so
+//! any node added or removed before it gives it another one. A
+//! [`StableId`] is derived from where the node comes from instead: it is a
+//! hash of the kind of the node, the addresses of the blocks in it, as
+//! [`Provenance`] tells them, and of the branches it tests, and how many
+//! nodes before it in the preorder have the same kind and addresses. A node
+//! without any addresses, like a `break`, is told apart by its parent
+//! instead: its hash is of its kind, the id of its parent, and how many
+//! children of its parent before it have the same kind.
+//!
+//! Hence:
+//!  - the same input structured with the same options gives the same ids,
+//!    in any run and on any platform, and printing the AST doesn't change
+//!    them;
+//!  - where the input changes, e.g. a block grows, only the nodes with the
+//!    changed block in them get new ids, along with the nodes without
+//!    addresses below them, and those after them with the same kind and
+//!    addresses;
+//!  - structured with other options, a node with the same kind and
+//!    addresses as before, e.g. a block, keeps its id too.
+
+use super::ast::{AstNode, LoopType};
+use super::ast_path::AstPath;
+use super::decisions::AstNodeId;
+use super::provenance::{AddrSet, Provenance};
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// The id of a node of an AST, see the [module docs](self).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StableId(pub u64);
+
+impl fmt::Display for StableId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// The [`StableId`] of each node of an AST.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StableIds {
+    /// by [`AstNodeId`]
+    ids: Vec<StableId>,
+}
+
+impl StableIds {
+    /// The ids of the nodes of `ast`, whose blocks and conditions come from
+    /// where `prov` tells.
+    pub fn new<B, C, V, P>(prov: &P, ast: &AstNode<B, C, V>) -> Self
+    where
+        P: Provenance<B, C>,
+    {
+        let mut addrs = Vec::new();
+        addrs_in(prov, ast, &mut addrs);
+        let mut ret = StableIds { ids: Vec::new() };
+        ret.assign(ast, None, &addrs, &mut HashMap::new());
+        ret
+    }
+
+    /// The id of the node `id`, if there is one.
+    pub fn get(&self, id: AstNodeId) -> Option<StableId> {
+        self.ids.get(id.0).cloned()
+    }
+
+    /// The node with the id `id`, if there is one.
+    pub fn find(&self, id: StableId) -> Option<AstNodeId> {
+        self.ids.iter().position(|&i| i == id).map(AstNodeId)
+    }
+
+    /// The path in `ast`, the AST the ids are of, to the node with the id
+    /// `id`, if there is one.
+    pub fn path<B, C, V>(&self, ast: &AstNode<B, C, V>, id: StableId) -> Option<AstPath> {
+        AstPath::of(ast, self.find(id)?)
+    }
+
+    /// The id of each node, in preorder.
+    pub fn iter(&self) -> impl Iterator<Item = (AstNodeId, StableId)> + '_ {
+        self.ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (AstNodeId(i), id))
+    }
+
+    /// Gives `ast`, the child of the node with the id `parent` if it has a
+    /// parent, and its descendants their ids. `addrs` are those of each
+    /// node, by [`AstNodeId`], and `seen` counts the nodes with each hash
+    /// so far.
+    fn assign<B, C, V>(
+        &mut self,
+        ast: &AstNode<B, C, V>,
+        parent: Option<StableId>,
+        addrs: &[AddrSet],
+        seen: &mut HashMap<u64, u64>,
+    ) {
+        let mut hash = Fnv::new();
+        hash.bytes(kind(ast).as_bytes());
+        let own = &addrs[self.ids.len()];
+        if own.is_empty() {
+            hash.u64(parent.map_or(0, |p| p.0));
+        } else {
+            for r in own.ranges() {
+                hash.u64(r.start);
+                hash.u64(r.end);
+            }
+        }
+        let ordinal = seen.entry(hash.0).or_insert(0);
+        hash.u64(*ordinal);
+        *ordinal += 1;
+        let id = StableId(hash.0);
+        self.ids.push(id);
+        for c in children(ast) {
+            self.assign(c, Some(id), addrs, seen);
+        }
+    }
+}
+
+/// The nodes under `ast`, in the order of [`decisions::preorder`](super::decisions::preorder).
+fn children<B, C, V>(ast: &AstNode<B, C, V>) -> Vec<&AstNode<B, C, V>> {
+    use self::AstNode::*;
+    match ast {
+        Seq(seq) => seq.iter().collect(),
+        Cond(_, t, oe) => Some(&**t).into_iter().chain(oe.as_deref()).collect(),
+        Loop(_, b) | For(_, _, _, b) | Try(b, _) => vec![&**b],
+        Switch(_, cases, default) => cases
+            .iter()
+            .map(|(_, a)| a)
+            .chain(Some(&**default))
+            .collect(),
+        Unstructured(members) => members.iter().map(|(_, a)| a).collect(),
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_)
+        | Label(_) => Vec::new(),
+    }
+}
+
+fn kind<B, C, V>(ast: &AstNode<B, C, V>) -> &'static str {
+    use self::AstNode::*;
+    match ast {
+        BasicBlock(_) => "block",
+        Seq(_) => "seq",
+        Cond(..) => "if",
+        Loop(LoopType::PreChecked(_), _) => "while",
+        Loop(LoopType::PostChecked(_), _) => "do_while",
+        Loop(LoopType::Endless, _) => "endless",
+        For(..) => "for",
+        Switch(..) => "switch",
+        Break => "break",
+        Continue => "continue",
+        Return => "return",
+        TailCall(_) => "tail_call",
+        IndirectJump(_) => "indirect_jump",
+        Goto(_) => "goto",
+        Label(_) => "label",
+        Try(..) => "try",
+        Unstructured(_) => "unstructured",
+    }
+}
+
+/// Appends the addresses of `ast` and its descendants, in preorder, to
+/// `out`: those of the blocks in each, and of the branches it tests itself.
+/// Returns those of `ast`.
+fn addrs_in<B, C, V, P>(prov: &P, ast: &AstNode<B, C, V>, out: &mut Vec<AddrSet>) -> AddrSet
+where
+    P: Provenance<B, C>,
+{
+    use self::AstNode::*;
+    let i = out.len();
+    out.push(AddrSet::new());
+    let mut addrs = AddrSet::new();
+    let mut block = |b| {
+        if let Some(r) = prov.block_range(b) {
+            addrs.insert(r);
+        }
+    };
+    let cond = match ast {
+        BasicBlock(b) | TailCall(b) | IndirectJump(b) => {
+            block(b);
+            None
+        }
+        For(i, c, u, _) => {
+            block(i);
+            block(u);
+            Some(c)
+        }
+        Cond(c, _, _) | Loop(LoopType::PreChecked(c), _) | Loop(LoopType::PostChecked(c), _) => {
+            Some(c)
+        }
+        _ => None,
+    };
+    if let Some(a) = cond.and_then(|c| prov.cond_addr(c)) {
+        addrs.insert(a..a + 1);
+    }
+    for c in children(ast) {
+        addrs.union_with(&addrs_in(prov, c, out));
+    }
+    out[i] = addrs.clone();
+    addrs
+}
+
+/// The 64-bit FNV-1a hash, which unlike the hasher of the standard library
+/// is the same in each version of it.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn u64(&mut self, x: u64) {
+        self.bytes(&x.to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::decisions;
+    use crate::backend::ctrl_flow_struct::from_r2::{self, R2BasicBlock, R2Provenance};
+    use crate::backend::ctrl_flow_struct::provenance;
+    use crate::backend::ctrl_flow_struct::StructuringOptions;
+
+    use std::collections::HashSet;
+    use std::fs;
+
+    fn block(addr: u64, size: u64, jump: Option<u64>, fail: Option<u64>) -> R2BasicBlock {
+        R2BasicBlock {
+            addr,
+            size,
+            jump,
+            fail,
+            cases: Vec::new(),
+            default: None,
+            unresolved_jump: false,
+            esil: Vec::new(),
+        }
+    }
+
+    /// `if (a) b; if (c) d; while (e) ; return;`, with `d` of `d_size`
+    /// bytes
+    fn blocks(d_size: u64) -> Vec<R2BasicBlock> {
+        vec![
+            block(0x00, 4, Some(0x08), Some(0x04)),
+            block(0x04, 4, Some(0x08), None),
+            block(0x08, 4, Some(0x20), Some(0x10)),
+            block(0x10, d_size, Some(0x20), None),
+            block(0x20, 4, Some(0x20), Some(0x24)),
+            block(0x24, 4, None, None),
+        ]
+    }
+
+    #[test]
+    fn same_input_same_ids() {
+        let json = fs::read_to_string("test_files/loopy_main_afbj.json").unwrap();
+        let opts = StructuringOptions::default();
+        let a = from_r2::structure(&json, &opts).unwrap().ast;
+        let b = from_r2::structure(&json, &opts).unwrap().ast;
+        let ids = StableIds::new(&R2Provenance, &a);
+        assert_eq!(ids, StableIds::new(&R2Provenance, &b));
+        // each node has its own
+        let distinct: HashSet<_> = ids.iter().map(|(_, id)| id).collect();
+        assert_eq!(distinct.len(), decisions::preorder(&a).len());
+        for (n, id) in ids.iter() {
+            assert_eq!(ids.find(id), Some(n));
+            let path = ids.path(&a, id).unwrap();
+            assert!(std::ptr::eq(
+                a.get(&path).unwrap(),
+                decisions::preorder(&a)[n.0]
+            ));
+        }
+    }
+
+    #[test]
+    fn changed_block() {
+        let opts = StructuringOptions::default();
+        let structure = |d_size| {
+            let blocks = blocks(d_size);
+            from_r2::structure_blocks(&blocks, &Default::default(), &opts)
+                .unwrap()
+                .ast
+        };
+        let (before, after) = (structure(4), structure(8));
+        let after_ids: HashSet<_> = StableIds::new(&R2Provenance, &after)
+            .iter()
+            .map(|(_, id)| id)
+            .collect();
+        // the nodes without `d` in them keep their ids, and the others,
+        // along with the root, which has all of them, get new ones
+        let mut kept = 0;
+        for (n, id) in StableIds::new(&R2Provenance, &before).iter() {
+            let covered = provenance::covered(&R2Provenance, decisions::preorder(&before)[n.0]);
+            if covered.contains(0x10) {
+                assert!(!after_ids.contains(&id), "{:?} kept its id", n);
+            } else if !covered.is_empty() {
+                assert!(after_ids.contains(&id), "{:?} got a new id", n);
+                kept += 1;
+            }
+        }
+        // `a`, `b`, `if (a)`, `c`, `e`, the loop and `return`
+        assert!(kept >= 7, "{} kept", kept);
+    }
+}
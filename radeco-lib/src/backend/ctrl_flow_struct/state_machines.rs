@@ -0,0 +1,181 @@
+//! Finds the loops that run a state machine, as control-flow flattening
+//! makes them, see [`StateMachines`].
+
+use super::ast::{AstNode, ValueSet};
+use super::decisions::AstNodeId;
+use super::matchers::{self, Annotation, IdiomMatcher, MatchCtx, MatchNode, MatchOracle, VarId};
+
+use std::fmt;
+
+/// The transitions of a loop around a `Switch` on its state: for each case,
+/// and then the default, the values of the state it handles and the states
+/// it may assign next, sorted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StateMachine {
+    pub transitions: Vec<(ValueSet, Vec<u64>)>,
+}
+
+impl fmt::Display for StateMachine {
+    /// E.g. `state machine: 0 -> 1; 1 -> 2, 3; 4..=max -> exit`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "state machine:")?;
+        for (i, (vs, next)) in self.transitions.iter().enumerate() {
+            let states: Vec<_> = vs
+                .ranges()
+                .iter()
+                .map(|&(lo, hi)| match (lo == hi, hi == u64::MAX) {
+                    (true, _) => lo.to_string(),
+                    (false, true) => format!("{}..=max", lo),
+                    (false, false) => format!("{}..={}", lo, hi),
+                })
+                .collect();
+            let next: Vec<_> = next.iter().map(u64::to_string).collect();
+            let next = if next.is_empty() {
+                "exit".to_owned()
+            } else {
+                next.join(", ")
+            };
+            let sep = if i == 0 { " " } else { "; " };
+            write!(f, "{}{} -> {}", sep, states.join(" | "), next)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the loops of `ast` that are state machines, by their
+/// [`AstNodeId`] in `ast` alone, in pre-order, as [`StateMachines`] finds
+/// them. `assigned(block, var)` is the constant that `block` assigns to
+/// `var`, if any.
+pub fn find<B, C, V, F>(ast: &AstNode<B, C, V>, assigned: F) -> Vec<(AstNodeId, StateMachine)>
+where
+    F: Fn(&B, &V) -> Option<u64>,
+{
+    matchers::run(ast, &Assigned(assigned), &[&StateMachines])
+        .into_iter()
+        .filter_map(|(id, a)| match a {
+            Annotation::StateMachine(sm) => Some((id, sm)),
+            _ => None,
+        })
+        .collect()
+}
+
+struct Assigned<F>(F);
+
+impl<B, C, V, F: Fn(&B, &V) -> Option<u64>> MatchOracle<B, C, V> for Assigned<F> {
+    fn assigned_value(&self, block: &B, var: &V) -> Option<u64> {
+        (self.0)(block, var)
+    }
+}
+
+/// Matches the loops that run a state machine, as far as the context
+/// tells which constants blocks assign.
+///
+/// That is a loop whose body is a `Switch` on a variable, maybe between
+/// plain blocks, where at least two of the arms assign the variable a
+/// constant and at least one of those constants is handled by a case, so
+/// that the loop goes on to another state. The structure is left as it is.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StateMachines;
+
+impl IdiomMatcher for StateMachines {
+    fn try_match(&self, node: &MatchNode, ctx: &MatchCtx) -> Option<Annotation> {
+        state_machine(node, ctx).map(Annotation::StateMachine)
+    }
+}
+
+fn state_machine(ast: &MatchNode, ctx: &MatchCtx) -> Option<StateMachine> {
+    use self::AstNode::*;
+    let body = match ast {
+        Loop(_, body) | For(_, _, _, body) => &**body,
+        _ => return None,
+    };
+    let (var, cases, default) = match dispatch(body)? {
+        Switch(var, cases, default) => (*var, cases, default),
+        _ => unreachable!(),
+    };
+    let handled = cases
+        .iter()
+        .fold(ValueSet::empty(), |acc, (vs, _)| acc.union(vs));
+    let arms = cases
+        .iter()
+        .map(|(vs, a)| (vs.clone(), a))
+        .chain(Some((handled.complement(), &**default)));
+
+    let mut transitions = Vec::new();
+    let (mut assigning, mut goes_on) = (0, false);
+    for (vs, a) in arms {
+        let mut next = Vec::new();
+        assigned_in(a, var, ctx, &mut next);
+        next.sort();
+        next.dedup();
+        if !next.is_empty() {
+            assigning += 1;
+            goes_on |= next.iter().any(|&n| handled.contains(n));
+        }
+        if !vs.is_empty() {
+            transitions.push((vs, next));
+        }
+    }
+    if assigning < 2 || !goes_on {
+        return None;
+    }
+    Some(StateMachine { transitions })
+}
+
+/// The `Switch` that `body` is about, if it is one or there is one among
+/// its statements and the others are all blocks.
+fn dispatch<B, C, V>(body: &AstNode<B, C, V>) -> Option<&AstNode<B, C, V>> {
+    use self::AstNode::*;
+    match body {
+        Switch(..) => Some(body),
+        Seq(seq) => {
+            let mut switches = seq.iter().filter(|a| matches!(a, Switch(..)));
+            let switch = switches.next()?;
+            let rest_plain = seq.iter().all(|a| matches!(a, Switch(..) | BasicBlock(_)));
+            if switches.next().is_none() && rest_plain {
+                Some(switch)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Pushes the constants that the blocks of `ast` assign to `var` onto `out`.
+fn assigned_in(ast: &MatchNode, var: VarId, ctx: &MatchCtx, out: &mut Vec<u64>) {
+    use self::AstNode::*;
+    match *ast {
+        BasicBlock(b) | TailCall(b) | IndirectJump(b) => out.extend(ctx.assigned_value(b, var)),
+        Seq(ref seq) => {
+            for a in seq {
+                assigned_in(a, var, ctx, out);
+            }
+        }
+        Cond(_, ref t, ref oe) => {
+            assigned_in(t, var, ctx, out);
+            if let Some(e) = oe {
+                assigned_in(e, var, ctx, out);
+            }
+        }
+        Loop(_, ref b) | Try(ref b, _) => assigned_in(b, var, ctx, out),
+        For(i, _, u, ref b) => {
+            out.extend(ctx.assigned_value(i, var));
+            assigned_in(b, var, ctx, out);
+            out.extend(ctx.assigned_value(u, var));
+        }
+        Switch(_, ref cases, ref default) => {
+            for (_, a) in cases {
+                assigned_in(a, var, ctx, out);
+            }
+            assigned_in(default, var, ctx, out);
+        }
+        Unstructured(ref members) => {
+            for (_, a) in members {
+                assigned_in(a, var, ctx, out);
+            }
+        }
+        Break | Continue | Return | Goto(_) | Label(_) => (),
+    }
+}
@@ -0,0 +1,480 @@
+//! See [`fold`].
+
+use super::ast::{AstNode as AstNodeC, LoopType};
+use super::ast_context::AstContext;
+use super::refinement;
+use super::{AstNode, CondContext, CondVar, Condition};
+
+/// A variable that structuring introduced to tell apart the entries or the
+/// exits of a loop, see `funnel_abnormal_entries` and
+/// `funnel_abnormal_exits`.
+pub(super) struct StructVar<'cd, A: AstContext> {
+    pub(super) var: A::Variable,
+    /// its value before it is first assigned, if it matters
+    pub(super) initial: Option<u64>,
+    /// the condition variables that stand for it being equal to a value,
+    /// with that value
+    pub(super) tests: Vec<(CondVar<'cd, A>, u64)>,
+}
+
+/// What is known of the value of each variable at a point, or `None` if
+/// the point can't be reached.
+type Values = Option<Vec<Option<u64>>>;
+
+fn join(a: Values, b: Values) -> Values {
+    match (a, b) {
+        (None, v) | (v, None) => v,
+        (Some(a), Some(b)) => Some(
+            a.into_iter()
+                .zip(b)
+                .map(|(x, y)| if x == y { x } else { None })
+                .collect(),
+        ),
+    }
+}
+
+/// Folds the tests of the variables in `vars` that have the same outcome
+/// on every path to them, then takes out the assignments to those of the
+/// variables that are no longer tested anywhere in `ast`. `copied[i]` tells
+/// whether `dedup_conds` copied a test of `vars[i]` into a boolean
+/// variable, so that it is read where `ast` doesn't show it.
+///
+/// The assignments to the variables must all be blocks that `actx`
+/// recognizes, as [`AstContext::assigned_value`], so that no change to them
+/// goes unseen. An `ast` with a `Goto` in it is left as it is, since the
+/// jump may bring any values along.
+pub(super) fn fold<'cd, A: AstContext>(
+    cctx: CondContext<'cd, A>,
+    actx: &A,
+    vars: &[StructVar<'cd, A>],
+    copied: &[bool],
+    mut ast: AstNode<'cd, A>,
+) -> AstNode<'cd, A> {
+    if vars.is_empty() || super::count_gotos(&ast) > 0 {
+        return ast;
+    }
+    let mut folder = Folder {
+        cctx,
+        actx,
+        vars,
+        loops: Vec::new(),
+        changed: false,
+    };
+    let mut values = Some(vars.iter().map(|sv| sv.initial).collect());
+    folder.walk(&mut ast, &mut values, true);
+    if folder.changed {
+        ast = refinement::simplify_ast_node::<A>(cctx, ast).unwrap_or_default();
+    }
+
+    // taking out an assignment may take out the last test of another
+    // variable, in an `if` that was only around it
+    loop {
+        let mut tested = copied.to_vec();
+        find_tests(&ast, vars, &mut tested);
+        if !remove_assignments(actx, vars, &tested, &mut ast) {
+            return ast;
+        }
+        ast = refinement::simplify_ast_node::<A>(cctx, ast).unwrap_or_default();
+    }
+}
+
+struct Folder<'a, 'cd, A: AstContext> {
+    cctx: CondContext<'cd, A>,
+    actx: &'a A,
+    vars: &'a [StructVar<'cd, A>],
+    /// the values at the `continue`s and at the `break`s of each loop
+    /// around the node being walked, innermost last
+    loops: Vec<(Values, Values)>,
+    /// whether a condition was rewritten
+    changed: bool,
+}
+
+impl<'a, 'cd, A: AstContext> Folder<'a, 'cd, A> {
+    /// Takes `values` from the start of `ast` to its end. With `rewrite`,
+    /// also puts the known values into the conditions of the `if`s.
+    fn walk(&mut self, ast: &mut AstNode<'cd, A>, values: &mut Values, rewrite: bool) {
+        use self::AstNodeC::*;
+        match ast {
+            BasicBlock(b) => self.block(b, values),
+            Seq(seq) => {
+                for a in seq {
+                    self.walk(a, values, rewrite);
+                }
+            }
+            Cond(c, t, oe) => {
+                let known = self.assign(*c, values);
+                if rewrite && known != *c {
+                    *c = known;
+                    self.changed = true;
+                }
+                let mut else_values = if known.is_true() {
+                    None
+                } else {
+                    values.clone()
+                };
+                if known.is_false() {
+                    *values = None;
+                }
+                self.walk(t, values, rewrite);
+                if let Some(e) = oe {
+                    self.walk(e, &mut else_values, rewrite);
+                }
+                *values = join(values.take(), else_values);
+            }
+            Loop(lt, body) => {
+                let (end, cont, brk, head) = self.fixpoint(body, values.clone(), rewrite, |_, v| v);
+                *values = match lt {
+                    LoopType::Endless => brk,
+                    LoopType::PreChecked(_) => join(brk, head),
+                    LoopType::PostChecked(_) => join(brk, join(end, cont)),
+                };
+            }
+            For(i, _, u, body) => {
+                self.block(i, values);
+                let (_, _, brk, head) =
+                    self.fixpoint(body, values.clone(), rewrite, |f: &mut Self, mut v| {
+                        f.block(u, &mut v);
+                        v
+                    });
+                *values = join(brk, head);
+            }
+            Switch(_, cases, default) => {
+                let mut out = None;
+                for (_, a) in cases {
+                    let mut v = values.clone();
+                    self.walk(a, &mut v, rewrite);
+                    out = join(out, v);
+                }
+                self.walk(default, values, rewrite);
+                *values = join(out, values.take());
+            }
+            Try(b, _) => self.walk(b, values, rewrite),
+            // any member may be jumped to, with values we don't follow
+            Unstructured(members) => {
+                for (_, a) in members {
+                    self.walk(a, &mut None, rewrite);
+                }
+                *values = None;
+            }
+            Break | Continue => {
+                let (cont, brk) = match self.loops.last_mut() {
+                    Some(l) => l,
+                    None => return,
+                };
+                let at = if let Break = ast { brk } else { cont };
+                *at = join(at.take(), values.take());
+            }
+            Return | TailCall(_) | IndirectJump(_) | Goto(_) => *values = None,
+            Label(_) => (),
+        }
+    }
+
+    /// Walks the loop `body` from `entry` until the values at its head stop
+    /// changing, with `back` taking the values where the body goes around
+    /// to those at the head. Returns the values at the end of the body, at
+    /// its `continue`s and `break`s, and at the head.
+    fn fixpoint<F>(
+        &mut self,
+        body: &mut AstNode<'cd, A>,
+        entry: Values,
+        rewrite: bool,
+        back: F,
+    ) -> (Values, Values, Values, Values)
+    where
+        F: Fn(&mut Self, Values) -> Values,
+    {
+        let mut head = entry.clone();
+        loop {
+            let (end, cont, _) = self.iterate(body, head.clone(), false);
+            let around = back(self, join(end, cont));
+            let next = join(entry.clone(), around);
+            if next == head {
+                break;
+            }
+            head = next;
+        }
+        let (end, cont, brk) = self.iterate(body, head.clone(), rewrite);
+        (end, cont, brk, head)
+    }
+
+    fn iterate(
+        &mut self,
+        body: &mut AstNode<'cd, A>,
+        mut values: Values,
+        rewrite: bool,
+    ) -> (Values, Values, Values) {
+        self.loops.push((None, None));
+        self.walk(body, &mut values, rewrite);
+        let (cont, brk) = self.loops.pop().unwrap();
+        (values, cont, brk)
+    }
+
+    fn block(&self, block: &A::Block, values: &mut Values) {
+        if let Some(values) = values {
+            for (sv, v) in self.vars.iter().zip(values) {
+                if let Some(n) = self.actx.assigned_value(block, &sv.var) {
+                    *v = Some(n);
+                }
+            }
+        }
+    }
+
+    /// `cond`, with the tests whose outcome `values` tells replaced by it.
+    fn assign(&self, cond: Condition<'cd, A>, values: &Values) -> Condition<'cd, A> {
+        let values = match values {
+            Some(values) => values,
+            None => return cond,
+        };
+        self.cctx.assign(cond, &mut |var| {
+            self.vars.iter().zip(values).find_map(|(sv, v)| {
+                let &(_, n) = sv.tests.iter().find(|&&(t, _)| t == var)?;
+                Some(v.map(|v| v == n))
+            })?
+        })
+    }
+}
+
+/// Marks the variables that are tested in `ast` in `tested`.
+fn find_tests<'cd, A: AstContext>(
+    ast: &AstNode<'cd, A>,
+    vars: &[StructVar<'cd, A>],
+    tested: &mut [bool],
+) {
+    use self::AstNodeC::*;
+    let mut mark = |c: &Condition<'cd, A>| {
+        for var in c.vars() {
+            for (sv, t) in vars.iter().zip(tested.iter_mut()) {
+                *t |= sv.tests.iter().any(|&(test, _)| test == var);
+            }
+        }
+    };
+    match ast {
+        Cond(c, t, oe) => {
+            mark(c);
+            find_tests(t, vars, tested);
+            if let Some(e) = oe {
+                find_tests(e, vars, tested);
+            }
+        }
+        Loop(lt, b) => {
+            if let LoopType::PreChecked(c) | LoopType::PostChecked(c) = lt {
+                mark(c);
+            }
+            find_tests(b, vars, tested);
+        }
+        For(_, c, _, b) => {
+            mark(c);
+            find_tests(b, vars, tested);
+        }
+        Switch(v, cases, default) => {
+            for (sv, t) in vars.iter().zip(tested.iter_mut()) {
+                *t |= sv.var == *v;
+            }
+            for (_, a) in cases {
+                find_tests(a, vars, tested);
+            }
+            find_tests(default, vars, tested);
+        }
+        Seq(seq) => {
+            for a in seq {
+                find_tests(a, vars, tested);
+            }
+        }
+        Try(b, _) => find_tests(b, vars, tested),
+        Unstructured(members) => {
+            for (_, a) in members {
+                find_tests(a, vars, tested);
+            }
+        }
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_)
+        | Label(_) => (),
+    }
+}
+
+/// Empties the blocks of `ast` that assign a variable that isn't `tested`.
+/// Returns whether there were any.
+fn remove_assignments<'cd, A: AstContext>(
+    actx: &A,
+    vars: &[StructVar<'cd, A>],
+    tested: &[bool],
+    ast: &mut AstNode<'cd, A>,
+) -> bool {
+    use self::AstNodeC::*;
+    let go = |a: &mut AstNode<'cd, A>| remove_assignments(actx, vars, tested, a);
+    match ast {
+        BasicBlock(b) => {
+            let dead = vars
+                .iter()
+                .zip(tested)
+                .any(|(sv, &t)| !t && actx.assigned_value(b, &sv.var).is_some());
+            if dead {
+                *ast = Seq(Vec::new());
+            }
+            dead
+        }
+        Seq(seq) => seq.iter_mut().fold(false, |acc, a| go(a) | acc),
+        Cond(_, t, oe) => {
+            let in_then = go(t);
+            match oe {
+                Some(e) => go(e) | in_then,
+                None => in_then,
+            }
+        }
+        Loop(_, b) | For(_, _, _, b) | Try(b, _) => go(b),
+        Switch(_, cases, default) => {
+            let in_cases = cases.iter_mut().fold(false, |acc, (_, a)| go(a) | acc);
+            go(default) | in_cases
+        }
+        Unstructured(members) => members.iter_mut().fold(false, |acc, (_, a)| go(a) | acc),
+        Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::condition::Storage;
+
+    /// Understands the blocks `i = 1` and the like.
+    struct Assigns;
+
+    impl AstContext for Assigns {
+        type Block = &'static str;
+        type Variable = &'static str;
+        type BoolVariable = ();
+        type Condition = &'static str;
+
+        fn assigned_value(&self, block: &&str, var: &&str) -> Option<u64> {
+            block.strip_prefix(var)?.strip_prefix(" = ")?.parse().ok()
+        }
+    }
+
+    type Ast<'cd> = AstNode<'cd, Assigns>;
+
+    /// `loop { a; if (c) { first } b; if (d) { second } } after`, the way
+    /// the exits of a loop are funneled.
+    fn exits<'cd>(
+        cctx: CondContext<'cd, Assigns>,
+        first: Ast<'cd>,
+        second: Ast<'cd>,
+        after: Ast<'cd>,
+    ) -> Ast<'cd> {
+        let c = cctx.mk_var(cctx.new_var("c"));
+        let d = cctx.mk_var(cctx.new_var("d"));
+        Seq(vec![
+            Loop(
+                LoopType::Endless,
+                Box::new(Seq(vec![
+                    BasicBlock("a"),
+                    Cond(c, Box::new(first), None),
+                    BasicBlock("b"),
+                    Cond(d, Box::new(second), None),
+                ])),
+            ),
+            after,
+        ])
+    }
+
+    fn exit_1() -> Ast<'static> {
+        Seq(vec![BasicBlock("i = 1"), Break])
+    }
+
+    #[test]
+    fn removable() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let test = cctx.new_var("i == 1");
+        let var = StructVar {
+            var: "i",
+            initial: Some(0),
+            tests: vec![(test, 1)],
+        };
+        let cascade = Cond(
+            cctx.mk_var(test),
+            Box::new(BasicBlock("x")),
+            Some(Box::new(BasicBlock("y"))),
+        );
+
+        // the loop is only ever left with `i` at 1, e.g. once the other exit
+        // was found to be the same
+        let mut ast = exits(cctx, exit_1(), exit_1(), cascade);
+        let expected = match &mut ast {
+            Seq(seq) => Seq(vec![
+                Loop(
+                    LoopType::Endless,
+                    Box::new(Seq(vec![
+                        BasicBlock("a"),
+                        Cond(c_of(&seq[0], 1), Box::new(Break), None),
+                        BasicBlock("b"),
+                        Cond(c_of(&seq[0], 3), Box::new(Break), None),
+                    ])),
+                ),
+                BasicBlock("x"),
+            ]),
+            _ => unreachable!(),
+        };
+        assert_eq!(fold(cctx, &Assigns, &[var], &[false], ast), expected);
+    }
+
+    /// The condition of the `Cond` at `i` in the body of `looping`.
+    fn c_of<'cd>(looping: &Ast<'cd>, i: usize) -> Condition<'cd, Assigns> {
+        match looping {
+            Loop(_, box Seq(body)) => match body[i] {
+                Cond(c, ..) => c,
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn needed() {
+        let cstore = Storage::new();
+        let cctx = cstore.cctx();
+        let test = cctx.new_var("i == 1");
+        let var = || StructVar {
+            var: "i",
+            initial: Some(0),
+            tests: vec![(test, 1)],
+        };
+        let cascade = || {
+            Cond(
+                cctx.mk_var(test),
+                Box::new(BasicBlock("x")),
+                Some(Box::new(BasicBlock("y"))),
+            )
+        };
+
+        // the loop may be left either way
+        let ast = exits(cctx, exit_1(), Break, cascade());
+        assert_eq!(fold(cctx, &Assigns, &[var()], &[false], ast.clone()), ast);
+
+        // a test that `dedup_conds` copied into a boolean variable doesn't
+        // show in the AST, but still reads `i`
+        let ast = exits(cctx, exit_1(), exit_1(), cascade());
+        let folded = fold(cctx, &Assigns, &[var()], &[true], ast.clone());
+        match (folded, ast) {
+            (Seq(folded), Seq(ast)) => {
+                assert_eq!(folded[0], ast[0]);
+                assert_eq!(folded[1], BasicBlock("x"));
+            }
+            _ => unreachable!(),
+        }
+
+        // `i` is only assigned on the way around the loop, so it is 0 the
+        // first time it is tested, but not after
+        let ast = Loop(
+            LoopType::Endless,
+            Box::new(Seq(vec![
+                Cond(
+                    cctx.mk_var(test),
+                    Box::new(Seq(vec![BasicBlock("x"), Break])),
+                    None,
+                ),
+                BasicBlock("i = 1"),
+            ])),
+        );
+        assert_eq!(fold(cctx, &Assigns, &[var()], &[false], ast.clone()), ast);
+    }
+}
@@ -1,18 +1,28 @@
-use super::ast::LoopType;
+use super::ast::{LoopType, ValueSet};
 use super::condition;
 use super::*;
 
 use super::CfgEdge::False as CEFalse;
 use super::CfgEdge::True as CETrue;
 
+use fixedbitset::FixedBitSet;
+
+use std::env;
+use std::fs;
+use std::ops::Range;
+use std::thread;
+
 // NOTE: If a loop dominates the exit node, the algorithm tends to "suck" the
 // `return` up into the loop body, which may end up not testing what you wanted.
 // To work around this, simply add an additional branch at the entry node that
 // just jumps to the exit.
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 struct StringAst {
     vars: Vec<Option<u64>>,
+    /// whether to name the variables like the namer does, rather than
+    /// `i_N` and `c_N`
+    named: bool,
 }
 
 impl AstContext for StringAst {
@@ -20,6 +30,43 @@ impl AstContext for StringAst {
     type Variable = String;
     type BoolVariable = String;
     type Condition = String;
+
+    fn assigned_value(&self, block: &String, var: &String) -> Option<u64> {
+        block.strip_prefix(&format!("{} = ", var))?.parse().ok()
+    }
+
+    fn may_modify(&self, block: &String, cond: &String) -> bool {
+        block.starts_with(&format!("{} = ", cond))
+    }
+
+    fn describe_cond(&self, cond: &String) -> Option<String> {
+        Some(cond.clone())
+    }
+
+    fn constant_value(&self, cond: &String) -> Option<bool> {
+        cond.parse().ok()
+    }
+
+    /// The blocks written `0x100: ...` are at that address, and those of
+    /// lines like it span from the first to the last.
+    fn block_range(&self, block: &String) -> Option<Range<u64>> {
+        let addr = |line: &str| {
+            let (addr, _) = line.split_once(": ")?;
+            u64::from_str_radix(addr.strip_prefix("0x")?, 16).ok()
+        };
+        Some(addr(block.lines().next()?)?..addr(block.lines().last()?)? + 1)
+    }
+
+    /// The lines `test ...`, after their address if any, only compute what
+    /// the branch after the block tests.
+    fn has_side_effects(&self, block: &String) -> bool {
+        !block.lines().all(is_test)
+    }
+}
+
+fn is_test(line: &str) -> bool {
+    let code = line.split_once(": ").map_or(line, |(_, code)| code);
+    code.starts_with("test ")
 }
 
 impl AstContextMut for StringAst {
@@ -41,6 +88,30 @@ impl AstContextMut for StringAst {
         ret
     }
 
+    fn mk_named_var(&mut self, name: &str) -> String {
+        if !self.named {
+            return self.mk_fresh_var();
+        }
+        self.vars.push(None);
+        name.to_owned()
+    }
+
+    fn mk_named_var_zeroed(&mut self, name: &str) -> String {
+        if !self.named {
+            return self.mk_fresh_var_zeroed();
+        }
+        self.vars.push(Some(0));
+        name.to_owned()
+    }
+
+    fn mk_named_bool_var(&mut self, name: &str) -> String {
+        if !self.named {
+            return self.mk_fresh_bool_var();
+        }
+        self.vars.push(None);
+        name.to_owned()
+    }
+
     fn mk_cond_equals(&mut self, var: &String, val: u64) -> String {
         format!("{} == {}", var, val)
     }
@@ -56,6 +127,31 @@ impl AstContextMut for StringAst {
     fn mk_bool_var_assign(&mut self, var: &String, val: &String) -> String {
         format!("{} = {}", var, val)
     }
+
+    fn clone_block(&mut self, block: &String) -> Option<String> {
+        Some(block.clone())
+    }
+
+    fn clone_cond(&mut self, cond: &String) -> Option<String> {
+        Some(cond.clone())
+    }
+
+    /// The blocks with a `// ...` comment lose it when merged.
+    fn merge_blocks(&mut self, first: &String, second: &String) -> Option<String> {
+        if first.contains("//") || second.contains("//") {
+            return None;
+        }
+        Some(format!("{}\n{}", first, second))
+    }
+
+    /// The `test ...` lines of a latch run in its condition, before it.
+    fn split_latch(&mut self, block: &String, cond: &String) -> Option<(String, String)> {
+        let (tests, work): (Vec<_>, Vec<_>) = block.lines().partition(|l| is_test(l));
+        if tests.is_empty() {
+            return None;
+        }
+        Some((work.join("\n"), format!("{}; {}", tests.join("; "), cond)))
+    }
 }
 
 #[test]
@@ -365,7 +461,6 @@ fn ast_nmg_r3() {
 }
 
 #[test]
-#[ignore] // TODO
 fn ast_switchy() {
     /*
      * switch (n) {
@@ -427,19 +522,32 @@ fn ast_switchy() {
     graph.add_edge(n3, exit, CETrue);
 
     let actx = StringAst::default();
-    let cfg = ControlFlowGraph::new(graph, c1, cctx, actx);
+    let mut cfg = ControlFlowGraph::new(graph, c1, cctx, actx);
+    let n = || "n".to_owned();
+    cfg.set_value_set(c1, n(), ValueSet::single(7));
+    cfg.set_value_set(c2, n(), ValueSet::range(0, 7));
+    cfg.set_value_set(c3, n(), ValueSet::single(88));
+    cfg.set_value_set(c4, n(), ValueSet::single(1));
+    cfg.set_value_set(c5, n(), ValueSet::single(98));
+    cfg.set_value_set(c6, n(), ValueSet::single(4).complement());
+    cfg.set_value_set(c7, n(), ValueSet::single(34));
     let ast = cfg.structure_whole().0;
     println!("{:#?}", ast);
 
     use self::AstNodeC::*;
-    // XXX: need actual value set impl
     assert_eq!(
         Seq(vec![
             Switch(
                 "n".to_owned(),
                 vec![
-                    ((), BasicBlock("n1".to_owned())),
-                    ((), BasicBlock("n2".to_owned())),
+                    (
+                        vec![1, 4, 7, 98].into_iter().collect(),
+                        BasicBlock("n1".to_owned()),
+                    ),
+                    (
+                        vec![34, 88].into_iter().collect(),
+                        BasicBlock("n2".to_owned())
+                    ),
                 ],
                 Box::new(BasicBlock("n3".to_owned())),
             ),
@@ -449,6 +557,693 @@ fn ast_switchy() {
     );
 }
 
+#[test]
+fn ast_switch_dispatch() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_c0 = cond_s(cctx, "x == 0");
+    let v_c1 = cond_s(cctx, "x == 1");
+    let v_c2 = cond_s(cctx, "x == 2");
+
+    let mut graph = StableDiGraph::new();
+    let c0 = graph.add_node(cnode(v_c0));
+    let c1 = graph.add_node(cnode(v_c1));
+    let c2 = graph.add_node(cnode(v_c2));
+    let n0 = graph.add_node(node("n0"));
+    let n1 = graph.add_node(node("n1"));
+    let n2 = graph.add_node(node("n2"));
+    let nd = graph.add_node(node("nd"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(c0, n0, CETrue);
+    graph.add_edge(c0, c1, CEFalse);
+    graph.add_edge(c1, n1, CETrue);
+    graph.add_edge(c1, c2, CEFalse);
+    graph.add_edge(c2, n2, CETrue);
+    graph.add_edge(c2, nd, CEFalse);
+    graph.add_edge(n0, exit, CETrue);
+    graph.add_edge(n1, exit, CETrue);
+    graph.add_edge(n2, exit, CETrue);
+    graph.add_edge(nd, exit, CETrue);
+
+    let actx = StringAst::default();
+    let mut cfg = ControlFlowGraph::new(graph, c0, cctx, actx);
+    cfg.set_value_set(c0, "x".to_owned(), ValueSet::single(0));
+    cfg.set_value_set(c1, "x".to_owned(), ValueSet::single(1));
+    cfg.set_value_set(c2, "x".to_owned(), ValueSet::single(2));
+    let ast = cfg.structure_whole().0;
+    println!("{:#?}", ast);
+
+    use self::AstNodeC::*;
+    assert_eq!(
+        Seq(vec![
+            Switch(
+                "x".to_owned(),
+                vec![
+                    (ValueSet::single(0), BasicBlock("n0".to_owned())),
+                    (ValueSet::single(1), BasicBlock("n1".to_owned())),
+                    (ValueSet::single(2), BasicBlock("n2".to_owned())),
+                ],
+                // everything else
+                Box::new(BasicBlock("nd".to_owned())),
+            ),
+            BasicBlock("return".to_owned()),
+        ]),
+        ast
+    );
+}
+
+#[test]
+fn ast_switch_ranges() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let mut graph = StableDiGraph::new();
+    let c0 = graph.add_node(cnode(cond_s(cctx, "x <= 9")));
+    let c1 = graph.add_node(cnode(cond_s(cctx, "x == 12 || 14 <= x <= 20")));
+    let c2 = graph.add_node(cnode(cond_s(cctx, "x <= 11")));
+    let n0 = graph.add_node(node("n0"));
+    let n1 = graph.add_node(node("n1"));
+    let n2 = graph.add_node(node("n2"));
+    let nd = graph.add_node(node("nd"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(c0, n0, CETrue);
+    graph.add_edge(c0, c1, CEFalse);
+    graph.add_edge(c1, n1, CETrue);
+    graph.add_edge(c1, c2, CEFalse);
+    graph.add_edge(c2, n2, CETrue);
+    graph.add_edge(c2, nd, CEFalse);
+    for &n in &[n0, n1, n2, nd] {
+        graph.add_edge(n, exit, CETrue);
+    }
+
+    let mut cfg = ControlFlowGraph::new(graph, c0, cctx, StringAst::default());
+    let wide: ValueSet = iter::once(12).chain(14..=20).collect();
+    cfg.set_value_set(c0, "x".to_owned(), ValueSet::range(0, 9));
+    cfg.set_value_set(c1, "x".to_owned(), wide.clone());
+    // only 10 and 11 are left once `c0` fails
+    cfg.set_value_set(c2, "x".to_owned(), ValueSet::range(0, 11));
+    let (ast, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+    println!("{:#?}", ast);
+
+    // the cases are in the order of their smallest values
+    use self::AstNodeC::*;
+    assert_eq!(
+        ast,
+        Seq(vec![
+            Switch(
+                "x".to_owned(),
+                vec![
+                    (ValueSet::range(0, 9), BasicBlock("n0".to_owned())),
+                    (ValueSet::range(10, 11), BasicBlock("n2".to_owned())),
+                    (wide.clone(), BasicBlock("n1".to_owned())),
+                ],
+                Box::new(BasicBlock("nd".to_owned())),
+            ),
+            BasicBlock("return".to_owned()),
+        ])
+    );
+    assert!(report.overlapping_cases.is_empty());
+    assert_eq!(
+        crate::backend::lang_c::c_writer::case_constants(&wide),
+        vec!["12", "14 ... 20"]
+    );
+}
+
+/// A dispatch on `x`, like in `ast_switch_dispatch`, and then a `while`
+/// loop.
+fn dispatch_then_loop<'cd>(
+    cctx: condition::Context<'cd, String>,
+) -> ControlFlowGraph<'cd, StringAst> {
+    let mut graph = StableDiGraph::new();
+    let c0 = graph.add_node(cnode(cond_s(cctx, "x == 0")));
+    let c1 = graph.add_node(cnode(cond_s(cctx, "x == 1")));
+    let n0 = graph.add_node(node("n0"));
+    let n1 = graph.add_node(node("n1"));
+    let nd = graph.add_node(node("nd"));
+    let w = graph.add_node(cnode(cond_s(cctx, "w")));
+    let body = graph.add_node(node("body"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(c0, n0, CETrue);
+    graph.add_edge(c0, c1, CEFalse);
+    graph.add_edge(c1, n1, CETrue);
+    graph.add_edge(c1, nd, CEFalse);
+    for &n in &[n0, n1, nd] {
+        graph.add_edge(n, w, CETrue);
+    }
+    graph.add_edge(w, body, CETrue);
+    graph.add_edge(w, exit, CEFalse);
+    graph.add_edge(body, w, CETrue);
+
+    let mut cfg = ControlFlowGraph::new(graph, c0, cctx, StringAst::default());
+    cfg.set_value_set(c0, "x".to_owned(), ValueSet::single(0));
+    cfg.set_value_set(c1, "x".to_owned(), ValueSet::single(1));
+    cfg
+}
+
+/// The number of nodes of `ast` that `pred` holds for.
+fn count<B, C, V, F>(ast: &AstNodeC<B, C, V>, pred: &F) -> usize
+where
+    F: Fn(&AstNodeC<B, C, V>) -> bool,
+{
+    use self::AstNodeC::*;
+    let children = match ast {
+        Seq(seq) => seq.iter().map(|a| count(a, pred)).sum(),
+        Cond(_, t, e) => count(t, pred) + e.as_ref().map_or(0, |e| count(e, pred)),
+        Loop(_, body) | Try(body, _) => count(body, pred),
+        Switch(_, cases, default) => {
+            cases.iter().map(|(_, a)| count(a, pred)).sum::<usize>() + count(default, pred)
+        }
+        _ => 0,
+    };
+    children + pred(ast) as usize
+}
+
+#[test]
+fn structuring_options() {
+    use self::AstNodeC::*;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let shape = |opts: &StructuringOptions| {
+        let ast = dispatch_then_loop(cctx).structure_whole_with(opts).0;
+        (
+            count(&ast, &|a| matches!(a, Switch(..))),
+            count(&ast, &|a| matches!(a, Cond(_, _, Some(_)))),
+            count(&ast, &|a| matches!(a, Loop(LoopType::PreChecked(_), _))),
+            count(&ast, &|a| matches!(a, Loop(LoopType::Endless, _))),
+        )
+    };
+
+    assert_eq!(shape(&StructuringOptions::default()), (1, 0, 1, 0));
+    assert_eq!(shape(&StructuringOptions::strict()), (1, 0, 1, 0));
+    let no_switches = StructuringOptions::default().recover_switches(false);
+    // an `if`-`else` cascade instead
+    assert_eq!(shape(&no_switches), (0, 2, 1, 0));
+    // and without it, an `if` for each case, and in the loop, which then
+    // becomes a `do`-`while` around an `if`
+    assert_eq!(shape(&no_switches.refine_conditionals(false)), (0, 0, 0, 0));
+    assert_eq!(shape(&StructuringOptions::fast()), (0, 0, 0, 0));
+    assert_eq!(
+        shape(&StructuringOptions::default().refine_loops(false)),
+        (1, 0, 0, 1)
+    );
+
+    // a graph that `new` asserts against is only rejected up front when
+    // checking
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("a"));
+    let b = graph.add_node(node("b"));
+    graph.add_edge(entry, b, CETrue);
+    graph.add_edge(b, entry, CETrue);
+    let res = panic::catch_unwind(AssertUnwindSafe(|| {
+        unchecked_cfg(graph, entry, cctx).structure_whole_with(&StructuringOptions::strict())
+    }));
+    let err = res.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<String>().unwrap(),
+        "validate: node 0 is the entry or a landing pad, but has predecessors"
+    );
+}
+
+/// `s; switch (x) { ... }; return;`, as a chain of tests of `x` against
+/// `cases`, each going to the node of that name, the last of which goes on
+/// to `default`, or straight to the `return` without one.
+fn jump_table<'cd>(
+    cctx: CondContext<'cd, StringAst>,
+    cases: &[(ValueSet, &str)],
+    default: Option<&str>,
+) -> ControlFlowGraph<'cd, StringAst> {
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("s"));
+    let exit = graph.add_node(node("return"));
+    let mut targets = HashMap::new();
+    let mut target = |graph: &mut StableDiGraph<_, _>, name: &str| {
+        *targets.entry(name.to_owned()).or_insert_with(|| {
+            let t = graph.add_node(node(name));
+            graph.add_edge(t, exit, CETrue);
+            t
+        })
+    };
+    let mut tests = Vec::new();
+    let mut prev = (entry, CETrue);
+    for (vs, name) in cases {
+        let test = graph.add_node(cnode(cond_s(cctx, &format!("x in {:?}", vs))));
+        graph.add_edge(prev.0, test, prev.1);
+        let t = target(&mut graph, name);
+        graph.add_edge(test, t, CETrue);
+        tests.push((test, vs.clone()));
+        prev = (test, CEFalse);
+    }
+    let d = default.map_or(exit, |name| target(&mut graph, name));
+    graph.add_edge(prev.0, d, prev.1);
+
+    let mut cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    for (test, vs) in tests {
+        cfg.set_value_set(test, "x".to_owned(), vs);
+    }
+    cfg
+}
+
+#[test]
+fn switch_default() {
+    use self::AstNodeC::*;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let bb = |s: &str| BasicBlock(s.to_owned());
+    let switch = |cases: Vec<(ValueSet, &str)>, default| {
+        Seq(vec![
+            bb("s"),
+            Switch(
+                "x".to_owned(),
+                cases.into_iter().map(|(vs, s)| (vs, bb(s))).collect(),
+                Box::new(default),
+            ),
+            bb("return"),
+        ])
+    };
+
+    // the default is the target of the last test failing, even though a
+    // case has more values
+    let big = ValueSet::range(0, u64::MAX - 10);
+    let last = ValueSet::single(u64::MAX - 5);
+    let cases = vec![(big.clone(), "a"), (last.clone(), "b")];
+    let ast = jump_table(cctx, &cases, Some("d")).structure_whole().0;
+    assert_eq!(ast, switch(cases, bb("d")));
+
+    // without a default, the other values go straight on to the `return`
+    let cases = vec![
+        (ValueSet::single(0), "a"),
+        (ValueSet::single(1), "b"),
+        (ValueSet::single(2), "c"),
+    ];
+    let ast = jump_table(cctx, &cases, None).structure_whole().0;
+    assert_eq!(ast, switch(cases, Seq(Vec::new())));
+
+    // the default also runs for some cases, which go with it
+    let cases = vec![
+        (ValueSet::single(0), "a"),
+        (ValueSet::single(1), "b"),
+        (ValueSet::single(2), "c"),
+        (ValueSet::single(3), "a"),
+    ];
+    let ast = jump_table(cctx, &cases, Some("a")).structure_whole().0;
+    let cases = vec![(ValueSet::single(1), "b"), (ValueSet::single(2), "c")];
+    assert_eq!(ast, switch(cases, bb("a")));
+}
+
+#[test]
+fn switch_default_ignores_other_guards() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // `s; switch (x) { ... default: d; }; if (x in rest) g(); return;` where
+    // `rest` is exactly the values of the default
+    let big = ValueSet::range(0, u64::MAX - 10);
+    let last = ValueSet::single(u64::MAX - 5);
+    let rest = big.union(&last).complement();
+    let cases = vec![(big, "a"), (last, "b")];
+    let mut cfg = jump_table(cctx, &cases, Some("d"));
+    let exit = cfg
+        .graph
+        .node_indices()
+        .find(|&n| matches!(&cfg.graph[n], CfgNode::Code(AstNodeC::BasicBlock(b)) if b == "return"))
+        .unwrap();
+    let preds: Vec<_> = cfg
+        .graph
+        .edges_directed(exit, Incoming)
+        .map(|e| e.id())
+        .collect();
+    let guard = cfg.graph.add_node(cnode(cond_s(cctx, "x in rest")));
+    for e in preds {
+        let (src, _) = cfg.graph.edge_endpoints(e).unwrap();
+        cfg.graph.remove_edge(e);
+        cfg.graph.add_edge(src, guard, CETrue);
+    }
+    let g = cfg.graph.add_node(node("g"));
+    cfg.graph.add_edge(guard, g, CETrue);
+    cfg.graph.add_edge(guard, exit, CEFalse);
+    cfg.graph.add_edge(g, exit, CETrue);
+    cfg.set_value_set(guard, "x".to_owned(), rest);
+
+    use self::AstNodeC::*;
+    let bb = |s: &str| BasicBlock(s.to_owned());
+    let ast = stringify_conds(cfg.structure_whole().0);
+    assert_eq!(
+        ast,
+        Seq(vec![
+            bb("s"),
+            Switch(
+                "x".to_owned(),
+                cases.into_iter().map(|(vs, s)| (vs, bb(s))).collect(),
+                Box::new(bb("d")),
+            ),
+            Cond(r#""x in rest""#.to_owned(), Box::new(bb("g")), None),
+            bb("return"),
+        ])
+    );
+}
+
+#[test]
+fn switch_default_out_of_range() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // `if (x in in_range) { if (x in nonzero) b; else a; } else d;`, where
+    // the value set of `nonzero` covers the out-of-range values too, so only
+    // the out-of-range edge tells that `d` is the default and not `b`
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("s"));
+    let in_range = graph.add_node(cnode(cond_s(cctx, "x in in_range")));
+    let nonzero = graph.add_node(cnode(cond_s(cctx, "x in nonzero")));
+    let a = graph.add_node(node("a"));
+    let b = graph.add_node(node("b"));
+    let d = graph.add_node(node("d"));
+    let exit = graph.add_node(node("return"));
+    graph.add_edge(entry, in_range, CETrue);
+    graph.add_edge(in_range, nonzero, CETrue);
+    graph.add_edge(in_range, d, CEFalse);
+    graph.add_edge(nonzero, b, CETrue);
+    graph.add_edge(nonzero, a, CEFalse);
+    for &n in &[a, b, d] {
+        graph.add_edge(n, exit, CETrue);
+    }
+    let mk_cfg = || {
+        let mut cfg = ControlFlowGraph::new(graph.clone(), entry, cctx, StringAst::default());
+        let x = || "x".to_owned();
+        cfg.set_value_set(in_range, x(), ValueSet::range(0, u64::MAX - 11));
+        cfg.set_value_set(nonzero, x(), ValueSet::range(1, u64::MAX));
+        cfg
+    };
+
+    use self::AstNodeC::*;
+    let bb = |s: &str| BasicBlock(s.to_owned());
+    let switch = |cases: Vec<(ValueSet, &str)>, default| {
+        Seq(vec![
+            bb("s"),
+            Switch(
+                "x".to_owned(),
+                cases.into_iter().map(|(vs, s)| (vs, bb(s))).collect(),
+                Box::new(bb(default)),
+            ),
+            bb("return"),
+        ])
+    };
+    let d_values = ValueSet::range(u64::MAX - 10, u64::MAX);
+
+    let mut cfg = mk_cfg();
+    cfg.set_out_of_range(in_range, CEFalse);
+    let cases = vec![
+        (ValueSet::single(0), "a"),
+        (ValueSet::range(1, u64::MAX - 11), "b"),
+    ];
+    assert_eq!(cfg.structure_whole().0, switch(cases, "d"));
+
+    // unmarked, the biggest case becomes the default
+    let cases = vec![(ValueSet::single(0), "a"), (d_values, "d")];
+    assert_eq!(mk_cfg().structure_whole().0, switch(cases, "b"));
+}
+
+/// A loop flattened into a dispatcher on `state`, from `state = 0` on:
+/// state 0 goes on to 1, state 1 to 2 or 3 depending on `p`, state 2 back to
+/// 0, and state 3 leaves the loop.
+fn flattened_loop<'cd>(cctx: CondContext<'cd, StringAst>) -> ControlFlowGraph<'cd, StringAst> {
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("state = 0"));
+    let exit = graph.add_node(node("return"));
+    let tests: Vec<_> = (0..4)
+        .map(|i| graph.add_node(cnode(cond_s(cctx, &format!("state == {}", i)))))
+        .collect();
+    graph.add_edge(entry, tests[0], CETrue);
+    for w in tests.windows(2) {
+        graph.add_edge(w[0], w[1], CEFalse);
+    }
+    graph.add_edge(tests[3], exit, CEFalse);
+
+    let case = |graph: &mut StableDiGraph<_, _>, test, blocks: &[&str], next| {
+        let mut prev = (test, CETrue);
+        for b in blocks {
+            let n = graph.add_node(node(b));
+            graph.add_edge(prev.0, n, prev.1);
+            prev = (n, CETrue);
+        }
+        graph.add_edge(prev.0, next, prev.1);
+    };
+    case(&mut graph, tests[0], &["a", "state = 1"], tests[0]);
+    let p = graph.add_node(cnode(cond_s(cctx, "p")));
+    graph.add_edge(tests[1], p, CETrue);
+    case(&mut graph, p, &["state = 2"], tests[0]);
+    let q = graph.add_node(node("state = 3"));
+    graph.add_edge(p, q, CEFalse);
+    graph.add_edge(q, tests[0], CETrue);
+    case(&mut graph, tests[2], &["b", "state = 0"], tests[0]);
+    case(&mut graph, tests[3], &["c"], exit);
+
+    let mut cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    for (i, &t) in tests.iter().enumerate() {
+        cfg.set_value_set(t, "state".to_owned(), ValueSet::single(i as u64));
+    }
+    cfg
+}
+
+#[test]
+fn state_machine_loop() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let (ast, _, report) = flattened_loop(cctx).structure_whole_reported(&Default::default());
+
+    let (id, sm) = match &report.state_machines[..] {
+        [found] => found,
+        found => panic!("not one state machine: {:?}", found),
+    };
+    assert!(matches!(
+        decisions::preorder(&ast)[id.0],
+        AstNodeC::Loop(_, box AstNodeC::Switch(..))
+    ));
+    let transitions = vec![
+        (ValueSet::single(0), vec![1]),
+        (ValueSet::single(1), vec![2, 3]),
+        (ValueSet::single(2), vec![0]),
+        (ValueSet::range(3, u64::MAX), vec![]),
+    ];
+    assert_eq!(sm.transitions, transitions);
+    assert_eq!(
+        sm.to_string(),
+        "state machine: 0 -> 1; 1 -> 2, 3; 2 -> 0; 3..=max -> exit"
+    );
+}
+
+/// Tags every `do`-`while` loop.
+#[derive(Debug)]
+struct DoWhiles;
+
+impl matchers::IdiomMatcher for DoWhiles {
+    fn try_match(
+        &self,
+        node: &matchers::MatchNode,
+        _ctx: &matchers::MatchCtx,
+    ) -> Option<Annotation> {
+        match node {
+            AstNodeC::Loop(LoopType::PostChecked(_), _) => {
+                Some(Annotation::Custom("do-while".to_owned()))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn custom_matcher() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // two `do`-`while` loops in a row
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(cond_s(cctx, "ce")));
+    let n1 = graph.add_node(node("n1"));
+    let c1 = graph.add_node(cnode(cond_s(cctx, "c1")));
+    let n2 = graph.add_node(node("n2"));
+    let c2 = graph.add_node(cnode(cond_s(cctx, "c2")));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, n1, CETrue);
+    graph.add_edge(entry, exit, CEFalse);
+    graph.add_edge(n1, c1, CETrue);
+    graph.add_edge(c1, n1, CETrue);
+    graph.add_edge(c1, n2, CEFalse);
+    graph.add_edge(n2, c2, CETrue);
+    graph.add_edge(c2, n2, CETrue);
+    graph.add_edge(c2, exit, CEFalse);
+
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let opts = StructuringOptions::default().matcher(Rc::new(DoWhiles));
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+
+    let do_whiles: Vec<_> = decisions::preorder(&ast)
+        .into_iter()
+        .enumerate()
+        .filter(|(_, a)| matches!(a, AstNodeC::Loop(LoopType::PostChecked(_), _)))
+        .map(|(i, _)| {
+            (
+                decisions::AstNodeId(i),
+                Annotation::Custom("do-while".to_owned()),
+            )
+        })
+        .collect();
+    assert_eq!(do_whiles.len(), 2);
+    assert_eq!(report.annotations, do_whiles);
+    assert!(report.state_machines.is_empty());
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_value(&report).unwrap();
+        let annotations = json.get("annotations").unwrap().as_array().unwrap();
+        let expected: Vec<_> = do_whiles
+            .iter()
+            .map(|(id, _)| format!(r#"[{},{{"Custom":"do-while"}}]"#, id.0))
+            .collect();
+        let expected = serde_json::from_str(&format!("[{}]", expected.join(","))).unwrap();
+        assert_eq!(annotations, expected.as_array().unwrap());
+    }
+
+    // the built-in matchers run alongside, unless they are taken out
+    let (_, _, report) = flattened_loop(cctx).structure_whole_reported(&opts);
+    assert!(matches!(
+        &report.annotations[..],
+        [(_, Annotation::StateMachine(_)), (_, Annotation::Exits(_))]
+    ));
+    let opts = StructuringOptions {
+        matchers: Vec::new(),
+        summarize_loop_exits: false,
+        ..Default::default()
+    };
+    let (_, _, report) = flattened_loop(cctx).structure_whole_reported(&opts);
+    assert_eq!(report.annotations, Vec::new());
+    assert!(report.state_machines.is_empty());
+}
+
+#[test]
+fn ast_switch_overlapping_value_sets() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let vars: Vec<_> = ["a", "b", "c", "d", "e"]
+        .iter()
+        .map(|s| cond_s(cctx, &format!("x in {}", s)))
+        .collect();
+    let sets = vec![
+        ValueSet::range(0, 1),
+        ValueSet::range(1, 2),
+        ValueSet::single(3),
+        ValueSet::single(4),
+        ValueSet::single(5),
+    ];
+    // a region graph with the given reaching conditions, which can't all
+    // come from an actual CFG
+    let mut value_sets = HashMap::new();
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node((cctx.mk_true(), None));
+    for ((&v, vs), name) in vars.iter().zip(sets).zip(&["a", "b", "c", "d", "e"]) {
+        value_sets.insert(cond_var_key::<StringAst>(v), ("x".to_owned(), vs, None));
+        let n = graph.add_node((cctx.mk_var(v), Some(AstNodeC::BasicBlock(name.to_string()))));
+        graph.add_edge(entry, n, ());
+    }
+
+    let overlaps = RefCell::default();
+    let ast = refinement::refine::<StringAst>(
+        cctx,
+        &value_sets,
+        &HashMap::new(),
+        &RefCell::default(),
+        &overlaps,
+        graph,
+        entry,
+        &StructuringOptions::default(),
+    );
+    println!("{:#?}", ast);
+
+    use self::AstNodeC::*;
+    let seq = match stringify_conds(ast) {
+        Seq(seq) => seq,
+        ast => panic!("not a Seq: {:?}", ast),
+    };
+    // `a` and `b` can't both be cases, but the others still make a switch
+    let switches: Vec<_> = seq.iter().filter(|a| matches!(a, Switch(..))).collect();
+    assert_eq!(
+        switches,
+        vec![&Switch(
+            "x".to_owned(),
+            vec![
+                (ValueSet::single(3), BasicBlock("c".to_owned())),
+                (ValueSet::single(4), BasicBlock("d".to_owned())),
+                (ValueSet::single(5), BasicBlock("e".to_owned())),
+            ],
+            Box::new(Seq(Vec::new())),
+        )]
+    );
+    assert!(seq.contains(&Cond(
+        r#""x in a""#.to_owned(),
+        Box::new(BasicBlock("a".to_owned())),
+        None
+    )));
+    assert!(seq.contains(&Cond(
+        r#""x in b""#.to_owned(),
+        Box::new(BasicBlock("b".to_owned())),
+        None
+    )));
+    // and are reported, once
+    assert_eq!(overlaps.into_inner(), vec![ValueSet::single(1)]);
+
+    #[cfg(feature = "serde")]
+    {
+        let report = StructuringReport {
+            overlapping_cases: vec![CaseOverlap {
+                region: NodeIndex::new(0),
+                values: ValueSet::single(1).union(&ValueSet::range(5, 9)),
+            }],
+            ..Default::default()
+        };
+        assert!(serde_json::to_string(&report)
+            .unwrap()
+            .contains(r#""overlapping_cases":[{"region":0,"values":{"ranges":[[1,1],[5,9]]}}]"#));
+    }
+}
+
+#[test]
+fn value_set_ops() {
+    let small: ValueSet = vec![1, 2, 3, 7].into_iter().collect();
+    assert_eq!(small.ranges(), &[(1, 3), (7, 7)]);
+    assert_eq!(
+        small.complement().ranges(),
+        &[(0, 0), (4, 6), (8, u64::MAX)]
+    );
+    assert_eq!(small.complement().complement(), small);
+    assert!(small.union(&small.complement()).is_full());
+    assert!(small.is_disjoint(&small.complement()));
+    assert_eq!(
+        small.intersection(&ValueSet::range(2, 10)).ranges(),
+        &[(2, 3), (7, 7)]
+    );
+    assert!(ValueSet::full().complement().is_empty());
+    assert_eq!(
+        ValueSet::range(u64::MAX, u64::MAX).complement(),
+        ValueSet::range(0, u64::MAX - 1)
+    );
+    assert!(!small.contains(5));
+    assert_eq!(small.min(), Some(1));
+
+    let domain = ValueSet::range(0, 9);
+    assert_eq!(
+        small.complement_in(&domain).ranges(),
+        &[(0, 0), (4, 6), (8, 9)]
+    );
+    assert!(domain.complement_in(&small).is_empty());
+    assert_eq!(small.complement_in(&ValueSet::full()), small.complement());
+    assert!(!small.is_disjoint(&ValueSet::range(3, 5)));
+    assert!(small.is_disjoint(&ValueSet::range(4, 6)));
+}
+
 #[test]
 fn ast_ifelse_cascade() {
     let cstore = condition::Storage::new();
@@ -565,15 +1360,7 @@ fn ast_while() {
 }
 
 #[test]
-fn ast_do_while() {
-    /*
-     * if (ce) {
-     *   do {
-     *     puts("n");
-     *   } while (c);
-     * }
-     * return;
-     */
+fn ast_while_reported() {
     let cstore = condition::Storage::new();
     let cctx = cstore.cctx();
 
@@ -586,9 +1373,44 @@ fn ast_do_while() {
     let n = graph.add_node(node("n"));
     let exit = graph.add_node(node("return"));
 
-    graph.add_edge(entry, n, CETrue);
+    graph.add_edge(entry, c, CETrue);
     graph.add_edge(entry, exit, CEFalse);
-    graph.add_edge(n, c, CETrue);
+    graph.add_edge(c, n, CETrue);
+    graph.add_edge(c, exit, CEFalse);
+    graph.add_edge(n, c, CETrue);
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let (_, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+    assert_eq!((report.regions, report.loops, report.gotos), (1, 1, 0));
+    assert!(report.fallbacks.is_empty());
+}
+
+#[test]
+fn ast_do_while() {
+    /*
+     * if (ce) {
+     *   do {
+     *     puts("n");
+     *   } while (c);
+     * }
+     * return;
+     */
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_ce = cond_s(cctx, "ce");
+    let v_c1 = cond_s(cctx, "c1");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(v_ce));
+    let c = graph.add_node(cnode(v_c1));
+    let n = graph.add_node(node("n"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, n, CETrue);
+    graph.add_edge(entry, exit, CEFalse);
+    graph.add_edge(n, c, CETrue);
     graph.add_edge(c, n, CETrue);
     graph.add_edge(c, exit, CEFalse);
 
@@ -617,6 +1439,408 @@ fn ast_do_while() {
     );
 }
 
+#[test]
+fn ast_unrotated_loop() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // a rotated `while (c) n;`, with the test both before and after `n`
+    let build = |same_test: bool| {
+        let v_c = cond_s(cctx, "c");
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(cnode(v_c));
+        let v_latch = if same_test { v_c } else { cond_s(cctx, "d") };
+        let latch = graph.add_node(cnode(v_latch));
+        let n = graph.add_node(node("n"));
+        let exit = graph.add_node(node("return"));
+
+        graph.add_edge(entry, n, CETrue);
+        graph.add_edge(entry, exit, CEFalse);
+        graph.add_edge(n, latch, CETrue);
+        graph.add_edge(latch, n, CETrue);
+        graph.add_edge(latch, exit, CEFalse);
+        (
+            ControlFlowGraph::new(graph, entry, cctx, StringAst::default()),
+            v_c,
+        )
+    };
+
+    use self::AstNodeC::*;
+    let (cfg, v_c) = build(true);
+    let (ast, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+    let c_c = cctx.mk_var(v_c);
+    assert_eq!(
+        ast,
+        Seq(vec![
+            Loop(
+                LoopType::PreChecked(c_c),
+                Box::new(BasicBlock("n".to_owned()))
+            ),
+            BasicBlock("return".to_owned()),
+        ])
+    );
+    let expl = report.decisions.explain(decisions::AstNodeId(1)).unwrap();
+    assert_eq!(expl.rule, Some("Unrotate"));
+
+    // a different test after `n` stays a do-while in an `if`
+    let (cfg, v_c) = build(false);
+    let ast = cfg.structure_whole().0;
+    assert!(matches!(
+        ast,
+        Seq(ref seq) if matches!(
+            seq[0],
+            Cond(c, box Loop(LoopType::PostChecked(_), _), None) if c == cctx.mk_var(v_c)
+        )
+    ));
+
+    // without loop refinement, the loop isn't made a `while` at all
+    let (cfg, _) = build(true);
+    let ast = cfg
+        .structure_whole_with(&StructuringOptions::default().refine_loops(false))
+        .0;
+    assert!(!matches!(ast, Seq(ref seq) if matches!(seq[0], Loop(..))));
+}
+
+#[test]
+fn explain_do_while() {
+    use self::decisions::{AstNodeId, Phase};
+
+    // the graph of `ast_do_while`
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(cond_s(cctx, "ce")));
+    let c = graph.add_node(cnode(cond_s(cctx, "c1")));
+    let n = graph.add_node(node("n"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, n, CETrue);
+    graph.add_edge(entry, exit, CEFalse);
+    graph.add_edge(n, c, CETrue);
+    graph.add_edge(c, n, CETrue);
+    graph.add_edge(c, exit, CEFalse);
+
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let (ast, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+    let nodes = decisions::preorder(&ast);
+    let id_of = |pred: &dyn Fn(&AstNode<StringAst>) -> bool| {
+        AstNodeId(nodes.iter().position(|a| pred(a)).unwrap())
+    };
+
+    let do_while = id_of(&|a| matches!(a, AstNodeC::Loop(LoopType::PostChecked(_), _)));
+    let expl = report.decisions.explain(do_while).unwrap();
+    assert_eq!(expl.phase, Phase::LoopRefinement);
+    assert_eq!(expl.rule, Some("DoWhile"));
+    // the latch edge
+    assert_eq!(expl.edges, vec![(c, n)]);
+
+    let cond = id_of(&|a| matches!(a, AstNodeC::Cond(..)));
+    let expl = report.decisions.explain(cond).unwrap();
+    assert_eq!(expl.phase, Phase::InitialCollapse);
+    assert_eq!(expl.edges, vec![(entry, n)]);
+
+    let block = id_of(&|a| matches!(a, AstNodeC::BasicBlock(_)));
+    assert_eq!(report.decisions.explain(block), None);
+
+    // the explanations are serialized by node
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_value(&report).unwrap();
+        let explanations = json.get("decisions").unwrap().get("explanations");
+        let explanations = explanations.unwrap().as_array().unwrap();
+        let expl = &explanations[do_while.0];
+        assert_eq!(expl.get("phase").unwrap().as_str(), Some("LoopRefinement"));
+        assert_eq!(expl.get("rule").unwrap().as_str(), Some("DoWhile"));
+        assert!(explanations[block.0].is_null());
+    }
+}
+
+#[test]
+fn do_while_latch_test() {
+    /*
+     * do {
+     *   a;
+     *   l;
+     * } while (c);
+     * return;
+     */
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_c = cond_s(cctx, "c");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(empty_node());
+    let a = graph.add_node(node("a"));
+    let l = graph.add_node(node("l"));
+    let c = graph.add_node(cnode(v_c));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, a, CETrue);
+    graph.add_edge(a, l, CETrue);
+    graph.add_edge(l, c, CETrue);
+    graph.add_edge(c, a, CETrue);
+    graph.add_edge(c, exit, CEFalse);
+
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let ast = cfg.structure_whole().0;
+    println!("{:#?}", ast);
+
+    use self::AstNodeC::*;
+    // the statements of the latch stay in the body
+    assert_eq!(
+        Seq(vec![
+            Loop(
+                LoopType::PostChecked(cctx.mk_var(v_c)),
+                Box::new(Seq(vec![
+                    BasicBlock("a".to_owned()),
+                    BasicBlock("l".to_owned()),
+                ])),
+            ),
+            BasicBlock("return".to_owned()),
+        ]),
+        ast
+    );
+}
+
+#[test]
+fn do_while_pure_latch() {
+    /*
+     * do {
+     *   if (h) a;
+     *   work;
+     * } while (l);
+     * x;
+     *
+     * and the same without `work`, where the latch is only the test
+     */
+    use self::AstNodeC::*;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_h = cond_s(cctx, "h");
+    let v_l = cond_s(cctx, "l");
+
+    for &work in &[true, false] {
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(empty_node());
+        let h = graph.add_node(cnode(v_h));
+        let a = graph.add_node(node("a"));
+        let l = graph.add_node(cnode(v_l));
+        let x = graph.add_node(node("x"));
+
+        graph.add_edge(entry, h, CETrue);
+        graph.add_edge(h, a, CETrue);
+        if work {
+            let w = graph.add_node(node("work"));
+            graph.add_edge(h, w, CEFalse);
+            graph.add_edge(a, w, CETrue);
+            graph.add_edge(w, l, CETrue);
+        } else {
+            graph.add_edge(h, l, CEFalse);
+            graph.add_edge(a, l, CETrue);
+        }
+        graph.add_edge(l, h, CETrue);
+        graph.add_edge(l, x, CEFalse);
+
+        let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+        let ast = cfg.structure_whole().0;
+        println!("{:#?}", ast);
+
+        let if_a = Cond(cctx.mk_var(v_h), Box::new(BasicBlock("a".to_owned())), None);
+        let body = if work {
+            Seq(vec![if_a, BasicBlock("work".to_owned())])
+        } else {
+            if_a
+        };
+        assert_eq!(
+            Seq(vec![
+                Loop(LoopType::PostChecked(cctx.mk_var(v_l)), Box::new(body)),
+                BasicBlock("x".to_owned()),
+            ]),
+            ast
+        );
+    }
+}
+
+#[test]
+fn split_latches() {
+    /*
+     * do {
+     *   0x10: work
+     *   0x14: test l
+     * } while (l);
+     * x;
+     *
+     * is `do { 0x10: work } while (0x14: test l; l)`, and a latch that only
+     * tests is left whole
+     */
+    use self::AstNodeC::*;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    for &(block, split) in &[("0x10: work\n0x14: test l", true), ("0x14: test l", false)] {
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(empty_node());
+        let b = graph.add_node(node(block));
+        let l = graph.add_node(cnode(cond_s(cctx, "l")));
+        let x = graph.add_node(node("x"));
+        graph.add_edge(entry, b, CETrue);
+        graph.add_edge(b, l, CETrue);
+        graph.add_edge(l, b, CETrue);
+        graph.add_edge(l, x, CEFalse);
+
+        let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+        let opts = StructuringOptions::default().split_latches(true);
+        let (ast, actx, report) = cfg.structure_whole_reported(&opts);
+        let (cond, body) = match &ast {
+            Seq(seq) => match &seq[..] {
+                [Loop(LoopType::PostChecked(c), box BasicBlock(body)), BasicBlock(_)] => (c, body),
+                _ => panic!("{:#?}", ast),
+            },
+            _ => panic!("{:#?}", ast),
+        };
+        let cond: Vec<_> = cond.vars().into_iter().map(|v| (*v).clone()).collect();
+        if split {
+            assert_eq!(report.split_latches, vec![b]);
+            assert_eq!(body, "0x10: work");
+            assert_eq!(actx.block_range(body), Some(0x10..0x11));
+            assert_eq!(cond, vec!["0x14: test l; l"]);
+        } else {
+            assert_eq!(report.split_latches, Vec::new());
+            assert_eq!(body, block);
+            assert_eq!(cond, vec!["l"]);
+        }
+    }
+}
+
+#[test]
+fn latch_test_shapes() {
+    use self::AstNodeC::*;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let c = cctx.mk_var(cond_s(cctx, "c"));
+    let bb = |s: &str| BasicBlock(s.to_owned());
+    let refine = |body| {
+        let decisions = RefCell::default();
+        let ast = refinement::refine_loop::<StringAst>(cctx, &decisions, body);
+        (ast, decisions)
+    };
+    let do_while = |body| Loop(LoopType::PostChecked(c), Box::new(body));
+
+    // if (c) continue; break;
+    let (ast, decisions) = refine(Seq(vec![
+        bb("a"),
+        bb("l"),
+        Cond(c, Box::new(Continue), None),
+        Break,
+    ]));
+    assert_eq!(ast, do_while(Seq(vec![bb("a"), bb("l")])));
+    let expl = decisions
+        .borrow()
+        .explain(cctx, &Default::default(), |_| Vec::new(), Some(&ast));
+    assert_eq!(
+        expl.explain(decisions::AstNodeId(0)).unwrap().rule,
+        Some("LatchTest")
+    );
+
+    // if (!c) break; continue;
+    let (ast, _) = refine(Seq(vec![
+        bb("a"),
+        Cond(cctx.mk_not(c), Box::new(Break), None),
+        Continue,
+    ]));
+    assert_eq!(ast, do_while(bb("a")));
+
+    // if (c) continue; else break;
+    let (ast, _) = refine(Cond(c, Box::new(Continue), Some(Box::new(Break))));
+    assert_eq!(ast, do_while(Seq(Vec::new())));
+
+    // a `continue` before the test would skip it
+    let body = Seq(vec![
+        Cond(cctx.mk_var(cond_s(cctx, "d")), Box::new(Continue), None),
+        bb("a"),
+        Cond(c, Box::new(Continue), Some(Box::new(Break))),
+    ]);
+    let (ast, _) = refine(body.clone());
+    assert_eq!(ast, Loop(LoopType::Endless, Box::new(body)));
+
+    // if (!c) { x; break; } continue;
+    let body = Seq(vec![
+        bb("a"),
+        Cond(cctx.mk_not(c), Box::new(Seq(vec![bb("x"), Break])), None),
+        Continue,
+    ]);
+    let (ast, _) = refine(body.clone());
+    assert_eq!(ast, Loop(LoopType::Endless, Box::new(body)));
+}
+
+#[test]
+fn latch_test_side_exit() {
+    /*
+     * while (1) {
+     *   a;
+     *   if (!c0) break;
+     *   l;
+     *   if (!c) {
+     *     x;
+     *     break;
+     *   }
+     * }
+     * return;
+     */
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_c0 = cond_s(cctx, "c0");
+    let v_c = cond_s(cctx, "c");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(empty_node());
+    let a = graph.add_node(node("a"));
+    let c0 = graph.add_node(cnode(v_c0));
+    let l = graph.add_node(node("l"));
+    let c = graph.add_node(cnode(v_c));
+    let x = graph.add_node(node("x"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, a, CETrue);
+    graph.add_edge(a, c0, CETrue);
+    graph.add_edge(c0, l, CETrue);
+    graph.add_edge(c0, exit, CEFalse);
+    graph.add_edge(l, c, CETrue);
+    graph.add_edge(c, a, CETrue);
+    graph.add_edge(c, x, CEFalse);
+    graph.add_edge(x, exit, CETrue);
+
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let ast = cfg.structure_whole().0;
+    println!("{:#?}", ast);
+
+    // the test at the bottom also leads to `x`, so it stays in the loop
+    use self::AstNodeC::*;
+    match ast {
+        Seq(seq) => match &seq[0] {
+            Loop(LoopType::Endless, body) => assert_eq!(
+                **body,
+                Seq(vec![
+                    BasicBlock("a".to_owned()),
+                    Cond(cctx.mk_not(cctx.mk_var(v_c0)), Box::new(Break), None),
+                    BasicBlock("l".to_owned()),
+                    Cond(
+                        cctx.mk_not(cctx.mk_var(v_c)),
+                        Box::new(Seq(vec![BasicBlock("x".to_owned()), Break])),
+                        None,
+                    ),
+                ])
+            ),
+            a => panic!("not an endless loop: {:?}", a),
+        },
+        ast => panic!("not a Seq: {:?}", ast),
+    }
+}
+
 #[test]
 fn ast_infinite_loop() {
     /*
@@ -783,117 +2007,2487 @@ fn ast_complex_while_or() {
 }
 
 #[test]
-fn ast_single_node() {
+fn noreturn_in_loop() {
+    /*
+     * i = 0;
+     * while (i < 10) {
+     *   if (bad) {
+     *     abort();
+     *   } else {
+     *     i++;
+     *   }
+     * }
+     * return;
+     */
     let cstore = condition::Storage::new();
     let cctx = cstore.cctx();
 
+    let v_lt = cond_s(cctx, "i < 10");
+    let v_bad = cond_s(cctx, "bad");
+
     let mut graph = StableDiGraph::new();
-    let entry = graph.add_node(node("stuff"));
+    let entry = graph.add_node(node("i = 0"));
+    let head = graph.add_node(cnode(v_lt));
+    let check = graph.add_node(cnode(v_bad));
+    let err = graph.add_node(node("abort()"));
+    let latch = graph.add_node(node("i++"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, head, CETrue);
+    graph.add_edge(head, check, CETrue);
+    graph.add_edge(head, exit, CEFalse);
+    graph.add_edge(check, err, CETrue);
+    graph.add_edge(check, latch, CEFalse);
+    // the bogus fall-through out of the call
+    graph.add_edge(err, latch, CETrue);
+    graph.add_edge(latch, head, CETrue);
 
     let actx = StringAst::default();
-    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
-    let ast = cfg.structure_whole().0;
+    let mut cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    cfg.set_noreturn(err);
+    let (ast, actx) = cfg.structure_whole();
     println!("{:#?}", ast);
 
+    let c_lt = cctx.mk_var(v_lt);
+    let c_bad = cctx.mk_var(v_bad);
+
+    // no structuring variables were needed
+    assert!(actx.vars.is_empty());
     use self::AstNodeC::*;
-    assert_eq!(BasicBlock("stuff".to_owned()), ast);
+    assert_eq!(
+        Seq(vec![
+            BasicBlock("i = 0".to_owned()),
+            Loop(
+                LoopType::PreChecked(c_lt),
+                Box::new(Cond(
+                    c_bad,
+                    Box::new(Seq(vec![BasicBlock("abort()".to_owned()), Return])),
+                    Some(Box::new(BasicBlock("i++".to_owned()))),
+                )),
+            ),
+            BasicBlock("return".to_owned()),
+        ]),
+        ast
+    );
 }
 
 #[test]
-fn too_many_loops() {
+fn noreturn_drops_fall_through() {
     let cstore = condition::Storage::new();
     let cctx = cstore.cctx();
 
-    let v_e1 = cond_s(cctx, "e1");
-    let v_a2 = cond_s(cctx, "a2");
-    let v_a3 = cond_s(cctx, "a3");
-    let v_b2 = cond_s(cctx, "b2");
-    let v_b3 = cond_s(cctx, "b3");
-    let v_c2 = cond_s(cctx, "c2");
-    let v_c4 = cond_s(cctx, "c4");
+    let v_bad = cond_s(cctx, "bad");
 
     let mut graph = StableDiGraph::new();
-    let entry = graph.add_node(cnode(v_e1));
+    let entry = graph.add_node(cnode(v_bad));
+    let err = graph.add_node(node("exit(1)"));
+    // only reachable by falling through the call
+    let junk = graph.add_node(node("junk"));
+    let ok = graph.add_node(node("ok"));
     let exit = graph.add_node(node("return"));
-    // loop a
-    let a1 = graph.add_node(node("a1"));
-    let a2 = graph.add_node(cnode(v_a2));
-    let a3 = graph.add_node(cnode(v_a3));
-    // loop b
-    let b1 = graph.add_node(node("b1"));
-    let b2 = graph.add_node(cnode(v_b2));
-    let b3 = graph.add_node(cnode(v_b3));
-    // loop c
-    let c1 = graph.add_node(node("c1"));
-    let c2 = graph.add_node(cnode(v_c2));
-    let c3 = graph.add_node(node("c3"));
-    let c4 = graph.add_node(cnode(v_c4));
 
-    graph.add_edge(entry, a1, CETrue);
-    graph.add_edge(entry, b1, CEFalse);
-    // loop a
-    graph.add_edge(a1, a2, CETrue);
-    graph.add_edge(a2, a3, CETrue);
-    graph.add_edge(a2, c1, CEFalse);
-    graph.add_edge(a3, a1, CETrue);
-    graph.add_edge(a3, c3, CEFalse);
-    // loop b
-    graph.add_edge(b1, b2, CETrue);
-    graph.add_edge(b2, b3, CETrue);
-    graph.add_edge(b2, c1, CEFalse);
-    graph.add_edge(b3, b1, CETrue);
-    graph.add_edge(b3, c3, CEFalse);
-    // loop c
-    graph.add_edge(c1, c2, CETrue);
-    graph.add_edge(c2, c3, CETrue);
-    graph.add_edge(c2, exit, CEFalse);
-    graph.add_edge(c3, c4, CETrue);
-    graph.add_edge(c4, c1, CETrue);
-    graph.add_edge(c4, exit, CEFalse);
+    graph.add_edge(entry, err, CETrue);
+    graph.add_edge(entry, ok, CEFalse);
+    graph.add_edge(err, junk, CETrue);
+    graph.add_edge(junk, exit, CETrue);
+    graph.add_edge(ok, exit, CETrue);
 
     let actx = StringAst::default();
-    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let mut cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    cfg.set_noreturn(err);
     let ast = cfg.structure_whole().0;
     println!("{:#?}", ast);
+
+    let c_bad = cctx.mk_var(v_bad);
+
+    use self::AstNodeC::*;
+    assert_eq!(
+        Cond(
+            c_bad,
+            Box::new(Seq(vec![BasicBlock("exit(1)".to_owned()), Return])),
+            Some(Box::new(Seq(vec![
+                BasicBlock("ok".to_owned()),
+                BasicBlock("return".to_owned()),
+            ]))),
+        ),
+        ast
+    );
 }
 
 #[test]
-fn nested_continue() {
+fn constant_false_arm() {
+    /*
+     * a;
+     * if (false) {
+     *   t1;
+     *   t2;
+     * } else {
+     *   e;
+     * }
+     * return;
+     */
     let cstore = condition::Storage::new();
     let cctx = cstore.cctx();
 
-    let v_ce = cond_s(cctx, "ce");
-    let v_n2 = cond_s(cctx, "n2");
-    let v_n4 = cond_s(cctx, "n4");
-    let v_n6 = cond_s(cctx, "n6");
-    let v_n8 = cond_s(cctx, "n8");
-
     let mut graph = StableDiGraph::new();
-    let entry = graph.add_node(cnode(v_ce));
-    let n1 = graph.add_node(node("n1"));
-    let n2 = graph.add_node(cnode(v_n2));
-    let n3 = graph.add_node(node("n3"));
+    let entry = graph.add_node(node("a"));
+    let c = graph.add_node(cnode(cond_s(cctx, "c")));
+    let t1 = graph.add_node(node("t1"));
+    let t2 = graph.add_node(node("t2"));
+    let e = graph.add_node(node("e"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, c, CETrue);
+    graph.add_edge(c, t1, CETrue);
+    graph.add_edge(c, e, CEFalse);
+    graph.add_edge(t1, t2, CETrue);
+    graph.add_edge(t2, exit, CETrue);
+    graph.add_edge(e, exit, CETrue);
+
+    let actx = StringAst::default();
+    let mut cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    cfg.set_constant(c, false);
+    let (ast, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+    println!("{:#?}", ast);
+
+    use self::AstNodeC::*;
+    assert_eq!(
+        Seq(vec![
+            BasicBlock("a".to_owned()),
+            BasicBlock("e".to_owned()),
+            BasicBlock("return".to_owned()),
+        ]),
+        ast
+    );
+    assert_eq!(report.pruned, vec![t1, t2]);
+    #[cfg(feature = "serde")]
+    assert!(serde_json::to_string(&report).unwrap().contains(&format!(
+        "\"pruned\":[{},{}]",
+        t1.index(),
+        t2.index()
+    )));
+}
+
+#[test]
+fn constant_condition_from_context() {
+    // the diamond of `constant_false_arm`, on a condition that the context
+    // knows is false
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("a"));
+    let c = graph.add_node(cnode(cond_s(cctx, "false")));
+    let t1 = graph.add_node(node("t1"));
+    let t2 = graph.add_node(node("t2"));
+    let e = graph.add_node(node("e"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, c, CETrue);
+    graph.add_edge(c, t1, CETrue);
+    graph.add_edge(c, e, CEFalse);
+    graph.add_edge(t1, t2, CETrue);
+    graph.add_edge(t2, exit, CETrue);
+    graph.add_edge(e, exit, CETrue);
+
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let (ast, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+
+    use self::AstNodeC::*;
+    assert_eq!(
+        Seq(vec![
+            BasicBlock("a".to_owned()),
+            BasicBlock("e".to_owned()),
+            BasicBlock("return".to_owned()),
+        ]),
+        ast
+    );
+    assert_eq!(report.pruned, vec![t1, t2]);
+}
+
+/// `if (a) { if (b) { x; } else { exit(2); } } else { exit(1); }`
+fn nested_exits<'cd>(cctx: CondContext<'cd, StringAst>) -> ControlFlowGraph<'cd, StringAst> {
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("s"));
+    let a = graph.add_node(cnode(cond_s(cctx, "a")));
+    let b = graph.add_node(cnode(cond_s(cctx, "b")));
+    let x = graph.add_node(node("x"));
+    let exit1 = graph.add_node(node("exit(1)"));
+    let exit2 = graph.add_node(node("exit(2)"));
+
+    graph.add_edge(entry, a, CETrue);
+    graph.add_edge(a, b, CETrue);
+    graph.add_edge(a, exit1, CEFalse);
+    graph.add_edge(b, x, CETrue);
+    graph.add_edge(b, exit2, CEFalse);
+
+    let mut cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    cfg.set_noreturn(exit1);
+    cfg.set_noreturn(exit2);
+    cfg
+}
+
+#[test]
+fn guard_clauses() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let opts = StructuringOptions::default().guard_clauses(2);
+    let ast = stringify_conds(nested_exits(cctx).structure_whole_with(&opts).0);
+    println!("{:#?}", ast);
+
+    use self::AstNodeC::*;
+    let exit = |s: &str| Box::new(Seq(vec![BasicBlock(s.to_owned()), Return]));
+    assert_eq!(
+        ast,
+        Seq(vec![
+            BasicBlock("s".to_owned()),
+            Cond("-\"a\"".to_owned(), exit("exit(1)"), None),
+            Cond("-\"b\"".to_owned(), exit("exit(2)"), None),
+            BasicBlock("x".to_owned()),
+        ])
+    );
+}
+
+#[test]
+fn guard_clauses_below_threshold() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    // only two levels of nesting to take away
+    let opts = StructuringOptions::default().guard_clauses(3);
+    let ast = stringify_conds(nested_exits(cctx).structure_whole_with(&opts).0);
+    println!("{:#?}", ast);
+
+    let nested = stringify_conds(nested_exits(cctx).structure_whole().0);
+    assert_eq!(ast, nested);
+    use self::AstNodeC::*;
+    match ast {
+        Seq(seq) => match &seq[1] {
+            Cond(a, t, Some(_)) => {
+                assert_eq!(a, "\"a\"");
+                assert!(matches!(**t, Cond(_, _, Some(_))));
+            }
+            a => panic!("not an if-else: {:?}", a),
+        },
+        ast => panic!("not a Seq: {:?}", ast),
+    }
+}
+
+/// `if (a) { x; t } else if (b) t else y; return;`, with the `t`s merged
+/// into one node, and that node.
+fn shared_tail<'cd>(
+    cctx: CondContext<'cd, StringAst>,
+) -> (ControlFlowGraph<'cd, StringAst>, NodeIndex) {
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("s"));
+    let a = graph.add_node(cnode(cond_s(cctx, "a")));
+    let b = graph.add_node(cnode(cond_s(cctx, "b")));
+    let x = graph.add_node(node("x"));
+    let y = graph.add_node(node("y"));
+    let t = graph.add_node(node("t"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, a, CETrue);
+    graph.add_edge(a, x, CETrue);
+    graph.add_edge(a, b, CEFalse);
+    graph.add_edge(b, t, CETrue);
+    graph.add_edge(b, y, CEFalse);
+    graph.add_edge(x, t, CETrue);
+    graph.add_edge(t, exit, CETrue);
+    graph.add_edge(y, exit, CETrue);
+
+    (
+        ControlFlowGraph::new(graph, entry, cctx, StringAst::default()),
+        t,
+    )
+}
+
+#[test]
+fn duplicate_shared_tail() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    use self::AstNodeC::*;
+    let bb = |s: &str| BasicBlock(s.to_owned());
+
+    // left shared, `t` runs on either branch being taken
+    let (cfg, _) = shared_tail(cctx);
+    match stringify_conds(cfg.structure_whole().0) {
+        Seq(seq) => match &seq[3] {
+            Cond(c, t, _) => {
+                assert!(c.starts_with("Or"));
+                assert_eq!(**t, bb("t"));
+            }
+            a => panic!("not an if: {:?}", a),
+        },
+        ast => panic!("not a Seq: {:?}", ast),
+    }
+
+    let opts = StructuringOptions::default().duplicate_tails(1);
+    let (cfg, t) = shared_tail(cctx);
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    assert_eq!(
+        stringify_conds(ast),
+        Seq(vec![
+            bb("s"),
+            Cond(
+                "\"a\"".to_owned(),
+                Box::new(Seq(vec![bb("x"), bb("t")])),
+                Some(Box::new(Cond(
+                    "-\"b\"".to_owned(),
+                    Box::new(bb("y")),
+                    Some(Box::new(bb("t"))),
+                ))),
+            ),
+            bb("return"),
+        ])
+    );
+    assert_eq!(report.duplicated.len(), 1);
+    assert_eq!(report.duplicated[0].0, t);
+
+    // `if (a) x; t; return;`: the branches really join at `t`
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(cond_s(cctx, "a")));
+    let x = graph.add_node(node("x"));
+    let t = graph.add_node(node("t"));
+    let exit = graph.add_node(node("return"));
+    graph.add_edge(entry, x, CETrue);
+    graph.add_edge(entry, t, CEFalse);
+    graph.add_edge(x, t, CETrue);
+    graph.add_edge(t, exit, CETrue);
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    assert!(report.duplicated.is_empty());
+    assert_eq!(
+        stringify_conds(ast),
+        Seq(vec![
+            Cond("\"a\"".to_owned(), Box::new(bb("x")), None),
+            bb("t"),
+            bb("return"),
+        ])
+    );
+}
+
+#[test]
+fn split_shared_region() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // `a` and `b` both go on to the diamond on `d`, which leaves for `y`,
+    // also reached from `b`, and `z`
+    let conds = ["a", "b", "d", "e"];
+    #[rustfmt::skip]
+    let edges = [
+        ("a", "p", CETrue), ("a", "b", CEFalse), ("b", "h", CETrue),
+        ("b", "y", CEFalse), ("p", "h", CETrue), ("h", "d", CETrue),
+        ("d", "x", CETrue), ("d", "w", CEFalse), ("x", "e", CETrue),
+        ("e", "y", CETrue), ("e", "z", CEFalse), ("w", "z", CETrue),
+        ("y", "return", CETrue), ("z", "return", CETrue),
+    ];
+    let cfg = named_cfg(cctx, &conds, &edges);
+    let paths = cfg.paths(|values| StringMachine::new(values, &[]), 0, 100);
+    let opts = StructuringOptions::default().split_shared_regions(8);
+    let (ast, actx, report) = cfg.structure_whole_reported(&opts);
+    // `h`, `d`, `x`, `w`, `e` and `z`, for `p`
+    assert_eq!(report.duplicated.len(), 6);
+    let mk_machine = |values: &[(&String, bool)]| StringMachine::new(values, &actx.vars);
+    if let Err(div) = paths.check(&ast, mk_machine) {
+        panic!("{}\nast: {:#?}", div, ast);
+    }
+    // each copy of the diamond is nested in its branch
+    let ast = stringify_conds(ast);
+    let count = |b: &str| {
+        decisions::preorder(&ast)
+            .into_iter()
+            .filter(|a| matches!(a, AstNodeC::BasicBlock(s) if s == b))
+            .count()
+    };
+    assert_eq!(count("h"), 2);
+    assert_eq!(count("y"), 1);
+
+    // too large, or over the limit
+    let cfg = named_cfg(cctx, &conds, &edges);
+    let (_, _, report) =
+        cfg.structure_whole_reported(&StructuringOptions::default().split_shared_regions(5));
+    assert!(report.duplicated.is_empty());
+    let cfg = named_cfg(cctx, &conds, &edges);
+    let opts = StructuringOptions::default()
+        .split_shared_regions(8)
+        .max_duplicated_nodes(4, 100);
+    let (_, _, report) = cfg.structure_whole_reported(&opts);
+    assert!(report.duplicated.is_empty());
+    assert_eq!(report.declined.len(), 1);
+    assert!(matches!(
+        report.declined[0],
+        DeclinedCopy::SharedRegion { nodes: 6, .. }
+    ));
+}
+
+#[test]
+fn tail_call_in_else() {
+    /*
+     * if (c) {
+     *   a;
+     * } else {
+     *   b;
+     *   return g(x);
+     * }
+     * tail;
+     */
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_c = cond_s(cctx, "c");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(v_c));
+    let a = graph.add_node(node("a"));
+    // ends in a jump to `g`, which isn't part of the function
+    let b = graph.add_node(node("b"));
+    let tail = graph.add_node(node("tail"));
+
+    graph.add_edge(entry, a, CETrue);
+    graph.add_edge(entry, b, CEFalse);
+    graph.add_edge(a, tail, CETrue);
+
+    let actx = StringAst::default();
+    let mut cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    cfg.set_tail_call(b, "g(x)".to_owned());
+    let ast = cfg.structure_whole().0;
+    println!("{:#?}", ast);
+
+    let c_c = cctx.mk_var(v_c);
+
+    use self::AstNodeC::*;
+    assert_eq!(
+        Cond(
+            c_c,
+            Box::new(Seq(vec![
+                BasicBlock("a".to_owned()),
+                BasicBlock("tail".to_owned()),
+            ])),
+            Some(Box::new(Seq(vec![
+                BasicBlock("b".to_owned()),
+                TailCall("g(x)".to_owned()),
+            ]))),
+        ),
+        ast
+    );
+}
+
+#[test]
+fn try_region_with_handler() {
+    /*
+     * setup;
+     * try {
+     *   call1;
+     *   if (c) {
+     *     call2;
+     *   }
+     * } catch {
+     *   pad;
+     *   if (d) {
+     *     log;
+     *   }
+     *   resume;
+     * }
+     * tail;
+     */
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_c = cond_s(cctx, "c");
+    let v_d = cond_s(cctx, "d");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("setup"));
+    let call1 = graph.add_node(node("call1"));
+    let c = graph.add_node(cnode(v_c));
+    let call2 = graph.add_node(node("call2"));
+    let tail = graph.add_node(node("tail"));
+    let pad = graph.add_node(node("pad"));
+    let d = graph.add_node(cnode(v_d));
+    let log = graph.add_node(node("log"));
+    let resume = graph.add_node(node("resume"));
+
+    graph.add_edge(entry, call1, CETrue);
+    graph.add_edge(call1, c, CETrue);
+    graph.add_edge(c, call2, CETrue);
+    graph.add_edge(c, tail, CEFalse);
+    graph.add_edge(call2, tail, CETrue);
+    graph.add_edge(call1, pad, CfgEdge::Unwind);
+    graph.add_edge(call2, pad, CfgEdge::Unwind);
+    graph.add_edge(pad, d, CETrue);
+    graph.add_edge(d, log, CETrue);
+    graph.add_edge(d, resume, CEFalse);
+    graph.add_edge(log, resume, CETrue);
+    // the handler goes back to the normal path
+    graph.add_edge(resume, tail, CETrue);
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let (ast, handlers, _) = cfg.structure_with_handlers(&StructuringOptions::default());
+    println!("{:#?}", ast);
+    println!("{:#?}", handlers);
+
+    let c_c = cctx.mk_var(v_c);
+    let c_d = cctx.mk_var(v_d);
+
+    use self::AstNodeC::*;
+    let h = HandlerId(0);
+    assert_eq!(
+        Seq(vec![
+            BasicBlock("setup".to_owned()),
+            Try(Box::new(BasicBlock("call1".to_owned())), h),
+            Cond(
+                c_c,
+                Box::new(Try(Box::new(BasicBlock("call2".to_owned())), h)),
+                None
+            ),
+            BasicBlock("tail".to_owned()),
+        ]),
+        ast
+    );
+    assert_eq!(
+        vec![Seq(vec![
+            BasicBlock("pad".to_owned()),
+            Cond(c_d, Box::new(BasicBlock("log".to_owned())), None),
+            BasicBlock("resume".to_owned()),
+        ])],
+        handlers
+    );
+}
+
+#[test]
+fn setjmp_longjmp() {
+    /*
+     * setup;
+     * if (setjmp(env) != 0) {
+     *   recover;
+     *   goto done;
+     * }
+     * if (c) {
+     *   longjmp(env, 1);
+     * } else {
+     *   work;
+     * done:
+     *   tail;
+     * }
+     */
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_c = cond_s(cctx, "c");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("setup"));
+    let call = graph.add_node(node("setjmp"));
+    let c = graph.add_node(cnode(v_c));
+    let jump = graph.add_node(node("longjmp"));
+    let work = graph.add_node(node("work"));
+    let tail = graph.add_node(node("tail"));
+    let recover = graph.add_node(node("recover"));
+
+    graph.add_edge(entry, call, CETrue);
+    graph.add_edge(call, c, CETrue);
+    graph.add_edge(c, jump, CETrue);
+    graph.add_edge(c, work, CEFalse);
+    graph.add_edge(work, tail, CETrue);
+    // the second return, after the `longjmp`, enters the function anew
+    graph.add_edge(call, recover, CfgEdge::Abnormal);
+    graph.add_edge(recover, tail, CETrue);
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let (ast, handlers, _, report) = cfg.structure_all(&StructuringOptions::default());
+    println!("{:#?}", ast);
+    println!("{:#?}", handlers);
+
+    use self::AstNodeC::*;
+    assert_eq!(
+        Seq(vec![
+            BasicBlock("setup".to_owned()),
+            BasicBlock("setjmp".to_owned()),
+            Cond(
+                cctx.mk_var(v_c),
+                Box::new(BasicBlock("longjmp".to_owned())),
+                Some(Box::new(Seq(vec![
+                    BasicBlock("work".to_owned()),
+                    BasicBlock("tail".to_owned()),
+                ]))),
+            ),
+        ]),
+        ast
+    );
+    // from where the second return rejoins the main path on, it is only
+    // there
+    assert_eq!(vec![BasicBlock("recover".to_owned())], handlers);
+    assert_eq!(report.gotos, 0);
+    assert_eq!(
+        report.annotations,
+        vec![(
+            decisions::AstNodeId(2),
+            Annotation::ReturnsAgain(HandlerId(0))
+        )]
+    );
+}
+
+#[test]
+fn loop_exit_ends_function() {
+    /*
+     * do {
+     *   n;
+     * } while (c1);
+     * tail;
+     */
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_c1 = cond_s(cctx, "c1");
+
+    let c_c1 = cctx.mk_var(v_c1);
+
+    use self::AstNodeC::*;
+    for &collapse_sese_regions in &[true, false] {
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(node("entry"));
+        let n = graph.add_node(node("n"));
+        let c = graph.add_node(cnode(v_c1));
+        // a sink; the region it forms with `c` must not be collapsed into a
+        // node that goes back to `n`
+        let tail = graph.add_node(node("tail"));
+
+        graph.add_edge(entry, n, CETrue);
+        graph.add_edge(n, c, CETrue);
+        graph.add_edge(c, n, CETrue);
+        graph.add_edge(c, tail, CEFalse);
+
+        let actx = StringAst::default();
+        let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+        let ast = cfg
+            .structure_whole_with(&StructuringOptions {
+                collapse_sese_regions,
+                ..Default::default()
+            })
+            .0;
+        println!("{:#?}", ast);
+        assert_eq!(
+            Seq(vec![
+                BasicBlock("entry".to_owned()),
+                Loop(
+                    LoopType::PostChecked(c_c1),
+                    Box::new(BasicBlock("n".to_owned())),
+                ),
+                BasicBlock("tail".to_owned()),
+            ]),
+            ast
+        );
+    }
+}
+
+#[test]
+fn loop_body_ends_function() {
+    /*
+     * while (c1) {
+     *   if (c2) {
+     *     bail;
+     *     return;
+     *   }
+     *   n;
+     * }
+     * after;
+     *
+     * Both exits end the function, so both are pulled into the loop.
+     */
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_c1 = cond_s(cctx, "c1");
+    let v_c2 = cond_s(cctx, "c2");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("entry"));
+    let c1 = graph.add_node(cnode(v_c1));
+    let c2 = graph.add_node(cnode(v_c2));
+    let bail = graph.add_node(node("bail"));
+    let n = graph.add_node(node("n"));
+    let after = graph.add_node(node("after"));
+
+    graph.add_edge(entry, c1, CETrue);
+    graph.add_edge(c1, c2, CETrue);
+    graph.add_edge(c1, after, CEFalse);
+    graph.add_edge(c2, bail, CETrue);
+    graph.add_edge(c2, n, CEFalse);
+    graph.add_edge(n, c1, CETrue);
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let ast = cfg.structure_whole().0;
+    println!("{:#?}", ast);
+
+    let c_c1 = cctx.mk_var(v_c1);
+    let c_c2 = cctx.mk_var(v_c2);
+
+    use self::AstNodeC::*;
+    assert_eq!(
+        Seq(vec![
+            BasicBlock("entry".to_owned()),
+            Loop(
+                LoopType::Endless,
+                Box::new(Cond(
+                    c_c1,
+                    Box::new(Cond(
+                        c_c2,
+                        Box::new(Seq(vec![BasicBlock("bail".to_owned()), Return])),
+                        Some(Box::new(BasicBlock("n".to_owned()))),
+                    )),
+                    Some(Box::new(Seq(vec![BasicBlock("after".to_owned()), Return]))),
+                )),
+            ),
+        ]),
+        ast
+    );
+}
+
+#[test]
+fn ast_single_node() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("stuff"));
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let ast = cfg.structure_whole().0;
+    println!("{:#?}", ast);
+
+    use self::AstNodeC::*;
+    assert_eq!(BasicBlock("stuff".to_owned()), ast);
+}
+
+#[test]
+fn too_many_loops() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_e1 = cond_s(cctx, "e1");
+    let v_a2 = cond_s(cctx, "a2");
+    let v_a3 = cond_s(cctx, "a3");
+    let v_b2 = cond_s(cctx, "b2");
+    let v_b3 = cond_s(cctx, "b3");
+    let v_c2 = cond_s(cctx, "c2");
+    let v_c4 = cond_s(cctx, "c4");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(v_e1));
+    let exit = graph.add_node(node("return"));
+    // loop a
+    let a1 = graph.add_node(node("a1"));
+    let a2 = graph.add_node(cnode(v_a2));
+    let a3 = graph.add_node(cnode(v_a3));
+    // loop b
+    let b1 = graph.add_node(node("b1"));
+    let b2 = graph.add_node(cnode(v_b2));
+    let b3 = graph.add_node(cnode(v_b3));
+    // loop c
+    let c1 = graph.add_node(node("c1"));
+    let c2 = graph.add_node(cnode(v_c2));
+    let c3 = graph.add_node(node("c3"));
+    let c4 = graph.add_node(cnode(v_c4));
+
+    graph.add_edge(entry, a1, CETrue);
+    graph.add_edge(entry, b1, CEFalse);
+    // loop a
+    graph.add_edge(a1, a2, CETrue);
+    graph.add_edge(a2, a3, CETrue);
+    graph.add_edge(a2, c1, CEFalse);
+    graph.add_edge(a3, a1, CETrue);
+    graph.add_edge(a3, c3, CEFalse);
+    // loop b
+    graph.add_edge(b1, b2, CETrue);
+    graph.add_edge(b2, b3, CETrue);
+    graph.add_edge(b2, c1, CEFalse);
+    graph.add_edge(b3, b1, CETrue);
+    graph.add_edge(b3, c3, CEFalse);
+    // loop c
+    graph.add_edge(c1, c2, CETrue);
+    graph.add_edge(c2, c3, CETrue);
+    graph.add_edge(c2, exit, CEFalse);
+    graph.add_edge(c3, c4, CETrue);
+    graph.add_edge(c4, c1, CETrue);
+    graph.add_edge(c4, exit, CEFalse);
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let ast = cfg.structure_whole().0;
+    println!("{:#?}", ast);
+}
+
+#[test]
+fn nested_continue() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_ce = cond_s(cctx, "ce");
+    let v_n2 = cond_s(cctx, "n2");
+    let v_n4 = cond_s(cctx, "n4");
+    let v_n6 = cond_s(cctx, "n6");
+    let v_n8 = cond_s(cctx, "n8");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(v_ce));
+    let n1 = graph.add_node(node("n1"));
+    let n2 = graph.add_node(cnode(v_n2));
+    let n3 = graph.add_node(node("n3"));
+    let n4 = graph.add_node(cnode(v_n4));
+    let n8 = graph.add_node(cnode(v_n8));
+    let n5 = graph.add_node(node("n5"));
+    let n6 = graph.add_node(cnode(v_n6));
+    let n7 = graph.add_node(node("n7"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, n1, CETrue);
+    graph.add_edge(entry, exit, CEFalse);
+    graph.add_edge(n1, n2, CETrue);
+    graph.add_edge(n2, n3, CETrue);
+    graph.add_edge(n2, n5, CEFalse);
+    graph.add_edge(n3, n4, CETrue);
+    graph.add_edge(n4, n1, CETrue);
+    graph.add_edge(n4, n8, CEFalse);
+    graph.add_edge(n8, exit, CETrue);
+    graph.add_edge(n8, n7, CEFalse);
+    graph.add_edge(n5, n6, CETrue);
+    graph.add_edge(n6, exit, CETrue);
+    graph.add_edge(n6, n7, CEFalse);
+    graph.add_edge(n7, n1, CETrue);
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let ast = cfg.structure_whole().0;
+    println!("{:#?}", ast);
+}
+
+#[test]
+fn structure_part() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let opts = StructuringOptions::default();
+
+    // if (a) { if (b) x; else y; z; } else w; j; return;
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(cond_s(cctx, "a")));
+    let b = graph.add_node(cnode(cond_s(cctx, "b")));
+    let x = graph.add_node(node("x"));
+    let y = graph.add_node(node("y"));
+    let z = graph.add_node(node("z"));
+    let w = graph.add_node(node("w"));
+    let j = graph.add_node(node("j"));
+    let exit = graph.add_node(node("return"));
+    graph.add_edge(entry, b, CETrue);
+    graph.add_edge(entry, w, CEFalse);
+    graph.add_edge(b, x, CETrue);
+    graph.add_edge(b, y, CEFalse);
+    graph.add_edge(x, z, CETrue);
+    graph.add_edge(y, z, CETrue);
+    graph.add_edge(z, j, CETrue);
+    graph.add_edge(w, j, CETrue);
+    graph.add_edge(j, exit, CETrue);
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+
+    // the branch on its own, up to `z`, and on to the end
+    let standalone = |rest: bool| {
+        let mut graph = StableDiGraph::new();
+        let b = graph.add_node(cnode(cond_s(cctx, "b")));
+        let x = graph.add_node(node("x"));
+        let y = graph.add_node(node("y"));
+        let z = graph.add_node(node("z"));
+        graph.add_edge(b, x, CETrue);
+        graph.add_edge(b, y, CEFalse);
+        graph.add_edge(x, z, CETrue);
+        graph.add_edge(y, z, CETrue);
+        if rest {
+            let j = graph.add_node(node("j"));
+            let exit = graph.add_node(node("return"));
+            graph.add_edge(z, j, CETrue);
+            graph.add_edge(j, exit, CETrue);
+        }
+        let cfg = ControlFlowGraph::new(graph, b, cctx, StringAst::default());
+        stringify_conds(cfg.structure_whole_with(&opts).0)
+    };
+    let body = cfg.structure_dominated(b, &opts).unwrap();
+    assert_eq!(stringify_conds(body), standalone(false));
+    let rest = cfg.structure_from(b, &opts).unwrap();
+    assert_eq!(stringify_conds(rest), standalone(true));
+    assert!(cfg.structure_from(NodeIndex::new(100), &opts).is_err());
+
+    // the graph is still whole
+    let bb = |s: &str| AstNodeC::BasicBlock(s.to_owned());
+    match stringify_conds(cfg.structure_whole().0) {
+        AstNodeC::Seq(seq) => assert_eq!(seq[seq.len() - 2..], [bb("j"), bb("return")]),
+        ast => panic!("not a Seq: {:?}", ast),
+    }
+
+    // a loop entered at `h`, structured from its latch
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("s"));
+    let h = graph.add_node(node("h"));
+    let l = graph.add_node(cnode(cond_s(cctx, "l")));
+    let exit = graph.add_node(node("return"));
+    graph.add_edge(entry, h, CETrue);
+    graph.add_edge(h, l, CETrue);
+    graph.add_edge(l, h, CETrue);
+    graph.add_edge(l, exit, CEFalse);
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let loops = |ast: &AstNodeC<String, String, String>| {
+        decisions::preorder(ast)
+            .into_iter()
+            .filter(|a| matches!(a, AstNodeC::Loop(..)))
+            .count()
+    };
+    let ast = stringify_conds(cfg.structure_from(l, &opts).unwrap());
+    assert_eq!(loops(&ast), 1);
+    // `l` dominates only the exit, which the `Condition` goes on to
+    let ast = stringify_conds(cfg.structure_dominated(l, &opts).unwrap());
+    assert_eq!(loops(&ast), 0);
+}
+
+#[test]
+fn shared_context() {
+    use super::context::StructuringContext;
+
+    // `if (ready) f(); else g(); return;`, where `ready` only tests a
+    // global, and `if (x) ...` on something of each function's own
+    fn structure(sctx: &StructuringContext<String>, name: &str) -> Vec<condition::VarId> {
+        let cstore = sctx.storage();
+        let cctx = cstore.cctx();
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(cnode(cctx.intern_var("ready".to_owned())));
+        let f = graph.add_node(node(name));
+        let x = graph.add_node(cnode(cctx.new_var("x".to_owned())));
+        let g = graph.add_node(node("g"));
+        let exit = graph.add_node(node("return"));
+        graph.add_edge(entry, f, CETrue);
+        graph.add_edge(entry, x, CEFalse);
+        graph.add_edge(x, g, CETrue);
+        graph.add_edge(x, exit, CEFalse);
+        graph.add_edge(f, exit, CETrue);
+        graph.add_edge(g, exit, CETrue);
+        let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+        let (ast, _) = cfg.structure_whole();
+        // the leaf predicates of the conditions
+        let mut ids = Vec::new();
+        for a in decisions::preorder(&ast) {
+            if let AstNodeC::Cond(c, _, _) = a {
+                ids.extend(c.vars().into_iter().map(|v| v.id()));
+            }
+        }
+        ids
+    }
+
+    let sctx = StructuringContext::new();
+    let (f1, f2) = thread::scope(|s| {
+        let f1 = s.spawn(|| structure(&sctx, "f1"));
+        let f2 = s.spawn(|| structure(&sctx, "f2"));
+        (f1.join().unwrap(), f2.join().unwrap())
+    });
+    let ready = sctx.var_id(&"ready".to_owned()).unwrap();
+    assert_eq!(sctx.interned(), 1);
+    assert!(f1.contains(&ready) && f2.contains(&ready));
+    // the variables that weren't interned stay apart
+    let own = |ids: &[condition::VarId]| -> Vec<_> {
+        ids.iter().filter(|&&id| id != ready).cloned().collect()
+    };
+    assert!(!own(&f1).is_empty());
+    assert!(own(&f1).iter().all(|id| !own(&f2).contains(id)));
+}
+
+#[test]
+fn contiguous_blocks() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    use self::AstNodeC::*;
+    fn bb<C>(s: &str) -> AstNodeC<String, C, String> {
+        BasicBlock(s.to_owned())
+    }
+    let mut actx = StringAst::default();
+
+    // the run at 0x100 merges into one block, as does the one in the `if`,
+    // but neither with the block at 0x200 nor with `x = 1`, which has no
+    // address
+    let c = cctx.mk_var(cond_s(cctx, "c"));
+    let ast: AstNode<StringAst> = Seq(vec![
+        bb("0x100: a"),
+        bb("0x101: b"),
+        bb("0x102: c"),
+        bb("0x200: d"),
+        bb("x = 1"),
+        Cond(c, Box::new(Seq(vec![bb("0x300: e"), bb("0x301: f")])), None),
+    ]);
+    assert_eq!(
+        stringify_conds(contiguous_blocks::merge(&mut actx, ast)),
+        Seq(vec![
+            bb("0x100: a\n0x101: b\n0x102: c"),
+            bb("0x200: d"),
+            bb("x = 1"),
+            Cond(
+                "\"c\"".to_owned(),
+                Box::new(Seq(vec![bb("0x300: e\n0x301: f")])),
+                None,
+            ),
+        ])
+    );
+
+    // a `goto` may still jump to the block after a label, and the context
+    // won't drop the comments
+    let blocked = |l| {
+        Seq(vec![
+            bb("0x100: a"),
+            Label(l),
+            bb("0x101: b"),
+            bb("0x102: c // the end"),
+        ])
+    };
+    let label = LabelId(0);
+    let ast = blocked(label);
+    assert_eq!(
+        stringify_conds(contiguous_blocks::merge(&mut actx, ast)),
+        stringify_conds(blocked(label))
+    );
+
+    // and the same when structuring: `a; if (x) b; c` with `b` taken by the
+    // jump in `a` that structuring left out
+    let mut graph = StableDiGraph::new();
+    let a = graph.add_node(node("0x100: a"));
+    let x = graph.add_node(cnode(cond_s(cctx, "x")));
+    let b = graph.add_node(node("0x101: b"));
+    let d = graph.add_node(node("0x102: d"));
+    let e = graph.add_node(node("0x103: e"));
+    graph.add_edge(a, x, CETrue);
+    graph.add_edge(x, b, CETrue);
+    graph.add_edge(x, d, CEFalse);
+    graph.add_edge(b, d, CETrue);
+    graph.add_edge(d, e, CETrue);
+    let structure = |merge| {
+        let cfg = ControlFlowGraph::new(graph.clone(), a, cctx, StringAst::default());
+        let opts = StructuringOptions::default().merge_contiguous_blocks(merge);
+        stringify_conds(cfg.structure_whole_with(&opts).0)
+    };
+    assert_eq!(
+        structure(true),
+        Seq(vec![
+            bb("0x100: a"),
+            Cond("\"x\"".to_owned(), Box::new(bb("0x101: b")), None),
+            bb("0x102: d\n0x103: e"),
+        ])
+    );
+    assert_eq!(
+        structure(false),
+        Seq(vec![
+            bb("0x100: a"),
+            Cond("\"x\"".to_owned(), Box::new(bb("0x101: b")), None),
+            bb("0x102: d"),
+            bb("0x103: e"),
+        ])
+    );
+}
+
+#[test]
+fn order_by_weight() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    use self::AstNodeC::*;
+    let bb = |s: &str| BasicBlock(s.to_owned());
+
+    // `if (failed) log_error(); else work(); return;`, where `work` is
+    // what usually runs
+    let diamond = |weights: Option<(u64, u64)>| {
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(cnode(cond_s(cctx, "failed")));
+        let error = graph.add_node(node("log_error()"));
+        let work = graph.add_node(node("work()"));
+        let exit = graph.add_node(node("return"));
+        graph.add_edge(entry, error, CETrue);
+        graph.add_edge(entry, work, CEFalse);
+        graph.add_edge(error, exit, CETrue);
+        graph.add_edge(work, exit, CETrue);
+        let mut cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+        if let Some((holds, fails)) = weights {
+            cfg.set_branch_weights(entry, holds, fails);
+        }
+        let opts = StructuringOptions::default().order_by_weight(true);
+        let (ast, _, report) = cfg.structure_whole_reported(&opts);
+        (stringify_conds(ast), report.flipped)
+    };
+    let as_found = Seq(vec![
+        Cond(
+            "\"failed\"".to_owned(),
+            Box::new(bb("log_error()")),
+            Some(Box::new(bb("work()"))),
+        ),
+        bb("return"),
+    ]);
+
+    let (ast, flipped) = diamond(Some((1, 100)));
+    assert_eq!(
+        ast,
+        Seq(vec![
+            Cond(
+                "-\"failed\"".to_owned(),
+                Box::new(bb("work()")),
+                Some(Box::new(bb("log_error()"))),
+            ),
+            bb("return"),
+        ])
+    );
+    assert_eq!(flipped, vec![decisions::AstNodeId(1)]);
+
+    // the heavier arm is first already, or nothing tells
+    assert_eq!(diamond(Some((100, 1))), (as_found.clone(), Vec::new()));
+    assert_eq!(diamond(None), (as_found, Vec::new()));
+}
+
+#[test]
+fn inline_callee() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    use self::AstNodeC::*;
+    let bb = |s: &str| BasicBlock(s.to_owned());
+
+    // `s; check(); t; return;`
+    let caller = || {
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(node("0x100: s"));
+        let call = graph.add_node(node("check()"));
+        let t = graph.add_node(node("t"));
+        let exit = graph.add_node(node("return"));
+        graph.add_edge(entry, call, CETrue);
+        graph.add_edge(call, t, CETrue);
+        graph.add_edge(t, exit, CETrue);
+        (
+            ControlFlowGraph::new(graph, entry, cctx, StringAst::default()),
+            call,
+        )
+    };
+    // `check`: `x = load(); if (x) err = 1; else return log();`
+    let callee = |addr: u64| {
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(node(&format!("{:#x}: x = load()", addr)));
+        let x = graph.add_node(cnode(cond_s(cctx, "x")));
+        let err = graph.add_node(node("err = 1"));
+        let ok = graph.add_node(node("ok"));
+        graph.add_edge(entry, x, CETrue);
+        graph.add_edge(x, err, CETrue);
+        graph.add_edge(x, ok, CEFalse);
+        let mut cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+        cfg.set_tail_call(ok, "log()".to_owned());
+        (cfg, ok)
+    };
+
+    let (mut cfg, call) = caller();
+    let (check, _) = callee(0x200);
+    cfg.inline_at(call, &check).unwrap();
+    let (ast, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+    assert_eq!(
+        stringify_conds(ast),
+        Seq(vec![
+            bb("0x100: s"),
+            bb("check()"),
+            bb("0x200: x = load()"),
+            Cond(
+                "\"x\"".to_owned(),
+                Box::new(bb("err = 1")),
+                Some(Box::new(Seq(vec![bb("ok"), bb("log()")]))),
+            ),
+            bb("t"),
+            bb("return"),
+        ])
+    );
+    assert_eq!(report.inlined.len(), 1);
+    assert_eq!(report.inlined[0].call, call);
+    assert_eq!(report.inlined[0].callee, Some(0x200));
+    assert_eq!(report.inlined[0].nodes.len(), 4);
+
+    // the callee is the caller
+    let (mut cfg, call) = caller();
+    let (check, _) = callee(0x100);
+    let nodes = cfg.graph.node_count();
+    assert_eq!(
+        cfg.inline_at(call, &check),
+        Err(StructureError::Inline("the callee is the caller"))
+    );
+    assert_eq!(cfg.graph.node_count(), nodes);
+
+    // or had it inlined, which counts when it is inlined in turn
+    let (mut outer, _) = callee(0x300);
+    let (mut cfg, call) = caller();
+    cfg.inline_at(call, &callee(0x200).0).unwrap();
+    let outer_entry = outer.entry;
+    outer.inline_at(outer_entry, &cfg).unwrap();
+    assert_eq!(outer.report.inlined.len(), 2);
+    assert_eq!(outer.report.inlined[0].callee, Some(0x200));
+    let (mut cfg, call) = caller();
+    assert!(cfg.inline_at(call, &outer).is_err());
+
+    // unresolved indirect jumps may go anywhere
+    let (mut cfg, call) = caller();
+    let (mut check, ok) = callee(0x200);
+    check.set_unresolved_jump(ok, "goto *r".to_owned());
+    assert!(cfg.inline_at(call, &check).is_err());
+}
+
+#[test]
+fn split_critical_edges() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let split = StructuringOptions::default().split_critical_edges(true);
+
+    // a diamond has no critical edges, and the edge of `if (a) x;` that
+    // skips `x` is split with a node that leaves nothing behind
+    #[rustfmt::skip]
+    let shapes: [&[_]; 2] = [
+        &[
+            ("a", "x", CETrue), ("a", "y", CEFalse), ("x", "j", CETrue),
+            ("y", "j", CETrue), ("j", "return", CETrue),
+        ],
+        &[("a", "x", CETrue), ("a", "j", CEFalse), ("x", "j", CETrue), ("j", "return", CETrue)],
+    ];
+    for (i, edges) in shapes.iter().enumerate() {
+        let plain = stringify_conds(named_cfg(cctx, &["a"], edges).structure_whole().0);
+        let (ast, _, report) = named_cfg(cctx, &["a"], edges).structure_whole_reported(&split);
+        assert_eq!(report.split_edges.len(), i);
+        assert_eq!(stringify_conds(ast), plain);
+    }
+
+    // a loop entered at `x` and `y`, both from `a`: the entries assign the
+    // variable dispatched on in the nodes split off the edges from `a`
+    #[rustfmt::skip]
+    let edges = [
+        ("a", "x", CETrue), ("a", "y", CEFalse), ("x", "y", CETrue),
+        ("x", "return", CEFalse), ("y", "x", CETrue),
+    ];
+    let mut cfg = named_cfg(cctx, &["a", "x"], &edges);
+    let x = cfg
+        .graph
+        .node_indices()
+        .find(|&n| matches!(&cfg.graph[n], CfgNode::Condition(c) if **c == "x"))
+        .unwrap();
+    cfg.split_critical_edges();
+    assert_eq!(cfg.report.split_edges.len(), 3);
+    let (into_x, into_y, x_to_y) = (
+        cfg.report.split_edges[0],
+        cfg.report.split_edges[1],
+        cfg.report.split_edges[2],
+    );
+    let y = cfg.graph.neighbors(x_to_y).next().unwrap();
+    let loop_nodes: NodeSet = vec![x, y, x_to_y].into_iter().collect();
+    let nodes = cfg.graph.node_count();
+    cfg.funnel_abnormal_entries(x, &loop_nodes).unwrap();
+    let block = |n: NodeIndex| match &cfg.graph[n] {
+        CfgNode::Code(AstNodeC::BasicBlock(b)) => b.to_string(),
+        _ => panic!("not a block"),
+    };
+    assert_eq!(block(into_x), "i_0 = 0");
+    assert_eq!(block(into_y), "i_0 = 1");
+    // a test and a reset, and no other blocks
+    assert_eq!(cfg.graph.node_count(), nodes + 2);
+
+    // and the same structured as a whole, which does the same
+    let cfg = named_cfg(cctx, &["a", "x"], &edges);
+    let paths = cfg.paths(|values| StringMachine::new(values, &[]), 0, 100);
+    let (ast, actx, report) = cfg.structure_whole_reported(&split);
+    assert_eq!(report.fallbacks.len(), 1);
+    let mk_machine = |values: &[(&String, bool)]| StringMachine::new(values, &actx.vars);
+    if let Err(div) = paths.check(&ast, mk_machine) {
+        panic!("{}\nast: {:#?}", div, ast);
+    }
+
+    // a loop left for `z` on a critical edge, which becomes its `break`
+    #[rustfmt::skip]
+    let edges = [
+        ("s", "h", CETrue), ("s", "z", CEFalse), ("h", "q", CETrue),
+        ("h", "w", CEFalse), ("q", "h", CETrue), ("q", "z", CEFalse),
+        ("w", "return", CETrue), ("z", "return", CETrue),
+    ];
+    let cfg = named_cfg(cctx, &["s", "h", "q"], &edges);
+    let paths = cfg.paths(|values| StringMachine::new(values, &[]), 0, 100);
+    let (ast, actx, report) = cfg.structure_whole_reported(&split);
+    assert_eq!(report.split_edges.len(), 4);
+    let mk_machine = |values: &[(&String, bool)]| StringMachine::new(values, &actx.vars);
+    if let Err(div) = paths.check(&ast, mk_machine) {
+        panic!("{}\nast: {:#?}", div, ast);
+    }
+}
+
+#[test]
+fn multi_entry() {
+    use self::AstNodeC::*;
+    use super::multi_entry;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let opts = StructuringOptions::default();
+
+    // entered at `a`, `b` and `u`: `a; if (c) x; t; u;`, `b; t; u;` and `u;`
+    let mut graph = StableDiGraph::new();
+    let mut names = HashMap::new();
+    #[rustfmt::skip]
+    let edges = [
+        ("a", "c", CETrue), ("c", "x", CETrue), ("c", "t", CEFalse), ("x", "t", CETrue),
+        ("b", "t", CETrue), ("t", "u", CETrue),
+    ];
+    for &(from, to, edge) in &edges {
+        let mut get = |name: &str| {
+            *names.entry(name.to_owned()).or_insert_with(|| {
+                graph.add_node(if name == "c" {
+                    cnode(cond_s(cctx, name))
+                } else {
+                    node(name)
+                })
+            })
+        };
+        let (from, to) = (get(from), get(to));
+        graph.add_edge(from, to, edge);
+    }
+    let (a, b, t, u) = (names["a"], names["b"], names["t"], names["u"]);
+    let (asts, _) =
+        multi_entry::structure_entries(graph, &[a, b, u], cctx, StringAst::default(), &opts)
+            .unwrap();
+    let (lt, lu) = (LabelId(t.index()), LabelId(u.index()));
+    let bb = |b: &str| BasicBlock(b.to_owned());
+    assert_eq!(asts.entries.len(), 3);
+    assert_eq!(asts.shared.len(), 2);
+    // the tail from `t` on is structured once, and `u` is both an entry
+    // and the tail of `t`
+    assert_eq!(*asts.shared[&lt], Seq(vec![bb("t"), Goto(lu)]));
+    assert_eq!(*asts.shared[&lu], bb("u"));
+    assert!(Rc::ptr_eq(&asts.entries[2], &asts.shared[&lu]));
+    assert_eq!(*asts.entries[1], Seq(vec![bb("b"), Goto(lt)]));
+    match &*asts.entries[0] {
+        Seq(seq) => {
+            assert_eq!(seq[0], bb("a"));
+            assert!(matches!(seq[1], Cond(..)));
+            assert_eq!(seq[2..], [Goto(lt)]);
+        }
+        ast => panic!("{:?}", ast),
+    }
+
+    // both entered in the same loop: nothing is shared, but each entry is
+    // still structured
+    let mut graph = StableDiGraph::new();
+    let a = graph.add_node(node("a"));
+    let b = graph.add_node(node("b"));
+    let c = graph.add_node(cnode(cond_s(cctx, "c")));
+    let r = graph.add_node(node("r"));
+    graph.add_edge(a, b, CETrue);
+    graph.add_edge(b, c, CETrue);
+    graph.add_edge(c, a, CETrue);
+    graph.add_edge(c, r, CEFalse);
+    let (asts, _) =
+        multi_entry::structure_entries(graph, &[a, b], cctx, StringAst::default(), &opts).unwrap();
+    assert_eq!(asts.entries.len(), 2);
+    assert!(asts.shared.is_empty());
+    for ast in &asts.entries {
+        assert!(matches!(&**ast, Seq(seq) if seq.iter().any(|a| matches!(a, Loop(..)))));
+    }
+
+    let graph = StableDiGraph::new();
+    let res = multi_entry::structure_entries(graph, &[], cctx, StringAst::default(), &opts);
+    assert!(res.is_err());
+}
+
+#[test]
+fn lowering() {
+    use self::AstNodeC::*;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let opts = StructuringOptions::default();
+    let bb = |b: &str| BasicBlock(b.to_owned());
+    let c_ce = cctx.mk_var(cond_s(cctx, "ce"));
+    let c_c1 = cctx.mk_var(cond_s(cctx, "c1"));
+
+    // if (ce) { while (c1) { n; } } return;
+    let ast = Seq(vec![
+        Cond(
+            c_ce,
+            Box::new(Loop(LoopType::PreChecked(c_c1), Box::new(bb("n")))),
+            None,
+        ),
+        bb("return"),
+    ]);
+    let cfg = ControlFlowGraph::from_ast(&ast, cctx, StringAst::default()).unwrap();
+    // `ce`, `c1`, `n`, `return` and the empty end of the graph, the joins
+    // all contracted
+    assert_eq!(cfg.graph.node_count(), 5);
+    let (ast2, _, report) = cfg.structure_whole_reported(&opts);
+    assert_eq!((report.loops, report.gotos), (1, 0));
+    assert_eq!(ast2, ast);
+
+    // for (;;) { a; if (ce && c1) break; } b;
+    let ast = Seq(vec![
+        Loop(
+            LoopType::Endless,
+            Box::new(Seq(vec![
+                bb("a"),
+                Cond(cctx.mk_and(c_ce, c_c1), Box::new(Break), None),
+            ])),
+        ),
+        bb("b"),
+    ]);
+    let cfg = ControlFlowGraph::from_ast(&ast, cctx, StringAst::default()).unwrap();
+    let (ast2, _, report) = cfg.structure_whole_reported(&opts);
+    assert_eq!((report.loops, report.gotos), (1, 0));
+    assert_eq!(ast2.metrics().blocks, 2);
+
+    for ast in [Break, Seq(vec![bb("a"), Goto(LabelId(7))])] {
+        assert!(ControlFlowGraph::from_ast(&ast, cctx, StringAst::default()).is_err());
+    }
+}
+
+#[test]
+fn petgraph_algorithms() {
+    use petgraph::algo;
+    use petgraph::visit::{EdgeRef, IntoEdgeReferences, IntoEdges, NodeIndexable};
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // if (c) a; else b; r;
+    let mut graph = StableDiGraph::new();
+    let c = graph.add_node(cnode(cond_s(cctx, "c")));
+    let a = graph.add_node(node("a"));
+    let b = graph.add_node(node("b"));
+    let r = graph.add_node(node("r"));
+    graph.add_edge(c, a, CETrue);
+    graph.add_edge(c, b, CEFalse);
+    graph.add_edge(a, r, CETrue);
+    graph.add_edge(b, r, CETrue);
+    let cfg = ControlFlowGraph::new(graph, c, cctx, StringAst::default());
+
+    assert_eq!(cfg.entry(), c);
+    let doms = algo::dominators::simple_fast(&cfg, cfg.entry());
+    assert_eq!(doms.immediate_dominator(r), Some(c));
+    assert_eq!(doms.immediate_dominator(a), Some(c));
+    let order = algo::toposort(&cfg, None).unwrap();
+    let pos = |n| order.iter().position(|&m| m == n).unwrap();
+    assert_eq!((pos(c), pos(r)), (0, 3));
+    assert!(pos(a) < pos(r) && pos(b) < pos(r));
+    assert_eq!((&cfg).edge_references().count(), 4);
+    assert!((&cfg).edges(c).all(|e| e.source() == c));
+    assert_eq!(cfg.node_bound(), 4);
+}
+
+#[test]
+fn given_dominators() {
+    use petgraph::algo::dominators;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let opts = StructuringOptions::default().collapse_sese_regions(false);
+
+    // the same ASTs as without them
+    let cfg = if_chain(cctx, 3);
+    let (expected, _) = if_chain(cctx, 3).structure_whole_with(&opts);
+    let mut cfg2 = if_chain(cctx, 3);
+    cfg2.set_dominators(&dominators::simple_fast(&cfg, cfg.entry()))
+        .unwrap();
+    assert_eq!(
+        format!("{:?}", cfg2.structure_whole_with(&opts).0),
+        format!("{:?}", expected)
+    );
+
+    // e; if (h) a; b; return
+    let mut graph = StableDiGraph::new();
+    let e = graph.add_node(node("e"));
+    let h = graph.add_node(cnode(cond_s(cctx, "h")));
+    let a = graph.add_node(node("a"));
+    let b = graph.add_node(node("b"));
+    let exit = graph.add_node(node("return"));
+    graph.add_edge(e, h, CETrue);
+    graph.add_edge(h, a, CETrue);
+    graph.add_edge(h, b, CEFalse);
+    graph.add_edge(a, b, CETrue);
+    graph.add_edge(b, exit, CETrue);
+    let cfg = || ControlFlowGraph::new(graph.clone(), e, cctx, StringAst::default());
+    let mut cfg2 = cfg();
+    cfg2.set_dominators(&dominators::simple_fast(&graph, e))
+        .unwrap();
+    assert_eq!(
+        format!("{:?}", cfg2.structure_dominated(h, &opts).unwrap()),
+        format!("{:?}", cfg().structure_dominated(h, &opts).unwrap())
+    );
+
+    // the dominators of other graphs
+    let err = |doms| cfg().set_dominators(&doms).unwrap_err().to_string();
+    assert_eq!(
+        err(dominators::simple_fast(&graph, h)),
+        "set_dominators: the dominators are rooted at node 1, not at the entry, node 0"
+    );
+    let mut other = graph.clone();
+    other.remove_edge(other.find_edge(e, h).unwrap());
+    assert_eq!(
+        err(dominators::simple_fast(&other, e)),
+        "set_dominators: node 1 has no immediate dominator"
+    );
+    // without `h -> b`, `a` would dominate `b`
+    let mut other = graph.clone();
+    other.remove_edge(other.find_edge(h, b).unwrap());
+    assert_eq!(
+        err(dominators::simple_fast(&other, e)),
+        "set_dominators: node 2 is the immediate dominator of node 3, but not of its predecessor, \
+         node 1"
+    );
+    // with `e -> a`, `h` wouldn't dominate `a`
+    let mut other = graph.clone();
+    other.add_edge(e, a, CEFalse);
+    let wider = dominators::simple_fast(&other, e);
+    assert_eq!(
+        err(dominators::simple_fast(&other, e)),
+        "set_dominators: node 0 is the immediate dominator of node 2, but node 1 dominates all of \
+         its predecessors"
+    );
+    // the dominators a failed call was given aren't used
+    let mut cfg2 = cfg();
+    cfg2.set_dominators(&dominators::simple_fast(&graph, e))
+        .unwrap();
+    assert!(cfg2.set_dominators(&wider).is_err());
+    assert_eq!(
+        format!("{:?}", cfg2.structure_dominated(h, &opts).unwrap()),
+        format!("{:?}", cfg().structure_dominated(h, &opts).unwrap())
+    );
+
+    // and they are forgotten once the graph changes
+    cfg2.set_noreturn(a);
+    assert!(cfg2.dominators.is_none());
+    assert_eq!(
+        format!("{:?}", cfg2.structure_dominated(h, &opts).unwrap()),
+        format!("{:?}", {
+            let mut cfg = cfg();
+            cfg.set_noreturn(a);
+            cfg.structure_dominated(h, &opts).unwrap()
+        })
+    );
+}
+
+#[test]
+fn namer() {
+    #[derive(Debug, Default)]
+    struct Prefixed(usize);
+
+    impl naming::Namer for Prefixed {
+        fn fresh_var(&mut self, hint: &str) -> String {
+            self.0 += 1;
+            format!("my_{}{}", hint, self.0)
+        }
+
+        fn fresh_label(&mut self) -> String {
+            self.0 += 1;
+            format!("L{}", self.0)
+        }
+    }
+
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let structure = |opts: &StructuringOptions| {
+        let (mut cfg, _) = loop_entered_thrice(cctx);
+        cfg.actx.named = true;
+        let (ast, _, report) = cfg.structure_whole_reported(opts);
+        let blocks: Vec<_> = decisions::preorder(&ast)
+            .into_iter()
+            .filter_map(|a| match a {
+                AstNodeC::BasicBlock(b) => Some(b.clone()),
+                _ => None,
+            })
+            .collect();
+        (blocks, report)
+    };
+
+    // the loop is entered at three nodes, so it dispatches on a variable
+    let opts = StructuringOptions::default().namer(Rc::new(RefCell::new(Prefixed::default())));
+    let (blocks, report) = structure(&opts);
+    // and saves the tests of the variable that both the loop and what
+    // enters it make
+    assert_eq!(report.var_names, ["my_entry1", "my_cond2", "my_cond3"]);
+    assert!(blocks.iter().any(|b| b == "my_entry1 = 1"));
+    assert!(blocks.iter().any(|b| b == "my_cond2 = my_entry1 == 0"));
+    assert!(report.labels.is_empty());
+    // the same options name the same way
+    let opts = StructuringOptions::default().namer(Rc::new(RefCell::new(Prefixed::default())));
+    let (again, again_report) = structure(&opts);
+    assert_eq!((again, again_report.var_names), (blocks, report.var_names));
+
+    // by default, the hint and a count; `StringAst` ignores the names unless
+    // told otherwise
+    let (blocks, report) = structure(&StructuringOptions::default());
+    assert_eq!(report.var_names, ["entry_0", "cond_1", "cond_2"]);
+    assert!(blocks.iter().any(|b| b == "entry_0 = 1"));
+    let (cfg, _) = loop_entered_thrice(cctx);
+    let (ast, _) = cfg.structure_whole();
+    assert!(!decisions::preorder(&ast)
+        .into_iter()
+        .any(|a| matches!(a, AstNodeC::BasicBlock(b) if b.contains("entry_0"))));
+
+    // the labels of the gotos left once the budget is used up
+    let opts = StructuringOptions::default()
+        .namer(Rc::new(RefCell::new(Prefixed::default())))
+        .budget(Budget::Steps(0));
+    let (_, report) = structure(&opts);
+    assert!(report.budget_exhausted && report.gotos > 0);
+    assert!(!report.labels.is_empty());
+    let mut ids: Vec<_> = report.labels.iter().map(|&(l, _)| l).collect();
+    ids.dedup();
+    assert_eq!(ids.len(), report.labels.len());
+    assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    let first = 1 + report.var_names.len();
+    for (i, (_, name)) in report.labels.iter().enumerate() {
+        assert_eq!(*name, format!("L{}", first + i));
+    }
+    #[cfg(feature = "serde")]
+    {
+        let (label, name) = &report.labels[0];
+        assert!(serde_json::to_string(&report)
+            .unwrap()
+            .contains(&format!("\"labels\":[[{},\"{}\"]", label.0, name)));
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn report_json_escapes_names() {
+    // the names come from the caller's `Namer`, so they may be anything
+    let report = StructuringReport {
+        var_names: vec!["a\u{1b}\"b".to_owned()],
+        labels: vec![(LabelId(3), "\t\\".to_owned())],
+        ..Default::default()
+    };
+    let json = serde_json::to_value(&report).unwrap();
+    let var_names = json.get("var_names").unwrap().as_array().unwrap();
+    assert_eq!(var_names[0].as_str(), Some("a\u{1b}\"b"));
+    let label = &json.get("labels").unwrap().as_array().unwrap()[0];
+    assert_eq!(label.as_array().unwrap()[0].as_u64(), Some(3));
+    assert_eq!(label.as_array().unwrap()[1].as_str(), Some("\t\\"));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn report_json_escapes_errors() {
+    // what failed may be told in any text
+    let report = StructuringReport {
+        failed_regions: vec![FailedRegion {
+            header: NodeIndex::new(1),
+            nodes: vec![NodeIndex::new(1)],
+            error: StructureError::Internal {
+                location: "test",
+                detail: "\u{7}\"\n".to_owned(),
+            },
+        }],
+        ..Default::default()
+    };
+    let json = serde_json::to_value(&report).unwrap();
+    let error = json.get("failed_regions").unwrap().as_array().unwrap()[0]
+        .get("error")
+        .unwrap();
+    let detail = error.get("Internal").unwrap().get("detail").unwrap();
+    assert_eq!(detail.as_str(), Some("\u{7}\"\n"));
+}
+
+#[test]
+fn preheaders() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // if (c) a; else b; do { h; } while (l); return;
+    // and the same loop entered at `h` from `c` itself
+    #[rustfmt::skip]
+    let shapes: [&[_]; 2] = [
+        &[
+            ("c", "a", CETrue), ("c", "b", CEFalse), ("a", "h", CETrue),
+            ("b", "h", CETrue), ("h", "l", CETrue), ("l", "h", CETrue),
+            ("l", "return", CEFalse),
+        ],
+        &[
+            ("c", "h", CETrue), ("c", "b", CEFalse), ("b", "return", CETrue),
+            ("h", "l", CETrue), ("l", "h", CETrue), ("l", "return", CEFalse),
+        ],
+    ];
+    for edges in shapes.iter() {
+        let cfg = named_cfg(cctx, &["c", "l"], edges);
+        let paths = cfg.paths(|values| StringMachine::new(values, &[]), 0, 100);
+        let opts = StructuringOptions::default().insert_preheaders(true);
+        let (ast, actx, report) = cfg.structure_whole_reported(&opts);
+        assert_eq!(report.preheaders.len(), 1);
+        let mk_machine = |values: &[(&String, bool)]| StringMachine::new(values, &actx.vars);
+        if let Err(div) = paths.check(&ast, mk_machine) {
+            panic!("{}\nast: {:#?}", div, ast);
+        }
+        // the preheader leaves nothing behind
+        let ast = stringify_conds(ast);
+        assert!(decisions::preorder(&ast)
+            .into_iter()
+            .all(|a| !matches!(a, AstNodeC::Seq(seq) if seq.is_empty())));
+    }
+
+    // the header goes on from its preheader, which all of its entries go to
+    let mut cfg = named_cfg(cctx, &["c", "l"], shapes[0]);
+    cfg.insert_preheaders();
+    let preheader = cfg.report.preheaders[0];
+    let h = cfg.graph.neighbors(preheader).collect::<Vec<_>>();
+    assert_eq!(h.len(), 1);
+    let preds: Vec<_> = cfg.graph.neighbors_directed(h[0], Incoming).collect();
+    assert_eq!(preds.len(), 2);
+    assert!(preds.contains(&preheader));
+
+    // already entered from a single code node, and a loop entered at two
+    // nodes, which isn't natural
+    #[rustfmt::skip]
+    let mut cfg = named_cfg(cctx, &["c", "l", "m"], &[
+        ("s", "h", CETrue), ("h", "l", CETrue), ("l", "h", CETrue),
+        ("l", "c", CEFalse), ("c", "x", CETrue), ("c", "y", CEFalse),
+        ("x", "y", CETrue), ("y", "m", CETrue), ("m", "x", CETrue),
+        ("m", "return", CEFalse),
+    ]);
+    cfg.insert_preheaders();
+    assert_eq!(cfg.report.preheaders, Vec::new());
+}
+
+/// The blocks of each code node of `cfg`, sorted.
+fn code_chains<'a>(cfg: &'a ControlFlowGraph<StringAst>) -> Vec<Vec<&'a str>> {
+    let mut chains: Vec<Vec<&str>> = cfg
+        .graph
+        .node_indices()
+        .filter_map(|n| match &cfg.graph[n] {
+            CfgNode::Code(ast) => Some(
+                decisions::preorder(ast)
+                    .into_iter()
+                    .filter_map(|a| match a {
+                        AstNodeC::BasicBlock(b) => Some(&b[..]),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        })
+        .collect();
+    chains.sort();
+    chains
+}
+
+#[test]
+fn linear_chains() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // a; b; if (c) { d; e; } do { h; i; } while (l); r; return;
+    #[rustfmt::skip]
+    let edges = [
+        ("a", "b", CETrue), ("b", "c", CETrue), ("c", "d", CETrue),
+        ("c", "h", CEFalse), ("d", "e", CETrue), ("e", "h", CETrue),
+        ("h", "i", CETrue), ("i", "l", CETrue), ("l", "h", CETrue),
+        ("l", "r", CEFalse), ("r", "return", CETrue),
+    ];
+    let mut cfg = named_cfg(cctx, &["c", "l"], &edges);
+    cfg.merge_linear_chains();
+    // the loop header keeps its own node, with what comes after it
+    assert_eq!(
+        code_chains(&cfg),
+        vec![
+            vec!["a", "b"],
+            vec!["d", "e"],
+            vec!["h", "i"],
+            vec!["r", "return"]
+        ]
+    );
+    assert_eq!(cfg.graph.node_count(), 6);
+    assert_eq!(cfg.report.merged_chains.len(), 4);
+    assert!(cfg
+        .report
+        .merged_chains
+        .iter()
+        .all(|(head, chain)| cfg.graph.contains_node(*head)
+            && chain.iter().all(|&n| !cfg.graph.contains_node(n))));
+
+    // it structures the same as without merging, and runs the same
+    let (merged, _) = cfg.structure_whole();
+    let (unmerged, _) = named_cfg(cctx, &["c", "l"], &edges).structure_whole();
+    assert_eq!(stringify_conds(merged), stringify_conds(unmerged));
+    let cfg = named_cfg(cctx, &["c", "l"], &edges);
+    let paths = cfg.paths(|values| StringMachine::new(values, &[]), 0, 100);
+    let opts = StructuringOptions::default().merge_linear_chains(true);
+    let (ast, actx, report) = cfg.structure_whole_reported(&opts);
+    assert_eq!(report.merged_chains.len(), 4);
+    let mk_machine = |values: &[(&String, bool)]| StringMachine::new(values, &actx.vars);
+    if let Err(div) = paths.check(&ast, mk_machine) {
+        panic!("{}\nast: {:#?}", div, ast);
+    }
+
+    // a node with an `Unwind` edge stays on its own, and a landing pad
+    // heads a chain, which keeps the marks of the nodes merged into it
+    #[rustfmt::skip]
+    let mut cfg = named_cfg(cctx, &[], &[
+        ("s", "t", CETrue), ("t", "u", CETrue), ("t", "pad", CfgEdge::Unwind),
+        ("u", "return", CETrue), ("pad", "v", CETrue),
+    ]);
+    let v = cfg
+        .graph
+        .node_indices()
+        .find(|&n| matches!(&cfg.graph[n], CfgNode::Code(AstNodeC::BasicBlock(b)) if b == "v"))
+        .unwrap();
+    cfg.set_no_duplicate(v);
+    cfg.merge_linear_chains();
+    assert_eq!(
+        code_chains(&cfg),
+        vec![vec!["pad", "v"], vec!["s"], vec!["t"], vec!["u", "return"]]
+    );
+    let pad = cfg
+        .report
+        .merged_chains
+        .iter()
+        .find(|(_, chain)| chain == &[v])
+        .unwrap()
+        .0;
+    assert!(cfg.no_duplicate.contains(pad));
+    assert!(!cfg.no_duplicate.contains(v));
+}
+
+#[test]
+fn instruction_granular() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // 8 `if`s in a row, with a node for each of the 50 instructions before
+    // each of them and in each of their arms
+    const INSNS: usize = 50;
+    let names: Vec<Vec<String>> = (0..16)
+        .map(|k| (0..INSNS).map(|i| format!("{}.{}", k, i)).collect())
+        .collect();
+    let conds: Vec<_> = (0..8).map(|k| format!("p{}", k)).collect();
+    let mut edges = Vec::new();
+    for k in 0..8 {
+        let (before, arm) = (&names[2 * k], &names[2 * k + 1]);
+        for chain in &[before, arm] {
+            for pair in chain.windows(2) {
+                edges.push((&pair[0][..], &pair[1][..], CETrue));
+            }
+        }
+        let next = names.get(2 * k + 2).map_or("return", |n| &n[0][..]);
+        edges.push((&before[INSNS - 1][..], &conds[k][..], CETrue));
+        edges.push((&conds[k][..], &arm[0][..], CETrue));
+        edges.push((&conds[k][..], next, CEFalse));
+        edges.push((&arm[INSNS - 1][..], next, CETrue));
+    }
+    let cond_names: Vec<_> = conds.iter().map(|c| &c[..]).collect();
+
+    let mut cfg = named_cfg(cctx, &cond_names, &edges);
+    assert_eq!(cfg.graph.node_count(), 16 * INSNS + 9);
+    cfg.merge_linear_chains();
+    assert_eq!(cfg.graph.node_count(), 16 + 9);
+    let (merged, _) = cfg.structure_whole();
+    let cfg = named_cfg(cctx, &cond_names, &edges);
+    let (unmerged, _) = cfg.structure_whole();
+    assert_eq!(stringify_conds(merged), stringify_conds(unmerged));
+}
+
+#[test]
+fn side_exits() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // if (a) { if (c) b; else goto err; d; } else { err: err; } return;
+    #[rustfmt::skip]
+    let edges = [
+        ("a", "c", CETrue), ("a", "err", CEFalse), ("c", "b", CETrue),
+        ("c", "err", CEFalse), ("b", "d", CETrue), ("d", "return", CETrue),
+        ("err", "return", CETrue),
+    ];
+    let (c, err, d) = (NodeIndex::new(1), NodeIndex::new(2), NodeIndex::new(4));
+    // `{c, b}`, as a region going on to `d`, would drop the edge from `c`
+    // to the error block
+    let region: NodeSet = [c, NodeIndex::new(3)].iter().collect();
+    let defect = InputDefect::SideExit {
+        header: c,
+        from: c,
+        to: err,
+    };
+
+    let mut cfg = named_cfg(cctx, &["a", "c"], &edges);
+    let opts = StructuringOptions::default().input_mode(InputMode::Strict);
+    let ret = cfg.collapse_acyclic_region(&opts, TraceOp::AcyclicCollapse, c, &region, Some(d));
+    assert_eq!(ret, Err(StructureError::Input(defect.clone())));
+    assert_eq!(cfg.graph.node_count(), 6);
+    assert!(cfg.report.side_exits.is_empty());
+
+    let mut cfg = named_cfg(cctx, &["a", "c"], &edges);
+    let paths = cfg.paths(|values| StringMachine::new(values, &[]), 0, 100);
+    let opts = StructuringOptions::default();
+    cfg.collapse_acyclic_region(&opts, TraceOp::AcyclicCollapse, c, &region, Some(d))
+        .unwrap();
+    assert_eq!(cfg.report.side_exits, vec![(c, err)]);
+    assert_eq!(cfg.report.warnings, vec![defect]);
+    let (ast, actx) = cfg.structure_whole();
+    let nodes = decisions::preorder(&ast);
+    let gotos: Vec<_> = nodes
+        .iter()
+        .filter_map(|a| match a {
+            AstNodeC::Goto(l) => Some(*l),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(gotos.len(), 1);
+    assert!(nodes.contains(&&AstNodeC::Label(gotos[0])));
+    assert!(nodes.contains(&&AstNodeC::BasicBlock("err".to_owned())));
+    let mk_machine = |values: &[(&String, bool)]| StringMachine::new(values, &actx.vars);
+    if let Err(div) = paths.check(&ast, mk_machine) {
+        panic!("{}\nast: {:#?}", div, ast);
+    }
+}
+
+/// A change to the edges of a graph, as [`mutant_cfg`] takes them, that
+/// doesn't change what the graph does.
+#[derive(Copy, Clone, Debug)]
+enum Mutation {
+    /// a code node `x` becomes two, `x` going on to `x~`, which goes on to
+    /// where `x` did
+    Split,
+    /// an edge goes through a new empty node on the way
+    Empty,
+    /// an edge goes through a new condition that is always true, and whose
+    /// false edge goes to a new node that is then unreachable
+    Unreachable,
+    /// the edges, and with them the nodes, are added in another order
+    Permute,
+}
+
+type Edges = Vec<(String, String, CfgEdge)>;
+
+/// A deterministic xorshift generator, so that a failing mutant can be made
+/// again.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+/// Applies `mutation` to `edges`, a graph that has the conditions `conds`,
+/// where `rng` picks.
+fn mutate(conds: &[&str], edges: &mut Edges, mutation: Mutation, rng: &mut Rng) {
+    // new nodes are told apart by how many edges there were before them
+    let k = edges.len();
+    // the edges out of reachable nodes, so that a new node on one of them
+    // is reachable too
+    let live: Vec<_> = (0..edges.len())
+        .filter(|&i| !edges[i].0.starts_with("dead~"))
+        .collect();
+    let pick = |rng: &mut Rng| live[rng.below(live.len())];
+    match mutation {
+        Mutation::Split => {
+            let mut code: Vec<_> = edges
+                .iter()
+                .flat_map(|(from, to, _)| vec![from, to])
+                .filter(|n| !conds.contains(&&n[..]) && !n.contains('~') && !n.starts_with('?'))
+                .cloned()
+                .collect();
+            code.sort();
+            code.dedup();
+            let x = code[rng.below(code.len())].clone();
+            let tail = format!("{}~", x);
+            let at = edges.iter().position(|e| e.0 == x).unwrap_or(edges.len());
+            for e in edges.iter_mut().filter(|e| e.0 == x) {
+                e.0 = tail.clone();
+            }
+            edges.insert(at, (x, tail, CETrue));
+        }
+        Mutation::Empty => {
+            let i = pick(rng);
+            let (from, to, edge) = edges[i].clone();
+            let empty = format!("~{}", k);
+            edges[i] = (from, empty.clone(), edge);
+            edges.insert(i + 1, (empty, to, CETrue));
+        }
+        Mutation::Unreachable => {
+            let i = pick(rng);
+            let (from, to, edge) = edges[i].clone();
+            let (cond, dead) = (format!("?{}", k), format!("dead~{}", k));
+            edges[i] = (from, cond.clone(), edge);
+            edges.insert(i + 1, (cond.clone(), to.clone(), CETrue));
+            edges.insert(i + 2, (cond, dead.clone(), CEFalse));
+            edges.insert(i + 3, (dead, to, CETrue));
+        }
+        Mutation::Permute => {
+            for i in (1..edges.len()).rev() {
+                edges.swap(i, rng.below(i + 1));
+            }
+        }
+    }
+}
+
+/// A graph like [`named_cfg`] makes, but entered at `entry`, with the nodes
+/// named with a `~` first empty, and those named with a `?` first
+/// conditions that are then set to be true, see
+/// [`ControlFlowGraph::set_constant`].
+fn mutant_cfg<'cd>(
+    cctx: condition::Context<'cd, String>,
+    conds: &[&str],
+    entry: &str,
+    edges: &Edges,
+) -> ControlFlowGraph<'cd, StringAst> {
+    let mut graph = StableDiGraph::new();
+    let mut nodes = HashMap::new();
+    let mut get = |graph: &mut StableDiGraph<_, _>, name: &str| {
+        *nodes.entry(name.to_owned()).or_insert_with(|| {
+            graph.add_node(if name.starts_with('~') {
+                empty_node()
+            } else if name.starts_with('?') || conds.contains(&name) {
+                cnode(cond_s(cctx, name))
+            } else {
+                node(name)
+            })
+        })
+    };
+    for (from, to, edge) in edges {
+        let from = get(&mut graph, from);
+        let to = get(&mut graph, to);
+        graph.add_edge(from, to, *edge);
+    }
+    let entry = get(&mut graph, entry);
+    let mut constants: Vec<_> = nodes
+        .iter()
+        .filter(|(name, _)| name.starts_with('?'))
+        .map(|(_, &n)| n)
+        .collect();
+    constants.sort();
+    let mut cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    for n in constants {
+        cfg.set_constant(n, true);
+    }
+    cfg
+}
+
+/// `ast` without the blocks that mutations made up, those with a `~` in
+/// their name, and with the arms of each `if`-`else` the way around that
+/// makes its condition read first. Which way around structuring puts them
+/// follows the order of the nodes, which [`Mutation::Permute`] changes.
+fn strip_mutations<'cd>(
+    cctx: condition::Context<'cd, String>,
+    ast: AstNode<'cd, StringAst>,
+) -> AstNodeC<String, String, String> {
+    fn strip<'cd>(
+        cctx: condition::Context<'cd, String>,
+        ast: AstNode<'cd, StringAst>,
+    ) -> AstNode<'cd, StringAst> {
+        use self::AstNodeC::*;
+        let go = |a| strip(cctx, a);
+        match ast {
+            BasicBlock(b) if b.contains('~') => Seq(Vec::new()),
+            Seq(seq) => Seq(seq.into_iter().map(go).collect()),
+            Cond(c, t, Some(e)) if format!("{:?}", cctx.mk_not(c)) < format!("{:?}", c) => {
+                Cond(cctx.mk_not(c), Box::new(go(*e)), Some(Box::new(go(*t))))
+            }
+            Cond(c, t, e) => Cond(c, Box::new(go(*t)), e.map(|e| Box::new(go(*e)))),
+            Loop(t, b) => Loop(t, Box::new(go(*b))),
+            Switch(v, cases, default) => Switch(
+                v,
+                cases.into_iter().map(|(vs, a)| (vs, go(a))).collect(),
+                Box::new(go(*default)),
+            ),
+            ast => ast,
+        }
+    }
+    let ast = strip(cctx, ast);
+    stringify_conds(refinement::simplify_ast_node::<StringAst>(cctx, ast).unwrap_or_default())
+}
+
+/// A [`StringMachine`] that doesn't record the blocks that mutations made
+/// up, those with a `~` in their name, so that a graph and its mutants run
+/// alike.
+struct Unmutated(StringMachine);
+
+impl semantics::Machine<StringAst> for Unmutated {
+    type Visit = String;
+
+    fn run(&mut self, block: &String) -> Option<String> {
+        if block.contains('~') {
+            None
+        } else {
+            self.0.run(block)
+        }
+    }
+
+    fn test(&mut self, cond: &String) -> bool {
+        self.0.test(cond)
+    }
+
+    fn value(&mut self, var: &String) -> u64 {
+        self.0.value(var)
+    }
+}
+
+type Fixture = (
+    &'static str,
+    &'static [&'static str],
+    &'static [(&'static str, &'static str, CfgEdge)],
+);
+
+/// The graphs that [`mutations_preserve_structure`] mutates.
+#[rustfmt::skip]
+const MUTATION_FIXTURES: &[Fixture] = &[
+    // a; if (c1) b; else d; e; if (c2) f; return;
+    ("diamonds", &["c1", "c2"], &[
+        ("a", "c1", CETrue), ("c1", "b", CETrue), ("c1", "d", CEFalse),
+        ("b", "e", CETrue), ("d", "e", CETrue), ("e", "c2", CETrue),
+        ("c2", "f", CETrue), ("c2", "return", CEFalse), ("f", "return", CETrue),
+    ]),
+    // a; if (c1 && c2) t; else f; return;
+    ("short_circuit", &["c1", "c2"], &[
+        ("a", "c1", CETrue), ("c1", "c2", CETrue), ("c1", "f", CEFalse),
+        ("c2", "t", CETrue), ("c2", "f", CEFalse), ("t", "return", CETrue),
+        ("f", "return", CETrue),
+    ]),
+    // a; b; if (c) { d; e; } do { h; i; } while (l); r; return;
+    ("chains", &["c", "l"], &[
+        ("a", "b", CETrue), ("b", "c", CETrue), ("c", "d", CETrue),
+        ("c", "h", CEFalse), ("d", "e", CETrue), ("e", "h", CETrue),
+        ("h", "i", CETrue), ("i", "l", CETrue), ("l", "h", CETrue),
+        ("l", "r", CEFalse), ("r", "return", CETrue),
+    ]),
+    // a; while (h) { x; if (b) break; y; } return;
+    ("loop_break", &["h", "b"], &[
+        ("a", "h", CETrue), ("h", "x", CETrue), ("h", "return", CEFalse),
+        ("x", "b", CETrue), ("b", "return", CETrue), ("b", "y", CEFalse),
+        ("y", "h", CETrue),
+    ]),
+    // a; while (h1) { p; while (h2) q; r; } return;
+    ("nested_loops", &["h1", "h2"], &[
+        ("a", "h1", CETrue), ("h1", "p", CETrue), ("h1", "return", CEFalse),
+        ("p", "h2", CETrue), ("h2", "q", CETrue), ("h2", "r", CEFalse),
+        ("q", "h2", CETrue), ("r", "h1", CETrue),
+    ]),
+    // a; do { top; if (e) { err; return; } body; } while (l); return;
+    ("early_return", &["e", "l"], &[
+        ("a", "top", CETrue), ("top", "e", CETrue), ("e", "err", CETrue),
+        ("e", "body", CEFalse), ("body", "l", CETrue), ("l", "top", CETrue),
+        ("l", "return", CEFalse),
+    ]),
+    // a; while (h) { x; if (c) { out2; goto end; } } out1; end: return;
+    ("two_exits", &["h", "c"], &[
+        ("a", "h", CETrue), ("h", "x", CETrue), ("h", "out1", CEFalse),
+        ("x", "c", CETrue), ("c", "out2", CETrue), ("c", "h", CEFalse),
+        ("out1", "return", CETrue), ("out2", "return", CETrue),
+    ]),
+];
+
+/// Structures each fixture and mutants of it, see [`Mutation`], which must
+/// give the same AST but for the blocks the mutations made up, and run
+/// like the fixture, and the fixture like them. A mutant that doesn't is
+/// structured differently for the order of its nodes or the shape of its
+/// edges, which single graphs don't show.
+#[test]
+fn mutations_preserve_structure() {
+    use self::Mutation::*;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let all = [Split, Empty, Unreachable, Permute];
+    let mut plans: Vec<Vec<Mutation>> = all.iter().chain(&all).map(|&m| vec![m]).collect();
+    plans.push(all.to_vec());
+
+    let mut checked = 0;
+    for &(name, conds, fixture) in MUTATION_FIXTURES {
+        let edges: Edges = fixture
+            .iter()
+            .map(|&(from, to, edge)| (from.to_owned(), to.to_owned(), edge))
+            .collect();
+        let entry = fixture[0].0;
+        let mk_machine = |vars: &[Option<u64>]| {
+            let vars = vars.to_vec();
+            move |values: &[(&String, bool)]| Unmutated(StringMachine::new(values, &vars))
+        };
+        let fixture_cfg = mutant_cfg(cctx, conds, entry, &edges);
+        let fixture_paths = fixture_cfg.paths(mk_machine(&[]), 0, 100);
+        let (fixture_ast, fixture_actx) = fixture_cfg.structure_whole();
+
+        for plan in &plans {
+            let mut mutant = edges.clone();
+            for &m in plan {
+                mutate(conds, &mut mutant, m, &mut rng);
+            }
+            let context = format!("{} {:?}: {:?}", name, plan, mutant);
+            let cfg = mutant_cfg(cctx, conds, entry, &mutant);
+            let paths = cfg.paths(mk_machine(&[]), 0, 100);
+            let (ast, actx) = cfg.structure_whole();
+
+            assert_eq!(
+                strip_mutations(cctx, ast.clone()),
+                strip_mutations(cctx, fixture_ast.clone()),
+                "{}",
+                context
+            );
+            if let Err(div) = fixture_paths.check(&ast, mk_machine(&actx.vars)) {
+                panic!("{}\n{}\nast: {:#?}", context, div, ast);
+            }
+            if let Err(div) = paths.check(&fixture_ast, mk_machine(&fixture_actx.vars)) {
+                panic!("{}\n{}\nfixture ast: {:#?}", context, div, fixture_ast);
+            }
+            checked += 1;
+        }
+    }
+    assert_eq!(checked, 63);
+}
+
+/// A loop at `h`, `h; x; y; if (l) continue;`, also entered at `x` and `y`,
+/// with its header and the loops' entries.
+fn loop_entered_thrice<'cd>(
+    cctx: condition::Context<'cd, String>,
+) -> (ControlFlowGraph<'cd, StringAst>, [NodeIndex; 3]) {
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(cond_s(cctx, "a")));
+    let s2 = graph.add_node(cnode(cond_s(cctx, "b")));
+    let h = graph.add_node(node("h"));
+    let x = graph.add_node(node("x"));
+    let y = graph.add_node(node("y"));
+    let l = graph.add_node(cnode(cond_s(cctx, "l")));
+    let exit = graph.add_node(node("return"));
+    graph.add_edge(entry, s2, CEFalse);
+    graph.add_edge(entry, h, CETrue);
+    graph.add_edge(s2, y, CEFalse);
+    graph.add_edge(s2, x, CETrue);
+    // loop
+    graph.add_edge(h, x, CETrue);
+    graph.add_edge(x, y, CETrue);
+    graph.add_edge(y, l, CETrue);
+    graph.add_edge(l, exit, CEFalse);
+    graph.add_edge(l, h, CETrue);
+    (
+        ControlFlowGraph::new(graph, entry, cctx, StringAst::default()),
+        [h, x, y],
+    )
+}
+
+#[test]
+fn duplication_limit() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let blocks = |ast: &AstNode<StringAst>, name: &str| {
+        decisions::preorder(ast)
+            .into_iter()
+            .filter(|a| matches!(a, AstNodeC::BasicBlock(b) if b == name))
+            .count()
+    };
+
+    // the part from `x` is `x; y; l`, and the part from `y` is `y; l`
+    let (cfg, [h, x, y]) = loop_entered_thrice(cctx);
+    let opts = StructuringOptions::default().max_duplicated_nodes(0, 0);
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    assert!(report.duplicated.is_empty());
+    assert_eq!(
+        report.declined,
+        vec![
+            DeclinedCopy::LoopEntry {
+                header: h,
+                entry: x,
+                nodes: 3,
+                reason: DeclineReason::Limit,
+            },
+            DeclinedCopy::LoopEntry {
+                header: h,
+                entry: y,
+                nodes: 2,
+                reason: DeclineReason::Limit,
+            },
+        ]
+    );
+    assert_eq!(
+        report.fallbacks,
+        vec![Fallback::AbnormalEntries {
+            header: h,
+            entries: 2
+        }]
+    );
+    assert_eq!(report.gotos, 0);
+    assert_eq!(blocks(&ast, "y"), 1);
+
+    // only the part from `y` fits
+    let (cfg, [h, x, _]) = loop_entered_thrice(cctx);
+    let opts = StructuringOptions::default().max_duplicated_nodes(2, 2);
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    assert_eq!(report.duplicated.len(), 2);
+    assert_eq!(
+        report.declined,
+        vec![DeclinedCopy::LoopEntry {
+            header: h,
+            entry: x,
+            nodes: 3,
+            reason: DeclineReason::Limit,
+        }]
+    );
+    assert_eq!(
+        report.fallbacks,
+        vec![Fallback::AbnormalEntries {
+            header: h,
+            entries: 1
+        }]
+    );
+    assert_eq!(blocks(&ast, "x"), 1);
+    assert_eq!(blocks(&ast, "y"), 2);
+    #[cfg(feature = "serde")]
+    assert!(serde_json::to_string(&report).unwrap().contains(
+        r#""declined":[{"LoopEntry":{"header":2,"entry":3,"nodes":3,"reason":"Limit"}}]"#
+    ));
+
+    // both fit, and the loop is entered at its header only
+    let (cfg, _) = loop_entered_thrice(cctx);
+    let opts = StructuringOptions::default().max_duplicated_nodes(100, 100);
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    assert_eq!(report.duplicated.len(), 5);
+    assert!(report.declined.is_empty());
+    assert!(report.fallbacks.is_empty());
+    assert_eq!(report.gotos, 0);
+    assert_eq!(blocks(&ast, "x"), 2);
+    assert_eq!(blocks(&ast, "y"), 3);
+
+    // the total counts too: after the 3 nodes from `x`, the 2 from `y` are
+    // 1 too many
+    let (cfg, [h, _, y]) = loop_entered_thrice(cctx);
+    let opts = StructuringOptions::default().max_duplicated_nodes(100, 4);
+    let (_, _, report) = cfg.structure_whole_reported(&opts);
+    assert_eq!(report.duplicated.len(), 3);
+    assert_eq!(
+        report.declined,
+        vec![DeclinedCopy::LoopEntry {
+            header: h,
+            entry: y,
+            nodes: 2,
+            reason: DeclineReason::Limit,
+        }]
+    );
+}
+
+#[test]
+fn no_duplicate() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let blocks = |ast: &AstNode<StringAst>, name: &str| {
+        decisions::preorder(ast)
+            .into_iter()
+            .filter(|a| matches!(a, AstNodeC::BasicBlock(b) if b == name))
+            .count()
+    };
+    let opts = StructuringOptions::default().max_duplicated_nodes(100, 100);
+
+    // both parts have `y`, so the loop dispatches on a variable for both
+    // entries, however many copies the limit allows
+    let (mut cfg, [h, x, y]) = loop_entered_thrice(cctx);
+    cfg.set_no_duplicate(y);
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    assert!(report.duplicated.is_empty());
+    assert_eq!(
+        report.declined,
+        vec![
+            DeclinedCopy::LoopEntry {
+                header: h,
+                entry: x,
+                nodes: 3,
+                reason: DeclineReason::NoDuplicate(y),
+            },
+            DeclinedCopy::LoopEntry {
+                header: h,
+                entry: y,
+                nodes: 2,
+                reason: DeclineReason::NoDuplicate(y),
+            },
+        ]
+    );
+    assert_eq!(
+        report.fallbacks,
+        vec![Fallback::AbnormalEntries {
+            header: h,
+            entries: 2
+        }]
+    );
+    assert_eq!(report.gotos, 0);
+    assert_eq!(blocks(&ast, "y"), 1);
+    #[cfg(feature = "serde")]
+    assert!(serde_json::to_string(&report)
+        .unwrap()
+        .contains(r#""nodes":3,"reason":{"NoDuplicate":4}}"#));
+
+    // only the part from `x` has `x`
+    let (mut cfg, [h, x, _]) = loop_entered_thrice(cctx);
+    cfg.set_no_duplicate(x);
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    assert_eq!(report.duplicated.len(), 2);
+    assert_eq!(
+        report.declined,
+        vec![DeclinedCopy::LoopEntry {
+            header: h,
+            entry: x,
+            nodes: 3,
+            reason: DeclineReason::NoDuplicate(x),
+        }]
+    );
+    assert_eq!(blocks(&ast, "x"), 1);
+    assert_eq!(blocks(&ast, "y"), 2);
+
+    // and a shared tail stays shared
+    let (mut cfg, t) = shared_tail(cctx);
+    cfg.set_no_duplicate(t);
+    let opts = StructuringOptions::default().duplicate_tails(1);
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    assert!(report.duplicated.is_empty());
+    assert!(matches!(
+        report.declined[..],
+        [DeclinedCopy::SharedTail {
+            nodes: 1,
+            reason: DeclineReason::NoDuplicate(n),
+            ..
+        }] if n == t
+    ));
+    assert_eq!(blocks(&ast, "t"), 1);
+}
+
+#[test]
+fn abnormal_entries() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_e1 = cond_s(cctx, "e1");
+    let v_n1 = cond_s(cctx, "n1");
+    let v_n2 = cond_s(cctx, "n2");
+    let v_n3 = cond_s(cctx, "n3");
+    let v_n4 = cond_s(cctx, "n4");
+    let v_n5 = cond_s(cctx, "n5");
+    let v_l1 = cond_s(cctx, "l1");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(v_e1));
+    let n1 = graph.add_node(cnode(v_n1));
+    let n2 = graph.add_node(cnode(v_n2));
+    let n3 = graph.add_node(cnode(v_n3));
     let n4 = graph.add_node(cnode(v_n4));
-    let n8 = graph.add_node(cnode(v_n8));
-    let n5 = graph.add_node(node("n5"));
-    let n6 = graph.add_node(cnode(v_n6));
-    let n7 = graph.add_node(node("n7"));
+    let n5 = graph.add_node(cnode(v_n5));
+    let l1 = graph.add_node(cnode(v_l1));
+    let l2 = graph.add_node(node("l2"));
+    let l3 = graph.add_node(node("l3"));
     let exit = graph.add_node(node("return"));
 
-    graph.add_edge(entry, n1, CETrue);
-    graph.add_edge(entry, exit, CEFalse);
+    graph.add_edge(entry, l1, CETrue);
+    graph.add_edge(entry, n1, CEFalse);
     graph.add_edge(n1, n2, CETrue);
     graph.add_edge(n2, n3, CETrue);
-    graph.add_edge(n2, n5, CEFalse);
     graph.add_edge(n3, n4, CETrue);
-    graph.add_edge(n4, n1, CETrue);
-    graph.add_edge(n4, n8, CEFalse);
-    graph.add_edge(n8, exit, CETrue);
-    graph.add_edge(n8, n7, CEFalse);
-    graph.add_edge(n5, n6, CETrue);
-    graph.add_edge(n6, exit, CETrue);
-    graph.add_edge(n6, n7, CEFalse);
-    graph.add_edge(n7, n1, CETrue);
+    graph.add_edge(n4, n5, CETrue);
+    graph.add_edge(n5, exit, CETrue);
+    // loop
+    graph.add_edge(l1, l2, CETrue);
+    graph.add_edge(l2, l3, CETrue);
+    graph.add_edge(l3, l1, CETrue);
+    // loop exit
+    graph.add_edge(l1, exit, CEFalse);
+    // abnormal entries
+    graph.add_edge(n1, l1, CEFalse);
+    graph.add_edge(n2, l2, CEFalse);
+    graph.add_edge(n3, l3, CEFalse);
+    graph.add_edge(n4, l2, CEFalse);
+    graph.add_edge(n5, l2, CEFalse);
 
     let actx = StringAst::default();
     let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
@@ -902,7 +4496,7 @@ fn nested_continue() {
 }
 
 #[test]
-fn abnormal_entries() {
+fn abnormal_entries_reported() {
     let cstore = condition::Storage::new();
     let cctx = cstore.cctx();
 
@@ -922,8 +4516,185 @@ fn abnormal_entries() {
     let n4 = graph.add_node(cnode(v_n4));
     let n5 = graph.add_node(cnode(v_n5));
     let l1 = graph.add_node(cnode(v_l1));
-    let l2 = graph.add_node(node("l2"));
-    let l3 = graph.add_node(node("l3"));
+    let l2 = graph.add_node(node("l2"));
+    let l3 = graph.add_node(node("l3"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, l1, CETrue);
+    graph.add_edge(entry, n1, CEFalse);
+    graph.add_edge(n1, n2, CETrue);
+    graph.add_edge(n2, n3, CETrue);
+    graph.add_edge(n3, n4, CETrue);
+    graph.add_edge(n4, n5, CETrue);
+    graph.add_edge(n5, exit, CETrue);
+    // loop
+    graph.add_edge(l1, l2, CETrue);
+    graph.add_edge(l2, l3, CETrue);
+    graph.add_edge(l3, l1, CETrue);
+    // loop exit
+    graph.add_edge(l1, exit, CEFalse);
+    // abnormal entries
+    graph.add_edge(n1, l1, CEFalse);
+    graph.add_edge(n2, l2, CEFalse);
+    graph.add_edge(n3, l3, CEFalse);
+    graph.add_edge(n4, l2, CEFalse);
+    graph.add_edge(n5, l2, CEFalse);
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let (_, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+    // the loop is entered at `l2` and `l3` too
+    assert_eq!(report.loops, 1);
+    assert_eq!(
+        report.fallbacks,
+        vec![Fallback::AbnormalEntries {
+            header: l1,
+            entries: 2
+        }]
+    );
+}
+
+#[test]
+fn abnormal_entries_deterministic() {
+    fn run() -> AstNodeC<String, String, String> {
+        let cstore = condition::Storage::new();
+        let cctx = cstore.cctx();
+
+        let v_e1 = cond_s(cctx, "e1");
+        let v_n1 = cond_s(cctx, "n1");
+        let v_n2 = cond_s(cctx, "n2");
+        let v_n3 = cond_s(cctx, "n3");
+        let v_l1 = cond_s(cctx, "l1");
+
+        let mut graph = StableDiGraph::new();
+        let entry = graph.add_node(cnode(v_e1));
+        let n1 = graph.add_node(cnode(v_n1));
+        let n2 = graph.add_node(cnode(v_n2));
+        let n3 = graph.add_node(cnode(v_n3));
+        let l1 = graph.add_node(cnode(v_l1));
+        let l2 = graph.add_node(node("l2"));
+        let l3 = graph.add_node(node("l3"));
+        let l4 = graph.add_node(node("l4"));
+        let exit = graph.add_node(node("return"));
+
+        graph.add_edge(entry, l1, CETrue);
+        graph.add_edge(entry, n1, CEFalse);
+        graph.add_edge(n1, n2, CETrue);
+        graph.add_edge(n2, n3, CETrue);
+        graph.add_edge(n3, exit, CETrue);
+        // loop
+        graph.add_edge(l1, l2, CETrue);
+        graph.add_edge(l2, l3, CETrue);
+        graph.add_edge(l3, l4, CETrue);
+        graph.add_edge(l4, l1, CETrue);
+        // loop exit
+        graph.add_edge(l1, exit, CEFalse);
+        // abnormal entries
+        graph.add_edge(n1, l2, CEFalse);
+        graph.add_edge(n2, l3, CEFalse);
+        graph.add_edge(n3, l4, CEFalse);
+
+        let actx = StringAst::default();
+        let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+        stringify_conds(cfg.structure_whole().0)
+    }
+
+    // the entries are numbered in the order of their nodes, which doesn't
+    // depend on hash order, so neither does the AST
+    use self::AstNodeC::*;
+    let bb = |b: &str| BasicBlock(b.to_owned());
+    let expected = Seq(vec![
+        bb("c_4 = e1"),
+        Cond(
+            r#""c_4""#.to_owned(),
+            Box::new(bb("i_0 = 1")),
+            Some(Box::new(Seq(vec![
+                bb("c_5 = n1"),
+                Cond(
+                    r#"-"c_5""#.to_owned(),
+                    Box::new(bb("i_0 = 0")),
+                    Some(Box::new(Seq(vec![
+                        bb("c_6 = n2"),
+                        Cond(
+                            r#""c_6""#.to_owned(),
+                            Box::new(Seq(vec![
+                                bb("c_7 = n3"),
+                                Cond(r#"-"c_7""#.to_owned(), Box::new(bb("i_0 = 3")), None),
+                            ])),
+                            Some(Box::new(bb("i_0 = 2"))),
+                        ),
+                    ]))),
+                ),
+            ]))),
+        ),
+        Cond(
+            r#"Or{"c_4", -"c_5", -"c_6", -"c_7"}"#.to_owned(),
+            Box::new(Loop(
+                LoopType::PostChecked(r#""l1""#.to_owned()),
+                Box::new(Seq(vec![
+                    bb("c_1 = i_0 == 0"),
+                    Cond(
+                        r#""c_1""#.to_owned(),
+                        Box::new(bb("l2")),
+                        Some(Box::new(Seq(vec![
+                            bb("c_2 = i_0 == 1"),
+                            Cond(
+                                r#"-"c_2""#.to_owned(),
+                                Box::new(Seq(vec![
+                                    bb("c_3 = i_0 == 2"),
+                                    Cond(
+                                        r#""c_3""#.to_owned(),
+                                        Box::new(bb("i_0 = 0")),
+                                        Some(Box::new(bb("i_0 = 0"))),
+                                    ),
+                                ])),
+                                None,
+                            ),
+                        ]))),
+                    ),
+                    Cond(
+                        r#"Or{"c_1", And{"c_3", -"c_2"}}"#.to_owned(),
+                        Box::new(bb("l3")),
+                        None,
+                    ),
+                    Cond(
+                        r#"And{-"c_1", "c_2"}"#.to_owned(),
+                        Box::new(bb("i_0 = 0")),
+                        Some(Box::new(bb("l4"))),
+                    ),
+                ])),
+            )),
+            None,
+        ),
+        bb("return"),
+    ]);
+    assert_eq!(run(), expected);
+}
+
+#[test]
+fn abnormal_exits() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_e1 = cond_s(cctx, "e1");
+    let v_l1 = cond_s(cctx, "l1");
+    let v_l2 = cond_s(cctx, "l2");
+    let v_l3 = cond_s(cctx, "l3");
+    let v_l4 = cond_s(cctx, "l4");
+    let v_l5 = cond_s(cctx, "l5");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(v_e1));
+    let n1 = graph.add_node(node("n1"));
+    let n2 = graph.add_node(node("n2"));
+    let n3 = graph.add_node(node("n3"));
+    let n4 = graph.add_node(node("n4"));
+    let n5 = graph.add_node(node("n5"));
+    let l1 = graph.add_node(cnode(v_l1));
+    let l2 = graph.add_node(cnode(v_l2));
+    let l3 = graph.add_node(cnode(v_l3));
+    let l4 = graph.add_node(cnode(v_l4));
+    let l5 = graph.add_node(cnode(v_l5));
     let exit = graph.add_node(node("return"));
 
     graph.add_edge(entry, l1, CETrue);
@@ -936,24 +4707,30 @@ fn abnormal_entries() {
     // loop
     graph.add_edge(l1, l2, CETrue);
     graph.add_edge(l2, l3, CETrue);
-    graph.add_edge(l3, l1, CETrue);
+    graph.add_edge(l3, l4, CETrue);
+    graph.add_edge(l4, l5, CETrue);
+    graph.add_edge(l5, l1, CETrue);
     // loop exit
     graph.add_edge(l1, exit, CEFalse);
-    // abnormal entries
-    graph.add_edge(n1, l1, CEFalse);
-    graph.add_edge(n2, l2, CEFalse);
-    graph.add_edge(n3, l3, CEFalse);
-    graph.add_edge(n4, l2, CEFalse);
-    graph.add_edge(n5, l2, CEFalse);
+    graph.add_edge(l4, exit, CEFalse);
+    // abnormal exits
+    graph.add_edge(l2, n2, CEFalse);
+    graph.add_edge(l3, n2, CEFalse);
+    graph.add_edge(l5, n5, CEFalse);
 
     let actx = StringAst::default();
     let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
     let ast = cfg.structure_whole().0;
     println!("{:#?}", ast);
+    // which of them it was isn't known after the loop, so the variable that
+    // tells stays
+    let assigns =
+        |a: &AstNode<StringAst>| matches!(a, AstNodeC::BasicBlock(b) if b.starts_with("i_0 = "));
+    assert_eq!(count(&ast, &assigns), 3);
 }
 
 #[test]
-fn abnormal_exits() {
+fn abnormal_exits_reported() {
     let cstore = condition::Storage::new();
     let cctx = cstore.cctx();
 
@@ -1001,10 +4778,956 @@ fn abnormal_exits() {
 
     let actx = StringAst::default();
     let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let (_, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+    // the loop is left for `n2` and `n5` too
+    assert_eq!(report.loops, 1);
+    assert_eq!(
+        report.fallbacks,
+        vec![Fallback::AbnormalExits {
+            successor: exit,
+            exits: 2
+        }]
+    );
+}
+
+#[test]
+fn region_tree_nesting() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_a = cond_s(cctx, "a");
+    let v_b = cond_s(cctx, "b");
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("entry"));
+    let ca = graph.add_node(cnode(v_a));
+    let n1 = graph.add_node(node("n1"));
+    let n2 = graph.add_node(node("n2"));
+    let j = graph.add_node(node("j"));
+    let cb = graph.add_node(cnode(v_b));
+    let n3 = graph.add_node(node("n3"));
+    let exit = graph.add_node(node("exit"));
+
+    let e_entry = graph.add_edge(entry, ca, CETrue);
+    graph.add_edge(ca, n1, CETrue);
+    graph.add_edge(ca, n2, CEFalse);
+    graph.add_edge(n1, j, CETrue);
+    graph.add_edge(n2, j, CETrue);
+    let e_j = graph.add_edge(j, cb, CETrue);
+    graph.add_edge(cb, n3, CETrue);
+    graph.add_edge(cb, exit, CEFalse);
+    graph.add_edge(n3, exit, CETrue);
+
+    let actx = StringAst::default();
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, actx);
+    let tree = cfg.region_tree();
+    println!("{:#?}", tree);
+
+    let diamond = tree
+        .regions
+        .iter()
+        .position(|r| r.entry == Some(e_entry))
+        .unwrap();
+    assert_eq!(tree.regions[diamond].exit, Some(e_j));
+    assert_eq!(tree.regions[diamond].parent, Some(0));
+    assert_eq!(tree.regions[diamond].children.len(), 2);
+    let mut diamond_nodes = tree.all_nodes(diamond);
+    diamond_nodes.sort();
+    assert_eq!(diamond_nodes, vec![ca, n1, n2, j]);
+
+    // the `if` arm, on its own
+    let arm = tree.regions.iter().position(|r| r.header == n3).unwrap();
+    assert_eq!(tree.regions[arm].nodes, vec![n3]);
+    assert_eq!(tree.regions[arm].parent, Some(0));
+
+    let postorder = tree.postorder();
+    assert_eq!(postorder.len(), tree.regions.len());
+    assert_eq!(postorder.last(), Some(&0));
+    for &child in &tree.regions[diamond].children {
+        let pos = |r| postorder.iter().position(|&p| p == r);
+        assert!(pos(child) < pos(diamond));
+    }
+
     let ast = cfg.structure_whole().0;
     println!("{:#?}", ast);
 }
 
+#[test]
+fn regions() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    /// The headers of the regions, with the children of each in brackets.
+    fn shape(r: &NestedRegion, names: &HashMap<NodeIndex, &str>) -> String {
+        let children: Vec<_> = r.children.iter().map(|c| shape(c, names)).collect();
+        if children.is_empty() {
+            names[&r.header].to_owned()
+        } else {
+            format!("{}[{}]", names[&r.header], children.join(" "))
+        }
+    }
+    fn members(r: &NestedRegion, names: &HashMap<NodeIndex, &str>) -> String {
+        let members: Vec<_> = r
+            .members
+            .ones()
+            .map(|n| names[&NodeIndex::new(n)])
+            .collect();
+        members.join(" ")
+    }
+
+    // if (a) { if (b) x; else y; } else z; return
+    let mut graph = StableDiGraph::new();
+    let mut names = HashMap::new();
+    let mut add = |graph: &mut StableDiGraph<_, _>, name, n| {
+        let n = graph.add_node(n);
+        names.insert(n, name);
+        n
+    };
+    let entry = add(&mut graph, "entry", node("entry"));
+    let ca = add(&mut graph, "a", cnode(cond_s(cctx, "a")));
+    let cb = add(&mut graph, "b", cnode(cond_s(cctx, "b")));
+    let x = add(&mut graph, "x", node("x"));
+    let y = add(&mut graph, "y", node("y"));
+    let jb = add(&mut graph, "jb", node("jb"));
+    let z = add(&mut graph, "z", node("z"));
+    let ja = add(&mut graph, "ja", node("ja"));
+    let exit = add(&mut graph, "return", node("return"));
+    graph.add_edge(entry, ca, CETrue);
+    graph.add_edge(ca, cb, CETrue);
+    graph.add_edge(ca, z, CEFalse);
+    graph.add_edge(cb, x, CETrue);
+    graph.add_edge(cb, y, CEFalse);
+    graph.add_edge(x, jb, CETrue);
+    graph.add_edge(y, jb, CETrue);
+    graph.add_edge(jb, ja, CETrue);
+    graph.add_edge(z, ja, CETrue);
+    graph.add_edge(ja, exit, CETrue);
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let root = cfg.regions();
+    assert_eq!(shape(&root, &names), "entry[a[z b[y x]]]");
+    assert_eq!(root.exit, None);
+    assert_eq!(members(&root, &names), "entry a b x y jb z ja return");
+    let outer = &root.children[0];
+    assert_eq!(outer.exit, Some(exit));
+    assert_eq!(members(outer, &names), "a b x y jb z ja");
+    let inner = &outer.children[1];
+    assert_eq!(inner.exit, Some(ja));
+    assert_eq!(members(inner, &names), "b x y jb");
+    // the graph is left as it is
+    assert_eq!(cfg.graph.node_count(), 9);
+    // and structuring collapses the same regions
+    let collapsed: Vec<_> = cfg
+        .region_tree()
+        .regions
+        .iter()
+        .map(|r| (r.header, r.successor))
+        .collect();
+    let nested: Vec<_> = root.iter().map(|r| (r.header, r.exit)).collect();
+    assert_eq!(collapsed.len(), nested.len());
+    assert!(collapsed.iter().all(|r| nested.contains(r)));
+
+    // a loop with an `if` in its body: the loop is a region, and so is the
+    // `if` in it
+    let mut graph = StableDiGraph::new();
+    let mut names = HashMap::new();
+    let mut add = |graph: &mut StableDiGraph<_, _>, name, n| {
+        let n = graph.add_node(n);
+        names.insert(n, name);
+        n
+    };
+    let entry = add(&mut graph, "entry", node("entry"));
+    let h = add(&mut graph, "h", cnode(cond_s(cctx, "h")));
+    let t = add(&mut graph, "t", node("t"));
+    let l = add(&mut graph, "l", cnode(cond_s(cctx, "l")));
+    let exit = add(&mut graph, "return", node("return"));
+    graph.add_edge(entry, h, CETrue);
+    graph.add_edge(h, t, CETrue);
+    graph.add_edge(h, l, CEFalse);
+    graph.add_edge(t, l, CETrue);
+    graph.add_edge(l, h, CETrue);
+    graph.add_edge(l, exit, CEFalse);
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let root = cfg.regions();
+    assert_eq!(shape(&root, &names), "entry[h[t]]");
+    let body = &root.children[0];
+    assert_eq!(body.exit, Some(exit));
+    assert_eq!(members(body, &names), "h t l");
+    assert_eq!(body.children[0].exit, Some(l));
+    let headers: Vec<_> = root.iter().map(|r| names[&r.header]).collect();
+    assert_eq!(headers, ["entry", "h", "t"]);
+}
+
+/// Renames SSA-ish value names that are whole words in a condition.
+struct WordNames(HashMap<&'static str, &'static str>);
+
+impl WordNames {
+    fn rename(&self, s: &str) -> Option<String> {
+        let mut renamed = false;
+        let words: Vec<_> = s
+            .split(' ')
+            .map(|w| match self.0.get(w) {
+                Some(n) => {
+                    renamed = true;
+                    *n
+                }
+                None => w,
+            })
+            .collect();
+        if renamed {
+            Some(words.join(" "))
+        } else {
+            None
+        }
+    }
+}
+
+impl rename::Renamer<String, String> for WordNames {
+    fn rename_cond(&mut self, cond: &String) -> Option<String> {
+        self.rename(cond)
+    }
+
+    fn rename_var(&mut self, var: &String) -> Option<String> {
+        self.rename(var)
+    }
+}
+
+#[test]
+fn rename_partial() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let v_lt = cctx.mk_var(cond_s(cctx, "v_0x8(rsp) < 10"));
+    let v_zero = cctx.mk_var(cond_s(cctx, "rax_3 == 0"));
+    let v_ne = cctx.mk_var(cond_s(cctx, "v_0x8(rsp) != 1"));
+
+    use self::AstNodeC::*;
+    let ast: AstNode<StringAst> = Seq(vec![
+        Loop(
+            LoopType::PreChecked(v_lt),
+            Box::new(Seq(vec![
+                Cond(cctx.mk_and(v_zero, v_ne), Box::new(Break), None),
+                Switch(
+                    "rdi_1".to_owned(),
+                    vec![(ValueSet::single(1), BasicBlock("a".to_owned()))],
+                    Box::new(BasicBlock("b".to_owned())),
+                ),
+            ])),
+        ),
+        Cond(cctx.mk_not(v_lt), Box::new(Return), None),
+        Switch(
+            "rax_3".to_owned(),
+            vec![(ValueSet::single(2), BasicBlock("c".to_owned()))],
+            Box::new(Seq(Vec::new())),
+        ),
+    ]);
+
+    // `rax_3` stays unnamed
+    let mut names = WordNames(
+        vec![("v_0x8(rsp)", "argc"), ("rdi_1", "argv")]
+            .into_iter()
+            .collect(),
+    );
+    let ast = rename::rename::<StringAst, _>(cctx, ast, &mut names);
+
+    // the loop guard and the condition after the loop still use the same leaf
+    let guard_vars = match &ast {
+        Seq(seq) => match (&seq[0], &seq[1]) {
+            (Loop(LoopType::PreChecked(g), _), Cond(c, _, _)) => (g.vars(), c.vars()),
+            _ => panic!("unexpected shape: {:?}", ast),
+        },
+        _ => panic!("unexpected shape: {:?}", ast),
+    };
+    assert_eq!(guard_vars.0, guard_vars.1);
+
+    assert_eq!(
+        Seq(vec![
+            Loop(
+                LoopType::PreChecked(r#""argc < 10""#.to_owned()),
+                Box::new(Seq(vec![
+                    Cond(
+                        r#"And{"rax_3 == 0", "argc != 1"}"#.to_owned(),
+                        Box::new(Break),
+                        None
+                    ),
+                    Switch(
+                        "argv".to_owned(),
+                        vec![(ValueSet::single(1), BasicBlock("a".to_owned()))],
+                        Box::new(BasicBlock("b".to_owned())),
+                    ),
+                ])),
+            ),
+            Cond(r#"-"argc < 10""#.to_owned(), Box::new(Return), None),
+            Switch(
+                "rax_3".to_owned(),
+                vec![(ValueSet::single(2), BasicBlock("c".to_owned()))],
+                Box::new(Seq(Vec::new())),
+            ),
+        ]),
+        stringify_conds(ast)
+    );
+}
+
+/// Like `ControlFlowGraph::new`, but without checking its preconditions, as
+/// a release build doesn't.
+fn unchecked_cfg<'cd>(
+    graph: StableDiGraph<CfgNode<'cd, StringAst>, CfgEdge>,
+    entry: NodeIndex,
+    cctx: condition::Context<'cd, String>,
+) -> ControlFlowGraph<'cd, StringAst> {
+    ControlFlowGraph {
+        graph,
+        entry,
+        cctx,
+        actx: StringAst::default(),
+        value_sets: HashMap::new(),
+        branch_weights: HashMap::new(),
+        no_duplicate: NodeSet::new(),
+        report: StructuringReport::default(),
+        trace: None,
+        observer: None,
+        namer: None,
+        cancel: None,
+        dominators: None,
+        budget: None,
+        dump: None,
+        decisions: RefCell::default(),
+        struct_vars: Vec::new(),
+    }
+}
+
+/// Graphs that `ControlFlowGraph::new` asserts against. Structuring them
+/// used to trip the `debug_assert!`s of structuring, or a panic.
+#[test]
+fn hostile_graphs() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // a node that can't be reached, which used to leave it in the graph
+    let mut unreachable = StableDiGraph::new();
+    let entry = unreachable.add_node(node("a"));
+    let b = unreachable.add_node(node("b"));
+    let dead = unreachable.add_node(node("dead"));
+    unreachable.add_edge(entry, b, CETrue);
+    unreachable.add_edge(dead, b, CETrue);
+
+    // a loop headed by the entry, which used to have no entries
+    let mut entry_loop = StableDiGraph::new();
+    let entry = entry_loop.add_node(node("a"));
+    let b = entry_loop.add_node(node("b"));
+    entry_loop.add_edge(entry, b, CETrue);
+    entry_loop.add_edge(b, entry, CETrue);
+
+    // an unwind edge from a condition, which `split_handlers` panicked on
+    let mut throwing_cond = StableDiGraph::new();
+    let c = throwing_cond.add_node(cnode(cond_s(cctx, "c")));
+    let a = throwing_cond.add_node(node("a"));
+    let b = throwing_cond.add_node(node("b"));
+    let pad = throwing_cond.add_node(node("pad"));
+    throwing_cond.add_edge(c, a, CETrue);
+    throwing_cond.add_edge(c, b, CEFalse);
+    throwing_cond.add_edge(c, pad, CfgEdge::Unwind);
+
+    let check = |graph, detail: &str| {
+        let err = unchecked_cfg(graph, NodeIndex::new(0), cctx)
+            .structure_whole_checked(&StructuringOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            StructureError::Internal {
+                location: "validate",
+                detail: detail.to_owned()
+            }
+        );
+    };
+    check(unreachable, "node 2 can't be reached");
+    check(
+        entry_loop,
+        "node 0 is the entry or a landing pad, but has predecessors",
+    );
+    check(throwing_cond, "node 0 has the wrong successors");
+}
+
+#[derive(Debug)]
+struct PanickingSink;
+
+impl trace::TraceSink for PanickingSink {
+    fn step(&mut self, _: &trace::TraceStep) {
+        panic!("the sink broke");
+    }
+}
+
+#[test]
+fn panic_while_structuring() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(cond_s(cctx, "c")));
+    let a = graph.add_node(node("a"));
+    let b = graph.add_node(node("b"));
+    graph.add_edge(entry, a, CETrue);
+    graph.add_edge(entry, b, CEFalse);
+
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let opts = StructuringOptions {
+        trace: Some(Rc::new(RefCell::new(PanickingSink))),
+        ..Default::default()
+    };
+    assert_eq!(
+        cfg.structure_whole_checked(&opts).unwrap_err(),
+        StructureError::Internal {
+            location: "structure_whole_checked",
+            detail: "the sink broke".to_owned()
+        }
+    );
+}
+
+/// A chain of `ifs` ifs, each going on to the next.
+fn if_chain<'cd>(
+    cctx: condition::Context<'cd, String>,
+    ifs: usize,
+) -> ControlFlowGraph<'cd, StringAst> {
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("entry"));
+    let mut prev = entry;
+    for i in 0..ifs {
+        let c = graph.add_node(cnode(cond_s(cctx, &format!("c{}", i))));
+        let then = graph.add_node(node(&format!("then{}", i)));
+        let join = graph.add_node(node(&format!("join{}", i)));
+        graph.add_edge(prev, c, CETrue);
+        graph.add_edge(c, then, CETrue);
+        graph.add_edge(c, join, CEFalse);
+        graph.add_edge(then, join, CETrue);
+        prev = join;
+    }
+    ControlFlowGraph::new(graph, entry, cctx, StringAst::default())
+}
+
+#[test]
+fn no_progress() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let dir = env::temp_dir().join("radeco_no_progress_test");
+    let _ = fs::remove_dir_all(&dir);
+    let opts = StructuringOptions {
+        collapse_sese_regions: false,
+        dump_dir: Some(dir.clone()),
+        disabled_collapse: Some(TraceOp::AcyclicCollapse),
+        ..Default::default()
+    };
+
+    // a few ifs get to the end of the pass, and more stall it first
+    for &ifs in &[2, MAX_STALLED_STEPS + 8] {
+        let cfg = if_chain(cctx, ifs);
+        let node_count = cfg.graph.node_count();
+        let (headers, graph) = match cfg.structure_whole_checked(&opts) {
+            Err(StructureError::NoProgress { headers, graph }) => (headers, graph),
+            res => panic!("{:?}", res.map(|sf| sf.0)),
+        };
+        assert_eq!(
+            headers,
+            (1..node_count).map(NodeIndex::new).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("residual.json")).unwrap(),
+            graph
+        );
+
+        // which structures without the disabled collapses
+        let blocks = from_r2::parse_blocks(&graph).unwrap();
+        assert_eq!(blocks.len(), node_count);
+        let sf = from_r2::structure_blocks(&blocks, &Default::default(), &Default::default());
+        assert_eq!(count_gotos(&sf.unwrap().ast), 0);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cancel() {
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    /// Has another thread cancel structuring at the tenth step, and waits
+    /// until it did.
+    #[derive(Debug)]
+    struct CancelAt {
+        steps: usize,
+        cancel: mpsc::Sender<()>,
+        cancelled: mpsc::Receiver<Instant>,
+        at: Option<Instant>,
+    }
+
+    impl trace::TraceSink for CancelAt {
+        fn step(&mut self, _: &trace::TraceStep) {
+            self.steps += 1;
+            if self.steps == 10 {
+                self.cancel.send(()).unwrap();
+                self.at = Some(self.cancelled.recv().unwrap());
+            }
+        }
+    }
+
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let token = CancelToken::new();
+    let (cancel, requested) = mpsc::channel();
+    let (done, cancelled) = mpsc::channel();
+    let sink = Rc::new(RefCell::new(CancelAt {
+        steps: 0,
+        cancel,
+        cancelled,
+        at: None,
+    }));
+    let opts = StructuringOptions::default()
+        .trace(sink.clone())
+        .cancel(token.clone());
+    let canceller = token.clone();
+    let res = thread::scope(|s| {
+        s.spawn(move || {
+            requested.recv().unwrap();
+            canceller.cancel();
+            done.send(Instant::now()).unwrap();
+        });
+        if_chain(cctx, 300).structure_whole_checked(&opts)
+    });
+    assert_eq!(res.map(|sf| sf.0).unwrap_err(), StructureError::Cancelled);
+    // right away, at the next region
+    let sink = sink.borrow();
+    assert_eq!(sink.steps, 10);
+    assert!(sink.at.unwrap().elapsed() < Duration::from_secs(1));
+
+    // a copy of the graph is structured, which is left as it was
+    let cfg = if_chain(cctx, 2);
+    let opts = StructuringOptions::default().cancel(token);
+    assert_eq!(
+        cfg.structure_from(cfg.entry(), &opts).unwrap_err(),
+        StructureError::Cancelled
+    );
+    let (ast, _) = cfg.structure_whole_with(&opts.cancel(CancelToken::new()));
+    assert_eq!(count_gotos(&ast), 0);
+    assert_eq!(ast.metrics().conds, 2);
+}
+
+#[test]
+fn observer() {
+    #[derive(Debug, Default)]
+    struct Recorder(Vec<String>);
+
+    impl observer::Observer for Recorder {
+        fn on_loop_found(&mut self, header: NodeIndex, latches: &FixedBitSet) {
+            let latches: Vec<_> = latches.ones().collect();
+            self.0
+                .push(format!("loop {} {:?}", header.index(), latches));
+        }
+
+        fn on_region_collapsed(
+            &mut self,
+            header: NodeIndex,
+            members: &FixedBitSet,
+            result: NodeIndex,
+        ) {
+            let members: Vec<_> = members.ones().collect();
+            self.0.push(format!(
+                "collapse {} {:?} {}",
+                header.index(),
+                members,
+                result.index()
+            ));
+        }
+
+        fn on_refinement(&mut self, pass: &str, changed: bool) {
+            self.0.push(format!("refine {} {}", pass, changed));
+        }
+
+        fn on_fallback(&mut self, fallback: &Fallback) {
+            self.0.push(format!("fallback {:?}", fallback));
+        }
+    }
+
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    // a loop of `h` and `l`, which the entry goes to either of
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(cnode(cond_s(cctx, "a")));
+    let h = graph.add_node(node("h"));
+    let l = graph.add_node(cnode(cond_s(cctx, "l")));
+    let exit = graph.add_node(node("return"));
+    graph.add_edge(entry, h, CETrue);
+    graph.add_edge(entry, l, CEFalse);
+    graph.add_edge(h, l, CETrue);
+    graph.add_edge(l, h, CETrue);
+    graph.add_edge(l, exit, CEFalse);
+
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let opts = StructuringOptions::default()
+        .collapse_sese_regions(false)
+        .observer(recorder.clone());
+    let cfg = ControlFlowGraph::new(graph.clone(), entry, cctx, StringAst::default());
+    let (ast, _, report) = cfg.structure_whole_reported(&opts);
+    // `l` heads the loop, which the entry also enters at `h`, so both
+    // entries go through a new header, node 5, that dispatches to them
+    assert_eq!(
+        recorder.borrow().0,
+        [
+            "loop 2 [1]",
+            "fallback AbnormalEntries { header: NodeIndex(2), entries: 1 }",
+            "refine refine_loop false",
+            "collapse 2 [1, 2, 8] 5",
+            "collapse 0 [0, 3, 4, 5, 7] 0",
+            "refine fold_struct_vars false",
+            "refine unrotate_loops false",
+        ]
+    );
+    assert_eq!(report.fallbacks.len(), 1);
+    // the same ASTs as without it
+    let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+    let (unobserved, _) =
+        cfg.structure_whole_with(&StructuringOptions::default().collapse_sese_regions(false));
+    assert_eq!(format!("{:?}", ast), format!("{:?}", unobserved));
+}
+
+#[cfg(feature = "trace_log")]
+mod capture {
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    use std::cell::RefCell;
+    use std::sync::Once;
+
+    thread_local! {
+        static EVENTS: RefCell<Option<Vec<(Level, String)>>> = RefCell::new(None);
+    }
+
+    /// Logs into `EVENTS` of the logging thread, if it is capturing.
+    struct Capture;
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            EVENTS.with(|events| {
+                if let Some(events) = events.borrow_mut().as_mut() {
+                    events.push((record.level(), record.args().to_string()));
+                }
+            });
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture;
+
+    /// Runs `f`, and returns what it logged on this thread.
+    pub fn capture<F: FnOnce()>(f: F) -> Vec<(Level, String)> {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&CAPTURE).unwrap();
+            log::set_max_level(LevelFilter::Trace);
+        });
+        EVENTS.with(|events| *events.borrow_mut() = Some(Vec::new()));
+        f();
+        EVENTS.with(|events| events.borrow_mut().take().unwrap())
+    }
+}
+
+#[cfg(feature = "trace_log")]
+#[test]
+fn debug_events() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    let mut graph = StableDiGraph::new();
+    let entry = graph.add_node(node("a"));
+    let c1 = graph.add_node(cnode(cond_s(cctx, "c1")));
+    let b = graph.add_node(node("b"));
+    let c2 = graph.add_node(cnode(cond_s(cctx, "c2")));
+    let d = graph.add_node(node("d"));
+    let e = graph.add_node(node("e"));
+    let exit = graph.add_node(node("return"));
+
+    graph.add_edge(entry, c1, CETrue);
+    graph.add_edge(c1, b, CETrue);
+    graph.add_edge(b, c1, CETrue);
+    graph.add_edge(c1, c2, CEFalse);
+    graph.add_edge(c2, d, CETrue);
+    graph.add_edge(c2, e, CEFalse);
+    graph.add_edge(d, exit, CETrue);
+    graph.add_edge(e, exit, CETrue);
+
+    let events = capture::capture(|| {
+        let cfg = ControlFlowGraph::new(graph, entry, cctx, StringAst::default());
+        cfg.structure_whole();
+    });
+    let debug: Vec<_> = events
+        .iter()
+        .filter(|(level, _)| *level == log::Level::Debug)
+        .map(|(_, msg)| &**msg)
+        .collect();
+    assert_eq!(
+        debug,
+        [
+            "structure: graph entry=0 nodes=7 edges=8",
+            // the `if` and the `return` after the loop
+            "structure: acyclic region header=3 nodes=4",
+            "structure: loop header=1 nodes=2 latches=1",
+            "structure: acyclic region header=0 nodes=3",
+        ]
+    );
+    let visits = events
+        .iter()
+        .filter(|(level, msg)| *level == log::Level::Trace && msg.contains("visit"))
+        .count();
+    assert_eq!(visits, 7);
+}
+
+/// Runs what `StringAst` makes up: `x = v` assigns to `x`, and `x == v`
+/// tests it, where `v` is a number or a condition.
+struct StringMachine {
+    conds: HashMap<String, bool>,
+    vars: HashMap<String, u64>,
+}
+
+impl StringMachine {
+    fn new(values: &[(&String, bool)], inits: &[Option<u64>]) -> Self {
+        let vars = inits
+            .iter()
+            .enumerate()
+            .filter_map(|(i, init)| init.map(|v| (format!("i_{}", i), v)))
+            .collect();
+        StringMachine {
+            conds: values.iter().map(|&(c, v)| (c.clone(), v)).collect(),
+            vars,
+        }
+    }
+}
+
+impl semantics::Machine<StringAst> for StringMachine {
+    type Visit = String;
+
+    fn run(&mut self, block: &String) -> Option<String> {
+        let mut parts = block.splitn(2, " = ");
+        let var = parts.next().unwrap().to_owned();
+        let val = match parts.next() {
+            Some(val) => val,
+            None => return Some(block.clone()),
+        };
+        match val.parse() {
+            Ok(val) => drop(self.vars.insert(var, val)),
+            Err(_) => {
+                let val = self.test(&val.to_owned());
+                self.conds.insert(var, val);
+            }
+        }
+        None
+    }
+
+    fn test(&mut self, cond: &String) -> bool {
+        let mut parts = cond.splitn(2, " == ");
+        let var = parts.next().unwrap();
+        match parts.next() {
+            Some(val) => self.value(&var.to_owned()) == val.parse::<u64>().unwrap(),
+            None => self.conds[var],
+        }
+    }
+
+    fn value(&mut self, var: &String) -> u64 {
+        // the fresh variables that aren't zeroed can start out as anything
+        self.vars.get(var).cloned().unwrap_or(u64::MAX)
+    }
+}
+
+/// A graph with `edges` between nodes named by their blocks, or by their
+/// conditions for those in `conds`. The first node named is the entry.
+fn named_cfg<'cd>(
+    cctx: condition::Context<'cd, String>,
+    conds: &[&str],
+    edges: &[(&str, &str, CfgEdge)],
+) -> ControlFlowGraph<'cd, StringAst> {
+    let mut graph = StableDiGraph::new();
+    let mut nodes = HashMap::new();
+    let mut get = |graph: &mut StableDiGraph<_, _>, name: &str| {
+        *nodes.entry(name.to_owned()).or_insert_with(|| {
+            graph.add_node(if conds.contains(&name) {
+                cnode(cond_s(cctx, name))
+            } else {
+                node(name)
+            })
+        })
+    };
+    let mut entry = None;
+    for &(from, to, edge) in edges {
+        let from = get(&mut graph, from);
+        let to = get(&mut graph, to);
+        entry = entry.or(Some(from));
+        graph.add_edge(from, to, edge);
+    }
+    ControlFlowGraph::new(graph, entry.unwrap(), cctx, StringAst::default())
+}
+
+/// Negates the condition of the first `Cond` of `ast`.
+fn flip_first_cond<'cd>(
+    cctx: CondContext<'cd, StringAst>,
+    ast: &mut AstNode<'cd, StringAst>,
+) -> bool {
+    use self::AstNodeC::*;
+    match ast {
+        Cond(c, _, _) => {
+            *c = cctx.mk_not(*c);
+            true
+        }
+        Seq(seq) => seq.iter_mut().any(|a| flip_first_cond(cctx, a)),
+        Loop(_, body) => flip_first_cond(cctx, body),
+        _ => false,
+    }
+}
+
+/// Checks the AST of `cfg` against it, then checks that negating one of its
+/// conditions is caught.
+fn check_paths<'cd>(
+    cctx: CondContext<'cd, StringAst>,
+    cfg: ControlFlowGraph<'cd, StringAst>,
+    samples: usize,
+) {
+    let paths = cfg.paths(|values| StringMachine::new(values, &[]), samples, 100);
+    let (mut ast, actx) = cfg.structure_whole();
+    let mk_machine = |values: &[(&String, bool)]| StringMachine::new(values, &actx.vars);
+    if let Err(div) = paths.check(&ast, mk_machine) {
+        panic!("{}\nast: {:#?}", div, ast);
+    }
+    assert!(flip_first_cond(cctx, &mut ast));
+    assert!(paths.check(&ast, mk_machine).is_err(), "{:#?}", ast);
+}
+
+#[test]
+fn path_semantics() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // the example of the paper, with every assignment of its 9 conditions
+    let conds = ["A", "c1", "c2", "c3", "b1", "b2", "d1", "d2", "d3"];
+    #[rustfmt::skip]
+    let cfg = named_cfg(cctx, &conds, &[
+        ("A", "c1", CETrue), ("A", "b1", CEFalse), ("n9", "return", CETrue),
+        ("c1", "n1", CETrue), ("n1", "c1", CETrue), ("c1", "c2", CEFalse),
+        ("c2", "n2", CETrue), ("n2", "n9", CETrue), ("c2", "n3", CEFalse),
+        ("n3", "c3", CETrue), ("c3", "c1", CETrue), ("c3", "n9", CEFalse),
+        ("b1", "b2", CETrue), ("b2", "n6", CETrue), ("n6", "n7", CETrue),
+        ("n7", "d1", CETrue), ("b2", "n5", CEFalse), ("n5", "n7", CETrue),
+        ("b1", "n4", CEFalse), ("n4", "n5", CETrue),
+        ("d1", "d3", CETrue), ("d3", "n8", CETrue), ("n8", "d1", CETrue),
+        ("d3", "n9", CEFalse), ("d1", "d2", CEFalse), ("d2", "n8", CETrue),
+        ("d2", "n9", CEFalse),
+    ]);
+    assert_eq!(cfg.leaves().len(), conds.len());
+    check_paths(cctx, cfg, 0);
+
+    // too many conditions for all of them: a loop around 12 `if`s
+    let names: Vec<_> = (0..13).map(|i| format!("p{}", i)).collect();
+    let blocks: Vec<_> = (0..12)
+        .map(|i| (format!("t{}", i), format!("f{}", i)))
+        .collect();
+    let mut edges = vec![("start", &names[0][..], CETrue)];
+    for i in 0..12 {
+        let (t, f) = (&blocks[i].0[..], &blocks[i].1[..]);
+        edges.push((&names[i][..], t, CETrue));
+        edges.push((&names[i][..], f, CEFalse));
+        edges.push((t, &names[i + 1][..], CETrue));
+        edges.push((f, &names[i + 1][..], CETrue));
+    }
+    edges.push((&names[12][..], &names[0][..], CETrue));
+    edges.push((&names[12][..], "return", CEFalse));
+    let conds: Vec<_> = names.iter().map(|n| &n[..]).collect();
+    let cfg = named_cfg(cctx, &conds, &edges);
+    check_paths(cctx, cfg, 200);
+}
+
+#[test]
+fn irreducible_nest_semantics() {
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+
+    // the DFS goes a, b, c, so `c` is found as a loop header first, and the
+    // slice from it to its latch `b` goes back up through `a` and `b`
+    let conds = ["a", "b", "c"];
+    #[rustfmt::skip]
+    let cfg = named_cfg(cctx, &conds, &[
+        ("start", "a", CETrue),
+        ("a", "c", CETrue), ("a", "b", CEFalse), ("b", "a", CETrue),
+        ("b", "c", CEFalse), ("c", "b", CETrue), ("c", "return", CEFalse),
+    ]);
+    check_paths(cctx, cfg, 0);
+}
+
+#[test]
+fn walk_path() {
+    use self::semantics::End;
+    let cstore = condition::Storage::new();
+    let cctx = cstore.cctx();
+    let var = |c: &str| cctx.mk_var(cond_s(cctx, c));
+    let bb = |b: &str| AstNodeC::BasicBlock(b.to_owned());
+
+    // `start; if (x && y) both; else not_both; while (l) body; end`
+    let ast: AstNode<StringAst> = AstNodeC::Seq(vec![
+        bb("start"),
+        AstNodeC::Cond(
+            cctx.mk_and(var("x"), var("y")),
+            Box::new(bb("both")),
+            Some(Box::new(bb("not_both"))),
+        ),
+        AstNodeC::Loop(LoopType::PreChecked(var("l")), Box::new(bb("body"))),
+        bb("end"),
+    ]);
+    let walk = |known: &[(&str, bool)], limit| {
+        let env = |c: &String| known.iter().find(|&&(k, _)| k == c).map(|&(_, v)| v);
+        let path = ast.walk_path(&env, limit);
+        let visits: Vec<&str> = path.visits.iter().map(|b| &b[..]).collect();
+        (visits, path.end)
+    };
+
+    // `x` decides the `if` without `y`
+    assert_eq!(
+        walk(&[("x", false), ("l", false)], 100),
+        (vec!["start", "not_both", "end"], End::Return)
+    );
+    assert_eq!(
+        walk(&[("x", true), ("y", true), ("l", false)], 100),
+        (vec!["start", "both", "end"], End::Return)
+    );
+    // but not with it true
+    assert_eq!(
+        walk(&[("x", true), ("l", false)], 100),
+        (vec!["start"], End::Undecided)
+    );
+    // nor `l` the loop
+    assert_eq!(
+        walk(&[("x", false)], 100),
+        (vec!["start", "not_both"], End::Undecided)
+    );
+    // a loop that is always taken runs until the limit, and a limit that is
+    // just enough doesn't stop the run
+    let (visits, end) = walk(&[("x", false), ("l", true)], 10);
+    assert_eq!(end, End::StepLimit);
+    assert_eq!(visits.len(), 10);
+    assert!(visits[2..].iter().all(|&b| b == "body"));
+    assert_eq!(
+        walk(&[("x", false), ("l", false)], 3),
+        (vec!["start", "not_both", "end"], End::Return)
+    );
+    assert_eq!(
+        walk(&[("x", false), ("l", false)], 2),
+        (vec!["start", "not_both"], End::StepLimit)
+    );
+
+    // an endless loop without blocks doesn't run forever
+    let spin: AstNode<StringAst> = AstNodeC::Seq(vec![
+        bb("start"),
+        AstNodeC::Loop(LoopType::Endless, Box::new(AstNodeC::default())),
+    ]);
+    let path = spin.walk_path(&|_| None, 100);
+    assert_eq!(path.visits, vec!["start"]);
+    assert_eq!(path.end, End::SilentLoop);
+}
+
 fn cond_s<'cd>(cctx: condition::Context<'cd, String>, c: &str) -> CondVar<'cd, StringAst> {
     cctx.new_var(c.to_owned())
 }
@@ -1044,7 +5767,21 @@ fn stringify_conds<'cd>(ast: AstNode<'cd, StringAst>) -> AstNodeC<String, String
             Box::new(stringify_conds(*b)),
         ),
         Loop(Endless, b) => Loop(Endless, Box::new(stringify_conds(*b))),
+        For(i, c, u, b) => For(i, format!("{:?}", c), u, Box::new(stringify_conds(*b))),
         Break => Break,
+        Continue => Continue,
+        Return => Return,
+        TailCall(b) => TailCall(b),
+        IndirectJump(b) => IndirectJump(b),
+        Goto(l) => Goto(l),
+        Label(l) => Label(l),
+        Try(b, h) => Try(Box::new(stringify_conds(*b)), h),
+        Unstructured(members) => Unstructured(
+            members
+                .into_iter()
+                .map(|(l, a)| (l, stringify_conds(a)))
+                .collect(),
+        ),
         Switch(v, cases, default) => Switch(
             v,
             cases
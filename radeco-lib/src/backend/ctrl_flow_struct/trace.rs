@@ -0,0 +1,259 @@
+//! A step-by-step trace of structuring, e.g. for a debugging UI or a bug
+//! report.
+//!
+//! Structuring reports each of its steps to the [`TraceSink`] installed in
+//! [`StructuringOptions::trace`](super::StructuringOptions::trace), if any.
+//! Each step is one region or loop of the graph being structured: its nodes
+//! are refined into an AST, and then collapsed into the node that holds the
+//! AST from then on. [`TraceCollector`] keeps the steps in memory;
+//! [`JsonLinesTrace`] writes them out as one JSON object per line.
+
+use petgraph::graph::NodeIndex;
+
+use std::fmt;
+use std::io;
+
+/// Where structuring reports its steps.
+pub trait TraceSink: fmt::Debug {
+    fn step(&mut self, step: &TraceStep);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    pub op: TraceOp,
+    /// the node the region or loop is entered through
+    pub header: NodeIndex,
+    /// all the nodes of the region or loop, `header` included
+    pub nodes: Vec<NodeIndex>,
+    /// the node that holds the resulting AST
+    pub result: NodeIndex,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TraceOp {
+    /// the acyclic region was refined into an AST
+    Refinement,
+    /// a canonical SESE region was collapsed before the main pass, see
+    /// [`StructuringOptions::collapse_sese_regions`](super::StructuringOptions::collapse_sese_regions)
+    SeseCollapse,
+    /// an acyclic region was collapsed by the main pass
+    AcyclicCollapse,
+    /// a loop was collapsed, after its body was refined
+    LoopCollapse,
+}
+
+impl TraceOp {
+    fn name(self) -> &'static str {
+        match self {
+            TraceOp::Refinement => "refinement",
+            TraceOp::SeseCollapse => "sese_collapse",
+            TraceOp::AcyclicCollapse => "acyclic_collapse",
+            TraceOp::LoopCollapse => "loop_collapse",
+        }
+    }
+}
+
+impl TraceStep {
+    /// The step as a JSON object, with the nodes as their indices.
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<_> = self.nodes.iter().map(|n| n.index().to_string()).collect();
+        format!(
+            "{{\"op\":\"{}\",\"header\":{},\"nodes\":[{}],\"result\":{}}}",
+            self.op.name(),
+            self.header.index(),
+            nodes.join(","),
+            self.result.index()
+        )
+    }
+}
+
+/// Keeps every step, in order.
+#[derive(Debug, Default)]
+pub struct TraceCollector {
+    pub steps: Vec<TraceStep>,
+}
+
+impl TraceSink for TraceCollector {
+    fn step(&mut self, step: &TraceStep) {
+        self.steps.push(step.clone());
+    }
+}
+
+/// Writes each step to a writer as a line of JSON, see
+/// [`TraceStep::to_json`]. Writing stops at the first error, which
+/// [`finish`](Self::finish) returns.
+pub struct JsonLinesTrace<W: io::Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> JsonLinesTrace<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesTrace {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Flushes the writer and returns it, or the first error.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: io::Write> fmt::Debug for JsonLinesTrace<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("JsonLinesTrace")
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl<W: io::Write> TraceSink for JsonLinesTrace<W> {
+    fn step(&mut self, step: &TraceStep) {
+        if self.error.is_none() {
+            if let Err(err) = writeln!(self.writer, "{}", step.to_json()) {
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::condition;
+    use super::super::from_r2::{self, R2BasicBlock};
+    use super::super::StructuringOptions;
+    use super::*;
+
+    use serde_json::{self, Value};
+
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::rc::Rc;
+
+    fn block(addr: u64, jump: Option<u64>, fail: Option<u64>) -> R2BasicBlock {
+        R2BasicBlock {
+            addr,
+            size: 4,
+            jump,
+            fail,
+            cases: Vec::new(),
+            default: None,
+            unresolved_jump: false,
+            esil: Vec::new(),
+        }
+    }
+
+    /// A loop with an `if` in it, and an `if` after it.
+    fn blocks() -> Vec<R2BasicBlock> {
+        vec![
+            block(0x10, Some(0x20), None),
+            block(0x20, Some(0x28), Some(0x24)),
+            block(0x24, Some(0x28), None),
+            block(0x28, Some(0x20), Some(0x30)),
+            block(0x30, Some(0x40), Some(0x38)),
+            block(0x38, Some(0x40), None),
+            block(0x40, None, None),
+        ]
+    }
+
+    /// Structures `blocks` with `sink` installed, and returns the number of
+    /// nodes of the graph it was imported as.
+    fn structure(
+        blocks: &[R2BasicBlock],
+        collapse_sese_regions: bool,
+        sink: Rc<RefCell<dyn TraceSink>>,
+    ) -> usize {
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import(cstore.cctx(), blocks).unwrap();
+        let node_count = cfg.graph.node_count();
+        let opts = StructuringOptions {
+            collapse_sese_regions,
+            trace: Some(sink),
+            ..Default::default()
+        };
+        cfg.structure_whole_with(&opts);
+        node_count
+    }
+
+    #[test]
+    fn replay() {
+        let mut collapses = Vec::new();
+        for &collapse_sese_regions in &[false, true] {
+            let collector = Rc::new(RefCell::new(TraceCollector::default()));
+            let node_count = structure(&blocks(), collapse_sese_regions, collector.clone());
+            let steps = &collector.borrow().steps;
+
+            // collapse the nodes of each step into its result, like
+            // structuring did. The headers are nodes that are still there,
+            // and so are the other nodes, unless they are ones structuring
+            // made, which may reuse the indices of removed nodes
+            let mut left: HashSet<_> = (0..node_count).map(NodeIndex::new).collect();
+            let mut collapsed = Vec::new();
+            let mut refined = HashSet::new();
+            for step in steps {
+                assert!(step.nodes.contains(&step.header), "{:?}", step);
+                assert!(left.contains(&step.header), "{:?}", step);
+                if step.op == TraceOp::Refinement {
+                    refined.insert(step.header);
+                    continue;
+                }
+                // every collapse refines its region first
+                assert!(refined.remove(&step.result), "{:?}", step);
+                for n in &step.nodes {
+                    if *n != step.result {
+                        left.remove(n);
+                    }
+                }
+                left.insert(step.result);
+                collapsed.push((step.op, step.header.index()));
+            }
+            // only the entry is left
+            assert_eq!(left.into_iter().collect::<Vec<_>>(), [NodeIndex::new(0)]);
+            assert!(refined.is_empty());
+            collapses.push(collapsed);
+        }
+
+        // the loop is collapsed once, and the entry last
+        let plain = &collapses[0];
+        let loops: Vec<_> = plain
+            .iter()
+            .filter(|c| c.0 == TraceOp::LoopCollapse)
+            .collect();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(plain.last(), Some(&(TraceOp::AcyclicCollapse, 0)));
+        // the prepass only adds collapses ahead of the main pass here
+        let sese = &collapses[1];
+        assert_eq!(sese[0].0, TraceOp::SeseCollapse);
+        assert_eq!(&sese[1..], &plain[..]);
+    }
+
+    #[test]
+    fn json_lines() {
+        let collector = Rc::new(RefCell::new(TraceCollector::default()));
+        structure(&blocks(), true, collector.clone());
+        let writer = Rc::new(RefCell::new(JsonLinesTrace::new(Vec::new())));
+        structure(&blocks(), true, writer.clone());
+
+        let writer = Rc::try_unwrap(writer).unwrap().into_inner();
+        let out = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        let steps = &collector.borrow().steps;
+        assert_eq!(lines.len(), steps.len());
+        for (line, step) in lines.iter().zip(steps) {
+            let json: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(json.get("op").unwrap().as_str(), Some(step.op.name()));
+            assert_eq!(
+                json.get("result").unwrap().as_u64(),
+                Some(step.result.index() as u64)
+            );
+            let nodes = json.get("nodes").unwrap().as_array().unwrap();
+            assert_eq!(nodes.len(), step.nodes.len());
+        }
+    }
+}
@@ -0,0 +1,416 @@
+//! Tells how many times the loops that count run, see [`TripCounts`].
+
+use super::ast::{AstNode, LoopType};
+use super::idioms::Cmp;
+use super::matchers::{Annotation, BlockId, IdiomMatcher, MatchCtx, MatchNode};
+
+use std::fmt;
+
+/// How many times the body of a loop runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Count {
+    /// when the counter starts at a constant and is compared with one
+    Known(u64),
+    /// an expression of where the counter starts and what it is compared
+    /// with, e.g. `(n - 0) / 1`, right if the loop runs at all
+    Symbolic(String),
+}
+
+impl fmt::Display for Count {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Count::Known(n) => n.fmt(f),
+            Count::Symbolic(expr) => f.write_str(expr),
+        }
+    }
+}
+
+/// The number of times a loop runs, see [`TripCounts`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TripCount {
+    pub count: Count,
+    /// whether the count is only right if the counter doesn't wrap around:
+    /// a step other than 1 may take it past the bound instead of onto it,
+    /// and past the largest value of its type if the bound is close to it
+    pub approximate: bool,
+}
+
+impl fmt::Display for TripCount {
+    /// E.g. `trip count: 10`, or `trip count: (n - 0 + 3) / 4 (approximate)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "trip count: {}", self.count)?;
+        if self.approximate {
+            f.write_str(" (approximate)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Matches the loops that count: a `for` whose update the context says
+/// [adds a constant](super::matchers::MatchOracle::increment) to a variable
+/// that its condition [compares](super::matchers::MatchOracle::comparison)
+/// with something else, and a `while` whose body ends in such an update.
+/// The counter of a `for` starts at what its `init` assigns to it, if the
+/// context can tell, and that of a `while` at its value before the loop,
+/// which the count names it for.
+///
+/// The count only holds if nothing else in the loop changes the counter or
+/// leaves the loop, so a loop whose body is seen to assign the counter
+/// anywhere but in the update, or that has a `break`, `continue`, `return`
+/// or `goto` of its own, isn't matched; nor is one whose step goes away
+/// from its bound.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TripCounts;
+
+impl IdiomMatcher for TripCounts {
+    fn try_match(&self, node: &MatchNode, ctx: &MatchCtx) -> Option<Annotation> {
+        trip_count(node, ctx).map(Annotation::TripCount)
+    }
+}
+
+fn trip_count(ast: &MatchNode, ctx: &MatchCtx) -> Option<TripCount> {
+    use self::AstNode::*;
+    let (init, c, update, body) = match ast {
+        For(i, c, u, body) => (Some(*i), *c, *u, &**body),
+        Loop(LoopType::PreChecked(c), body) => {
+            let update = match &**body {
+                BasicBlock(u) => *u,
+                Seq(seq) => match seq.last()? {
+                    BasicBlock(u) => *u,
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            (None, *c, update, &**body)
+        }
+        _ => return None,
+    };
+    let (var, step) = ctx.increment(update)?;
+    let (cmp, bound) = match ctx.comparison(c)? {
+        (l, cmp, r) if l == var => (cmp, r),
+        (l, cmp, r) if r == var => (flip(cmp), l),
+        _ => return None,
+    };
+    let mut blocks = Vec::new();
+    if escapes(body, false, &mut blocks) {
+        return None;
+    }
+    let writes = |b: BlockId| {
+        let dest = |d: Option<(String, String)>| d.is_some_and(|(d, _)| d == var);
+        dest(ctx.assignment(b))
+            || dest(ctx.negation(b))
+            || ctx.increment(b).is_some_and(|(d, _)| d == var)
+    };
+    if blocks.into_iter().any(|b| b != update && writes(b)) {
+        return None;
+    }
+    let start = match init.and_then(|i| ctx.assignment(i)) {
+        Some((dest, src)) if dest == var => src,
+        _ => var.clone(),
+    };
+
+    let up = matches!(cmp, Cmp::Lt | Cmp::Le);
+    if step == 0 || (step > 0) != up {
+        return None;
+    }
+    let inclusive = matches!(cmp, Cmp::Le | Cmp::Ge);
+    let size = i128::from(step).abs();
+    // the counter goes from `from` to `to`, whichever way it steps
+    let (from, to) = if up {
+        (&start, &bound)
+    } else {
+        (&bound, &start)
+    };
+    Some(match (constant(from), constant(to)) {
+        (Some(from), Some(to)) => {
+            let distance = to - from;
+            let (count, approximate) = if inclusive {
+                let count = if distance < 0 { 0 } else { distance / size + 1 };
+                (count, size != 1 && distance >= 0)
+            } else {
+                let count = if distance <= 0 {
+                    0
+                } else {
+                    (distance + size - 1) / size
+                };
+                (count, distance > 0 && distance % size != 0)
+            };
+            TripCount {
+                count: Count::Known(count as u64),
+                approximate,
+            }
+        }
+        _ => {
+            let expr = match (inclusive, size) {
+                (true, _) => format!("({} - {}) / {} + 1", to, from, size),
+                (false, 1) => format!("({} - {}) / 1", to, from),
+                (false, _) => format!("({} - {} + {}) / {}", to, from, size - 1, size),
+            };
+            TripCount {
+                count: Count::Symbolic(expr),
+                approximate: size != 1,
+            }
+        }
+    })
+}
+
+/// `a cmp b` as `b cmp' a`.
+fn flip(cmp: Cmp) -> Cmp {
+    match cmp {
+        Cmp::Lt => Cmp::Gt,
+        Cmp::Le => Cmp::Ge,
+        Cmp::Gt => Cmp::Lt,
+        Cmp::Ge => Cmp::Le,
+    }
+}
+
+/// The value of `operand` if it is a constant, like `10`, `-1` or `0x10`.
+fn constant(operand: &str) -> Option<i128> {
+    let (negative, digits) = match operand.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, operand),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from(u64::from_str_radix(hex, 16).ok()?),
+        None => i128::from(digits.parse::<u64>().ok()?),
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Whether `ast`, in the body of the loop being matched, may leave it or
+/// skip to its next iteration, or be jumped into; `nested` if it is in a
+/// loop within that one too. Appends the blocks of `ast` to `blocks`.
+fn escapes(ast: &MatchNode, nested: bool, blocks: &mut Vec<BlockId>) -> bool {
+    use self::AstNode::*;
+    match ast {
+        BasicBlock(b) => {
+            blocks.push(*b);
+            false
+        }
+        Seq(seq) => seq.iter().any(|a| escapes(a, nested, blocks)),
+        Cond(_, t, oe) => {
+            escapes(t, nested, blocks) || oe.as_ref().is_some_and(|e| escapes(e, nested, blocks))
+        }
+        Loop(_, b) => escapes(b, true, blocks),
+        For(i, _, u, b) => {
+            blocks.push(*i);
+            blocks.push(*u);
+            escapes(b, true, blocks)
+        }
+        Switch(_, cases, default) => {
+            cases.iter().any(|(_, a)| escapes(a, nested, blocks))
+                || escapes(default, nested, blocks)
+        }
+        Try(b, _) => escapes(b, nested, blocks),
+        Break | Continue => !nested,
+        Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) | Unstructured(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::decisions::AstNodeId;
+    use crate::backend::ctrl_flow_struct::matchers::{self, MatchOracle};
+
+    type Ast = AstNode<&'static str, &'static str, ()>;
+
+    /// Understands `a < b` and the like, `x = a`, `x += 2` and `x -= 2`,
+    /// with single words as operands.
+    struct Words;
+
+    impl MatchOracle<&'static str, &'static str, ()> for Words {
+        fn comparison(&self, cond: &&str) -> Option<(String, Cmp, String)> {
+            let words: Vec<_> = cond.split(' ').collect();
+            let cmp = match words.get(1) {
+                Some(&"<") => Cmp::Lt,
+                Some(&"<=") => Cmp::Le,
+                Some(&">") => Cmp::Gt,
+                Some(&">=") => Cmp::Ge,
+                _ => return None,
+            };
+            Some((words[0].to_owned(), cmp, words.get(2)?.to_string()))
+        }
+
+        fn assignment(&self, block: &&str) -> Option<(String, String)> {
+            let mut words = block.split(" = ");
+            Some((words.next()?.to_owned(), words.next()?.to_owned()))
+        }
+
+        fn increment(&self, block: &&str) -> Option<(String, i64)> {
+            let words: Vec<_> = block.split(' ').collect();
+            let step: i64 = words.get(2)?.parse().ok()?;
+            match words[1] {
+                "+=" => Some((words[0].to_owned(), step)),
+                "-=" => Some((words[0].to_owned(), -step)),
+                _ => None,
+            }
+        }
+    }
+
+    fn for_loop(init: &'static str, cond: &'static str, update: &'static str) -> Ast {
+        For(init, cond, update, Box::new(BasicBlock("x")))
+    }
+
+    fn trip_counts(ast: &Ast) -> Vec<(AstNodeId, TripCount)> {
+        matchers::run(ast, &Words, &[&TripCounts])
+            .into_iter()
+            .map(|(id, a)| match a {
+                Annotation::TripCount(count) => (id, count),
+                a => panic!("{:?}", a),
+            })
+            .collect()
+    }
+
+    fn trip_count(ast: &Ast) -> Option<TripCount> {
+        let mut found = trip_counts(ast);
+        assert!(found.len() <= 1, "{:?}", found);
+        found.pop().map(|(_, count)| count)
+    }
+
+    fn exact(count: Count) -> Option<TripCount> {
+        Some(TripCount {
+            count,
+            approximate: false,
+        })
+    }
+
+    fn approximate(count: Count) -> Option<TripCount> {
+        Some(TripCount {
+            count,
+            approximate: true,
+        })
+    }
+
+    fn symbolic(expr: &str) -> Count {
+        Count::Symbolic(expr.to_owned())
+    }
+
+    #[test]
+    fn exact_counts() {
+        let cases = [
+            (for_loop("i = 0", "i < 10", "i += 1"), 10),
+            (for_loop("i = 0", "i <= 10", "i += 1"), 11),
+            (for_loop("i = 10", "i > 0", "i -= 1"), 10),
+            (for_loop("i = 10", "i >= 0", "i -= 1"), 11),
+            (for_loop("i = 0x10", "0 < i", "i -= 1"), 16),
+            (for_loop("i = -2", "i < 2", "i += 1"), 4),
+            // steps that land on the bound
+            (for_loop("i = 0", "i < 12", "i += 3"), 4),
+            (for_loop("i = 12", "i > 0", "i -= 4"), 3),
+            // loops that never run
+            (for_loop("i = 10", "i < 10", "i += 3"), 0),
+            (for_loop("i = 10", "i <= 9", "i += 3"), 0),
+        ];
+        for (ast, count) in &cases {
+            assert_eq!(trip_count(ast), exact(Count::Known(*count)), "{:?}", ast);
+        }
+        assert_eq!(
+            trip_count(&cases[0].0).unwrap().to_string(),
+            "trip count: 10"
+        );
+    }
+
+    #[test]
+    fn symbolic_bound() {
+        let ast = for_loop("i = 0", "i < n", "i += 1");
+        assert_eq!(trip_count(&ast), exact(symbolic("(n - 0) / 1")));
+        assert_eq!(
+            trip_count(&ast).unwrap().to_string(),
+            "trip count: (n - 0) / 1"
+        );
+        let ast = for_loop("i = n", "i >= 0", "i -= 1");
+        assert_eq!(trip_count(&ast), exact(symbolic("(n - 0) / 1 + 1")));
+
+        // a `while` counts from the value its counter has before it
+        let ast = Seq(vec![
+            BasicBlock("a"),
+            Loop(
+                LoopType::PreChecked("i < 10"),
+                Box::new(Seq(vec![BasicBlock("x"), BasicBlock("i += 1")])),
+            ),
+        ]);
+        assert_eq!(
+            trip_counts(&ast),
+            vec![(AstNodeId(2), exact(symbolic("(10 - i) / 1")).unwrap())]
+        );
+    }
+
+    #[test]
+    fn skip_possible() {
+        // steps that go past the bound
+        let cases = [
+            (for_loop("i = 0", "i < 10", "i += 3"), 4),
+            (for_loop("i = 0", "i <= 12", "i += 3"), 5),
+            (for_loop("i = 10", "i > 0", "i -= 4"), 3),
+        ];
+        for (ast, count) in &cases {
+            assert_eq!(
+                trip_count(ast),
+                approximate(Count::Known(*count)),
+                "{:?}",
+                ast
+            );
+        }
+        let ast = for_loop("i = 0", "i < n", "i += 4");
+        assert_eq!(trip_count(&ast), approximate(symbolic("(n - 0 + 3) / 4")));
+        assert_eq!(
+            trip_count(&ast).unwrap().to_string(),
+            "trip count: (n - 0 + 3) / 4 (approximate)"
+        );
+    }
+
+    #[test]
+    fn not_counted() {
+        let asts = vec![
+            // stepping away from the bound
+            for_loop("i = 0", "i < 10", "i -= 1"),
+            for_loop("i = 0", "i > 10", "i += 1"),
+            for_loop("i = 0", "i < 10", "i += 0"),
+            // updating something else than the counter
+            for_loop("i = 0", "j < 10", "i += 1"),
+            // changing it in the body too
+            For(
+                "i = 0",
+                "i < 10",
+                "i += 1",
+                Box::new(Cond("p", Box::new(BasicBlock("i = 5")), None)),
+            ),
+            // leaving early
+            For(
+                "i = 0",
+                "i < 10",
+                "i += 1",
+                Box::new(Cond("p", Box::new(Break), None)),
+            ),
+            // a `continue` that skips the update of a `while`
+            Loop(
+                LoopType::PreChecked("i < 10"),
+                Box::new(Seq(vec![
+                    Cond("p", Box::new(Continue), None),
+                    BasicBlock("i += 1"),
+                ])),
+            ),
+            // a `while` whose update isn't last
+            Loop(
+                LoopType::PreChecked("i < 10"),
+                Box::new(Seq(vec![BasicBlock("i += 1"), BasicBlock("x")])),
+            ),
+        ];
+        for ast in asts {
+            assert_eq!(trip_count(&ast), None, "{:?}", ast);
+        }
+
+        // but a `break` leaving a nested loop is fine
+        let ast = For(
+            "i = 0",
+            "i < 10",
+            "i += 1",
+            Box::new(Loop(LoopType::Endless, Box::new(Break))),
+        );
+        assert_eq!(trip_count(&ast), exact(Count::Known(10)));
+    }
+}
@@ -0,0 +1,103 @@
+//! The [`petgraph::visit`] traits for a graph, so that the algorithms of
+//! petgraph, or those of the user, run on it directly, e.g.
+//! `petgraph::algo::dominators::simple_fast(&cfg, cfg.entry())`.
+//!
+//! They only let the graph be read, and all of them go to the nodes and
+//! edges as they are, including the `Unwind` and `Abnormal` edges and the
+//! handlers only they lead to, unlike e.g.
+//! [`region_tree`](ControlFlowGraph::region_tree), which leaves them out.
+
+use super::ast_context::AstContext;
+use super::{CfgEdge, CfgNode, ControlFlowGraph};
+
+use petgraph::prelude::*;
+use petgraph::visit::{
+    Data, GraphBase, IntoEdgeReferences, IntoEdges, IntoNeighbors, IntoNeighborsDirected,
+    IntoNodeIdentifiers, NodeIndexable, Visitable,
+};
+
+type Graph<'cd, A> = StableDiGraph<CfgNode<'cd, A>, CfgEdge>;
+
+impl<'cd, A: AstContext> ControlFlowGraph<'cd, A> {
+    /// The node the graph is entered at.
+    pub fn entry(&self) -> NodeIndex {
+        self.entry
+    }
+}
+
+impl<'cd, A: AstContext> GraphBase for ControlFlowGraph<'cd, A> {
+    type NodeId = NodeIndex;
+    type EdgeId = EdgeIndex;
+}
+
+impl<'cd, A: AstContext> Data for ControlFlowGraph<'cd, A> {
+    type NodeWeight = CfgNode<'cd, A>;
+    type EdgeWeight = CfgEdge;
+}
+
+impl<'cd, A: AstContext> NodeIndexable for ControlFlowGraph<'cd, A> {
+    fn node_bound(&self) -> usize {
+        self.graph.node_bound()
+    }
+
+    fn to_index(&self, n: NodeIndex) -> usize {
+        self.graph.to_index(n)
+    }
+
+    fn from_index(&self, i: usize) -> NodeIndex {
+        self.graph.from_index(i)
+    }
+}
+
+impl<'cd, A: AstContext> Visitable for ControlFlowGraph<'cd, A> {
+    type Map = <Graph<'cd, A> as Visitable>::Map;
+
+    fn visit_map(&self) -> Self::Map {
+        self.graph.visit_map()
+    }
+
+    fn reset_map(&self, map: &mut Self::Map) {
+        self.graph.reset_map(map)
+    }
+}
+
+impl<'a, 'cd, A: AstContext> IntoNeighbors for &'a ControlFlowGraph<'cd, A> {
+    type Neighbors = <&'a Graph<'cd, A> as IntoNeighbors>::Neighbors;
+
+    fn neighbors(self, n: NodeIndex) -> Self::Neighbors {
+        self.graph.neighbors(n)
+    }
+}
+
+impl<'a, 'cd, A: AstContext> IntoNeighborsDirected for &'a ControlFlowGraph<'cd, A> {
+    type NeighborsDirected = <&'a Graph<'cd, A> as IntoNeighborsDirected>::NeighborsDirected;
+
+    fn neighbors_directed(self, n: NodeIndex, dir: Direction) -> Self::NeighborsDirected {
+        self.graph.neighbors_directed(n, dir)
+    }
+}
+
+impl<'a, 'cd, A: AstContext> IntoNodeIdentifiers for &'a ControlFlowGraph<'cd, A> {
+    type NodeIdentifiers = <&'a Graph<'cd, A> as IntoNodeIdentifiers>::NodeIdentifiers;
+
+    fn node_identifiers(self) -> Self::NodeIdentifiers {
+        self.graph.node_identifiers()
+    }
+}
+
+impl<'a, 'cd, A: AstContext> IntoEdgeReferences for &'a ControlFlowGraph<'cd, A> {
+    type EdgeRef = <&'a Graph<'cd, A> as IntoEdgeReferences>::EdgeRef;
+    type EdgeReferences = <&'a Graph<'cd, A> as IntoEdgeReferences>::EdgeReferences;
+
+    fn edge_references(self) -> Self::EdgeReferences {
+        self.graph.edge_references()
+    }
+}
+
+impl<'a, 'cd, A: AstContext> IntoEdges for &'a ControlFlowGraph<'cd, A> {
+    type Edges = <&'a Graph<'cd, A> as IntoEdges>::Edges;
+
+    fn edges(self, n: NodeIndex) -> Self::Edges {
+        self.graph.edges(n)
+    }
+}
@@ -0,0 +1,325 @@
+//! Turns the flag tests of x86 conditional jumps into comparisons of the
+//! operands of the instruction that set the flags.
+//!
+//! A `jcc` only tests flags (`jbe` jumps if `cf || zf`), which says little
+//! when read on its own. Knowing that the flags were set by `cmp a, b`,
+//! `jbe` jumps if `a <=u b`. [`normalize`] does this rewrite for `cmp`,
+//! `sub`, `test` and `and`; [`lift_condition`] also finds the instruction
+//! that set the flags in the disassembly of a block.
+//!
+//! When that instruction isn't known, or the jump tests flags that don't
+//! make for a comparison (parity, or overflow on its own), the predicate is
+//! a [`Predicate::Raw`] holding the flag test in C syntax, e.g. `cf || zf`.
+
+use super::esil::{CmpOp, Operand, Predicate};
+
+/// The condition of an x86 conditional jump, named after the shortest of
+/// its mnemonics.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Jcc {
+    O,
+    No,
+    B,
+    Ae,
+    E,
+    Ne,
+    Be,
+    A,
+    S,
+    Ns,
+    P,
+    Np,
+    L,
+    Ge,
+    Le,
+    G,
+}
+
+/// An instruction that set the flags tested by a jump.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FlagDef {
+    Cmp(Operand, Operand),
+    /// like `Cmp`, but the first operand is overwritten with the difference
+    Sub(Operand, Operand),
+    Test(Operand, Operand),
+    /// like `Test`, but the first operand is overwritten with the result
+    And(Operand, Operand),
+}
+
+impl Jcc {
+    /// Recognizes the mnemonic of a conditional jump, including aliases
+    /// such as `jz` and `jnae`.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Jcc> {
+        use self::Jcc::*;
+        Some(match mnemonic {
+            "jo" => O,
+            "jno" => No,
+            "jb" | "jc" | "jnae" => B,
+            "jae" | "jnb" | "jnc" => Ae,
+            "je" | "jz" => E,
+            "jne" | "jnz" => Ne,
+            "jbe" | "jna" => Be,
+            "ja" | "jnbe" => A,
+            "js" => S,
+            "jns" => Ns,
+            "jp" | "jpe" => P,
+            "jnp" | "jpo" => Np,
+            "jl" | "jnge" => L,
+            "jge" | "jnl" => Ge,
+            "jle" | "jng" => Le,
+            "jg" | "jnle" => G,
+            _ => return None,
+        })
+    }
+
+    /// The flags the jump tests, in C syntax.
+    pub fn flag_test(self) -> &'static str {
+        use self::Jcc::*;
+        match self {
+            O => "of",
+            No => "!of",
+            B => "cf",
+            Ae => "!cf",
+            E => "zf",
+            Ne => "!zf",
+            Be => "cf || zf",
+            A => "!cf && !zf",
+            S => "sf",
+            Ns => "!sf",
+            P => "pf",
+            Np => "!pf",
+            L => "sf != of",
+            Ge => "sf == of",
+            Le => "zf || sf != of",
+            G => "!zf && sf == of",
+        }
+    }
+}
+
+/// Rewrites the condition of `jcc` as a comparison of the operands of
+/// `def`, the instruction that set the flags, if it is known.
+///
+/// After a `sub`, the comparison is of the operands as they were before the
+/// subtraction. After a `test` or `and`, it is of their conjunction with
+/// zero, since those clear `cf` and `of`; a jump on `cf` or `of` alone then
+/// always or never jumps, which is left as its flag test.
+pub fn normalize(def: Option<&FlagDef>, jcc: Jcc) -> Predicate {
+    use self::Jcc::*;
+    let raw = || Predicate::Raw(jcc.flag_test().to_owned());
+    match def {
+        Some(FlagDef::Cmp(a, b)) | Some(FlagDef::Sub(a, b)) => {
+            let op = match jcc {
+                E => CmpOp::Eq,
+                Ne => CmpOp::Ne,
+                B => CmpOp::ULt,
+                Ae => CmpOp::UGe,
+                Be => CmpOp::ULe,
+                A => CmpOp::UGt,
+                L => CmpOp::SLt,
+                Ge => CmpOp::SGe,
+                Le => CmpOp::SLe,
+                G => CmpOp::SGt,
+                O | No | S | Ns | P | Np => return raw(),
+            };
+            Predicate::Compare(op, a.clone(), b.clone())
+        }
+        Some(FlagDef::Test(a, b)) | Some(FlagDef::And(a, b)) => {
+            let op = match jcc {
+                // `cf` is clear, so `cf || zf` is `zf`
+                E | Be => CmpOp::Eq,
+                Ne | A => CmpOp::Ne,
+                // `of` is clear, so `sf != of` is `sf`
+                S | L => CmpOp::SLt,
+                Ns | Ge => CmpOp::SGe,
+                Le => CmpOp::SLe,
+                G => CmpOp::SGt,
+                O | No | B | Ae | P | Np => return raw(),
+            };
+            let result = if a == b {
+                a.clone()
+            } else {
+                Operand::BitAnd(Box::new(a.clone()), Box::new(b.clone()))
+            };
+            Predicate::Compare(op, result, Operand::Const(0))
+        }
+        None => raw(),
+    }
+}
+
+/// Lifts the predicate of the conditional jump ending a basic block from
+/// `insns`, the disassembly of each instruction of the block (`cmp eax, 4`),
+/// the jump being the last one. The flags are assumed to be set by the
+/// last `cmp`, `sub`, `test` or `and` before it, unless an instruction not
+/// known to leave the flags alone comes in between.
+///
+/// If the last instruction isn't a conditional jump, its disassembly is
+/// returned as a [`Predicate::Raw`].
+pub fn lift_condition<S: AsRef<str>>(insns: &[S]) -> Predicate {
+    let (jump, rest) = match insns.split_last() {
+        Some(split) => split,
+        None => return Predicate::Raw(String::new()),
+    };
+    let jcc = match Jcc::from_mnemonic(split_insn(jump.as_ref()).0) {
+        Some(jcc) => jcc,
+        None => return Predicate::Raw(jump.as_ref().to_owned()),
+    };
+    let mut def = None;
+    for insn in rest.iter().rev() {
+        let (mnemonic, operands) = split_insn(insn.as_ref());
+        if PRESERVE_FLAGS.contains(&mnemonic) {
+            continue;
+        }
+        def = match (mnemonic, &operands[..]) {
+            ("cmp", [a, b]) => Some(FlagDef::Cmp(operand(a), operand(b))),
+            ("sub", [a, b]) => Some(FlagDef::Sub(operand(a), operand(b))),
+            ("test", [a, b]) => Some(FlagDef::Test(operand(a), operand(b))),
+            ("and", [a, b]) => Some(FlagDef::And(operand(a), operand(b))),
+            _ => None,
+        };
+        break;
+    }
+    normalize(def.as_ref(), jcc)
+}
+
+/// Mnemonics of instructions that don't change the flags.
+const PRESERVE_FLAGS: &[&str] = &[
+    "mov", "movzx", "movsx", "movsxd", "lea", "push", "pop", "nop",
+];
+
+/// Splits `insn` into its mnemonic and operands.
+fn split_insn(insn: &str) -> (&str, Vec<&str>) {
+    let insn = insn.trim();
+    let (mnemonic, operands) = match insn.find(char::is_whitespace) {
+        Some(i) => (&insn[..i], insn[i..].trim()),
+        None => (insn, ""),
+    };
+    let operands = if operands.is_empty() {
+        Vec::new()
+    } else {
+        operands.split(',').map(str::trim).collect()
+    };
+    (mnemonic, operands)
+}
+
+/// An operand as disassembled. Memory operands are kept as they are
+/// written, as if they were registers.
+fn operand(text: &str) -> Operand {
+    let num = if let Some(hex) = text.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    };
+    match num {
+        Some(num) => Operand::Const(num),
+        None => Operand::Reg(text.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Jcc::*;
+    use super::*;
+
+    fn reg(r: &str) -> Operand {
+        Operand::Reg(r.to_owned())
+    }
+
+    fn cmp(op: CmpOp, a: Operand, b: Operand) -> Predicate {
+        Predicate::Compare(op, a, b)
+    }
+
+    fn raw(s: &str) -> Predicate {
+        Predicate::Raw(s.to_owned())
+    }
+
+    #[test]
+    fn after_cmp() {
+        let def = FlagDef::Cmp(reg("eax"), Operand::Const(4));
+        let table = [
+            (E, CmpOp::Eq),
+            (Ne, CmpOp::Ne),
+            (B, CmpOp::ULt),
+            (Ae, CmpOp::UGe),
+            (Be, CmpOp::ULe),
+            (A, CmpOp::UGt),
+            (L, CmpOp::SLt),
+            (Ge, CmpOp::SGe),
+            (Le, CmpOp::SLe),
+            (G, CmpOp::SGt),
+        ];
+        for &(jcc, op) in &table {
+            assert_eq!(
+                normalize(Some(&def), jcc),
+                cmp(op, reg("eax"), Operand::Const(4)),
+                "{:?}",
+                jcc
+            );
+        }
+        for &jcc in &[O, No, S, Ns, P, Np] {
+            assert_eq!(normalize(Some(&def), jcc), raw(jcc.flag_test()));
+        }
+        let sub = FlagDef::Sub(reg("eax"), reg("ebx"));
+        assert_eq!(
+            normalize(Some(&sub), Le),
+            cmp(CmpOp::SLe, reg("eax"), reg("ebx"))
+        );
+    }
+
+    #[test]
+    fn after_test() {
+        let def = FlagDef::Test(reg("eax"), reg("eax"));
+        let zero = || Operand::Const(0);
+        let table = [
+            (E, CmpOp::Eq),
+            (Be, CmpOp::Eq),
+            (Ne, CmpOp::Ne),
+            (A, CmpOp::Ne),
+            (S, CmpOp::SLt),
+            (L, CmpOp::SLt),
+            (Ns, CmpOp::SGe),
+            (Ge, CmpOp::SGe),
+            (Le, CmpOp::SLe),
+            (G, CmpOp::SGt),
+        ];
+        for &(jcc, op) in &table {
+            assert_eq!(
+                normalize(Some(&def), jcc),
+                cmp(op, reg("eax"), zero()),
+                "{:?}",
+                jcc
+            );
+        }
+        for &jcc in &[O, No, B, Ae, P, Np] {
+            assert_eq!(normalize(Some(&def), jcc), raw(jcc.flag_test()));
+        }
+        let and = FlagDef::And(reg("eax"), Operand::Const(8));
+        let masked = Operand::BitAnd(Box::new(reg("eax")), Box::new(Operand::Const(8)));
+        assert_eq!(normalize(Some(&and), Ne), cmp(CmpOp::Ne, masked, zero()));
+    }
+
+    #[test]
+    fn unknown_flags() {
+        assert_eq!(normalize(None, Be), raw("cf || zf"));
+        assert_eq!(normalize(None, G), raw("!zf && sf == of"));
+    }
+
+    #[test]
+    fn from_disassembly() {
+        assert_eq!(
+            lift_condition(&["cmp dword [rbp - 4], 9", "mov eax, 1", "jle 0x40"]),
+            cmp(CmpOp::SLe, reg("dword [rbp - 4]"), Operand::Const(9))
+        );
+        assert_eq!(
+            lift_condition(&["test al, al", "jnz 0x40"]),
+            cmp(CmpOp::Ne, reg("al"), Operand::Const(0))
+        );
+        // `add` sets the flags in a way we don't rewrite
+        assert_eq!(
+            lift_condition(&["cmp eax, ebx", "add eax, 1", "jb 0x40"]),
+            raw("cf")
+        );
+        assert_eq!(lift_condition(&["ret"]), raw("ret"));
+        assert_eq!(Jcc::from_mnemonic("jnae"), Some(B));
+        assert_eq!(Jcc::from_mnemonic("jmp"), None);
+    }
+}
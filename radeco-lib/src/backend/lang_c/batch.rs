@@ -0,0 +1,503 @@
+//! Structures every function of a binary and writes each one out as C.
+//!
+//! [`run`] takes the functions as radare2 basic block JSON (see
+//! [`from_r2`](crate::backend::ctrl_flow_struct::from_r2)) and fills an output
+//! directory with:
+//!  - one `<addr>_<name>.c` file per function that could be structured, see
+//!    [`output_file_name`];
+//!  - `index.json`, listing every function with its output file, number of
+//!    `goto`s, unresolved indirect jumps, time taken, the
+//!    [`StructuringReport`] with the `serde` feature, and error, if any, and
+//!    the blocks shared by several functions;
+//!  - `summary.txt`, the same for humans.
+//!
+//! A function that fails to structure, or makes structuring panic, is only
+//! recorded as a failure; the rest of the batch carries on.
+
+use super::c_writer;
+use super::r2_comments::R2Renderer;
+use crate::backend::ctrl_flow_struct::ast::AstNode;
+use crate::backend::ctrl_flow_struct::from_r2::{
+    self, Block, CondExpr, ImportOptions, R2BasicBlock, R2Provenance, SharedBlock, Var,
+};
+use crate::backend::ctrl_flow_struct::provenance::Provenance;
+use crate::backend::ctrl_flow_struct::{StructuringOptions, StructuringReport};
+
+use serde_json::Value;
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+pub const INDEX_FILE: &str = "index.json";
+pub const SUMMARY_FILE: &str = "summary.txt";
+
+/// A function to structure, as exported by radare2.
+#[derive(Clone, Debug)]
+pub struct R2Function {
+    pub name: String,
+    pub addr: u64,
+    /// the output of `afbj` for the function
+    pub blocks_json: String,
+}
+
+/// What happened to one function of a batch.
+#[derive(Clone, Debug)]
+pub struct FunctionReport {
+    pub name: String,
+    pub addr: u64,
+    /// the output file, relative to the output directory, if the function
+    /// was structured
+    pub file: Option<String>,
+    pub gotos: usize,
+    /// the addresses of the blocks ending in an indirect jump whose targets
+    /// couldn't be resolved, i.e. where the control flow is incomplete
+    pub unresolved_jumps: Vec<u64>,
+    pub duration: Duration,
+    /// what structuring did, if the function was structured
+    pub structuring: Option<StructuringReport>,
+    /// why the function couldn't be structured
+    pub error: Option<String>,
+}
+
+/// What happened to every function of a batch, in the order they were
+/// given.
+#[derive(Clone, Debug, Default)]
+pub struct BatchReport {
+    pub functions: Vec<FunctionReport>,
+    /// the blocks listed in more than one function, with the indices of the
+    /// functions in `functions`. Each function was structured with its own
+    /// copy of them
+    pub shared_blocks: Vec<SharedBlock>,
+}
+
+impl BatchReport {
+    pub fn failures(&self) -> impl Iterator<Item = &FunctionReport> {
+        self.functions.iter().filter(|f| f.error.is_some())
+    }
+
+    /// The contents of the index file.
+    pub fn to_json(&self) -> String {
+        let mut ret = String::from("{\"functions\":[");
+        for (i, f) in self.functions.iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+            let _ = write!(
+                ret,
+                "{{\"name\":{},\"addr\":{},\"file\":{},\"gotos\":{},\"unresolved_jumps\":[{}],\"\
+                 micros\":{},\"structuring\":{},\"error\":{}}}",
+                Value::from(f.name.as_str()),
+                f.addr,
+                f.file.as_deref().map_or(Value::Null, Value::from),
+                f.gotos,
+                f.unresolved_jumps
+                    .iter()
+                    .map(u64::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+                f.duration.as_micros(),
+                f.structuring.as_ref().map_or(Value::Null, report_json),
+                f.error.as_deref().map_or(Value::Null, Value::from),
+            );
+        }
+        ret.push_str("],\"shared_blocks\":[");
+        for (i, b) in self.shared_blocks.iter().enumerate() {
+            if i > 0 {
+                ret.push(',');
+            }
+            let _ = write!(
+                ret,
+                "{{\"addr\":{},\"functions\":[{}]}}",
+                b.addr,
+                b.functions
+                    .iter()
+                    .map(|&f| self.functions[f].addr.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            );
+        }
+        ret.push_str("]}\n");
+        ret
+    }
+
+    /// The contents of the summary file.
+    pub fn summary(&self) -> String {
+        let mut ret = String::new();
+        let total: Duration = self.functions.iter().map(|f| f.duration).sum();
+        let gotos: usize = self.functions.iter().map(|f| f.gotos).sum();
+        let unresolved: usize = self
+            .functions
+            .iter()
+            .map(|f| f.unresolved_jumps.len())
+            .sum();
+        let _ = writeln!(
+            ret,
+            "{} functions, {} failed, {} gotos, {} unresolved jumps, {:.3}s",
+            self.functions.len(),
+            self.failures().count(),
+            gotos,
+            unresolved,
+            total.as_secs_f64()
+        );
+        for f in &self.functions {
+            let _ = write!(ret, "{:#010x} {}: ", f.addr, f.name);
+            let _ = match &f.error {
+                Some(e) => writeln!(ret, "FAILED ({})", e),
+                None => {
+                    let _ = write!(ret, "{} gotos, ", f.gotos);
+                    if !f.unresolved_jumps.is_empty() {
+                        let addrs: Vec<_> = f
+                            .unresolved_jumps
+                            .iter()
+                            .map(|a| format!("{:#x}", a))
+                            .collect();
+                        let _ = write!(ret, "unresolved jumps at {}, ", addrs.join(" "));
+                    }
+                    writeln!(ret, "{:.3}ms", f.duration.as_secs_f64() * 1000.0)
+                }
+            };
+        }
+        for b in &self.shared_blocks {
+            let names: Vec<_> = b
+                .functions
+                .iter()
+                .map(|&f| &*self.functions[f].name)
+                .collect();
+            let _ = writeln!(ret, "block {:#x} is shared by {}", b.addr, names.join(", "));
+        }
+        ret
+    }
+}
+
+/// `report` in the index file, which needs the `serde` feature.
+#[cfg(feature = "serde")]
+fn report_json(report: &StructuringReport) -> Value {
+    serde_json::to_value(report).unwrap_or(Value::Null)
+}
+
+#[cfg(not(feature = "serde"))]
+fn report_json(_: &StructuringReport) -> Value {
+    Value::Null
+}
+
+/// The name of the output file for a function. It only depends on the
+/// address and name of the function, so it stays the same across runs.
+pub fn output_file_name(addr: u64, name: &str) -> String {
+    format!("{:08x}_{}.c", addr, c_ident(name))
+}
+
+/// Structures each of `funcs` with `opts` and writes the results to
+/// `out_dir`, creating it if needed. Only I/O errors abort the batch.
+pub fn run(
+    funcs: &[R2Function],
+    opts: &StructuringOptions,
+    out_dir: &Path,
+) -> io::Result<BatchReport> {
+    fs::create_dir_all(out_dir)?;
+
+    let parsed: Vec<_> = funcs
+        .iter()
+        .map(|f| from_r2::parse_blocks(&f.blocks_json))
+        .collect();
+    let mut report = BatchReport {
+        functions: Vec::new(),
+        shared_blocks: from_r2::shared_blocks(
+            parsed
+                .iter()
+                .map(|p| p.as_ref().map_or(&[][..], |b| &b[..])),
+        ),
+    };
+    for (func, blocks) in funcs.iter().zip(&parsed) {
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| structure_one(func, blocks, opts)));
+        let duration = start.elapsed();
+
+        let (file, unresolved_jumps, structuring, error) = match result {
+            Ok(Ok(structured)) => {
+                let file = output_file_name(func.addr, &func.name);
+                fs::write(out_dir.join(&file), structured.c)?;
+                (
+                    Some(file),
+                    structured.unresolved_jumps,
+                    Some(structured.report),
+                    None,
+                )
+            }
+            Ok(Err(err)) => (None, Vec::new(), None, Some(err)),
+            Err(_) => (
+                None,
+                Vec::new(),
+                None,
+                Some("structuring panicked".to_owned()),
+            ),
+        };
+        let gotos = structuring.as_ref().map_or(0, |r| r.gotos);
+        report.functions.push(FunctionReport {
+            name: func.name.clone(),
+            addr: func.addr,
+            file,
+            gotos,
+            unresolved_jumps,
+            duration,
+            structuring,
+            error,
+        });
+    }
+
+    fs::write(out_dir.join(INDEX_FILE), report.to_json())?;
+    fs::write(out_dir.join(SUMMARY_FILE), report.summary())?;
+    Ok(report)
+}
+
+/// A function that was structured.
+struct Structured {
+    c: String,
+    unresolved_jumps: Vec<u64>,
+    report: StructuringReport,
+}
+
+fn structure_one(
+    func: &R2Function,
+    blocks: &Result<Vec<R2BasicBlock>, &'static str>,
+    opts: &StructuringOptions,
+) -> Result<Structured, String> {
+    let blocks = blocks.as_ref().map_err(|e| e.to_string())?;
+    let (sf, report) = from_r2::structure_blocks_reported(blocks, &ImportOptions::default(), opts)
+        .map_err(|e| e.to_string())?;
+    let c = c_writer::write_function(&c_ident(&func.name), &sf.ast, &mut R2Renderer);
+    let mut unresolved_jumps = Vec::new();
+    find_unresolved_jumps(&sf.ast, &mut unresolved_jumps);
+    unresolved_jumps.sort();
+    Ok(Structured {
+        c,
+        unresolved_jumps,
+        report,
+    })
+}
+
+/// Appends the addresses of the blocks in `ast` that end in an unresolved
+/// indirect jump to `out`.
+fn find_unresolved_jumps(ast: &AstNode<Block, CondExpr, Var>, out: &mut Vec<u64>) {
+    use self::AstNode::*;
+    match ast {
+        IndirectJump(b) => out.extend(R2Provenance.block_addr(b)),
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | Goto(_) | Label(_) => (),
+        Seq(seq) => {
+            for a in seq {
+                find_unresolved_jumps(a, out);
+            }
+        }
+        Cond(_, t, oe) => {
+            find_unresolved_jumps(t, out);
+            if let Some(e) = oe {
+                find_unresolved_jumps(e, out);
+            }
+        }
+        Loop(_, b) | For(_, _, _, b) | Try(b, _) => find_unresolved_jumps(b, out),
+        Unstructured(members) => {
+            for (_, a) in members {
+                find_unresolved_jumps(a, out);
+            }
+        }
+        Switch(_, cases, default) => {
+            for (_, a) in cases {
+                find_unresolved_jumps(a, out);
+            }
+            find_unresolved_jumps(default, out);
+        }
+    }
+}
+
+/// Turns a symbol name like `sym.imp.exit` into a C identifier.
+fn c_ident(name: &str) -> String {
+    let mut ret: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if ret.is_empty() || ret.starts_with(|c: char| c.is_ascii_digit()) {
+        ret.insert(0, '_');
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::{self, Value};
+
+    use std::env;
+
+    fn fixtures() -> Vec<R2Function> {
+        let loopy = fs::read_to_string("test_files/loopy_main_afbj.json").unwrap();
+        vec![
+            R2Function {
+                name: "sym.main".to_owned(),
+                addr: 0x400526,
+                blocks_json: loopy,
+            },
+            R2Function {
+                name: "sym.straight".to_owned(),
+                addr: 0x1000,
+                blocks_json: r#"[{"addr":4096,"size":4,"jump":4100},{"addr":4100,"size":2}]"#
+                    .to_owned(),
+            },
+            // no blocks at all
+            R2Function {
+                name: "sym.broken".to_owned(),
+                addr: 0x2000,
+                blocks_json: "[]".to_owned(),
+            },
+            R2Function {
+                name: "sym.if".to_owned(),
+                addr: 0x3000,
+                blocks_json: r#"[
+                    {"addr":12288,"size":4,"jump":12296,"fail":12292},
+                    {"addr":12292,"size":4,"jump":12296},
+                    {"addr":12296,"size":2}
+                ]"#
+                .to_owned(),
+            },
+            R2Function {
+                name: "sym.dispatch".to_owned(),
+                addr: 0x4000,
+                blocks_json: r#"[
+                    {"addr":16384,"size":4,"jump":16392,"fail":16388},
+                    {"addr":16388,"size":4,"unresolved_jump":true},
+                    {"addr":16392,"size":2}
+                ]"#
+                .to_owned(),
+            },
+        ]
+    }
+
+    #[test]
+    fn batch_fixtures() {
+        let out_dir = env::temp_dir().join("radeco_batch_test");
+        let _ = fs::remove_dir_all(&out_dir);
+        let report = run(&fixtures(), &StructuringOptions::default(), &out_dir).unwrap();
+
+        // the broken function didn't stop the ones after it
+        let files: Vec<_> = report.functions.iter().map(|f| f.file.clone()).collect();
+        assert_eq!(
+            files,
+            vec![
+                Some("00400526_sym_main.c".to_owned()),
+                Some("00001000_sym_straight.c".to_owned()),
+                None,
+                Some("00003000_sym_if.c".to_owned()),
+                Some("00004000_sym_dispatch.c".to_owned()),
+            ]
+        );
+        let failures: Vec<_> = report.failures().map(|f| &*f.name).collect();
+        assert_eq!(failures, vec!["sym.broken"]);
+        assert!(report.functions.iter().all(|f| f.gotos == 0));
+        let unresolved: Vec<_> = report
+            .functions
+            .iter()
+            .map(|f| f.unresolved_jumps.clone())
+            .collect();
+        assert_eq!(
+            unresolved,
+            vec![vec![], vec![], vec![], vec![], vec![0x4004]]
+        );
+        let dispatch_c = fs::read_to_string(out_dir.join("00004000_sym_dispatch.c")).unwrap();
+        assert!(dispatch_c.contains("goto *target_4004; /* unresolved */"));
+
+        let main_c = fs::read_to_string(out_dir.join("00400526_sym_main.c")).unwrap();
+        assert!(main_c.starts_with("void sym_main(void) {"));
+        let if_c = fs::read_to_string(out_dir.join("00003000_sym_if.c")).unwrap();
+        assert!(if_c.contains("if ("));
+
+        let index: Value =
+            serde_json::from_str(&fs::read_to_string(out_dir.join(INDEX_FILE)).unwrap()).unwrap();
+        let entries = index.get("functions").unwrap().as_array().unwrap();
+        assert_eq!(entries.len(), 5);
+        assert_eq!(entries[0].get("addr").unwrap().as_u64(), Some(0x400526));
+        assert_eq!(
+            entries[1].get("file").unwrap().as_str(),
+            Some("00001000_sym_straight.c")
+        );
+        assert!(entries[2].get("file").unwrap().is_null());
+        assert!(entries[2].get("structuring").unwrap().is_null());
+        #[cfg(feature = "serde")]
+        {
+            let straight = entries[1].get("structuring").unwrap();
+            assert_eq!(straight.get("loops").unwrap().as_u64(), Some(0));
+            assert!(straight
+                .get("fallbacks")
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .is_empty());
+        }
+        assert!(entries[2].get("error").unwrap().as_str().is_some());
+        let jumps = entries[4]
+            .get("unresolved_jumps")
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert_eq!(
+            jumps.iter().map(|j| j.as_u64()).collect::<Vec<_>>(),
+            vec![Some(0x4004)]
+        );
+
+        let summary = fs::read_to_string(out_dir.join(SUMMARY_FILE)).unwrap();
+        assert!(summary.starts_with("5 functions, 1 failed, 0 gotos, 1 unresolved jumps"));
+        assert!(summary.contains("0x00002000 sym.broken: FAILED"));
+        assert!(summary.contains("0x00004000 sym.dispatch: 0 gotos, unresolved jumps at 0x4004, "));
+    }
+
+    #[test]
+    fn shared_blocks_reported() {
+        let out_dir = env::temp_dir().join("radeco_batch_shared_test");
+        let _ = fs::remove_dir_all(&out_dir);
+        let funcs = vec![
+            R2Function {
+                name: "sym.a".to_owned(),
+                addr: 0x10,
+                blocks_json: r#"[{"addr":16,"size":4,"jump":256},{"addr":256,"size":2}]"#
+                    .to_owned(),
+            },
+            R2Function {
+                name: "sym.b".to_owned(),
+                addr: 0x20,
+                blocks_json: r#"[{"addr":32,"size":4,"jump":256},{"addr":256,"size":2}]"#
+                    .to_owned(),
+            },
+        ];
+        let report = run(&funcs, &StructuringOptions::default(), &out_dir).unwrap();
+        assert_eq!(report.failures().count(), 0);
+
+        let index: Value = serde_json::from_str(&report.to_json()).unwrap();
+        let shared = index.get("shared_blocks").unwrap().as_array().unwrap();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].get("addr").unwrap().as_u64(), Some(0x100));
+        let in_funcs = shared[0].get("functions").unwrap().as_array().unwrap();
+        assert_eq!(
+            in_funcs.iter().map(|f| f.as_u64()).collect::<Vec<_>>(),
+            vec![Some(0x10), Some(0x20)]
+        );
+        // each function got its own copy
+        for f in &report.functions {
+            let c = fs::read_to_string(out_dir.join(f.file.as_ref().unwrap())).unwrap();
+            assert!(c.contains("block_100"));
+        }
+        assert!(report
+            .summary()
+            .ends_with("block 0x100 is shared by sym.a, sym.b\n"));
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            output_file_name(0x10, "sym.imp.exit"),
+            "00000010_sym_imp_exit.c"
+        );
+        assert_eq!(output_file_name(0x10, "3dfx"), "00000010__3dfx.c");
+    }
+}
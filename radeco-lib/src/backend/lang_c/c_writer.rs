@@ -0,0 +1,1403 @@
+//! Writes a structured [`AstNode`] out as C source.
+//!
+//! Only the control flow is handled here; the statements inside basic blocks,
+//! conditions, and switch heads are rendered by a caller-supplied
+//! [`StmtRenderer`]. Wrapping it in a [`LineAnnotator`] adds the source lines
+//! they come from, and in a [`LabelNames`] names the labels like structuring
+//! did. [`address_markers`] marks each statement with the address it comes
+//! from, on a line of its own, for tools that read the output.
+
+use crate::backend::ctrl_flow_struct::ast::{AstNode, LabelId, LoopType, ValueSet};
+use crate::backend::ctrl_flow_struct::decisions::{self, AstNodeId};
+use crate::backend::ctrl_flow_struct::provenance::{self, LineTable, Provenance};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::iter;
+
+const INDENT: &str = "    ";
+
+/// Renders the parts of an `AstNode` that aren't control flow.
+pub trait StmtRenderer<B, C, V> {
+    /// Returns the C statements that make up `block`, one per element.
+    fn block(&mut self, block: &B) -> Vec<String>;
+
+    /// Returns a C expression for `cond`.
+    fn cond(&mut self, cond: &C) -> String;
+
+    /// Returns a C expression for the value of `var`, e.g. in a switch head.
+    fn var(&mut self, var: &V) -> String;
+
+    /// Returns the C statements that make up `block`, which ends in a call
+    /// whose result is returned, e.g. `return f(x);`. By default, the
+    /// statements of `block` followed by a plain `return`.
+    fn tail_call(&mut self, block: &B) -> Vec<String> {
+        let mut ret = self.block(block);
+        ret.push("return;".to_owned());
+        ret
+    }
+
+    /// Returns the C statements that make up `block`, which ends in an
+    /// indirect jump whose targets are unknown. By default, the statements
+    /// of `block` followed by a computed `goto`.
+    fn indirect_jump(&mut self, block: &B) -> Vec<String> {
+        let mut ret = self.block(block);
+        ret.push("goto *target; /* unresolved */".to_owned());
+        ret
+    }
+
+    /// Returns the `case` constants that select a switch case. Only used
+    /// for C; Rust `match` arms always use `lo..=hi` patterns.
+    fn case_values(&mut self, vs: &ValueSet) -> Vec<String> {
+        case_constants(vs)
+    }
+
+    /// Returns the name of the C label for `label`.
+    fn label(&mut self, label: LabelId) -> String {
+        format!("label_{}", label.0)
+    }
+
+    /// Returns a C expression with the effect of `block`, for the head of a
+    /// `for` loop. By default, the statements of `block` without their `;`,
+    /// joined by commas.
+    fn for_clause(&mut self, block: &B) -> String {
+        let stmts: Vec<_> = self
+            .block(block)
+            .iter()
+            .map(|s| s.trim_end_matches(';').to_owned())
+            .collect();
+        stmts.join(", ")
+    }
+}
+
+/// A [`StmtRenderer`] that annotates what another one renders with the
+/// source lines it comes from, e.g. `block_10(); /* foo.c:42-44 */`. The
+/// comment goes on the first statement of each block and after each
+/// condition.
+pub struct LineAnnotator<'a, R, P> {
+    pub renderer: R,
+    prov: &'a P,
+    lines: &'a LineTable,
+}
+
+impl<'a, R, P> LineAnnotator<'a, R, P> {
+    pub fn new(renderer: R, prov: &'a P, lines: &'a LineTable) -> Self {
+        LineAnnotator {
+            renderer,
+            prov,
+            lines,
+        }
+    }
+
+    fn annotate_first<B, C>(&self, block: &B, mut stmts: Vec<String>) -> Vec<String>
+    where
+        P: Provenance<B, C>,
+    {
+        let lines = self
+            .prov
+            .block_range(block)
+            .and_then(|r| self.lines.lines_in(r));
+        if let (Some(first), Some(lines)) = (stmts.first_mut(), lines) {
+            let _ = write!(first, " /* {} */", lines);
+        }
+        stmts
+    }
+}
+
+impl<'a, B, C, V, R, P> StmtRenderer<B, C, V> for LineAnnotator<'a, R, P>
+where
+    R: StmtRenderer<B, C, V>,
+    P: Provenance<B, C>,
+{
+    fn block(&mut self, block: &B) -> Vec<String> {
+        let stmts = self.renderer.block(block);
+        self.annotate_first(block, stmts)
+    }
+
+    fn cond(&mut self, cond: &C) -> String {
+        let mut ret = self.renderer.cond(cond);
+        let lines = self
+            .prov
+            .cond_addr(cond)
+            .and_then(|a| self.lines.lines_in(a..a + 1));
+        if let Some(lines) = lines {
+            let _ = write!(ret, " /* {} */", lines);
+        }
+        ret
+    }
+
+    fn var(&mut self, var: &V) -> String {
+        self.renderer.var(var)
+    }
+
+    fn tail_call(&mut self, block: &B) -> Vec<String> {
+        let stmts = self.renderer.tail_call(block);
+        self.annotate_first(block, stmts)
+    }
+
+    fn indirect_jump(&mut self, block: &B) -> Vec<String> {
+        let stmts = self.renderer.indirect_jump(block);
+        self.annotate_first(block, stmts)
+    }
+
+    fn case_values(&mut self, vs: &ValueSet) -> Vec<String> {
+        self.renderer.case_values(vs)
+    }
+
+    fn label(&mut self, label: LabelId) -> String {
+        self.renderer.label(label)
+    }
+
+    fn for_clause(&mut self, block: &B) -> String {
+        self.renderer.for_clause(block)
+    }
+}
+
+/// A [`StmtRenderer`] that names the labels that another one renders by the
+/// names that structuring gave them, see
+/// [`StructuringReport::labels`](crate::backend::ctrl_flow_struct::StructuringReport::labels).
+/// The labels without a name are rendered by the other one.
+pub struct LabelNames<'a, R> {
+    pub renderer: R,
+    names: HashMap<LabelId, &'a str>,
+}
+
+impl<'a, R> LabelNames<'a, R> {
+    pub fn new(renderer: R, labels: &'a [(LabelId, String)]) -> Self {
+        LabelNames {
+            renderer,
+            names: labels.iter().map(|(l, n)| (*l, &**n)).collect(),
+        }
+    }
+}
+
+impl<'a, B, C, V, R> StmtRenderer<B, C, V> for LabelNames<'a, R>
+where
+    R: StmtRenderer<B, C, V>,
+{
+    fn block(&mut self, block: &B) -> Vec<String> {
+        self.renderer.block(block)
+    }
+
+    fn cond(&mut self, cond: &C) -> String {
+        self.renderer.cond(cond)
+    }
+
+    fn var(&mut self, var: &V) -> String {
+        self.renderer.var(var)
+    }
+
+    fn tail_call(&mut self, block: &B) -> Vec<String> {
+        self.renderer.tail_call(block)
+    }
+
+    fn indirect_jump(&mut self, block: &B) -> Vec<String> {
+        self.renderer.indirect_jump(block)
+    }
+
+    fn case_values(&mut self, vs: &ValueSet) -> Vec<String> {
+        self.renderer.case_values(vs)
+    }
+
+    fn label(&mut self, label: LabelId) -> String {
+        match self.names.get(&label) {
+            Some(name) => (*name).to_owned(),
+            None => self.renderer.label(label),
+        }
+    }
+
+    fn for_clause(&mut self, block: &B) -> String {
+        self.renderer.for_clause(block)
+    }
+}
+
+/// Returns a `case` constant for each range in `vs`, using GNU C case
+/// ranges (`lo ... hi`) for ranges of more than one value.
+pub fn case_constants(vs: &ValueSet) -> Vec<String> {
+    vs.ranges()
+        .iter()
+        .map(|&(lo, hi)| {
+            if lo == hi {
+                lo.to_string()
+            } else {
+                format!("{} ... {}", lo, hi)
+            }
+        })
+        .collect()
+}
+
+/// The language [`write_function_with`] writes in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    C,
+    /// Rust-flavored pseudocode: `if c {}`, `while c {}`, `loop {}` and
+    /// `match`. Only the control flow is Rust; the statements are whatever
+    /// the renderer makes of them.
+    ///
+    /// `switch`es never fall through, since each case of a `Switch` is its
+    /// own AST (structuring duplicates or guards the code that cases
+    /// share), so each becomes one arm. A `do`-`while` loop becomes a
+    /// `loop` ending in a check; if its body `continue`s, the body is a
+    /// labeled block that `continue` breaks out of, so the check still runs.
+    /// `goto`s and their labels have no Rust counterpart and are written
+    /// like in C.
+    Rust,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::C
+    }
+}
+
+/// Knobs controlling how [`write_function_with`] writes a function.
+#[derive(Clone, Debug, Default)]
+pub struct WriterOptions {
+    pub dialect: Dialect,
+    /// Comments to put at the end of the first line of the nodes of the
+    /// AST, by their [`AstNodeId`], e.g. the
+    /// [`StructuringReport::annotations`](crate::backend::ctrl_flow_struct::StructuringReport::annotations)
+    /// as strings. Those of nodes that write no lines are dropped.
+    pub annotations: HashMap<AstNodeId, String>,
+    /// Lines to put before the first line of the nodes of the AST, by their
+    /// [`AstNodeId`], e.g. the [`address_markers`]. Only a node that starts
+    /// a statement of its own gets its line, so not one that is the `else
+    /// if` of another, nor a `Seq` or a `Try`, whose first statement does.
+    pub markers: HashMap<AstNodeId, String>,
+}
+
+/// How [`address_markers`] writes an address.
+#[derive(Copy, Clone, Debug)]
+pub enum MarkerFormat {
+    /// `/*@ 0x401234 */`
+    Comment,
+    /// `#pragma addr 0x401234`, which compilers ignore or warn about
+    Pragma,
+    /// whatever the function makes of the address, which must be a line
+    /// of its own on its own
+    Custom(fn(u64) -> String),
+}
+
+impl MarkerFormat {
+    fn marker(self, addr: u64) -> String {
+        match self {
+            MarkerFormat::Comment => format!("/*@ {:#x} */", addr),
+            MarkerFormat::Pragma => format!("#pragma addr {:#x}", addr),
+            MarkerFormat::Custom(f) => f(addr),
+        }
+    }
+}
+
+/// The address that each statement of `ast` comes from, as `format` writes
+/// it, for [`WriterOptions::markers`]. A block comes from its first address,
+/// and a construct from the block heading it: an `if` and a `while` from
+/// the block ending in the branch they test, a `do`-`while` and an endless
+/// loop from the first statement of their body, a `for` from its `init`,
+/// and a `switch`, whose head has no address of its own, from the lowest
+/// address in it. Statements without an address, like `break`, get no
+/// marker.
+pub fn address_markers<B, C, V, P>(
+    prov: &P,
+    ast: &AstNode<B, C, V>,
+    format: MarkerFormat,
+) -> HashMap<AstNodeId, String>
+where
+    P: Provenance<B, C>,
+{
+    decisions::preorder(ast)
+        .into_iter()
+        .enumerate()
+        .filter(|(_, a)| !matches!(a, AstNode::Seq(_) | AstNode::Try(..)))
+        .filter_map(|(i, a)| Some((AstNodeId(i), format.marker(head_addr(prov, a)?))))
+        .collect()
+}
+
+/// The address of the block heading `ast`, see [`address_markers`].
+fn head_addr<B, C, V, P>(prov: &P, ast: &AstNode<B, C, V>) -> Option<u64>
+where
+    P: Provenance<B, C>,
+{
+    use self::AstNode::*;
+    match ast {
+        BasicBlock(b) | TailCall(b) | IndirectJump(b) | For(b, _, _, _) => prov.block_addr(b),
+        Cond(c, _, _) | Loop(LoopType::PreChecked(c), _) => prov.cond_addr(c),
+        Loop(_, b) | Try(b, _) => head_addr(prov, b),
+        Unstructured(members) => members
+            .iter()
+            .find(|(_, a)| !is_empty(a))
+            .and_then(|(_, a)| head_addr(prov, a)),
+        Seq(seq) => seq
+            .iter()
+            .find(|a| !is_empty(a))
+            .and_then(|a| head_addr(prov, a)),
+        Switch(..) => provenance::covered(prov, ast)
+            .ranges()
+            .first()
+            .map(|r| r.start),
+        Break | Continue | Return | Goto(_) | Label(_) => None,
+    }
+}
+
+/// Writes `ast` as the body of a C function called `name` that takes no
+/// arguments and returns nothing.
+pub fn write_function<B, C, V, R>(name: &str, ast: &AstNode<B, C, V>, renderer: &mut R) -> String
+where
+    R: StmtRenderer<B, C, V>,
+{
+    write_function_with(name, ast, renderer, &WriterOptions::default())
+}
+
+/// Like [`write_function`], but in the dialect chosen by `opts`.
+pub fn write_function_with<B, C, V, R>(
+    name: &str,
+    ast: &AstNode<B, C, V>,
+    renderer: &mut R,
+    opts: &WriterOptions,
+) -> String
+where
+    R: StmtRenderer<B, C, V>,
+{
+    let mut used_labels = HashSet::new();
+    find_gotos(ast, &mut used_labels);
+    let annotations = decisions::preorder(ast)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, a)| {
+            let text = opts.annotations.get(&AstNodeId(i))?;
+            Some((a as *const AstNode<B, C, V> as usize, text.clone()))
+        })
+        .collect();
+    let markers = decisions::preorder(ast)
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, a)| {
+            let text = opts.markers.get(&AstNodeId(i))?;
+            Some((a as *const AstNode<B, C, V> as usize, text.clone()))
+        })
+        .collect();
+
+    let mut writer = Writer {
+        renderer,
+        dialect: opts.dialect,
+        out: String::new(),
+        used_labels,
+        annotations,
+        markers,
+        pending: None,
+        scopes: Vec::new(),
+        next_break: 0,
+        next_loop: 0,
+    };
+    let _ = match opts.dialect {
+        Dialect::C => writeln!(writer.out, "void {}(void) {{", name),
+        Dialect::Rust => writeln!(writer.out, "fn {}() {{", name),
+    };
+    writer.stmt(ast, 1);
+    writer.out.push_str("}\n");
+    writer.out
+}
+
+struct Writer<'r, R: 'r> {
+    renderer: &'r mut R,
+    dialect: Dialect,
+    out: String,
+    /// labels that are the target of some `Goto`; the others aren't emitted
+    used_labels: HashSet<LabelId>,
+    /// the [`WriterOptions::annotations`], by the address of their node
+    annotations: HashMap<usize, String>,
+    /// the [`WriterOptions::markers`], by the address of their node
+    markers: HashMap<usize, String>,
+    /// the annotations of the nodes being written, for the next line
+    pending: Option<String>,
+    /// enclosing loops and switches, innermost last
+    scopes: Vec<Scope>,
+    next_break: usize,
+    next_loop: usize,
+}
+
+enum Scope {
+    /// The label that a `break` of this loop from inside a nested switch
+    /// jumps to, if one was needed.
+    Loop(Option<String>),
+    /// A Rust loop whose body is a labeled block, see [`Dialect::Rust`]:
+    /// the labels of the loop and of its body.
+    LabeledLoop(String, String),
+    Switch,
+}
+
+impl<'r, R> Writer<'r, R> {
+    fn line(&mut self, depth: usize, s: &str) {
+        self.out.extend(iter::repeat(INDENT).take(depth));
+        self.out.push_str(s);
+        if let Some(text) = self.pending.take() {
+            let _ = write!(self.out, " // {}", text);
+        }
+        self.out.push('\n');
+    }
+
+    fn is_rust(&self) -> bool {
+        self.dialect == Dialect::Rust
+    }
+
+    fn stmt<B, C, V>(&mut self, ast: &AstNode<B, C, V>, depth: usize)
+    where
+        R: StmtRenderer<B, C, V>,
+    {
+        self.mark(ast, depth);
+        self.annotate(ast);
+        self.node(ast, depth);
+        self.pending = None;
+    }
+
+    /// Writes the marker of `ast`, if it has one, on a line of its own
+    /// before it, leaving the annotations for the first line of `ast`.
+    fn mark<B, C, V>(&mut self, ast: &AstNode<B, C, V>, depth: usize) {
+        let key = ast as *const AstNode<B, C, V> as usize;
+        if let Some(text) = self.markers.get(&key) {
+            for _ in 0..depth {
+                self.out.push_str(INDENT);
+            }
+            self.out.push_str(text);
+            self.out.push('\n');
+        }
+    }
+
+    fn node<B, C, V>(&mut self, ast: &AstNode<B, C, V>, depth: usize)
+    where
+        R: StmtRenderer<B, C, V>,
+    {
+        use self::AstNode::*;
+        match ast {
+            BasicBlock(b) => {
+                for s in self.renderer.block(b) {
+                    self.line(depth, &s);
+                }
+            }
+            Seq(seq) => {
+                for a in seq {
+                    self.stmt(a, depth);
+                }
+            }
+            Cond(c, t, oe) => {
+                let c = self.renderer.cond(c);
+                let head = self.if_head("if", &c);
+                self.line(depth, &head);
+                self.stmt(t, depth + 1);
+                let mut else_opt = oe.as_ref().map(|e| &**e);
+                // turn `else { if ... }` into `else if ...`
+                while let Some(else_if @ &Cond(ref c, ref t, ref oe)) = else_opt {
+                    let c = self.renderer.cond(c);
+                    let head = self.if_head("} else if", &c);
+                    self.annotate(else_if);
+                    self.line(depth, &head);
+                    self.stmt(t, depth + 1);
+                    else_opt = oe.as_ref().map(|e| &**e);
+                }
+                if let Some(e) = else_opt {
+                    self.line(depth, "} else {");
+                    self.stmt(e, depth + 1);
+                }
+                self.line(depth, "}");
+            }
+            Loop(lt, b) if self.is_rust() => self.rust_loop(lt, b, depth),
+            // a bare `;` rather than an empty block, as in `while (!flag) ;`
+            Loop(lt, b) if is_empty(b) => {
+                let text = match lt {
+                    LoopType::PreChecked(c) => format!("while ({}) ;", self.renderer.cond(c)),
+                    LoopType::PostChecked(c) => format!("do ; while ({});", self.renderer.cond(c)),
+                    LoopType::Endless => "for (;;) ;".to_owned(),
+                };
+                self.line(depth, &text);
+            }
+            Loop(lt, b) => {
+                let (head, tail) = match lt {
+                    LoopType::PreChecked(c) => (
+                        format!("while ({}) {{", self.renderer.cond(c)),
+                        "}".to_owned(),
+                    ),
+                    LoopType::PostChecked(c) => (
+                        "do {".to_owned(),
+                        format!("}} while ({});", self.renderer.cond(c)),
+                    ),
+                    LoopType::Endless => ("for (;;) {".to_owned(), "}".to_owned()),
+                };
+                self.c_loop(&head, b, &tail, depth);
+            }
+            For(i, c, u, b) if self.is_rust() => self.rust_for(i, c, u, b, depth),
+            For(i, c, u, b) => {
+                let head = format!(
+                    "for ({}; {}; {}) {{",
+                    self.renderer.for_clause(i),
+                    self.renderer.cond(c),
+                    self.renderer.for_clause(u)
+                );
+                self.c_loop(&head, b, "}", depth);
+            }
+            Break => {
+                // in C, `break` inside a `switch` leaves the switch, but ours
+                // always leaves the nearest loop
+                let mut crosses_switch = false;
+                let mut target = None;
+                for scope in self.scopes.iter_mut().rev() {
+                    match scope {
+                        Scope::Switch => crosses_switch = true,
+                        Scope::Loop(opt_break) => {
+                            target = Some(opt_break);
+                            break;
+                        }
+                        Scope::LabeledLoop(l, _) => {
+                            let text = format!("break {};", l);
+                            self.line(depth, &text);
+                            return;
+                        }
+                    }
+                }
+                match target {
+                    // a Rust `break` inside a `match` leaves the loop already
+                    Some(opt_break) if crosses_switch && self.dialect == Dialect::C => {
+                        let next_break = &mut self.next_break;
+                        let l = opt_break
+                            .get_or_insert_with(|| {
+                                *next_break += 1;
+                                format!("break_{}", *next_break - 1)
+                            })
+                            .clone();
+                        self.line(depth, &format!("goto {};", l));
+                    }
+                    _ => self.line(depth, "break;"),
+                }
+            }
+            Switch(v, cases, default) if self.is_rust() => {
+                let v = self.renderer.var(v);
+                self.line(depth, &format!("match {} {{", v));
+                self.scopes.push(Scope::Switch);
+                for (vs, a) in cases {
+                    self.match_arm(&match_pattern(vs), a, depth + 1);
+                }
+                self.match_arm("_", default, depth + 1);
+                self.scopes.pop();
+                self.line(depth, "}");
+            }
+            Switch(v, cases, default) => {
+                let v = self.renderer.var(v);
+                self.line(depth, &format!("switch ({}) {{", v));
+                self.scopes.push(Scope::Switch);
+                for (vs, a) in cases {
+                    for val in self.renderer.case_values(vs) {
+                        self.line(depth, &format!("case {}:", val));
+                    }
+                    self.case_body(a, depth + 1);
+                }
+                self.line(depth, "default:");
+                self.case_body(default, depth + 1);
+                self.scopes.pop();
+                self.line(depth, "}");
+            }
+            Continue => {
+                let body_label = self.scopes.iter().rev().find_map(|s| match s {
+                    Scope::Loop(_) => Some(None),
+                    Scope::LabeledLoop(_, body) => Some(Some(body.clone())),
+                    Scope::Switch => None,
+                });
+                match body_label {
+                    Some(Some(body)) => self.line(depth, &format!("break {};", body)),
+                    _ => self.line(depth, "continue;"),
+                }
+            }
+            Return => self.line(depth, "return;"),
+            TailCall(b) => {
+                for s in self.renderer.tail_call(b) {
+                    self.line(depth, &s);
+                }
+            }
+            IndirectJump(b) => {
+                for s in self.renderer.indirect_jump(b) {
+                    self.line(depth, &s);
+                }
+            }
+            Goto(l) => {
+                let l = self.renderer.label(*l);
+                self.line(depth, &format!("goto {};", l));
+            }
+            Label(l) => {
+                if self.used_labels.contains(l) {
+                    let l = self.renderer.label(*l);
+                    let text = match self.dialect {
+                        // the empty statement keeps this valid at the end of a
+                        // block
+                        Dialect::C => format!("{}:;", l),
+                        Dialect::Rust => format!("{}:", l),
+                    };
+                    self.line(depth.saturating_sub(1), &text);
+                }
+            }
+            Try(b, h) => {
+                // C has no exceptions, so only say where they would go
+                self.line(depth, &format!("// unwinds to handler_{}", h.0));
+                self.stmt(b, depth);
+            }
+            Unstructured(members) => {
+                self.line(depth, "// unstructured region");
+                for (l, a) in members {
+                    self.node(&Label(*l), depth);
+                    self.stmt(a, depth);
+                }
+            }
+        }
+    }
+
+    /// Puts the annotation of `ast`, if it has one, at the end of the next
+    /// line, after those of the nodes it is the first line of too.
+    fn annotate<B, C, V>(&mut self, ast: &AstNode<B, C, V>) {
+        let key = ast as *const AstNode<B, C, V> as usize;
+        if let Some(text) = self.annotations.get(&key) {
+            self.pending = Some(match self.pending.take() {
+                Some(outer) => format!("{}; {}", outer, text),
+                None => text.clone(),
+            });
+        }
+    }
+
+    /// The line opening an `if` on the rendered condition `c`.
+    fn if_head(&self, keyword: &str, c: &str) -> String {
+        match self.dialect {
+            Dialect::C => format!("{} ({}) {{", keyword, c),
+            Dialect::Rust => format!("{} {} {{", keyword, c),
+        }
+    }
+
+    /// Writes a C loop with the body `b` between the lines `head` and
+    /// `tail`.
+    fn c_loop<B, C, V>(&mut self, head: &str, b: &AstNode<B, C, V>, tail: &str, depth: usize)
+    where
+        R: StmtRenderer<B, C, V>,
+    {
+        self.line(depth, head);
+        self.scopes.push(Scope::Loop(None));
+        self.stmt(b, depth + 1);
+        let opt_break = match self.scopes.pop() {
+            Some(Scope::Loop(opt_break)) => opt_break,
+            _ => unreachable!(),
+        };
+        self.line(depth, tail);
+        if let Some(l) = opt_break {
+            self.line(depth.saturating_sub(1), &format!("{}:;", l));
+        }
+    }
+
+    /// Writes a `For` as its `init` followed by a `while` loop whose body
+    /// ends in its `update`. If the body `continue`s, it is a labeled block
+    /// that `continue` breaks out of, so the update still runs.
+    fn rust_for<B, C, V>(&mut self, i: &B, c: &C, u: &B, b: &AstNode<B, C, V>, depth: usize)
+    where
+        R: StmtRenderer<B, C, V>,
+    {
+        for s in self.renderer.block(i) {
+            self.line(depth, &s);
+        }
+        let c = self.renderer.cond(c);
+        if continues(b) {
+            let n = self.next_loop;
+            self.next_loop += 1;
+            let (loop_label, body_label) = (format!("'loop_{}", n), format!("'body_{}", n));
+            self.line(depth, &format!("{}: while {} {{", loop_label, c));
+            self.line(depth + 1, &format!("{}: {{", body_label));
+            self.scopes.push(Scope::LabeledLoop(loop_label, body_label));
+            self.stmt(b, depth + 2);
+            self.scopes.pop();
+            self.line(depth + 1, "}");
+        } else {
+            self.line(depth, &format!("while {} {{", c));
+            self.loop_body(b, depth + 1);
+        }
+        for s in self.renderer.block(u) {
+            self.line(depth + 1, &s);
+        }
+        self.line(depth, "}");
+    }
+
+    fn rust_loop<B, C, V>(&mut self, lt: &LoopType<C>, b: &AstNode<B, C, V>, depth: usize)
+    where
+        R: StmtRenderer<B, C, V>,
+    {
+        match lt {
+            LoopType::PreChecked(c) => {
+                let c = self.renderer.cond(c);
+                self.line(depth, &format!("while {} {{", c));
+                self.loop_body(b, depth + 1);
+            }
+            LoopType::Endless => {
+                self.line(depth, "loop {");
+                self.loop_body(b, depth + 1);
+            }
+            LoopType::PostChecked(c) if continues(b) => {
+                let n = self.next_loop;
+                self.next_loop += 1;
+                let (loop_label, body_label) = (format!("'loop_{}", n), format!("'body_{}", n));
+                self.line(depth, &format!("{}: loop {{", loop_label));
+                self.line(depth + 1, &format!("{}: {{", body_label));
+                self.scopes
+                    .push(Scope::LabeledLoop(loop_label.clone(), body_label));
+                self.stmt(b, depth + 2);
+                self.scopes.pop();
+                self.line(depth + 1, "}");
+                let c = self.renderer.cond(c);
+                self.line(depth + 1, &format!("if !({}) {{", c));
+                self.line(depth + 2, &format!("break {};", loop_label));
+                self.line(depth + 1, "}");
+            }
+            LoopType::PostChecked(c) => {
+                self.line(depth, "loop {");
+                self.loop_body(b, depth + 1);
+                let c = self.renderer.cond(c);
+                self.line(depth + 1, &format!("if !({}) {{", c));
+                self.line(depth + 2, "break;");
+                self.line(depth + 1, "}");
+            }
+        }
+        self.line(depth, "}");
+    }
+
+    fn loop_body<B, C, V>(&mut self, b: &AstNode<B, C, V>, depth: usize)
+    where
+        R: StmtRenderer<B, C, V>,
+    {
+        self.scopes.push(Scope::Loop(None));
+        self.stmt(b, depth);
+        self.scopes.pop();
+    }
+
+    fn match_arm<B, C, V>(&mut self, pattern: &str, ast: &AstNode<B, C, V>, depth: usize)
+    where
+        R: StmtRenderer<B, C, V>,
+    {
+        self.line(depth, &format!("{} => {{", pattern));
+        self.stmt(ast, depth + 1);
+        self.line(depth, "}");
+    }
+
+    fn case_body<B, C, V>(&mut self, ast: &AstNode<B, C, V>, depth: usize)
+    where
+        R: StmtRenderer<B, C, V>,
+    {
+        self.stmt(ast, depth);
+        if !ends_in_jump(ast) {
+            self.line(depth, "break;");
+        }
+    }
+}
+
+/// A Rust pattern matching the values in `vs`.
+fn match_pattern(vs: &ValueSet) -> String {
+    let alternatives: Vec<_> = vs
+        .ranges()
+        .iter()
+        .map(|&(lo, hi)| {
+            if lo == hi {
+                lo.to_string()
+            } else {
+                format!("{}..={}", lo, hi)
+            }
+        })
+        .collect();
+    alternatives.join(" | ")
+}
+
+/// Whether `ast` does nothing at all.
+fn is_empty<B, C, V>(ast: &AstNode<B, C, V>) -> bool {
+    match ast {
+        AstNode::Seq(seq) => seq.iter().all(is_empty),
+        _ => false,
+    }
+}
+
+/// Whether `ast` `continue`s the loop it is the body of.
+fn continues<B, C, V>(ast: &AstNode<B, C, V>) -> bool {
+    use self::AstNode::*;
+    match ast {
+        Continue => true,
+        Seq(seq) => seq.iter().any(continues),
+        Cond(_, t, oe) => continues(t) || oe.as_ref().map_or(false, |e| continues(e)),
+        Switch(_, cases, default) => cases.iter().any(|(_, a)| continues(a)) || continues(default),
+        Try(b, _) => continues(b),
+        Unstructured(members) => members.iter().any(|(_, a)| continues(a)),
+        // a nested loop has its own `continue`s
+        Loop(_, _) | For(..) => false,
+        BasicBlock(_) | Break | Return | TailCall(_) | IndirectJump(_) | Goto(_) | Label(_) => {
+            false
+        }
+    }
+}
+
+fn find_gotos<B, C, V>(ast: &AstNode<B, C, V>, labels: &mut HashSet<LabelId>) {
+    use self::AstNode::*;
+    match ast {
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Label(_) => (),
+        Seq(seq) => {
+            for a in seq {
+                find_gotos(a, labels);
+            }
+        }
+        Cond(_, t, oe) => {
+            find_gotos(t, labels);
+            if let Some(e) = oe {
+                find_gotos(e, labels);
+            }
+        }
+        Loop(_, b) | For(_, _, _, b) | Try(b, _) => find_gotos(b, labels),
+        Unstructured(members) => {
+            for (_, a) in members {
+                find_gotos(a, labels);
+            }
+        }
+        Switch(_, cases, default) => {
+            for (_, a) in cases {
+                find_gotos(a, labels);
+            }
+            find_gotos(default, labels);
+        }
+        Goto(l) => {
+            labels.insert(*l);
+        }
+    }
+}
+
+/// Whether control never falls off the end of `ast`.
+fn ends_in_jump<B, C, V>(ast: &AstNode<B, C, V>) -> bool {
+    use self::AstNode::*;
+    match ast {
+        Seq(seq) => seq.last().map_or(false, ends_in_jump),
+        Break | Continue | Return | TailCall(_) | IndirectJump(_) | Goto(_) => true,
+        Try(b, _) => ends_in_jump(b),
+        Unstructured(members) => members.last().is_some_and(|(_, a)| ends_in_jump(a)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::ast::LoopType::*;
+
+    use super::super::r2_comments::R2Renderer;
+    use crate::backend::ctrl_flow_struct::from_r2::{
+        Block, CondExpr, R2Code, R2Cond, R2Provenance, Var,
+    };
+
+    use std::env;
+    use std::fs;
+    use std::process::Command;
+
+    struct StringRenderer;
+
+    impl StmtRenderer<String, String, String> for StringRenderer {
+        fn block(&mut self, block: &String) -> Vec<String> {
+            vec![format!("{};", block)]
+        }
+
+        fn cond(&mut self, cond: &String) -> String {
+            cond.clone()
+        }
+
+        fn var(&mut self, var: &String) -> String {
+            var.clone()
+        }
+    }
+
+    fn bb(s: &str) -> AstNode<String, String, String> {
+        BasicBlock(s.to_owned())
+    }
+
+    fn nested_ast() -> AstNode<String, String, String> {
+        Seq(vec![
+            bb("a = 0"),
+            Loop(
+                PreChecked("a < 10".to_owned()),
+                Box::new(Seq(vec![
+                    Cond(
+                        "b".to_owned(),
+                        Box::new(Continue),
+                        Some(Box::new(Cond(
+                            "c".to_owned(),
+                            Box::new(Goto(LabelId(0))),
+                            Some(Box::new(bb("b = a"))),
+                        ))),
+                    ),
+                    Switch(
+                        "a".to_owned(),
+                        vec![
+                            (ValueSet::single(1), Seq(vec![bb("c = 1"), Break])),
+                            (ValueSet::range(5, 7), bb("c = 5")),
+                        ],
+                        Box::new(bb("c = 0")),
+                    ),
+                    bb("a = a + 1"),
+                ])),
+            ),
+            Loop(
+                PostChecked("b".to_owned()),
+                Box::new(Seq(vec![bb("b = b - 1"), Label(LabelId(1))])),
+            ),
+            Loop(
+                Endless,
+                Box::new(Cond("c".to_owned(), Box::new(Break), None)),
+            ),
+            Label(LabelId(0)),
+            Return,
+        ])
+    }
+
+    const NESTED_C: &str = "\
+void f(void) {
+    a = 0;
+    while (a < 10) {
+        if (b) {
+            continue;
+        } else if (c) {
+            goto label_0;
+        } else {
+            b = a;
+        }
+        switch (a) {
+        case 1:
+            c = 1;
+            goto break_0;
+        case 5 ... 7:
+            c = 5;
+            break;
+        default:
+            c = 0;
+            break;
+        }
+        a = a + 1;
+    }
+break_0:;
+    do {
+        b = b - 1;
+    } while (b);
+    for (;;) {
+        if (c) {
+            break;
+        }
+    }
+label_0:;
+    return;
+}
+";
+
+    #[test]
+    fn write_nested() {
+        let c = write_function("f", &nested_ast(), &mut StringRenderer);
+        assert_eq!(c, NESTED_C);
+    }
+
+    #[test]
+    fn write_label_names() {
+        let labels = [(LabelId(0), "out".to_owned())];
+        let c = write_function(
+            "f",
+            &nested_ast(),
+            &mut LabelNames::new(StringRenderer, &labels),
+        );
+        assert_eq!(c, NESTED_C.replace("label_0", "out"));
+    }
+
+    #[test]
+    fn write_tail_call() {
+        let ast = Cond(
+            "c".to_owned(),
+            Box::new(bb("a = 1")),
+            Some(Box::new(TailCall("g(a)".to_owned()))),
+        );
+        // by default, the call is just a statement before the `return`
+        let c = write_function("f", &ast, &mut StringRenderer);
+        assert_eq!(
+            c,
+            "\
+void f(void) {
+    if (c) {
+        a = 1;
+    } else {
+        g(a);
+        return;
+    }
+}
+"
+        );
+    }
+
+    const NESTED_RUST: &str = "\
+fn f() {
+    a = 0;
+    while a < 10 {
+        if b {
+            continue;
+        } else if c {
+            goto label_0;
+        } else {
+            b = a;
+        }
+        match a {
+            1 => {
+                c = 1;
+                break;
+            }
+            5..=7 => {
+                c = 5;
+            }
+            _ => {
+                c = 0;
+            }
+        }
+        a = a + 1;
+    }
+    loop {
+        b = b - 1;
+        if !(b) {
+            break;
+        }
+    }
+    loop {
+        if c {
+            break;
+        }
+    }
+label_0:
+    return;
+}
+";
+
+    fn rust() -> WriterOptions {
+        WriterOptions {
+            dialect: Dialect::Rust,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn write_nested_rust() {
+        let rust = write_function_with("f", &nested_ast(), &mut StringRenderer, &rust());
+        assert_eq!(rust, NESTED_RUST);
+    }
+
+    #[test]
+    fn write_do_while_continue_rust() {
+        let ast = Loop(
+            PostChecked("a".to_owned()),
+            Box::new(Seq(vec![
+                Cond("b".to_owned(), Box::new(Continue), None),
+                Cond("c".to_owned(), Box::new(Break), None),
+                Switch(
+                    "a".to_owned(),
+                    vec![(vec![1, 3, 4].into_iter().collect(), Break)],
+                    Box::new(bb("a = a - 1")),
+                ),
+            ])),
+        );
+        let rust = write_function_with("f", &ast, &mut StringRenderer, &rust());
+        assert_eq!(
+            rust,
+            "\
+fn f() {
+    'loop_0: loop {
+        'body_0: {
+            if b {
+                break 'body_0;
+            }
+            if c {
+                break 'loop_0;
+            }
+            match a {
+                1 | 3..=4 => {
+                    break 'loop_0;
+                }
+                _ => {
+                    a = a - 1;
+                }
+            }
+        }
+        if !(a) {
+            break 'loop_0;
+        }
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn write_for() {
+        let ast = For(
+            "i = 0".to_owned(),
+            "i < n".to_owned(),
+            "i = i + 1".to_owned(),
+            Box::new(Cond(
+                "b".to_owned(),
+                Box::new(Continue),
+                Some(Box::new(bb("f(i)"))),
+            )),
+        );
+        let c = write_function("f", &ast, &mut StringRenderer);
+        assert_eq!(
+            c,
+            "\
+void f(void) {
+    for (i = 0; i < n; i = i + 1) {
+        if (b) {
+            continue;
+        } else {
+            f(i);
+        }
+    }
+}
+"
+        );
+        // `continue` must not skip the update
+        let rust = write_function_with("f", &ast, &mut StringRenderer, &rust());
+        assert_eq!(
+            rust,
+            "\
+fn f() {
+    i = 0;
+    'loop_0: while i < n {
+        'body_0: {
+            if b {
+                break 'body_0;
+            } else {
+                f(i);
+            }
+        }
+        i = i + 1;
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn write_annotations() {
+        let ast = Seq(vec![
+            bb("s"),
+            Cond(
+                "a < b".to_owned(),
+                Box::new(bb("x = a")),
+                Some(Box::new(bb("x = b"))),
+            ),
+            Cond(
+                "c".to_owned(),
+                Box::new(bb("f()")),
+                Some(Box::new(Cond(
+                    "a < 0".to_owned(),
+                    Box::new(bb("a = -a")),
+                    None,
+                ))),
+            ),
+            Loop(LoopType::Endless, Box::new(bb("g()"))),
+        ]);
+        let mut opts = WriterOptions::default();
+        opts.annotations
+            .insert(AstNodeId(2), "x = min(a, b)".to_owned());
+        opts.annotations
+            .insert(AstNodeId(7), "a = abs(a)".to_owned());
+        opts.annotations
+            .insert(AstNodeId(9), "state machine".to_owned());
+        let c = write_function_with("f", &ast, &mut StringRenderer, &opts);
+        assert_eq!(
+            c,
+            "\
+void f(void) {
+    s;
+    if (a < b) { // x = min(a, b)
+        x = a;
+    } else {
+        x = b;
+    }
+    if (c) {
+        f();
+    } else if (a < 0) { // a = abs(a)
+        a = -a;
+    }
+    for (;;) { // state machine
+        g();
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn write_empty_loops() {
+        let empty = || Box::new(Seq(Vec::new()));
+        let ast = Seq(vec![
+            Loop(PreChecked("!flag".to_owned()), empty()),
+            Loop(PostChecked("!r".to_owned()), empty()),
+            Loop(Endless, empty()),
+        ]);
+        let mut opts = WriterOptions::default();
+        opts.annotations
+            .insert(AstNodeId(1), "spin-wait".to_owned());
+        let c = write_function_with("f", &ast, &mut StringRenderer, &opts);
+        assert_eq!(
+            c,
+            "\
+void f(void) {
+    while (!flag) ; // spin-wait
+    do ; while (!r);
+    for (;;) ;
+}
+"
+        );
+    }
+
+    #[test]
+    fn write_source_lines() {
+        let mut lines = LineTable::new();
+        lines.add_row(0x10, "foo.c", 41);
+        lines.add_row(0x14, "foo.c", 42);
+        lines.add_row(0x18, "foo.c", 44);
+        lines.end_sequence(0x1c);
+        let code = |addr, size| BasicBlock(Block::Code(R2Code::Block { addr, size }));
+        let ast = Seq(vec![
+            code(0x10, 8),
+            Cond(
+                CondExpr::Pred(R2Cond::Taken(0x14)),
+                Box::new(code(0x18, 4)),
+                None,
+            ),
+            // no line information
+            code(0x40, 4),
+        ]);
+        let mut renderer = LineAnnotator::new(R2Renderer, &R2Provenance, &lines);
+        let c = write_function("f", &ast, &mut renderer);
+        assert_eq!(
+            c,
+            "\
+void f(void) {
+    block_10(); /* foo.c:41-42 */
+    if (cond_14 /* foo.c:42 */) {
+        block_18(); /* foo.c:44 */
+    }
+    block_40();
+}
+"
+        );
+    }
+
+    fn addressed_ast() -> AstNode<Block, CondExpr, Var> {
+        let code = |addr, size| BasicBlock(Block::Code(R2Code::Block { addr, size }));
+        Seq(vec![
+            code(0x10, 8),
+            Cond(
+                CondExpr::Pred(R2Cond::Taken(0x14)),
+                Box::new(code(0x18, 4)),
+                Some(Box::new(Cond(
+                    CondExpr::Pred(R2Cond::Taken(0x18)),
+                    Box::new(Return),
+                    None,
+                ))),
+            ),
+            Loop(
+                PostChecked(CondExpr::Pred(R2Cond::Taken(0x24))),
+                Box::new(Seq(vec![
+                    Seq(Vec::new()),
+                    code(0x20, 8),
+                    Cond(CondExpr::Pred(R2Cond::Taken(0x20)), Box::new(Break), None),
+                ])),
+            ),
+            // no address
+            BasicBlock(Block::Assign(Var(0), 1)),
+        ])
+    }
+
+    const ADDRESSED_C: &str = "\
+void f(void) {
+    block_10();
+    if (cond_14) {
+        block_18();
+    } else if (cond_18) {
+        return;
+    }
+    do {
+        block_20();
+        if (cond_20) {
+            break;
+        }
+    } while (cond_24);
+    v0 = 1;
+}
+";
+
+    #[test]
+    fn write_address_markers() {
+        let ast = addressed_ast();
+        let c = write_function("f", &ast, &mut R2Renderer);
+        assert_eq!(c, ADDRESSED_C);
+
+        let mut opts = WriterOptions {
+            markers: address_markers(&R2Provenance, &ast, MarkerFormat::Comment),
+            ..Default::default()
+        };
+        opts.annotations.insert(AstNodeId(2), "x".to_owned());
+        let c = write_function_with("f", &ast, &mut R2Renderer, &opts);
+        assert_eq!(
+            c,
+            "\
+void f(void) {
+    /*@ 0x10 */
+    block_10();
+    /*@ 0x14 */
+    if (cond_14) { // x
+        /*@ 0x18 */
+        block_18();
+    } else if (cond_18) {
+        return;
+    }
+    /*@ 0x20 */
+    do {
+        /*@ 0x20 */
+        block_20();
+        /*@ 0x20 */
+        if (cond_20) {
+            break;
+        }
+    } while (cond_24);
+    v0 = 1;
+}
+"
+        );
+        // and without the markers, the same code
+        let unmarked: Vec<_> = c.lines().filter(|l| !l.contains("/*@")).collect();
+        assert_eq!(
+            unmarked.join("\n") + "\n",
+            ADDRESSED_C.replace("(cond_14) {", "(cond_14) { // x")
+        );
+
+        opts.markers = address_markers(&R2Provenance, &ast, MarkerFormat::Pragma);
+        let c = write_function_with("f", &ast, &mut R2Renderer, &opts);
+        assert!(c.starts_with("void f(void) {\n    #pragma addr 0x10\n    block_10();\n"));
+        opts.markers = address_markers(
+            &R2Provenance,
+            &ast,
+            MarkerFormat::Custom(|a| format!("// @{}", a)),
+        );
+        let c = write_function_with("f", &ast, &mut R2Renderer, &opts);
+        assert!(c.contains("    // @16\n    block_10();\n"));
+    }
+
+    #[test]
+    #[ignore]
+    fn write_nested_gcc() {
+        let c = write_function("f", &nested_ast(), &mut StringRenderer);
+        let path = env::temp_dir().join("radeco_c_writer_test.c");
+        fs::write(&path, format!("int a, b, c;\n{}", c)).unwrap();
+        let status = Command::new("gcc")
+            .arg("-fsyntax-only")
+            .arg("-Wall")
+            .arg("-Werror")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
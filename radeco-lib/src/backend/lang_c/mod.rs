@@ -5,9 +5,13 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
+pub mod batch;
 pub mod c_ast;
 pub mod c_cfg;
 pub mod c_cfg_builder;
+pub mod c_writer;
+pub mod r2_comments;
+pub mod r2_overlay;
 
 #[cfg(test)]
 mod test;
@@ -0,0 +1,429 @@
+//! Turns a structured [`AstNode`] into comments for radare2.
+//!
+//! Every control flow construct is summarized by its C header (`if (...)`,
+//! `while (...)`, `switch (...)`, ...) and attached to the address where it
+//! originates: the branch block for a condition that came from one, or the
+//! header of the construct for conditions introduced by structuring. The
+//! result is a plain list of `(address, comment)` pairs, see [`comments`];
+//! [`r2_commands`] formats them as radare2 commands.
+
+use super::c_writer::StmtRenderer;
+use crate::backend::ctrl_flow_struct::ast::{AstNode, LoopType, ValueSet};
+use crate::backend::ctrl_flow_struct::esil::Predicate;
+use crate::backend::ctrl_flow_struct::from_r2::{Block, CondExpr, R2Code, R2Cond, Var};
+use crate::backend::ctrl_flow_struct::provenance::Provenance;
+
+use std::collections::HashMap;
+
+/// Returns the comments summarizing the control flow of `ast`, at most one
+/// per address, in the order their constructs appear in `ast`.
+///
+/// A condition without an address of its own is attached to the last block
+/// before it, so an `if` lands on the block that branches, or to the header
+/// of the enclosing loop if there is no block before it in the loop.
+pub fn comments<B, C, V, P, R>(
+    ast: &AstNode<B, C, V>,
+    prov: &P,
+    renderer: &mut R,
+) -> Vec<(u64, String)>
+where
+    P: Provenance<B, C>,
+    R: StmtRenderer<B, C, V>,
+{
+    let mut collector = Collector {
+        prov,
+        renderer,
+        comments: Vec::new(),
+        last_addr: None,
+    };
+    collector.node(ast);
+
+    // merge the comments at the same address
+    let mut ret: Vec<(u64, String)> = Vec::new();
+    let mut index_of: HashMap<u64, usize> = HashMap::new();
+    for (addr, text) in collector.comments {
+        match index_of.get(&addr) {
+            Some(&i) => {
+                ret[i].1.push_str("; ");
+                ret[i].1.push_str(&text);
+            }
+            None => {
+                index_of.insert(addr, ret.len());
+                ret.push((addr, text));
+            }
+        }
+    }
+    ret
+}
+
+/// Formats `comments` as radare2 commands that set them, one per comment.
+pub fn r2_commands(comments: &[(u64, String)]) -> Vec<String> {
+    comments
+        .iter()
+        .map(|(addr, text)| {
+            // quoted so that `;`, `|`, `@`, ... in `text` aren't interpreted
+            let text = text.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("\"CCu {}\" @ {:#x}", text, addr)
+        })
+        .collect()
+}
+
+struct Collector<'r, P: 'r, R: 'r> {
+    prov: &'r P,
+    renderer: &'r mut R,
+    comments: Vec<(u64, String)>,
+    /// the address of the last block seen
+    last_addr: Option<u64>,
+}
+
+impl<'r, P, R> Collector<'r, P, R> {
+    fn push(&mut self, opt_addr: Option<u64>, text: String) {
+        if let Some(addr) = opt_addr {
+            self.comments.push((addr, text));
+        }
+    }
+
+    fn node<B, C, V>(&mut self, ast: &AstNode<B, C, V>)
+    where
+        P: Provenance<B, C>,
+        R: StmtRenderer<B, C, V>,
+    {
+        use self::AstNode::*;
+        match ast {
+            BasicBlock(b) => {
+                if let Some(addr) = self.prov.block_addr(b) {
+                    self.last_addr = Some(addr);
+                }
+            }
+            Seq(seq) => {
+                for a in seq {
+                    self.node(a);
+                }
+            }
+            Cond(c, t, oe) => {
+                let addr = self
+                    .prov
+                    .cond_addr(c)
+                    .or(self.last_addr)
+                    .or_else(|| first_addr(self.prov, t));
+                let text = format!("if ({})", self.renderer.cond(c));
+                self.push(addr, text);
+                self.node(t);
+                if let Some(e) = oe {
+                    self.node(e);
+                }
+            }
+            Loop(lt, b) => {
+                let header = first_addr(self.prov, b).or(self.last_addr);
+                // synthesized constructs at the start of the body belong to
+                // the header, not to whatever came before the loop
+                self.last_addr = header;
+                match lt {
+                    LoopType::PreChecked(c) => {
+                        let addr = self.prov.cond_addr(c).or(header);
+                        let text = format!("while ({})", self.renderer.cond(c));
+                        self.push(addr, text);
+                        self.node(b);
+                    }
+                    LoopType::PostChecked(c) => {
+                        self.push(header, "do".to_owned());
+                        self.node(b);
+                        let addr = self.prov.cond_addr(c).or(self.last_addr);
+                        let text = format!("while ({})", self.renderer.cond(c));
+                        self.push(addr, text);
+                    }
+                    LoopType::Endless => {
+                        self.push(header, "for (;;)".to_owned());
+                        self.node(b);
+                    }
+                }
+            }
+            For(i, c, u, b) => {
+                if let Some(addr) = self.prov.block_addr(i) {
+                    self.last_addr = Some(addr);
+                }
+                let addr = self.prov.cond_addr(c).or(self.last_addr);
+                let text = format!(
+                    "for ({}; {}; {})",
+                    self.renderer.for_clause(i),
+                    self.renderer.cond(c),
+                    self.renderer.for_clause(u)
+                );
+                self.push(addr, text);
+                self.node(b);
+            }
+            Switch(v, cases, default) => {
+                let addr = self.last_addr.or_else(|| first_addr(self.prov, ast));
+                let text = format!("switch ({})", self.renderer.var(v));
+                self.push(addr, text);
+                for (vs, a) in cases {
+                    self.case(vs, a);
+                }
+                let addr = first_addr(self.prov, default).or(self.last_addr);
+                self.push(addr, "default:".to_owned());
+                self.node(default);
+            }
+            Break => self.push(self.last_addr, "break".to_owned()),
+            Continue => self.push(self.last_addr, "continue".to_owned()),
+            Return => self.push(self.last_addr, "return".to_owned()),
+            TailCall(b) => {
+                let addr = self.prov.block_addr(b).or(self.last_addr);
+                self.push(addr, "return".to_owned());
+            }
+            IndirectJump(b) => {
+                let addr = self.prov.block_addr(b).or(self.last_addr);
+                self.push(addr, "goto * (unresolved)".to_owned());
+            }
+            Goto(l) => {
+                let text = format!("goto {}", self.renderer.label(*l));
+                self.push(self.last_addr, text);
+            }
+            Label(_) => (),
+            Try(b, h) => {
+                let addr = first_addr(self.prov, b).or(self.last_addr);
+                self.push(addr, format!("unwinds to handler_{}", h.0));
+                self.node(b);
+            }
+            Unstructured(members) => {
+                let addr = first_addr(self.prov, ast).or(self.last_addr);
+                self.push(addr, "unstructured region".to_owned());
+                for (_, a) in members {
+                    self.node(a);
+                }
+            }
+        }
+    }
+
+    fn case<B, C, V>(&mut self, vs: &ValueSet, ast: &AstNode<B, C, V>)
+    where
+        P: Provenance<B, C>,
+        R: StmtRenderer<B, C, V>,
+    {
+        let addr = first_addr(self.prov, ast).or(self.last_addr);
+        let text = format!("case {}:", self.renderer.case_values(vs).join(", "));
+        self.push(addr, text);
+        self.node(ast);
+    }
+}
+
+/// Returns the address of the first block in `ast` that has one.
+fn first_addr<B, C, V, P>(prov: &P, ast: &AstNode<B, C, V>) -> Option<u64>
+where
+    P: Provenance<B, C>,
+{
+    use self::AstNode::*;
+    match ast {
+        BasicBlock(b) | TailCall(b) | IndirectJump(b) => prov.block_addr(b),
+        Seq(seq) => seq.iter().filter_map(|a| first_addr(prov, a)).next(),
+        Cond(_, t, oe) => {
+            first_addr(prov, t).or_else(|| oe.as_ref().and_then(|e| first_addr(prov, e)))
+        }
+        Loop(_, b) | Try(b, _) => first_addr(prov, b),
+        Unstructured(members) => members
+            .iter()
+            .filter_map(|(_, a)| first_addr(prov, a))
+            .next(),
+        For(i, _, u, b) => prov
+            .block_addr(i)
+            .or_else(|| first_addr(prov, b))
+            .or_else(|| prov.block_addr(u)),
+        Switch(_, cases, default) => cases
+            .iter()
+            .filter_map(|(_, a)| first_addr(prov, a))
+            .next()
+            .or_else(|| first_addr(prov, default)),
+        Break | Continue | Return | Goto(_) | Label(_) => None,
+    }
+}
+
+/// Renders functions imported from radare2 with
+/// [`from_r2`](crate::backend::ctrl_flow_struct::from_r2).
+#[derive(Debug, Default)]
+pub struct R2Renderer;
+
+impl StmtRenderer<Block, CondExpr, Var> for R2Renderer {
+    fn block(&mut self, block: &Block) -> Vec<String> {
+        vec![match block {
+            Block::Code(R2Code::Block { addr, .. }) => format!("block_{:x}();", addr),
+            Block::Code(R2Code::ExternalJump(addr)) => format!("fcn_{:x}();", addr),
+            Block::Assign(var, val) => format!("{} = {};", self.var(var), val),
+            Block::BoolAssign(var, cond) => format!("{} = {};", self.var(var), self.cond(cond)),
+        }]
+    }
+
+    fn cond(&mut self, cond: &CondExpr) -> String {
+        match cond {
+            CondExpr::Pred(c) => c.to_c(),
+            CondExpr::Equals(var, val) => format!("{} == {}", self.var(var), val),
+            CondExpr::BoolVar(var) => self.var(var),
+            CondExpr::Not(c) => format!("!{}", self.operand(c)),
+            CondExpr::All(cs) if cs.is_empty() => "1".to_owned(),
+            CondExpr::Any(cs) if cs.is_empty() => "0".to_owned(),
+            CondExpr::All(cs) => self.join(cs, " && "),
+            CondExpr::Any(cs) => self.join(cs, " || "),
+        }
+    }
+
+    fn var(&mut self, var: &Var) -> String {
+        var.to_string()
+    }
+
+    fn tail_call(&mut self, block: &Block) -> Vec<String> {
+        match block {
+            Block::Code(R2Code::ExternalJump(addr)) => vec![format!("return fcn_{:x}();", addr)],
+            _ => {
+                let mut ret = self.block(block);
+                ret.push("return;".to_owned());
+                ret
+            }
+        }
+    }
+
+    fn indirect_jump(&mut self, block: &Block) -> Vec<String> {
+        let mut ret = self.block(block);
+        let target = match block {
+            Block::Code(R2Code::Block { addr, .. }) => format!("target_{:x}", addr),
+            _ => "target".to_owned(),
+        };
+        ret.push(format!("goto *{}; /* unresolved */", target));
+        ret
+    }
+}
+
+impl R2Renderer {
+    /// Renders `cond` so that it can be an operand of `!`, `&&` or `||`.
+    fn operand(&mut self, cond: &CondExpr) -> String {
+        match cond {
+            CondExpr::Pred(R2Cond::Case(..) | R2Cond::Predicate(_, Predicate::Compare(..)))
+            | CondExpr::Equals(..)
+            | CondExpr::All(_)
+            | CondExpr::Any(_) => {
+                format!("({})", self.cond(cond))
+            }
+            _ => self.cond(cond),
+        }
+    }
+
+    fn join(&mut self, conds: &[CondExpr], sep: &str) -> String {
+        let operands: Vec<_> = conds.iter().map(|c| self.operand(c)).collect();
+        operands.join(sep)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::c_writer;
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::from_r2::{self, R2Provenance};
+    use crate::backend::ctrl_flow_struct::StructuringOptions;
+
+    use std::fs;
+
+    fn code(addr: u64) -> AstNode<Block, CondExpr, Var> {
+        BasicBlock(Block::Code(R2Code::Block { addr, size: 4 }))
+    }
+
+    #[test]
+    fn comment_addresses() {
+        let v = Var(0);
+        let ast = Seq(vec![
+            code(0x10),
+            Loop(
+                LoopType::PreChecked(CondExpr::Not(Box::new(CondExpr::Pred(R2Cond::Taken(0x14))))),
+                Box::new(Seq(vec![
+                    code(0x14),
+                    // synthesized: attached to the preceding block
+                    Cond(CondExpr::BoolVar(v), Box::new(Break), None),
+                    code(0x18),
+                ])),
+            ),
+            Loop(
+                LoopType::PostChecked(CondExpr::Equals(v, 1)),
+                Box::new(Seq(vec![code(0x20), code(0x24)])),
+            ),
+            // nothing before it in the loop; attached to the loop header
+            Loop(
+                LoopType::Endless,
+                Box::new(Seq(vec![
+                    Cond(CondExpr::Equals(v, 2), Box::new(Return), None),
+                    code(0x30),
+                ])),
+            ),
+        ]);
+        let comments = comments(&ast, &R2Provenance, &mut R2Renderer);
+        assert_eq!(
+            comments,
+            vec![
+                (0x14, "while (!cond_14); if (v0); break".to_owned()),
+                (0x20, "do".to_owned()),
+                (0x24, "while (v0 == 1)".to_owned()),
+                (0x30, "for (;;); if (v0 == 2); return".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn commands_are_quoted() {
+        let cmds = r2_commands(&[(0x400526, "if (a; \"b\")".to_owned())]);
+        assert_eq!(
+            cmds,
+            vec!["\"CCu if (a; \\\"b\\\")\" @ 0x400526".to_owned()]
+        );
+    }
+
+    #[test]
+    fn tail_call_is_returned() {
+        let json = r#"[
+            {"addr": 16, "size": 4, "jump": 4096, "fail": 20},
+            {"addr": 20, "size": 2}
+        ]"#;
+        let sf = from_r2::structure(json, &StructuringOptions::default()).unwrap();
+        let c = c_writer::write_function("f", &sf.ast, &mut R2Renderer);
+        assert!(c.contains("return fcn_1000();"), "{}", c);
+    }
+
+    #[test]
+    fn lifted_condition() {
+        let json = r#"[
+            {"addr": 16, "size": 4, "jump": 32, "fail": 20, "esil": [
+                "0,eax,eax,&,==,$z,zf,:=,$p,pf,:=,31,$s,sf,:=,0,cf,:=,0,of,:=",
+                "sf,?{,32,rip,=,}"
+            ]},
+            {"addr": 20, "size": 2, "jump": 32},
+            {"addr": 32, "size": 2}
+        ]"#;
+        let import_opts = from_r2::ImportOptions {
+            esil_conditions: true,
+            ..from_r2::ImportOptions::default()
+        };
+        let sf =
+            from_r2::structure_with(json, &import_opts, &StructuringOptions::default()).unwrap();
+        let c = c_writer::write_function("f", &sf.ast, &mut R2Renderer);
+        // the block at 20 runs when the jump isn't taken
+        assert!(c.contains("if (!((signed)eax < (signed)0))"), "{}", c);
+    }
+
+    #[test]
+    fn sample_comments() {
+        let json = fs::read_to_string("test_files/loopy_main_afbj.json").unwrap();
+        let sf = from_r2::structure(&json, &StructuringOptions::default()).unwrap();
+        let comments = comments(&sf.ast, &R2Provenance, &mut R2Renderer);
+        let text_at = |addr| {
+            comments
+                .iter()
+                .find(|&&(a, _)| a == addr)
+                .map(|(_, t)| t.clone())
+                .unwrap_or_default()
+        };
+        // the loop condition comes from the block at 0x40055c and the cases
+        // from the switch at 0x40053c
+        assert!(comments.iter().any(|(_, t)| t.contains("cond_40055c")));
+        assert!(text_at(0x40053c).contains("switch_40053c"));
+        assert!(text_at(0x400566).contains("return"));
+        let mut addrs: Vec<_> = comments.iter().map(|&(a, _)| a).collect();
+        addrs.sort();
+        addrs.dedup();
+        assert_eq!(addrs.len(), comments.len());
+    }
+}
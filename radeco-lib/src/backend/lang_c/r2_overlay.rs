@@ -0,0 +1,323 @@
+//! Describes which blocks of a function each control flow construct of its
+//! structured [`AstNode`] is made of, to overlay on radare2's graph view.
+//!
+//! [`groups`] lists one [`Group`] per loop, `if` and `switch`, with the
+//! addresses of all the blocks inside it, nested constructs included.
+//! [`to_json`] writes them out and [`r2_commands`] turns them into a
+//! radare2 graph of the nesting, with one node per construct listing its
+//! blocks, to be shown with `agg`.
+//!
+//! A block duplicated by structuring is listed in each construct that one of
+//! its copies ended up in.
+
+use super::c_writer::StmtRenderer;
+use crate::backend::ctrl_flow_struct::ast::{AstNode, LoopType};
+use crate::backend::ctrl_flow_struct::provenance::Provenance;
+
+use serde_json::Value;
+
+use std::fmt::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GroupKind {
+    Loop,
+    If,
+    Switch,
+}
+
+/// A control flow construct and the blocks it contains.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Group {
+    pub kind: GroupKind,
+    /// the header of the construct in C, e.g. `while (cond_40055c)`
+    pub label: String,
+    /// the index of the innermost group containing this one
+    pub parent: Option<usize>,
+    /// the addresses of the blocks in the construct, sorted
+    pub blocks: Vec<u64>,
+}
+
+/// Returns the groups of blocks making up the constructs in `ast`, outer
+/// constructs before the ones they contain.
+pub fn groups<B, C, V, P, R>(ast: &AstNode<B, C, V>, prov: &P, renderer: &mut R) -> Vec<Group>
+where
+    P: Provenance<B, C>,
+    R: StmtRenderer<B, C, V>,
+{
+    let mut collector = Collector {
+        prov,
+        renderer,
+        groups: Vec::new(),
+        open: Vec::new(),
+    };
+    collector.node(ast);
+    for g in &mut collector.groups {
+        g.blocks.sort();
+        g.blocks.dedup();
+    }
+    collector.groups
+}
+
+/// Writes `groups` as JSON: an object whose `"groups"` are objects with the
+/// fields of a [`Group`], `kind` being `"loop"`, `"if"` or `"switch"`.
+pub fn to_json(groups: &[Group]) -> String {
+    let mut ret = String::from("{\"groups\":[");
+    for (i, g) in groups.iter().enumerate() {
+        if i > 0 {
+            ret.push(',');
+        }
+        let kind = match g.kind {
+            GroupKind::Loop => "loop",
+            GroupKind::If => "if",
+            GroupKind::Switch => "switch",
+        };
+        let _ = write!(
+            ret,
+            "{{\"kind\":\"{}\",\"label\":{},\"parent\":{},\"blocks\":[{}]}}",
+            kind,
+            Value::from(g.label.as_str()),
+            g.parent.map_or("null".to_owned(), |p| p.to_string()),
+            g.blocks
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        );
+    }
+    ret.push_str("]}\n");
+    ret
+}
+
+/// Formats `groups` as radare2 commands that build a graph of them: a node
+/// `group_<index>` per group, titled with its label and listing its blocks,
+/// and an edge from each group to the ones nested in it.
+pub fn r2_commands(groups: &[Group]) -> Vec<String> {
+    let mut ret = vec!["ag-".to_owned()];
+    for (i, g) in groups.iter().enumerate() {
+        let blocks: Vec<_> = g.blocks.iter().map(|a| format!("{:#x}", a)).collect();
+        let body = format!("{}\n{}", g.label, blocks.join(" "));
+        // in base64, since radare2 splits the arguments at spaces
+        ret.push(format!(
+            "agn group_{} base64:{}",
+            i,
+            base64(body.as_bytes())
+        ));
+    }
+    for (i, g) in groups.iter().enumerate() {
+        if let Some(p) = g.parent {
+            ret.push(format!("age group_{} group_{}", p, i));
+        }
+    }
+    ret
+}
+
+struct Collector<'r, P: 'r, R: 'r> {
+    prov: &'r P,
+    renderer: &'r mut R,
+    groups: Vec<Group>,
+    /// the indices of the groups we are inside of, innermost last
+    open: Vec<usize>,
+}
+
+impl<'r, P, R> Collector<'r, P, R> {
+    fn block<B, C>(&mut self, block: &B)
+    where
+        P: Provenance<B, C>,
+    {
+        if let Some(addr) = self.prov.block_addr(block) {
+            for &i in &self.open {
+                self.groups[i].blocks.push(addr);
+            }
+        }
+    }
+
+    fn group<F>(&mut self, kind: GroupKind, label: String, f: F)
+    where
+        F: FnOnce(&mut Self),
+    {
+        let index = self.groups.len();
+        self.groups.push(Group {
+            kind,
+            label,
+            parent: self.open.last().cloned(),
+            blocks: Vec::new(),
+        });
+        self.open.push(index);
+        f(self);
+        self.open.pop();
+    }
+
+    fn node<B, C, V>(&mut self, ast: &AstNode<B, C, V>)
+    where
+        P: Provenance<B, C>,
+        R: StmtRenderer<B, C, V>,
+    {
+        use self::AstNode::*;
+        match ast {
+            BasicBlock(b) | TailCall(b) | IndirectJump(b) => self.block(b),
+            Seq(seq) => {
+                for a in seq {
+                    self.node(a);
+                }
+            }
+            Cond(c, t, oe) => {
+                let label = format!("if ({})", self.renderer.cond(c));
+                self.group(GroupKind::If, label, |this| {
+                    this.node(t);
+                    if let Some(e) = oe {
+                        this.node(e);
+                    }
+                });
+            }
+            Loop(lt, b) => {
+                let label = match lt {
+                    LoopType::PreChecked(c) => format!("while ({})", self.renderer.cond(c)),
+                    LoopType::PostChecked(c) => {
+                        format!("do while ({})", self.renderer.cond(c))
+                    }
+                    LoopType::Endless => "for (;;)".to_owned(),
+                };
+                self.group(GroupKind::Loop, label, |this| this.node(b));
+            }
+            For(i, c, u, b) => {
+                let label = format!(
+                    "for ({}; {}; {})",
+                    self.renderer.for_clause(i),
+                    self.renderer.cond(c),
+                    self.renderer.for_clause(u)
+                );
+                self.group(GroupKind::Loop, label, |this| {
+                    this.block(i);
+                    this.node(b);
+                    this.block(u);
+                });
+            }
+            Switch(v, cases, default) => {
+                let label = format!("switch ({})", self.renderer.var(v));
+                self.group(GroupKind::Switch, label, |this| {
+                    for (_, a) in cases {
+                        this.node(a);
+                    }
+                    this.node(default);
+                });
+            }
+            Try(b, _) => self.node(b),
+            Unstructured(members) => {
+                for (_, a) in members {
+                    self.node(a);
+                }
+            }
+            Break | Continue | Return | Goto(_) | Label(_) => (),
+        }
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut ret = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::r2_comments::R2Renderer;
+    use super::*;
+    use crate::backend::ctrl_flow_struct::ast::AstNode::*;
+    use crate::backend::ctrl_flow_struct::from_r2::{
+        Block, CondExpr, R2Code, R2Cond, R2Provenance, Var,
+    };
+
+    use serde_json::{self, Value};
+
+    fn code(addr: u64) -> AstNode<Block, CondExpr, Var> {
+        BasicBlock(Block::Code(R2Code::Block { addr, size: 4 }))
+    }
+
+    /// Two nested loops; the block at 0x28 was duplicated into the inner
+    /// loop and after the outer one.
+    fn nested_loops() -> AstNode<Block, CondExpr, Var> {
+        Seq(vec![
+            code(0x10),
+            Loop(
+                LoopType::PreChecked(CondExpr::Pred(R2Cond::Taken(0x14))),
+                Box::new(Seq(vec![
+                    code(0x14),
+                    Loop(
+                        LoopType::PostChecked(CondExpr::Pred(R2Cond::Taken(0x20))),
+                        Box::new(Seq(vec![
+                            code(0x1c),
+                            Cond(
+                                CondExpr::Pred(R2Cond::Taken(0x1c)),
+                                Box::new(code(0x28)),
+                                None,
+                            ),
+                            code(0x20),
+                        ])),
+                    ),
+                ])),
+            ),
+            code(0x28),
+        ])
+    }
+
+    #[test]
+    fn nested_loop_groups() {
+        let groups = groups(&nested_loops(), &R2Provenance, &mut R2Renderer);
+        let json: Value = serde_json::from_str(&to_json(&groups)).unwrap();
+        let groups = json.get("groups").unwrap().as_array().unwrap();
+        assert_eq!(groups.len(), 3);
+        let expect = [
+            (
+                "loop",
+                "while (cond_14)",
+                None,
+                vec![0x14, 0x1c, 0x20, 0x28],
+            ),
+            (
+                "loop",
+                "do while (cond_20)",
+                Some(0),
+                vec![0x1c, 0x20, 0x28],
+            ),
+            ("if", "if (cond_1c)", Some(1), vec![0x28]),
+        ];
+        for (g, (kind, label, parent, blocks)) in groups.iter().zip(expect.iter()) {
+            let field = |name| g.get(name).unwrap();
+            assert_eq!(field("kind").as_str(), Some(*kind));
+            assert_eq!(field("label").as_str(), Some(*label));
+            assert_eq!(field("parent").as_u64(), *parent);
+            let addrs: Vec<_> = field("blocks")
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|a| a.as_u64().unwrap())
+                .collect();
+            // 0x28 is in both the inner `if` and after the outer loop
+            assert_eq!(addrs, *blocks);
+        }
+    }
+
+    #[test]
+    fn graph_commands() {
+        let groups = groups(&nested_loops(), &R2Provenance, &mut R2Renderer);
+        let cmds = r2_commands(&groups);
+        assert_eq!(cmds.len(), 1 + 3 + 2);
+        assert_eq!(cmds[0], "ag-");
+        // "if (cond_1c)\n0x28"
+        assert_eq!(cmds[3], "agn group_2 base64:aWYgKGNvbmRfMWMpCjB4Mjg=");
+        assert_eq!(&cmds[4..], ["age group_0 group_1", "age group_1 group_2"]);
+    }
+}
@@ -0,0 +1,642 @@
+//! A C interface to control flow structuring, for tools that aren't written
+//! in Rust. Only built with the `ffi` feature; `include/radeco_structure.h`
+//! declares it for C and C++. To get a library to link against, run
+//! `cargo rustc --release --features ffi --crate-type staticlib` (or
+//! `cdylib`).
+//!
+//! A caller describes a function as an array of basic blocks and an array of
+//! edges between them, gets a `RadecoCfg` back from [`radeco_cfg_new`], and
+//! structures it with [`radeco_structure`], which returns the structured AST
+//! as JSON (see [`ast_json`] for the format). Every function returns one of
+//! the `RADECO_*` codes; on failure, a message is returned through
+//! `out_error` instead. Everything returned must be freed with the matching
+//! `radeco_*_free` function. Panics don't cross the interface: they are
+//! caught and reported as `RADECO_ERR_PANIC`.
+
+use crate::backend::ctrl_flow_struct::ast::{AstNode, LoopType};
+use crate::backend::ctrl_flow_struct::esil::{Operand, Predicate};
+use crate::backend::ctrl_flow_struct::from_r2::{
+    self, Block, CondExpr, ImportOptions, R2BasicBlock, R2Code, R2Cond, R2Provenance,
+    StructuredFunction, Var,
+};
+use crate::backend::ctrl_flow_struct::stable_ids::StableIds;
+use crate::backend::ctrl_flow_struct::StructuringOptions;
+
+use serde_json::Value;
+
+use std::collections::HashSet;
+use std::ffi::CString;
+use std::fmt::Write;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+pub const RADECO_OK: c_int = 0;
+/// A pointer was null or an index out of range.
+pub const RADECO_ERR_ARGUMENT: c_int = 1;
+/// The blocks and edges don't describe a function that can be structured.
+pub const RADECO_ERR_CFG: c_int = 2;
+/// Structuring panicked.
+pub const RADECO_ERR_PANIC: c_int = 3;
+
+/// The only successor of a block.
+pub const RADECO_EDGE_ALWAYS: u32 = 0;
+/// Where the conditional jump ending a block goes if it is taken.
+pub const RADECO_EDGE_TAKEN: u32 = 1;
+/// Where it goes if it isn't.
+pub const RADECO_EDGE_NOT_TAKEN: u32 = 2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RadecoBlock {
+    pub addr: u64,
+    pub size: u64,
+}
+
+/// An edge between the blocks at the indices `from` and `to`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RadecoEdge {
+    pub from: usize,
+    pub to: usize,
+    /// one of the `RADECO_EDGE_*` constants
+    pub kind: u32,
+}
+
+/// See [`StructuringOptions`].
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RadecoOptions {
+    pub collapse_sese_regions: bool,
+}
+
+/// A function ready to be structured. Opaque to C.
+#[derive(Debug)]
+pub struct RadecoCfg {
+    blocks: Vec<R2BasicBlock>,
+}
+
+pub type FfiResult<T> = Result<T, (c_int, String)>;
+
+/// Builds a function out of `n_blocks` blocks and `n_edges` edges. The first
+/// block is the entry. A block has either no successors, one
+/// `RADECO_EDGE_ALWAYS` edge, or one `RADECO_EDGE_TAKEN` and one
+/// `RADECO_EDGE_NOT_TAKEN` edge.
+///
+/// # Safety
+///
+/// `blocks` and `edges` must point to that many elements, and `out_cfg` to
+/// writable memory. `out_error` may be null.
+#[no_mangle]
+pub unsafe extern "C" fn radeco_cfg_new(
+    blocks: *const RadecoBlock,
+    n_blocks: usize,
+    edges: *const RadecoEdge,
+    n_edges: usize,
+    out_cfg: *mut *mut RadecoCfg,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    guard(out_error, || {
+        if out_cfg.is_null() {
+            return Err((RADECO_ERR_ARGUMENT, "out_cfg is null".to_owned()));
+        }
+        let blocks = array(blocks, n_blocks, "blocks")?;
+        let edges = array(edges, n_edges, "edges")?;
+        let cfg = RadecoCfg {
+            blocks: r2_blocks(blocks, edges)?,
+        };
+        *out_cfg = Box::into_raw(Box::new(cfg));
+        Ok(())
+    })
+}
+
+/// Frees a function returned by [`radeco_cfg_new`]. Does nothing if `cfg`
+/// is null.
+///
+/// # Safety
+///
+/// `cfg` must come from `radeco_cfg_new` and not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn radeco_cfg_free(cfg: *mut RadecoCfg) {
+    if !cfg.is_null() {
+        drop(Box::from_raw(cfg));
+    }
+}
+
+/// Structures `cfg` and returns the result as a JSON string through
+/// `out_json`. `opts` may be null for the default options.
+///
+/// # Safety
+///
+/// `cfg` must come from [`radeco_cfg_new`], `opts` be null or valid, and
+/// `out_json` point to writable memory. `out_error` may be null.
+#[no_mangle]
+pub unsafe extern "C" fn radeco_structure(
+    cfg: *const RadecoCfg,
+    opts: *const RadecoOptions,
+    out_json: *mut *mut c_char,
+    out_error: *mut *mut c_char,
+) -> c_int {
+    guard(out_error, || {
+        if cfg.is_null() || out_json.is_null() {
+            return Err((RADECO_ERR_ARGUMENT, "cfg or out_json is null".to_owned()));
+        }
+        let opts = match opts.as_ref() {
+            Some(opts) => StructuringOptions {
+                collapse_sese_regions: opts.collapse_sese_regions,
+                ..Default::default()
+            },
+            None => StructuringOptions::default(),
+        };
+        let sf = from_r2::structure_blocks(&(*cfg).blocks, &ImportOptions::default(), &opts)
+            .map_err(|e| (RADECO_ERR_CFG, e.to_string()))?;
+        *out_json = c_string(ast_json(&sf));
+        Ok(())
+    })
+}
+
+/// Frees a string returned by any of the functions above. Does nothing if
+/// `s` is null.
+///
+/// # Safety
+///
+/// `s` must come from one of them and not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn radeco_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Runs `f`, turning its error or panic into a code and a message in
+/// `*out_error`, if `out_error` isn't null.
+unsafe fn guard<F>(out_error: *mut *mut c_char, f: F) -> c_int
+where
+    F: FnOnce() -> FfiResult<()>,
+{
+    let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let msg = match (
+            payload.downcast_ref::<&str>(),
+            payload.downcast_ref::<String>(),
+        ) {
+            (Some(msg), _) => format!("structuring panicked: {}", msg),
+            (_, Some(msg)) => format!("structuring panicked: {}", msg),
+            _ => "structuring panicked".to_owned(),
+        };
+        Err((RADECO_ERR_PANIC, msg))
+    });
+    match result {
+        Ok(()) => RADECO_OK,
+        Err((code, msg)) => {
+            if !out_error.is_null() {
+                *out_error = c_string(msg);
+            }
+            code
+        }
+    }
+}
+
+unsafe fn array<'a, T>(ptr: *const T, len: usize, name: &str) -> FfiResult<&'a [T]> {
+    if len == 0 {
+        Ok(&[])
+    } else if ptr.is_null() {
+        Err((RADECO_ERR_ARGUMENT, format!("{} is null", name)))
+    } else {
+        Ok(slice::from_raw_parts(ptr, len))
+    }
+}
+
+fn c_string(s: String) -> *mut c_char {
+    // JSON and our messages have no NULs, but don't let one abort the caller
+    CString::new(s)
+        .unwrap_or_else(|_| CString::new("<NUL in string>").unwrap())
+        .into_raw()
+}
+
+/// Describes the blocks and edges as radare2 would, so that they can go
+/// through the importer of [`from_r2`]. On failure, returns the code to
+/// report and why.
+pub fn r2_blocks(blocks: &[RadecoBlock], edges: &[RadecoEdge]) -> FfiResult<Vec<R2BasicBlock>> {
+    if blocks.is_empty() {
+        return Err((RADECO_ERR_CFG, "there are no blocks".to_owned()));
+    }
+    let mut addrs = HashSet::new();
+    if let Some(b) = blocks.iter().find(|b| !addrs.insert(b.addr)) {
+        let msg = format!("two blocks are at {:#x}", b.addr);
+        return Err((RADECO_ERR_CFG, msg));
+    }
+    let mut ret: Vec<_> = blocks
+        .iter()
+        .map(|b| R2BasicBlock {
+            addr: b.addr,
+            size: b.size,
+            jump: None,
+            fail: None,
+            cases: Vec::new(),
+            default: None,
+            unresolved_jump: false,
+            esil: Vec::new(),
+        })
+        .collect();
+    let mut kinds = vec![Vec::new(); blocks.len()];
+    for (i, e) in edges.iter().enumerate() {
+        if e.from >= blocks.len() || e.to >= blocks.len() {
+            let msg = format!("edge {} is between blocks that don't exist", i);
+            return Err((RADECO_ERR_ARGUMENT, msg));
+        }
+        let target = Some(blocks[e.to].addr);
+        match e.kind {
+            RADECO_EDGE_ALWAYS | RADECO_EDGE_TAKEN => ret[e.from].jump = target,
+            RADECO_EDGE_NOT_TAKEN => ret[e.from].fail = target,
+            kind => {
+                let msg = format!("edge {} has unknown kind {}", i, kind);
+                return Err((RADECO_ERR_ARGUMENT, msg));
+            }
+        }
+        kinds[e.from].push(e.kind);
+    }
+    for (i, kinds) in kinds.iter_mut().enumerate() {
+        kinds.sort();
+        let valid = matches!(
+            &kinds[..],
+            [] | [RADECO_EDGE_ALWAYS] | [RADECO_EDGE_TAKEN, RADECO_EDGE_NOT_TAKEN]
+        );
+        if !valid {
+            let msg = format!("block {} has successors {:?}", i, kinds);
+            return Err((RADECO_ERR_CFG, msg));
+        }
+    }
+    Ok(ret)
+}
+
+/// Serializes a structured function as JSON: an object with the `"ast"`,
+/// the `"var_inits"`, the initial value of each variable introduced by
+/// structuring (or `null` if it doesn't matter), and the `"ids"`, the
+/// [`StableId`](crate::backend::ctrl_flow_struct::stable_ids::StableId) of
+/// each node of the AST in preorder, as a string of 16 hex digits.
+///
+/// Each node of the AST is an object with a single field naming its kind:
+/// `{"seq": [node...]}`, `{"block": {"addr": n, "size": n}}`,
+/// `{"if": {"cond": cond, "then": node, "else": node or null}}`,
+/// `{"loop": {"kind": "while" or "do_while" or "endless", "cond": cond or
+/// null, "body": node}}`, `{"for": {"init": block, "cond": cond, "update":
+/// block, "body": node}}`, `{"switch": {"var": n, "cases": [{"values": [[lo,
+/// hi]...], "body": node}...], "default": node}}`, `{"tail_call": block}`,
+/// `{"indirect_jump": block}`, `{"goto": n}`, `{"label": n}`,
+/// `{"try": {"body": node, "handler": n}}`, `{"unstructured": [{"label": n,
+/// "body": node}...]}`, or one of the strings `"break"`,
+/// `"continue"` and `"return"`. A block is `{"addr": n, "size": n}`,
+/// `{"external_jump": n}`, `{"assign": {"var": n, "value": n}}` or
+/// `{"bool_assign": {"var": n, "cond": cond}}`.
+///
+/// A condition is `{"taken": addr}`, `{"case": {"addr": n, "value": n}}`,
+/// `{"equals": {"var": n, "value": n}}`, `{"bool_var": n}`, `{"not": cond}`,
+/// `{"all": [cond...]}`, `{"any": [cond...]}`, or `{"predicate": {"addr": n,
+/// "raw": s}}` or `{"predicate": {"addr": n, "op": s, "lhs": operand, "rhs":
+/// operand}}`, `op` being the name of an [`esil::CmpOp`] in lower case.
+/// An operand is `{"reg": s}`, `{"const": n}` or `{"and": [operand,
+/// operand]}`.
+///
+/// [`esil::CmpOp`]: crate::backend::ctrl_flow_struct::esil::CmpOp
+pub fn ast_json(sf: &StructuredFunction) -> String {
+    let mut ret = String::from("{\"ast\":");
+    node_json(&sf.ast, &mut ret);
+    let inits: Vec<_> = sf
+        .var_inits
+        .iter()
+        .map(|i| i.map_or("null".to_owned(), |i| i.to_string()))
+        .collect();
+    let ids: Vec<_> = StableIds::new(&R2Provenance, &sf.ast)
+        .iter()
+        .map(|(_, id)| format!("\"{}\"", id))
+        .collect();
+    let _ = write!(
+        ret,
+        ",\"var_inits\":[{}],\"ids\":[{}]}}",
+        inits.join(","),
+        ids.join(",")
+    );
+    ret
+}
+
+fn node_json(ast: &AstNode<Block, CondExpr, Var>, out: &mut String) {
+    use self::AstNode::*;
+    match ast {
+        BasicBlock(b) => block_json(b, out),
+        Seq(seq) => {
+            out.push_str("{\"seq\":[");
+            for (i, a) in seq.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                node_json(a, out);
+            }
+            out.push_str("]}");
+        }
+        Cond(c, t, oe) => {
+            out.push_str("{\"if\":{\"cond\":");
+            cond_json(c, out);
+            out.push_str(",\"then\":");
+            node_json(t, out);
+            out.push_str(",\"else\":");
+            match oe {
+                Some(e) => node_json(e, out),
+                None => out.push_str("null"),
+            }
+            out.push_str("}}");
+        }
+        Loop(lt, b) => {
+            let (kind, cond) = match lt {
+                LoopType::PreChecked(c) => ("while", Some(c)),
+                LoopType::PostChecked(c) => ("do_while", Some(c)),
+                LoopType::Endless => ("endless", None),
+            };
+            let _ = write!(out, "{{\"loop\":{{\"kind\":\"{}\",\"cond\":", kind);
+            match cond {
+                Some(c) => cond_json(c, out),
+                None => out.push_str("null"),
+            }
+            out.push_str(",\"body\":");
+            node_json(b, out);
+            out.push_str("}}");
+        }
+        For(i, c, u, b) => {
+            out.push_str("{\"for\":{\"init\":");
+            block_json(i, out);
+            out.push_str(",\"cond\":");
+            cond_json(c, out);
+            out.push_str(",\"update\":");
+            block_json(u, out);
+            out.push_str(",\"body\":");
+            node_json(b, out);
+            out.push_str("}}");
+        }
+        Switch(v, cases, default) => {
+            let _ = write!(out, "{{\"switch\":{{\"var\":{},\"cases\":[", v.0);
+            for (i, (vs, a)) in cases.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let ranges: Vec<_> = vs
+                    .ranges()
+                    .iter()
+                    .map(|(lo, hi)| format!("[{},{}]", lo, hi))
+                    .collect();
+                let _ = write!(out, "{{\"values\":[{}],\"body\":", ranges.join(","));
+                node_json(a, out);
+                out.push('}');
+            }
+            out.push_str("],\"default\":");
+            node_json(default, out);
+            out.push_str("}}");
+        }
+        Break => out.push_str("\"break\""),
+        Continue => out.push_str("\"continue\""),
+        Return => out.push_str("\"return\""),
+        TailCall(b) => {
+            out.push_str("{\"tail_call\":");
+            block_json(b, out);
+            out.push('}');
+        }
+        IndirectJump(b) => {
+            out.push_str("{\"indirect_jump\":");
+            block_json(b, out);
+            out.push('}');
+        }
+        Goto(l) => {
+            let _ = write!(out, "{{\"goto\":{}}}", l.0);
+        }
+        Label(l) => {
+            let _ = write!(out, "{{\"label\":{}}}", l.0);
+        }
+        Try(b, h) => {
+            out.push_str("{\"try\":{\"body\":");
+            node_json(b, out);
+            let _ = write!(out, ",\"handler\":{}}}}}", h.0);
+        }
+        Unstructured(members) => {
+            out.push_str("{\"unstructured\":[");
+            for (i, (l, a)) in members.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let _ = write!(out, "{{\"label\":{},\"body\":", l.0);
+                node_json(a, out);
+                out.push('}');
+            }
+            out.push_str("]}");
+        }
+    }
+}
+
+fn block_json(block: &Block, out: &mut String) {
+    let _ = match block {
+        Block::Code(R2Code::Block { addr, size }) => {
+            write!(out, "{{\"addr\":{},\"size\":{}}}", addr, size)
+        }
+        Block::Code(R2Code::ExternalJump(addr)) => write!(out, "{{\"external_jump\":{}}}", addr),
+        Block::Assign(var, val) => write!(
+            out,
+            "{{\"assign\":{{\"var\":{},\"value\":{}}}}}",
+            var.0, val
+        ),
+        Block::BoolAssign(var, cond) => {
+            let _ = write!(out, "{{\"bool_assign\":{{\"var\":{},\"cond\":", var.0);
+            cond_json(cond, out);
+            out.push_str("}}");
+            Ok(())
+        }
+    };
+}
+
+fn cond_json(cond: &CondExpr, out: &mut String) {
+    let _ = match cond {
+        CondExpr::Pred(R2Cond::Taken(addr)) => write!(out, "{{\"taken\":{}}}", addr),
+        CondExpr::Pred(R2Cond::Case(addr, val)) => {
+            write!(out, "{{\"case\":{{\"addr\":{},\"value\":{}}}}}", addr, val)
+        }
+        CondExpr::Equals(var, val) => write!(
+            out,
+            "{{\"equals\":{{\"var\":{},\"value\":{}}}}}",
+            var.0, val
+        ),
+        CondExpr::BoolVar(var) => write!(out, "{{\"bool_var\":{}}}", var.0),
+        CondExpr::Not(c) => {
+            out.push_str("{\"not\":");
+            cond_json(c, out);
+            out.push('}');
+            Ok(())
+        }
+        CondExpr::All(cs) | CondExpr::Any(cs) => {
+            let kind = match cond {
+                CondExpr::All(_) => "all",
+                _ => "any",
+            };
+            let _ = write!(out, "{{\"{}\":[", kind);
+            for (i, c) in cs.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                cond_json(c, out);
+            }
+            out.push_str("]}");
+            Ok(())
+        }
+        CondExpr::Pred(R2Cond::Predicate(addr, Predicate::Raw(raw))) => write!(
+            out,
+            "{{\"predicate\":{{\"addr\":{},\"raw\":{}}}}}",
+            addr,
+            Value::from(raw.as_str())
+        ),
+        CondExpr::Pred(R2Cond::Predicate(addr, Predicate::Compare(op, lhs, rhs))) => {
+            let op = format!("{:?}", op).to_lowercase();
+            let _ = write!(
+                out,
+                "{{\"predicate\":{{\"addr\":{},\"op\":\"{}\",\"lhs\":",
+                addr, op
+            );
+            operand_json(lhs, out);
+            out.push_str(",\"rhs\":");
+            operand_json(rhs, out);
+            out.push_str("}}");
+            Ok(())
+        }
+    };
+}
+
+fn operand_json(op: &Operand, out: &mut String) {
+    match op {
+        Operand::Reg(r) => {
+            let _ = write!(out, "{{\"reg\":{}}}", Value::from(r.as_str()));
+        }
+        Operand::Const(c) => {
+            let _ = write!(out, "{{\"const\":{}}}", c);
+        }
+        Operand::BitAnd(a, b) => {
+            out.push_str("{\"and\":[");
+            operand_json(a, out);
+            out.push(',');
+            operand_json(b, out);
+            out.push_str("]}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::{self, Value};
+    use std::ffi::CStr;
+    use std::ptr;
+
+    /// Takes a string returned through the interface.
+    unsafe fn take(s: *mut c_char) -> String {
+        assert!(!s.is_null());
+        let ret = CStr::from_ptr(s).to_str().unwrap().to_owned();
+        radeco_string_free(s);
+        ret
+    }
+
+    fn edge(from: usize, to: usize, kind: u32) -> RadecoEdge {
+        RadecoEdge { from, to, kind }
+    }
+
+    #[test]
+    fn structure_diamond() {
+        let blocks = [
+            RadecoBlock { addr: 16, size: 4 },
+            RadecoBlock { addr: 20, size: 4 },
+            RadecoBlock { addr: 24, size: 2 },
+        ];
+        let edges = [
+            edge(0, 2, RADECO_EDGE_TAKEN),
+            edge(0, 1, RADECO_EDGE_NOT_TAKEN),
+            edge(1, 2, RADECO_EDGE_ALWAYS),
+        ];
+        unsafe {
+            let mut cfg = ptr::null_mut();
+            let mut error = ptr::null_mut();
+            let code = radeco_cfg_new(
+                blocks.as_ptr(),
+                blocks.len(),
+                edges.as_ptr(),
+                edges.len(),
+                &mut cfg,
+                &mut error,
+            );
+            assert_eq!(code, RADECO_OK);
+            assert!(error.is_null());
+
+            let opts = RadecoOptions {
+                collapse_sese_regions: false,
+            };
+            let mut json = ptr::null_mut();
+            let code = radeco_structure(cfg, &opts, &mut json, &mut error);
+            assert_eq!(code, RADECO_OK);
+            radeco_cfg_free(cfg);
+
+            let json: Value = serde_json::from_str(&take(json)).unwrap();
+            let seq = json
+                .get("ast")
+                .unwrap()
+                .get("seq")
+                .unwrap()
+                .as_array()
+                .unwrap();
+            assert_eq!(seq.len(), 3);
+            assert_eq!(seq[0].get("addr").unwrap().as_u64(), Some(16));
+            let cond = seq[1].get("if").unwrap().get("cond").unwrap();
+            // the block at 20 runs when the jump isn't taken
+            let taken = cond.get("not").unwrap().get("taken").unwrap();
+            assert_eq!(taken.as_u64(), Some(16));
+            assert!(json.get("var_inits").unwrap().as_array().is_some());
+            // the `Seq`, the block at 16, the `if`, its block, and the
+            // block at 24
+            let ids = json.get("ids").unwrap().as_array().unwrap();
+            assert_eq!(ids.len(), 5);
+            assert_eq!(ids[1].as_str().unwrap().len(), 16);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let blocks = [RadecoBlock { addr: 16, size: 4 }];
+        unsafe {
+            let mut cfg = ptr::null_mut();
+            let mut error = ptr::null_mut();
+            // a conditional jump needs both of its edges
+            let edges = [edge(0, 0, RADECO_EDGE_TAKEN)];
+            let code = radeco_cfg_new(blocks.as_ptr(), 1, edges.as_ptr(), 1, &mut cfg, &mut error);
+            assert_eq!(code, RADECO_ERR_CFG);
+            assert!(cfg.is_null());
+            assert!(take(error).contains("block 0"));
+
+            let edges = [edge(0, 1, RADECO_EDGE_ALWAYS)];
+            let code = radeco_cfg_new(blocks.as_ptr(), 1, edges.as_ptr(), 1, &mut cfg, &mut error);
+            assert_eq!(code, RADECO_ERR_ARGUMENT);
+            take(error);
+
+            // `out_error` is optional
+            let code = radeco_cfg_new(ptr::null(), 1, ptr::null(), 0, &mut cfg, ptr::null_mut());
+            assert_eq!(code, RADECO_ERR_ARGUMENT);
+            let code = radeco_structure(ptr::null(), ptr::null(), ptr::null_mut(), &mut error);
+            assert_eq!(code, RADECO_ERR_ARGUMENT);
+            take(error);
+
+            // freeing null is fine
+            radeco_cfg_free(ptr::null_mut());
+            radeco_string_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn panics_are_caught() {
+        unsafe {
+            let mut error = ptr::null_mut();
+            let code = guard(&mut error, || panic!("oops"));
+            assert_eq!(code, RADECO_ERR_PANIC);
+            assert_eq!(take(error), "structuring panicked: oops");
+        }
+    }
+}
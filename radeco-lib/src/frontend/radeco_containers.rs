@@ -29,6 +29,8 @@
 //!
 //! For more examples of loading, check the `examples/` directory of this project.
 
+use crate::backend::ctrl_flow_struct::from_ssa::StructuredFunction;
+use crate::backend::ctrl_flow_struct::StructureError;
 use crate::frontend::imports::ImportInfo;
 use crate::frontend::llanalyzer;
 use crate::frontend::radeco_source::Source;
@@ -402,6 +404,9 @@ pub struct RadecoFunction {
 
     /// Kind of the function.
     pub kind: FunctionKind,
+
+    /// Structured control flow, if the structuring stage has been run
+    structured: Option<Result<StructuredFunction, StructureError>>,
 }
 
 #[derive(Default)]
@@ -1213,6 +1218,16 @@ impl RadecoFunction {
         &mut self.ssa
     }
 
+    /// Returns the result of structuring this function's control flow, if
+    /// it has been structured.
+    pub fn structured(&self) -> Option<&Result<StructuredFunction, StructureError>> {
+        self.structured.as_ref()
+    }
+
+    pub fn set_structured(&mut self, res: Result<StructuredFunction, StructureError>) {
+        self.structured = Some(res);
+    }
+
     /// Returns the id in the call graph for this function.
     pub fn cgid(&self) -> NodeIndex {
         self.cgid
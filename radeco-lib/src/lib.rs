@@ -88,3 +88,11 @@ pub mod analysis;
 
 pub mod backend;
 pub mod frontend;
+
+pub use crate::backend::ctrl_flow_struct::from_graph::{
+    structure_cfg, Block, BlockText, Cond, EdgeCondition, StructuredCfg, Var,
+};
+pub use crate::backend::ctrl_flow_struct::{StructureError, StructuringOptions, StructuringReport};
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
@@ -1124,7 +1124,8 @@ where
             }
         }
 
-        // Associate basic block with correct block sizes.
+        // Associate basic block with correct block sizes. A block ends after
+        // the last instruction before the next block starts.
         for opn in ops.windows(2) {
             let op1 = &opn[0];
             let op2 = &opn[1];
@@ -1133,14 +1134,24 @@ where
 
             match (self.block_of(off1), self.block_of(off2)) {
                 (Some(b1), Some(b2)) if b1 == b2 => { /* Nothing to do */ }
-                (Some(b1), Some(_)) => {
-                    if let Some(start) = self.ssa.starting_address(b1) {
-                        let size = off1.address - start.address;
-                        self.ssa.set_block_size(b1, size);
-                    }
-                }
+                (Some(b1), Some(_)) => self.set_size_up_to(b1, op1),
                 (_, _) => {}
             }
         }
+        if let Some(last) = ops.last() {
+            let off = MAddress::new(last.offset.unwrap_or(0), 0);
+            if let Some(b) = self.block_of(off) {
+                self.set_size_up_to(b, last);
+            }
+        }
+    }
+
+    /// Sets the size of `block` so that it ends after `last_op`.
+    fn set_size_up_to(&mut self, block: T::ActionRef, last_op: &LOpInfo) {
+        if let Some(start) = self.ssa.starting_address(block) {
+            let end = last_op.offset.unwrap_or(0) + last_op.size.unwrap_or(0);
+            self.ssa
+                .set_block_size(block, end.saturating_sub(start.address));
+        }
     }
 }
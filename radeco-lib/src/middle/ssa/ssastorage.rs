@@ -26,7 +26,7 @@ use super::ssa_traits::NodeType as TNodeType;
 use super::ssa_traits::{SSAExtra, SSAMod, SSAWalk, ValueInfo, SSA};
 
 #[cfg(feature = "trace_log")]
-use utils::logger;
+use crate::utils::logger;
 
 /// Structure that represents data that maybe associated with an node in the
 /// SSA
@@ -73,6 +73,18 @@ macro_rules! radeco_trace {
     });
 }
 
+/// Like `radeco_trace`, but at the `trace` level, for details too fine for
+/// `debug`.
+#[macro_export]
+macro_rules! radeco_detail {
+    ($fmt:expr, $($arg:tt)*) => ({
+        if cfg!(feature = "trace_log") {
+            #[cfg(feature="trace_log")]
+            trace!("{}", format_args!($fmt, $($arg)*));
+        }
+    });
+}
+
 #[macro_export]
 macro_rules! radeco_warn {
     ($t: expr) => ({
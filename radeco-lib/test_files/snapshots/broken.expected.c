@@ -0,0 +1,8 @@
+void broken(void) {
+    block_20();
+    if (!cond_20) {
+        block_28();
+    }
+    block_30();
+    block_40();
+}
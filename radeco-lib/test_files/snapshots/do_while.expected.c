@@ -0,0 +1,7 @@
+void do_while(void) {
+    block_401000();
+    do {
+        block_401004();
+    } while (cond_401004);
+    block_40100f();
+}
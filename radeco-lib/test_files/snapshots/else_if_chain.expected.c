@@ -0,0 +1,19 @@
+void else_if_chain(void) {
+    block_401000();
+    if (!cond_401000) {
+        block_401009();
+        if (!cond_401009) {
+            block_40100e();
+            if (!cond_40100e) {
+                block_401013();
+            } else {
+                block_40103a();
+            }
+        } else {
+            block_40102e();
+        }
+    } else {
+        block_40101f();
+    }
+    block_401029();
+}
@@ -0,0 +1,17 @@
+void irreducible(void) {
+    block_10();
+    if (cond_10) {
+        v0 = 0;
+    } else {
+        v0 = 1;
+    }
+    do {
+        if (v0 == 0) {
+            block_20();
+        } else {
+            v0 = 0;
+        }
+        block_30();
+    } while (cond_30);
+    block_40();
+}
@@ -0,0 +1,11 @@
+void loop_break(void) {
+    block_401000();
+    for (;;) {
+        block_40100b();
+        if (!cond_40100b) {
+            break;
+        }
+        block_401006();
+    }
+    block_401019();
+}
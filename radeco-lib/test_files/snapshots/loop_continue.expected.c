@@ -0,0 +1,16 @@
+void loop_continue(void) {
+    block_401000();
+    if (!cond_401000) {
+        block_401004();
+        do {
+            block_40101a();
+            if (!cond_40101a) {
+                block_401025();
+            }
+            block_401013();
+        } while (!cond_401013);
+        block_40102e();
+    } else {
+        block_401035();
+    }
+}
@@ -0,0 +1,21 @@
+void loopy_main(void) {
+    block_400526();
+    for (;;) {
+        block_40055c();
+        if (!cond_40055c) {
+            block_400566();
+            return fcn_400430();
+        } else {
+            block_40053c();
+            if (!(switch_40053c == 0)) {
+                if (!(switch_40053c == 1)) {
+                    block_400556();
+                } else {
+                    block_40054e();
+                }
+            } else {
+                block_400546();
+            }
+        }
+    }
+}
@@ -0,0 +1,20 @@
+void multi_exit(void) {
+    block_10();
+    for (;;) {
+        block_20();
+        if (cond_20) {
+            break;
+        }
+        block_28();
+        if (!cond_28) {
+            block_30();
+            if (!cond_30) {
+                block_38();
+                goto *target_38; /* unresolved */
+            }
+        } else {
+            return fcn_1000();
+        }
+    }
+    block_60();
+}
@@ -0,0 +1,14 @@
+void nested_ifs(void) {
+    block_401000();
+    if (!cond_401000) {
+        block_401008();
+        if (!cond_401008) {
+            block_40100c();
+        } else {
+            block_401025();
+        }
+    } else {
+        block_401031();
+    }
+    block_401016();
+}
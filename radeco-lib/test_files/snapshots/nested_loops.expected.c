@@ -0,0 +1,17 @@
+void nested_loops(void) {
+    block_401000();
+    if (cond_401000) {
+        do {
+            block_40103d();
+            if (!cond_40103d) {
+                block_401047();
+            } else {
+                do {
+                    block_401024();
+                } while (cond_401024);
+            }
+            block_401035();
+        } while (!cond_401035);
+    }
+    block_401019();
+}
@@ -0,0 +1,31 @@
+void nested_loops_gotos(void) {
+    block_401000();
+    if (cond_401000) {
+        goto label_4;
+    } else {
+        goto label_3;
+    }
+label_4:;
+    block_40103d();
+    if (cond_40103d) {
+        goto label_9;
+    } else {
+        goto label_6;
+    }
+label_9:;
+    do {
+        block_401024();
+    } while (cond_401024);
+    goto label_7;
+label_6:;
+    block_401047();
+label_7:;
+    block_401035();
+    if (cond_401035) {
+        goto label_3;
+    } else {
+        goto label_4;
+    }
+label_3:;
+    block_401019();
+}
@@ -0,0 +1,27 @@
+void shared_diamond(void) {
+    block_10();
+    v0 = cond_10;
+    if (!v0) {
+        block_18();
+    } else {
+        block_20();
+    }
+    v1 = cond_18;
+    if (v0 || v1) {
+        block_40();
+        v2 = cond_40;
+        if (!v2) {
+            block_50();
+        } else {
+            block_48();
+        }
+        v3 = cond_48;
+        if (!v2 || !v3) {
+            block_70();
+        }
+    }
+    if (((v0 || v1) && v2 && v3) || (!v0 && !v1)) {
+        block_60();
+    }
+    block_80();
+}
@@ -0,0 +1,40 @@
+void shared_diamond_split(void) {
+    block_10();
+    v0 = cond_10;
+    if (!v0) {
+        block_18();
+        v1 = cond_18;
+        if (v1) {
+            block_40();
+            v2 = cond_40;
+            if (v2) {
+                block_48();
+            } else {
+                block_50();
+            }
+            v3 = cond_48;
+            if (!v2 || !v3) {
+                block_70();
+            }
+        }
+    } else {
+        block_20();
+        block_40();
+        v4 = cond_40;
+        if (!v4) {
+            block_50();
+        } else {
+            block_48();
+        }
+        v5 = cond_48;
+        if (!v4 || !v5) {
+            block_70();
+        }
+    }
+    if ((v0 && v4 && v5) || (!v0 && (!v1 || (v3 && v2)))) {
+        block_60();
+    }
+    if ((v0 && (!v4 || !v5)) || !v0 || (v5 && v4)) {
+        block_80();
+    }
+}
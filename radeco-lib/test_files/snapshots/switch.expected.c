@@ -0,0 +1,26 @@
+void switch(void) {
+    block_401000();
+    if (!cond_401000) {
+        block_401009();
+        switch (v0) {
+        case 0:
+            block_401012();
+            break;
+        case 1:
+            block_40102b();
+            break;
+        case 2:
+            block_401037();
+            break;
+        case 3:
+            block_401043();
+            break;
+        default:
+            block_40104f();
+            break;
+        }
+    } else {
+        block_40105b();
+    }
+    block_40101c();
+}
@@ -0,0 +1,26 @@
+void switch_chain(void) {
+    block_401000();
+    if (!cond_401000) {
+        block_401009();
+        if (!(switch_401009 == 0)) {
+            if (!(switch_401009 == 1)) {
+                if (!(switch_401009 == 2)) {
+                    if (!(switch_401009 == 3)) {
+                        block_40104f();
+                    } else {
+                        block_401043();
+                    }
+                } else {
+                    block_401037();
+                }
+            } else {
+                block_40102b();
+            }
+        } else {
+            block_401012();
+        }
+    } else {
+        block_40105b();
+    }
+    block_40101c();
+}
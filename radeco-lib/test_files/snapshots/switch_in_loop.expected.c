@@ -0,0 +1,33 @@
+void switch_in_loop(void) {
+    block_401000();
+    if (!cond_401000) {
+        block_401004();
+        do {
+            block_401024();
+            if (!cond_401024) {
+                block_401030();
+                switch (v0) {
+                case 0:
+                    block_401013();
+                    break;
+                case 1:
+                    block_401039();
+                    break;
+                case 2:
+                    block_401045();
+                    break;
+                case 3:
+                    block_401051();
+                    break;
+                default:
+                    block_40105d();
+                    break;
+                }
+            }
+            block_40101d();
+        } while (!cond_40101d);
+        block_401069();
+    } else {
+        block_401070();
+    }
+}
@@ -0,0 +1,12 @@
+void while_loop(void) {
+    block_401000();
+    if (!cond_401000) {
+        block_401004();
+        do {
+            block_401011();
+        } while (cond_401011);
+        block_40101f();
+    } else {
+        block_401026();
+    }
+}
@@ -0,0 +1,7 @@
+int g(int);
+
+void f(int n) {
+    do {
+        n = g(n);
+    } while (n > 0);
+}
@@ -0,0 +1,13 @@
+void g(int);
+
+void f(int a) {
+    if (a < 10) {
+        g(1);
+    } else if (a < 20) {
+        g(2);
+    } else if (a < 30) {
+        g(3);
+    } else {
+        g(4);
+    }
+}
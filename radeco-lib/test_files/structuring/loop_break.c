@@ -0,0 +1,12 @@
+int g(int);
+void h(void);
+
+void f(void) {
+    for (;;) {
+        if (g(0)) {
+            break;
+        }
+        h();
+    }
+    h();
+}
@@ -0,0 +1,11 @@
+int g(int);
+void h(int);
+
+void f(int n) {
+    for (int i = 0; i < n; i++) {
+        if (g(i)) {
+            continue;
+        }
+        h(i);
+    }
+}
@@ -0,0 +1,14 @@
+void g(int);
+
+void f(int a, int b) {
+    if (a) {
+        if (b) {
+            g(1);
+        } else {
+            g(2);
+        }
+    } else {
+        g(3);
+    }
+    g(4);
+}
@@ -0,0 +1,9 @@
+void g(int, int);
+
+void f(int n, int m) {
+    for (int i = 0; i < n; i++) {
+        for (int j = 0; j < m; j++) {
+            g(i, j);
+        }
+    }
+}
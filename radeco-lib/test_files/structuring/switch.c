@@ -0,0 +1,25 @@
+void g(int);
+
+void f(unsigned x) {
+    switch (x) {
+    case 0:
+        g(10);
+        break;
+    case 1:
+        g(11);
+        break;
+    case 2:
+        g(12);
+        break;
+    case 3:
+        g(13);
+        break;
+    case 4:
+        g(14);
+        break;
+    default:
+        g(15);
+        break;
+    }
+    g(16);
+}
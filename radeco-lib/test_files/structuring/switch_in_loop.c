@@ -0,0 +1,24 @@
+int g(int);
+void h(int);
+
+void f(int n) {
+    for (int i = 0; i < n; i++) {
+        switch (g(i)) {
+        case 0:
+            h(0);
+            break;
+        case 1:
+            h(1);
+            break;
+        case 2:
+            h(2);
+            break;
+        case 3:
+            h(3);
+            break;
+        case 4:
+            h(4);
+            break;
+        }
+    }
+}
@@ -0,0 +1,9 @@
+void g(int);
+
+void f(int n) {
+    int i = 0;
+    while (i < n) {
+        g(i);
+        i++;
+    }
+}
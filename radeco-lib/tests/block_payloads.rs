@@ -0,0 +1,111 @@
+//! Counts the bytes structuring a graph with large blocks allocates, with the
+//! blocks as `String`s and as [`BlockText`]s, which are stored once however
+//! many copies of them structuring makes.
+//!
+//! The counting allocator is installed for the whole test binary, so this
+//! is one of its own; each thread counts what it allocates itself.
+
+extern crate petgraph;
+extern crate radeco_lib;
+
+use petgraph::stable_graph::StableDiGraph;
+use radeco_lib::{structure_cfg, Block, BlockText, EdgeCondition, StructuringOptions};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::HashMap;
+
+/// Counts the bytes allocated with the [`System`] allocator.
+struct Counting;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.with(|a| a.set(a.get() + layout.size()));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+/// The size of each block.
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// `a` and `b` both go on to the diamond on `d`, which leaves for `y`, also
+/// reached from `b`, and `z`, so that splitting it copies 6 blocks
+fn graph<B>(mut text: impl FnMut(&str) -> B) -> StableDiGraph<B, EdgeCondition<usize>> {
+    #[rustfmt::skip]
+    let edges = [
+        ("a", "p", Some(true)), ("a", "b", Some(false)), ("b", "h", Some(true)),
+        ("b", "y", Some(false)), ("p", "h", None), ("h", "d", None),
+        ("d", "x", Some(true)), ("d", "w", Some(false)), ("x", "e", None),
+        ("e", "y", Some(true)), ("e", "z", Some(false)), ("w", "z", None),
+        ("y", "return", None), ("z", "return", None),
+    ];
+    let mut graph = StableDiGraph::new();
+    let mut nodes = HashMap::new();
+    for &(from, to, cond) in &edges {
+        let mut node = |name: &str| {
+            *nodes
+                .entry(name.to_owned())
+                .or_insert_with(|| graph.add_node(text(name)))
+        };
+        let (from, to) = (node(from), node(to));
+        let cond = match cond {
+            Some(true) => EdgeCondition::If(from.index()),
+            Some(false) => EdgeCondition::Else,
+            None => EdgeCondition::Always,
+        };
+        graph.add_edge(from, to, cond);
+    }
+    graph
+}
+
+/// Structures `graph`, and returns how many bytes it allocated, and how many
+/// copies of the blocks it left.
+fn measure<B: Clone>(graph: StableDiGraph<B, EdgeCondition<usize>>) -> (usize, usize) {
+    let entry = graph.node_indices().next().unwrap();
+    let blocks = graph.node_count();
+    let opts = StructuringOptions::default().split_shared_regions(8);
+    let before = ALLOCATED.with(Cell::get);
+    let (structured, _) = structure_cfg(graph, entry, &opts).unwrap();
+    let bytes = ALLOCATED.with(Cell::get) - before;
+    let mut occurrences = 0;
+    structured.ast.map_blocks(&mut |b| {
+        if let Block::Code(_) = b {
+            occurrences += 1;
+        }
+    });
+    (bytes, occurrences - blocks)
+}
+
+/// `name` over and over, to the size of a block.
+fn text(name: &str) -> String {
+    name.chars().cycle().take(BLOCK_SIZE).collect()
+}
+
+#[test]
+fn shared_block_text() {
+    let (string_bytes, copies) = measure(graph(text));
+    let (text_bytes, text_copies) = measure(graph(|name| BlockText::from(text(name))));
+    assert_eq!((copies, text_copies), (6, 6));
+    // each copy of a `String` is a block's worth of text, and no copy of a
+    // `BlockText` is; what structuring allocates besides varies a little
+    // either way with the order it finds things in
+    let extra = string_bytes - text_bytes;
+    assert_eq!(
+        (extra + BLOCK_SIZE / 2) / BLOCK_SIZE,
+        copies,
+        "{} bytes, {} with `BlockText`",
+        string_bytes,
+        text_bytes
+    );
+}
@@ -0,0 +1,168 @@
+//! Structures every fixture in `test_files` and compares the metrics of the
+//! results with the ones recorded in `test_files/quality_baseline.json`, so
+//! that a change that structures some function worse, with more `goto`s or
+//! deeper nesting, fails even though the result is still correct.
+//!
+//! The other metrics are only shown when they change. After an intended
+//! change, or one that makes things better, record the new metrics with
+//!
+//! ```text
+//! UPDATE_QUALITY_BASELINE=1 cargo test --test quality
+//! ```
+
+extern crate radeco_lib;
+extern crate serde_json;
+
+use radeco_lib::backend::ctrl_flow_struct::ast::AstMetrics;
+use radeco_lib::backend::ctrl_flow_struct::from_r2::{self, ImportOptions};
+use radeco_lib::backend::ctrl_flow_struct::StructuringOptions;
+
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const FIXTURES: &str = "test_files";
+const BASELINE: &str = "test_files/quality_baseline.json";
+
+/// The metrics, by name, and whether a larger value is worse and fails.
+const METRICS: &[(&str, bool)] = &[
+    ("blocks", false),
+    ("conds", false),
+    ("loops", false),
+    ("switches", false),
+    ("gotos", true),
+    ("max_depth", true),
+];
+
+fn values(m: &AstMetrics) -> [usize; 6] {
+    [m.blocks, m.conds, m.loops, m.switches, m.gotos, m.max_depth]
+}
+
+/// The radare2 blocks in `test_files`: the ones of the structuring samples
+/// and snapshots, and those of whole functions, named by their path without
+/// `.json`.
+fn fixtures() -> Vec<String> {
+    let mut fixtures = Vec::new();
+    for dir in &["", "structuring", "snapshots"] {
+        for entry in fs::read_dir(Path::new(FIXTURES).join(dir)).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            let name = match name.strip_suffix(".json") {
+                Some(name) if !dir.is_empty() || name.ends_with("_afbj") => name,
+                _ => continue,
+            };
+            fixtures.push(Path::new(dir).join(name).to_str().unwrap().to_owned());
+        }
+    }
+    fixtures.sort();
+    fixtures
+}
+
+fn measure(fixture: &str) -> AstMetrics {
+    let json = fs::read_to_string(format!("{}/{}.json", FIXTURES, fixture)).unwrap();
+    let blocks = from_r2::parse_blocks(&json).unwrap();
+    let import_opts = ImportOptions {
+        switches: true,
+        ..Default::default()
+    };
+    from_r2::structure_blocks(&blocks, &import_opts, &StructuringOptions::default())
+        .unwrap_or_else(|err| panic!("{}: {}", fixture, err))
+        .ast
+        .metrics()
+}
+
+/// One line per fixture, so that changes to it diff well.
+fn to_json(all: &BTreeMap<String, AstMetrics>) -> String {
+    let lines: Vec<_> = all
+        .iter()
+        .map(|(fixture, m)| {
+            let fields: Vec<_> = METRICS
+                .iter()
+                .zip(&values(m))
+                .map(|(&(name, _), v)| format!("\"{}\": {}", name, v))
+                .collect();
+            format!("  \"{}\": {{{}}}", fixture, fields.join(", "))
+        })
+        .collect();
+    format!("{{\n{}\n}}\n", lines.join(",\n"))
+}
+
+fn from_json(json: &str) -> BTreeMap<String, Vec<usize>> {
+    let value: Value = serde_json::from_str(json).unwrap();
+    let fixtures = value.as_object().unwrap();
+    fixtures
+        .iter()
+        .map(|(fixture, m)| {
+            let values = METRICS
+                .iter()
+                .map(|&(name, _)| m.get(name).and_then(Value::as_u64).unwrap() as usize)
+                .collect();
+            (fixture.clone(), values)
+        })
+        .collect()
+}
+
+#[test]
+fn quality_does_not_regress() {
+    let measured: BTreeMap<_, _> = fixtures()
+        .into_iter()
+        .map(|fixture| {
+            let metrics = measure(&fixture);
+            (fixture, metrics)
+        })
+        .collect();
+    if env::var_os("UPDATE_QUALITY_BASELINE").is_some() {
+        fs::write(BASELINE, to_json(&measured)).unwrap();
+        return;
+    }
+    let mut baseline = from_json(&fs::read_to_string(BASELINE).unwrap());
+
+    let mut table = vec![format!(
+        "{:<32} {:<10} {:>8} {:>8}",
+        "fixture", "metric", "baseline", "now"
+    )];
+    let mut failed = false;
+    for (fixture, metrics) in &measured {
+        let recorded = match baseline.remove(fixture) {
+            Some(recorded) => recorded,
+            None => {
+                table.push(format!("{:<32} not in the baseline", fixture));
+                failed = true;
+                continue;
+            }
+        };
+        for ((&(name, fails), &now), &was) in METRICS.iter().zip(&values(metrics)).zip(&recorded) {
+            if now != was {
+                let worse = fails && now > was;
+                failed |= worse;
+                table.push(format!(
+                    "{:<32} {:<10} {:>8} {:>8}{}",
+                    fixture,
+                    name,
+                    was,
+                    now,
+                    if worse { "  worse" } else { "" }
+                ));
+            }
+        }
+    }
+    for fixture in baseline.keys() {
+        table.push(format!("{:<32} no longer a fixture", fixture));
+        failed = true;
+    }
+
+    if table.len() > 1 {
+        let table = table.join("\n");
+        assert!(
+            !failed,
+            "{}\nrerun with UPDATE_QUALITY_BASELINE=1 if this is intended",
+            table
+        );
+        println!(
+            "{}\nrerun with UPDATE_QUALITY_BASELINE=1 to record this",
+            table
+        );
+    }
+}
@@ -0,0 +1,339 @@
+//! Structures fixture CFGs, writes them as C and compares the result with
+//! the `<fixture>.expected.c` files checked in to `test_files/snapshots`,
+//! so that changes to structuring show up as diffs of readable code.
+//!
+//! After an intended change, rewrite the expected files with
+//!
+//! ```text
+//! UPDATE_SNAPSHOTS=1 cargo test --test snapshots
+//! ```
+//!
+//! and review the diff before committing it.
+
+extern crate radeco_lib;
+
+use radeco_lib::backend::ctrl_flow_struct::from_r2::{
+    self, ImportOptions, R2Provenance, StructuredFunction,
+};
+use radeco_lib::backend::ctrl_flow_struct::{
+    condition, provenance, roundtrip, Budget, ControlFlowGraph, Fallback, StructuringOptions,
+};
+use radeco_lib::backend::lang_c::c_writer;
+use radeco_lib::backend::lang_c::r2_comments::R2Renderer;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+const SNAPSHOTS: &str = "test_files/snapshots";
+
+/// How a fixture is structured.
+#[derive(Clone, Copy, Debug)]
+enum Mode {
+    Plain,
+    /// with the switches recovered
+    Switches,
+    /// with a budget of this many steps, leaving `goto`s behind
+    Steps(usize),
+    /// with the shared parts of at most this many nodes copied
+    SplitShared(usize),
+}
+
+/// The fixtures: names, radare2 blocks and how to structure them.
+const FIXTURES: &[(&str, &str, Mode)] = &[
+    ("nested_ifs", "structuring/nested_ifs.json", Mode::Plain),
+    (
+        "else_if_chain",
+        "structuring/else_if_chain.json",
+        Mode::Plain,
+    ),
+    ("while_loop", "structuring/while_loop.json", Mode::Plain),
+    ("do_while", "structuring/do_while.json", Mode::Plain),
+    ("loop_break", "structuring/loop_break.json", Mode::Plain),
+    (
+        "loop_continue",
+        "structuring/loop_continue.json",
+        Mode::Plain,
+    ),
+    ("nested_loops", "structuring/nested_loops.json", Mode::Plain),
+    ("switch", "structuring/switch.json", Mode::Switches),
+    (
+        "switch_in_loop",
+        "structuring/switch_in_loop.json",
+        Mode::Switches,
+    ),
+    ("switch_chain", "structuring/switch.json", Mode::Plain),
+    ("loopy_main", "loopy_main_afbj.json", Mode::Plain),
+    ("broken", "broken_afbj.json", Mode::Plain),
+    (
+        "nested_loops_gotos",
+        "structuring/nested_loops.json",
+        Mode::Steps(1),
+    ),
+    ("irreducible", "snapshots/irreducible.json", Mode::Plain),
+    ("multi_exit", "snapshots/multi_exit.json", Mode::Plain),
+    (
+        "shared_diamond",
+        "snapshots/shared_diamond.json",
+        Mode::Plain,
+    ),
+    (
+        "shared_diamond_split",
+        "snapshots/shared_diamond.json",
+        Mode::SplitShared(8),
+    ),
+];
+
+fn write_fixture(name: &str, json: &str, mode: Mode) -> String {
+    let json = fs::read_to_string(format!("test_files/{}", json)).unwrap();
+    let blocks = from_r2::parse_blocks(&json).unwrap();
+    let (import_opts, opts) = options(mode);
+    let sf = from_r2::structure_blocks(&blocks, &import_opts, &opts).unwrap();
+    normalize(&c_writer::write_function(name, &sf.ast, &mut R2Renderer))
+}
+
+/// How to import and structure a fixture in `mode`.
+fn options(mode: Mode) -> (ImportOptions, StructuringOptions) {
+    let import_opts = ImportOptions {
+        switches: matches!(mode, Mode::Switches),
+        ..Default::default()
+    };
+    let opts = StructuringOptions {
+        budget: match mode {
+            Mode::Steps(steps) => Some(Budget::Steps(steps)),
+            _ => None,
+        },
+        split_shared_regions: match mode {
+            Mode::SplitShared(max_nodes) => Some(max_nodes),
+            _ => None,
+        },
+        ..Default::default()
+    };
+    (import_opts, opts)
+}
+
+/// Numbers the variables structuring introduced in the order they first
+/// appear, which doesn't depend on the order they were made in, and drops
+/// trailing whitespace.
+fn normalize(c: &str) -> String {
+    let mut names = HashMap::new();
+    let mut out = String::new();
+    for line in c.lines() {
+        let mut rest = line.trim_end();
+        while let Some(at) = rest.find('v') {
+            let (before, var) = rest.split_at(at);
+            out.push_str(before);
+            let digits = var[1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(var.len() - 1);
+            let word_start = !before.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+            if digits == 0 || !word_start {
+                out.push('v');
+                rest = &var[1..];
+                continue;
+            }
+            let next = names.len();
+            let n = *names.entry(var[..1 + digits].to_owned()).or_insert(next);
+            out.push_str(&format!("v{}", n));
+            rest = &var[1 + digits..];
+        }
+        out.push_str(rest);
+        out.push('\n');
+    }
+    out
+}
+
+/// The first line where `expected` and `found` differ, with the lines
+/// around it.
+fn first_difference(expected: &str, found: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let found: Vec<_> = found.lines().collect();
+    let at = (0..).find(|&i| expected.get(i) != found.get(i)).unwrap();
+    let mut out = String::new();
+    for i in at.saturating_sub(2)..at + 3 {
+        match (expected.get(i), found.get(i)) {
+            (Some(e), Some(f)) if e == f => out.push_str(&format!("  {}\n", e)),
+            (e, f) => {
+                if let Some(e) = e {
+                    out.push_str(&format!("- {}\n", e));
+                }
+                if let Some(f) = f {
+                    out.push_str(&format!("+ {}\n", f));
+                }
+            }
+        }
+    }
+    format!("line {}:\n{}", at + 1, out)
+}
+
+#[test]
+fn snapshots() {
+    let update = env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut stale = Vec::new();
+    for &(name, json, mode) in FIXTURES {
+        let found = write_fixture(name, json, mode);
+        let path = format!("{}/{}.expected.c", SNAPSHOTS, name);
+        if update {
+            fs::write(&path, &found).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&path).unwrap_or_default();
+        if expected != found {
+            stale.push(format!("{}, {}", path, first_difference(&expected, &found)));
+        }
+    }
+    assert!(
+        stale.is_empty(),
+        "{}\nrerun with UPDATE_SNAPSHOTS=1 to accept the changes",
+        stale.join("\n")
+    );
+}
+
+#[test]
+fn output_is_deterministic() {
+    for &(name, json, mode) in FIXTURES {
+        let first = write_fixture(name, json, mode);
+        for _ in 0..3 {
+            assert_eq!(write_fixture(name, json, mode), first, "fixture {}", name);
+        }
+    }
+}
+
+/// The dry run of `analyze` finds the loops that structuring collapses, and
+/// the entries and exits it dispatches on a variable for.
+#[test]
+fn analysis_matches_structuring() {
+    for &(name, json, mode) in FIXTURES {
+        if let Mode::Steps(_) = mode {
+            continue;
+        }
+        let json = fs::read_to_string(format!("test_files/{}", json)).unwrap();
+        let blocks = from_r2::parse_blocks(&json).unwrap();
+        let import_opts = ImportOptions {
+            switches: matches!(mode, Mode::Switches),
+            ..Default::default()
+        };
+        let (blocks, _) = from_r2::repair_blocks(&blocks, import_opts.mode).unwrap();
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import_with(cstore.cctx(), &blocks, &import_opts).unwrap();
+
+        let decomposition = cfg.analyze();
+        let (_, _, report) = cfg.structure_whole_reported(&StructuringOptions::default());
+        assert_eq!(decomposition.loops.len(), report.loops, "fixture {}", name);
+        let (mut entries, mut exits) = (Vec::new(), Vec::new());
+        for f in &report.fallbacks {
+            match *f {
+                Fallback::AbnormalEntries { entries: n, .. } => entries.push(n),
+                Fallback::AbnormalExits { exits: n, .. } => exits.push(n),
+            }
+        }
+        let irreducible: Vec<_> = decomposition
+            .irreducible()
+            .map(|l| l.entries.len())
+            .collect();
+        let left: Vec<_> = decomposition
+            .loops
+            .iter()
+            .map(|l| l.exits.len())
+            .filter(|&n| n > 0)
+            .collect();
+        assert_eq!(irreducible, entries, "fixture {}", name);
+        assert_eq!(left, exits, "fixture {}", name);
+        assert_eq!(report.gotos, 0, "fixture {}", name);
+        let dispatched = entries.iter().chain(&exits).sum::<usize>();
+        assert!(
+            decomposition.goto_targets().len() <= dispatched,
+            "fixture {}",
+            name
+        );
+    }
+}
+
+/// The fixtures with loops entered in more than one place are the ones that
+/// structuring dispatches on a variable to enter.
+#[test]
+fn reducibility() {
+    for &(name, json, mode) in FIXTURES {
+        let json = fs::read_to_string(format!("test_files/{}", json)).unwrap();
+        let blocks = from_r2::parse_blocks(&json).unwrap();
+        let import_opts = ImportOptions {
+            switches: matches!(mode, Mode::Switches),
+            ..Default::default()
+        };
+        let (blocks, _) = from_r2::repair_blocks(&blocks, import_opts.mode).unwrap();
+        let cstore = condition::Storage::new();
+        let cfg = from_r2::import_with(cstore.cctx(), &blocks, &import_opts).unwrap();
+        let irreducible = match cfg.check_reducible() {
+            Ok(()) => 0,
+            Err(regions) => regions.len(),
+        };
+        let expected = if name == "irreducible" { 1 } else { 0 };
+        assert_eq!(irreducible, expected, "fixture {}", name);
+        assert_eq!(cfg.is_reducible(), expected == 0, "fixture {}", name);
+    }
+}
+
+/// Lowering the AST of a fixture back into a graph and structuring that
+/// again, with the default options, gives a function made of the same
+/// blocks that still runs like them, with the same constructs unless it had
+/// `goto`s, and lowering that once more gives the same loops again.
+#[test]
+fn lowered_asts_restructure() {
+    for &(name, json, mode) in FIXTURES {
+        let json = fs::read_to_string(format!("test_files/{}", json)).unwrap();
+        let blocks = from_r2::parse_blocks(&json).unwrap();
+        let (import_opts, opts) = options(mode);
+        let (blocks, _) = from_r2::repair_blocks(&blocks, import_opts.mode).unwrap();
+        let cstore = condition::Storage::new();
+        let cctx = cstore.cctx();
+        let cfg = from_r2::import_with(cctx, &blocks, &import_opts).unwrap();
+        let (ast, actx, _) = cfg.structure_whole_checked(&opts).unwrap();
+        let sf = StructuredFunction::new(ast.clone(), &actx);
+
+        let again = StructuringOptions::default();
+        let lowered = ControlFlowGraph::from_ast(&ast, cctx, actx).unwrap();
+        let (ast2, actx2, report) = lowered.structure_whole_checked(&again).unwrap();
+        assert_eq!(report.gotos, 0, "fixture {}", name);
+        let sf2 = StructuredFunction::new(ast2.clone(), &actx2);
+        assert_eq!(
+            provenance::covered(&R2Provenance, &sf2.ast),
+            provenance::covered(&R2Provenance, &sf.ast),
+            "fixture {}",
+            name
+        );
+        // the checker can't run the `Switch`es on the operands of jump
+        // tables, which are never assigned
+        if roundtrip::check(&blocks, &sf).is_ok() {
+            if let Err(err) = roundtrip::check(&blocks, &sf2) {
+                panic!("fixture {}: {}\nast: {:#?}", name, err, sf2.ast);
+            }
+        }
+
+        let metrics = sf.ast.metrics();
+        if metrics.gotos == 0 {
+            assert_eq!(sf2.ast.metrics(), metrics, "fixture {}", name);
+        }
+
+        // conditions that test a variable more than once are lowered into
+        // tests of copies of it, which may then be saved in more of them,
+        // so only the loops and `Switch`es are the same each time
+        let lowered = ControlFlowGraph::from_ast(&ast2, cctx, actx2).unwrap();
+        let (ast3, _, report) = lowered.structure_whole_checked(&again).unwrap();
+        assert_eq!(report.gotos, 0, "fixture {}", name);
+        let (m2, m3) = (sf2.ast.metrics(), ast3.metrics());
+        assert_eq!(
+            (m3.loops, m3.switches),
+            (m2.loops, m2.switches),
+            "fixture {}",
+            name
+        );
+    }
+}
+
+#[test]
+fn normalization() {
+    assert_eq!(
+        normalize("v7 = 1;  \nif (v3 == v7) {\n    block_v1(); rev2 = v3;\n"),
+        "v0 = 1;\nif (v1 == v0) {\n    block_v1(); rev2 = v1;\n"
+    );
+}
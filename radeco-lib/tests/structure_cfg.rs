@@ -0,0 +1,130 @@
+//! Structures graphs built by hand through [`radeco_lib::structure_cfg`],
+//! both of `String`s and of other types of blocks and conditions.
+
+extern crate petgraph;
+extern crate radeco_lib;
+
+use petgraph::stable_graph::StableDiGraph;
+use radeco_lib::backend::ctrl_flow_struct::ast::{AstNode, LoopType};
+use radeco_lib::{
+    structure_cfg, Block, Cond, EdgeCondition, StructureError, StructuredCfg, StructuringOptions,
+};
+
+fn block<B, P>(b: B) -> AstNode<Block<B, P>, Cond<P>, radeco_lib::Var> {
+    AstNode::BasicBlock(Block::Code(b))
+}
+
+#[test]
+fn strings() {
+    // a; if (c) b; else d; return;
+    let mut graph = StableDiGraph::new();
+    let a = graph.add_node("a".to_owned());
+    let b = graph.add_node("b".to_owned());
+    let d = graph.add_node("d".to_owned());
+    let ret = graph.add_node("return".to_owned());
+    graph.add_edge(a, b, EdgeCondition::If("c".to_owned()));
+    graph.add_edge(a, d, EdgeCondition::Else);
+    graph.add_edge(b, ret, EdgeCondition::Always);
+    graph.add_edge(d, ret, EdgeCondition::Always);
+
+    let (structured, report): (StructuredCfg, _) =
+        structure_cfg(graph, a, &StructuringOptions::default()).unwrap();
+    assert_eq!(
+        structured.ast,
+        AstNode::Seq(vec![
+            block("a".to_owned()),
+            AstNode::Cond(
+                Cond::Pred("c".to_owned()),
+                Box::new(block("b".to_owned())),
+                Some(Box::new(block("d".to_owned()))),
+            ),
+            block("return".to_owned()),
+        ])
+    );
+    assert!(structured.var_inits.is_empty());
+    assert_eq!(report.fallbacks.len(), 0);
+}
+
+/// An instruction of a toy machine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Insn {
+    Dec(u8),
+    Ret,
+}
+
+/// A test of a toy machine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Test {
+    NonZero(u8),
+}
+
+#[test]
+fn other_types() {
+    // while (r0 != 0) r0--; return;
+    let mut graph = StableDiGraph::new();
+    let head = graph.add_node(Vec::new());
+    let body = graph.add_node(vec![Insn::Dec(0)]);
+    let ret = graph.add_node(vec![Insn::Ret]);
+    graph.add_edge(head, body, EdgeCondition::If(Test::NonZero(0)));
+    graph.add_edge(head, ret, EdgeCondition::Else);
+    graph.add_edge(body, head, EdgeCondition::Always);
+    // never reached, so left out
+    let dead = graph.add_node(vec![Insn::Dec(1)]);
+    graph.add_edge(dead, ret, EdgeCondition::Always);
+
+    let (structured, _) = structure_cfg(graph, head, &StructuringOptions::default()).unwrap();
+    // the test comes after `head`, which may change what it tests, so the
+    // loop can't be a `while`
+    assert_eq!(
+        structured.ast,
+        AstNode::Seq(vec![
+            AstNode::Loop(
+                LoopType::Endless,
+                Box::new(AstNode::Seq(vec![
+                    block(Vec::new()),
+                    AstNode::Cond(
+                        Cond::Not(Box::new(Cond::Pred(Test::NonZero(0)))),
+                        Box::new(AstNode::Break),
+                        None,
+                    ),
+                    block(vec![Insn::Dec(0)]),
+                ])),
+            ),
+            block(vec![Insn::Ret]),
+        ])
+    );
+}
+
+#[test]
+fn bad_graphs() {
+    let opts = StructuringOptions::default();
+    let import_error = |res: Result<(StructuredCfg, _), StructureError>| match res {
+        Err(StructureError::Import(_)) => (),
+        res => panic!("not an import error: {:?}", res.map(|(s, _)| s)),
+    };
+
+    // the entry isn't in the graph
+    let mut graph = StableDiGraph::new();
+    let a = graph.add_node("a".to_owned());
+    let b = graph.add_node("b".to_owned());
+    graph.add_edge(a, b, EdgeCondition::Always);
+    let mut removed = graph.clone();
+    removed.remove_node(a);
+    import_error(structure_cfg(removed, a, &opts));
+
+    // an `If` without an `Else`
+    let mut no_else = graph.clone();
+    no_else.add_edge(a, b, EdgeCondition::If("c".to_owned()));
+    import_error(structure_cfg(no_else, a, &opts));
+
+    // two `Always`s
+    let mut two = graph.clone();
+    two.add_edge(a, b, EdgeCondition::Always);
+    import_error(structure_cfg(two, a, &opts));
+
+    // but a bad block that can't be reached is left out
+    let mut dead = graph;
+    let c = dead.add_node("c".to_owned());
+    dead.add_edge(c, b, EdgeCondition::Else);
+    assert!(structure_cfg(dead, a, &opts).is_ok());
+}
@@ -0,0 +1,156 @@
+//! Structures the CFGs of the small C programs in
+//! `test_files/structuring` and checks that the result has the shape of the
+//! source.
+//!
+//! Each `<sample>.c` there comes with `<sample>.json`, the basic blocks of
+//! its compiled function `f` in the format of radare2's `afbj`. They are
+//! checked in, so this doesn't need a compiler; after changing a sample,
+//! regenerate them with `scripts/gen-structuring-fixtures.py`.
+
+extern crate radeco_lib;
+
+use radeco_lib::backend::ctrl_flow_struct::ast::AstNode;
+use radeco_lib::backend::ctrl_flow_struct::{condition, from_r2, StructuringOptions};
+
+use std::cmp;
+use std::ffi::OsStr;
+use std::fs;
+
+const SAMPLES: &str = "test_files/structuring";
+
+/// What the structured AST of a sample must look like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Shape {
+    loops: usize,
+    /// how deeply `if`s, loops and `switch`es are nested
+    depth: usize,
+    switches: usize,
+    gotos: usize,
+}
+
+const fn shape(loops: usize, depth: usize, switches: usize) -> Shape {
+    Shape {
+        loops,
+        depth,
+        switches,
+        gotos: 0,
+    }
+}
+
+/// gcc guards a rotated loop with an `if` and checks the bounds of a jump
+/// table before indexing it, so those add a level of nesting over the source.
+const EXPECTED: &[(&str, Shape)] = &[
+    ("nested_ifs", shape(0, 2, 0)),
+    ("else_if_chain", shape(0, 3, 0)),
+    ("while_loop", shape(1, 2, 0)),
+    ("do_while", shape(1, 1, 0)),
+    ("loop_break", shape(1, 2, 0)),
+    ("loop_continue", shape(1, 3, 0)),
+    ("nested_loops", shape(2, 4, 0)),
+    ("switch", shape(0, 2, 1)),
+    ("switch_in_loop", shape(1, 4, 1)),
+];
+
+fn shape_of<B, C, V>(ast: &AstNode<B, C, V>) -> Shape {
+    use self::AstNode::*;
+    let nested = |inner: Shape| Shape {
+        depth: inner.depth + 1,
+        ..inner
+    };
+    match ast {
+        BasicBlock(_) | Break | Continue | Return | TailCall(_) | IndirectJump(_) | Label(_) => {
+            shape(0, 0, 0)
+        }
+        Goto(_) => Shape {
+            gotos: 1,
+            ..shape(0, 0, 0)
+        },
+        Seq(seq) => seq.iter().map(shape_of).fold(shape(0, 0, 0), sibling),
+        Cond(_, t, oe) => {
+            let e = oe.as_ref().map_or(shape(0, 0, 0), |e| shape_of(e));
+            nested(sibling(shape_of(t), e))
+        }
+        Loop(_, b) | For(_, _, _, b) => Shape {
+            loops: 1,
+            ..shape(0, 0, 0)
+        }
+        .add(nested(shape_of(b))),
+        Try(b, _) => shape_of(b),
+        Unstructured(members) => members
+            .iter()
+            .map(|(_, a)| shape_of(a))
+            .fold(shape(0, 0, 0), sibling),
+        Switch(_, cases, default) => {
+            let arms = cases
+                .iter()
+                .map(|(_, a)| shape_of(a))
+                .fold(shape_of(default), sibling);
+            shape(0, 0, 1).add(nested(arms))
+        }
+    }
+}
+
+/// The shape of two nodes next to each other.
+fn sibling(a: Shape, b: Shape) -> Shape {
+    Shape {
+        depth: cmp::max(a.depth, b.depth),
+        ..a.add(Shape { depth: 0, ..b })
+    }
+}
+
+impl Shape {
+    fn add(self, other: Shape) -> Shape {
+        Shape {
+            loops: self.loops + other.loops,
+            depth: self.depth + other.depth,
+            switches: self.switches + other.switches,
+            gotos: self.gotos + other.gotos,
+        }
+    }
+}
+
+fn structure_sample(name: &str, opts: &StructuringOptions) -> Shape {
+    let json = fs::read_to_string(format!("{}/{}.json", SAMPLES, name)).unwrap();
+    let blocks = from_r2::parse_blocks(&json).unwrap();
+    let cstore = condition::Storage::new();
+    let cfg = from_r2::import_with_switches(cstore.cctx(), &blocks).unwrap();
+    let (ast, _) = cfg.structure_whole_with(opts);
+    shape_of(&ast)
+}
+
+#[test]
+fn samples_have_their_source_shape() {
+    for &(name, expected) in EXPECTED {
+        assert_eq!(
+            structure_sample(name, &StructuringOptions::default()),
+            expected,
+            "sample {}",
+            name
+        );
+    }
+}
+
+#[test]
+fn samples_without_collapsing_sese_regions() {
+    let opts = StructuringOptions {
+        collapse_sese_regions: false,
+        ..Default::default()
+    };
+    for &(name, expected) in EXPECTED {
+        assert_eq!(structure_sample(name, &opts), expected, "sample {}", name);
+    }
+}
+
+#[test]
+fn every_sample_is_checked() {
+    let mut sources: Vec<_> = fs::read_dir(SAMPLES)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension() == Some(OsStr::new("c")))
+        .map(|p| p.file_stem().unwrap().to_str().unwrap().to_owned())
+        .collect();
+    sources.sort();
+    let mut checked: Vec<_> = EXPECTED.iter().map(|&(name, _)| name).collect();
+    checked.sort();
+    assert_eq!(sources, checked);
+}
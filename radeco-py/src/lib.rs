@@ -0,0 +1,225 @@
+//! Python bindings to the control flow structuring of radeco-lib.
+//!
+//! Everything here is behind the `python` feature, so that building the
+//! workspace doesn't need a Python installation. To get a module Python can
+//! import, build with `maturin develop --cargo-extra-args="--features
+//! extension-module"`; the tests embed an interpreter instead and run with
+//! `cargo test -p radeco-py --features python`.
+//!
+//! ```python
+//! import radeco
+//! # a loop: 0x10 -> 0x14 -> (0x14 or 0x18)
+//! cfg = radeco.CFG([(0x10, 4), (0x14, 4), (0x18, 2)],
+//!                  [(0, 1, "always"), (1, 1, "taken"), (1, 2, "not_taken")],
+//!                  0)
+//! s = cfg.structure(collapse_sese_regions=False)
+//! s.ast        # {"seq": [{"addr": 16, "size": 4}, {"loop": ...}, ...]}
+//! print(s.to_c("f"))
+//! ```
+//!
+//! The AST is made of the dicts, lists and strings of the JSON format of
+//! `radeco_lib::ffi::ast_json`. Something that can't be structured raises
+//! `radeco.StructureError`; malformed arguments raise `ValueError`.
+#![cfg(feature = "python")]
+
+extern crate pyo3;
+extern crate radeco_lib;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyValueError};
+use pyo3::prelude::*;
+
+use radeco_lib::backend::ctrl_flow_struct::from_r2::{
+    self, ImportOptions, R2BasicBlock, StructuredFunction,
+};
+use radeco_lib::backend::ctrl_flow_struct::StructuringOptions;
+use radeco_lib::backend::lang_c::c_writer::{self, Dialect, WriterOptions};
+use radeco_lib::backend::lang_c::r2_comments::R2Renderer;
+use radeco_lib::ffi::{self, RadecoBlock, RadecoEdge};
+
+create_exception!(radeco, StructureError, PyException);
+
+/// A function: its blocks as `(addr, size)`, the edges between them as
+/// `(from, to, kind)` with the indices of the blocks and `kind` one of
+/// `"always"`, `"taken"` and `"not_taken"`, and the index of the entry.
+#[pyclass(name = "CFG")]
+struct Cfg {
+    blocks: Vec<R2BasicBlock>,
+}
+
+#[pymethods]
+impl Cfg {
+    #[new]
+    fn new(
+        blocks: Vec<(u64, u64)>,
+        edges: Vec<(usize, usize, String)>,
+        entry: usize,
+    ) -> PyResult<Self> {
+        if entry >= blocks.len() {
+            return Err(PyValueError::new_err("the entry isn't one of the blocks"));
+        }
+        // the entry goes first
+        let index = |i: usize| match i {
+            i if i == entry => 0,
+            i if i < entry => i + 1,
+            i => i,
+        };
+        let mut ffi_blocks = vec![RadecoBlock { addr: 0, size: 0 }; blocks.len()];
+        for (i, &(addr, size)) in blocks.iter().enumerate() {
+            ffi_blocks[index(i)] = RadecoBlock { addr, size };
+        }
+        let ffi_edges = edges
+            .iter()
+            .map(|&(from, to, ref kind)| {
+                let kind = match kind.as_str() {
+                    "always" => ffi::RADECO_EDGE_ALWAYS,
+                    "taken" => ffi::RADECO_EDGE_TAKEN,
+                    "not_taken" => ffi::RADECO_EDGE_NOT_TAKEN,
+                    _ => {
+                        let msg = format!("unknown edge kind {:?}", kind);
+                        return Err(PyValueError::new_err(msg));
+                    }
+                };
+                Ok(RadecoEdge {
+                    from: index(from),
+                    to: index(to),
+                    kind,
+                })
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        let blocks = ffi::r2_blocks(&ffi_blocks, &ffi_edges)
+            .map_err(|(_, msg)| PyValueError::new_err(msg))?;
+        Ok(Cfg { blocks })
+    }
+
+    /// Structures the function. The keyword arguments are the fields of
+    /// `StructuringOptions`.
+    #[args(collapse_sese_regions = "true")]
+    fn structure(&self, py: Python, collapse_sese_regions: bool) -> PyResult<Structured> {
+        let opts = StructuringOptions {
+            collapse_sese_regions,
+            ..Default::default()
+        };
+        let sf = from_r2::structure_blocks(&self.blocks, &ImportOptions::default(), &opts)
+            .map_err(|e| StructureError::new_err(e.to_string()))?;
+        let json = py
+            .import("json")?
+            .call_method1("loads", (ffi::ast_json(&sf),))?;
+        Ok(Structured {
+            ast: json.get_item("ast")?.into(),
+            var_inits: sf.var_inits.clone(),
+            sf,
+        })
+    }
+}
+
+/// A structured function.
+#[pyclass]
+struct Structured {
+    /// the structured AST, see the module docs
+    #[pyo3(get)]
+    ast: PyObject,
+    /// the value each variable introduced by structuring must start with, or
+    /// `None` if it doesn't matter
+    #[pyo3(get)]
+    var_inits: Vec<Option<u64>>,
+    sf: StructuredFunction,
+}
+
+#[pymethods]
+impl Structured {
+    /// Pretty-prints the function as C, or as Rust-like pseudocode if
+    /// `dialect` is `"rust"`.
+    #[args(name = "\"f\"", dialect = "\"c\"")]
+    fn to_c(&self, name: &str, dialect: &str) -> PyResult<String> {
+        let dialect = match dialect {
+            "c" => Dialect::C,
+            "rust" => Dialect::Rust,
+            _ => {
+                let msg = format!("unknown dialect {:?}", dialect);
+                return Err(PyValueError::new_err(msg));
+            }
+        };
+        let opts = WriterOptions {
+            dialect,
+            ..Default::default()
+        };
+        Ok(c_writer::write_function_with(
+            name,
+            &self.sf.ast,
+            &mut R2Renderer,
+            &opts,
+        ))
+    }
+}
+
+#[pymodule]
+fn radeco(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<Cfg>()?;
+    m.add_class::<Structured>()?;
+    m.add("StructureError", py.get_type::<StructureError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use pyo3::types::PyDict;
+
+    /// Runs `code` with the module imported as `radeco`.
+    fn run(code: &str) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::new(py, "radeco").unwrap();
+            radeco(py, module).unwrap();
+            let globals = PyDict::new(py);
+            globals.set_item("radeco", module).unwrap();
+            if let Err(e) = py.run(code, Some(globals), None) {
+                e.print(py);
+                panic!("python code failed");
+            }
+        });
+    }
+
+    #[test]
+    fn structure_loop() {
+        run(r#"
+cfg = radeco.CFG([(0x10, 4), (0x14, 4), (0x18, 2)],
+                 [(0, 1, "always"), (1, 1, "taken"), (1, 2, "not_taken")],
+                 0)
+s = cfg.structure(collapse_sese_regions=False)
+seq = s.ast["seq"]
+assert seq[0] == {"addr": 0x10, "size": 4}, seq
+loops = [a["loop"] for a in seq if isinstance(a, dict) and "loop" in a]
+assert len(loops) == 1, seq
+assert loops[0]["kind"] == "do_while", loops
+assert loops[0]["cond"] == {"taken": 0x14}, loops
+c = s.to_c("f")
+assert c.startswith("void f(void) {"), c
+assert "} while (cond_14);" in c, c
+assert s.to_c("f", dialect="rust").startswith("fn f() {")
+"#);
+    }
+
+    #[test]
+    fn errors_raise() {
+        run(r#"
+try:
+    radeco.CFG([(0x10, 4)], [(0, 0, "taken")], 0)
+    assert False
+except ValueError as e:
+    assert "block 0" in str(e), e
+try:
+    radeco.CFG([(0x10, 4)], [(0, 0, "sideways")], 0)
+    assert False
+except ValueError:
+    pass
+try:
+    radeco.CFG([], [], 0)
+    assert False
+except ValueError:
+    pass
+"#);
+    }
+}
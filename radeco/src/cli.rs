@@ -3,7 +3,15 @@ use std::process;
 
 use super::MAX_ITERATIONS;
 
-pub fn parse_args() -> (Option<String>, Option<String>, bool, bool, bool, u32) {
+pub fn parse_args() -> (
+    Option<String>,
+    Option<String>,
+    bool,
+    bool,
+    bool,
+    u32,
+    Option<String>,
+) {
     let vs = env!("VERSION_STR");
     let matches = App::new("radeco")
         .version(vs)
@@ -28,6 +36,15 @@ pub fn parse_args() -> (Option<String>, Option<String>, bool, bool, bool, u32) {
             "-a --append 'Append separator to the end of every output.'",
         ))
         .arg(Arg::from_usage("-b --batch 'Decompile the whole binary'"))
+        .arg(
+            Arg::with_name("structure")
+                .help("In batch mode, structure every function into per-function C files in DIR")
+                .short("s")
+                .long("structure")
+                .value_name("DIR")
+                .required(false)
+                .takes_value(true),
+        )
         .arg(Arg::from_usage(
             "-l --no-highlight 'Disable syntax highlight on output'",
         ))
@@ -37,6 +54,7 @@ pub fn parse_args() -> (Option<String>, Option<String>, bool, bool, bool, u32) {
     let no_highlight = matches.is_present("no-highlight");
     let bin = matches.value_of("BIN").map(|s| s.to_string());
     let command = matches.value_of("command").map(|s| s.to_string());
+    let structure_dir = matches.value_of("structure").map(|s| s.to_string());
 
     if is_batch && bin.is_none() {
         eprintln!("Pass a binary for batch mode");
@@ -46,6 +64,10 @@ pub fn parse_args() -> (Option<String>, Option<String>, bool, bool, bool, u32) {
         eprintln!("Passed a command in interactive mode");
         process::exit(0);
     }
+    if structure_dir.is_some() && !is_batch {
+        eprintln!("Passed a structuring directory in interactive mode");
+        process::exit(0);
+    }
     let max_it = match matches.value_of("max-iterations") {
         Some(s) => {
             // TODO -> Implement error management.
@@ -65,5 +87,13 @@ pub fn parse_args() -> (Option<String>, Option<String>, bool, bool, bool, u32) {
         process::exit(0);
     }
 
-    (bin, command, is_append, is_batch, no_highlight, max_it)
+    (
+        bin,
+        command,
+        is_append,
+        is_batch,
+        no_highlight,
+        max_it,
+        structure_dir,
+    )
 }
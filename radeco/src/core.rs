@@ -1,6 +1,8 @@
 use base64;
 use r2pipe::{R2Pipe, R2};
 use radeco_lib::analysis::engine::{Engine, RadecoEngine};
+use radeco_lib::backend::ctrl_flow_struct::StructuringOptions;
+use radeco_lib::backend::lang_c::batch::{self, R2Function};
 use radeco_lib::backend::lang_c::c_cfg::ctrl_flow_struct;
 use radeco_lib::backend::lang_c::c_cfg::CCFGVerifier;
 use radeco_lib::backend::lang_c::c_cfg_builder;
@@ -11,6 +13,7 @@ use radeco_lib::middle::ssa::ssastorage::SSAStorage;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::panic;
+use std::path::Path;
 use std::rc::Rc;
 use std::str;
 
@@ -156,6 +159,32 @@ fn decompile_priv(
     }
 }
 
+/// Structures every function of `proj` from the basic blocks radare2 has for
+/// it and writes the results to `out_dir`. Returns the summary of the batch.
+pub fn structure_all_functions(proj: &RadecoProject, out_dir: &str) -> Result<String, String> {
+    let mut funcs = Vec::new();
+    for rmod in proj.iter().map(|i| i.module) {
+        let src = rmod
+            .source
+            .as_ref()
+            .ok_or_else(|| "Module has no source to get basic blocks from".to_owned())?;
+        for rfn in rmod.functions.values() {
+            // a function without blocks is reported as a failure by the batch
+            let blocks_json = src
+                .raw(format!("afbj @ {:#x}", rfn.offset))
+                .unwrap_or_default();
+            funcs.push(R2Function {
+                name: rfn.name.to_string(),
+                addr: rfn.offset,
+                blocks_json,
+            });
+        }
+    }
+    batch::run(&funcs, &StructuringOptions::default(), Path::new(out_dir))
+        .map(|report| report.summary())
+        .map_err(|e| e.to_string())
+}
+
 pub fn load_proj_by_path(path: &str, max_it: u32) -> RadecoProject {
     let mut p = ProjectLoader::new().path(path).load();
     let regfile = p.regfile().clone();
@@ -158,7 +158,8 @@ const SEP: &'static str = "END";
 fn main() {
     #[cfg(feature = "trace_log")]
     env_logger::init();
-    let (arg, cmd_opt, is_append_mode, is_batch_mode, no_highlight, max_it) = cli::parse_args();
+    let (arg, cmd_opt, is_append_mode, is_batch_mode, no_highlight, max_it, structure_dir) =
+        cli::parse_args();
     let config = Config::builder()
         .auto_add_history(true)
         .history_ignore_space(true)
@@ -202,6 +203,12 @@ fn main() {
             // otherwise decompile all functions.
             if let Some(command) = cmd_opt {
                 cmd(command, no_highlight, max_it);
+            } else if let Some(dir) = structure_dir {
+                let proj_ = proj_opt.borrow();
+                match core::structure_all_functions(proj_.as_ref().unwrap(), &dir) {
+                    Ok(summary) => print!("{}", summary),
+                    Err(err) => eprintln!("{}", err),
+                }
             } else {
                 let mut proj_ = proj_opt.borrow_mut();
                 let proj = proj_.as_mut().unwrap();
@@ -0,0 +1,169 @@
+#!/usr/bin/env python3
+
+# Regenerates the CFG fixtures of the structuring samples.
+# Usage: ./gen-structuring-fixtures.py [SAMPLE.c ...]
+#
+# Each sample in radeco-lib/test_files/structuring is compiled for x86-64
+# with gcc, the function `f` is disassembled with objdump, and its basic
+# blocks are written next to the source as `SAMPLE.json`, in the format of
+# radare2's `afbj` (only the fields radeco reads). Jump tables are read out
+# of `.rodata`, so switches come out with their cases like radare2 prints
+# them.
+#
+# Only maintainers need this: the fixtures are checked in, so the tests
+# don't need a compiler.
+
+import json
+import os
+import re
+import subprocess
+import sys
+import tempfile
+
+SAMPLES = os.path.join(os.path.dirname(os.path.abspath(__file__)),
+                       '..', 'radeco-lib', 'test_files', 'structuring')
+
+CFLAGS = ['-O1', '-fno-pie', '-no-pie', '-nostdlib', '-Wl,-e,f',
+          '-Wl,--unresolved-symbols=ignore-all', '-fcf-protection=none',
+          '-fno-asynchronous-unwind-tables']
+
+UT64_MAX = 2**64 - 1
+
+INSN = re.compile(r'^\s*([0-9a-f]+):\s+(\S+)\s*(.*)$')
+SWITCH = re.compile(r'^\*0x([0-9a-f]+)\(,%(\w+),8\)$')
+
+
+def run(*args):
+    return subprocess.run(args, check=True, stdout=subprocess.PIPE,
+                          universal_newlines=True).stdout
+
+
+def function_bounds(binary):
+    for line in run('nm', '-S', binary).splitlines():
+        fields = line.split()
+        if len(fields) == 4 and fields[3] == 'f':
+            start = int(fields[0], 16)
+            return start, start + int(fields[1], 16)
+    raise Exception('no function f in ' + binary)
+
+
+def disassemble(binary, start, end):
+    out = run('objdump', '-d', '--no-show-raw-insn',
+              '--start-address=%#x' % start, '--stop-address=%#x' % end,
+              binary)
+    insns = []
+    for line in out.splitlines():
+        m = INSN.match(line)
+        if m:
+            insns.append((int(m.group(1), 16), m.group(2), m.group(3)))
+    return insns
+
+
+def rodata(binary):
+    mem = {}
+    out = run('objdump', '-s', '-j', '.rodata', binary)
+    for line in out.splitlines()[4:]:
+        fields = line.split()
+        addr = int(fields[0], 16)
+        for word in fields[1:5]:
+            if not re.match(r'^[0-9a-f]+$', word):
+                break
+            for i in range(0, len(word), 2):
+                mem[addr] = int(word[i:i + 2], 16)
+                addr += 1
+    return mem
+
+
+def read_u64(mem, addr):
+    return sum(mem[addr + i] << (8 * i) for i in range(8))
+
+
+def target(operands):
+    return int(operands.split()[0], 16)
+
+
+def switch_cases(insns, i, binary):
+    """The cases of the jump table jumped through by `insns[i]`, whose
+    bound is checked by a `cmp` and `ja` before it."""
+    table = int(SWITCH.match(insns[i][2]).group(1), 16)
+    for j in range(i - 1, -1, -1):
+        if insns[j][1] == 'cmp':
+            bound = int(insns[j][2].split(',')[0].lstrip('$'), 16)
+            break
+    else:
+        raise Exception('unbounded jump table at %#x' % insns[i][0])
+    mem = rodata(binary)
+    return [(v, read_u64(mem, table + 8 * v)) for v in range(bound + 1)]
+
+
+def blocks(binary):
+    start, end = function_bounds(binary)
+    insns = disassemble(binary, start, end)
+    next_addr = [a for a, _, _ in insns[1:]] + [end]
+
+    # what each control flow instruction does: (jump, fail, cases)
+    flow = {}
+    for i, (addr, mnem, ops) in enumerate(insns):
+        if mnem == 'ret':
+            flow[addr] = (None, None, [])
+        elif mnem == 'jmp' and SWITCH.match(ops):
+            flow[addr] = (None, None, switch_cases(insns, i, binary))
+        elif mnem == 'jmp':
+            flow[addr] = (target(ops), None, [])
+        elif mnem.startswith('j'):
+            flow[addr] = (target(ops), next_addr[i], [])
+
+    leaders = {start}
+    for i, (addr, _, _) in enumerate(insns):
+        if addr in flow:
+            jump, fail, cases = flow[addr]
+            leaders.add(next_addr[i])
+            leaders.update(t for t in [jump, fail] if t is not None)
+            leaders.update(t for _, t in cases)
+    leaders = {l for l in leaders if start <= l < end}
+
+    ret = []
+    cur = None
+    for i, (addr, _, _) in enumerate(insns):
+        if addr in leaders:
+            cur = {'addr': addr, 'size': 0, 'ninstr': 0}
+            ret.append(cur)
+        cur['size'] = next_addr[i] - cur['addr']
+        cur['ninstr'] += 1
+        ends = addr in flow or next_addr[i] in leaders
+        if not ends:
+            continue
+        jump, fail, cases = flow.get(addr, (next_addr[i], None, []))
+        if jump is not None:
+            cur['jump'] = jump
+        if fail is not None:
+            cur['fail'] = fail
+        if cases:
+            cur['switch_op'] = {
+                'addr': addr,
+                'min_val': cases[0][0],
+                'max_val': cases[-1][0],
+                'default': UT64_MAX,
+                'cases': [{'addr': addr, 'jump': t, 'value': v}
+                          for v, t in cases],
+            }
+    return ret
+
+
+def regenerate(source):
+    with tempfile.TemporaryDirectory() as tmp:
+        binary = os.path.join(tmp, 'sample')
+        subprocess.run(['gcc'] + CFLAGS + ['-o', binary, source], check=True)
+        fixture = os.path.splitext(source)[0] + '.json'
+        with open(fixture, 'w') as out:
+            json.dump(blocks(binary), out)
+            out.write('\n')
+    print('wrote ' + fixture)
+
+
+if __name__ == '__main__':
+    sources = sys.argv[1:] or sorted(
+        os.path.join(SAMPLES, f) for f in os.listdir(SAMPLES)
+        if f.endswith('.c'))
+    for source in sources:
+        regenerate(source)